@@ -69,8 +69,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }],
             fake_users: vec!["admin".to_string(), "webuser".to_string()],
             fake_network_interfaces: vec!["eth0".to_string(), "eth1".to_string()],
+            fake_mac_addresses: vec![],
+            persona_pack: None,
             system_uptime_days: Some(127),
+            honeytokens: vec![],
+            randomize: Default::default(),
         },
+        ..ServiceConfig::default()
     };
 
     let http_service = ServiceConfig {
@@ -82,6 +87,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         header_patterns: vec!["GET".to_string(), "POST".to_string()],
         banner_response: Some("HTTP/1.1 200 OK\r\nServer: nginx/1.18.0".to_string()),
         obfuscation: miel::configuration::types::ObfuscationConfig::default(),
+        ..ServiceConfig::default()
     };
 
     // Create containers
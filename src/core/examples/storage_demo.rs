@@ -66,32 +66,46 @@ async fn main() {
         container_id: Some("container-123".to_string()),
         bytes_transferred: 42,
         status: SessionStatus::Completed,
+        enrichment: None,
+        original_dst: None,
     };
-    storage_db.save_session(&sess).expect("save session db");
-    storage_fs.save_session(&sess).expect("save session fs");
+    storage_db
+        .save_session(&sess)
+        .await
+        .expect("save session db");
+    storage_fs
+        .save_session(&sess)
+        .await
+        .expect("save session fs");
     info!("Saved session {} to DB and FS", sess.id);
 
     // Append some interaction data to both backends
     storage_db
         .save_interaction(session_id, b"Hello, ")
+        .await
         .expect("save interaction db 1");
     storage_db
         .save_interaction(session_id, b"world!\n")
+        .await
         .expect("save interaction db 2");
 
     storage_fs
         .save_interaction(session_id, b"Hello, ")
+        .await
         .expect("save interaction fs 1");
     storage_fs
         .save_interaction(session_id, b"world!\n")
+        .await
         .expect("save interaction fs 2");
 
     // Load and display interaction data from both backends
     let data_db = storage_db
         .get_session_data(session_id)
+        .await
         .expect("get data db");
     let data_fs = storage_fs
         .get_session_data(session_id)
+        .await
         .expect("get data fs");
     info!(
         "DB interaction data ({} bytes): {}",
@@ -127,20 +141,34 @@ async fn main() {
         stdio_timestamps: vec![],
         total_bytes: 6,
         duration: Duration::seconds(1),
+        flow: None,
+        tls: None,
+        uploaded_files: Vec::new(),
+        messages: Vec::new(),
+        carved_files: Vec::new(),
+        rule_matches: Vec::new(),
+        keystrokes: None,
+        ssh_channels: Vec::new(),
+        offloaded: Vec::new(),
+        persona: None,
     };
     storage_db
         .save_capture_artifacts(&arts)
+        .await
         .expect("save artifacts db");
     storage_fs
         .save_capture_artifacts(&arts)
+        .await
         .expect("save artifacts fs");
 
     // Load artifacts from both backends and display quick summary
     let fetched_db = storage_db
         .get_capture_artifacts(session_id)
+        .await
         .expect("fetch artifacts db");
     let fetched_fs = storage_fs
         .get_capture_artifacts(session_id)
+        .await
         .expect("fetch artifacts fs");
     info!(
         "Artifacts -> DB total_bytes={}, FS total_bytes={}",
@@ -165,8 +193,14 @@ async fn main() {
     );
 
     // Query sessions from both backends and display counts
-    let all_db = storage_db.get_sessions(None).expect("list sessions db");
-    let all_fs = storage_fs.get_sessions(None).expect("list sessions fs");
+    let all_db = storage_db
+        .get_sessions(None)
+        .await
+        .expect("list sessions db");
+    let all_fs = storage_fs
+        .get_sessions(None)
+        .await
+        .expect("list sessions fs");
     info!(
         "Total sessions -> DB: {}, FS: {}",
         all_db.len(),
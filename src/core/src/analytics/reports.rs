@@ -0,0 +1,711 @@
+//! Summary reports of the stored sessions.
+//!
+//! Once a day or a week has ended, [`run`] summarizes the sessions started during
+//! it: the sessions per service, the top source countries, the top credentials
+//! tried and the hashes of the files uploaded or carved for the first time, see
+//! [`Summary`]. The report is written to the `[reports]` directory as Markdown
+//! and HTML, named after its period (`daily-2026-10-13.md`, `weekly-2026-W41.html`),
+//! then POSTed to the webhooks and mailed in the background.
+//!
+//! A period whose report is already on disk is skipped, so the controller checks
+//! every hour and a honeypot down when a period ended reports it on startup. The
+//! file hashes reported are appended to `payloads.txt`, a hash being new until
+//! it was reported once.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Datelike, NaiveTime, TimeDelta, Utc};
+use log::{error, info};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::configuration::types::{
+    ReportConfig, ReportFormat, ReportPeriod, WebhookConfig, WebhookFormat,
+};
+use crate::http_client::HttpEndpoint;
+use crate::smtp_client::{Mail, SmtpEndpoint};
+use crate::storage::storage_trait::Storage;
+use crate::storage::types::{CredentialFilter, SessionFilter};
+
+/// File of the hashes already reported, one per line
+const PAYLOADS_FILE: &str = "payloads.txt";
+
+/// Longest message Discord accepts
+const DISCORD_MAX_LEN: usize = 2000;
+
+/// Sessions counted under a name, e.g. a service or a country
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Count {
+    pub name: String,
+    pub sessions: usize,
+}
+
+/// Login tried during the period
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CredentialCount {
+    pub username: String,
+    pub password: Option<String>,
+    pub attempts: usize,
+}
+
+/// File hash reported for the first time
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NewPayload {
+    pub sha256: String,
+    /// Names the clients gave to the file
+    pub names: BTreeSet<String>,
+    pub sessions: usize,
+}
+
+/// What happened on the honeypot during a period
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Summary {
+    /// e.g. `daily-2026-10-13`, the reports being named after it
+    pub name: String,
+    pub period: ReportPeriod,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub generated_at: DateTime<Utc>,
+    pub sessions: usize,
+    pub client_ips: usize,
+    /// Most targeted first
+    pub services: Vec<Count>,
+    /// Of the enriched sessions, most frequent first
+    pub countries: Vec<Count>,
+    /// Most tried first
+    pub credentials: Vec<CredentialCount>,
+    /// Most seen first
+    pub new_payloads: Vec<NewPayload>,
+}
+
+/// Bounds and name of the last `period` ended at `now`
+pub fn last_period(
+    period: ReportPeriod,
+    now: DateTime<Utc>,
+) -> (DateTime<Utc>, DateTime<Utc>, String) {
+    let today = now.date_naive().and_time(NaiveTime::MIN).and_utc();
+    match period {
+        ReportPeriod::Daily => {
+            let start = today - TimeDelta::days(1);
+            (start, today, format!("daily-{}", start.format("%Y-%m-%d")))
+        }
+        ReportPeriod::Weekly => {
+            let end = today - TimeDelta::days(now.weekday().num_days_from_monday() as i64);
+            let start = end - TimeDelta::weeks(1);
+            let week = start.iso_week();
+            (
+                start,
+                end,
+                format!("weekly-{}-W{:02}", week.year(), week.week()),
+            )
+        }
+    }
+}
+
+/// Summarizes the sessions of `storage` started between `start` and `end`, listing
+/// `top` countries and credentials and the file hashes missing from `known`
+pub async fn summarize(
+    storage: &dyn Storage,
+    period: ReportPeriod,
+    (start, end, name): (DateTime<Utc>, DateTime<Utc>, String),
+    top: usize,
+    known: &HashSet<String>,
+) -> io::Result<Summary> {
+    let filter = SessionFilter {
+        start_date: Some(start),
+        ..SessionFilter::default()
+    };
+    let sessions: Vec<_> = storage
+        .get_sessions(Some(filter))
+        .await
+        .map_err(io::Error::other)?
+        .into_iter()
+        .filter(|session| session.start_time < end)
+        .collect();
+
+    let mut services: HashMap<&str, usize> = HashMap::new();
+    let mut countries: HashMap<&str, usize> = HashMap::new();
+    let mut client_ips: HashSet<IpAddr> = HashSet::new();
+    let mut payloads: BTreeMap<String, NewPayload> = BTreeMap::new();
+    for session in &sessions {
+        *services.entry(&session.service_name).or_default() += 1;
+        if let Some(country) = session
+            .enrichment
+            .as_ref()
+            .and_then(|e| e.country_code.as_deref())
+        {
+            *countries.entry(country).or_default() += 1;
+        }
+        client_ips.insert(session.client_addr.ip());
+
+        let Ok(artifacts) = storage.get_capture_artifacts(session.id).await else {
+            continue;
+        };
+        let uploaded = artifacts
+            .uploaded_files
+            .iter()
+            .map(|f| (&f.sha256, &f.name));
+        let carved = artifacts.carved_files.iter().map(|f| (&f.sha256, &f.name));
+        let mut seen = HashSet::new();
+        for (sha256, file_name) in uploaded.chain(carved) {
+            let sha256 = sha256.to_lowercase();
+            if known.contains(&sha256) {
+                continue;
+            }
+            let payload = payloads
+                .entry(sha256.clone())
+                .or_insert_with(|| NewPayload {
+                    sha256: sha256.clone(),
+                    names: BTreeSet::new(),
+                    sessions: 0,
+                });
+            payload.names.insert(file_name.clone());
+            if seen.insert(sha256) {
+                payload.sessions += 1;
+            }
+        }
+    }
+
+    let attempts = storage
+        .get_credentials(Some(CredentialFilter {
+            start_date: Some(start),
+            end_date: Some(end),
+            ..CredentialFilter::default()
+        }))
+        .await
+        .map_err(io::Error::other)?;
+    let mut credentials: HashMap<(&str, Option<&str>), usize> = HashMap::new();
+    for attempt in attempts.iter().filter(|attempt| attempt.timestamp < end) {
+        *credentials
+            .entry((&attempt.username, attempt.password.as_deref()))
+            .or_default() += 1;
+    }
+
+    let mut credentials: Vec<CredentialCount> = credentials
+        .into_iter()
+        .map(|((username, password), attempts)| CredentialCount {
+            username: username.to_string(),
+            password: password.map(str::to_string),
+            attempts,
+        })
+        .collect();
+    credentials.sort_by(|a, b| {
+        b.attempts
+            .cmp(&a.attempts)
+            .then_with(|| (&a.username, &a.password).cmp(&(&b.username, &b.password)))
+    });
+    credentials.truncate(top);
+    let mut new_payloads: Vec<NewPayload> = payloads.into_values().collect();
+    new_payloads.sort_by(|a, b| b.sessions.cmp(&a.sessions).then(a.sha256.cmp(&b.sha256)));
+
+    Ok(Summary {
+        name,
+        period,
+        start,
+        end,
+        generated_at: Utc::now(),
+        sessions: sessions.len(),
+        client_ips: client_ips.len(),
+        services: ranked(services, usize::MAX),
+        countries: ranked(countries, top),
+        credentials,
+        new_payloads,
+    })
+}
+
+/// The `top` largest counts, ties by name
+fn ranked(counts: HashMap<&str, usize>, top: usize) -> Vec<Count> {
+    let mut counts: Vec<Count> = counts
+        .into_iter()
+        .map(|(name, sessions)| Count {
+            name: name.to_string(),
+            sessions,
+        })
+        .collect();
+    counts.sort_by(|a, b| b.sessions.cmp(&a.sessions).then(a.name.cmp(&b.name)));
+    counts.truncate(top);
+    counts
+}
+
+/// Section of a report
+struct Table {
+    title: &'static str,
+    columns: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Summary {
+    /// Title of the report, e.g. "miel daily report 2026-10-13"
+    pub fn title(&self) -> String {
+        match self.period {
+            ReportPeriod::Daily => format!("miel daily report {}", self.start.format("%Y-%m-%d")),
+            ReportPeriod::Weekly => format!(
+                "miel weekly report {}",
+                self.name.trim_start_matches("weekly-")
+            ),
+        }
+    }
+
+    /// Sections of the report
+    fn tables(&self) -> Vec<Table> {
+        let counts = |counts: &[Count]| {
+            counts
+                .iter()
+                .map(|c| vec![c.name.clone(), c.sessions.to_string()])
+                .collect()
+        };
+        vec![
+            Table {
+                title: "Sessions per service",
+                columns: vec!["Service", "Sessions"],
+                rows: counts(&self.services),
+            },
+            Table {
+                title: "Top source countries",
+                columns: vec!["Country", "Sessions"],
+                rows: counts(&self.countries),
+            },
+            Table {
+                title: "Top credentials",
+                columns: vec!["Username", "Password", "Attempts"],
+                rows: self
+                    .credentials
+                    .iter()
+                    .map(|c| {
+                        vec![
+                            c.username.clone(),
+                            c.password.clone().unwrap_or_else(|| "-".to_string()),
+                            c.attempts.to_string(),
+                        ]
+                    })
+                    .collect(),
+            },
+            Table {
+                title: "New payload hashes",
+                columns: vec!["SHA-256", "Names", "Sessions"],
+                rows: self
+                    .new_payloads
+                    .iter()
+                    .map(|p| {
+                        let names: Vec<&str> = p.names.iter().map(String::as_str).collect();
+                        vec![p.sha256.clone(), names.join(", "), p.sessions.to_string()]
+                    })
+                    .collect(),
+            },
+        ]
+    }
+
+    fn overview(&self) -> String {
+        format!(
+            "{} sessions from {} client IPs between {} and {}.",
+            self.sessions,
+            self.client_ips,
+            self.start.format("%Y-%m-%d %H:%M UTC"),
+            self.end.format("%Y-%m-%d %H:%M UTC")
+        )
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = format!("# {}\n\n{}\n", self.title(), self.overview());
+        for Table {
+            title,
+            columns,
+            rows,
+        } in self.tables()
+        {
+            let _ = write!(markdown, "\n## {}\n\n", title);
+            if rows.is_empty() {
+                markdown.push_str("None.\n");
+                continue;
+            }
+            let _ = writeln!(markdown, "| {} |", columns.join(" | "));
+            let _ = writeln!(markdown, "|{}", "---|".repeat(columns.len()));
+            for row in rows {
+                let cells: Vec<String> = row.iter().map(|cell| markdown_cell(cell)).collect();
+                let _ = writeln!(markdown, "| {} |", cells.join(" | "));
+            }
+        }
+        markdown
+    }
+
+    pub fn to_html(&self) -> String {
+        let title = html_escape(&self.title());
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+             <style>body{{font-family:sans-serif}}table{{border-collapse:collapse}}\
+             th,td{{border:1px solid #ccc;padding:4px 8px;text-align:left}}</style>\n\
+             </head>\n<body>\n<h1>{}</h1>\n<p>{}</p>\n",
+            title,
+            title,
+            html_escape(&self.overview())
+        );
+        for Table {
+            title: section,
+            columns,
+            rows,
+        } in self.tables()
+        {
+            let _ = writeln!(html, "<h2>{}</h2>", section);
+            if rows.is_empty() {
+                html.push_str("<p>None.</p>\n");
+                continue;
+            }
+            html.push_str("<table>\n<tr>");
+            for column in columns {
+                let _ = write!(html, "<th>{}</th>", column);
+            }
+            html.push_str("</tr>\n");
+            for row in rows {
+                html.push_str("<tr>");
+                for cell in row {
+                    let _ = write!(html, "<td>{}</td>", html_escape(&cell));
+                }
+                html.push_str("</tr>\n");
+            }
+            html.push_str("</table>\n");
+        }
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+
+    /// Request body for a webhook of the given format
+    pub fn payload(&self, format: WebhookFormat) -> String {
+        let markdown = self.to_markdown();
+        match format {
+            WebhookFormat::Generic => json!({ "report": self, "markdown": markdown }).to_string(),
+            WebhookFormat::Slack => json!({ "text": markdown }).to_string(),
+            WebhookFormat::Discord => {
+                let mut content = markdown;
+                if content.len() > DISCORD_MAX_LEN {
+                    let mut end = DISCORD_MAX_LEN - 1;
+                    while !content.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    content.truncate(end);
+                    content.push('…');
+                }
+                json!({ "content": content }).to_string()
+            }
+        }
+    }
+}
+
+/// Directory of the reports of `config`
+pub fn report_dir(config: &ReportConfig, storage_path: &Path) -> PathBuf {
+    config
+        .dir
+        .clone()
+        .unwrap_or_else(|| storage_path.join("reports"))
+}
+
+/// Writes the report of the last period ended at `now`, unless it exists, and
+/// delivers it in the background. Returns the summary of a new report.
+pub async fn run(
+    storage: &dyn Storage,
+    config: &ReportConfig,
+    storage_path: &Path,
+    now: DateTime<Utc>,
+) -> io::Result<Option<Summary>> {
+    let dir = report_dir(config, storage_path);
+    let period = last_period(config.period, now);
+    let written = |format: &ReportFormat| dir.join(file_name(&period.2, *format)).exists();
+    if !config.formats.is_empty() && config.formats.iter().all(written) {
+        return Ok(None);
+    }
+
+    fs::create_dir_all(&dir)?;
+    let payloads_path = dir.join(PAYLOADS_FILE);
+    let known: HashSet<String> = match fs::read_to_string(&payloads_path) {
+        Ok(content) => content.lines().map(str::to_string).collect(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => HashSet::new(),
+        Err(e) => return Err(e),
+    };
+    let summary = summarize(storage, config.period, period, config.top, &known).await?;
+
+    for format in &config.formats {
+        let content = match format {
+            ReportFormat::Markdown => summary.to_markdown(),
+            ReportFormat::Html => summary.to_html(),
+        };
+        fs::write(dir.join(file_name(&summary.name, *format)), content)?;
+    }
+    if !summary.new_payloads.is_empty() {
+        let mut hashes = String::new();
+        for payload in &summary.new_payloads {
+            hashes.push_str(&payload.sha256);
+            hashes.push('\n');
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&payloads_path)?;
+        io::Write::write_all(&mut file, hashes.as_bytes())?;
+    }
+    info!(
+        "Wrote the {} report of {} sessions to {}",
+        summary.name,
+        summary.sessions,
+        dir.display()
+    );
+
+    spawn_delivery(config, summary.clone());
+    Ok(Some(summary))
+}
+
+fn file_name(name: &str, format: ReportFormat) -> String {
+    match format {
+        ReportFormat::Markdown => format!("{}.md", name),
+        ReportFormat::Html => format!("{}.html", name),
+    }
+}
+
+/// POSTs `summary` to the webhooks and mails it, failures being logged
+fn spawn_delivery(config: &ReportConfig, summary: Summary) {
+    let webhooks: Vec<WebhookConfig> = config.webhooks.clone();
+    let email = config.email.clone();
+    let html = config.formats.contains(&ReportFormat::Html);
+    if webhooks.is_empty() && email.is_none() {
+        return;
+    }
+    tokio::spawn(async move {
+        for webhook in webhooks {
+            let result = match HttpEndpoint::parse(&webhook.url) {
+                Ok(endpoint) => endpoint.post_json(&summary.payload(webhook.format)).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                error!(
+                    "Cannot post the {} report to {}: {}",
+                    summary.name, webhook.url, e
+                );
+            }
+        }
+
+        if let Some(email) = email {
+            let mail = Mail {
+                from: email.from.clone(),
+                to: email.to.clone(),
+                subject: summary.title(),
+                text: summary.to_markdown(),
+                html: html.then(|| summary.to_html()),
+            };
+            let login = email
+                .username
+                .as_deref()
+                .map(|username| (username, email.password.as_deref().unwrap_or_default()));
+            let result = match SmtpEndpoint::parse(&email.smtp_url) {
+                Ok(relay) => relay.send(login, &mail).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => info!(
+                    "Mailed the {} report to {}",
+                    summary.name,
+                    email.to.join(", ")
+                ),
+                Err(e) => error!("Cannot mail the {} report: {}", summary.name, e),
+            }
+        }
+    });
+}
+
+/// `cell` with the characters ending a Markdown table cell escaped
+fn markdown_cell(cell: &str) -> String {
+    cell.replace('|', "\\|").replace(['\r', '\n'], " ")
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_capture::types::UploadedFile;
+    use crate::data_capture::CaptureArtifacts;
+    use crate::enrichment::IpEnrichment;
+    use crate::session::Session;
+    use crate::session_management::SessionStatus;
+    use crate::storage::export::sha256_hex;
+    use crate::storage::memory_storage::MemoryStorage;
+    use crate::storage::types::Credential;
+    use uuid::Uuid;
+
+    fn at(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().to_utc()
+    }
+
+    #[test]
+    fn periods_end_at_midnight_and_monday() {
+        // A Wednesday
+        let now = at("2026-10-14T09:30:00Z");
+        assert_eq!(
+            last_period(ReportPeriod::Daily, now),
+            (
+                at("2026-10-13T00:00:00Z"),
+                at("2026-10-14T00:00:00Z"),
+                "daily-2026-10-13".to_string()
+            )
+        );
+        assert_eq!(
+            last_period(ReportPeriod::Weekly, now),
+            (
+                at("2026-10-05T00:00:00Z"),
+                at("2026-10-12T00:00:00Z"),
+                "weekly-2026-W41".to_string()
+            )
+        );
+    }
+
+    async fn session(
+        storage: &MemoryStorage,
+        start: &str,
+        service: &str,
+        country: &str,
+        files: &[&[u8]],
+    ) -> Session {
+        let session = Session {
+            id: Uuid::new_v4(),
+            service_name: service.to_string(),
+            client_addr: "198.51.100.23:51000".parse().unwrap(),
+            start_time: at(start),
+            end_time: Some(at(start) + TimeDelta::minutes(1)),
+            container_id: None,
+            bytes_transferred: 0,
+            status: SessionStatus::Completed,
+            enrichment: Some(IpEnrichment {
+                country_code: Some(country.to_string()),
+                ..IpEnrichment::default()
+            }),
+            original_dst: None,
+        };
+        storage.save_session(&session).await.unwrap();
+        let artifacts = CaptureArtifacts {
+            session_id: session.id,
+            duration: chrono::Duration::minutes(1),
+            uploaded_files: files
+                .iter()
+                .map(|content| UploadedFile {
+                    name: "x.sh".to_string(),
+                    sha256: sha256_hex(content),
+                    md5: String::new(),
+                    sha1: String::new(),
+                    content: content.to_vec(),
+                    truncated: false,
+                    virustotal: None,
+                })
+                .collect(),
+            ..Default::default()
+        };
+        storage.save_capture_artifacts(&artifacts).await.unwrap();
+        let attempts: Vec<Credential> = [("root", "123456"), ("root", "admin")]
+            .iter()
+            .map(|(username, password)| Credential {
+                session_id: session.id,
+                timestamp: session.start_time,
+                service: service.to_string(),
+                client_ip: Some(session.client_addr.ip()),
+                username: username.to_string(),
+                password: Some(password.to_string()),
+                accepted: Some(false),
+            })
+            .collect();
+        let attempts = if service == "ssh" {
+            attempts
+        } else {
+            attempts[..1].to_vec()
+        };
+        storage.save_credentials(&attempts).await.unwrap();
+        session
+    }
+
+    #[tokio::test]
+    async fn reports_the_last_day_once() {
+        let storage = MemoryStorage::new();
+        let dir = tempfile::tempdir().unwrap();
+        session(&storage, "2026-10-12T23:00:00Z", "ssh", "FR", &[b"old"]).await;
+        session(
+            &storage,
+            "2026-10-13T01:00:00Z",
+            "ssh",
+            "CN",
+            &[b"old", b"miner"],
+        )
+        .await;
+        session(&storage, "2026-10-13T02:00:00Z", "ssh", "CN", &[b"miner"]).await;
+        session(&storage, "2026-10-13T03:00:00Z", "telnet", "US", &[]).await;
+        session(&storage, "2026-10-14T01:00:00Z", "ftp", "US", &[]).await;
+        fs::write(
+            dir.path().join(PAYLOADS_FILE),
+            format!("{}\n", sha256_hex(b"old")),
+        )
+        .unwrap();
+
+        let config = ReportConfig {
+            enabled: true,
+            dir: Some(dir.path().to_path_buf()),
+            top: 1,
+            ..ReportConfig::default()
+        };
+        let now = at("2026-10-14T09:30:00Z");
+        let summary = run(&storage, &config, Path::new("/nonexistent"), now)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!((summary.sessions, summary.client_ips), (3, 1));
+        assert_eq!(
+            summary.services,
+            vec![
+                Count {
+                    name: "ssh".to_string(),
+                    sessions: 2
+                },
+                Count {
+                    name: "telnet".to_string(),
+                    sessions: 1
+                }
+            ]
+        );
+        assert_eq!(summary.countries[0].name, "CN");
+        assert_eq!(summary.countries.len(), 1);
+        assert_eq!(
+            summary.credentials,
+            vec![CredentialCount {
+                username: "root".to_string(),
+                password: Some("123456".to_string()),
+                attempts: 3,
+            }]
+        );
+        assert_eq!(summary.new_payloads.len(), 1);
+        assert_eq!(summary.new_payloads[0].sha256, sha256_hex(b"miner"));
+        assert_eq!(summary.new_payloads[0].sessions, 2);
+
+        let markdown = fs::read_to_string(dir.path().join("daily-2026-10-13.md")).unwrap();
+        assert!(markdown.starts_with("# miel daily report 2026-10-13\n"));
+        assert!(markdown.contains("| root | 123456 | 3 |"));
+        let html = fs::read_to_string(dir.path().join("daily-2026-10-13.html")).unwrap();
+        assert!(html.contains("<td>telnet</td><td>1</td>"));
+        let known = fs::read_to_string(dir.path().join(PAYLOADS_FILE)).unwrap();
+        assert_eq!(known.lines().count(), 2);
+
+        assert!(run(&storage, &config, Path::new("/nonexistent"), now)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn cells_are_escaped() {
+        assert_eq!(markdown_cell("a|b\nc"), "a\\|b c");
+        assert_eq!(html_escape("<script>\"&"), "&lt;script&gt;&quot;&amp;");
+    }
+}
@@ -1,5 +1,7 @@
 pub mod config;
+pub mod includes;
 pub mod types;
+pub mod validation;
 
 pub use types::Protocol;
 pub use types::ServiceConfig;
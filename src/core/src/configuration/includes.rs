@@ -0,0 +1,298 @@
+//! Layering of the configuration files, see [`Config::from_file_with_profile`].
+//!
+//! The main file can list other files in `include`, relative to its directory,
+//! whose last path component may contain `*` wildcards (`services.d/*.toml`).
+//! Each file is a partial configuration merged over the previous ones, in the
+//! order of the patterns and by file name within a pattern. Tables are merged
+//! key by key, `services` entries with the name of a known service are merged
+//! into it and the other values replace the previous ones.
+//!
+//! Named profiles are partial configurations under `[profiles.<name>]`, in the
+//! main or an included file, merged last when selected.
+//!
+//! [`Config::from_file_with_profile`]: super::config::Config::from_file_with_profile
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use toml::{Table, Value};
+
+use crate::error_handling::types::ConfigError;
+
+/// Key listing the included files
+pub const INCLUDE_KEY: &str = "include";
+/// Key of the profile tables
+pub const PROFILES_KEY: &str = "profiles";
+
+/// Parses the TOML file at `path` into a table
+///
+/// # Errors
+/// [`ConfigError::IoError`] if the file cannot be read, [`ConfigError::TomlError`]
+/// naming the file if it does not parse.
+pub fn read_table(path: &Path) -> Result<Table, ConfigError> {
+    let content = fs::read_to_string(path).map_err(ConfigError::IoError)?;
+    content
+        .parse::<Table>()
+        .map_err(|e| ConfigError::TomlError(format!("{}: {}", path.display(), e)))
+}
+
+/// Reads the files the main table `main`, loaded from `dir`, includes and merges
+/// them over it, then removes the `include` key
+///
+/// # Errors
+/// [`ConfigError::IncludeConfig`] when `include` is not a list of strings, when a
+/// pattern without wildcards names no file or when an included file includes
+/// others, and the errors of [`read_table`].
+pub fn merge_includes(main: &mut Table, dir: &Path) -> Result<(), ConfigError> {
+    let Some(include) = main.remove(INCLUDE_KEY) else {
+        return Ok(());
+    };
+    let patterns = match include {
+        Value::Array(patterns) => patterns,
+        _ => {
+            return Err(ConfigError::IncludeConfig(
+                "include must be a list of file patterns".to_string(),
+            ))
+        }
+    };
+
+    for pattern in patterns {
+        let Value::String(pattern) = pattern else {
+            return Err(ConfigError::IncludeConfig(
+                "include must be a list of file patterns".to_string(),
+            ));
+        };
+        for path in expand(dir, &pattern)? {
+            let layer = read_table(&path)?;
+            if layer.contains_key(INCLUDE_KEY) {
+                return Err(ConfigError::IncludeConfig(format!(
+                    "{} cannot include other files, only the main file can",
+                    path.display()
+                )));
+            }
+            debug!("Merging configuration file {}", path.display());
+            merge(main, layer);
+        }
+    }
+    Ok(())
+}
+
+/// Removes the profiles of `main` and merges the one named `profile` over it
+///
+/// # Errors
+/// [`ConfigError::ProfileConfig`] when `profiles` is not a table of tables or
+/// when no profile is named `profile`.
+pub fn apply_profile(main: &mut Table, profile: Option<&str>) -> Result<(), ConfigError> {
+    let profiles = match main.remove(PROFILES_KEY) {
+        Some(Value::Table(profiles)) => profiles,
+        Some(_) => {
+            return Err(ConfigError::ProfileConfig(
+                "profiles must be a table of profile tables".to_string(),
+            ))
+        }
+        None => Table::new(),
+    };
+    let Some(name) = profile else {
+        return Ok(());
+    };
+
+    match profiles.get(name) {
+        Some(Value::Table(layer)) => {
+            merge(main, layer.clone());
+            Ok(())
+        }
+        Some(_) => Err(ConfigError::ProfileConfig(format!(
+            "profile {} must be a table",
+            name
+        ))),
+        None => {
+            let known: Vec<&str> = profiles.keys().map(String::as_str).collect();
+            Err(ConfigError::ProfileConfig(format!(
+                "no profile named {}, the configuration defines [{}]",
+                name,
+                known.join(", ")
+            )))
+        }
+    }
+}
+
+/// Merges the service table `service` into the `services` of `main`
+pub fn merge_service(main: &mut Table, service: Table) {
+    let mut layer = Table::new();
+    layer.insert(
+        "services".to_string(),
+        Value::Array(vec![Value::Table(service)]),
+    );
+    merge(main, layer);
+}
+
+/// Merges `layer` over `base`, as described in the [module](self) documentation
+pub fn merge(base: &mut Table, layer: Table) {
+    for (key, value) in layer {
+        let merged = match (base.remove(&key), value) {
+            (Some(Value::Table(mut table)), Value::Table(overlay)) => {
+                merge(&mut table, overlay);
+                Value::Table(table)
+            }
+            (Some(Value::Array(mut services)), Value::Array(overlay)) if key == "services" => {
+                for service in overlay {
+                    merge_named(&mut services, service);
+                }
+                Value::Array(services)
+            }
+            (_, value) => value,
+        };
+        base.insert(key, merged);
+    }
+}
+
+/// Merges `service` into the entry of `services` with the same name, or appends it
+fn merge_named(services: &mut Vec<Value>, service: Value) {
+    let name = service.get("name").and_then(Value::as_str);
+    let existing = services
+        .iter_mut()
+        .find(|s| name.is_some() && s.get("name").and_then(Value::as_str) == name);
+    match (existing, service) {
+        (Some(Value::Table(table)), Value::Table(overlay)) => merge(table, overlay),
+        (_, service) => services.push(service),
+    }
+}
+
+/// Files matching `pattern` relative to `dir`, sorted by name
+fn expand(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>, ConfigError> {
+    let path = dir.join(pattern);
+    let file_pattern = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if !file_pattern.contains('*') {
+        if !path.is_file() {
+            return Err(ConfigError::IncludeConfig(format!(
+                "included file {} does not exist",
+                path.display()
+            )));
+        }
+        return Ok(vec![path]);
+    }
+
+    let parent = path.parent().unwrap_or(dir);
+    if parent.to_string_lossy().contains('*') {
+        return Err(ConfigError::IncludeConfig(format!(
+            "include pattern {} can only have wildcards in its file name",
+            pattern
+        )));
+    }
+    // A missing directory matches no file, like an empty one
+    let Ok(entries) = fs::read_dir(parent) else {
+        return Ok(Vec::new());
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| glob_matches(&file_pattern, &name.to_string_lossy()))
+        })
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+/// Whether `name` matches `pattern`, where `*` stands for any run of characters
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_match_runs_of_characters() {
+        assert!(glob_matches("*.toml", "ssh.toml"));
+        assert!(glob_matches("*.toml", ".toml"));
+        assert!(!glob_matches("*.toml", "ssh.toml.bak"));
+        assert!(glob_matches("10-*-*.toml", "10-web-http.toml"));
+        assert!(!glob_matches("10-*-*.toml", "10-web.toml"));
+        assert!(glob_matches("exact.toml", "exact.toml"));
+    }
+
+    #[test]
+    fn services_are_merged_by_name() {
+        let mut base: Table = r#"
+            max_sessions = 100
+            [[services]]
+            name = "ssh"
+            port = 22
+            [services.obfuscation]
+            enabled = true
+            fake_users = ["admin"]
+        "#
+        .parse()
+        .unwrap();
+        let layer: Table = r#"
+            max_sessions = 10
+            [[services]]
+            name = "ssh"
+            enabled = false
+            [services.obfuscation]
+            fake_users = ["root"]
+            [[services]]
+            name = "http"
+            port = 80
+        "#
+        .parse()
+        .unwrap();
+        merge(&mut base, layer);
+
+        assert_eq!(base["max_sessions"].as_integer(), Some(10));
+        let services = base["services"].as_array().unwrap();
+        assert_eq!(services.len(), 2);
+        assert_eq!(services[0]["port"].as_integer(), Some(22));
+        assert_eq!(services[0]["enabled"].as_bool(), Some(false));
+        assert_eq!(services[0]["obfuscation"]["enabled"].as_bool(), Some(true));
+        assert_eq!(
+            services[0]["obfuscation"]["fake_users"].as_array().unwrap()[0].as_str(),
+            Some("root")
+        );
+        assert_eq!(services[1]["name"].as_str(), Some("http"));
+    }
+
+    #[test]
+    fn unknown_profiles_are_refused() {
+        let mut main: Table = r#"
+            [profiles.research]
+            max_sessions = 500
+            [profiles.production]
+            max_sessions = 50
+        "#
+        .parse()
+        .unwrap();
+        let mut other = main.clone();
+        apply_profile(&mut main, Some("production")).unwrap();
+        assert_eq!(main["max_sessions"].as_integer(), Some(50));
+        assert!(!main.contains_key(PROFILES_KEY));
+
+        assert!(matches!(
+            apply_profile(&mut other, Some("staging")),
+            Err(ConfigError::ProfileConfig(e)) if e.contains("production")
+        ));
+    }
+}
@@ -0,0 +1,148 @@
+//! Diagnostics of a full validation pass over the configuration.
+//!
+//! [`Config::check`](super::config::Config::check) reports every problem it
+//! finds instead of stopping at the first one, each at the path of the field
+//! it concerns (`bind_address`, `services[1].port`, `ip_filter.allowlist[0]`).
+//! Errors make the configuration unusable, warnings point at settings that are
+//! accepted but probably not what was meant.
+
+use std::fmt;
+
+use crate::error_handling::types::ConfigError;
+
+/// How bad a [`Diagnostic`] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found at `field`
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Path of the field, dotted with the index of list items
+    pub field: String,
+    pub message: String,
+    /// Error returned by [`Config::validate`](super::config::Config::validate)
+    /// for this diagnostic, only set on errors
+    error: Option<ConfigError>,
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", severity, self.field, self.message)
+    }
+}
+
+/// Every diagnostic of a validation pass, in the order of the fields
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl ValidationReport {
+    /// Records `error` found at `field`
+    pub fn error(&mut self, field: impl Into<String>, error: ConfigError) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            field: field.into(),
+            message: error.to_string(),
+            error: Some(error),
+        });
+    }
+
+    /// Records a warning about `field`
+    pub fn warn(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Warning,
+            field: field.into(),
+            message: message.into(),
+            error: None,
+        });
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|d| d.is_error())
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|d| !d.is_error())
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+
+    /// The first error of the report, if any
+    ///
+    /// # Errors
+    /// Returns the [`ConfigError`] of the first error diagnostic.
+    pub fn into_result(self) -> Result<(), ConfigError> {
+        match self.diagnostics.into_iter().find_map(|d| d.error) {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for diagnostic in &self.diagnostics {
+            writeln!(f, "{}", diagnostic)?;
+        }
+        let errors = self.errors().count();
+        write!(
+            f,
+            "{} error(s), {} warning(s)",
+            errors,
+            self.diagnostics.len() - errors
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_error_is_returned_and_warnings_are_kept() {
+        let mut report = ValidationReport::default();
+        report.warn("services[0].container_image", "image not found");
+        assert!(!report.has_errors());
+
+        report.error(
+            "services[1].port",
+            ConfigError::BadPortsRange("port 22 already used".to_string()),
+        );
+        report.error(
+            "bind_address",
+            ConfigError::BadIPFormatting("not an IPv4".to_string()),
+        );
+        assert_eq!(report.errors().count(), 2);
+        assert_eq!(report.warnings().count(), 1);
+        assert_eq!(
+            report.diagnostics()[1].to_string(),
+            "error: services[1].port: Port range error: port 22 already used"
+        );
+        assert!(report.to_string().ends_with("2 error(s), 1 warning(s)"));
+        assert!(matches!(
+            report.into_result(),
+            Err(ConfigError::BadPortsRange(_))
+        ));
+    }
+}
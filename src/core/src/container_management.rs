@@ -1,12 +1,22 @@
 //! Container management subsystem.
 //!
 //! This module exposes a minimal API to create and manage lightweight containers
-//! for honeypot services. The current implementation targets `systemd-nspawn`
-//! and focuses on simple lifecycle management and bookkeeping.
+//! for honeypot services. The supported runtimes are `systemd-nspawn` (default),
+//! Docker and rootless Podman, selected globally through [`Runtime`] or per
+//! service, plus an unisolated process sandbox for development on hosts without
+//! any of them. The focus is on simple lifecycle management and bookkeeping.
 //!
 //! Re-exports:
 //! - [`ContainerManager`]: main entry point to create/cleanup containers.
-//! - [`ContainerHandle`], [`ContainerStats`], [`Runtime`]: core types.
+//! - [`ContainerPool`]: keeps started containers ready for new sessions.
+//! - [`ImageProvisioner`]: unpacks rootfs images for nspawn containers.
+//! - [`EgressFilter`]: enforces the outbound network policy of a container.
+//! - [`ContainerPersona`]: hostname, banner, uptime and MAC addresses drawn per container.
+//! - [`PersonaPack`]: files, users and activity of a kind of machine, for the obfuscation.
+//! - [`PtyMaster`]: broadcasts what the services of a container write to their terminal.
+//! - [`ContainerHandle`], [`ContainerHealth`], [`ContainerStats`], [`Runtime`]: core types.
+//! - [`ContainerReport`], [`ResourceUsage`]: the running containers and what they use,
+//!   read from their [`cgroup`].
 //!
 //! Example (non-running):
 //! ```ignore
@@ -19,9 +29,27 @@
 //! # Ok::<(), Box<dyn std::error::Error>>(())
 //! ```
 
+pub mod cgroup;
 pub mod container_manager;
+pub mod container_pool;
+pub mod egress;
+pub mod image_provisioner;
 pub mod obfuscation;
+pub mod persona;
+pub mod persona_pack;
+pub mod pty;
 pub mod types;
+pub mod web_app;
 
+pub use cgroup::ResourceUsage;
 pub use container_manager::ContainerManager;
-pub use types::{ContainerHandle, ContainerStats, Runtime};
+pub use container_pool::ContainerPool;
+pub use egress::EgressFilter;
+pub use image_provisioner::ImageProvisioner;
+pub use persona::ContainerPersona;
+pub use persona_pack::PersonaPack;
+pub use pty::PtyMaster;
+pub use types::{
+    ContainerHandle, ContainerHealth, ContainerInfo, ContainerPaths, ContainerReport,
+    ContainerStats, Runtime,
+};
@@ -0,0 +1,139 @@
+//! Resource usage of the containers, read from their cgroup (v2).
+//!
+//! - nspawn containers are registered by `systemd-machined` in
+//!   `machine.slice/machine-<name>.scope`.
+//! - Docker and Podman containers are found through the cgroup of their init
+//!   process, as reported by `<runtime> inspect`, whatever the cgroup driver.
+//! - Process sandboxes share the cgroup of the honeypot, their usage is unknown.
+//!
+//! Reading is best effort: a counter the kernel does not expose, e.g. without
+//! the memory controller, is left out.
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use tokio::process::Command;
+use tracing::debug;
+
+use super::types::Runtime;
+
+/// Where the cgroup v2 hierarchy is mounted
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// What a container uses, as last counted by the kernel
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ResourceUsage {
+    /// Memory charged to the container, page cache included
+    pub memory_bytes: Option<u64>,
+    /// CPU time consumed since the container started
+    pub cpu_usage_usec: Option<u64>,
+    /// Processes and threads running in the container
+    pub pids: Option<u64>,
+}
+
+/// Usage of the container `container_id` started with `runtime`, `None` when its
+/// cgroup cannot be found
+pub async fn resource_usage(runtime: &Runtime, container_id: &str) -> Option<ResourceUsage> {
+    let dir = cgroup_dir(runtime, container_id).await?;
+    let usage = read_usage(&dir);
+    (usage != ResourceUsage::default()).then_some(usage)
+}
+
+/// Directory of the cgroup of `container_id`
+async fn cgroup_dir(runtime: &Runtime, container_id: &str) -> Option<PathBuf> {
+    let root = Path::new(CGROUP_ROOT);
+    match runtime {
+        Runtime::SystemdNspawn => {
+            let dir = root
+                .join("machine.slice")
+                .join(format!("machine-{}.scope", systemd_escape(container_id)));
+            dir.is_dir().then_some(dir)
+        }
+        Runtime::Docker | Runtime::Podman => {
+            let output = Command::new(runtime.binary())
+                .args(["inspect", "--format", "{{.State.Pid}}", container_id])
+                .output()
+                .await
+                .ok()?;
+            let pid = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if !output.status.success() || pid.is_empty() || pid == "0" {
+                debug!("No running process found for container {}", container_id);
+                return None;
+            }
+            let cgroup = std::fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+            Some(root.join(unified_path(&cgroup)?.trim_start_matches('/')))
+        }
+        Runtime::ProcessSandbox => None,
+    }
+}
+
+/// Path of the unified hierarchy in the content of `/proc/<pid>/cgroup`
+fn unified_path(proc_cgroup: &str) -> Option<&str> {
+    proc_cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .filter(|path| !path.is_empty())
+}
+
+/// Unit name escaping of systemd, `-` being the separator of the slices
+fn systemd_escape(name: &str) -> String {
+    name.bytes()
+        .enumerate()
+        .map(|(i, b)| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b':' | b'_' => (b as char).to_string(),
+            b'.' if i > 0 => ".".to_string(),
+            _ => format!("\\x{:02x}", b),
+        })
+        .collect()
+}
+
+/// Reads the counters of the cgroup in `dir`
+fn read_usage(dir: &Path) -> ResourceUsage {
+    let read = |file: &str| std::fs::read_to_string(dir.join(file)).ok();
+    ResourceUsage {
+        memory_bytes: read("memory.current").and_then(|v| v.trim().parse().ok()),
+        cpu_usage_usec: read("cpu.stat").and_then(|stat| {
+            stat.lines()
+                .find_map(|line| line.strip_prefix("usage_usec "))
+                .and_then(|v| v.trim().parse().ok())
+        }),
+        pids: read("pids.current").and_then(|v| v.trim().parse().ok()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cgroup_counters_are_read() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("memory.current"), "52428800\n").unwrap();
+        std::fs::write(
+            dir.path().join("cpu.stat"),
+            "usage_usec 1250000\nuser_usec 1000000\nsystem_usec 250000\n",
+        )
+        .unwrap();
+        assert_eq!(
+            read_usage(dir.path()),
+            ResourceUsage {
+                memory_bytes: Some(52_428_800),
+                cpu_usage_usec: Some(1_250_000),
+                pids: None,
+            }
+        );
+    }
+
+    #[test]
+    fn cgroups_are_located() {
+        assert_eq!(
+            systemd_escape("miel-ssh-1f2e"),
+            "miel\\x2dssh\\x2d1f2e".to_string()
+        );
+        assert_eq!(
+            unified_path("0::/system.slice/docker-ab12.scope\n"),
+            Some("/system.slice/docker-ab12.scope")
+        );
+        assert_eq!(unified_path("1:name=systemd:/init.scope\n"), None);
+    }
+}
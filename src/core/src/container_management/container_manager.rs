@@ -1,67 +1,148 @@
-use chrono::Utc;
-use log::{debug, error, info, warn};
+use chrono::{DateTime, Utc};
 use std::collections::HashMap;
 use std::fs::File;
 use std::path::Path;
 use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::net::TcpStream;
-use tokio::process::Command;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::process::{Child, Command};
+use tokio::sync::Mutex;
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
-use crate::configuration::types::ServiceConfig;
+use crate::configuration::types::{EgressPolicy, Protocol, ResourceLimits, ServiceConfig, SshMode};
+use crate::container_management::egress::{self, EgressFilter, EgressTarget};
+use crate::container_management::image_provisioner::ImageProvisioner;
 use crate::container_management::obfuscation::ObfuscationManager;
-use crate::container_management::types::{ContainerHandle, ContainerStats, Runtime};
+use crate::container_management::persona::ContainerPersona;
+use crate::container_management::pty::{Pty, PtyMaster};
+use crate::container_management::types::{
+    activity_log, mail_dir, upload_dir, ContainerHandle, ContainerHealth, ContainerInfo,
+    ContainerPaths, ContainerStats, Runtime,
+};
+use crate::container_management::web_app::Site;
+use crate::data_capture::file_capture::{MAX_MESSAGE_LEN, MAX_UPLOAD_LEN};
 use crate::error_handling::types::ContainerError;
+use crate::journal::{JournalEntry, JournaledContainer, SessionJournal};
+
+/// Longest wait for the service port of a container to accept a health probe
+const HEALTH_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Longest wait for a runtime to list the containers left by previous runs
+const LIST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Prefix of the names of the containers, see [`ContainerManager::prepare`]
+const CONTAINER_PREFIX: &str = "miel-";
+
+/// The container a directory belongs to, from its name
+type ContainerOfDir = fn(&str) -> Option<&str>;
 
 /// Orchestrates container lifecycle and bookkeeping for honeypot services.
 ///
-/// The manager abstracts over a container runtime (currently [`Runtime::SystemdNspawn`])
-/// and maintains a registry of active containers along with simple counters.
+/// The manager abstracts over a container runtime ([`Runtime::SystemdNspawn`],
+/// [`Runtime::Docker`], [`Runtime::Podman`] or the development-only
+/// [`Runtime::ProcessSandbox`]) and maintains a registry of active containers along with
+/// simple counters. The default runtime can be overridden per service through
+/// [`ServiceConfig::runtime`].
 ///
 /// Design notes:
-/// - Containers are created under `/tmp/miel-containers/<id>` and run with
-///   `systemd-nspawn --ephemeral` and `--private-network`.
+/// - nspawn containers are created under `/tmp/miel-containers/<id>` and run with
+///   `systemd-nspawn --ephemeral` and `--private-network`. Their directories, like
+///   the others of `/tmp`, can be moved with [`ContainerManager::with_paths`].
+/// - Docker and Podman containers run the configured `container_image` with `--rm`
+///   and are force-removed on cleanup. Podman runs in its own user namespace so
+///   the honeypot does not need root.
+/// - Process sandboxes run the scripted emulators directly on the host from
+///   `/tmp/miel-sandboxes/<id>`, without any isolation.
+/// - Only the nspawn runtime requires root privileges, as do services restricting
+///   egress traffic: their containers get nftables rules ([`EgressFilter`]) loaded
+///   before the client is proxied and removed on cleanup.
+/// - The containers are recorded in the [`SessionJournal`] before they start and
+///   once they are cleaned up, so that those left by a crash are removed on startup.
 /// - A random ephemeral host port is allocated and mapped to the container's
 ///   internal service port.
 /// - This is a minimal, best-effort implementation not meant for production isolation.
 #[derive(Clone)]
 pub struct ContainerManager {
     runtime: Runtime,
+    image_provisioner: ImageProvisioner,
     active_containers: HashMap<String, ContainerHandle>,
+    /// Egress rules of the containers whose service restricts outbound traffic
+    egress_filters: HashMap<String, EgressFilter>,
     stats: ContainerStats,
+    /// Records the containers started and cleaned up
+    journal: Arc<SessionJournal>,
+    /// Where the containers and their logs live on the host
+    paths: ContainerPaths,
+}
+
+/// A container checked by [`ContainerManager::prepare`], to be started
+struct ContainerLaunch {
+    /// The service, with its persona applied
+    service_config: ServiceConfig,
+    container_id: String,
+    runtime: Runtime,
+    persona: Option<ContainerPersona>,
 }
 
 impl ContainerManager {
-    /// Creates a new `ContainerManager`.
+    /// Creates a new `ContainerManager` using the `systemd-nspawn` runtime.
     ///
     /// Returns an error if the configured runtime is not available on the host.
     pub fn new() -> Result<Self, ContainerError> {
+        Self::with_runtime(Runtime::SystemdNspawn)
+    }
+
+    /// Creates a new `ContainerManager` whose default runtime is `runtime`.
+    ///
+    /// Returns an error if the runtime is not available on the host, or if it
+    /// requires privileges the current process does not have.
+    pub fn with_runtime(runtime: Runtime) -> Result<Self, ContainerError> {
         debug!("Initializing container manager");
 
-        // Check if systemd-nspawn is available, otherwise fail
-        if !Self::is_runtime_available() {
-            error!("Container runtime systemd-nspawn is not available");
+        // Check if the runtime binary is available, otherwise fail
+        if !Self::is_runtime_available(&runtime) {
+            error!("Container runtime {} is not available", runtime.binary());
             return Err(ContainerError::RuntimeNotAvailable);
         }
 
         // Require root privileges: unprivileged nspawn with a plain directory tree is not supported
         // on many systems and will implicitly enable private networking, breaking host-port binding.
-        if !Self::is_running_as_root() {
-            error!("Insufficient privileges: container manager requires root access");
+        if runtime.requires_root() && !Self::is_running_as_root() {
+            error!(
+                "Insufficient privileges: {} runtime requires root access",
+                runtime.binary()
+            );
             return Err(ContainerError::StartFailed(
                 "systemd-nspawn requires root privileges for this setup. Please run the program with sudo.".to_string(),
             ));
         }
 
+        if runtime == Runtime::ProcessSandbox {
+            warn!(
+                "The process sandbox runtime provides no isolation and is meant for development only"
+            );
+        }
+
+        // Rules of containers left behind by a previous run
+        if Self::is_running_as_root() {
+            egress::reset();
+        }
+
         let manager = ContainerManager {
-            runtime: Runtime::SystemdNspawn,
+            runtime,
+            image_provisioner: ImageProvisioner::default(),
             active_containers: HashMap::new(),
+            egress_filters: HashMap::new(),
             stats: ContainerStats {
                 active_count: 0,
                 total_created: 0,
                 failed_count: 0,
             },
+            journal: Arc::new(SessionJournal::disabled()),
+            paths: ContainerPaths::default(),
         };
 
         info!(
@@ -71,25 +152,49 @@ impl ContainerManager {
         Ok(manager)
     }
 
+    /// Looks nspawn images up and caches their unpacked rootfs under `image_dir`
+    /// instead of the default `/var/lib/miel/images`.
+    pub fn with_image_dir<P: Into<std::path::PathBuf>>(mut self, image_dir: P) -> Self {
+        self.image_provisioner = ImageProvisioner::new(image_dir);
+        self
+    }
+
+    /// Creates the containers and their logs under `paths` instead of the default
+    /// directories of `/tmp`
+    pub fn with_paths(mut self, paths: ContainerPaths) -> Self {
+        self.paths = paths;
+        self
+    }
+
+    /// Records the containers started and cleaned up in `journal`
+    pub fn with_journal(mut self, journal: Arc<SessionJournal>) -> Self {
+        self.journal = journal;
+        self
+    }
+
     /// Creates a mock `ContainerManager` for testing that doesn't require root privileges.
     #[cfg(test)]
     pub fn new_mock() -> Self {
-        use log::debug;
+        use tracing::debug;
         debug!("Creating mock ContainerManager for testing");
 
         ContainerManager {
             runtime: Runtime::SystemdNspawn,
+            image_provisioner: ImageProvisioner::default(),
             active_containers: HashMap::new(),
+            egress_filters: HashMap::new(),
             stats: ContainerStats {
                 active_count: 0,
                 total_created: 0,
                 failed_count: 0,
             },
+            journal: Arc::new(SessionJournal::disabled()),
+            paths: ContainerPaths::default(),
         }
     }
 
     /// Best-effort check for root privileges (EUID == 0).
-    fn is_running_as_root() -> bool {
+    pub(crate) fn is_running_as_root() -> bool {
         let is_root = if let Ok(output) = std::process::Command::new("id").arg("-u").output() {
             if output.status.success() {
                 if let Ok(s) = String::from_utf8(output.stdout) {
@@ -110,9 +215,13 @@ impl ContainerManager {
 
     /// Creates a new container for the given `service_config` and returns its handle.
     ///
+    /// The service's `runtime` override is used when set, otherwise the manager's
+    /// default runtime.
+    ///
     /// Side effects:
     /// - Allocates an ephemeral host port (127.0.0.1) and maps it to the service port.
-    /// - Spawns a `systemd-nspawn` process with an ephemeral rootfs under `/tmp`.
+    /// - Spawns a `systemd-nspawn` process with an ephemeral rootfs under `/tmp`, or a
+    ///   `docker run` process for the configured image.
     /// - Updates internal stats and registry.
     ///
     /// Errors if the container cannot be prepared or started.
@@ -120,21 +229,204 @@ impl ContainerManager {
         &mut self,
         service_config: &ServiceConfig,
     ) -> Result<ContainerHandle, ContainerError> {
-        let container_id = format!("miel-{}-{}", service_config.name, Uuid::new_v4());
+        let persona = ContainerPersona::draw(service_config);
+        self.create_container_as(service_config, persona).await
+    }
+
+    /// Creates a container like [`ContainerManager::create_container`], with
+    /// `manager` only locked to check the service and to register the container.
+    ///
+    /// Starting a container and waiting for its service to accept connections
+    /// takes seconds, meanwhile the containers of other sessions start and the
+    /// running ones are cleaned up.
+    pub async fn create_unlocked(
+        manager: &Mutex<ContainerManager>,
+        service_config: &ServiceConfig,
+    ) -> Result<ContainerHandle, ContainerError> {
+        let persona = ContainerPersona::draw(service_config);
+        let (launch, launcher) = {
+            let mut manager = manager.lock().await;
+            (
+                manager.prepare(service_config, persona)?,
+                manager.launcher(),
+            )
+        };
+        let handle = launcher.start(&launch).await?;
+        manager.lock().await.register(handle, &launch).await
+    }
+
+    /// Creates a container of the service running as `persona`, see
+    /// [`ContainerManager::create_container`]
+    async fn create_container_as(
+        &mut self,
+        service_config: &ServiceConfig,
+        persona: Option<ContainerPersona>,
+    ) -> Result<ContainerHandle, ContainerError> {
+        let launch = self.prepare(service_config, persona)?;
+        let handle = self.start(&launch).await?;
+        self.register(handle, &launch).await
+    }
+
+    /// Checks that the runtime of the service can run it, and names its container
+    fn prepare(
+        &mut self,
+        service_config: &ServiceConfig,
+        persona: Option<ContainerPersona>,
+    ) -> Result<ContainerLaunch, ContainerError> {
+        let service_config = match &persona {
+            Some(persona) => persona.apply(service_config),
+            None => service_config.clone(),
+        };
+        let container_id = format!(
+            "{}{}-{}",
+            CONTAINER_PREFIX,
+            service_config.name,
+            Uuid::new_v4()
+        );
 
         debug!(
             "Creating container {} for service {}",
             container_id, service_config.name
         );
 
+        let runtime = service_config
+            .runtime
+            .clone()
+            .unwrap_or_else(|| self.runtime.clone());
+
+        // The default runtime was checked at init, overrides are checked on use
+        if runtime != self.runtime && runtime.requires_root() && !Self::is_running_as_root() {
+            error!(
+                "Container runtime {} requested by service {} requires root privileges",
+                runtime.binary(),
+                service_config.name
+            );
+            self.stats.failed_count += 1;
+            return Err(ContainerError::InsufficientPrivileges);
+        }
+        if runtime != self.runtime && !Self::is_runtime_available(&runtime) {
+            error!(
+                "Container runtime {} requested by service {} is not available",
+                runtime.binary(),
+                service_config.name
+            );
+            self.stats.failed_count += 1;
+            return Err(ContainerError::RuntimeNotAvailable);
+        }
+
+        // A sandbox shares the network of the host, there is nothing to filter
+        if runtime == Runtime::ProcessSandbox && service_config.egress.policy != EgressPolicy::Allow
+        {
+            error!(
+                "Service {} restricts egress traffic, which the process sandbox cannot enforce",
+                service_config.name
+            );
+            self.stats.failed_count += 1;
+            return Err(ContainerError::CreationFailed(
+                "Egress policies are not supported by the process sandbox".to_string(),
+            ));
+        }
+
+        // nftables rules can only be loaded by root
+        if service_config.egress.policy != EgressPolicy::Allow && !Self::is_running_as_root() {
+            error!(
+                "Service {} restricts egress traffic, which requires root privileges",
+                service_config.name
+            );
+            self.stats.failed_count += 1;
+            return Err(ContainerError::InsufficientPrivileges);
+        }
+
+        Ok(ContainerLaunch {
+            service_config,
+            container_id,
+            runtime,
+            persona,
+        })
+    }
+
+    /// A manager of no container with the runtime and images of this one, which
+    /// starts containers while this one is not borrowed
+    fn launcher(&self) -> Self {
+        Self {
+            runtime: self.runtime.clone(),
+            image_provisioner: self.image_provisioner.clone(),
+            active_containers: HashMap::new(),
+            egress_filters: HashMap::new(),
+            stats: ContainerStats {
+                active_count: 0,
+                total_created: 0,
+                failed_count: 0,
+            },
+            journal: self.journal.clone(),
+            paths: self.paths.clone(),
+        }
+    }
+
+    /// Starts the container of `launch` and connects to its service
+    async fn start(&self, launch: &ContainerLaunch) -> Result<ContainerHandle, ContainerError> {
+        let container_id = &launch.container_id;
+
+        // Written ahead, a crash while starting leaves the container behind
+        self.journal
+            .record(JournalEntry::ContainerStarted(JournaledContainer {
+                container_id: container_id.clone(),
+                runtime: launch.runtime.clone(),
+                started_at: Utc::now(),
+            }))
+            .await;
+        let handle = self.spawn(launch).await;
+        if handle.is_err() {
+            self.journal
+                .record(JournalEntry::ContainerRemoved {
+                    container_id: container_id.clone(),
+                })
+                .await;
+        }
+        handle
+    }
+
+    /// Spawns the container of `launch` with its runtime
+    async fn spawn(&self, launch: &ContainerLaunch) -> Result<ContainerHandle, ContainerError> {
+        let service_config = &launch.service_config;
+        let container_id = &launch.container_id;
+
         // Use the runtime to create the container
-        let handle = match self.runtime {
+        let mut handle = match launch.runtime {
             Runtime::SystemdNspawn => {
                 debug!("Using systemd-nspawn runtime for container creation");
-                self.create_nspawn_container(service_config, &container_id)
+                self.create_nspawn_container(service_config, container_id)
+                    .await?
+            }
+            Runtime::Docker | Runtime::Podman => {
+                debug!(
+                    "Using {} runtime for container creation",
+                    launch.runtime.binary()
+                );
+                self.create_image_container(&launch.runtime, service_config, container_id)
+                    .await?
+            }
+            Runtime::ProcessSandbox => {
+                debug!("Using the process sandbox for container creation");
+                self.create_process_container(service_config, container_id)
                     .await?
             }
         };
+        if let Some(persona) = &launch.persona {
+            debug!("Container {} runs as {:?}", container_id, persona);
+        }
+        handle.persona = launch.persona.clone();
+        Ok(handle)
+    }
+
+    /// Tracks a started container, once its egress rules are loaded
+    async fn register(
+        &mut self,
+        handle: ContainerHandle,
+        launch: &ContainerLaunch,
+    ) -> Result<ContainerHandle, ContainerError> {
+        let service_config = &launch.service_config;
+        let container_id = &launch.container_id;
 
         // Update stats
         self.stats.total_created += 1;
@@ -144,6 +436,17 @@ impl ContainerManager {
         self.active_containers
             .insert(container_id.clone(), handle.clone());
 
+        // The client is only proxied once the container can no longer reach out
+        if let Err(e) = self.restrict_egress(&handle, service_config).await {
+            error!(
+                "Failed to apply the egress policy of container {}: {}",
+                container_id, e
+            );
+            self.stats.failed_count += 1;
+            let _ = self.cleanup_container(handle).await;
+            return Err(e);
+        }
+
         info!(
             "Container {} created for service {}",
             container_id, service_config.name
@@ -151,6 +454,31 @@ impl ContainerManager {
         Ok(handle)
     }
 
+    /// Loads the egress rules of the service for a started container
+    async fn restrict_egress(
+        &mut self,
+        handle: &ContainerHandle,
+        service_config: &ServiceConfig,
+    ) -> Result<(), ContainerError> {
+        if service_config.egress.policy == EgressPolicy::Allow {
+            return Ok(());
+        }
+
+        let target = EgressTarget::lookup(&handle.runtime, &handle.id).await?;
+        debug!("Egress rules of container {} match {:?}", handle.id, target);
+        if let Some(filter) = EgressFilter::apply(
+            &handle.id,
+            &service_config.name,
+            &target,
+            &service_config.egress,
+        )
+        .await?
+        {
+            self.egress_filters.insert(handle.id.clone(), filter);
+        }
+        Ok(())
+    }
+
     /// Cleans up a specific container.
     ///
     /// Best-effort: attempts to kill the process, remove the registry entry,
@@ -164,6 +492,9 @@ impl ContainerManager {
         // Kill the process if it's still running
         if let Some(mut process) = handle.process_handle.take() {
             debug!("Terminating process for container: {}", handle.id);
+            if handle.runtime == Runtime::ProcessSandbox {
+                Self::kill_process_group(&process, &handle.id);
+            }
             if let Err(e) = process.kill().await {
                 warn!("Failed to terminate container process {}: {}", handle.id, e);
             } else {
@@ -177,22 +508,324 @@ impl ContainerManager {
         self.active_containers.remove(&handle.id);
         self.stats.active_count = self.stats.active_count.saturating_sub(1);
 
-        // Clean up container directory
-        let container_path = format!("/tmp/miel-containers/{}", handle.id);
-        debug!("Removing container directory: {}", container_path);
-        if let Err(e) = std::fs::remove_dir_all(&container_path) {
-            warn!(
-                "Failed to remove container directory {}: {}",
-                container_path, e
-            );
-        } else {
-            debug!("Container directory removed: {}", container_path);
+        // Uploads and emails were collected when the session capture was finalized
+        self.remove_resources(&handle.runtime, &handle.id).await;
+
+        if let Some(filter) = self.egress_filters.remove(&handle.id) {
+            debug!("Removing egress rules of container: {}", handle.id);
+            filter.remove().await;
         }
 
+        self.journal
+            .record(JournalEntry::ContainerRemoved {
+                container_id: handle.id.clone(),
+            })
+            .await;
         debug!("Container cleanup completed: {}", handle.id);
         Ok(())
     }
 
+    /// Removes what the runtime keeps of container `container_id` once its process
+    /// is stopped, along with its upload and mail directories
+    async fn remove_resources(&self, runtime: &Runtime, container_id: &str) {
+        match runtime {
+            Runtime::SystemdNspawn => {
+                // Clean up container directory
+                let container_path = self.paths.rootfs(container_id);
+                debug!("Removing container directory: {}", container_path.display());
+                if let Err(e) = std::fs::remove_dir_all(&container_path) {
+                    warn!(
+                        "Failed to remove container directory {}: {}",
+                        container_path.display(),
+                        e
+                    );
+                } else {
+                    debug!("Container directory removed: {}", container_path.display());
+                }
+            }
+            Runtime::Docker | Runtime::Podman => {
+                // Killing the attached client does not stop the container itself
+                let binary = runtime.binary();
+                debug!("Removing {} container: {}", binary, container_id);
+                match Command::new(binary)
+                    .arg("rm")
+                    .arg("--force")
+                    .arg(container_id)
+                    .output()
+                    .await
+                {
+                    Ok(output) if output.status.success() => {
+                        debug!("{} container removed: {}", binary, container_id)
+                    }
+                    Ok(output) => warn!(
+                        "Failed to remove {} container {}: {}",
+                        binary,
+                        container_id,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ),
+                    Err(e) => warn!("Failed to run {} rm for {}: {}", binary, container_id, e),
+                }
+            }
+            Runtime::ProcessSandbox => {
+                let root = self.paths.sandbox(container_id);
+                if let Err(e) = std::fs::remove_dir_all(&root) {
+                    warn!(
+                        "Failed to remove sandbox directory {}: {}",
+                        root.display(),
+                        e
+                    );
+                }
+            }
+        }
+
+        let log_dir = &self.paths.log_dir;
+        for dir in [
+            upload_dir(log_dir, container_id),
+            mail_dir(log_dir, container_id),
+        ] {
+            if dir.exists() {
+                if let Err(e) = std::fs::remove_dir_all(&dir) {
+                    warn!(
+                        "Failed to remove {} of {}: {}",
+                        dir.display(),
+                        container_id,
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    /// Stops and removes a container a previous run left behind, see
+    /// [`journal::recover`](crate::session_management::journal::recover).
+    ///
+    /// Its egress rules were flushed when the manager was created. The processes
+    /// of a process sandbox cannot be told apart once their parent is gone, only
+    /// its directory is removed.
+    pub async fn remove_orphan(&self, container: &JournaledContainer) {
+        let container_id = &container.container_id;
+        info!("Removing container {} left by a previous run", container_id);
+        // nspawn outlives its parent, machined knows the container by its name
+        if container.runtime == Runtime::SystemdNspawn {
+            match Command::new("machinectl")
+                .arg("terminate")
+                .arg(container_id)
+                .output()
+                .await
+            {
+                Ok(output) if output.status.success() => {
+                    debug!("Container {} terminated", container_id)
+                }
+                Ok(_) => debug!("Container {} was not running", container_id),
+                Err(e) => warn!(
+                    "Failed to run machinectl terminate for {}: {}",
+                    container_id, e
+                ),
+            }
+        }
+        self.remove_resources(&container.runtime, container_id)
+            .await;
+    }
+
+    /// Stops and removes what previous runs left of their containers and the
+    /// journal does not know of, e.g. after a crash of a run predating it,
+    /// returning how many were reclaimed:
+    /// - the nspawn machines listed by `machinectl` are terminated;
+    /// - the Docker and Podman containers are force-removed;
+    /// - the directories of the nspawn containers and process sandboxes, and the
+    ///   upload and mail directories of the containers are removed, see
+    ///   [`ContainerPaths`].
+    ///
+    /// Containers are told apart by the `miel-` prefix of their name, which no
+    /// other program on the host is expected to use. Must run before any container
+    /// is started.
+    pub async fn reclaim_leftovers(&self) -> usize {
+        let mut machines = 0;
+        // nspawn machines outlive the run that started them
+        if let Some(listed) = Self::list_names("machinectl", &["list", "--no-legend"]).await {
+            for name in self.leftovers(&listed) {
+                match Command::new("machinectl")
+                    .arg("terminate")
+                    .arg(&name)
+                    .output()
+                    .await
+                {
+                    Ok(output) if output.status.success() => machines += 1,
+                    Ok(output) => warn!(
+                        "Failed to terminate leftover machine {}: {}",
+                        name,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ),
+                    Err(e) => warn!("Failed to run machinectl terminate for {}: {}", name, e),
+                }
+            }
+        }
+
+        let mut containers = 0;
+        for runtime in [Runtime::Docker, Runtime::Podman] {
+            let args = [
+                "ps",
+                "--all",
+                "--filter",
+                "name=^miel-",
+                "--format",
+                "{{.Names}}",
+            ];
+            let Some(listed) = Self::list_names(runtime.binary(), &args).await else {
+                continue;
+            };
+            for name in self.leftovers(&listed) {
+                self.remove_resources(&runtime, &name).await;
+                containers += 1;
+            }
+        }
+
+        let directories = self.remove_stale_dirs();
+        if machines + containers + directories > 0 {
+            info!(
+                "Reclaimed what previous runs left: {} nspawn machine(s), {} Docker or Podman container(s), {} stale directories",
+                machines, containers, directories
+            );
+        }
+        machines + containers + directories
+    }
+
+    /// Output of `binary` listing containers, `None` when it is not installed or
+    /// fails, e.g. when its daemon is not running
+    async fn list_names(binary: &str, args: &[&str]) -> Option<String> {
+        let output = tokio::time::timeout(
+            LIST_TIMEOUT,
+            Command::new(binary)
+                .args(args)
+                .stdin(Stdio::null())
+                .output(),
+        )
+        .await
+        .ok()?
+        .ok()?;
+        if !output.status.success() {
+            debug!(
+                "Cannot list the containers with {}: {}",
+                binary,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return None;
+        }
+        Some(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    /// Names of containers that are not tracked among those `listed`, one per line
+    /// in the first column
+    fn leftovers(&self, listed: &str) -> Vec<String> {
+        listed
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .filter(|name| name.starts_with(CONTAINER_PREFIX))
+            .filter(|name| !self.active_containers.contains_key(*name))
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Removes the directories of the containers that are not tracked, returning
+    /// how many were removed
+    fn remove_stale_dirs(&self) -> usize {
+        let roots: [(&Path, ContainerOfDir); 3] = [
+            (&self.paths.container_dir, |name| Some(name)),
+            (&self.paths.sandbox_dir, |name| Some(name)),
+            (&self.paths.log_dir, |name| {
+                let name = name.strip_prefix("container-")?;
+                name.strip_suffix("-uploads")
+                    .or_else(|| name.strip_suffix("-mail"))
+            }),
+        ];
+        let mut removed = 0;
+        for (root, container_of) in roots {
+            let Ok(entries) = std::fs::read_dir(root) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let Some(container_id) = container_of(&name) else {
+                    continue;
+                };
+                let path = entry.path();
+                if !container_id.starts_with(CONTAINER_PREFIX)
+                    || self.active_containers.contains_key(container_id)
+                    || !path.is_dir()
+                {
+                    continue;
+                }
+                match std::fs::remove_dir_all(&path) {
+                    Ok(()) => removed += 1,
+                    Err(e) => warn!(
+                        "Failed to remove stale container directory {}: {}",
+                        path.display(),
+                        e
+                    ),
+                }
+            }
+        }
+        removed
+    }
+
+    /// Checks that the container of `handle` is still up, recording the outcome in
+    /// `handle.health`.
+    ///
+    /// The runtime process must still run and, for TCP services, the published port
+    /// must accept a connection. Connections the probe opens are closed right away
+    /// and never reach the session capture. A container found unhealthy is counted
+    /// as a failure, once.
+    pub async fn check_health(
+        &mut self,
+        handle: &mut ContainerHandle,
+        service_config: &ServiceConfig,
+    ) -> ContainerHealth {
+        let mut health = match handle.process_handle.as_mut().map(Child::try_wait) {
+            Some(Ok(Some(status))) => ContainerHealth::Exited(status.to_string()),
+            Some(Err(e)) => {
+                debug!("Cannot check the process of container {}: {}", handle.id, e);
+                ContainerHealth::Healthy
+            }
+            Some(Ok(None)) | None => ContainerHealth::Healthy,
+        };
+        if health.is_healthy() && service_config.protocol == Protocol::TCP {
+            let probe = tokio::time::timeout(HEALTH_PROBE_TIMEOUT, handle.connect_service()).await;
+            if !matches!(probe, Ok(Ok(_))) {
+                health = ContainerHealth::PortClosed;
+            }
+        }
+
+        if !health.is_healthy() && handle.health.is_healthy() {
+            warn!("Container {} is unhealthy: {}", handle.id, health);
+            self.stats.failed_count += 1;
+        }
+        handle.health = health.clone();
+        if let Some(registered) = self.active_containers.get_mut(&handle.id) {
+            registered.health = health.clone();
+        }
+        health
+    }
+
+    /// Replaces the container of `handle` by a new one of the same service and
+    /// persona, for a session to go on after its container stopped.
+    ///
+    /// Errors if the new container cannot be created, the old one being cleaned up
+    /// regardless.
+    pub async fn restart_container(
+        &mut self,
+        handle: ContainerHandle,
+        service_config: &ServiceConfig,
+    ) -> Result<ContainerHandle, ContainerError> {
+        let old_id = handle.id.clone();
+        let persona = handle.persona.clone();
+        if let Err(e) = self.cleanup_container(handle).await {
+            warn!("Failed to clean up stopped container {}: {}", old_id, e);
+        }
+        let handle = self.create_container_as(service_config, persona).await?;
+        info!("Container {} restarted as {}", old_id, handle.id);
+        Ok(handle)
+    }
+
     /// Cleans up all tracked containers, continuing on errors and counting failures.
     pub async fn cleanup_all_containers(&mut self) -> Result<(), ContainerError> {
         let container_count = self.active_containers.len();
@@ -249,6 +882,26 @@ impl ContainerManager {
         result
     }
 
+    /// Describes the active containers at `now`, oldest first, without their
+    /// resource usage which [`resource_usage`](super::cgroup::resource_usage) reads.
+    pub fn list_containers(&self, now: DateTime<Utc>) -> Vec<ContainerInfo> {
+        let mut containers: Vec<ContainerInfo> = self
+            .active_containers
+            .values()
+            .map(|handle| ContainerInfo {
+                id: handle.id.clone(),
+                service_name: handle.service_name.clone(),
+                runtime: handle.runtime.clone(),
+                created_at: handle.created_at,
+                age_secs: (now - handle.created_at).num_seconds().max(0) as u64,
+                healthy: handle.health == ContainerHealth::Healthy,
+                usage: None,
+            })
+            .collect();
+        containers.sort_by_key(|info| info.created_at);
+        containers
+    }
+
     /// Lists the identifiers of all active containers.
     pub fn list_active_containers(&self) -> Vec<String> {
         let ids = self.active_containers.keys().cloned().collect::<Vec<_>>();
@@ -256,15 +909,20 @@ impl ContainerManager {
         ids
     }
 
-    /// Checks whether the selected container runtime is available on the system.
-    fn is_runtime_available() -> bool {
-        let available = std::process::Command::new("systemd-nspawn")
-            .arg("--version")
+    /// Checks whether the given container runtime is available on the system.
+    fn is_runtime_available(runtime: &Runtime) -> bool {
+        // The POSIX sh of the process sandbox has no --version
+        let check: &[&str] = match runtime {
+            Runtime::ProcessSandbox => &["-c", "true"],
+            _ => &["--version"],
+        };
+        let available = std::process::Command::new(runtime.binary())
+            .args(check)
             .output()
             .map(|output| output.status.success())
             .unwrap_or(false);
 
-        debug!("systemd-nspawn availability: {}", available);
+        debug!("{} availability: {}", runtime.binary(), available);
         available
     }
 
@@ -277,7 +935,7 @@ impl ContainerManager {
         debug!("Creating systemd-nspawn container: {}", container_id);
 
         // Create a basic container directory structure
-        let container_path = format!("/tmp/miel-containers/{}", container_id);
+        let container_path = self.paths.rootfs(container_id).display().to_string();
         debug!("Preparing container directory: {}", container_path);
         std::fs::create_dir_all(&container_path).map_err(|e| {
             error!(
@@ -287,12 +945,21 @@ impl ContainerManager {
             ContainerError::CreationFailed(format!("Failed to create container directory: {}", e))
         })?;
 
-        // Create a basic rootfs structure
-        self.setup_container_rootfs(&container_path, service_config)
-            .await?;
+        // Unpack the service image when there is one, otherwise fabricate a basic rootfs
+        let provisioned = self
+            .image_provisioner
+            .provision(&service_config.container_image, Path::new(&container_path))?;
+        if !provisioned {
+            self.setup_container_rootfs(&container_path, service_config)
+                .await?;
+        }
 
         // Apply obfuscation enhancements to the container
-        ObfuscationManager::setup_obfuscation(&container_path, &service_config.obfuscation)?;
+        ObfuscationManager::setup_obfuscation(
+            &container_path,
+            container_id,
+            &service_config.obfuscation,
+        )?;
 
         // Prepare systemd-nspawn command
         let mut cmd = Command::new("systemd-nspawn");
@@ -302,66 +969,46 @@ impl ContainerManager {
             .arg("--bind-ro=/etc/resolv.conf");
 
         // Create and bind the log directory so containers can write to it
-        let log_dir = "/tmp/miel-logs";
-        std::fs::create_dir_all(log_dir).map_err(|e| {
-            error!("Failed to create log directory {}: {}", log_dir, e);
-            ContainerError::CreationFailed(format!("Failed to create log directory: {}", e))
-        })?;
-
-        // Set permissions on the log directory to be writable by all users
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            let mut perms = std::fs::metadata(log_dir)
-                .map_err(|e| {
-                    error!("Failed to get log directory metadata: {}", e);
-                    ContainerError::CreationFailed(format!(
-                        "Failed to get log directory metadata: {}",
-                        e
-                    ))
-                })?
-                .permissions();
-            perms.set_mode(0o777); // rwxrwxrwx - allow all users to write
-            std::fs::set_permissions(log_dir, perms).map_err(|e| {
-                error!("Failed to set log directory permissions: {}", e);
-                ContainerError::CreationFailed(format!(
-                    "Failed to set log directory permissions: {}",
-                    e
-                ))
-            })?;
-        }
+        let log_dir = self.paths.log_dir.display().to_string();
+        Self::create_log_dir(&log_dir)?;
 
         // Bind the log directory so it's accessible from within the container
         cmd.arg(format!("--bind={}", log_dir));
         debug!("Log directory bound to container: {}", log_dir);
 
         // Bind essential host dirs so common binaries and their libs are available
-        // inside the minimal rootfs. Only bind paths that exist on the host.
-        let mut bound_paths = 0;
-        for p in [
-            "/bin",
-            "/usr/bin",
-            "/sbin",
-            "/usr/sbin",
-            "/usr/libexec",
-            "/lib",
-            "/lib64",
-            "/usr/lib",
-            "/usr/lib64",
-        ]
-        .iter()
-        {
-            if Path::new(p).exists() {
-                cmd.arg(format!("--bind-ro={}", p));
-                bound_paths += 1;
+        // inside the minimal rootfs. Only bind paths that exist on the host. Images
+        // bring their own userland and are left untouched.
+        if !provisioned {
+            let mut bound_paths = 0;
+            for p in [
+                "/bin",
+                "/usr/bin",
+                "/sbin",
+                "/usr/sbin",
+                "/usr/libexec",
+                "/lib",
+                "/lib64",
+                "/usr/lib",
+                "/usr/lib64",
+            ]
+            .iter()
+            {
+                if Path::new(p).exists() {
+                    cmd.arg(format!("--bind-ro={}", p));
+                    bound_paths += 1;
+                }
             }
+            debug!("Bound {} system paths to container", bound_paths);
         }
-        debug!("Bound {} system paths to container", bound_paths);
+
+        cmd.args(Self::nspawn_resource_args(&service_config.resources));
 
         cmd.arg(format!("--machine={}", container_id))
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped());
+        let pty = Self::attach_pty(&mut cmd, container_id);
 
         // Allocate an ephemeral host port for the service
         let host_port = self.allocate_ephemeral_port(&service_config.protocol)?;
@@ -383,48 +1030,20 @@ impl ContainerManager {
             ContainerError::StartFailed(format!("Failed to spawn container: {}", e))
         })?;
 
-        // Capture stderr
-        if let Some(stderr) = process.stderr.take() {
-            let mut reader = BufReader::new(stderr).lines();
-            let cid = container_id.to_string();
-            tokio::spawn(async move {
-                while let Ok(Some(line)) = reader.next_line().await {
-                    debug!("[container:{}][stderr] {}", cid, line);
-                }
-                debug!("stderr monitoring ended for container: {}", cid);
-            });
-        }
-
-        // Create a PTY for stdio capture - now creates unified activity log
-        let pty_master = self.create_pty_master(container_id).ok();
+        drop(cmd);
+        let pty_master = pty.and_then(|master| Self::spawn_pty_reader(master, container_id));
+        let activity_log_file = self.open_activity_log(container_id).ok();
 
-        // Capture stdout and redirect to unified log file
-        if let Some(stdout) = process.stdout.take() {
-            let log_path = format!("/tmp/miel-logs/container-{}-activity.log", container_id);
-            let mut reader = BufReader::new(stdout).lines();
-            let cid = container_id.to_string();
-            tokio::spawn(async move {
-                while let Ok(Some(line)) = reader.next_line().await {
-                    debug!("[container:{}][stdout] {}", cid, line);
-                    // Also write to the unified log file
-                    if let Ok(mut file) = std::fs::OpenOptions::new().append(true).open(&log_path) {
-                        use std::io::Write;
-                        let timestamp = Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
-                        let _ = writeln!(file, "[{}] [CONTAINER] {}", timestamp, line);
-                        let _ = file.flush();
-                    }
-                }
-                debug!("stdout monitoring ended for container: {}", cid);
-            });
-        }
+        // Forward stderr to the logs, and stdout when not on the PTY to the unified log file
+        self.spawn_output_monitors(&mut process, container_id);
 
-        // Wait for the service to start up and establish a TCP connection
+        // Wait for the service to start up and establish a connection
         info!(
-            "Waiting for service to start and establishing TCP connection to container {}",
+            "Waiting for service to start and establishing connection to container {}",
             container_id
         );
-        let tcp_socket = self
-            .establish_container_connection(host_port, container_id)
+        let (tcp_socket, udp_socket) = self
+            .connect_container_service(&service_config.protocol, host_port, container_id)
             .await?;
 
         let handle = ContainerHandle {
@@ -435,7 +1054,13 @@ impl ContainerManager {
             created_at: Utc::now(),
             process_handle: Some(process),
             pty_master,
-            tcp_socket: Some(tcp_socket),
+            activity_log_file,
+            tcp_socket,
+            udp_socket,
+            runtime: Runtime::SystemdNspawn,
+            health: ContainerHealth::Healthy,
+            persona: None,
+            log_dir: self.paths.log_dir.clone(),
         };
 
         debug!(
@@ -445,10 +1070,418 @@ impl ContainerManager {
         Ok(handle)
     }
 
-    /// Establishes a TCP connection to the container service with retry logic.
+    /// Starts the service of a [`Runtime::ProcessSandbox`] container as a child
+    /// process of miel and returns its handle.
     ///
-    /// This method waits for the service inside the container to start up and
-    /// then establishes a TCP connection that can be used for traffic forwarding.
+    /// The scripted emulators are written under `<sandbox_dir>/<id>` and run
+    /// from there with the host's `sh` and `python3`, in a process group of their
+    /// own so that the shells they start are killed with them. Nothing is isolated.
+    async fn create_process_container(
+        &self,
+        service_config: &ServiceConfig,
+        container_id: &str,
+    ) -> Result<ContainerHandle, ContainerError> {
+        debug!("Creating process sandbox: {}", container_id);
+
+        // A real sshd would run as the honeypot user with the host's accounts
+        if service_config.name == "ssh" && service_config.ssh.mode != SshMode::Embedded {
+            return Err(ContainerError::CreationFailed(
+                "The process sandbox cannot run sshd, use the embedded SSH mode".to_string(),
+            ));
+        }
+
+        let root = self.paths.sandbox(container_id).display().to_string();
+        for dir in ["usr/local/bin", "usr/bin", "tmp"] {
+            std::fs::create_dir_all(format!("{}/{}", root, dir)).map_err(|e| {
+                error!("Failed to create sandbox directory {}/{}: {}", root, dir, e);
+                ContainerError::CreationFailed(format!("Failed to create sandbox directory: {}", e))
+            })?;
+        }
+        Self::write_service_script(&root, service_config)?;
+        Self::create_log_dir(&self.paths.log_dir.display().to_string())?;
+
+        let host_port = self.allocate_ephemeral_port(&service_config.protocol)?;
+        debug!("Allocated ephemeral port {} for sandbox", host_port);
+
+        let mut cmd = Command::new("/bin/sh");
+        cmd.arg("-c")
+            .arg(self.service_command_in(&root, service_config, host_port, container_id))
+            .current_dir(&root)
+            .process_group(0)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let pty = Self::attach_pty(&mut cmd, container_id);
+
+        let mut process = cmd.spawn().map_err(|e| {
+            error!("Failed to spawn sandbox {}: {}", container_id, e);
+            ContainerError::StartFailed(format!("Failed to spawn sandbox: {}", e))
+        })?;
+
+        drop(cmd);
+        let pty_master = pty.and_then(|master| Self::spawn_pty_reader(master, container_id));
+        let activity_log_file = self.open_activity_log(container_id).ok();
+        self.spawn_output_monitors(&mut process, container_id);
+
+        let (tcp_socket, udp_socket) = match self
+            .connect_container_service(&service_config.protocol, host_port, container_id)
+            .await
+        {
+            Ok(sockets) => sockets,
+            Err(e) => {
+                Self::kill_process_group(&process, container_id);
+                let _ = process.kill().await;
+                let _ = std::fs::remove_dir_all(&root);
+                return Err(e);
+            }
+        };
+
+        warn!(
+            "Container {} is a process sandbox: its service runs unisolated on the host",
+            container_id
+        );
+        Ok(ContainerHandle {
+            id: container_id.to_string(),
+            service_name: service_config.name.clone(),
+            port: service_config.port,
+            host_port,
+            created_at: Utc::now(),
+            process_handle: Some(process),
+            pty_master,
+            activity_log_file,
+            tcp_socket,
+            udp_socket,
+            runtime: Runtime::ProcessSandbox,
+            health: ContainerHealth::Healthy,
+            persona: None,
+            log_dir: self.paths.log_dir.clone(),
+        })
+    }
+
+    /// Kills the process group led by the sandbox process, the emulator and the
+    /// shells it started
+    fn kill_process_group(process: &tokio::process::Child, container_id: &str) {
+        let Some(pid) = process.id() else {
+            return;
+        };
+        // SAFETY: killpg only sends a signal, the group was created at spawn
+        if unsafe { libc::killpg(pid as libc::pid_t, libc::SIGKILL) } != 0 {
+            debug!(
+                "Failed to kill the process group of sandbox {}: {}",
+                container_id,
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+
+    /// Creates the log directory shared with the containers, writable by all users
+    fn create_log_dir(log_dir: &str) -> Result<(), ContainerError> {
+        std::fs::create_dir_all(log_dir).map_err(|e| {
+            error!("Failed to create log directory {}: {}", log_dir, e);
+            ContainerError::CreationFailed(format!("Failed to create log directory: {}", e))
+        })?;
+
+        // Set permissions on the log directory to be writable by all users
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mut perms = std::fs::metadata(log_dir)
+                .map_err(|e| {
+                    error!("Failed to get log directory metadata: {}", e);
+                    ContainerError::CreationFailed(format!(
+                        "Failed to get log directory metadata: {}",
+                        e
+                    ))
+                })?
+                .permissions();
+            perms.set_mode(0o777); // rwxrwxrwx - allow all users to write
+            std::fs::set_permissions(log_dir, perms).map_err(|e| {
+                error!("Failed to set log directory permissions: {}", e);
+                ContainerError::CreationFailed(format!(
+                    "Failed to set log directory permissions: {}",
+                    e
+                ))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Builds the `run` arguments shared by the Docker and Podman CLIs.
+    ///
+    /// The service port inside the image (`container_port`, defaulting to `port`)
+    /// is published on `host_port` bound to 127.0.0.1. Podman containers also get
+    /// an explicit user namespace: `--userns=auto` when running as root so that
+    /// container root never maps to host root, otherwise container root is mapped
+    /// to the invoking unprivileged user (`--userns=keep-id:uid=0,gid=0`). The
+    /// service's resource limits are passed as `--memory`, `--cpus` and `--pids-limit`.
+    fn image_run_args(
+        runtime: &Runtime,
+        service_config: &ServiceConfig,
+        container_id: &str,
+        host_port: u16,
+        as_root: bool,
+    ) -> Vec<String> {
+        let container_port = service_config.container_port.unwrap_or(service_config.port);
+        let protocol = match service_config.protocol {
+            crate::configuration::types::Protocol::TCP => "tcp",
+            crate::configuration::types::Protocol::UDP => "udp",
+        };
+
+        let mut args = vec![
+            "run".to_string(),
+            "--rm".to_string(),
+            "--name".to_string(),
+            container_id.to_string(),
+            "--label".to_string(),
+            "miel.managed=true".to_string(),
+            "--publish".to_string(),
+            format!("127.0.0.1:{}:{}/{}", host_port, container_port, protocol),
+        ];
+
+        if *runtime == Runtime::Podman {
+            let userns = if as_root {
+                "auto"
+            } else {
+                "keep-id:uid=0,gid=0"
+            };
+            args.push(format!("--userns={}", userns));
+        }
+
+        let limits = &service_config.resources;
+        if let Some(memory_mb) = limits.memory_mb {
+            // Same value for swap, so that the container cannot swap past the limit
+            args.push(format!("--memory={}m", memory_mb));
+            args.push(format!("--memory-swap={}m", memory_mb));
+        }
+        if let Some(cpu_percent) = limits.cpu_percent {
+            args.push(format!("--cpus={}", cpu_percent as f64 / 100.0));
+        }
+        if let Some(pids_max) = limits.pids_max {
+            args.push(format!("--pids-limit={}", pids_max));
+        }
+
+        // Images bring their own userland, only the hostname can be faked from here
+        if service_config.obfuscation.enabled {
+            if let Some(hostname) = &service_config.obfuscation.fake_hostname {
+                args.push("--hostname".to_string());
+                args.push(hostname.clone());
+            }
+        }
+
+        args.push(service_config.container_image.clone());
+        args
+    }
+
+    /// systemd unit properties applying the service's resource limits to the nspawn
+    /// machine scope
+    fn nspawn_resource_args(limits: &ResourceLimits) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(memory_mb) = limits.memory_mb {
+            args.push(format!("--property=MemoryMax={}M", memory_mb));
+            args.push("--property=MemorySwapMax=0".to_string());
+        }
+        if let Some(cpu_percent) = limits.cpu_percent {
+            args.push(format!("--property=CPUQuota={}%", cpu_percent));
+        }
+        if let Some(pids_max) = limits.pids_max {
+            args.push(format!("--property=TasksMax={}", pids_max));
+        }
+        args
+    }
+
+    /// Creates a container from the service's `container_image` using the docker
+    /// or podman CLI.
+    ///
+    /// The container runs attached (`run --rm` without `--detach`) so that its
+    /// output can be captured like an nspawn container.
+    async fn create_image_container(
+        &self,
+        runtime: &Runtime,
+        service_config: &ServiceConfig,
+        container_id: &str,
+    ) -> Result<ContainerHandle, ContainerError> {
+        let binary = runtime.binary();
+        debug!("Creating {} container: {}", binary, container_id);
+
+        let host_port = self.allocate_ephemeral_port(&service_config.protocol)?;
+        debug!("Allocated ephemeral port {} for container", host_port);
+
+        let container_port = service_config.container_port.unwrap_or(service_config.port);
+        let as_root = *runtime == Runtime::Podman && Self::is_running_as_root();
+
+        let mut cmd = Command::new(binary);
+        cmd.args(Self::image_run_args(
+            runtime,
+            service_config,
+            container_id,
+            host_port,
+            as_root,
+        ))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+        let pty = Self::attach_pty(&mut cmd, container_id);
+
+        debug!(
+            "Starting {} process for container {} from image {}",
+            binary, container_id, service_config.container_image
+        );
+
+        let mut process = cmd.spawn().map_err(|e| {
+            error!("Failed to spawn container {}: {}", container_id, e);
+            ContainerError::StartFailed(format!("Failed to spawn container: {}", e))
+        })?;
+
+        drop(cmd);
+        let pty_master = pty.and_then(|master| Self::spawn_pty_reader(master, container_id));
+        let activity_log_file = self.open_activity_log(container_id).ok();
+
+        self.spawn_output_monitors(&mut process, container_id);
+
+        info!(
+            "Waiting for service to start and establishing connection to container {}",
+            container_id
+        );
+        let (tcp_socket, udp_socket) = match self
+            .connect_container_service(&service_config.protocol, host_port, container_id)
+            .await
+        {
+            Ok(sockets) => sockets,
+            Err(e) => {
+                // Don't leave a half-started container behind
+                let _ = process.kill().await;
+                let _ = Command::new(binary)
+                    .arg("rm")
+                    .arg("--force")
+                    .arg(container_id)
+                    .output()
+                    .await;
+                return Err(e);
+            }
+        };
+
+        let handle = ContainerHandle {
+            id: container_id.to_string(),
+            service_name: service_config.name.clone(),
+            port: container_port,
+            host_port,
+            created_at: Utc::now(),
+            process_handle: Some(process),
+            pty_master,
+            activity_log_file,
+            tcp_socket,
+            udp_socket,
+            runtime: runtime.clone(),
+            health: ContainerHealth::Healthy,
+            persona: None,
+            log_dir: self.paths.log_dir.clone(),
+        };
+
+        debug!(
+            "Successfully created {} container with TCP connection: {}",
+            binary, container_id
+        );
+        Ok(handle)
+    }
+
+    /// Sets the slave of a new PTY as stdin and stdout of `cmd`, returning its
+    /// master. The pipes set before are kept when no PTY can be opened.
+    fn attach_pty(cmd: &mut Command, container_id: &str) -> Option<File> {
+        match Pty::open().and_then(|pty| Ok((pty.master, pty.slave.try_clone()?, pty.slave))) {
+            Ok((master, stdin, stdout)) => {
+                cmd.stdin(Stdio::from(stdin)).stdout(Stdio::from(stdout));
+                Some(master)
+            }
+            Err(e) => {
+                warn!(
+                    "No PTY for container {}, falling back to pipes: {}",
+                    container_id, e
+                );
+                None
+            }
+        }
+    }
+
+    /// Starts broadcasting what is written to the PTY of a spawned container, see
+    /// [`PtyMaster`].
+    fn spawn_pty_reader(master: File, container_id: &str) -> Option<PtyMaster> {
+        PtyMaster::spawn(master, container_id)
+            .map_err(|e| warn!("Cannot read the PTY of container {}: {}", container_id, e))
+            .ok()
+    }
+
+    /// Spawns background tasks draining the runtime process output.
+    ///
+    /// stderr is only logged. stdout, when it is a pipe rather than the PTY, is
+    /// also appended to the container's unified activity log.
+    fn spawn_output_monitors(&self, process: &mut Child, container_id: &str) {
+        // Capture stderr
+        if let Some(stderr) = process.stderr.take() {
+            let mut reader = BufReader::new(stderr).lines();
+            let cid = container_id.to_string();
+            tokio::spawn(async move {
+                while let Ok(Some(line)) = reader.next_line().await {
+                    debug!("[container:{}][stderr] {}", cid, line);
+                }
+                debug!("stderr monitoring ended for container: {}", cid);
+            });
+        }
+
+        // Capture stdout and redirect to unified log file
+        if let Some(stdout) = process.stdout.take() {
+            let log_path = activity_log(&self.paths.log_dir, container_id);
+            let mut reader = BufReader::new(stdout).lines();
+            let cid = container_id.to_string();
+            tokio::spawn(async move {
+                while let Ok(Some(line)) = reader.next_line().await {
+                    debug!("[container:{}][stdout] {}", cid, line);
+                    // Also write to the unified log file
+                    if let Ok(mut file) = std::fs::OpenOptions::new().append(true).open(&log_path) {
+                        use std::io::Write;
+                        let timestamp = Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
+                        let _ = writeln!(file, "[{}] [CONTAINER] {}", timestamp, line);
+                        let _ = file.flush();
+                    }
+                }
+                debug!("stdout monitoring ended for container: {}", cid);
+            });
+        }
+    }
+
+    /// Connects to the service published on `host_port` with the service's protocol.
+    ///
+    /// TCP services are retried until the service accepts. UDP has no handshake, so
+    /// the socket is only connected to the port; datagrams sent before the service
+    /// binds are refused and dropped by the UDP proxy.
+    async fn connect_container_service(
+        &self,
+        protocol: &crate::configuration::types::Protocol,
+        host_port: u16,
+        container_id: &str,
+    ) -> Result<(Option<TcpStream>, Option<UdpSocket>), ContainerError> {
+        match protocol {
+            crate::configuration::types::Protocol::TCP => {
+                let socket = self
+                    .establish_container_connection(host_port, container_id)
+                    .await?;
+                Ok((Some(socket), None))
+            }
+            crate::configuration::types::Protocol::UDP => {
+                let socket = UdpSocket::bind("127.0.0.1:0").await?;
+                socket.connect(("127.0.0.1", host_port)).await?;
+                debug!(
+                    "UDP socket for container {} connected to 127.0.0.1:{}",
+                    container_id, host_port
+                );
+                Ok((None, Some(socket)))
+            }
+        }
+    }
+
+    /// Establishes a TCP connection to the container service with retry logic.
+    ///
+    /// This method waits for the service inside the container to start up and
+    /// then establishes a TCP connection that can be used for traffic forwarding.
     async fn establish_container_connection(
         &self,
         host_port: u16,
@@ -561,6 +1594,18 @@ impl ContainerManager {
             }
         }
 
+        Self::write_service_script(container_path, service_config)?;
+
+        debug!("Successfully set up container rootfs");
+        Ok(())
+    }
+
+    /// Writes the placeholder `/usr/bin/service` script under `root`, run by the
+    /// services no emulator exists for.
+    fn write_service_script(
+        root: &str,
+        service_config: &ServiceConfig,
+    ) -> Result<(), ContainerError> {
         // Create service script for the configuration
         debug!("Creating service files for: {}", service_config.name);
         let service_script = format!(
@@ -568,11 +1613,7 @@ impl ContainerManager {
             service_config.name, service_config.port, service_config.name
         );
 
-        std::fs::write(
-            format!("{}/usr/bin/service", container_path),
-            service_script,
-        )
-        .map_err(|e| {
+        std::fs::write(format!("{}/usr/bin/service", root), service_script).map_err(|e| {
             error!("Failed to create service script: {}", e);
             ContainerError::CreationFailed(format!("Failed to create service script: {}", e))
         })?;
@@ -582,39 +1623,216 @@ impl ContainerManager {
         {
             use std::os::unix::fs::PermissionsExt;
             debug!("Setting executable permissions for service script");
-            let mut perms = std::fs::metadata(format!("{}/usr/bin/service", container_path))
+            let mut perms = std::fs::metadata(format!("{}/usr/bin/service", root))
                 .map_err(|e| {
                     error!("Failed to get script metadata: {}", e);
                     ContainerError::CreationFailed(format!("Failed to get script metadata: {}", e))
                 })?
                 .permissions();
             perms.set_mode(0o755);
-            std::fs::set_permissions(format!("{}/usr/bin/service", container_path), perms)
-                .map_err(|e| {
-                    error!("Failed to set script permissions: {}", e);
-                    ContainerError::CreationFailed(format!(
-                        "Failed to set script permissions: {}",
-                        e
-                    ))
-                })?;
+            std::fs::set_permissions(format!("{}/usr/bin/service", root), perms).map_err(|e| {
+                error!("Failed to set script permissions: {}", e);
+                ContainerError::CreationFailed(format!("Failed to set script permissions: {}", e))
+            })?;
         }
-
-        debug!("Successfully set up container rootfs");
         Ok(())
     }
 
     /// Returns the command line to run for a given `service_config`.
     ///
     /// For SSH services, this includes comprehensive logging configuration to capture
-    /// all session activity to the unified log file.
+    /// all session activity to the unified log file. Services whose SSH connections
+    /// are terminated by miel, see [`SshMode::Embedded`], run a scripted terminal
+    /// starting the shell, command or `sftp-server` described by the first line it
+    /// receives, logging each command as `[SSH] [STDIN]`. FTP services run a scripted
+    /// daemon that accepts any credentials and stores uploads in the container's
+    /// [`upload_dir`](ContainerHandle::upload_dir). Telnet services run a scripted
+    /// login logging each attempt as `[TELNET] [LOGIN]`, followed by a logged shell.
+    /// SMTP services run a scripted MTA accepting every message, stored in the
+    /// container's [`mail_dir`](ContainerHandle::mail_dir) without being relayed.
+    /// HTTP services serve the fake web application of their [`Site`], logging the
+    /// credentials posted to it as `[HTTP] [LOGIN]` and storing uploads like FTP.
+    /// MySQL and Redis services run scripted servers speaking enough of their wire
+    /// protocol to accept any login, logged as `[MYSQL] [LOGIN]` or `[REDIS] [LOGIN]`,
+    /// and to answer the queries and commands they log as `[STDIN]`.
+    /// Other services run the dummy script.
     fn get_service_command(
         &self,
         service_config: &ServiceConfig,
         host_port: u16,
         container_id: &str,
     ) -> String {
-        let log_path = format!("/tmp/miel-logs/container-{}-activity.log", container_id);
+        self.service_command_in("", service_config, host_port, container_id)
+    }
+
+    /// Same as [`ContainerManager::get_service_command`] for a service whose files
+    /// live under `root` rather than `/`, see [`Runtime::ProcessSandbox`].
+    fn service_command_in(
+        &self,
+        root: &str,
+        service_config: &ServiceConfig,
+        host_port: u16,
+        container_id: &str,
+    ) -> String {
+        let log_path = activity_log(&self.paths.log_dir, container_id)
+            .display()
+            .to_string();
         match service_config.name.as_str() {
+            _ if service_config.ssh.mode == SshMode::Embedded => {
+                let p = host_port;
+                let hostname = service_config
+                    .obfuscation
+                    .fake_hostname
+                    .as_deref()
+                    .unwrap_or("localhost");
+                // JSON strings are valid Python string literals
+                let hostname = serde_json::to_string(hostname).unwrap_or_default();
+                format!(
+                    r##"
+                    export PATH="/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin"
+                    PY="/usr/bin/python3"; [ -x "$PY" ] || PY="/usr/local/bin/python3"; [ -x "$PY" ] || PY="/bin/python3"; [ -x "$PY" ] || PY="$(command -v python3)";
+                    if [ ! -x "$PY" ]; then echo "[$(date '+%Y-%m-%d %H:%M:%S UTC')] [SSH] [STDERR] Python 3 not found" >> {log_path}; exit 1; fi
+                    cat >{root}/usr/local/bin/ssh_shell_server.py <<'PYEOF'
+import datetime, fcntl, json, os, pty, select, signal, socket, struct, subprocess, termios, threading
+
+LOG_PATH = r"{log_path}"
+PORT = {p}
+HOSTNAME = {hostname}
+BASHRC = "{root}/tmp/miel_bashrc"
+MAX_REQUEST_LEN = 65536
+SFTP_SERVERS = ['/usr/lib/openssh/sftp-server', '/usr/libexec/openssh/sftp-server', '/usr/lib/ssh/sftp-server', '/usr/libexec/sftp-server']
+
+def log(service, stream, line):
+    ts = datetime.datetime.now(datetime.timezone.utc).strftime('%Y-%m-%d %H:%M:%S UTC')
+    tags = '[%s] [%s]' % (service, stream) if stream else '[%s]' % service
+    try:
+        with open(LOG_PATH, 'a') as f:
+            f.write('[%s] %s %s\n' % (ts, tags, line))
+    except Exception:
+        pass
+
+def one_line(text):
+    return text.replace('\\', '\\\\').replace('\r', '\\r').replace('\n', '\\n')
+
+def write_bashrc():
+    with open(BASHRC, 'w') as f:
+        f.write('''log_bash_command() {{
+  local TS="$(date '+%Y-%m-%d %H:%M:%S UTC')"
+  printf '[%s] [SSH] [STDIN] %s\\n' "$TS" "$BASH_COMMAND" >> "$MIEL_LOG_PATH"
+}}
+PS1="$MIEL_PS1"
+trap 'log_bash_command' DEBUG
+''')
+
+# miel sends what the SSH client asked for as a JSON line ahead of the channel
+def read_request(conn):
+    data = b''
+    while not data.endswith(b'\n'):
+        chunk = conn.recv(1)
+        if not chunk or len(data) > MAX_REQUEST_LEN:
+            return None
+        data += chunk
+    return json.loads(data.decode('utf-8', 'replace'))
+
+def environment(request):
+    user = request.get('user') or 'root'
+    home = '/root' if user == 'root' else '/home/' + user
+    if not os.path.isdir(home):
+        home = '/'
+    prompt = '#' if user == 'root' else '\\$'
+    return dict(
+        PATH='/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin',
+        HOME=home, USER=user, LOGNAME=user, SHELL='/bin/bash',
+        TERM=request.get('term') or 'dumb',
+        PS1='%s@%s:\\w%s ' % (user, HOSTNAME, prompt),
+        MIEL_PS1='%s@%s:\\w%s ' % (user, HOSTNAME, prompt),
+        MIEL_LOG_PATH=LOG_PATH,
+    )
+
+def command_line(request):
+    if request.get('subsystem') == 'sftp':
+        log('SSH-INFO', 'SUBSYSTEM', 'sftp')
+        return [next((path for path in SFTP_SERVERS if os.path.exists(path)), '/bin/false')]
+    command = request.get('command')
+    if command is not None:
+        log('SSH', 'STDIN', one_line(command))
+        return ['/bin/sh', '-c', command]
+    log('SSH-SESSION', None, 'Interactive shell session started')
+    if os.path.exists('/bin/bash'):
+        return ['/bin/bash', '--rcfile', BASHRC, '-i']
+    return ['/bin/sh', '-i']
+
+def run_pty(conn, args, env, request):
+    pid, fd = pty.fork()
+    if pid == 0:
+        os.chdir(env['HOME'])
+        os.execvpe(args[0], args, env)
+    fcntl.ioctl(fd, termios.TIOCSWINSZ, struct.pack('HHHH', request.get('rows') or 24, request.get('cols') or 80, 0, 0))
+    try:
+        while True:
+            readable, _, _ = select.select([conn, fd], [], [])
+            if conn in readable:
+                data = conn.recv(4096)
+                if not data:
+                    break
+                os.write(fd, data)
+            if fd in readable:
+                try:
+                    data = os.read(fd, 4096)
+                except OSError:
+                    break
+                if not data:
+                    break
+                conn.sendall(data)
+    finally:
+        try:
+            os.kill(pid, signal.SIGHUP)
+        except OSError:
+            pass
+        os.waitpid(pid, 0)
+        os.close(fd)
+
+def handle(conn, address):
+    try:
+        request = read_request(conn)
+        if request is None:
+            return
+        log('SSH-INFO', 'LOGIN', 'user %s from %s:%d' % (one_line(str(request.get('user'))), address[0], address[1]))
+        args = command_line(request)
+        env = environment(request)
+        if request.get('term') and not request.get('subsystem'):
+            run_pty(conn, args, env, request)
+        else:
+            subprocess.run(args, stdin=conn.fileno(), stdout=conn.fileno(), stderr=conn.fileno(),
+                           cwd=env['HOME'], env=env)
+        log('SSH-EXIT', None, 'Session closed')
+    except Exception as e:
+        log('SSH-ERROR', 'SESSION', str(e))
+    finally:
+        conn.close()
+
+def main():
+    write_bashrc()
+    srv = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+    srv.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
+    srv.bind(("127.0.0.1", PORT))
+    srv.listen(50)
+    log('SSH-INFO', 'SERVER', 'listening on 127.0.0.1:%d' % PORT)
+    while True:
+        conn, address = srv.accept()
+        threading.Thread(target=handle, args=(conn, address), daemon=True).start()
+
+if __name__ == "__main__":
+    main()
+PYEOF
+                    chmod +x {root}/usr/local/bin/ssh_shell_server.py
+                    exec "$PY" {root}/usr/local/bin/ssh_shell_server.py
+                "##,
+                    p = p,
+                    log_path = log_path,
+                    hostname = hostname
+                )
+            }
             "ssh" => {
                 let p = host_port;
                 format!(
@@ -707,39 +1925,435 @@ EOF
             }
             "http" => {
                 let p = host_port;
+                let hostname = service_config
+                    .obfuscation
+                    .fake_hostname
+                    .as_deref()
+                    .unwrap_or("localhost");
+                // JSON objects of strings are valid Python dict literals
+                let site =
+                    serde_json::to_string(&Site::from_config(&service_config.http, hostname))
+                        .unwrap_or_default();
                 format!(
-                    r#"
+                    r##"
                     export PATH="/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin"
-                    PY="/usr/bin/python3"; [ -x "$PY" ] || PY="/usr/local/bin/python3"; [ -x "$PY" ] || PY="/bin/python3";
+                    PY="/usr/bin/python3"; [ -x "$PY" ] || PY="/usr/local/bin/python3"; [ -x "$PY" ] || PY="/bin/python3"; [ -x "$PY" ] || PY="$(command -v python3)";
                     if [ ! -x "$PY" ]; then echo "[$(date '+%Y-%m-%d %H:%M:%S UTC')] [HTTP] [STDERR] Python 3 not found" >> {log_path}; exit 1; fi
-                    cat >/usr/local/bin/http_server.py <<'PYEOF'
-import socket, threading, datetime
+                    cat >{root}/usr/local/bin/http_server.py <<'PYEOF'
+import datetime, json, mimetypes, os, re, socket, threading
+from urllib.parse import parse_qs
 
 LOG_PATH = r"{log_path}"
+UPLOAD_DIR = r"{upload_dir}"
 PORT = {p}
-
-def log(line):
+SITE = {site}
+MAX_UPLOAD = {max_upload}
+MAX_BODY = MAX_UPLOAD + 1024 * 1024
+MAX_HEADERS = 65536
+USER_FIELDS = ('username', 'user', 'login', 'email', 'pma_username', 'log', 'uname', 'userid')
+PASS_FIELDS = ('password', 'pass', 'passwd', 'pwd', 'pma_password', 'secret')
+REASONS = dict([(200, 'OK'), (400, 'Bad Request'), (404, 'Not Found'), (405, 'Method Not Allowed')])
+LOCK = threading.Lock()
+UPLOADS = [0]
+
+def log(service, stream, line):
+    ts = datetime.datetime.now(datetime.timezone.utc).strftime('%Y-%m-%d %H:%M:%S UTC')
     try:
         with open(LOG_PATH, 'a') as f:
-            f.write(line + "\n")
-            f.flush()
+            f.write('[%s] [%s] [%s] %s\n' % (ts, service, stream, line))
     except Exception:
         pass
 
-def handle(conn, addr):
+def one_line(text):
+    return text.replace('\\', '\\\\').replace('\r', '\\r').replace('\n', '\\n')
+
+def upload_path(name):
+    name = os.path.basename(name.replace('\\', '/')).strip() or 'upload'
+    name = ''.join(c if c.isalnum() or c in '._-' else '_' for c in name)[:128]
+    with LOCK:
+        UPLOADS[0] += 1
+        sequence = UPLOADS[0]
+    os.makedirs(UPLOAD_DIR, exist_ok=True)
+    return os.path.join(UPLOAD_DIR, '%04d-%s' % (sequence, name))
+
+def save_upload(name, content):
+    # One byte over the limit marks the upload as truncated
+    with open(upload_path(name), 'wb') as f:
+        f.write(content[:MAX_UPLOAD + 1])
+    log('HTTP-INFO', 'UPLOAD', 'received %d bytes for %s' % (len(content), one_line(name)))
+
+def multipart(body, content_type):
+    fields, files = dict(), []
+    boundary = re.search(r'boundary="?([^";]+)"?', content_type)
+    if not boundary:
+        return fields, files
+    for part in body.split(b'--' + boundary.group(1).encode('latin-1'))[1:]:
+        if part.startswith(b'--'):
+            break
+        head, _, content = part.partition(b'\r\n\r\n')
+        if content.endswith(b'\r\n'):
+            content = content[:-2]
+        head = head.decode('utf-8', 'replace')
+        name = re.search(r'\bname="([^"]*)"', head)
+        filename = re.search(r'\bfilename="([^"]*)"', head)
+        if filename:
+            files.append((filename.group(1), content))
+        elif name:
+            fields[name.group(1)] = content.decode('utf-8', 'replace')
+    return fields, files
+
+def form(body, content_type):
+    if content_type.startswith('multipart/form-data'):
+        return multipart(body, content_type)
+    if content_type.startswith('application/json'):
+        try:
+            data = json.loads(body.decode('utf-8', 'replace'))
+        except ValueError:
+            return dict(), []
+        if isinstance(data, dict):
+            return dict((k, str(v)) for k, v in data.items()), []
+        return dict(), []
+    fields = parse_qs(body.decode('utf-8', 'replace'), keep_blank_values=True)
+    return dict((k, v[0]) for k, v in fields.items()), []
+
+def field(fields, names):
+    lowered = dict((k.lower(), v) for k, v in fields.items())
+    for name in names:
+        if name in lowered:
+            return lowered[name]
+    return None
+
+def page(path, error=''):
+    body = SITE['pages'].get(path)
+    if body is None:
+        return None
+    return body.replace('{{{{error}}}}', error)
+
+def respond(conn, method, status, body, content_type='text/html; charset=UTF-8', keep_alive=True):
+    data = body.encode('utf-8')
+    head = 'HTTP/1.1 %d %s\r\nServer: %s\r\nDate: %s\r\nContent-Type: %s\r\nContent-Length: %d\r\nConnection: %s\r\n\r\n' % (
+        status, REASONS.get(status, 'OK'), SITE['server'],
+        datetime.datetime.now(datetime.timezone.utc).strftime('%a, %d %b %Y %H:%M:%S GMT'),
+        content_type, len(data), 'keep-alive' if keep_alive else 'close')
+    conn.sendall(head.encode('latin-1', 'replace') + (b'' if method == 'HEAD' else data))
+
+def read_request(reader):
+    line = reader.readline(MAX_HEADERS)
+    if not line:
+        return None
+    headers = dict()
+    size = len(line)
+    while True:
+        raw = reader.readline(MAX_HEADERS)
+        size += len(raw)
+        if not raw or raw in (b'\r\n', b'\n') or size > MAX_HEADERS:
+            break
+        name, _, value = raw.decode('latin-1').partition(':')
+        headers[name.strip().lower()] = value.strip()
+    body = b''
     try:
-        data = conn.recv(4096)
-        line = data.decode('utf-8', errors='ignore').splitlines()[0] if data else ''
-        ts = datetime.datetime.now(datetime.timezone.utc).strftime('%Y-%m-%d %H:%M:%S UTC')
-        if line:
-            log('[%s] [HTTP] [STDIN] %s' % (ts, line))
+        length = int(headers.get('content-length', '0'))
+    except ValueError:
+        length = 0
+    if length > 0:
+        body = reader.read(min(length, MAX_BODY))
+        left = length - len(body)
+        while left > 0:
+            chunk = reader.read(min(left, 65536))
+            if not chunk:
+                break
+            left -= len(chunk)
+    return line.decode('latin-1').rstrip('\r\n'), headers, body
+
+def handle_request(conn, request):
+    line, headers, body = request
+    log('HTTP', 'STDIN', line)
+    parts = line.split(' ')
+    if len(parts) != 3 or not parts[2].startswith('HTTP/'):
+        respond(conn, 'GET', 400, SITE['not_found'].replace('404 Not Found', '400 Bad Request'), keep_alive=False)
+        return False
+    method, target, version = parts
+    path = target.split('?', 1)[0].split('#', 1)[0] or '/'
+    keep_alive = version == 'HTTP/1.1' and headers.get('connection', '').lower() != 'close'
+
+    if method == 'POST':
+        fields, files = form(body, headers.get('content-type', '').lower())
+        for name, content in files:
+            save_upload(name, content)
+        password = field(fields, PASS_FIELDS)
+        if password is not None:
+            username = field(fields, USER_FIELDS) or ''
+            log('HTTP', 'LOGIN', json.dumps(dict(username=username, password=password, accepted=False)))
+            body = page(path, SITE['login_error'])
+            if body is None or '{{{{error}}}}' not in SITE['pages'].get(path, ''):
+                body = page(SITE['login'], SITE['login_error'])
+            respond(conn, method, 200, body, keep_alive=keep_alive)
+            return keep_alive
+    elif method not in ('GET', 'HEAD'):
+        respond(conn, method, 405, SITE['not_found'].replace('404 Not Found', '405 Not Allowed'), keep_alive=keep_alive)
+        return keep_alive
+
+    body = page(path)
+    if body is None and not path.endswith('/'):
+        body = page(path + '/')
+    if body is None:
+        respond(conn, method, 404, SITE['not_found'], keep_alive=keep_alive)
+    else:
+        content_type = mimetypes.guess_type(path)[0] if not path.endswith('/') else None
+        if content_type is None or content_type == 'text/html':
+            content_type = 'text/html; charset=UTF-8'
+        respond(conn, method, 200, body, content_type, keep_alive)
+    return keep_alive
+
+def handle(conn):
+    try:
+        reader = conn.makefile('rb')
+        while True:
+            request = read_request(reader)
+            if request is None or not handle_request(conn, request):
+                break
+    except Exception as e:
+        log('HTTP', 'STDERR', str(e))
+    finally:
         try:
-            conn.send(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nOK")
+            conn.shutdown(socket.SHUT_RDWR)
         except Exception:
             pass
+        conn.close()
+
+def main():
+    srv = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+    srv.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
+    srv.bind(("127.0.0.1", PORT))
+    srv.listen(50)
+    log('HTTP-INFO', 'SERVER', 'listening on 127.0.0.1:%d' % PORT)
+    while True:
+        conn, _ = srv.accept()
+        conn.settimeout(60)
+        threading.Thread(target=handle, args=(conn,), daemon=True).start()
+
+if __name__ == "__main__":
+    main()
+PYEOF
+                    chmod +x {root}/usr/local/bin/http_server.py
+                    exec "$PY" {root}/usr/local/bin/http_server.py
+                "##,
+                    p = p,
+                    log_path = log_path,
+                    upload_dir = upload_dir(&self.paths.log_dir, container_id).display(),
+                    site = site,
+                    max_upload = MAX_UPLOAD_LEN
+                )
+            }
+            "ftp" => {
+                let p = host_port;
+                let banner = service_config
+                    .banner_response
+                    .as_deref()
+                    .map(str::trim_end)
+                    .unwrap_or("220 (vsFTPd 3.0.3)");
+                // JSON strings are valid Python string literals
+                let banner = serde_json::to_string(banner).unwrap_or_default();
+                let passive_address = match service_config.ftp.passive_address {
+                    Some(address) => format!("\"{}\"", address),
+                    None => "None".to_string(),
+                };
+                format!(
+                    r##"
+                    export PATH="/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin"
+                    PY="/usr/bin/python3"; [ -x "$PY" ] || PY="/usr/local/bin/python3"; [ -x "$PY" ] || PY="/bin/python3"; [ -x "$PY" ] || PY="$(command -v python3)";
+                    if [ ! -x "$PY" ]; then echo "[$(date '+%Y-%m-%d %H:%M:%S UTC')] [FTP] [STDERR] Python 3 not found" >> {log_path}; exit 1; fi
+                    cat >{root}/usr/local/bin/ftp_server.py <<'PYEOF'
+import datetime, os, socket, threading
+
+LOG_PATH = r"{log_path}"
+UPLOAD_DIR = r"{upload_dir}"
+PORT = {p}
+BANNER = {banner}
+PASV_ADDRESS = {passive_address}
+PASV_PORTS = range({passive_port_min}, {passive_port_max} + 1)
+MAX_UPLOAD = {max_upload}
+LOCK = threading.Lock()
+UPLOADS = [0]
+
+def log(service, stream, line):
+    ts = datetime.datetime.now(datetime.timezone.utc).strftime('%Y-%m-%d %H:%M:%S UTC')
+    try:
+        with open(LOG_PATH, 'a') as f:
+            f.write('[%s] [%s] [%s] %s\n' % (ts, service, stream, line))
+    except Exception:
+        pass
+
+def upload_path(name):
+    name = os.path.basename(name.replace('\\', '/')).strip() or 'upload'
+    name = ''.join(c if c.isalnum() or c in '._-' else '_' for c in name)[:128]
+    with LOCK:
+        UPLOADS[0] += 1
+        sequence = UPLOADS[0]
+    os.makedirs(UPLOAD_DIR, exist_ok=True)
+    return os.path.join(UPLOAD_DIR, '%04d-%s' % (sequence, name))
+
+class Session:
+    def __init__(self, conn):
+        self.conn = conn
+        self.reader = conn.makefile('rb')
+        self.cwd = '/'
+        self.passive = None
+
+    def reply(self, line):
+        self.conn.sendall((line + '\r\n').encode('utf-8', 'replace'))
+        log('FTP', 'STDOUT', line)
+
+    def close_passive(self):
+        if self.passive is not None:
+            self.passive.close()
+            self.passive = None
+
+    def open_passive(self):
+        if PASV_ADDRESS is None:
+            return None
+        for port in PASV_PORTS:
+            s = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+            s.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
+            try:
+                s.bind(('0.0.0.0', port))
+            except OSError:
+                s.close()
+                continue
+            s.listen(1)
+            s.settimeout(30)
+            self.close_passive()
+            self.passive = s
+            return port
+        return None
+
+    def data_connection(self):
+        try:
+            if self.passive is not None:
+                conn, _ = self.passive.accept()
+                self.close_passive()
+                conn.settimeout(60)
+                return conn
+        except OSError as e:
+            log('FTP', 'STDERR', 'data connection failed: %s' % e)
+        self.close_passive()
+        return None
+
+    def path(self, arg):
+        return os.path.normpath(os.path.join(self.cwd, arg or '.'))
+
+    def receive(self, data, name):
+        path = upload_path(name)
+        stored = total = 0
+        with open(path, 'wb') as f:
+            while True:
+                try:
+                    chunk = data.recv(65536)
+                except OSError:
+                    break
+                if not chunk:
+                    break
+                total += len(chunk)
+                # One byte over the limit marks the upload as truncated
+                kept = chunk[:max(0, MAX_UPLOAD + 1 - stored)]
+                f.write(kept)
+                stored += len(kept)
+        data.close()
+        log('FTP-INFO', 'UPLOAD', 'received %d bytes for %s' % (total, name))
+
+    def transfer(self, arg, send=None):
+        data = self.data_connection()
+        if data is None:
+            self.reply('425 Use PORT or PASV first.')
+            return
+        if send is None:
+            self.reply('150 Ok to send data.')
+            self.receive(data, arg)
+            self.reply('226 Transfer complete.')
+        else:
+            self.reply('150 Here comes the directory listing.')
+            try:
+                data.sendall(send)
+            finally:
+                data.close()
+            self.reply('226 Directory send OK.')
+
+    def dispatch(self, cmd, arg):
+        if cmd == 'USER':
+            self.reply('331 Please specify the password.')
+        elif cmd == 'PASS':
+            self.reply('230 Login successful.')
+        elif cmd == 'AUTH':
+            self.reply('530 Please login with USER and PASS.')
+        elif cmd == 'SYST':
+            self.reply('215 UNIX Type: L8')
+        elif cmd == 'FEAT':
+            for line in ('211-Features:', ' EPSV', ' PASV', ' SIZE', ' UTF8', '211 End'):
+                self.reply(line)
+        elif cmd in ('PWD', 'XPWD'):
+            self.reply('257 "%s" is the current directory' % self.cwd)
+        elif cmd in ('CWD', 'XCWD', 'CDUP'):
+            self.cwd = self.path('..' if cmd == 'CDUP' else arg)
+            self.reply('250 Directory successfully changed.')
+        elif cmd == 'TYPE':
+            self.reply('200 Switching to %s mode.' % ('ASCII' if arg.upper().startswith('A') else 'Binary'))
+        elif cmd == 'OPTS':
+            self.reply('200 Always in UTF8 mode.')
+        elif cmd == 'NOOP':
+            self.reply('200 NOOP ok.')
+        elif cmd in ('PORT', 'EPRT'):
+            # Active mode would connect wherever the client asks, from the honeypot
+            self.reply('550 Permission denied.')
+        elif cmd in ('PASV', 'EPSV'):
+            port = self.open_passive()
+            if port is None:
+                self.reply('425 Cannot open passive connection.')
+            elif cmd == 'PASV':
+                self.reply('227 Entering Passive Mode (%s,%d,%d).' % (PASV_ADDRESS.replace('.', ','), port // 256, port % 256))
+            else:
+                self.reply('229 Entering Extended Passive Mode (|||%d|)' % port)
+        elif cmd == 'LIST':
+            self.transfer(arg, b'drwxr-xr-x    2 0        0            4096 Mar 02 09:14 pub\r\n')
+        elif cmd in ('NLST', 'MLSD'):
+            self.transfer(arg, b'pub\r\n')
+        elif cmd in ('STOR', 'STOU', 'APPE'):
+            self.transfer(arg)
+        elif cmd == 'RETR':
+            self.reply('550 Failed to open file.')
+        elif cmd == 'SIZE':
+            self.reply('550 Could not get file size.')
+        elif cmd in ('MKD', 'XMKD'):
+            self.reply('257 "%s" created' % self.path(arg))
+        elif cmd in ('DELE', 'RMD', 'XRMD', 'RNTO'):
+            self.reply('250 Requested file action okay, completed.')
+        elif cmd == 'RNFR':
+            self.reply('350 Ready for RNTO.')
+        elif cmd == 'QUIT':
+            self.reply('221 Goodbye.')
+            return False
+        else:
+            self.reply('500 Unknown command.')
+        return True
+
+    def run(self):
+        self.reply(BANNER)
+        while True:
+            raw = self.reader.readline(4096)
+            if not raw:
+                break
+            line = raw.decode('utf-8', 'replace').rstrip('\r\n')
+            if not line.strip():
+                continue
+            log('FTP', 'STDIN', line)
+            cmd, _, arg = line.partition(' ')
+            if not self.dispatch(cmd.upper(), arg.strip()):
+                break
+
+def handle(conn):
+    try:
+        Session(conn).run()
     except Exception as e:
-        ts = datetime.datetime.now(datetime.timezone.utc).strftime('%Y-%m-%d %H:%M:%S UTC')
-        log('[%s] [HTTP] [STDERR] %s' % (ts, str(e)))
+        log('FTP', 'STDERR', str(e))
     finally:
         try:
             conn.shutdown(socket.SHUT_RDWR)
@@ -752,27 +2366,1043 @@ def main():
     srv.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
     srv.bind(("127.0.0.1", PORT))
     srv.listen(50)
+    log('FTP-INFO', 'SERVER', 'listening on 127.0.0.1:%d' % PORT)
+    while True:
+        conn, _ = srv.accept()
+        conn.settimeout(300)
+        threading.Thread(target=handle, args=(conn,), daemon=True).start()
+
+if __name__ == "__main__":
+    main()
+PYEOF
+                    chmod +x {root}/usr/local/bin/ftp_server.py
+                    exec "$PY" {root}/usr/local/bin/ftp_server.py
+                "##,
+                    p = p,
+                    log_path = log_path,
+                    upload_dir = upload_dir(&self.paths.log_dir, container_id).display(),
+                    banner = banner,
+                    passive_address = passive_address,
+                    passive_port_min = service_config.ftp.passive_port_min,
+                    passive_port_max = service_config.ftp.passive_port_max,
+                    max_upload = MAX_UPLOAD_LEN
+                )
+            }
+            "smtp" => {
+                let p = host_port;
+                let hostname = service_config
+                    .obfuscation
+                    .fake_hostname
+                    .as_deref()
+                    .unwrap_or("mail.localdomain");
+                let banner = match service_config.banner_response.as_deref() {
+                    Some(banner) => banner.trim_end().to_string(),
+                    None => format!("220 {} ESMTP Postfix (Ubuntu)", hostname),
+                };
+                // JSON strings are valid Python string literals
+                let banner = serde_json::to_string(&banner).unwrap_or_default();
+                let hostname = serde_json::to_string(hostname).unwrap_or_default();
+                format!(
+                    r##"
+                    export PATH="/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin"
+                    PY="/usr/bin/python3"; [ -x "$PY" ] || PY="/usr/local/bin/python3"; [ -x "$PY" ] || PY="/bin/python3"; [ -x "$PY" ] || PY="$(command -v python3)";
+                    if [ ! -x "$PY" ]; then echo "[$(date '+%Y-%m-%d %H:%M:%S UTC')] [SMTP] [STDERR] Python 3 not found" >> {log_path}; exit 1; fi
+                    cat >{root}/usr/local/bin/smtp_server.py <<'PYEOF'
+import base64, binascii, datetime, json, os, socket, threading
+
+LOG_PATH = r"{log_path}"
+MAIL_DIR = r"{mail_dir}"
+PORT = {p}
+BANNER = {banner}
+HOSTNAME = {hostname}
+MAX_MESSAGE = {max_message}
+MAX_RECIPIENTS = 100
+LOCK = threading.Lock()
+MESSAGES = [0]
+
+def log(service, stream, line):
+    ts = datetime.datetime.now(datetime.timezone.utc).strftime('%Y-%m-%d %H:%M:%S UTC')
+    try:
+        with open(LOG_PATH, 'a') as f:
+            f.write('[%s] [%s] [%s] %s\n' % (ts, service, stream, line))
+    except Exception:
+        pass
+
+def store(helo, mail_from, rcpt_to, data):
+    with LOCK:
+        MESSAGES[0] += 1
+        sequence = MESSAGES[0]
+    os.makedirs(MAIL_DIR, exist_ok=True)
+    base = os.path.join(MAIL_DIR, '%04d' % sequence)
+    with open(base + '.eml', 'wb') as f:
+        f.write(data)
+    envelope = dict(helo=helo, mail_from=mail_from, rcpt_to=rcpt_to,
+                    received_at=datetime.datetime.now(datetime.timezone.utc).isoformat())
+    # The envelope marks the message as complete
+    with open(base + '.json.tmp', 'w') as f:
+        json.dump(envelope, f)
+    os.rename(base + '.json.tmp', base + '.json')
+    log('SMTP-INFO', 'MESSAGE', 'message %04d from <%s> to %d recipients (%d bytes)' % (sequence, mail_from, len(rcpt_to), len(data)))
+
+def address(arg):
+    rest = arg.partition(':')[2].strip()
+    if rest.startswith('<'):
+        end = rest.find('>')
+        return rest[1:end] if end != -1 else rest[1:]
+    return rest.split(' ')[0]
+
+def decode(value):
+    try:
+        return base64.b64decode(value.strip(), validate=True).decode('utf-8', 'replace')
+    except (binascii.Error, ValueError):
+        return value
+
+class Session:
+    def __init__(self, conn):
+        self.conn = conn
+        self.reader = conn.makefile('rb')
+        self.helo = ''
+        self.reset()
+
+    def reset(self):
+        self.mail_from = None
+        self.rcpt_to = []
+
+    def reply(self, line):
+        self.conn.sendall((line + '\r\n').encode('utf-8', 'replace'))
+        log('SMTP', 'STDOUT', line)
+
+    def read_line(self):
+        raw = self.reader.readline(4096)
+        if not raw:
+            return None
+        line = raw.decode('utf-8', 'replace').rstrip('\r\n')
+        log('SMTP', 'STDIN', line)
+        return line
+
+    def auth(self, arg):
+        mechanism, _, initial = arg.partition(' ')
+        mechanism = mechanism.upper()
+        if mechanism == 'PLAIN':
+            if not initial:
+                self.reply('334 ')
+                initial = self.read_line()
+                if initial is None:
+                    return False
+            parts = decode(initial).split('\0')
+            username, password = (parts[1], parts[2]) if len(parts) == 3 else (parts[0], '')
+        elif mechanism == 'LOGIN':
+            if initial:
+                username = decode(initial)
+            else:
+                self.reply('334 VXNlcm5hbWU6')
+                line = self.read_line()
+                if line is None:
+                    return False
+                username = decode(line)
+            self.reply('334 UGFzc3dvcmQ6')
+            line = self.read_line()
+            if line is None:
+                return False
+            password = decode(line)
+        else:
+            self.reply('504 5.5.4 Unrecognized authentication type')
+            return True
+        log('SMTP', 'LOGIN', json.dumps(dict(username=username, password=password, accepted=True)))
+        self.reply('235 2.7.0 Authentication successful')
+        return True
+
+    def data(self):
+        self.reply('354 End data with <CR><LF>.<CR><LF>')
+        data = bytearray()
+        line_start = True
+        while True:
+            raw = self.reader.readline(65536)
+            if not raw:
+                return False
+            if line_start:
+                if raw in (b'.\r\n', b'.\n'):
+                    break
+                # Dot-stuffing
+                if raw.startswith(b'.'):
+                    raw = raw[1:]
+            line_start = raw.endswith(b'\n')
+            if len(data) <= MAX_MESSAGE:
+                data.extend(raw[:MAX_MESSAGE + 1 - len(data)])
+        store(self.helo, self.mail_from, self.rcpt_to, bytes(data))
+        self.reply('250 2.0.0 Ok: queued as %s' % os.urandom(5).hex().upper())
+        self.reset()
+        return True
+
+    def handle(self):
+        self.reply(BANNER)
+        while True:
+            line = self.read_line()
+            if line is None:
+                return
+            verb, _, arg = line.partition(' ')
+            verb = verb.upper()
+            if verb in ('HELO', 'EHLO'):
+                self.helo = arg.strip()
+                self.reset()
+                if verb == 'HELO':
+                    self.reply('250 %s' % HOSTNAME)
+                else:
+                    for reply in ('250-%s' % HOSTNAME, '250-PIPELINING', '250-SIZE %d' % MAX_MESSAGE,
+                                  '250-AUTH PLAIN LOGIN', '250-8BITMIME', '250 SMTPUTF8'):
+                        self.reply(reply)
+            elif verb == 'MAIL':
+                if not arg.upper().startswith('FROM:'):
+                    self.reply('501 5.5.4 Syntax: MAIL FROM:<address>')
+                else:
+                    self.reset()
+                    self.mail_from = address(arg)
+                    self.reply('250 2.1.0 Ok')
+            elif verb == 'RCPT':
+                if self.mail_from is None:
+                    self.reply('503 5.5.1 Error: need MAIL command')
+                elif not arg.upper().startswith('TO:'):
+                    self.reply('501 5.5.4 Syntax: RCPT TO:<address>')
+                elif len(self.rcpt_to) >= MAX_RECIPIENTS:
+                    self.reply('452 4.5.3 Error: too many recipients')
+                else:
+                    self.rcpt_to.append(address(arg))
+                    self.reply('250 2.1.5 Ok')
+            elif verb == 'DATA':
+                if not self.rcpt_to:
+                    self.reply('503 5.5.1 Error: need RCPT command')
+                elif not self.data():
+                    return
+            elif verb == 'AUTH':
+                if not self.auth(arg):
+                    return
+            elif verb == 'RSET':
+                self.reset()
+                self.reply('250 2.0.0 Ok')
+            elif verb == 'NOOP':
+                self.reply('250 2.0.0 Ok')
+            elif verb == 'VRFY':
+                self.reply('252 2.0.0 Cannot VRFY user')
+            elif verb == 'STARTTLS':
+                self.reply('454 4.7.0 TLS not available due to local problem')
+            elif verb == 'QUIT':
+                self.reply('221 2.0.0 Bye')
+                return
+            else:
+                self.reply('502 5.5.2 Error: command not recognized')
+
+def handle(conn, address):
+    log('SMTP-INFO', 'CONNECT', 'connection from %s:%d' % address)
+    try:
+        Session(conn).handle()
+    except Exception as e:
+        log('SMTP-ERROR', 'SESSION', str(e))
+    finally:
+        conn.close()
+
+def main():
+    srv = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+    srv.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
+    srv.bind(("127.0.0.1", PORT))
+    srv.listen(50)
+    log('SMTP-INFO', 'SERVER', 'listening on 127.0.0.1:%d' % PORT)
+    while True:
+        conn, address = srv.accept()
+        conn.settimeout(300)
+        threading.Thread(target=handle, args=(conn, address), daemon=True).start()
+
+if __name__ == "__main__":
+    main()
+PYEOF
+                    chmod +x {root}/usr/local/bin/smtp_server.py
+                    exec "$PY" {root}/usr/local/bin/smtp_server.py
+                "##,
+                    p = p,
+                    log_path = log_path,
+                    mail_dir = mail_dir(&self.paths.log_dir, container_id).display(),
+                    banner = banner,
+                    hostname = hostname,
+                    max_message = MAX_MESSAGE_LEN
+                )
+            }
+            "telnet" => {
+                let p = host_port;
+                let banner = service_config
+                    .banner_response
+                    .as_deref()
+                    .map(str::trim_end)
+                    .unwrap_or("Ubuntu 18.04.6 LTS");
+                let hostname = service_config
+                    .obfuscation
+                    .fake_hostname
+                    .as_deref()
+                    .unwrap_or("localhost");
+                // JSON strings are valid Python string literals
+                let banner = serde_json::to_string(banner).unwrap_or_default();
+                let hostname = serde_json::to_string(hostname).unwrap_or_default();
+                format!(
+                    r##"
+                    export PATH="/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin"
+                    PY="/usr/bin/python3"; [ -x "$PY" ] || PY="/usr/local/bin/python3"; [ -x "$PY" ] || PY="/bin/python3"; [ -x "$PY" ] || PY="$(command -v python3)";
+                    if [ ! -x "$PY" ]; then echo "[$(date '+%Y-%m-%d %H:%M:%S UTC')] [TELNET] [STDERR] Python 3 not found" >> {log_path}; exit 1; fi
+                    cat >{root}/usr/local/bin/telnet_server.py <<'PYEOF'
+import datetime, json, os, socket, subprocess, threading
+
+LOG_PATH = r"{log_path}"
+PORT = {p}
+BANNER = {banner}
+HOSTNAME = {hostname}
+SHELL_TIMEOUT = 30
+
+IAC, DONT, DO, WONT, WILL, SB, SE = 255, 254, 253, 252, 251, 250, 240
+ECHO, SGA = 1, 3
+
+def log(service, stream, line):
     ts = datetime.datetime.now(datetime.timezone.utc).strftime('%Y-%m-%d %H:%M:%S UTC')
-    log('[%s] [HTTP] [STDOUT] Server listening on 127.0.0.1:%d' % (ts, PORT))
+    try:
+        with open(LOG_PATH, 'a') as f:
+            f.write('[%s] [%s] [%s] %s\n' % (ts, service, stream, line))
+    except Exception:
+        pass
+
+class Session:
+    def __init__(self, conn):
+        self.conn = conn
+        self.last_cr = False
+        self.cwd = '/root' if os.path.isdir('/root') else '/'
+
+    def send(self, text):
+        self.conn.sendall(text.replace('\n', '\r\n').encode('utf-8', 'replace'))
+
+    def read_byte(self):
+        b = self.conn.recv(1)
+        return b[0] if b else None
+
+    def read_line(self, echo):
+        line = bytearray()
+        while True:
+            c = self.read_byte()
+            if c is None:
+                return None
+            if c == IAC:
+                command = self.read_byte()
+                if command in (DO, DONT, WILL, WONT):
+                    self.read_byte()
+                elif command == SB:
+                    previous = None
+                    while True:
+                        x = self.read_byte()
+                        if x is None:
+                            return None
+                        if previous == IAC and x == SE:
+                            break
+                        previous = x
+                elif command == IAC:
+                    line.append(IAC)
+                elif command is None:
+                    return None
+                continue
+            # Lines end with CR LF, CR NUL or a bare LF
+            if self.last_cr and c in (0, 10):
+                self.last_cr = False
+                continue
+            self.last_cr = c == 13
+            if c in (10, 13):
+                if echo:
+                    self.send('\n')
+                return line.decode('utf-8', 'replace')
+            if c in (8, 127):
+                if line:
+                    line.pop()
+                    if echo:
+                        self.conn.sendall(b'\b \b')
+                continue
+            if c < 32:
+                continue
+            line.append(c)
+            if echo:
+                self.conn.sendall(bytes([c]))
+
+    def login(self):
+        while True:
+            self.send('%s login: ' % HOSTNAME)
+            username = self.read_line(True)
+            if username is None:
+                return None
+            self.send('Password: ')
+            password = self.read_line(False)
+            if password is None:
+                return None
+            self.send('\n')
+            accepted = username.strip() != ''
+            log('TELNET', 'LOGIN', json.dumps(dict(username=username, password=password, accepted=accepted)))
+            if accepted:
+                return username.strip()
+            self.send('Login incorrect\n')
+
+    def run(self, command):
+        parts = command.split()
+        if parts[0] == 'cd':
+            target = os.path.join(self.cwd, os.path.expanduser(parts[1] if len(parts) > 1 else '~'))
+            if os.path.isdir(target):
+                self.cwd = os.path.normpath(target)
+            else:
+                self.output('STDERR', '-sh: cd: %s: No such file or directory\n' % parts[1])
+            return
+        try:
+            done = subprocess.run(['/bin/sh', '-c', command], cwd=self.cwd, stdin=subprocess.DEVNULL,
+                                  stdout=subprocess.PIPE, stderr=subprocess.PIPE, timeout=SHELL_TIMEOUT)
+            self.output('STDOUT', done.stdout.decode('utf-8', 'replace'))
+            self.output('STDERR', done.stderr.decode('utf-8', 'replace'))
+        except subprocess.TimeoutExpired:
+            pass
+        except Exception as e:
+            self.output('STDERR', '-sh: %s\n' % e)
+
+    def output(self, stream, text):
+        if not text:
+            return
+        self.send(text)
+        for line in text.splitlines():
+            log('TELNET', stream, line)
+
+    def shell(self, username):
+        self.send('Last login: %s from 10.0.0.2\n' % datetime.datetime.now().strftime('%a %b %d %H:%M:%S %Y'))
+        prompt = '#' if username == 'root' else '$'
+        while True:
+            self.send('%s@%s:%s%s ' % (username, HOSTNAME, self.cwd, prompt))
+            command = self.read_line(True)
+            if command is None:
+                return
+            command = command.strip()
+            if not command:
+                continue
+            log('TELNET', 'STDIN', command)
+            if command in ('exit', 'logout', 'quit'):
+                return
+            self.run(command)
+
+def handle(conn, address):
+    log('TELNET-INFO', 'CONNECT', 'connection from %s:%d' % address)
+    session = Session(conn)
+    try:
+        conn.sendall(bytes([IAC, WILL, ECHO, IAC, WILL, SGA]))
+        if BANNER:
+            session.send(BANNER + '\n\n')
+        username = session.login()
+        if username is not None:
+            session.shell(username)
+    except Exception as e:
+        log('TELNET-ERROR', 'SESSION', str(e))
+    finally:
+        conn.close()
+
+def main():
+    srv = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+    srv.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
+    srv.bind(("127.0.0.1", PORT))
+    srv.listen(50)
+    log('TELNET-INFO', 'SERVER', 'listening on 127.0.0.1:%d' % PORT)
     while True:
-        conn, addr = srv.accept()
-        t = threading.Thread(target=handle, args=(conn, addr), daemon=True)
-        t.start()
+        conn, address = srv.accept()
+        conn.settimeout(300)
+        threading.Thread(target=handle, args=(conn, address), daemon=True).start()
 
 if __name__ == "__main__":
     main()
 PYEOF
-                    chmod +x /usr/local/bin/http_server.py
-                    exec "$PY" /usr/local/bin/http_server.py
-                "#,
+                    chmod +x {root}/usr/local/bin/telnet_server.py
+                    exec "$PY" {root}/usr/local/bin/telnet_server.py
+                "##,
                     p = p,
-                    log_path = log_path
+                    log_path = log_path,
+                    banner = banner,
+                    hostname = hostname
+                )
+            }
+            "mysql" => {
+                let p = host_port;
+                let version = service_config
+                    .banner_response
+                    .as_deref()
+                    .map(str::trim_end)
+                    .unwrap_or("5.7.42-0ubuntu0.18.04.1");
+                let hostname = service_config
+                    .obfuscation
+                    .fake_hostname
+                    .as_deref()
+                    .unwrap_or("localhost");
+                // JSON strings are valid Python string literals
+                let version = serde_json::to_string(version).unwrap_or_default();
+                let hostname = serde_json::to_string(hostname).unwrap_or_default();
+                format!(
+                    r##"
+                    export PATH="/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin"
+                    PY="/usr/bin/python3"; [ -x "$PY" ] || PY="/usr/local/bin/python3"; [ -x "$PY" ] || PY="/bin/python3"; [ -x "$PY" ] || PY="$(command -v python3)";
+                    if [ ! -x "$PY" ]; then echo "[$(date '+%Y-%m-%d %H:%M:%S UTC')] [MYSQL] [STDERR] Python 3 not found" >> {log_path}; exit 1; fi
+                    cat >{root}/usr/local/bin/mysql_server.py <<'PYEOF'
+import datetime, json, os, re, socket, struct, threading
+
+LOG_PATH = r"{log_path}"
+PORT = {p}
+VERSION = {version}
+HOSTNAME = {hostname}
+DATABASES = ['information_schema', 'mysql', 'performance_schema', 'sys']
+MAX_PACKET = 16 * 1024 * 1024
+CHARSET = 33
+# LONG_PASSWORD .. SECURE_CONNECTION without COMPRESS and SSL, then MULTI_STATEMENTS
+# .. PLUGIN_AUTH_LENENC_CLIENT_DATA
+CAPABILITIES = 0xf7df | (0x3f << 16)
+CONNECT_WITH_DB, SSL, SECURE_CONNECTION = 0x8, 0x800, 0x8000
+PLUGIN_AUTH, CONNECT_ATTRS, LENENC_AUTH = 0x80000, 0x100000, 0x200000
+STATUS = 0x0002
+CONNECTIONS = [0]
+LOCK = threading.Lock()
+
+def log(service, stream, line):
+    ts = datetime.datetime.now(datetime.timezone.utc).strftime('%Y-%m-%d %H:%M:%S UTC')
+    try:
+        with open(LOG_PATH, 'a') as f:
+            f.write('[%s] [%s] [%s] %s\n' % (ts, service, stream, line))
+    except Exception:
+        pass
+
+def one_line(text):
+    return text.replace('\\', '\\\\').replace('\r', '\\r').replace('\n', '\\n')
+
+def lenenc_int(n):
+    if n < 251:
+        return bytes([n])
+    if n < 1 << 16:
+        return b'\xfc' + struct.pack('<H', n)
+    if n < 1 << 24:
+        return b'\xfd' + struct.pack('<I', n)[:3]
+    return b'\xfe' + struct.pack('<Q', n)
+
+def lenenc_str(value):
+    if value is None:
+        return b'\xfb'
+    data = str(value).encode('utf-8')
+    return lenenc_int(len(data)) + data
+
+def read_lenenc(data, pos):
+    first = data[pos]
+    if first < 251:
+        return first, pos + 1
+    size = dict([(0xfc, 2), (0xfd, 3), (0xfe, 8)]).get(first, 0)
+    return int.from_bytes(data[pos + 1:pos + 1 + size], 'little'), pos + 1 + size
+
+def read_cstr(data, pos):
+    end = data.find(b'\0', pos)
+    if end < 0:
+        end = len(data)
+    return data[pos:end], end + 1
+
+class Session:
+    def __init__(self, conn):
+        self.conn = conn
+        self.seq = 0
+        self.user = ''
+        self.database = None
+        with LOCK:
+            CONNECTIONS[0] += 1
+            self.connection_id = CONNECTIONS[0] + 7
+
+    def recv_exact(self, n):
+        data = b''
+        while len(data) < n:
+            chunk = self.conn.recv(n - len(data))
+            if not chunk:
+                return None
+            data += chunk
+        return data
+
+    def read_packet(self):
+        head = self.recv_exact(4)
+        if head is None:
+            return None
+        length = int.from_bytes(head[:3], 'little')
+        self.seq = (head[3] + 1) % 256
+        if length > MAX_PACKET:
+            return None
+        return self.recv_exact(length)
+
+    def send(self, *payloads):
+        out = b''
+        for payload in payloads:
+            out += len(payload).to_bytes(3, 'little') + bytes([self.seq]) + payload
+            self.seq = (self.seq + 1) % 256
+        self.conn.sendall(out)
+
+    def ok(self, affected=0):
+        self.send(b'\x00' + lenenc_int(affected) + b'\x00' + struct.pack('<HH', STATUS, 0))
+
+    def error(self, code, state, message):
+        self.send(b'\xff' + struct.pack('<H', code) + b'#' + state.encode() + message.encode('utf-8'))
+
+    def eof(self):
+        return b'\xfe' + struct.pack('<HH', 0, STATUS)
+
+    def result(self, columns, rows):
+        packets = [lenenc_int(len(columns))]
+        for name in columns:
+            packets.append(lenenc_str('def') + lenenc_str('') + lenenc_str('') + lenenc_str('') +
+                           lenenc_str(name) + lenenc_str('') + b'\x0c' +
+                           struct.pack('<HIBHB', CHARSET, 1024, 0xfd, 0, 31) + b'\0\0')
+        packets.append(self.eof())
+        for row in rows:
+            packets.append(b''.join(lenenc_str(value) for value in row))
+        packets.append(self.eof())
+        self.send(*packets)
+
+    def handshake(self):
+        scramble = os.urandom(20)
+        scramble = bytes(b % 94 + 33 for b in scramble)
+        payload = (b'\x0a' + VERSION.encode() + b'\0' + struct.pack('<I', self.connection_id) +
+                   scramble[:8] + b'\0' + struct.pack('<H', CAPABILITIES & 0xffff) + bytes([CHARSET]) +
+                   struct.pack('<HH', STATUS, CAPABILITIES >> 16) + bytes([21]) + b'\0' * 10 +
+                   scramble[8:] + b'\0' + b'mysql_native_password\0')
+        self.send(payload)
+        return scramble
+
+    def login(self, scramble):
+        data = self.read_packet()
+        if data is None or len(data) < 32:
+            return False
+        flags = struct.unpack('<I', data[:4])[0]
+        if flags & SSL and len(data) == 32:
+            log('MYSQL-INFO', 'TLS', 'client asked for TLS, which is not offered')
+            return False
+        pos = 32
+        user, pos = read_cstr(data, pos)
+        if flags & LENENC_AUTH:
+            length, pos = read_lenenc(data, pos)
+        elif flags & SECURE_CONNECTION:
+            length, pos = data[pos], pos + 1
+        else:
+            length = data.find(b'\0', pos) - pos
+        response = data[pos:pos + length]
+        pos += length
+        if not flags & (LENENC_AUTH | SECURE_CONNECTION):
+            pos += 1
+        if flags & CONNECT_WITH_DB and pos < len(data):
+            database, pos = read_cstr(data, pos)
+            self.database = database.decode('utf-8', 'replace') or None
+        plugin = b''
+        if flags & PLUGIN_AUTH and pos < len(data):
+            plugin, pos = read_cstr(data, pos)
+        attrs = dict()
+        if flags & CONNECT_ATTRS and pos < len(data):
+            end, pos = read_lenenc(data, pos)
+            end += pos
+            while pos < end and pos < len(data):
+                key_len, pos = read_lenenc(data, pos)
+                key = data[pos:pos + key_len].decode('utf-8', 'replace')
+                pos += key_len
+                value_len, pos = read_lenenc(data, pos)
+                attrs[key] = data[pos:pos + value_len].decode('utf-8', 'replace')
+                pos += value_len
+        self.user = user.decode('utf-8', 'replace')
+        log('MYSQL-INFO', 'AUTH', json.dumps(dict(
+            username=self.user, database=self.database, plugin=plugin.decode('utf-8', 'replace'),
+            scramble=scramble.hex(), response=response.hex(), attributes=attrs)))
+
+        # Scrambled passwords cannot be read, so clients are asked for the clear one
+        password = '' if not response else None
+        answer = b''
+        if password is None:
+            self.send(b'\xfe' + b'mysql_clear_password\0')
+            answer = self.read_packet()
+            if answer is not None and answer[:1] not in (b'\xff', b''):
+                password = answer.rstrip(b'\0').decode('utf-8', 'replace')
+        accepted = answer is not None
+        log('MYSQL', 'LOGIN', json.dumps(dict(username=self.user, password=password, accepted=accepted)))
+        if not accepted:
+            return False
+        self.ok()
+        return True
+
+    def query(self, sql):
+        text = sql.strip().rstrip(';').strip()
+        lowered = re.sub(r'\s+', ' ', text.lower())
+        if lowered in ('select @@version_comment limit 1', 'select @@version_comment'):
+            return self.result(['@@version_comment'], [['(Ubuntu)']])
+        if lowered in ('select version()', 'select @@version'):
+            return self.result([text[7:]], [[VERSION]])
+        if lowered in ('select database()', 'select schema()'):
+            return self.result([text[7:]], [[self.database]])
+        if lowered in ('select user()', 'select current_user()', 'select current_user', 'select system_user()'):
+            return self.result([text[7:]], [['%s@%s' % (self.user, 'localhost')]])
+        if lowered in ('select @@hostname', 'select @@global.hostname'):
+            return self.result([text[7:]], [[HOSTNAME]])
+        if lowered == 'show databases' or lowered == 'show schemas':
+            return self.result(['Database'], [[name] for name in DATABASES])
+        if lowered.startswith('show tables'):
+            return self.result(['Tables_in_%s' % (self.database or 'mysql')], [])
+        if lowered.startswith('show variables') or lowered.startswith('show global variables'):
+            return self.result(['Variable_name', 'Value'], [['version', VERSION], ['hostname', HOSTNAME],
+                                                            ['secure_file_priv', '/var/lib/mysql-files/']])
+        if lowered.startswith('show '):
+            return self.result(['Value'], [])
+        if lowered.startswith('use '):
+            return self.use(text[4:].strip().strip('`'))
+        literal = re.match(r"^select\s+(-?\d+|'[^']*'|\"[^\"]*\")$", text, re.I)
+        if literal:
+            value = literal.group(1).strip('\'"')
+            return self.result([literal.group(1)], [[value]])
+        table = re.search(r'\bfrom\s+`?([\w.]+)`?', text, re.I)
+        if lowered.startswith('select') and table:
+            name = table.group(1) if '.' in table.group(1) else '%s.%s' % (self.database or 'mysql', table.group(1))
+            return self.error(1146, '42S02', "Table '%s' doesn't exist" % name)
+        if re.match(r'^(set|begin|commit|rollback|start|lock|unlock|flush|kill)\b', lowered):
+            return self.ok()
+        if re.match(r'^(insert|update|delete|replace|create|drop|alter|grant|revoke|truncate)\b', lowered):
+            if self.database is None and not re.match(r'^(create|drop) (database|schema|user)\b', lowered):
+                return self.error(1046, '3D000', 'No database selected')
+            return self.ok(1 if lowered.startswith(('insert', 'replace')) else 0)
+        if lowered.startswith('select'):
+            return self.error(1054, '42S22', "Unknown column in 'field list'")
+        return self.error(1064, '42000', "You have an error in your SQL syntax; check the manual that corresponds to your MySQL server version for the right syntax to use near '%s' at line 1" % text[:80])
+
+    def use(self, database):
+        log('MYSQL-INFO', 'USE', one_line(database))
+        if database not in DATABASES:
+            return self.error(1049, '42000', "Unknown database '%s'" % database)
+        self.database = database
+        return self.ok()
+
+    def serve(self):
+        while True:
+            data = self.read_packet()
+            if not data:
+                return
+            command, body = data[0], data[1:].decode('utf-8', 'replace')
+            if command == 0x01:
+                return
+            if command == 0x03:
+                log('MYSQL', 'STDIN', one_line(body))
+                self.query(body)
+            elif command == 0x02:
+                self.use(body)
+            elif command == 0x0e:
+                self.ok()
+            elif command == 0x04:
+                self.send(self.eof())
+            elif command == 0x16:
+                log('MYSQL', 'STDIN', one_line(body))
+                self.error(1295, 'HY000', 'This command is not supported in the prepared statement protocol yet')
+            else:
+                self.error(1047, '08S01', 'Unknown command')
+
+def handle(conn, address):
+    log('MYSQL-INFO', 'CONNECT', 'connection from %s:%d' % address)
+    try:
+        session = Session(conn)
+        scramble = session.handshake()
+        if session.login(scramble):
+            session.serve()
+    except Exception as e:
+        log('MYSQL-ERROR', 'SESSION', str(e))
+    finally:
+        conn.close()
+
+def main():
+    srv = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+    srv.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
+    srv.bind(("127.0.0.1", PORT))
+    srv.listen(50)
+    log('MYSQL-INFO', 'SERVER', 'listening on 127.0.0.1:%d' % PORT)
+    while True:
+        conn, address = srv.accept()
+        conn.settimeout(300)
+        threading.Thread(target=handle, args=(conn, address), daemon=True).start()
+
+if __name__ == "__main__":
+    main()
+PYEOF
+                    chmod +x {root}/usr/local/bin/mysql_server.py
+                    exec "$PY" {root}/usr/local/bin/mysql_server.py
+                "##,
+                    p = p,
+                    log_path = log_path,
+                    version = version,
+                    hostname = hostname
+                )
+            }
+            "redis" => {
+                let p = host_port;
+                let version = service_config
+                    .banner_response
+                    .as_deref()
+                    .map(str::trim_end)
+                    .unwrap_or("5.0.7");
+                // JSON strings are valid Python string literals
+                let version = serde_json::to_string(version).unwrap_or_default();
+                format!(
+                    r##"
+                    export PATH="/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin"
+                    PY="/usr/bin/python3"; [ -x "$PY" ] || PY="/usr/local/bin/python3"; [ -x "$PY" ] || PY="/bin/python3"; [ -x "$PY" ] || PY="$(command -v python3)";
+                    if [ ! -x "$PY" ]; then echo "[$(date '+%Y-%m-%d %H:%M:%S UTC')] [REDIS] [STDERR] Python 3 not found" >> {log_path}; exit 1; fi
+                    cat >{root}/usr/local/bin/redis_server.py <<'PYEOF'
+import datetime, fnmatch, json, socket, threading, time
+
+LOG_PATH = r"{log_path}"
+PORT = {p}
+VERSION = {version}
+MAX_BULK = 16 * 1024 * 1024
+MAX_LOGGED_ARG = 4096
+STARTED = time.time()
+LOCK = threading.Lock()
+STORE = dict()
+CONFIG = dict([('dir', '/var/lib/redis'), ('dbfilename', 'dump.rdb'), ('requirepass', ''),
+               ('masterauth', ''), ('maxmemory', '0'), ('bind', '127.0.0.1 ::1'),
+               ('protected-mode', 'no'), ('port', '6379'), ('appendonly', 'no'),
+               ('save', '900 1 300 10 60 10000'), ('slave-read-only', 'yes')])
+
+def log(service, stream, line):
+    ts = datetime.datetime.now(datetime.timezone.utc).strftime('%Y-%m-%d %H:%M:%S UTC')
+    try:
+        with open(LOG_PATH, 'a') as f:
+            f.write('[%s] [%s] [%s] %s\n' % (ts, service, stream, line))
+    except Exception:
+        pass
+
+def one_line(text):
+    return text.replace('\\', '\\\\').replace('\r', '\\r').replace('\n', '\\n')
+
+def quoted(arg):
+    text = arg.decode('utf-8', 'replace')
+    if len(text) > MAX_LOGGED_ARG:
+        text = text[:MAX_LOGGED_ARG] + '...'
+    if text and not any(c in text for c in ' "\r\n\t\\'):
+        return text
+    return '"%s"' % one_line(text).replace('"', '\\"').replace('\t', '\\t')
+
+def simple(text):
+    return ('+%s\r\n' % text).encode()
+
+def error(text):
+    return ('-%s\r\n' % text).encode()
+
+def integer(n):
+    return (':%d\r\n' % n).encode()
+
+def bulk(value):
+    if value is None:
+        return b'$-1\r\n'
+    if isinstance(value, str):
+        value = value.encode('utf-8')
+    return b'$%d\r\n%s\r\n' % (len(value), value)
+
+def array(values):
+    return b'*%d\r\n' % len(values) + b''.join(bulk(v) for v in values)
+
+def info(section):
+    uptime = int(time.time() - STARTED) + 1732819
+    sections = [
+        ('server', ['redis_version:%s' % VERSION, 'redis_git_sha1:00000000', 'redis_git_dirty:0',
+                    'redis_build_id:66bd629f924ac924', 'redis_mode:standalone',
+                    'os:Linux 4.15.0-213-generic x86_64', 'arch_bits:64', 'multiplexing_api:epoll',
+                    'gcc_version:7.5.0', 'process_id:1094', 'run_id:9b1a31c6f1e1a09c4a3b9d7b8fbb4e1b8a8a77f2',
+                    'tcp_port:6379', 'uptime_in_seconds:%d' % uptime, 'uptime_in_days:%d' % (uptime // 86400),
+                    'executable:/usr/bin/redis-server', 'config_file:/etc/redis/redis.conf']),
+        ('clients', ['connected_clients:1', 'blocked_clients:0']),
+        ('memory', ['used_memory:861288', 'used_memory_human:841.10K', 'maxmemory:0',
+                    'maxmemory_policy:noeviction']),
+        ('persistence', ['loading:0', 'rdb_changes_since_last_save:0', 'rdb_bgsave_in_progress:0',
+                         'aof_enabled:0']),
+        ('replication', ['role:master', 'connected_slaves:0']),
+        ('keyspace', ['db0:keys=%d,expires=0,avg_ttl=0' % len(STORE)] if STORE else []),
+    ]
+    wanted = section.lower() if section else None
+    parts = []
+    for name, lines in sections:
+        if wanted in (None, 'all', 'default', 'everything', name):
+            parts.append('# %s\r\n%s' % (name.capitalize(), ''.join(line + '\r\n' for line in lines)))
+    return '\r\n'.join(parts)
+
+class Session:
+    def __init__(self, conn):
+        self.conn = conn
+        self.buffer = b''
+
+    def fill(self):
+        chunk = self.conn.recv(65536)
+        if not chunk:
+            return False
+        self.buffer += chunk
+        return True
+
+    def read_line(self):
+        while b'\r\n' not in self.buffer and b'\n' not in self.buffer:
+            if len(self.buffer) > 64 * 1024 or not self.fill():
+                return None
+        end = self.buffer.find(b'\n')
+        line, self.buffer = self.buffer[:end], self.buffer[end + 1:]
+        return line.rstrip(b'\r')
+
+    def read_exact(self, n):
+        while len(self.buffer) < n:
+            if not self.fill():
+                return None
+        data, self.buffer = self.buffer[:n], self.buffer[n:]
+        return data
+
+    def read_command(self):
+        line = self.read_line()
+        if line is None:
+            return None
+        if not line.startswith(b'*'):
+            # Inline command, as typed in telnet
+            return line.split()
+        try:
+            count = int(line[1:])
+        except ValueError:
+            raise ValueError("Protocol error: invalid multibulk length")
+        args = []
+        for _ in range(max(count, 0)):
+            head = self.read_line()
+            if head is None:
+                return None
+            if not head.startswith(b'$'):
+                raise ValueError("Protocol error: expected '$', got '%s'" % head[:1].decode('latin-1'))
+            size = int(head[1:])
+            if size < 0 or size > MAX_BULK:
+                raise ValueError('Protocol error: invalid bulk length')
+            data = self.read_exact(size + 2)
+            if data is None:
+                return None
+            args.append(data[:size])
+        return args
+
+    def execute(self, args):
+        name = args[0].decode('utf-8', 'replace').lower()
+        rest = args[1:]
+        text = [a.decode('utf-8', 'replace') for a in rest]
+        if name == 'auth':
+            username = text[0] if len(text) == 2 else 'default'
+            password = text[-1] if text else ''
+            log('REDIS', 'LOGIN', json.dumps(dict(username=username, password=password, accepted=True)))
+            return simple('OK')
+        log('REDIS', 'STDIN', ' '.join([name.upper()] + [quoted(a) for a in rest]))
+        if name == 'ping':
+            return bulk(rest[0]) if rest else simple('PONG')
+        if name == 'echo' and rest:
+            return bulk(rest[0])
+        if name == 'quit':
+            return None
+        if name == 'info':
+            return bulk(info(text[0] if text else None))
+        if name == 'config' and text:
+            action = text[0].lower()
+            if action == 'get' and len(text) > 1:
+                found = []
+                for key, value in sorted(CONFIG.items()):
+                    if fnmatch.fnmatchcase(key, text[1].lower()):
+                        found += [key, value]
+                return array(found)
+            if action == 'set' and len(text) > 2:
+                CONFIG[text[1].lower()] = text[2]
+                return simple('OK')
+            if action in ('resetstat', 'rewrite'):
+                return simple('OK')
+            return error("ERR Unknown subcommand or wrong number of arguments for '%s'. Try CONFIG HELP." % text[0])
+        if name == 'set' and len(rest) >= 2:
+            with LOCK:
+                STORE[rest[0]] = rest[1]
+            return simple('OK')
+        if name == 'get' and len(rest) == 1:
+            return bulk(STORE.get(rest[0]))
+        if name == 'del' and rest:
+            with LOCK:
+                return integer(sum(1 for key in rest if STORE.pop(key, None) is not None))
+        if name == 'exists' and rest:
+            return integer(sum(1 for key in rest if key in STORE))
+        if name == 'keys' and len(text) == 1:
+            return array([k for k in STORE if fnmatch.fnmatchcase(k.decode('utf-8', 'replace'), text[0])])
+        if name == 'type' and len(rest) == 1:
+            return simple('string' if rest[0] in STORE else 'none')
+        if name == 'dbsize':
+            return integer(len(STORE))
+        if name in ('flushall', 'flushdb'):
+            with LOCK:
+                STORE.clear()
+            return simple('OK')
+        if name == 'select' and len(text) == 1:
+            return simple('OK') if text[0].isdigit() and int(text[0]) < 16 else error('ERR DB index is out of range')
+        if name == 'save':
+            return simple('OK')
+        if name == 'bgsave':
+            return simple('Background saving started')
+        if name in ('slaveof', 'replicaof') and len(text) == 2:
+            return simple('OK')
+        if name == 'client':
+            if text and text[0].lower() == 'list':
+                return bulk('id=3 addr=127.0.0.1:51842 fd=8 name= age=0 idle=0 flags=N db=0 cmd=client\n')
+            return simple('OK')
+        if name == 'command':
+            return b'*0\r\n'
+        if name == 'time':
+            now = time.time()
+            return array([str(int(now)), str(int(now % 1 * 1000000))])
+        if name == 'module' and text and text[0].lower() == 'load':
+            return error('ERR Error loading the extension. Please check the server logs.')
+        if name == 'eval':
+            return bulk(None)
+        if name == 'evalsha':
+            return error('NOSCRIPT No matching script. Please use EVAL.')
+        return error("ERR unknown command '%s'" % args[0].decode('utf-8', 'replace')[:128])
+
+def handle(conn, address):
+    log('REDIS-INFO', 'CONNECT', 'connection from %s:%d' % address)
+    session = Session(conn)
+    try:
+        while True:
+            try:
+                args = session.read_command()
+            except ValueError as e:
+                conn.sendall(error(str(e)))
+                return
+            if args is None:
+                return
+            if not args:
+                continue
+            reply = session.execute(args)
+            if reply is None:
+                conn.sendall(simple('OK'))
+                return
+            conn.sendall(reply)
+    except Exception as e:
+        log('REDIS-ERROR', 'SESSION', str(e))
+    finally:
+        conn.close()
+
+def main():
+    srv = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+    srv.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
+    srv.bind(("127.0.0.1", PORT))
+    srv.listen(50)
+    log('REDIS-INFO', 'SERVER', 'listening on 127.0.0.1:%d' % PORT)
+    while True:
+        conn, address = srv.accept()
+        conn.settimeout(300)
+        threading.Thread(target=handle, args=(conn, address), daemon=True).start()
+
+if __name__ == "__main__":
+    main()
+PYEOF
+                    chmod +x {root}/usr/local/bin/redis_server.py
+                    exec "$PY" {root}/usr/local/bin/redis_server.py
+                "##,
+                    p = p,
+                    log_path = log_path,
+                    version = version
                 )
             }
             _ => {
                 format!(
                     r#"
-                    exec /bin/sh /usr/bin/service 2>&1 | while IFS= read -r l; do echo "[$(date '+%Y-%m-%d %H:%M:%S UTC')] [SERVICE] $l" >> {log_path}; done
+                    exec /bin/sh {root}/usr/bin/service 2>&1 | while IFS= read -r l; do echo "[$(date '+%Y-%m-%d %H:%M:%S UTC')] [SERVICE] $l" >> {log_path}; done
                 "#,
                     log_path = log_path
                 )
@@ -780,7 +3410,7 @@ PYEOF
         }
     }
 
-    /// Creates a unified log file to capture all shell activity from the container.
+    /// Opens the unified log file capturing all shell activity from the container.
     ///
     /// This method creates a dedicated log file that will contain:
     /// - Main container shell output
@@ -789,17 +3419,21 @@ PYEOF
     ///
     /// The log file is created with appropriate permissions and can be read
     /// to monitor all terminal activity happening inside the container.
-    fn create_pty_master(&self, container_id: &str) -> Result<File, ContainerError> {
+    fn open_activity_log(&self, container_id: &str) -> Result<File, ContainerError> {
         // Create a dedicated log directory for container activity
-        let log_dir = "/tmp/miel-logs";
+        let log_dir = &self.paths.log_dir;
         std::fs::create_dir_all(log_dir).map_err(|e| {
-            error!("Failed to create log directory {}: {}", log_dir, e);
+            error!(
+                "Failed to create log directory {}: {}",
+                log_dir.display(),
+                e
+            );
             ContainerError::CreationFailed(format!("Failed to create log directory: {}", e))
         })?;
 
         // Create a unified log file for all container shell activity
-        let log_path = format!("{}/container-{}-activity.log", log_dir, container_id);
-        debug!("Creating unified activity log at: {}", log_path);
+        let log_path = activity_log(log_dir, container_id);
+        debug!("Creating unified activity log at: {}", log_path.display());
 
         let log_file = std::fs::OpenOptions::new()
             .create(true)
@@ -807,7 +3441,11 @@ PYEOF
             .read(true)
             .open(&log_path)
             .map_err(|e| {
-                error!("Failed to create activity log {}: {}", log_path, e);
+                error!(
+                    "Failed to create activity log {}: {}",
+                    log_path.display(),
+                    e
+                );
                 ContainerError::CreationFailed(format!("Failed to create activity log: {}", e))
             })?;
 
@@ -935,3 +3573,497 @@ impl Drop for ContainerManager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::types::SiteTemplate;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn service() -> ServiceConfig {
+        ServiceConfig {
+            name: "http".to_string(),
+            port: 8080,
+            container_image: "nginx:alpine".to_string(),
+            container_port: Some(80),
+            ..ServiceConfig::default()
+        }
+    }
+
+    #[test]
+    fn image_run_args_publishes_container_port_on_loopback() {
+        let args = ContainerManager::image_run_args(
+            &Runtime::Docker,
+            &service(),
+            "miel-http-1",
+            40000,
+            false,
+        );
+
+        assert_eq!(args[0], "run");
+        assert!(args.contains(&"127.0.0.1:40000:80/tcp".to_string()));
+        assert!(!args.iter().any(|a| a.starts_with("--userns")));
+        assert_eq!(args.last().unwrap(), "nginx:alpine");
+    }
+
+    #[test]
+    fn image_run_args_maps_podman_user_namespace() {
+        let rootless =
+            ContainerManager::image_run_args(&Runtime::Podman, &service(), "id", 40000, false);
+        assert!(rootless.contains(&"--userns=keep-id:uid=0,gid=0".to_string()));
+
+        let rootful =
+            ContainerManager::image_run_args(&Runtime::Podman, &service(), "id", 40000, true);
+        assert!(rootful.contains(&"--userns=auto".to_string()));
+    }
+
+    #[test]
+    fn resource_limits_translate_per_runtime() {
+        let mut service = service();
+        service.resources = ResourceLimits {
+            memory_mb: Some(256),
+            cpu_percent: Some(50),
+            pids_max: Some(64),
+        };
+
+        let args = ContainerManager::image_run_args(&Runtime::Docker, &service, "id", 40000, false);
+        for expected in [
+            "--memory=256m",
+            "--memory-swap=256m",
+            "--cpus=0.5",
+            "--pids-limit=64",
+        ] {
+            assert!(args.contains(&expected.to_string()), "missing {}", expected);
+        }
+        assert_eq!(args.last().unwrap(), "nginx:alpine");
+
+        assert_eq!(
+            ContainerManager::nspawn_resource_args(&service.resources),
+            vec![
+                "--property=MemoryMax=256M",
+                "--property=MemorySwapMax=0",
+                "--property=CPUQuota=50%",
+                "--property=TasksMax=64",
+            ]
+        );
+        assert!(ContainerManager::nspawn_resource_args(&ResourceLimits::default()).is_empty());
+    }
+
+    #[test]
+    fn only_nspawn_requires_root() {
+        assert!(Runtime::SystemdNspawn.requires_root());
+        assert!(!Runtime::Docker.requires_root());
+        assert!(!Runtime::Podman.requires_root());
+    }
+
+    #[test]
+    fn nspawn_overrides_require_root() {
+        let mut manager = ContainerManager::new_mock();
+        manager.runtime = Runtime::ProcessSandbox;
+        let service = ServiceConfig {
+            name: "ssh".to_string(),
+            runtime: Some(Runtime::SystemdNspawn),
+            ..ServiceConfig::default()
+        };
+
+        let denied = matches!(
+            manager.prepare(&service, None),
+            Err(ContainerError::InsufficientPrivileges)
+        );
+        assert_eq!(denied, !ContainerManager::is_running_as_root());
+    }
+
+    #[test]
+    fn ftp_command_runs_the_scripted_daemon() {
+        let mut service = ServiceConfig {
+            name: "ftp".to_string(),
+            port: 21,
+            banner_response: Some("220 ProFTPD Server\r\n".to_string()),
+            ..ServiceConfig::default()
+        };
+        let manager = ContainerManager::new_mock();
+
+        let command = manager.get_service_command(&service, 40021, "miel-ftp-1");
+        assert!(command.contains("PORT = 40021"));
+        assert!(command.contains("BANNER = \"220 ProFTPD Server\""));
+        assert!(command.contains("PASV_ADDRESS = None"));
+        assert!(command.contains("UPLOAD_DIR = r\"/tmp/miel-logs/container-miel-ftp-1-uploads\""));
+
+        service.ftp.passive_address = Some("203.0.113.10".parse().unwrap());
+        let command = manager.get_service_command(&service, 40021, "miel-ftp-1");
+        assert!(command.contains("PASV_ADDRESS = \"203.0.113.10\""));
+        assert!(command.contains("PASV_PORTS = range(30000, 30009 + 1)"));
+    }
+
+    #[test]
+    fn http_command_serves_the_site() {
+        let mut service = ServiceConfig {
+            name: "http".to_string(),
+            port: 80,
+            ..ServiceConfig::default()
+        };
+        let manager = ContainerManager::new_mock();
+
+        let command = manager.get_service_command(&service, 40080, "miel-http-1");
+        assert!(command.contains("PORT = 40080"));
+        assert!(command.contains("\"server\":\"nginx/1.18.0 (Ubuntu)\""));
+        assert!(command.contains("log('HTTP', 'LOGIN'"));
+        assert!(command.contains("'received %d bytes for %s' % (len(content), one_line(name))"));
+        assert!(command.contains("UPLOAD_DIR = r\"/tmp/miel-logs/container-miel-http-1-uploads\""));
+
+        service.http.template = SiteTemplate::PhpMyAdmin;
+        service.http.server_header = Some("Apache/2.4.29".to_string());
+        let command = manager.get_service_command(&service, 40080, "miel-http-1");
+        assert!(command.contains("\"server\":\"Apache/2.4.29\""));
+        assert!(command.contains("\"/phpmyadmin/index.php\""));
+        assert!(command.contains("pma_password"));
+    }
+
+    #[test]
+    fn mysql_and_redis_commands_run_the_protocol_emulators() {
+        let mut mysql = ServiceConfig {
+            name: "mysql".to_string(),
+            port: 3306,
+            ..ServiceConfig::default()
+        };
+        let manager = ContainerManager::new_mock();
+
+        let command = manager.get_service_command(&mysql, 43306, "miel-mysql-1");
+        assert!(command.contains("PORT = 43306"));
+        assert!(command.contains("VERSION = \"5.7.42-0ubuntu0.18.04.1\""));
+        assert!(command.contains("log('MYSQL', 'LOGIN'"));
+
+        mysql.banner_response = Some("8.0.36".to_string());
+        mysql.obfuscation.fake_hostname = Some("db-01".to_string());
+        let command = manager.get_service_command(&mysql, 43306, "miel-mysql-1");
+        assert!(command.contains("VERSION = \"8.0.36\""));
+        assert!(command.contains("HOSTNAME = \"db-01\""));
+
+        let redis = ServiceConfig {
+            name: "redis".to_string(),
+            port: 6379,
+            ..ServiceConfig::default()
+        };
+        let command = manager.get_service_command(&redis, 46379, "miel-redis-1");
+        assert!(command.contains("PORT = 46379"));
+        assert!(command.contains("VERSION = \"5.0.7\""));
+        assert!(command.contains("log('REDIS', 'LOGIN'"));
+    }
+
+    #[test]
+    fn telnet_command_runs_the_scripted_login() {
+        let mut service = ServiceConfig {
+            name: "telnet".to_string(),
+            port: 23,
+            ..ServiceConfig::default()
+        };
+        let manager = ContainerManager::new_mock();
+
+        let command = manager.get_service_command(&service, 40023, "miel-telnet-1");
+        assert!(command.contains("PORT = 40023"));
+        assert!(command.contains("BANNER = \"Ubuntu 18.04.6 LTS\""));
+        assert!(command.contains("HOSTNAME = \"localhost\""));
+        assert!(command.contains("log('TELNET', 'LOGIN'"));
+
+        service.banner_response = Some("BusyBox v1.19.4\r\n".to_string());
+        service.obfuscation.fake_hostname = Some("dvr-01".to_string());
+        let command = manager.get_service_command(&service, 40023, "miel-telnet-1");
+        assert!(command.contains("BANNER = \"BusyBox v1.19.4\""));
+        assert!(command.contains("HOSTNAME = \"dvr-01\""));
+    }
+
+    #[test]
+    fn embedded_ssh_command_runs_the_scripted_terminal() {
+        let mut service = ServiceConfig {
+            name: "ssh".to_string(),
+            port: 22,
+            ..ServiceConfig::default()
+        };
+        let manager = ContainerManager::new_mock();
+        assert!(manager
+            .get_service_command(&service, 40022, "miel-ssh-1")
+            .contains("sshd_config"));
+
+        service.ssh.mode = SshMode::Embedded;
+        service.obfuscation.fake_hostname = Some("web-01".to_string());
+        let command = manager.get_service_command(&service, 40022, "miel-ssh-1");
+        assert!(!command.contains("sshd_config"));
+        assert!(command.contains("PORT = 40022"));
+        assert!(command.contains("HOSTNAME = \"web-01\""));
+        assert!(command.contains("log('SSH', 'STDIN', one_line(command))"));
+        assert!(command.contains("'user %s from %s:%d' % (one_line(str(request.get('user')))"));
+        assert!(command.contains("/usr/lib/openssh/sftp-server"));
+    }
+
+    #[test]
+    fn smtp_command_runs_the_scripted_mta() {
+        let mut service = ServiceConfig {
+            name: "smtp".to_string(),
+            port: 25,
+            ..ServiceConfig::default()
+        };
+        let manager = ContainerManager::new_mock();
+
+        let command = manager.get_service_command(&service, 40025, "miel-smtp-1");
+        assert!(command.contains("PORT = 40025"));
+        assert!(command.contains("BANNER = \"220 mail.localdomain ESMTP Postfix (Ubuntu)\""));
+        assert!(command.contains("MAIL_DIR = r\"/tmp/miel-logs/container-miel-smtp-1-mail\""));
+
+        service.obfuscation.fake_hostname = Some("mx1.example.org".to_string());
+        let command = manager.get_service_command(&service, 40025, "miel-smtp-1");
+        assert!(command.contains("BANNER = \"220 mx1.example.org ESMTP Postfix (Ubuntu)\""));
+        assert!(command.contains("HOSTNAME = \"mx1.example.org\""));
+    }
+
+    #[tokio::test]
+    async fn process_sandboxes_run_the_emulators_on_the_host() {
+        if std::process::Command::new("python3")
+            .arg("--version")
+            .output()
+            .is_err()
+        {
+            return;
+        }
+        let service = ServiceConfig {
+            name: "telnet".to_string(),
+            port: 23,
+            runtime: Some(Runtime::ProcessSandbox),
+            ..ServiceConfig::default()
+        };
+        let mut manager = ContainerManager::new_mock();
+
+        let mut handle = manager.create_container_as(&service, None).await.unwrap();
+        assert_eq!(handle.runtime, Runtime::ProcessSandbox);
+        let root = format!("/tmp/miel-sandboxes/{}", handle.id);
+        assert!(Path::new(&root).join("usr/bin/service").exists());
+
+        let mut socket = handle.tcp_socket.take().unwrap();
+        let mut banner = Vec::new();
+        while !String::from_utf8_lossy(&banner).contains("login: ") {
+            let mut chunk = [0u8; 256];
+            let n = tokio::time::timeout(Duration::from_secs(5), socket.read(&mut chunk))
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(n > 0);
+            banner.extend_from_slice(&chunk[..n]);
+        }
+
+        manager.cleanup_container(handle).await.unwrap();
+        assert!(!Path::new(&root).exists());
+    }
+
+    #[tokio::test]
+    async fn mysql_and_redis_emulators_answer_on_the_host() {
+        if std::process::Command::new("python3")
+            .arg("--version")
+            .output()
+            .is_err()
+        {
+            return;
+        }
+        let mut manager = ContainerManager::new_mock();
+
+        let mysql = ServiceConfig {
+            name: "mysql".to_string(),
+            port: 3306,
+            runtime: Some(Runtime::ProcessSandbox),
+            ..ServiceConfig::default()
+        };
+        let mut handle = manager.create_container_as(&mysql, None).await.unwrap();
+        let mut socket = handle.tcp_socket.take().unwrap();
+        let mut greeting = Vec::new();
+        while !String::from_utf8_lossy(&greeting).contains("mysql_native_password") {
+            let mut chunk = [0u8; 256];
+            let n = tokio::time::timeout(Duration::from_secs(5), socket.read(&mut chunk))
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(n > 0);
+            greeting.extend_from_slice(&chunk[..n]);
+        }
+        // Protocol 10, then the server version
+        assert_eq!(greeting[4], 10);
+        assert!(String::from_utf8_lossy(&greeting).contains("5.7.42-0ubuntu0.18.04.1"));
+
+        // Logs in as root without a password, then selects a database whose
+        // name holds a line break
+        let mut login = vec![38, 0, 0, 1];
+        login.extend_from_slice(&(0x8000u32 | 0x200).to_le_bytes());
+        login.extend_from_slice(&[0; 28]);
+        login.extend_from_slice(b"root\0\0");
+        login.extend_from_slice(b"\x0b\0\0\0\x02mysql\nfake");
+        socket.write_all(&login).await.unwrap();
+        let mut replies = Vec::new();
+        while !String::from_utf8_lossy(&replies).contains("Unknown database") {
+            let mut chunk = [0u8; 256];
+            let n = tokio::time::timeout(Duration::from_secs(5), socket.read(&mut chunk))
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(n > 0);
+            replies.extend_from_slice(&chunk[..n]);
+        }
+        let log = std::fs::read_to_string(handle.activity_log()).unwrap();
+        assert!(log.contains("[MYSQL-INFO] [USE] mysql\\nfake\n"));
+        assert!(!log.lines().any(|line| line.starts_with("fake")));
+        manager.cleanup_container(handle).await.unwrap();
+
+        let redis = ServiceConfig {
+            name: "redis".to_string(),
+            port: 6379,
+            runtime: Some(Runtime::ProcessSandbox),
+            ..ServiceConfig::default()
+        };
+        let mut handle = manager.create_container_as(&redis, None).await.unwrap();
+        let mut socket = handle.tcp_socket.take().unwrap();
+        socket
+            .write_all(b"*2\r\n$4\r\nAUTH\r\n$8\r\nfoobared\r\n*1\r\n$4\r\nPING\r\n")
+            .await
+            .unwrap();
+        let mut replies = Vec::new();
+        while replies != b"+OK\r\n+PONG\r\n" {
+            let mut chunk = [0u8; 64];
+            let n = tokio::time::timeout(Duration::from_secs(5), socket.read(&mut chunk))
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(n > 0);
+            replies.extend_from_slice(&chunk[..n]);
+        }
+        let log = std::fs::read_to_string(handle.activity_log()).unwrap();
+        assert!(log.contains(
+            r#"[REDIS] [LOGIN] {"username": "default", "password": "foobared", "accepted": true}"#
+        ));
+        assert!(log.contains("[REDIS] [STDIN] PING"));
+        manager.cleanup_container(handle).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ftp_emulator_refuses_active_mode() {
+        if std::process::Command::new("python3")
+            .arg("--version")
+            .output()
+            .is_err()
+        {
+            return;
+        }
+        let service = ServiceConfig {
+            name: "ftp".to_string(),
+            port: 21,
+            runtime: Some(Runtime::ProcessSandbox),
+            ..ServiceConfig::default()
+        };
+        let mut manager = ContainerManager::new_mock();
+        let target = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = target.local_addr().unwrap().port();
+
+        let mut handle = manager.create_container_as(&service, None).await.unwrap();
+        let mut socket = handle.tcp_socket.take().unwrap();
+        let commands = format!(
+            "USER anonymous\r\nPASS x\r\nPORT 127,0,0,1,{},{}\r\n\
+             EPRT |1|127.0.0.1|{}|\r\nLIST\r\n",
+            port / 256,
+            port % 256,
+            port
+        );
+        socket.write_all(commands.as_bytes()).await.unwrap();
+        let mut replies = Vec::new();
+        while !String::from_utf8_lossy(&replies).contains("425 ") {
+            let mut chunk = [0u8; 256];
+            let n = tokio::time::timeout(Duration::from_secs(5), socket.read(&mut chunk))
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(n > 0);
+            replies.extend_from_slice(&chunk[..n]);
+        }
+        let replies = String::from_utf8_lossy(&replies);
+        assert_eq!(replies.matches("550 Permission denied.").count(), 2);
+        assert!(replies.contains("425 Use PORT or PASV first."));
+        // Nothing connected to the address named by the client
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), target.accept())
+                .await
+                .is_err()
+        );
+        manager.cleanup_container(handle).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn stopped_containers_are_unhealthy_once() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut handle = ContainerHandle {
+            id: "miel-http-1".to_string(),
+            service_name: "http".to_string(),
+            port: 80,
+            host_port: listener.local_addr().unwrap().port(),
+            created_at: Utc::now(),
+            process_handle: Some(Command::new("true").spawn().unwrap()),
+            pty_master: None,
+            activity_log_file: None,
+            tcp_socket: None,
+            udp_socket: None,
+            runtime: Runtime::Docker,
+            health: ContainerHealth::Healthy,
+            persona: None,
+            log_dir: ContainerPaths::default().log_dir,
+        };
+        let mut manager = ContainerManager::new_mock();
+
+        handle
+            .process_handle
+            .as_mut()
+            .unwrap()
+            .wait()
+            .await
+            .unwrap();
+        let health = manager.check_health(&mut handle, &service()).await;
+        assert!(matches!(health, ContainerHealth::Exited(_)));
+        manager.check_health(&mut handle, &service()).await;
+        assert_eq!(manager.get_container_stats().failed_count, 1);
+
+        handle.process_handle = None;
+        handle.health = ContainerHealth::Healthy;
+        assert!(manager
+            .check_health(&mut handle, &service())
+            .await
+            .is_healthy());
+        drop(listener);
+        assert_eq!(
+            manager.check_health(&mut handle, &service()).await,
+            ContainerHealth::PortClosed
+        );
+    }
+
+    #[test]
+    fn leftovers_of_previous_runs_are_told_apart() {
+        let mut manager = ContainerManager::new_mock();
+        manager.active_containers.insert(
+            "miel-ssh-2".to_string(),
+            ContainerHandle {
+                id: "miel-ssh-2".to_string(),
+                service_name: "ssh".to_string(),
+                port: 22,
+                host_port: 40022,
+                created_at: Utc::now(),
+                process_handle: None,
+                pty_master: None,
+                activity_log_file: None,
+                tcp_socket: None,
+                udp_socket: None,
+                runtime: Runtime::SystemdNspawn,
+                health: ContainerHealth::Healthy,
+                persona: None,
+                log_dir: ContainerPaths::default().log_dir,
+            },
+        );
+
+        let machinectl = "miel-ssh-1 container systemd-nspawn - - -\n\
+                          miel-ssh-2 container systemd-nspawn - - -\n\
+                          debian-dev container systemd-nspawn debian 12 -\n";
+        assert_eq!(manager.leftovers(machinectl), vec!["miel-ssh-1"]);
+        assert!(manager.leftovers("").is_empty());
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex as StdMutex};
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::configuration::types::{Protocol, ServiceConfig};
+use crate::container_management::container_manager::ContainerManager;
+use crate::container_management::types::ContainerHandle;
+use crate::error_handling::types::ContainerError;
+
+/// Keeps started containers ready for new sessions.
+///
+/// Creating a container and waiting for its service to accept connections takes
+/// seconds, a delay attackers can measure. The pool keeps `size` containers per
+/// enabled service started in advance; [`acquire`](Self::acquire) hands one out
+/// immediately and a background task replaces it.
+///
+/// Design notes:
+/// - Warm containers are created through the shared [`ContainerManager`], so they
+///   are part of its registry and stats and are removed by `cleanup_all_containers`.
+/// - The connection used to check that the service is up is closed once the
+///   container is stocked; a fresh one is opened on hand-out, so that no idle
+///   connection times out while the container waits.
+/// - Containers of a service whose configuration changed are discarded on
+///   [`configure`](Self::configure) and never handed out.
+/// - With a size of `0` (the default), or once a service's warm containers are
+///   exhausted, containers are created on demand as without a pool.
+#[derive(Clone)]
+pub struct ContainerPool {
+    manager: Arc<Mutex<ContainerManager>>,
+    state: Arc<StdMutex<PoolState>>,
+}
+
+#[derive(Default)]
+struct PoolState {
+    /// Warm containers to keep per service
+    size: usize,
+    /// Configuration of the enabled services, by name
+    services: HashMap<String, ServiceConfig>,
+    warm: HashMap<String, VecDeque<ContainerHandle>>,
+    /// Services a replenishing task is running for
+    filling: HashSet<String>,
+}
+
+impl ContainerPool {
+    /// Creates an empty pool, see [`configure`](Self::configure) to start pre-warming.
+    pub fn new(manager: Arc<Mutex<ContainerManager>>) -> Self {
+        Self {
+            manager,
+            state: Arc::new(StdMutex::new(PoolState::default())),
+        }
+    }
+
+    /// Keeps `size` warm containers for each enabled service of `services`, tarpits aside.
+    ///
+    /// Warm containers of removed, disabled or modified services, and those over a
+    /// lowered `size`, are cleaned up in the background.
+    pub fn configure(&self, services: &[ServiceConfig], size: usize) {
+        let stale = {
+            let mut state = self.state.lock().unwrap();
+            state.size = size;
+            state.services = services
+                .iter()
+                .filter(|service| service.enabled && service.tarpit.is_none())
+                .map(|service| (service.name.clone(), service.clone()))
+                .collect();
+
+            let mut stale = Vec::new();
+            let current = std::mem::take(&mut state.warm);
+            for (name, mut handles) in current {
+                let kept = if state.services.contains_key(&name) {
+                    size.min(handles.len())
+                } else {
+                    0
+                };
+                stale.extend(handles.drain(kept..));
+                if !handles.is_empty() {
+                    state.warm.insert(name, handles);
+                }
+            }
+            stale
+        };
+
+        if !stale.is_empty() {
+            debug!("Discarding {} warm containers", stale.len());
+            self.discard(stale);
+        }
+        if size > 0 {
+            info!("Keeping {} warm containers per service", size);
+        }
+
+        let names: Vec<String> = self
+            .state
+            .lock()
+            .unwrap()
+            .services
+            .keys()
+            .cloned()
+            .collect();
+        for name in names {
+            self.replenish(&name);
+        }
+    }
+
+    /// Hands out a warm container for `service_config`, or creates one when none is ready.
+    pub async fn acquire(
+        &self,
+        service_config: &ServiceConfig,
+    ) -> Result<ContainerHandle, ContainerError> {
+        while let Some(mut handle) = self.take_warm(service_config) {
+            match Self::reconnect(&mut handle, service_config).await {
+                Ok(()) => {
+                    debug!(
+                        "Handing out warm container {} for service {}",
+                        handle.id, service_config.name
+                    );
+                    self.replenish(&service_config.name);
+                    return Ok(handle);
+                }
+                Err(e) => {
+                    warn!(
+                        "Discarding unresponsive warm container {}: {}",
+                        handle.id, e
+                    );
+                    self.discard(vec![handle]);
+                }
+            }
+        }
+
+        let handle = ContainerManager::create_unlocked(&self.manager, service_config).await?;
+        self.replenish(&service_config.name);
+        Ok(handle)
+    }
+
+    /// Cleans up a container [acquired](Self::acquire) but never used by a session
+    pub async fn release(&self, handle: ContainerHandle) -> Result<(), ContainerError> {
+        self.manager.lock().await.cleanup_container(handle).await
+    }
+
+    /// Number of warm containers currently ready for `service_name`
+    pub fn warm_count(&self, service_name: &str) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .warm
+            .get(service_name)
+            .map_or(0, VecDeque::len)
+    }
+
+    /// Pops a warm container, unless the pool was configured for another version
+    /// of the service
+    fn take_warm(&self, service_config: &ServiceConfig) -> Option<ContainerHandle> {
+        let mut state = self.state.lock().unwrap();
+        if state.services.get(&service_config.name) != Some(service_config) {
+            return None;
+        }
+        state.warm.get_mut(&service_config.name)?.pop_front()
+    }
+
+    /// Opens the connection the session will be proxied over
+    async fn reconnect(
+        handle: &mut ContainerHandle,
+        service_config: &ServiceConfig,
+    ) -> Result<(), ContainerError> {
+        if service_config.protocol == Protocol::TCP {
+            handle.tcp_socket = Some(handle.connect_service().await?);
+        }
+        Ok(())
+    }
+
+    /// Adds a started container to the pool, handing it back when it is not wanted anymore
+    fn stock(
+        &self,
+        service_config: &ServiceConfig,
+        mut handle: ContainerHandle,
+    ) -> Option<ContainerHandle> {
+        let mut state = self.state.lock().unwrap();
+        let size = state.size;
+        if state.services.get(&service_config.name) != Some(service_config) {
+            return Some(handle);
+        }
+        let warm = state.warm.entry(service_config.name.clone()).or_default();
+        if warm.len() >= size {
+            return Some(handle);
+        }
+        handle.tcp_socket = None;
+        warm.push_back(handle);
+        None
+    }
+
+    /// Starts creating containers for `service_name` until its pool is full
+    fn replenish(&self, service_name: &str) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.size == 0 || !state.filling.insert(service_name.to_string()) {
+                return;
+            }
+        }
+
+        let pool = self.clone();
+        let service_name = service_name.to_string();
+        tokio::spawn(async move {
+            loop {
+                let service_config = {
+                    let mut state = pool.state.lock().unwrap();
+                    let ready = state.warm.get(&service_name).map_or(0, VecDeque::len);
+                    match state.services.get(&service_name) {
+                        Some(config) if ready < state.size => config.clone(),
+                        _ => {
+                            state.filling.remove(&service_name);
+                            break;
+                        }
+                    }
+                };
+
+                let created =
+                    ContainerManager::create_unlocked(&pool.manager, &service_config).await;
+                match created {
+                    Ok(handle) => {
+                        if let Some(handle) = pool.stock(&service_config, handle) {
+                            pool.discard(vec![handle]);
+                        }
+                    }
+                    Err(e) => {
+                        // Retried on the next hand-out rather than in a tight loop
+                        warn!(
+                            "Failed to pre-warm a container for service {}: {}",
+                            service_name, e
+                        );
+                        pool.state.lock().unwrap().filling.remove(&service_name);
+                        break;
+                    }
+                }
+            }
+            debug!("Warm containers replenished for service {}", service_name);
+        });
+    }
+
+    /// Cleans `handles` up in the background
+    fn discard(&self, handles: Vec<ContainerHandle>) {
+        let manager = self.manager.clone();
+        tokio::spawn(async move {
+            let mut manager = manager.lock().await;
+            for handle in handles {
+                if let Err(e) = manager.cleanup_container(handle).await {
+                    warn!("Failed to clean up warm container: {}", e);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container_management::{ContainerHealth, ContainerPaths, Runtime};
+    use chrono::Utc;
+    use tokio::net::TcpListener;
+
+    fn service() -> ServiceConfig {
+        ServiceConfig {
+            name: "ssh".to_string(),
+            port: 22,
+            protocol: Protocol::TCP,
+            container_image: "minimal-ssh".to_string(),
+            ..ServiceConfig::default()
+        }
+    }
+
+    fn handle(id: &str, host_port: u16) -> ContainerHandle {
+        ContainerHandle {
+            id: id.to_string(),
+            service_name: "ssh".to_string(),
+            port: 22,
+            host_port,
+            created_at: Utc::now(),
+            process_handle: None,
+            pty_master: None,
+            activity_log_file: None,
+            tcp_socket: None,
+            udp_socket: None,
+            runtime: Runtime::SystemdNspawn,
+            health: ContainerHealth::Healthy,
+            persona: None,
+            log_dir: ContainerPaths::default().log_dir,
+        }
+    }
+
+    fn pool() -> ContainerPool {
+        ContainerPool::new(Arc::new(Mutex::new(ContainerManager::new_mock())))
+    }
+
+    /// Configures the pool without starting the replenishing tasks
+    fn configure_idle(pool: &ContainerPool, services: &[ServiceConfig], size: usize) {
+        let mut state = pool.state.lock().unwrap();
+        state.size = size;
+        state.services = services
+            .iter()
+            .map(|s| (s.name.clone(), s.clone()))
+            .collect();
+        state.filling = state.services.keys().cloned().collect();
+    }
+
+    #[tokio::test]
+    async fn warm_containers_are_handed_out_connected() {
+        let service_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host_port = service_listener.local_addr().unwrap().port();
+
+        let pool = pool();
+        configure_idle(&pool, &[service()], 1);
+        assert!(pool
+            .stock(&service(), handle("warm-1", host_port))
+            .is_none());
+        // Full
+        assert!(pool
+            .stock(&service(), handle("warm-2", host_port))
+            .is_some());
+        assert_eq!(pool.warm_count("ssh"), 1);
+
+        let handle = pool.acquire(&service()).await.unwrap();
+        assert_eq!(handle.id, "warm-1");
+        assert!(handle.tcp_socket.is_some());
+        assert!(service_listener.accept().await.is_ok());
+        assert_eq!(pool.warm_count("ssh"), 0);
+    }
+
+    #[tokio::test]
+    async fn containers_of_another_configuration_are_not_handed_out() {
+        let pool = pool();
+        configure_idle(&pool, &[service()], 2);
+        assert!(pool.stock(&service(), handle("warm-1", 1)).is_none());
+
+        let mut modified = service();
+        modified.banner_response = Some("SSH-2.0-OpenSSH_9.6".to_string());
+        assert!(pool.take_warm(&modified).is_none());
+        assert!(pool.stock(&modified, handle("warm-2", 1)).is_some());
+
+        // Reconfiguring drops the outdated containers
+        pool.configure(&[modified], 0);
+        assert_eq!(pool.warm_count("ssh"), 0);
+    }
+}
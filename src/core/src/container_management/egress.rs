@@ -0,0 +1,365 @@
+//! Outbound traffic control of service containers with nftables.
+//!
+//! Each container whose service has a restrictive [`EgressPolicy`] gets its own base
+//! chain in the `inet miel_egress` table, named after the container id. Every rule of
+//! the chain matches the container, either by its machine scope cgroup (nspawn
+//! containers share the host network, so their packets go through the `output` hook)
+//! or by its bridge address (docker and podman packets are routed through `forward`).
+//!
+//! Dropped packets are logged by the kernel with a `miel egress` prefix and counted.
+//! The counter is polled so that blocked attempts are reported as
+//! [`Event::EgressBlocked`] events, the last time when the chain is removed.
+
+use std::net::IpAddr;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+use crate::configuration::types::{EgressConfig, EgressPolicy};
+use crate::container_management::Runtime;
+use crate::error_handling::types::ContainerError;
+use crate::events::{self, Event};
+
+const TABLE: &str = "miel_egress";
+/// How often the drop counter of a container is checked for new blocked attempts
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+const LOOKUP_ATTEMPTS: u32 = 10;
+const LOOKUP_INTERVAL: Duration = Duration::from_millis(200);
+/// Kernel log lines per second, the drops past it are still counted
+const LOG_RATE_PER_SECOND: u32 = 5;
+
+/// What the rules of a container match on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EgressTarget {
+    /// cgroup v2 path of an nspawn machine scope, e.g. `/machine.slice/machine-x.scope`
+    Cgroup(String),
+    /// Address of a docker or podman container on its bridge network
+    Address(IpAddr),
+}
+
+impl EgressTarget {
+    /// Looks the target of a started container up, retrying while the runtime
+    /// registers it
+    ///
+    /// # Errors
+    /// Returns [`ContainerError::StartFailed`] when neither a machine scope nor a
+    /// bridge address can be found, and for process sandboxes which have neither.
+    pub async fn lookup(runtime: &Runtime, container_id: &str) -> Result<Self, ContainerError> {
+        let (program, args) = match runtime {
+            Runtime::SystemdNspawn => (
+                "machinectl",
+                ["show", container_id, "--property=ControlGroup", "--value"],
+            ),
+            Runtime::Docker | Runtime::Podman => (
+                runtime.binary(),
+                [
+                    "inspect",
+                    "--format",
+                    "{{range .NetworkSettings.Networks}}{{.IPAddress}} {{end}}",
+                    container_id,
+                ],
+            ),
+            Runtime::ProcessSandbox => {
+                return Err(ContainerError::StartFailed(format!(
+                    "Process sandbox {} has no network of its own to filter",
+                    container_id
+                )))
+            }
+        };
+
+        for _ in 0..LOOKUP_ATTEMPTS {
+            if let Ok(output) = Command::new(program).args(args).output().await {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                let target = match (runtime, stdout.split_whitespace().next()) {
+                    (_, None) => None,
+                    (Runtime::SystemdNspawn, Some(path)) => {
+                        Some(EgressTarget::Cgroup(path.to_string()))
+                    }
+                    (_, Some(address)) => address.parse().ok().map(EgressTarget::Address),
+                };
+                if let Some(target) = target {
+                    return Ok(target);
+                }
+            }
+            tokio::time::sleep(LOOKUP_INTERVAL).await;
+        }
+
+        Err(ContainerError::StartFailed(format!(
+            "no cgroup or address found to apply the egress policy of container {}",
+            container_id
+        )))
+    }
+
+    fn hook(&self) -> &'static str {
+        match self {
+            EgressTarget::Cgroup(_) => "output",
+            EgressTarget::Address(_) => "forward",
+        }
+    }
+
+    fn matcher(&self) -> String {
+        match self {
+            EgressTarget::Cgroup(path) => {
+                let path = path.trim_matches('/');
+                format!(
+                    "socket cgroupv2 level {} \"{}\"",
+                    path.split('/').count(),
+                    path
+                )
+            }
+            EgressTarget::Address(IpAddr::V4(ip)) => format!("ip saddr {}", ip),
+            EgressTarget::Address(IpAddr::V6(ip)) => format!("ip6 saddr {}", ip),
+        }
+    }
+}
+
+/// Filter enforcing the egress policy of one container, removed with [`remove`](Self::remove)
+#[derive(Clone)]
+pub struct EgressFilter {
+    container_id: String,
+    service: String,
+    /// Blocked packets already reported as events
+    reported: Arc<AtomicU64>,
+}
+
+impl EgressFilter {
+    /// Loads the rules of `config` for the container and starts reporting its
+    /// blocked attempts. Returns `None` for the `allow` policy.
+    ///
+    /// # Errors
+    /// Returns [`ContainerError::StartFailed`] when `nft` is missing or rejects the rules.
+    pub async fn apply(
+        container_id: &str,
+        service: &str,
+        target: &EgressTarget,
+        config: &EgressConfig,
+    ) -> Result<Option<Self>, ContainerError> {
+        let Some(ruleset) = ruleset(container_id, target, config) else {
+            return Ok(None);
+        };
+
+        nft(&["-f", "-"], Some(&ruleset))
+            .await
+            .map_err(|e| ContainerError::StartFailed(format!("egress policy: {}", e)))?;
+        info!(
+            "Egress policy {:?} applied to container {}",
+            config.policy, container_id
+        );
+
+        let filter = Self {
+            container_id: container_id.to_string(),
+            service: service.to_string(),
+            reported: Arc::new(AtomicU64::new(0)),
+        };
+        filter.spawn_poller();
+        Ok(Some(filter))
+    }
+
+    /// Reports the attempts blocked since the last poll and deletes the rules
+    pub async fn remove(self) {
+        self.report().await;
+        let chain = chain_name(&self.container_id);
+        if let Err(e) = nft(&["delete", "chain", "inet", TABLE, &chain], None).await {
+            warn!(
+                "Failed to remove egress rules of container {}: {}",
+                self.container_id, e
+            );
+        }
+    }
+
+    /// Polls the drop counter until the chain disappears
+    fn spawn_poller(&self) {
+        let filter = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                if !filter.report().await {
+                    break;
+                }
+            }
+            debug!(
+                "Egress monitoring ended for container: {}",
+                filter.container_id
+            );
+        });
+    }
+
+    /// Emits the new blocked attempts, `false` once the chain is gone
+    async fn report(&self) -> bool {
+        let chain = chain_name(&self.container_id);
+        let Ok(listing) = nft(&["-j", "list", "chain", "inet", TABLE, &chain], None).await else {
+            return false;
+        };
+        let blocked = blocked_packets(&listing);
+        let previous = self.reported.fetch_max(blocked, Ordering::Relaxed);
+        if blocked > previous {
+            events::emit(Event::EgressBlocked {
+                container_id: self.container_id.clone(),
+                service: self.service.clone(),
+                packets: blocked - previous,
+            });
+        }
+        true
+    }
+}
+
+/// Deletes the rules left behind by a previous run, best effort
+pub fn reset() {
+    let _ = std::process::Command::new("nft")
+        .args(["delete", "table", "inet", TABLE])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
+
+fn chain_name(container_id: &str) -> String {
+    container_id.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+}
+
+/// `nft -f` script applying `config` to `target`, `None` for the `allow` policy
+pub fn ruleset(container_id: &str, target: &EgressTarget, config: &EgressConfig) -> Option<String> {
+    let accepted = match config.policy {
+        EgressPolicy::Allow => return None,
+        EgressPolicy::BlockAll => None,
+        EgressPolicy::DnsOnly => Some("meta l4proto { tcp, udp } th dport 53 accept".to_string()),
+        EgressPolicy::RateLimited => Some(format!(
+            "ct state new limit rate {}/minute accept",
+            config.rate_per_minute
+        )),
+    };
+
+    let chain = chain_name(container_id);
+    let matcher = target.matcher();
+    let mut rules = vec!["ct state established,related accept".to_string()];
+    rules.extend(accepted);
+    rules.push(format!(
+        "limit rate {}/second log prefix \"miel egress {}: \" level warn",
+        LOG_RATE_PER_SECOND, container_id
+    ));
+    rules.push("counter drop".to_string());
+
+    let mut script = format!(
+        "add table inet {table}\nadd chain inet {table} {chain} {{ type filter hook {hook} priority filter; policy accept; }}\n",
+        table = TABLE,
+        chain = chain,
+        hook = target.hook()
+    );
+    for rule in rules {
+        script.push_str(&format!(
+            "add rule inet {} {} {} {}\n",
+            TABLE, chain, matcher, rule
+        ));
+    }
+    Some(script)
+}
+
+/// Packets counted by the rules of a `nft -j list chain` output
+fn blocked_packets(listing: &str) -> u64 {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(listing) else {
+        return 0;
+    };
+    value["nftables"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|item| item["rule"]["expr"].as_array())
+        .flatten()
+        .filter_map(|expr| expr["counter"]["packets"].as_u64())
+        .sum()
+}
+
+/// Runs `nft` with `args`, feeding it `input`, and returns its output
+async fn nft(args: &[&str], input: Option<&str>) -> std::io::Result<String> {
+    let mut child = Command::new("nft")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        if let Some(input) = input {
+            stdin.write_all(input.as_bytes()).await?;
+        }
+    }
+
+    let output = child.wait_with_output().await?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(std::io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(policy: EgressPolicy) -> EgressConfig {
+        EgressConfig {
+            policy,
+            ..EgressConfig::default()
+        }
+    }
+
+    #[test]
+    fn rules_match_the_container_and_end_with_a_counted_drop() {
+        let target =
+            EgressTarget::Cgroup("/machine.slice/machine-miel\\x2dssh\\x2d1.scope".to_string());
+        let script = ruleset("miel-ssh-1", &target, &config(EgressPolicy::DnsOnly)).unwrap();
+
+        assert!(script.contains(
+            "add chain inet miel_egress miel_ssh_1 { type filter hook output priority filter; policy accept; }"
+        ));
+        let rules: Vec<&str> = script
+            .lines()
+            .filter(|line| line.starts_with("add rule"))
+            .collect();
+        assert!(rules.iter().all(|rule| rule.contains(
+            "socket cgroupv2 level 2 \"machine.slice/machine-miel\\x2dssh\\x2d1.scope\""
+        )));
+        assert!(rules[1].ends_with("meta l4proto { tcp, udp } th dport 53 accept"));
+        assert!(rules.last().unwrap().ends_with("counter drop"));
+    }
+
+    #[test]
+    fn policies_translate_to_rules() {
+        let target = EgressTarget::Address("172.17.0.2".parse().unwrap());
+
+        assert!(ruleset("c", &target, &config(EgressPolicy::Allow)).is_none());
+
+        let blocked = ruleset("c", &target, &config(EgressPolicy::BlockAll)).unwrap();
+        assert!(blocked.contains("hook forward"));
+        assert!(blocked.contains("ip saddr 172.17.0.2 counter drop"));
+        assert!(!blocked.contains("dport 53"));
+
+        let limited = ruleset("c", &target, &config(EgressPolicy::RateLimited)).unwrap();
+        assert!(limited.contains("ip saddr 172.17.0.2 ct state new limit rate 10/minute accept"));
+
+        let v6 = EgressTarget::Address("fd00::2".parse().unwrap());
+        assert!(ruleset("c", &v6, &config(EgressPolicy::BlockAll))
+            .unwrap()
+            .contains("ip6 saddr fd00::2 counter drop"));
+    }
+
+    #[test]
+    fn blocked_packets_sums_rule_counters() {
+        let listing = r#"{"nftables": [
+            {"metainfo": {"version": "1.0.9"}},
+            {"chain": {"family": "inet", "table": "miel_egress", "name": "c", "handle": 1}},
+            {"rule": {"chain": "c", "handle": 2, "expr": [{"accept": null}]}},
+            {"rule": {"chain": "c", "handle": 3, "expr": [
+                {"counter": {"packets": 7, "bytes": 420}}, {"drop": null}
+            ]}}
+        ]}"#;
+
+        assert_eq!(blocked_packets(listing), 7);
+        assert_eq!(blocked_packets("not json"), 0);
+    }
+}
@@ -0,0 +1,585 @@
+//! Image based rootfs provisioning for nspawn containers.
+//!
+//! A service's `container_image` can point to a root filesystem image instead of
+//! relying on the fabricated rootfs. Supported sources:
+//! - a tarball (`.tar`, `.tar.gz`, `.tgz`) of a root filesystem
+//! - an OCI image layout directory (as produced by `skopeo copy ... oci:<dir>`),
+//!   whose layers are applied in order, honoring whiteouts
+//!
+//! `container_image` is either a path to such a source, or a name looked up in the
+//! images directory (`<name>.tar.gz`, `<name>.tgz`, `<name>.tar` or `<name>/`).
+//! Sources are unpacked once into `<images dir>/cache/` and copied into each
+//! container directory, so unpacking cost is only paid when the source changes.
+
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::time::UNIX_EPOCH;
+use tracing::{debug, info, warn};
+
+use crate::error_handling::types::ContainerError;
+
+/// Default location of the images and of their unpacked cache
+pub const DEFAULT_IMAGE_DIR: &str = "/var/lib/miel/images";
+
+/// Where an image is unpacked from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImageSource {
+    /// Root filesystem tarball, optionally gzip compressed
+    Tarball(PathBuf),
+    /// OCI image layout directory
+    OciLayout(PathBuf),
+}
+
+/// Resolves, unpacks and caches container images.
+#[derive(Debug, Clone)]
+pub struct ImageProvisioner {
+    image_dir: PathBuf,
+    /// Restoring file owners needs root, unprivileged runs keep the current user
+    preserve_ownerships: bool,
+}
+
+#[derive(Deserialize)]
+struct OciIndex {
+    manifests: Vec<OciDescriptor>,
+}
+
+#[derive(Deserialize)]
+struct OciManifest {
+    layers: Vec<OciDescriptor>,
+}
+
+#[derive(Deserialize)]
+struct OciDescriptor {
+    #[serde(rename = "mediaType", default)]
+    media_type: String,
+    digest: String,
+}
+
+impl Default for ImageProvisioner {
+    fn default() -> Self {
+        Self::new(DEFAULT_IMAGE_DIR)
+    }
+}
+
+impl ImageProvisioner {
+    pub fn new<P: Into<PathBuf>>(image_dir: P) -> Self {
+        #[cfg(unix)]
+        let preserve_ownerships = {
+            use std::os::unix::fs::MetadataExt;
+            fs::metadata("/proc/self").is_ok_and(|meta| meta.uid() == 0)
+        };
+        #[cfg(not(unix))]
+        let preserve_ownerships = false;
+
+        Self {
+            image_dir: image_dir.into(),
+            preserve_ownerships,
+        }
+    }
+
+    pub fn image_dir(&self) -> &Path {
+        &self.image_dir
+    }
+
+    /// Finds the source of `image`, `None` when it does not name an image on disk.
+    pub fn resolve(&self, image: &str) -> Option<ImageSource> {
+        if image.is_empty() {
+            return None;
+        }
+
+        let literal = PathBuf::from(image);
+        let named = [
+            self.image_dir.join(format!("{}.tar.gz", image)),
+            self.image_dir.join(format!("{}.tgz", image)),
+            self.image_dir.join(format!("{}.tar", image)),
+            self.image_dir.join(image),
+        ];
+
+        std::iter::once(literal)
+            .chain(named)
+            .find_map(|candidate| Self::source_at(&candidate))
+    }
+
+    fn source_at(path: &Path) -> Option<ImageSource> {
+        if path.is_dir() && path.join("oci-layout").is_file() {
+            return Some(ImageSource::OciLayout(path.to_path_buf()));
+        }
+
+        let name = path.file_name()?.to_string_lossy();
+        let is_tarball =
+            name.ends_with(".tar") || name.ends_with(".tar.gz") || name.ends_with(".tgz");
+        if path.is_file() && is_tarball {
+            return Some(ImageSource::Tarball(path.to_path_buf()));
+        }
+        None
+    }
+
+    /// Populates `container_path` from `image`.
+    ///
+    /// Returns `Ok(false)` without touching `container_path` when `image` does not
+    /// resolve to a source, so the caller can fall back to the fabricated rootfs.
+    pub fn provision(&self, image: &str, container_path: &Path) -> Result<bool, ContainerError> {
+        let Some(source) = self.resolve(image) else {
+            debug!("No image source found for '{}'", image);
+            return Ok(false);
+        };
+
+        let rootfs = self.cached_rootfs(image, &source)?;
+
+        debug!(
+            "Copying cached rootfs {} into {}",
+            rootfs.display(),
+            container_path.display()
+        );
+        fs::create_dir_all(container_path)?;
+        // cp keeps ownership, modes, symlinks and device nodes, and reflinks when possible
+        let output = Command::new("cp")
+            .arg("--archive")
+            .arg("--reflink=auto")
+            .arg(format!("{}/.", rootfs.display()))
+            .arg(container_path)
+            .output()?;
+        if !output.status.success() {
+            return Err(ContainerError::CreationFailed(format!(
+                "Failed to copy image rootfs: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        info!(
+            "Provisioned {} from image '{}'",
+            container_path.display(),
+            image
+        );
+        Ok(true)
+    }
+
+    /// Returns the unpacked rootfs of `source`, unpacking it if not cached yet.
+    pub fn cached_rootfs(
+        &self,
+        image: &str,
+        source: &ImageSource,
+    ) -> Result<PathBuf, ContainerError> {
+        let cache_root = self.image_dir.join("cache");
+        let key = format!("{}-{}", Self::sanitize(image), Self::fingerprint(source)?);
+        let rootfs = cache_root.join(&key);
+
+        // Marker written once unpacking succeeded, kept outside the rootfs so it is not copied
+        let complete = cache_root.join(format!("{}.complete", key));
+        if complete.is_file() && rootfs.is_dir() {
+            debug!("Using cached rootfs {}", rootfs.display());
+            return Ok(rootfs);
+        }
+
+        info!("Unpacking image '{}' into {}", image, rootfs.display());
+        // Unpack next to the final location and rename, so a crash never leaves a partial cache
+        let staging = cache_root.join(format!(".{}.partial", key));
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        fs::create_dir_all(&staging)?;
+
+        let unpacked = match source {
+            ImageSource::Tarball(path) => self.apply_layer(File::open(path)?, &staging),
+            ImageSource::OciLayout(dir) => self.unpack_oci(dir, &staging),
+        };
+        if let Err(e) = unpacked {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e);
+        }
+
+        if rootfs.exists() {
+            fs::remove_dir_all(&rootfs)?;
+        }
+        fs::rename(&staging, &rootfs)?;
+        File::create(complete)?;
+        Ok(rootfs)
+    }
+
+    /// Cache key part that changes whenever the source content is replaced
+    fn fingerprint(source: &ImageSource) -> Result<String, ContainerError> {
+        match source {
+            ImageSource::Tarball(path) => {
+                let meta = fs::metadata(path)?;
+                let mtime = meta
+                    .modified()?
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+                Ok(format!("{}-{}", meta.len(), mtime))
+            }
+            ImageSource::OciLayout(dir) => {
+                let manifest = Self::oci_index(dir)?.manifests.remove(0);
+                Ok(Self::digest_hex(&manifest.digest)?
+                    .chars()
+                    .take(16)
+                    .collect())
+            }
+        }
+    }
+
+    fn sanitize(image: &str) -> String {
+        let name = Path::new(image)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| image.to_string());
+        name.chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
+
+    fn oci_index(dir: &Path) -> Result<OciIndex, ContainerError> {
+        let index: OciIndex = serde_json::from_reader(File::open(dir.join("index.json"))?)
+            .map_err(|e| ContainerError::CreationFailed(format!("Invalid OCI index: {}", e)))?;
+        if index.manifests.is_empty() {
+            return Err(ContainerError::CreationFailed(
+                "OCI index has no manifest".to_string(),
+            ));
+        }
+        Ok(index)
+    }
+
+    fn digest_hex(digest: &str) -> Result<&str, ContainerError> {
+        match digest.split_once(':') {
+            Some(("sha256", hex)) if hex.chars().all(|c| c.is_ascii_hexdigit()) => Ok(hex),
+            _ => Err(ContainerError::CreationFailed(format!(
+                "Unsupported OCI digest '{}'",
+                digest
+            ))),
+        }
+    }
+
+    fn blob_path(dir: &Path, digest: &str) -> Result<PathBuf, ContainerError> {
+        Ok(dir
+            .join("blobs")
+            .join("sha256")
+            .join(Self::digest_hex(digest)?))
+    }
+
+    fn unpack_oci(&self, dir: &Path, dest: &Path) -> Result<(), ContainerError> {
+        let manifest_desc = Self::oci_index(dir)?.manifests.remove(0);
+        let manifest: OciManifest =
+            serde_json::from_reader(File::open(Self::blob_path(dir, &manifest_desc.digest)?)?)
+                .map_err(|e| {
+                    ContainerError::CreationFailed(format!("Invalid OCI manifest: {}", e))
+                })?;
+
+        for layer in &manifest.layers {
+            if layer.media_type.contains("zstd") {
+                return Err(ContainerError::CreationFailed(format!(
+                    "Unsupported OCI layer media type {}",
+                    layer.media_type
+                )));
+            }
+            debug!("Applying OCI layer {}", layer.digest);
+            self.apply_layer(File::open(Self::blob_path(dir, &layer.digest)?)?, dest)?;
+        }
+        Ok(())
+    }
+
+    /// Extracts a (possibly gzip compressed) tar layer into `dest`, applying OCI whiteouts.
+    fn apply_layer(&self, file: File, dest: &Path) -> Result<(), ContainerError> {
+        let mut reader = BufReader::new(file);
+        let mut magic = [0u8; 2];
+        let n = reader.read(&mut magic)?;
+        let gzipped = n == 2 && magic == [0x1f, 0x8b];
+        let prefix = std::io::Cursor::new(magic[..n].to_vec());
+        let stream: Box<dyn Read> = if gzipped {
+            Box::new(GzDecoder::new(prefix.chain(reader)))
+        } else {
+            Box::new(prefix.chain(reader))
+        };
+
+        let mut archive = tar::Archive::new(stream);
+        archive.set_preserve_permissions(true);
+        archive.set_preserve_ownerships(self.preserve_ownerships);
+        archive.set_overwrite(true);
+
+        // Paths added by this layer, which an opaque whiteout must not hide
+        let mut layer_paths = HashSet::new();
+        let root = dest.canonicalize()?;
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+
+            // Never let an archive escape the destination
+            if path
+                .components()
+                .any(|c| matches!(c, Component::ParentDir | Component::Prefix(_)))
+            {
+                warn!("Skipping unsafe archive path {}", path.display());
+                continue;
+            }
+
+            let file_name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+
+            if file_name == ".wh..wh..opq" {
+                // Opaque whiteout: hide everything lower layers put in this directory
+                let parent = path.parent().unwrap_or(Path::new("")).to_path_buf();
+                if let Some(dir) = Self::resolve_dir(&root, &parent) {
+                    for child in fs::read_dir(&dir)? {
+                        let child = child?;
+                        if !layer_paths.contains(&parent.join(child.file_name())) {
+                            Self::remove_path(&child.path())?;
+                        }
+                    }
+                }
+                continue;
+            }
+
+            if let Some(hidden) = file_name.strip_prefix(".wh.") {
+                if matches!(hidden, "" | "." | "..") || hidden.contains(std::path::is_separator) {
+                    warn!("Skipping unsafe whiteout {}", path.display());
+                    continue;
+                }
+                let parent = path.parent().unwrap_or(Path::new(""));
+                if let Some(dir) = Self::resolve_dir(&root, parent) {
+                    let target = dir.join(hidden);
+                    if target.starts_with(&root) && target != root {
+                        Self::remove_path(&target)?;
+                    }
+                }
+                continue;
+            }
+
+            entry.unpack_in(dest)?;
+            layer_paths.insert(path.components().collect::<PathBuf>());
+        }
+        Ok(())
+    }
+
+    /// Directory `dir` of the rootfs at `root` with the symlinks of lower layers
+    /// resolved, `None` when it does not exist or a symlink leads out of `root`
+    fn resolve_dir(root: &Path, dir: &Path) -> Option<PathBuf> {
+        let resolved = root.join(dir).canonicalize().ok()?;
+        if !resolved.starts_with(root) {
+            warn!(
+                "Skipping whiteout in {}, which leads out of the rootfs",
+                dir.display()
+            );
+            return None;
+        }
+        fs::symlink_metadata(&resolved)
+            .is_ok_and(|meta| meta.is_dir())
+            .then_some(resolved)
+    }
+
+    fn remove_path(path: &Path) -> Result<(), ContainerError> {
+        match fs::symlink_metadata(path) {
+            Ok(meta) if meta.is_dir() => fs::remove_dir_all(path)?,
+            Ok(_) => fs::remove_file(path)?,
+            Err(_) => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    fn tar_bytes(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_uid(0);
+            header.set_gid(0);
+            header.set_mtime(0);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn resolves_named_tarball_and_unknown_images() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("debian.tar.gz"), gzip(&tar_bytes(&[]))).unwrap();
+        let provisioner = ImageProvisioner::new(dir.path());
+
+        assert_eq!(
+            provisioner.resolve("debian"),
+            Some(ImageSource::Tarball(dir.path().join("debian.tar.gz")))
+        );
+        assert_eq!(provisioner.resolve("minimal-ssh"), None);
+    }
+
+    #[test]
+    fn provisions_from_cached_tarball() {
+        let dir = tempdir().unwrap();
+        let tarball = dir.path().join("rootfs.tgz");
+        fs::write(
+            &tarball,
+            gzip(&tar_bytes(&[("etc/os-release", "ID=debian\n")])),
+        )
+        .unwrap();
+        let provisioner = ImageProvisioner::new(dir.path().join("images"));
+
+        let first = dir.path().join("c1");
+        assert!(provisioner
+            .provision(tarball.to_str().unwrap(), &first)
+            .unwrap());
+        assert_eq!(
+            fs::read_to_string(first.join("etc/os-release")).unwrap(),
+            "ID=debian\n"
+        );
+
+        // Second container reuses the unpacked cache
+        let cache_entries = fs::read_dir(dir.path().join("images/cache"))
+            .unwrap()
+            .count();
+        let second = dir.path().join("c2");
+        assert!(provisioner
+            .provision(tarball.to_str().unwrap(), &second)
+            .unwrap());
+        assert!(second.join("etc/os-release").is_file());
+        assert_eq!(
+            fs::read_dir(dir.path().join("images/cache"))
+                .unwrap()
+                .count(),
+            cache_entries
+        );
+    }
+
+    #[test]
+    fn applies_oci_layers_with_whiteouts() {
+        let dir = tempdir().unwrap();
+        let layout = dir.path().join("alpine");
+        let blobs = layout.join("blobs/sha256");
+        fs::create_dir_all(&blobs).unwrap();
+        fs::write(
+            layout.join("oci-layout"),
+            r#"{"imageLayoutVersion":"1.0.0"}"#,
+        )
+        .unwrap();
+
+        let base = gzip(&tar_bytes(&[
+            ("etc/motd", "base"),
+            ("etc/secret", "remove me"),
+            ("opt/app/old", "old"),
+        ]));
+        let top = tar_bytes(&[
+            ("etc/.wh.secret", ""),
+            ("opt/app/new", "new"),
+            ("opt/app/.wh..wh..opq", ""),
+        ]);
+        fs::write(blobs.join("aa11"), base).unwrap();
+        fs::write(blobs.join("bb22"), top).unwrap();
+        fs::write(
+            blobs.join("cc33"),
+            r#"{"layers":[
+                {"mediaType":"application/vnd.oci.image.layer.v1.tar+gzip","digest":"sha256:aa11"},
+                {"mediaType":"application/vnd.oci.image.layer.v1.tar","digest":"sha256:bb22"}
+            ]}"#,
+        )
+        .unwrap();
+        fs::write(
+            layout.join("index.json"),
+            r#"{"manifests":[{"mediaType":"application/vnd.oci.image.manifest.v1+json","digest":"sha256:cc33"}]}"#,
+        )
+        .unwrap();
+
+        let provisioner = ImageProvisioner::new(dir.path());
+        let container = dir.path().join("container");
+        assert!(provisioner.provision("alpine", &container).unwrap());
+
+        assert_eq!(
+            fs::read_to_string(container.join("etc/motd")).unwrap(),
+            "base"
+        );
+        assert!(!container.join("etc/secret").exists());
+        assert!(!container.join("opt/app/old").exists());
+        assert!(container.join("opt/app/new").is_file());
+    }
+
+    #[test]
+    fn whiteouts_do_not_follow_symlinks_out_of_the_rootfs() {
+        let dir = tempdir().unwrap();
+        let host = dir.path().join("host");
+        fs::create_dir_all(host.join("keys")).unwrap();
+        fs::write(host.join("passwd"), "root:x:0:0").unwrap();
+        fs::write(host.join("keys/id"), "key").unwrap();
+        let rootfs = dir.path().join("rootfs");
+        fs::create_dir_all(&rootfs).unwrap();
+        // Left by a lower layer
+        std::os::unix::fs::symlink(&host, rootfs.join("etc")).unwrap();
+        std::os::unix::fs::symlink("../host/keys", rootfs.join("keys")).unwrap();
+
+        let layer = dir.path().join("layer.tar");
+        fs::write(
+            &layer,
+            tar_bytes(&[("etc/.wh.passwd", ""), ("keys/.wh..wh..opq", "")]),
+        )
+        .unwrap();
+        ImageProvisioner::new(dir.path())
+            .apply_layer(File::open(&layer).unwrap(), &rootfs)
+            .unwrap();
+
+        assert!(host.join("passwd").is_file());
+        assert!(host.join("keys/id").is_file());
+    }
+
+    #[test]
+    fn whiteouts_of_dot_entries_are_skipped() {
+        let dir = tempdir().unwrap();
+        let rootfs = dir.path().join("rootfs");
+        fs::create_dir_all(rootfs.join("etc")).unwrap();
+        fs::write(rootfs.join("etc/hostname"), "honeypot").unwrap();
+        fs::write(dir.path().join("cached"), "image").unwrap();
+
+        let layer = dir.path().join("layer.tar");
+        fs::write(
+            &layer,
+            tar_bytes(&[(".wh...", ""), ("etc/.wh..", ""), ("etc/.wh.", "")]),
+        )
+        .unwrap();
+        ImageProvisioner::new(dir.path())
+            .apply_layer(File::open(&layer).unwrap(), &rootfs)
+            .unwrap();
+
+        assert!(dir.path().join("cached").is_file());
+        assert!(rootfs.join("etc/hostname").is_file());
+    }
+
+    #[test]
+    fn empty_layers_are_not_padded() {
+        let dir = tempdir().unwrap();
+        let rootfs = dir.path().join("rootfs");
+        fs::create_dir_all(&rootfs).unwrap();
+        let layer = dir.path().join("layer.tar");
+        fs::write(&layer, "").unwrap();
+
+        ImageProvisioner::new(dir.path())
+            .apply_layer(File::open(&layer).unwrap(), &rootfs)
+            .unwrap();
+    }
+}
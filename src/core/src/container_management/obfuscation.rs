@@ -1,9 +1,12 @@
-use log::debug;
+use sha2::{Digest, Sha256};
 use std::fs;
 use std::os::unix::fs::PermissionsExt;
+use tracing::debug;
 
+use super::persona_pack::PersonaPack;
 use crate::configuration::types::{FakeProcess, ObfuscationConfig};
 use crate::error_handling::types::ContainerError;
+use crate::honeytokens;
 
 /// Handles obfuscation setup within containers to make them appear more realistic
 pub struct ObfuscationManager;
@@ -12,6 +15,7 @@ impl ObfuscationManager {
     /// Sets up obfuscation artifacts in the container based on the configuration
     pub fn setup_obfuscation(
         container_path: &str,
+        container_id: &str,
         config: &ObfuscationConfig,
     ) -> Result<(), ContainerError> {
         if !config.enabled {
@@ -24,12 +28,32 @@ impl ObfuscationManager {
             container_path
         );
 
+        // A persona pack completes the settings of the service
+        let pack = match &config.persona_pack {
+            Some(name) => Some(PersonaPack::load(name).map_err(|e| {
+                ContainerError::CreationFailed(format!("Failed to load persona pack: {}", e))
+            })?),
+            None => None,
+        };
+        let merged;
+        let config = match &pack {
+            Some(pack) => {
+                merged = pack.merge(config);
+                &merged
+            }
+            None => config,
+        };
+
         Self::setup_fake_hostname(container_path, config)?;
         Self::setup_fake_processes(container_path, config)?;
         Self::setup_fake_files(container_path, config)?;
         Self::setup_fake_users(container_path, config)?;
         Self::setup_fake_network(container_path, config)?;
         Self::setup_fake_uptime(container_path, config)?;
+        Self::setup_honeytokens(container_path, container_id, config)?;
+        if let Some(pack) = &pack {
+            Self::setup_pack_activity(container_path, pack)?;
+        }
 
         debug!("Obfuscation setup completed for container");
         Ok(())
@@ -139,10 +163,11 @@ impl ObfuscationManager {
                 })?;
             }
 
-            let content = fake_file
-                .content
-                .as_deref()
-                .unwrap_or("# Fake file content\n");
+            let content = match (&fake_file.content, fake_file.size_bytes) {
+                (Some(content), _) => content.as_bytes().to_vec(),
+                (None, Some(size)) => Self::filler(&fake_file.path, size),
+                (None, None) => b"# Fake file content\n".to_vec(),
+            };
             fs::write(&file_path, content).map_err(|e| {
                 ContainerError::CreationFailed(format!(
                     "Failed to create fake file {}: {}",
@@ -232,7 +257,10 @@ impl ObfuscationManager {
         );
 
         // Create a fake ifconfig command
-        let ifconfig_script = Self::generate_fake_ifconfig_script(&config.fake_network_interfaces);
+        let ifconfig_script = Self::generate_fake_ifconfig_script(
+            &config.fake_network_interfaces,
+            &config.fake_mac_addresses,
+        );
         let ifconfig_path = format!("{}/tmp/ifconfig", container_path);
 
         fs::write(&ifconfig_path, ifconfig_script).map_err(|e| {
@@ -256,7 +284,10 @@ impl ObfuscationManager {
         })?;
 
         // Also create fake ip command
-        let ip_script = Self::generate_fake_ip_script(&config.fake_network_interfaces);
+        let ip_script = Self::generate_fake_ip_script(
+            &config.fake_network_interfaces,
+            &config.fake_mac_addresses,
+        );
         let ip_path = format!("{}/tmp/ip", container_path);
 
         fs::write(&ip_path, ip_script).map_err(|e| {
@@ -325,6 +356,107 @@ echo " $(date '+%H:%M:%S') up $UPTIME_DAYS days, $(($UPTIME_HOURS % 24)):$(($UPT
         Ok(())
     }
 
+    /// `len` bytes looking like compressed data, the same for each `path`
+    fn filler(path: &str, len: u64) -> Vec<u8> {
+        let mut content = Vec::with_capacity(len as usize + 32);
+        let mut block = Sha256::digest(path.as_bytes());
+        while (content.len() as u64) < len {
+            content.extend_from_slice(&block);
+            block = Sha256::digest(block);
+        }
+        content.truncate(len as usize);
+        content
+    }
+
+    /// Writes the shell history of the users of a persona pack and its crontab
+    fn setup_pack_activity(container_path: &str, pack: &PersonaPack) -> Result<(), ContainerError> {
+        for user in &pack.users {
+            if user.history.is_empty() {
+                continue;
+            }
+            let home = format!("{}{}", container_path, PersonaPack::home(&user.name));
+            fs::create_dir_all(&home).map_err(|e| {
+                ContainerError::CreationFailed(format!(
+                    "Failed to create home directory of {}: {}",
+                    user.name, e
+                ))
+            })?;
+            let mut history = user.history.join("\n");
+            history.push('\n');
+            fs::write(format!("{}/.bash_history", home), history).map_err(|e| {
+                ContainerError::CreationFailed(format!(
+                    "Failed to write shell history of {}: {}",
+                    user.name, e
+                ))
+            })?;
+        }
+
+        if !pack.cron_jobs.is_empty() {
+            let etc = format!("{}/etc", container_path);
+            fs::create_dir_all(&etc).map_err(|e| {
+                ContainerError::CreationFailed(format!("Failed to create /etc: {}", e))
+            })?;
+            fs::write(format!("{}/crontab", etc), pack.crontab()).map_err(|e| {
+                ContainerError::CreationFailed(format!("Failed to write crontab: {}", e))
+            })?;
+        }
+
+        debug!(
+            "Persona pack activity configured for {} users and {} cron jobs",
+            pack.users.len(),
+            pack.cron_jobs.len()
+        );
+        Ok(())
+    }
+
+    /// Writes freshly generated honeytokens and registers them for the container
+    fn setup_honeytokens(
+        container_path: &str,
+        container_id: &str,
+        config: &ObfuscationConfig,
+    ) -> Result<(), ContainerError> {
+        if config.honeytokens.is_empty() {
+            return Ok(());
+        }
+
+        debug!("Setting up {} honeytokens", config.honeytokens.len());
+
+        let mut tokens = Vec::with_capacity(config.honeytokens.len());
+        for spec in &config.honeytokens {
+            let token = honeytokens::generate(spec);
+            let file_path = format!("{}{}", container_path, token.path);
+
+            if let Some(parent) = std::path::Path::new(&file_path).parent() {
+                fs::create_dir_all(parent).map_err(|e| {
+                    ContainerError::CreationFailed(format!(
+                        "Failed to create directory for honeytoken {}: {}",
+                        token.path, e
+                    ))
+                })?;
+            }
+            fs::write(&file_path, &token.content).map_err(|e| {
+                ContainerError::CreationFailed(format!(
+                    "Failed to create honeytoken {}: {}",
+                    token.path, e
+                ))
+            })?;
+
+            // Readable by its owner only, as the secrets are expected to be
+            fs::set_permissions(&file_path, fs::Permissions::from_mode(0o600)).map_err(|e| {
+                ContainerError::CreationFailed(format!(
+                    "Failed to set honeytoken permissions: {}",
+                    e
+                ))
+            })?;
+
+            debug!("Created {} honeytoken: {}", spec.kind.as_str(), token.path);
+            tokens.push(token);
+        }
+        honeytokens::plant(container_id, tokens);
+
+        Ok(())
+    }
+
     /// Generates a fake ps script that includes configured processes
     fn generate_fake_ps_script(processes: &[FakeProcess]) -> String {
         let mut script = String::from("#!/bin/sh\n# Fake ps command\necho 'PID TTY TIME CMD'\n");
@@ -386,8 +518,15 @@ echo "    1 root      20   0 19232 1464 1172 S  0.0  0.1   0:01.23 systemd"
         script
     }
 
+    /// MAC address of the interface at `index`, configured or derived from the index
+    fn mac_address(macs: &[String], index: usize) -> String {
+        macs.get(index)
+            .cloned()
+            .unwrap_or_else(|| format!("02:42:ac:11:00:{:02x}", index))
+    }
+
     /// Generates a fake ifconfig script
-    fn generate_fake_ifconfig_script(interfaces: &[String]) -> String {
+    fn generate_fake_ifconfig_script(interfaces: &[String], macs: &[String]) -> String {
         let mut script = String::from("#!/bin/sh\n# Fake ifconfig command\n");
 
         // Always show loopback
@@ -403,13 +542,13 @@ echo ""
         for (i, interface) in interfaces.iter().enumerate() {
             let ip_suffix = 100 + i;
             script.push_str(&format!(
-                r#"echo "{interface}      Link encap:Ethernet  HWaddr 02:42:ac:11:00:{i:02x}"
+                r#"echo "{interface}      Link encap:Ethernet  HWaddr {mac}"
 echo "          inet addr:192.168.1.{ip_suffix}  Bcast:192.168.1.255  Mask:255.255.255.0"
 echo "          UP BROADCAST RUNNING MULTICAST  MTU:1500  Metric:1"
 echo ""
 "#,
                 interface = interface,
-                i = i,
+                mac = Self::mac_address(macs, i),
                 ip_suffix = ip_suffix
             ));
         }
@@ -418,7 +557,7 @@ echo ""
     }
 
     /// Generates a fake ip script
-    fn generate_fake_ip_script(interfaces: &[String]) -> String {
+    fn generate_fake_ip_script(interfaces: &[String], macs: &[String]) -> String {
         let mut script = String::from("#!/bin/sh\n# Fake ip command\n");
 
         if interfaces.is_empty() {
@@ -441,6 +580,10 @@ echo ""
                 "echo '{}: {}: <BROADCAST,MULTICAST,UP,LOWER_UP> mtu 1500 qdisc pfifo_fast state UP'\n",
                 if_index, interface
             ));
+            script.push_str(&format!(
+                "echo '    link/ether {} brd ff:ff:ff:ff:ff:ff'\n",
+                Self::mac_address(macs, i)
+            ));
             script.push_str(&format!(
                 "echo '    inet 192.168.1.{}/24 brd 192.168.1.255 scope global {}'\n",
                 ip_suffix, interface
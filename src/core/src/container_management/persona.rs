@@ -0,0 +1,190 @@
+//! Identity drawn for each container.
+//!
+//! Static banners and hostnames make every sensor running a service look the
+//! same. When a service sets `obfuscation.randomize`, a [`ContainerPersona`] is
+//! drawn from its pools and templates for every new container: its hostname,
+//! banner, uptime and the MAC addresses of its fake interfaces. The persona
+//! replaces the configured values for that container only, is kept by a
+//! restarted container so that returning clients see the same machine, and is
+//! recorded in the [`CaptureArtifacts`] of the sessions it served.
+//!
+//! [`CaptureArtifacts`]: crate::data_capture::CaptureArtifacts
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::configuration::types::{RandomizationConfig, ServiceConfig};
+
+/// Organizationally unique identifiers of the hypervisors, QEMU/KVM, Xen,
+/// VMware and VirtualBox
+const VENDOR_PREFIXES: [&str; 4] = ["52:54:00", "00:16:3e", "00:50:56", "08:00:27"];
+
+/// Values drawn for one container, see the [module](self) documentation
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ContainerPersona {
+    pub hostname: Option<String>,
+    pub banner: Option<String>,
+    pub uptime_days: Option<u32>,
+    /// One per fake network interface, in order
+    #[serde(default)]
+    pub mac_addresses: Vec<String>,
+}
+
+impl ContainerPersona {
+    /// Persona of a new container of `service`, `None` when the service
+    /// randomizes nothing
+    pub fn draw(service: &ServiceConfig) -> Option<Self> {
+        let obfuscation = &service.obfuscation;
+        let config: &RandomizationConfig = &obfuscation.randomize;
+        if !obfuscation.enabled || config.is_empty() {
+            return None;
+        }
+        let mac_addresses = if config.mac_addresses {
+            let prefix = pick(&VENDOR_PREFIXES);
+            (0..obfuscation.fake_network_interfaces.len())
+                .map(|_| {
+                    format!(
+                        "{}:{:02x}:{:02x}:{:02x}",
+                        prefix,
+                        random_below(256),
+                        random_below(256),
+                        random_below(256)
+                    )
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Some(Self {
+            hostname: (!config.hostnames.is_empty()).then(|| expand(pick(&config.hostnames))),
+            banner: (!config.banners.is_empty()).then(|| expand(pick(&config.banners))),
+            uptime_days: config
+                .uptime_days
+                .map(|(min, max)| min.min(max) + random_below(min.abs_diff(max) + 1)),
+            mac_addresses,
+        })
+    }
+
+    /// `service` as this persona's container runs it
+    pub fn apply(&self, service: &ServiceConfig) -> ServiceConfig {
+        let mut service = service.clone();
+        if let Some(hostname) = &self.hostname {
+            service.obfuscation.fake_hostname = Some(hostname.clone());
+        }
+        if let Some(banner) = &self.banner {
+            service.banner_response = Some(banner.clone());
+        }
+        if let Some(uptime_days) = self.uptime_days {
+            service.obfuscation.system_uptime_days = Some(uptime_days);
+        }
+        if !self.mac_addresses.is_empty() {
+            service.obfuscation.fake_mac_addresses = self.mac_addresses.clone();
+        }
+        service
+    }
+}
+
+/// `template` with its placeholders replaced by random values
+pub fn expand(template: &str) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = if rest.starts_with("{digit}") {
+            Some(("{digit}", random_below(10).to_string()))
+        } else if rest.starts_with("{number}") {
+            Some(("{number}", (1 + random_below(254)).to_string()))
+        } else if rest.starts_with("{hex}") {
+            Some(("{hex}", format!("{:04x}", random_below(0x10000))))
+        } else {
+            None
+        };
+        match value {
+            Some((placeholder, value)) => {
+                expanded.push_str(&value);
+                rest = &rest[placeholder.len()..];
+            }
+            None => {
+                expanded.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+fn pick<T: AsRef<str>>(pool: &[T]) -> &str {
+    pool[random_below(pool.len() as u32) as usize].as_ref()
+}
+
+/// Random number below `bound`, from the low bits of a random uuid
+fn random_below(bound: u32) -> u32 {
+    (Uuid::new_v4().as_u128() % u128::from(bound.max(1))) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::types::ObfuscationConfig;
+
+    #[test]
+    fn templates_are_expanded() {
+        let expanded = expand("SSH-2.0-OpenSSH_8.{digit}p1 {x} host-{hex}.{number}");
+        let (version, rest) = expanded
+            .strip_prefix("SSH-2.0-OpenSSH_8.")
+            .unwrap()
+            .split_at(1);
+        assert!(version.parse::<u8>().unwrap() < 10);
+        let host = rest.strip_prefix("p1 {x} host-").unwrap();
+        let (hex, number) = host.split_once('.').unwrap();
+        assert_eq!(hex.len(), 4);
+        assert!(u16::from_str_radix(hex, 16).is_ok());
+        assert!((1..=254).contains(&number.parse::<u32>().unwrap()));
+        assert_eq!(expand("{"), "{");
+    }
+
+    #[test]
+    fn personas_replace_the_static_values() {
+        let mut service = ServiceConfig {
+            banner_response: Some("SSH-2.0-OpenSSH_9.6".to_string()),
+            obfuscation: ObfuscationConfig {
+                enabled: true,
+                fake_hostname: Some("web-01".to_string()),
+                fake_network_interfaces: vec!["eth0".to_string(), "eth1".to_string()],
+                system_uptime_days: Some(100),
+                ..ObfuscationConfig::default()
+            },
+            ..ServiceConfig::default()
+        };
+        assert!(ContainerPersona::draw(&service).is_none());
+
+        service.obfuscation.randomize = RandomizationConfig {
+            hostnames: vec!["srv-{hex}".to_string()],
+            banners: Vec::new(),
+            uptime_days: Some((30, 10)),
+            mac_addresses: true,
+        };
+        let persona = ContainerPersona::draw(&service).unwrap();
+        assert!(persona.hostname.as_deref().unwrap().starts_with("srv-"));
+        assert_eq!(persona.banner, None);
+        assert!((10..=30).contains(&persona.uptime_days.unwrap()));
+        assert_eq!(persona.mac_addresses.len(), 2);
+        let prefix = &persona.mac_addresses[0][..8];
+        assert!(VENDOR_PREFIXES.contains(&prefix));
+        assert!(persona.mac_addresses[1].starts_with(prefix));
+
+        let applied = persona.apply(&service);
+        assert_eq!(applied.obfuscation.fake_hostname, persona.hostname);
+        assert_eq!(applied.obfuscation.system_uptime_days, persona.uptime_days);
+        assert_eq!(
+            applied.obfuscation.fake_mac_addresses,
+            persona.mac_addresses
+        );
+        assert_eq!(applied.banner_response, service.banner_response);
+
+        service.obfuscation.enabled = false;
+        assert!(ContainerPersona::draw(&service).is_none());
+    }
+}
@@ -0,0 +1,333 @@
+//! Persona packs: ready-made filesystems for the obfuscation of a container.
+//!
+//! Listing `fake_files` by hand does not get a container past a glance at its
+//! home directories. A [`PersonaPack`] describes a whole kind of machine, e.g. a
+//! WordPress server, an IP camera or a developer workstation: its hostname, its
+//! users and their shell history, cron jobs, processes, a few hand-written files
+//! and sets of generated ones, hundreds of uploads, recordings or sources.
+//!
+//! Packs are TOML files. The built-in ones ([`BUILTIN_PACKS`]) ship with the
+//! binary, and a service selects one by name or gives the path of its own with
+//! `obfuscation.persona_pack`. The pack adds to the obfuscation settings of the
+//! service, whose own values take precedence, when the [`ObfuscationManager`]
+//! prepares the container rootfs.
+//!
+//! [`ObfuscationManager`]: super::obfuscation::ObfuscationManager
+
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::configuration::types::{FakeFile, FakeProcess, ObfuscationConfig};
+
+/// Names and sources of the packs shipped with the binary
+pub const BUILTIN_PACKS: [(&str, &str); 3] = [
+    (
+        "wordpress-server",
+        include_str!("../../personas/wordpress-server.toml"),
+    ),
+    ("iot-camera", include_str!("../../personas/iot-camera.toml")),
+    (
+        "dev-workstation",
+        include_str!("../../personas/dev-workstation.toml"),
+    ),
+];
+/// Most files a file set may generate
+pub const MAX_SET_FILES: usize = 1000;
+
+/// A kind of machine, see the [module](self) documentation
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default)]
+pub struct PersonaPack {
+    pub description: String,
+    /// Used when the service sets no `fake_hostname`
+    pub hostname: Option<String>,
+    pub users: Vec<PackUser>,
+    pub processes: Vec<FakeProcess>,
+    pub files: Vec<FakeFile>,
+    pub file_sets: Vec<FileSet>,
+    pub cron_jobs: Vec<CronJob>,
+}
+
+/// A user of the machine, with the commands it typed
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PackUser {
+    pub name: String,
+    /// Lines of its `.bash_history`
+    #[serde(default)]
+    pub history: Vec<String>,
+}
+
+/// Files of the same kind in some directories, e.g. a month of uploads
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FileSet {
+    pub directories: Vec<String>,
+    /// File name, `{n}` being replaced by the index of the file from 1
+    pub name: String,
+    /// Files per directory
+    pub count: usize,
+    /// Content of the files, `{n}` being replaced as in `name`
+    #[serde(default)]
+    pub content: Option<String>,
+    /// Size of the filler making up the content when there is none
+    #[serde(default)]
+    pub size_bytes: Option<u64>,
+}
+
+/// A line of the crontab of a user
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CronJob {
+    #[serde(default = "root")]
+    pub user: String,
+    /// Five cron fields, e.g. `*/5 * * * *`
+    pub schedule: String,
+    pub command: String,
+}
+
+fn root() -> String {
+    "root".to_string()
+}
+
+impl PersonaPack {
+    /// Built-in pack `name`, or the pack file at `name` when it is a path
+    ///
+    /// # Errors
+    /// Fails with [`io::ErrorKind::NotFound`] for an unknown pack, the IO error of
+    /// reading a pack file, or [`io::ErrorKind::InvalidData`] when the pack is not
+    /// valid.
+    pub fn load(name: &str) -> io::Result<Self> {
+        let pack: Self = if name.contains('/') || name.ends_with(".toml") {
+            let source = std::fs::read_to_string(Path::new(name))?;
+            toml::from_str(&source).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", name, e))
+            })?
+        } else {
+            let (_, source) = BUILTIN_PACKS
+                .iter()
+                .find(|(builtin, _)| *builtin == name)
+                .ok_or_else(|| {
+                    let names: Vec<&str> = BUILTIN_PACKS.iter().map(|(name, _)| *name).collect();
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!(
+                            "no persona pack {:?}, built-in ones are {}",
+                            name,
+                            names.join(", ")
+                        ),
+                    )
+                })?;
+            toml::from_str(source).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", name, e))
+            })?
+        };
+        pack.validate()?;
+        Ok(pack)
+    }
+
+    fn validate(&self) -> io::Result<()> {
+        let invalid = |reason: String| Err(io::Error::new(io::ErrorKind::InvalidData, reason));
+        let paths = self.files.iter().map(|file| file.path.as_str()).chain(
+            self.file_sets
+                .iter()
+                .flat_map(|set| set.directories.iter().map(String::as_str)),
+        );
+        for path in paths {
+            if !path.starts_with('/') || path.split('/').any(|c| c == "..") {
+                return invalid(format!("{:?} is not an absolute path", path));
+            }
+        }
+        for set in &self.file_sets {
+            if set.name.contains('/') || set.count > MAX_SET_FILES {
+                return invalid(format!(
+                    "file set {:?} needs a file name and at most {} files",
+                    set.name, MAX_SET_FILES
+                ));
+            }
+        }
+        for user in &self.users {
+            if user.name.is_empty() || user.name.contains(['/', ':']) {
+                return invalid(format!("{:?} is not a user name", user.name));
+            }
+        }
+        for job in &self.cron_jobs {
+            if job.schedule.split_whitespace().count() != 5 {
+                return invalid(format!("cron schedule {:?} needs 5 fields", job.schedule));
+            }
+        }
+        Ok(())
+    }
+
+    /// Hand-written and generated files of the pack
+    pub fn all_files(&self) -> Vec<FakeFile> {
+        let mut files = self.files.clone();
+        for set in &self.file_sets {
+            for directory in &set.directories {
+                for n in 1..=set.count {
+                    let index = n.to_string();
+                    files.push(FakeFile {
+                        path: format!(
+                            "{}/{}",
+                            directory.trim_end_matches('/'),
+                            set.name.replace("{n}", &index)
+                        ),
+                        content: set.content.as_ref().map(|c| c.replace("{n}", &index)),
+                        size_bytes: set.size_bytes,
+                        is_executable: false,
+                    });
+                }
+            }
+        }
+        files
+    }
+
+    /// `config` completed with the pack, the values of `config` coming first
+    pub fn merge(&self, config: &ObfuscationConfig) -> ObfuscationConfig {
+        let mut merged = config.clone();
+        if merged.fake_hostname.is_none() {
+            merged.fake_hostname = self.hostname.clone();
+        }
+        merged.fake_processes.extend(self.processes.iter().cloned());
+        // Files of the service are written last and override those of the pack
+        let mut files = self.all_files();
+        files.append(&mut merged.fake_files);
+        merged.fake_files = files;
+        for user in &self.users {
+            if user.name != "root" && !merged.fake_users.contains(&user.name) {
+                merged.fake_users.push(user.name.clone());
+            }
+        }
+        merged
+    }
+
+    /// Home directory of a user of the pack
+    pub fn home(user: &str) -> String {
+        match user {
+            "root" => "/root".to_string(),
+            name => format!("/home/{}", name),
+        }
+    }
+
+    /// Lines of `/etc/crontab`, the user field included
+    pub fn crontab(&self) -> String {
+        let mut crontab = String::from(
+            "SHELL=/bin/sh\nPATH=/usr/local/sbin:/usr/local/bin:/sbin:/bin:/usr/sbin:/usr/bin\n\n",
+        );
+        for job in &self.cron_jobs {
+            crontab.push_str(&format!("{} {} {}\n", job.schedule, job.user, job.command));
+        }
+        crontab
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container_management::obfuscation::ObfuscationManager;
+
+    #[test]
+    fn builtin_packs_describe_whole_machines() {
+        for (name, _) in BUILTIN_PACKS {
+            let pack = PersonaPack::load(name).unwrap();
+            assert!(pack.hostname.is_some(), "{}", name);
+            assert!(pack.all_files().len() >= 200, "{}", name);
+            assert!(pack.users.iter().any(|u| !u.history.is_empty()), "{}", name);
+            assert!(!pack.cron_jobs.is_empty(), "{}", name);
+        }
+        let e = PersonaPack::load("mainframe").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        assert!(e.to_string().contains("wordpress-server"));
+    }
+
+    #[test]
+    fn packs_complete_the_service_settings() {
+        let pack: PersonaPack = toml::from_str(
+            r#"
+            hostname = "nas-01"
+
+            [[users]]
+            name = "backup"
+            history = ["rsync -a /srv/ nas:/backup/"]
+
+            [[files]]
+            path = "/etc/motd"
+            content = "Welcome"
+            is_executable = false
+
+            [[file_sets]]
+            directories = ["/srv/photos/2023", "/srv/photos/2024/"]
+            name = "DSC_{n}.jpg"
+            count = 3
+            size_bytes = 512
+
+            [[cron_jobs]]
+            schedule = "0 3 * * *"
+            command = "/usr/local/bin/backup.sh"
+            "#,
+        )
+        .unwrap();
+        pack.validate().unwrap();
+
+        let config = ObfuscationConfig {
+            enabled: true,
+            fake_hostname: Some("files".to_string()),
+            fake_files: vec![FakeFile {
+                path: "/etc/motd".to_string(),
+                content: Some("Authorized use only".to_string()),
+                size_bytes: None,
+                is_executable: false,
+            }],
+            ..ObfuscationConfig::default()
+        };
+        let merged = pack.merge(&config);
+        assert_eq!(merged.fake_hostname.as_deref(), Some("files"));
+        assert_eq!(merged.fake_users, ["backup"]);
+        let paths: Vec<&str> = merged.fake_files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths.len(), 8);
+        assert_eq!(paths[1], "/srv/photos/2023/DSC_1.jpg");
+        assert_eq!(paths[6], "/srv/photos/2024/DSC_3.jpg");
+        assert_eq!(
+            merged.fake_files.last().unwrap().content.as_deref(),
+            Some("Authorized use only")
+        );
+        assert!(pack
+            .crontab()
+            .ends_with("0 3 * * * root /usr/local/bin/backup.sh\n"));
+
+        let mut broken = pack.clone();
+        broken.cron_jobs[0].schedule = "@daily".to_string();
+        assert!(broken.validate().is_err());
+    }
+
+    #[test]
+    fn obfuscation_populates_the_rootfs_from_the_pack() {
+        let rootfs = tempfile::tempdir().unwrap();
+        for dir in ["etc", "tmp"] {
+            std::fs::create_dir(rootfs.path().join(dir)).unwrap();
+        }
+        let config = ObfuscationConfig {
+            enabled: true,
+            persona_pack: Some("wordpress-server".to_string()),
+            ..ObfuscationConfig::default()
+        };
+        ObfuscationManager::setup_obfuscation(
+            rootfs.path().to_str().unwrap(),
+            "miel-http-1",
+            &config,
+        )
+        .unwrap();
+
+        let root = rootfs.path();
+        assert!(root.join("var/www/html/wp-config.php").is_file());
+        let hostname = std::fs::read_to_string(root.join("etc/hostname")).unwrap();
+        assert_eq!(hostname.trim(), "wp-prod-01");
+        let passwd = std::fs::read_to_string(root.join("etc/passwd")).unwrap();
+        assert!(passwd.contains("deploy:x:"));
+        let history = std::fs::read_to_string(root.join("home/deploy/.bash_history")).unwrap();
+        assert!(history.contains("wp plugin update --all"));
+        let crontab = std::fs::read_to_string(root.join("etc/crontab")).unwrap();
+        assert!(crontab.contains("wp-cron.php"));
+        let upload = root.join("var/www/html/wp-content/uploads/2024/03/IMG_1.jpg");
+        assert_eq!(std::fs::metadata(upload).unwrap().len(), 48_000);
+    }
+}
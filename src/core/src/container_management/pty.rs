@@ -0,0 +1,177 @@
+//! Pseudo-terminals of the container processes.
+//!
+//! The runtime process of a container gets the slave side of a PTY as its stdin
+//! and stdout, stderr staying a pipe drained into the logs. The services then run
+//! on a terminal, and what they write to it, control characters included, is read
+//! from the master as it arrives. [`PtyMaster`] drains the master for the life of
+//! the container and broadcasts timestamped [`PtyChunk`]s to the recorders of the
+//! sessions using the container.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::sync::Arc;
+
+use chrono::Utc;
+use tokio::io::unix::AsyncFd;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{debug, trace};
+
+use crate::data_capture::PtyChunk;
+
+/// Window size of the terminal given to the services
+const PTY_ROWS: u16 = 24;
+const PTY_COLS: u16 = 80;
+/// Chunks a subscriber may lag behind before losing the oldest ones
+const PTY_BACKLOG: usize = 1024;
+
+/// Both sides of a new PTY
+#[derive(Debug)]
+pub struct Pty {
+    pub master: File,
+    /// Given to the runtime process as its stdin and stdout
+    pub slave: File,
+}
+
+impl Pty {
+    /// Opens a PTY with an 80x24 window. Both sides are close-on-exec, the slave
+    /// is only inherited through the stdio it is set as.
+    pub fn open() -> io::Result<Self> {
+        let mut master: libc::c_int = -1;
+        let mut slave: libc::c_int = -1;
+        let size = libc::winsize {
+            ws_row: PTY_ROWS,
+            ws_col: PTY_COLS,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        // SAFETY: openpty only writes the two descriptors, the name and termios
+        // arguments are optional
+        let rc = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                &size,
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: both descriptors were just opened and are owned by nothing else
+        let (master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+        add_flag(&master, libc::F_GETFD, libc::F_SETFD, libc::FD_CLOEXEC)?;
+        add_flag(&slave, libc::F_GETFD, libc::F_SETFD, libc::FD_CLOEXEC)?;
+        Ok(Self { master, slave })
+    }
+}
+
+/// Sets `flag` on `file` with the `get` and `set` commands of `fcntl`
+fn add_flag(file: &File, get: libc::c_int, set: libc::c_int, flag: libc::c_int) -> io::Result<()> {
+    // SAFETY: fcntl on a descriptor owned by `file`
+    let flags = unsafe { libc::fcntl(file.as_raw_fd(), get) };
+    if flags < 0 || unsafe { libc::fcntl(file.as_raw_fd(), set, flags | flag) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Output side of the PTY of a container, see the [module](self) documentation
+#[derive(Debug)]
+pub struct PtyMaster {
+    output: broadcast::Sender<PtyChunk>,
+    reader: JoinHandle<()>,
+}
+
+impl PtyMaster {
+    /// Starts draining `master` in the background. The output written while
+    /// nobody is subscribed is dropped, so that the services never block on a
+    /// full terminal.
+    ///
+    /// # Errors
+    /// Fails outside of a tokio runtime, or when `master` cannot be made
+    /// non-blocking.
+    pub fn spawn(master: File, container_id: &str) -> io::Result<Self> {
+        add_flag(&master, libc::F_GETFL, libc::F_SETFL, libc::O_NONBLOCK)?;
+        let master = AsyncFd::new(master)?;
+        let (output, _) = broadcast::channel(PTY_BACKLOG);
+        let sender = output.clone();
+        let cid = container_id.to_string();
+        let reader = tokio::spawn(async move {
+            let mut buf = [0u8; 4096];
+            loop {
+                let Ok(mut guard) = master.readable().await else {
+                    break;
+                };
+                match guard.try_io(|inner| inner.get_ref().read(&mut buf)) {
+                    Ok(Ok(0)) => break,
+                    Ok(Ok(n)) => {
+                        trace!("[container:{}][pty] {} bytes", cid, n);
+                        let _ = sender.send(PtyChunk {
+                            timestamp: Utc::now(),
+                            data: Arc::from(&buf[..n]),
+                        });
+                    }
+                    Ok(Err(e)) if e.kind() == io::ErrorKind::Interrupted => {}
+                    // EIO once the last process holding the slave exited
+                    Ok(Err(e)) => {
+                        debug!("PTY of container {} closed: {}", cid, e);
+                        break;
+                    }
+                    Err(_would_block) => {}
+                }
+            }
+            debug!("PTY monitoring ended for container: {}", cid);
+        });
+        Ok(Self { output, reader })
+    }
+
+    /// Receives the output written from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<PtyChunk> {
+        self.output.subscribe()
+    }
+}
+
+impl Drop for PtyMaster {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn terminal_output_is_broadcast_with_control_characters() {
+        let Pty { master, mut slave } = Pty::open().unwrap();
+        let pty = PtyMaster::spawn(master, "miel-test").unwrap();
+        let mut output = pty.subscribe();
+
+        slave.write_all(b"root@srv:~# \x1b[1mls\x1b[0m\n").unwrap();
+        let mut received = Vec::new();
+        while !received.ends_with(b"\r\n") {
+            let chunk = tokio::time::timeout(Duration::from_secs(5), output.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            received.extend_from_slice(&chunk.data);
+        }
+        // The line discipline translates the line break, escapes are kept as is
+        assert_eq!(received, b"root@srv:~# \x1b[1mls\x1b[0m\r\n");
+
+        // Closing the slave ends the reader
+        drop(slave);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !pty.reader.is_finished() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+}
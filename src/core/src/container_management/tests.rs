@@ -65,7 +65,11 @@ mod tests {
                 ],
                 fake_users: vec!["admin".to_string(), "webuser".to_string()],
                 fake_network_interfaces: vec!["eth0".to_string()],
+                fake_mac_addresses: vec![],
+                persona_pack: None,
                 system_uptime_days: Some(100),
+                honeytokens: vec![],
+                randomize: Default::default(),
             },
         }
     }
@@ -101,6 +105,7 @@ mod tests {
                 created_at: Utc::now(),
                 process_handle: None,
                 pty_master: None,
+                activity_log_file: None,
                 tcp_socket: None,
             };
             self.stats.total_created += 1;
@@ -158,6 +163,7 @@ mod tests {
             created_at: creation_time,
             process_handle: None,
             pty_master: None,
+            activity_log_file: None,
             tcp_socket: None,
         };
 
@@ -312,6 +318,7 @@ mod tests {
             created_at: Utc::now(),
             process_handle: None,
             pty_master: None,
+            activity_log_file: None,
             tcp_socket: None,
         };
 
@@ -333,6 +340,7 @@ mod tests {
             created_at: Utc::now(),
             process_handle: None,
             pty_master: None,
+            activity_log_file: None,
             tcp_socket: None,
         };
 
@@ -422,6 +430,7 @@ mod tests {
             created_at: now,
             process_handle: None,
             pty_master: None,
+            activity_log_file: None,
             tcp_socket: None,
         };
         let hc = h.clone();
@@ -552,6 +561,7 @@ mod tests {
                 created_at: Utc::now(),
                 process_handle: None,
                 pty_master: None,
+                activity_log_file: None,
                 tcp_socket: None,
             };
             registry.insert(handle.id.clone(), handle);
@@ -584,6 +594,7 @@ mod tests {
             created_at: Utc::now(),
             process_handle: None,
             pty_master: None,
+            activity_log_file: None,
             tcp_socket: None,
         };
         registry.insert(handle.id.clone(), handle);
@@ -722,6 +733,7 @@ mod tests {
                     created_at: Utc::now(),
                     process_handle: None,
                     pty_master: None,
+                    activity_log_file: None,
                     tcp_socket: None,
                 };
                 self.stats.total_created += 1;
@@ -770,6 +782,7 @@ mod tests {
             created_at: Utc::now(),
             process_handle: None,
             pty_master: None,
+            activity_log_file: None,
             tcp_socket: None,
         };
 
@@ -1,11 +1,61 @@
 //! Core types used by the container management subsystem.
 
 use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use std::fs::File;
-use tokio::net::TcpStream;
+use std::path::{Path, PathBuf};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::broadcast;
+
+use super::cgroup::ResourceUsage;
+use super::persona::ContainerPersona;
+use super::pty::PtyMaster;
+use crate::data_capture::PtyChunk;
+
+/// Default directory of the ephemeral rootfs of the nspawn containers
+pub const DEFAULT_CONTAINER_DIR: &str = "/tmp/miel-containers";
+/// Default directory of the process sandboxes
+pub const DEFAULT_SANDBOX_DIR: &str = "/tmp/miel-sandboxes";
+/// Default directory of the activity logs, uploads and emails of the containers
+pub const DEFAULT_LOG_DIR: &str = "/tmp/miel-logs";
+
+/// Host directories the containers are created in, set with `container_dir`,
+/// `sandbox_dir` and `log_dir` in the configuration file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContainerPaths {
+    /// Holds the ephemeral rootfs of each nspawn container, `<container_dir>/<id>`
+    pub container_dir: PathBuf,
+    /// Holds the root of each process sandbox, `<sandbox_dir>/<id>`
+    pub sandbox_dir: PathBuf,
+    /// Holds the activity log, upload and mail directories of each container. It
+    /// is bound into the nspawn containers at the same path
+    pub log_dir: PathBuf,
+}
+
+impl Default for ContainerPaths {
+    fn default() -> Self {
+        Self {
+            container_dir: PathBuf::from(DEFAULT_CONTAINER_DIR),
+            sandbox_dir: PathBuf::from(DEFAULT_SANDBOX_DIR),
+            log_dir: PathBuf::from(DEFAULT_LOG_DIR),
+        }
+    }
+}
+
+impl ContainerPaths {
+    /// Rootfs of nspawn container `container_id`
+    pub fn rootfs(&self, container_id: &str) -> PathBuf {
+        self.container_dir.join(container_id)
+    }
+
+    /// Root of process sandbox `container_id`
+    pub fn sandbox(&self, container_id: &str) -> PathBuf {
+        self.sandbox_dir.join(container_id)
+    }
+}
 
 /// Aggregate counters describing the current and historical container state.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ContainerStats {
     /// Number of containers currently tracked as active.
     pub active_count: usize,
@@ -15,6 +65,58 @@ pub struct ContainerStats {
     pub failed_count: u64,
 }
 
+/// A running container, as listed by [`ContainerManager::list_containers`].
+///
+/// [`ContainerManager::list_containers`]: super::ContainerManager::list_containers
+#[derive(Debug, Clone, Serialize)]
+pub struct ContainerInfo {
+    pub id: String,
+    pub service_name: String,
+    pub runtime: Runtime,
+    pub created_at: DateTime<Utc>,
+    /// Seconds since the container was created
+    pub age_secs: u64,
+    /// Whether the last health check found it running
+    pub healthy: bool,
+    /// What it uses, see [`cgroup`](super::cgroup), `None` when unknown
+    pub usage: Option<ResourceUsage>,
+}
+
+/// The counters of the container manager along with its running containers
+#[derive(Debug, Clone, Serialize)]
+pub struct ContainerReport {
+    pub stats: ContainerStats,
+    pub containers: Vec<ContainerInfo>,
+}
+
+/// Health of a container, as last checked while its session was idle.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ContainerHealth {
+    /// Running and, for TCP services, accepting connections.
+    #[default]
+    Healthy,
+    /// The runtime process exited, with its exit status.
+    Exited(String),
+    /// The service no longer accepts connections on its published port.
+    PortClosed,
+}
+
+impl ContainerHealth {
+    pub fn is_healthy(&self) -> bool {
+        *self == ContainerHealth::Healthy
+    }
+}
+
+impl std::fmt::Display for ContainerHealth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContainerHealth::Healthy => write!(f, "healthy"),
+            ContainerHealth::Exited(status) => write!(f, "process exited ({})", status),
+            ContainerHealth::PortClosed => write!(f, "service port closed"),
+        }
+    }
+}
+
 /// Handle describing a specific container instance managed by the system.
 #[derive(Debug)]
 pub struct ContainerHandle {
@@ -30,10 +132,79 @@ pub struct ContainerHandle {
     pub created_at: DateTime<Utc>,
     /// Process handle for the runtime container process, if available.
     pub process_handle: Option<tokio::process::Child>,
-    /// Optional PTY master used to capture stdio from the container.
-    pub pty_master: Option<File>,
+    /// PTY the runtime process runs on, `None` when its stdio are pipes.
+    pub pty_master: Option<PtyMaster>,
+    /// Unified activity log opened for reading, tailed by the stdio capture.
+    pub activity_log_file: Option<File>,
     /// Optional TCP socket associated to the service connection lifecycle.
     pub tcp_socket: Option<TcpStream>,
+    /// Optional UDP socket connected to the service, set instead of `tcp_socket` for UDP services.
+    pub udp_socket: Option<UdpSocket>,
+    /// Runtime backend that created this container.
+    pub runtime: Runtime,
+    /// Outcome of the last health check, see [`ContainerManager::check_health`].
+    ///
+    /// [`ContainerManager::check_health`]: super::ContainerManager::check_health
+    pub health: ContainerHealth,
+    /// Identity drawn for the container, when its service randomizes one
+    pub persona: Option<ContainerPersona>,
+    /// Directory of the activity log, uploads and emails, see [`ContainerPaths::log_dir`]
+    pub log_dir: PathBuf,
+}
+
+impl ContainerHandle {
+    /// Opens a new connection to the service published on `host_port`, for client
+    /// connections after the one set up at creation.
+    pub async fn connect_service(&self) -> std::io::Result<TcpStream> {
+        TcpStream::connect(("127.0.0.1", self.host_port)).await
+    }
+
+    /// Host directory where the service stores the files clients upload
+    pub fn upload_dir(&self) -> PathBuf {
+        upload_dir(&self.log_dir, &self.id)
+    }
+
+    /// Host directory where the service stores the emails clients submit
+    pub fn mail_dir(&self) -> PathBuf {
+        mail_dir(&self.log_dir, &self.id)
+    }
+
+    /// Sources of the stdio capture of a session: a new descriptor of the activity
+    /// log, and the output of the PTY when the container runs on one.
+    ///
+    /// # Errors
+    /// [`std::io::ErrorKind::NotFound`] when the activity log could not be opened
+    /// at creation.
+    pub fn stdio_sources(&self) -> std::io::Result<(File, Option<broadcast::Receiver<PtyChunk>>)> {
+        let log = self
+            .activity_log_file
+            .as_ref()
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))?
+            .try_clone()?;
+        Ok((log, self.pty_master.as_ref().map(PtyMaster::subscribe)))
+    }
+
+    /// Host path of the unified activity log the service writes its stdio and
+    /// login attempts to
+    pub fn activity_log(&self) -> PathBuf {
+        activity_log(&self.log_dir, &self.id)
+    }
+}
+
+/// Activity log of container `container_id` in `log_dir`
+pub(crate) fn activity_log(log_dir: &Path, container_id: &str) -> PathBuf {
+    log_dir.join(format!("container-{}-activity.log", container_id))
+}
+
+/// Upload directory of container `container_id`, inside the log directory bound
+/// into nspawn containers
+pub(crate) fn upload_dir(log_dir: &Path, container_id: &str) -> PathBuf {
+    log_dir.join(format!("container-{}-uploads", container_id))
+}
+
+/// Mail directory of container `container_id`, next to its upload directory
+pub(crate) fn mail_dir(log_dir: &Path, container_id: &str) -> PathBuf {
+    log_dir.join(format!("container-{}-mail", container_id))
 }
 
 // Implement Clone manually since tokio::process::Child and File don't implement Clone
@@ -45,16 +216,61 @@ impl Clone for ContainerHandle {
             port: self.port,
             host_port: self.host_port,
             created_at: self.created_at,
-            process_handle: None, // Can't clone process handle
-            pty_master: None,     // Can't clone file handle
-            tcp_socket: None,     // Can't clone TCP stream
+            process_handle: None,    // Can't clone process handle
+            pty_master: None,        // Can't clone the PTY reader
+            activity_log_file: None, // Can't clone file handle
+            tcp_socket: None,        // Can't clone TCP stream
+            udp_socket: None,        // Can't clone UDP socket
+            runtime: self.runtime.clone(),
+            health: self.health.clone(),
+            persona: self.persona.clone(),
+            log_dir: self.log_dir.clone(),
         }
     }
 }
 
 /// Supported container runtime backends.
-#[derive(Debug, Clone)]
+///
+/// Selected globally with `container_runtime` in the configuration file and
+/// optionally overridden per service with `runtime`.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize, clap::ValueEnum)]
 pub enum Runtime {
     /// systemd-nspawn based containers.
+    #[default]
+    #[serde(rename = "nspawn")]
+    #[value(name = "nspawn")]
     SystemdNspawn,
+    /// Docker containers driven through the `docker` CLI, using real service images.
+    #[serde(rename = "docker")]
+    #[value(name = "docker")]
+    Docker,
+    /// Podman containers, same image workflow as Docker but usable without root.
+    #[serde(rename = "podman")]
+    #[value(name = "podman")]
+    Podman,
+    /// Development fallback for hosts without a container runtime (macOS, WSL):
+    /// the service emulators run as plain child processes bound to localhost.
+    ///
+    /// **No isolation whatsoever** — an attacker reaching a shell runs commands
+    /// as the honeypot user on the host. Never expose it to untrusted networks.
+    #[serde(rename = "process")]
+    #[value(name = "process")]
+    ProcessSandbox,
+}
+
+impl Runtime {
+    /// Name of the host binary used to drive this runtime.
+    pub fn binary(&self) -> &'static str {
+        match self {
+            Runtime::SystemdNspawn => "systemd-nspawn",
+            Runtime::Docker => "docker",
+            Runtime::Podman => "podman",
+            Runtime::ProcessSandbox => "sh",
+        }
+    }
+
+    /// Whether the runtime needs the honeypot to run as root (EUID == 0).
+    pub fn requires_root(&self) -> bool {
+        matches!(self, Runtime::SystemdNspawn)
+    }
 }
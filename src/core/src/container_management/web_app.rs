@@ -0,0 +1,282 @@
+//! Fake web applications served by the scripted daemon of `http` services.
+//!
+//! A [`Site`] is rendered from the [`HttpSiteConfig`] of the service when its
+//! container starts, and handed to the daemon as JSON. The built-in templates
+//! ship with the binary, under `sites/`:
+//! - `default`: the nginx welcome page, with a login form under `/admin/`
+//! - `login_portal`: the sign-in page of a company intranet
+//! - `phpmyadmin`: the Apache default page, and phpMyAdmin under `/phpmyadmin/`
+//! - `router`: the administration of a home router, with a firmware upgrade form
+//!
+//! `{{title}}` and `{{hostname}}` are replaced in the pages, `{{error}}` is left
+//! for the daemon, which fills it once it rejected credentials.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::configuration::types::{HttpSiteConfig, SiteTemplate};
+
+/// Largest page read from a `pages_dir`
+const MAX_PAGE_LEN: u64 = 1024 * 1024;
+/// Most pages read from a `pages_dir`
+const MAX_PAGES: usize = 256;
+
+const NOT_FOUND: &str = include_str!("../../sites/not_found.html");
+
+/// Pages, headers and login behavior of a built-in template
+struct Template {
+    server: &'static str,
+    title: &'static str,
+    pages: &'static [(&'static str, &'static str)],
+    login: &'static str,
+    error: &'static str,
+}
+
+const DEFAULT: Template = Template {
+    server: "nginx/1.18.0 (Ubuntu)",
+    title: "nginx",
+    pages: &[
+        ("/", include_str!("../../sites/default/index.html")),
+        ("/admin/", include_str!("../../sites/default/admin.html")),
+    ],
+    login: "/admin/",
+    error: "Invalid username or password.",
+};
+
+const LOGIN_PORTAL: Template = Template {
+    server: "Microsoft-IIS/10.0",
+    title: "Employee Portal",
+    pages: &[
+        ("/", include_str!("../../sites/login-portal/login.html")),
+        (
+            "/login",
+            include_str!("../../sites/login-portal/login.html"),
+        ),
+    ],
+    login: "/login",
+    error: "The user name or password is incorrect.",
+};
+
+const PHPMYADMIN: Template = Template {
+    server: "Apache/2.4.41 (Ubuntu)",
+    title: "phpMyAdmin",
+    pages: &[
+        ("/", include_str!("../../sites/phpmyadmin/index.html")),
+        (
+            "/phpmyadmin/",
+            include_str!("../../sites/phpmyadmin/login.html"),
+        ),
+        (
+            "/phpmyadmin/index.php",
+            include_str!("../../sites/phpmyadmin/login.html"),
+        ),
+    ],
+    login: "/phpmyadmin/index.php",
+    error: "mysqli_real_connect(): (HY000/1045): Access denied for user",
+};
+
+const ROUTER: Template = Template {
+    server: "mini_httpd/1.30 26Oct2018",
+    title: "Wireless N Router",
+    pages: &[
+        ("/", include_str!("../../sites/router/login.html")),
+        ("/login.cgi", include_str!("../../sites/router/login.html")),
+        (
+            "/upgrade.html",
+            include_str!("../../sites/router/upgrade.html"),
+        ),
+        (
+            "/upgrade.cgi",
+            include_str!("../../sites/router/upgrading.html"),
+        ),
+    ],
+    login: "/login.cgi",
+    error: "Incorrect username or password, please try again.",
+};
+
+fn template(kind: SiteTemplate) -> &'static Template {
+    match kind {
+        SiteTemplate::Default => &DEFAULT,
+        SiteTemplate::LoginPortal => &LOGIN_PORTAL,
+        SiteTemplate::PhpMyAdmin => &PHPMYADMIN,
+        SiteTemplate::Router => &ROUTER,
+    }
+}
+
+/// What the daemon of an `http` service serves
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Site {
+    /// `Server` header of the responses
+    pub server: String,
+    /// Pages by path, query strings left out
+    pub pages: BTreeMap<String, String>,
+    /// Path of the page answering rejected credentials
+    pub login: String,
+    /// Message filling `{{error}}` once credentials are rejected
+    pub login_error: String,
+    /// Body of the `404 Not Found` responses
+    pub not_found: String,
+}
+
+impl Site {
+    /// Renders the template of `config` for a machine named `hostname`, with the
+    /// pages of its `pages_dir`. A `pages_dir` that cannot be read is logged and
+    /// left out.
+    pub fn from_config(config: &HttpSiteConfig, hostname: &str) -> Self {
+        let template = template(config.template);
+        let title = escape(config.title.as_deref().unwrap_or(template.title));
+        let hostname = escape(hostname);
+        let render = |page: &str| {
+            page.replace("{{title}}", &title)
+                .replace("{{hostname}}", &hostname)
+        };
+
+        let mut pages: BTreeMap<String, String> = template
+            .pages
+            .iter()
+            .map(|(path, page)| (path.to_string(), render(page)))
+            .collect();
+        if let Some(dir) = &config.pages_dir {
+            match read_pages(dir) {
+                Ok(custom) => pages.extend(custom.into_iter().map(|(path, page)| {
+                    let page = render(&page);
+                    (path, page)
+                })),
+                Err(e) => warn!("Cannot read the pages of {}: {}", dir.display(), e),
+            }
+        }
+
+        let server = config
+            .server_header
+            .clone()
+            .unwrap_or_else(|| template.server.to_string());
+        Self {
+            not_found: NOT_FOUND.replace("{{server}}", &escape(&server)),
+            server,
+            pages,
+            login: template.login.to_string(),
+            login_error: template.error.to_string(),
+        }
+    }
+}
+
+/// Files under `dir` by the path they serve, `index.html` serving its directory
+fn read_pages(dir: &Path) -> io::Result<BTreeMap<String, String>> {
+    let mut pages = BTreeMap::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in std::fs::read_dir(&current)? {
+            let entry = entry?;
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            if !file_type.is_file() || entry.metadata()?.len() > MAX_PAGE_LEN {
+                continue;
+            }
+            if pages.len() == MAX_PAGES {
+                warn!(
+                    "Only the first {} pages of {} are served",
+                    MAX_PAGES,
+                    dir.display()
+                );
+                return Ok(pages);
+            }
+            let Ok(relative) = path.strip_prefix(dir) else {
+                continue;
+            };
+            let mut served = format!("/{}", relative.to_string_lossy().replace('\\', "/"));
+            if served.ends_with("/index.html") {
+                served.truncate(served.len() - "index.html".len());
+            }
+            let content = std::fs::read(&path)?;
+            pages.insert(served, String::from_utf8_lossy(&content).into_owned());
+        }
+    }
+    Ok(pages)
+}
+
+/// `text` with the characters HTML gives a meaning to escaped
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_are_rendered_with_the_service_names() {
+        let config = HttpSiteConfig {
+            template: SiteTemplate::Router,
+            title: Some("Archer <C7>".to_string()),
+            ..HttpSiteConfig::default()
+        };
+        let site = Site::from_config(&config, "gw-01");
+        assert_eq!(site.server, "mini_httpd/1.30 26Oct2018");
+        assert_eq!(site.login, "/login.cgi");
+        let login = &site.pages["/"];
+        assert!(login.contains("<title>Archer &lt;C7&gt;</title>"));
+        assert!(login.contains("gw-01"));
+        assert!(login.contains("{{error}}"));
+        assert!(site.pages["/upgrade.html"].contains("multipart/form-data"));
+        assert!(site
+            .not_found
+            .contains("<hr><center>mini_httpd/1.30 26Oct2018</center>"));
+
+        for kind in [
+            SiteTemplate::Default,
+            SiteTemplate::LoginPortal,
+            SiteTemplate::PhpMyAdmin,
+        ] {
+            let site = Site::from_config(
+                &HttpSiteConfig {
+                    template: kind,
+                    ..HttpSiteConfig::default()
+                },
+                "web-01",
+            );
+            assert!(site.pages[&site.login].contains("{{error}}"));
+            assert!(site.pages.values().all(|page| !page.contains("{{title}}")));
+        }
+    }
+
+    #[test]
+    fn pages_dir_adds_and_replaces_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.html"), "<h1>{{hostname}}</h1>").unwrap();
+        std::fs::create_dir(dir.path().join("wp-admin")).unwrap();
+        std::fs::write(dir.path().join("wp-admin/index.html"), "dashboard").unwrap();
+        std::fs::write(dir.path().join("robots.txt"), "User-agent: *").unwrap();
+
+        let config = HttpSiteConfig {
+            pages_dir: Some(dir.path().to_path_buf()),
+            server_header: Some("Apache".to_string()),
+            ..HttpSiteConfig::default()
+        };
+        let site = Site::from_config(&config, "blog");
+        assert_eq!(site.server, "Apache");
+        assert_eq!(site.pages["/"], "<h1>blog</h1>");
+        assert_eq!(site.pages["/wp-admin/"], "dashboard");
+        assert_eq!(site.pages["/robots.txt"], "User-agent: *");
+        assert!(site.pages.contains_key("/admin/"));
+
+        let missing = HttpSiteConfig {
+            pages_dir: Some(dir.path().join("missing")),
+            ..HttpSiteConfig::default()
+        };
+        assert_eq!(
+            Site::from_config(&missing, "blog").pages.len(),
+            DEFAULT.pages.len()
+        );
+    }
+}
@@ -1 +1,7 @@
+pub mod control_api;
 pub mod controller_handler;
+pub mod operations;
+pub mod replay;
+pub mod scheduler;
+pub mod service_api;
+pub mod systemd;
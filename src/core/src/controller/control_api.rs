@@ -0,0 +1,419 @@
+//! gRPC control plane of a sensor, the `Control` service of `proto/control.proto`.
+//!
+//! Served with tonic when `control.listen` is set. Sessions are read from the
+//! storage, running ones are ended or finalized through [`SessionControl`], and
+//! reloads are requested to the controller through [`ServiceControl`], like the
+//! web interface does. Calls without one of the `control.tokens` as a bearer
+//! token are refused.
+
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use tokio::net::TcpListener;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+use super::service_api::ServiceControl;
+use crate::error_handling::types::SessionError;
+use crate::session::Session;
+use crate::session_control::SessionControl;
+use crate::storage::storage_trait::Storage;
+use crate::storage::types::SessionFilter;
+use crate::web_interface::auth::constant_time_eq;
+use crate::SessionStatus;
+
+use proto::control_server::{Control, ControlServer};
+
+/// Messages and stubs generated from `proto/control.proto`
+pub mod proto {
+    tonic::include_proto!("miel.control.v1");
+}
+
+/// Implementation of the `Control` service
+pub struct ControlApi {
+    storage: Arc<dyn Storage + Send + Sync>,
+    sessions: SessionControl,
+    services: ServiceControl,
+}
+
+impl ControlApi {
+    pub fn new(
+        storage: Arc<dyn Storage + Send + Sync>,
+        sessions: SessionControl,
+        services: ServiceControl,
+    ) -> Self {
+        Self {
+            storage,
+            sessions,
+            services,
+        }
+    }
+
+    /// Serves the API on `listener`, refusing the calls without one of `tokens`
+    pub async fn serve(
+        self,
+        listener: TcpListener,
+        tokens: Vec<String>,
+    ) -> Result<(), tonic::transport::Error> {
+        let service = ControlServer::with_interceptor(self, move |request: Request<()>| {
+            authorize(&tokens, request)
+        });
+        Server::builder()
+            .add_service(service)
+            .serve_with_incoming(TcpIncoming::from(listener))
+            .await
+    }
+}
+
+#[tonic::async_trait]
+impl Control for ControlApi {
+    async fn list_sessions(
+        &self,
+        request: Request<proto::ListSessionsRequest>,
+    ) -> Result<Response<proto::ListSessionsResponse>, Status> {
+        let filter = session_filter(request.into_inner())?;
+        let sessions = self
+            .storage
+            .get_sessions(Some(filter))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(proto::ListSessionsResponse {
+            sessions: sessions.into_iter().map(proto::Session::from).collect(),
+        }))
+    }
+
+    async fn end_session(
+        &self,
+        request: Request<proto::SessionRequest>,
+    ) -> Result<Response<proto::EndSessionResponse>, Status> {
+        let session_id = session_id(&request.into_inner())?;
+        self.sessions
+            .end_session(&session_id)
+            .await
+            .map_err(session_error)?;
+        Ok(Response::new(proto::EndSessionResponse {}))
+    }
+
+    async fn finalize_capture(
+        &self,
+        request: Request<proto::SessionRequest>,
+    ) -> Result<Response<proto::FinalizeCaptureResponse>, Status> {
+        let session_id = session_id(&request.into_inner())?;
+        self.sessions
+            .finalize_session_capture(&session_id)
+            .await
+            .map_err(session_error)?;
+        Ok(Response::new(proto::FinalizeCaptureResponse {}))
+    }
+
+    async fn get_container_stats(
+        &self,
+        _request: Request<proto::GetContainerStatsRequest>,
+    ) -> Result<Response<proto::ContainerStats>, Status> {
+        let stats = self
+            .sessions
+            .container_report()
+            .await
+            .map_err(session_error)?
+            .stats;
+        Ok(Response::new(proto::ContainerStats {
+            active_count: stats.active_count as u64,
+            total_created: stats.total_created,
+            failed_count: stats.failed_count,
+        }))
+    }
+
+    async fn reload_config(
+        &self,
+        _request: Request<proto::ReloadConfigRequest>,
+    ) -> Result<Response<proto::ReloadConfigResponse>, Status> {
+        let services = self
+            .services
+            .reload()
+            .await
+            .map_err(|e| Status::failed_precondition(e.to_string()))?;
+        Ok(Response::new(proto::ReloadConfigResponse {
+            services: services as u32,
+        }))
+    }
+}
+
+/// Lets `request` through when it carries one of `tokens` as a bearer token
+fn authorize(tokens: &[String], request: Request<()>) -> Result<Request<()>, Status> {
+    let header = request
+        .metadata()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok());
+    let authorized =
+        header
+            .and_then(|h| h.trim().split_once(' '))
+            .is_some_and(|(scheme, token)| {
+                scheme.eq_ignore_ascii_case("bearer")
+                    && tokens.iter().any(|known| {
+                        !known.is_empty()
+                            && constant_time_eq(known.as_bytes(), token.trim().as_bytes())
+                    })
+            });
+    if authorized {
+        Ok(request)
+    } else {
+        Err(Status::unauthenticated("A control token is required"))
+    }
+}
+
+fn session_id(request: &proto::SessionRequest) -> Result<Uuid, Status> {
+    Uuid::parse_str(&request.session_id)
+        .map_err(|e| Status::invalid_argument(format!("session_id: {}", e)))
+}
+
+fn session_error(error: SessionError) -> Status {
+    match error {
+        SessionError::NotFound => Status::not_found("No running session with this id"),
+        e => Status::internal(e.to_string()),
+    }
+}
+
+fn session_filter(request: proto::ListSessionsRequest) -> Result<SessionFilter, Status> {
+    let date = |field: &str, value: Option<String>| {
+        value
+            .map(|value| {
+                DateTime::parse_from_rfc3339(&value)
+                    .map(|date| date.with_timezone(&Utc))
+                    .map_err(|e| Status::invalid_argument(format!("{}: {}", field, e)))
+            })
+            .transpose()
+    };
+    let client_addr = request
+        .client_addr
+        .map(|addr| addr.parse::<IpAddr>())
+        .transpose()
+        .map_err(|e| Status::invalid_argument(format!("client_addr: {}", e)))?;
+    let status = match proto::SessionStatus::try_from(request.status) {
+        Ok(proto::SessionStatus::Unspecified) => None,
+        Ok(status) => Some(SessionStatus::from(status)),
+        Err(e) => return Err(Status::invalid_argument(format!("status: {}", e))),
+    };
+    Ok(SessionFilter {
+        service_name: request.service_name,
+        start_date: date("start_date", request.start_date)?,
+        end_date: date("end_date", request.end_date)?,
+        client_addr,
+        status,
+        offset: request.offset,
+        limit: request.limit,
+        ..Default::default()
+    })
+}
+
+impl From<proto::SessionStatus> for SessionStatus {
+    fn from(status: proto::SessionStatus) -> Self {
+        match status {
+            proto::SessionStatus::Unspecified | proto::SessionStatus::Pending => Self::Pending,
+            proto::SessionStatus::Active => Self::Active,
+            proto::SessionStatus::Completed => Self::Completed,
+            proto::SessionStatus::Error => Self::Error,
+            proto::SessionStatus::Interrupted => Self::Interrupted,
+        }
+    }
+}
+
+impl From<SessionStatus> for proto::SessionStatus {
+    fn from(status: SessionStatus) -> Self {
+        match status {
+            SessionStatus::Pending => Self::Pending,
+            SessionStatus::Active => Self::Active,
+            SessionStatus::Completed => Self::Completed,
+            SessionStatus::Error => Self::Error,
+            SessionStatus::Interrupted => Self::Interrupted,
+        }
+    }
+}
+
+impl From<Session> for proto::Session {
+    fn from(session: Session) -> Self {
+        Self {
+            id: session.id.to_string(),
+            service_name: session.service_name,
+            client_addr: session.client_addr.to_string(),
+            start_time: session.start_time.to_rfc3339(),
+            end_time: session.end_time.map(|time| time.to_rfc3339()),
+            container_id: session.container_id,
+            bytes_transferred: session.bytes_transferred,
+            status: proto::SessionStatus::from(session.status).into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container_management::container_manager::ContainerManager;
+    use crate::controller::service_api::ServiceCommand;
+    use crate::session_manager::SessionManager;
+    use crate::storage::memory_storage::MemoryStorage;
+    use proto::control_client::ControlClient;
+    use tokio::sync::{mpsc, Mutex};
+    use tonic::transport::Channel;
+
+    const TOKEN: &str = "s3cr3t";
+
+    fn session(service: &str, status: SessionStatus) -> Session {
+        Session {
+            id: Uuid::new_v4(),
+            service_name: service.to_string(),
+            client_addr: "203.0.113.7:40000".parse().unwrap(),
+            start_time: Utc::now(),
+            end_time: None,
+            container_id: None,
+            bytes_transferred: 42,
+            status,
+            enrichment: None,
+            original_dst: None,
+        }
+    }
+
+    /// Client of an API serving `storage`, and the commands it sends the controller
+    async fn serve(
+        storage: Arc<dyn Storage + Send + Sync>,
+    ) -> (ControlClient<Channel>, mpsc::Receiver<ServiceCommand>) {
+        let manager = SessionManager::new(
+            Arc::new(Mutex::new(ContainerManager::new_mock())),
+            storage.clone(),
+            4,
+        );
+        let (services, service_rx) = ServiceControl::channel();
+        let api = ControlApi::new(storage, SessionControl::spawn(manager), services);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(api.serve(listener, vec![TOKEN.to_string()]));
+        let client = ControlClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+        (client, service_rx)
+    }
+
+    fn authorized<T>(message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        request.metadata_mut().insert(
+            "authorization",
+            format!("Bearer {}", TOKEN).parse().unwrap(),
+        );
+        request
+    }
+
+    #[tokio::test]
+    async fn calls_without_a_token_are_refused() {
+        let (mut client, _service_rx) = serve(Arc::new(MemoryStorage::new())).await;
+
+        let missing = client
+            .get_container_stats(proto::GetContainerStatsRequest {})
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::Unauthenticated);
+
+        let mut wrong = Request::new(proto::GetContainerStatsRequest {});
+        wrong
+            .metadata_mut()
+            .insert("authorization", "Bearer s3cr3".parse().unwrap());
+        let wrong = client.get_container_stats(wrong).await.unwrap_err();
+        assert_eq!(wrong.code(), tonic::Code::Unauthenticated);
+
+        let stats = client
+            .get_container_stats(authorized(proto::GetContainerStatsRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(stats.active_count, 0);
+    }
+
+    #[tokio::test]
+    async fn sessions_are_listed_with_the_filter() {
+        let storage = Arc::new(MemoryStorage::new());
+        let ssh = session("ssh", SessionStatus::Completed);
+        storage.save_session(&ssh).await.unwrap();
+        storage
+            .save_session(&session("http", SessionStatus::Error))
+            .await
+            .unwrap();
+        let (mut client, _service_rx) = serve(storage).await;
+
+        let listed = client
+            .list_sessions(authorized(proto::ListSessionsRequest {
+                status: proto::SessionStatus::Completed.into(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .sessions;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, ssh.id.to_string());
+        assert_eq!(listed[0].service_name, "ssh");
+        assert_eq!(listed[0].client_addr, "203.0.113.7:40000");
+        assert_eq!(listed[0].bytes_transferred, 42);
+        assert_eq!(listed[0].status(), proto::SessionStatus::Completed);
+
+        let all = client
+            .list_sessions(authorized(proto::ListSessionsRequest::default()))
+            .await
+            .unwrap()
+            .into_inner()
+            .sessions;
+        assert_eq!(all.len(), 2);
+
+        let invalid = client
+            .list_sessions(authorized(proto::ListSessionsRequest {
+                start_date: Some("yesterday".to_string()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(invalid.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn unknown_sessions_are_not_found() {
+        let (mut client, _service_rx) = serve(Arc::new(MemoryStorage::new())).await;
+        let request = || proto::SessionRequest {
+            session_id: Uuid::new_v4().to_string(),
+        };
+
+        let ended = client.end_session(authorized(request())).await.unwrap_err();
+        assert_eq!(ended.code(), tonic::Code::NotFound);
+        let finalized = client
+            .finalize_capture(authorized(request()))
+            .await
+            .unwrap_err();
+        assert_eq!(finalized.code(), tonic::Code::NotFound);
+
+        let invalid = client
+            .end_session(authorized(proto::SessionRequest {
+                session_id: "not-a-uuid".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(invalid.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn reloads_are_requested_to_the_controller() {
+        let (mut client, mut service_rx) = serve(Arc::new(MemoryStorage::new())).await;
+        tokio::spawn(async move {
+            while let Some(command) = service_rx.recv().await {
+                if let ServiceCommand::Reload { reply } = command {
+                    let _ = reply.send(Ok(3));
+                }
+            }
+        });
+
+        let reloaded = client
+            .reload_config(authorized(proto::ReloadConfigRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reloaded.services, 3);
+    }
+}
@@ -1,160 +1,686 @@
+use crate::active_session::ActiveSessionSummary;
+use crate::analytics::campaigns::{self, CampaignBoard};
+use crate::analytics::reports;
 use crate::configuration::config::Config;
 use crate::configuration::{ServiceConfig, StorageBackend};
-use crate::container_management::ContainerManager;
-use crate::error_handling::types::{ControllerError, SessionError};
-use crate::network::{network_listener::NetworkListener, types::SessionRequest};
+use crate::container_management::{ContainerManager, ContainerReport};
+use crate::controller::control_api::ControlApi;
+use crate::controller::scheduler::{MaintenanceTask, Scheduler};
+use crate::controller::service_api::{ServiceCommand, ServiceControl};
+use crate::controller::systemd::{self, Watchdog};
+use crate::data_capture::spool;
+use crate::data_capture::yara::RuleSet;
+use crate::enrichment::Enricher;
+use crate::error_handling::types::{
+    ConfigError, Context, ControllerError, SessionError, StorageError,
+};
+use crate::events::{self, Event};
+use crate::honeytokens;
+use crate::http_client::{self, HttpEndpoint};
+use crate::journal::{self, SessionJournal};
+use crate::network::{
+    connection_filter::ConnectionFilter,
+    network_listener::NetworkListener,
+    redirect,
+    scan_detector::PortScan,
+    types::{SessionRequest, UdpSessionRequest},
+};
+use crate::notifier;
+use crate::session::Session;
+use crate::session_control::SessionControl;
 use crate::session_manager::SessionManager;
+use crate::storage::buffered_storage::{self, BufferedStorage};
 use crate::storage::database_storage::DatabaseStorage;
+use crate::storage::deduplicating_storage::{DedupStore, DeduplicatingStorage};
+use crate::storage::encryption::EncryptionKey;
 use crate::storage::file_storage::FileStorage;
+use crate::storage::forwarding_storage::ForwardingStorage;
+use crate::storage::integrity::{IntegrityLedger, IntegrityStorage};
+use crate::storage::memory_storage::MemoryStorage;
+use crate::storage::metered_storage::MeteredStorage;
+use crate::storage::object_store::ObjectStore;
+use crate::storage::offloading_storage::OffloadingStorage;
+use crate::storage::retention;
+use crate::storage::stix;
 use crate::storage::storage_trait::Storage;
+use crate::storage::types::{SessionAnnotations, TagSource};
+use crate::tagging::Tagger;
 use crate::web_interface::WebServer;
-use log::{error, info};
-use std::net::Ipv4Addr;
+use crate::SessionStatus;
+use chrono::Utc;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
-use tokio::sync::mpsc;
-use tokio::task::JoinHandle;
+use std::time::Duration;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tracing::{error, info, info_span, warn, Instrument};
+use uuid::Uuid;
+
+/// Service name of the pseudo-sessions recording port scans
+const PORT_SCAN_SERVICE: &str = "portscan";
 
 pub struct Controller {
     // Fields for the Controller struct
     config: Config,
     listener: Option<NetworkListener>,
     session_rx: Option<mpsc::Receiver<SessionRequest>>,
+    udp_session_rx: Option<mpsc::Receiver<UdpSessionRequest>>,
     storage: Arc<dyn Storage + Send + Sync>,
     container_manager: Arc<tokio::sync::Mutex<ContainerManager>>,
-    session_manager: SessionManager,
-    listener_handle: Option<JoinHandle<()>>,
+    /// Handle of the session registry
+    session_control: SessionControl,
+    /// Bounds the session requests set up at once, see `Config::session_workers`
+    session_permits: Arc<Semaphore>,
+    /// Tasks serving the session requests
+    session_tasks: JoinSet<()>,
+    /// File the configuration is reloaded from on SIGHUP
+    config_path: Option<PathBuf>,
+    /// Profile applied when reloading from `config_path`
+    profile: Option<String>,
+    service_control: ServiceControl,
+    service_rx: mpsc::Receiver<ServiceCommand>,
+    /// When the drain in progress gives up on the connections, see [`Controller::drain`]
+    drain_deadline: Option<Instant>,
+    /// Campaigns of the last clustering, served by the web interface
+    campaigns: Arc<CampaignBoard>,
 }
 
 impl Controller {
     pub async fn new(config: Config) -> Result<Self, ControllerError> {
-        let container_manager = Arc::new(tokio::sync::Mutex::new(ContainerManager::new().unwrap()));
-
-        // Create storage backend based on configuration
-        let storage: Arc<dyn Storage + Send + Sync> = match config.storage_backend {
-            StorageBackend::Database => {
-                info!("Initializing Database storage backend");
-                Arc::new(
-                    DatabaseStorage::from_config_path(&config.storage_path)
-                        .await
-                        .map_err(ControllerError::StorageError)?,
-                )
-            }
-            StorageBackend::FileSystem => {
-                info!("Initializing FileSystem storage backend");
-                Arc::new(
-                    FileStorage::from_config_path(&config.storage_path)
-                        .map_err(ControllerError::StorageError)?,
-                )
+        http_client::set_timeout(Duration::from_secs(config.outbound_timeout_secs));
+        let (journal, orphans) =
+            SessionJournal::open(&config.storage_path.join(journal::JOURNAL_FILE))
+                .context("Cannot open the session journal")?;
+        let journal = Arc::new(journal);
+        let container_manager = Arc::new(tokio::sync::Mutex::new(
+            ContainerManager::with_runtime(config.container_runtime.clone())
+                .map_err(ControllerError::ContainerError)?
+                .with_image_dir(&config.image_dir)
+                .with_paths(config.container_paths())
+                .with_journal(journal.clone()),
+        ));
+
+        let storage = Self::open_storage(&config).await?;
+        let storage: Arc<dyn Storage + Send + Sync> = match &config.forwarding.collector_url {
+            Some(url) => {
+                info!("Forwarding sessions to the collector at {}", url);
+                let collector = HttpEndpoint::parse(url).context("Invalid collector url")?;
+                Arc::new(ForwardingStorage::new(
+                    storage,
+                    collector,
+                    &config.forwarding.token,
+                    config.forwarding.queue_size,
+                ))
             }
+            None => storage,
         };
+        let storage = Arc::new(BufferedStorage::new(Arc::new(MeteredStorage::new(storage))));
+        storage.spawn_flusher(buffered_storage::DEFAULT_FLUSH_INTERVAL);
+        let storage: Arc<dyn Storage + Send + Sync> = storage;
 
-        if config.web_ui_enabled {
-            let ws = WebServer::new(storage.clone());
-            tokio::spawn(async move {
-                let _ = ws.start(config.web_ui_port).await;
-            });
-        }
+        events::install(&config.events)
+            .await
+            .context("Cannot open event sink")?;
+        notifier::install(&config.notifications).context("Cannot set up notifications")?;
+        honeytokens::install(storage.clone())
+            .await
+            .context("Cannot restore honeytokens")?;
 
-        let session_manager = SessionManager::new(
+        let mut session_manager = SessionManager::new(
             container_manager.clone(),
             storage.clone(),
             config.max_sessions,
         );
+        session_manager.set_capture_spool(&config.capture_spool, &config.storage_path);
+        // Before any container starts, and the streams left by a crash are cleared
+        journal::recover(
+            &journal,
+            orphans,
+            &storage,
+            &container_manager,
+            session_manager.capture_spool(),
+        )
+        .await;
+        container_manager.lock().await.reclaim_leftovers().await;
+        spool::clear(session_manager.capture_spool());
+        session_manager.set_journal(journal);
+        session_manager.set_warm_containers(&config.services, config.warm_containers);
+        session_manager.set_session_reuse(config.session_reuse_minutes);
+        session_manager.set_session_timeout(config.session_timeout_secs);
+        session_manager.set_idle_timeout(config.idle_timeout_secs);
+        session_manager.set_container_restarts(config.maintenance.max_container_restarts);
+        let enricher = Arc::new(
+            Enricher::from_config(&config.enrichment)
+                .context("Cannot load enrichment providers")?,
+        );
+        session_manager.set_enrichment(enricher.clone());
+        let rules = RuleSet::load(&config.yara.rule_files).context("Cannot load YARA rules")?;
+        if !rules.is_empty() {
+            info!("Scanning captures with {} YARA rule(s)", rules.len());
+        }
+        session_manager.set_rules(rules);
+        session_manager
+            .set_tagging(Tagger::new(&config.tagging).context("Cannot load tagging rules")?);
+        session_manager
+            .set_ssh_servers(&config.services)
+            .context("Cannot start SSH servers")?;
+
+        let (service_control, service_rx) = ServiceControl::channel();
+        let lifecycle = session_manager.lifecycle();
+        stix::spawn_taxii_push(&config.stix, storage.clone(), &lifecycle)
+            .context("Invalid TAXII url")?;
+        let session_control = SessionControl::spawn(session_manager);
+        let campaigns = Arc::new(CampaignBoard::new());
+
+        if let Some(listen) = config.control.listen {
+            let listener = tokio::net::TcpListener::bind(listen)
+                .await
+                .context("Cannot bind the control API")?;
+            info!("Control API listening on {}", listen);
+            let api = ControlApi::new(
+                storage.clone(),
+                session_control.clone(),
+                service_control.clone(),
+            );
+            let tokens = config.control.tokens.clone();
+            tokio::spawn(async move {
+                if let Err(e) = api.serve(listener, tokens).await {
+                    error!("Control API stopped: {}", e);
+                }
+            });
+        }
+
+        if config.web_ui_enabled {
+            let ws = WebServer::new(storage.clone())
+                .with_config(config.web_ui.clone())
+                .with_lifecycle(lifecycle)
+                .with_stix_identity(config.stix.identity.clone())
+                .with_dedup(
+                    DedupStore::from_config(&config.dedup, &config.storage_path).map(Arc::new),
+                )
+                .with_campaigns(campaigns.clone())
+                .with_enricher(enricher)
+                .with_services(service_control.clone())
+                .with_sessions(session_control.clone())
+                .with_integrity(
+                    IntegrityLedger::from_config(&config.integrity, &config.storage_path)
+                        .map(Arc::new),
+                );
+            tokio::spawn(async move {
+                let _ = ws
+                    .start(SocketAddr::new(config.web_ui_address, config.web_ui_port))
+                    .await;
+            });
+        }
 
         Ok(Self {
+            session_permits: Arc::new(Semaphore::new(config.session_workers)),
             config,
             listener: None,
             session_rx: None,
-            listener_handle: None,
+            udp_session_rx: None,
+            config_path: None,
+            profile: None,
             container_manager,
-            session_manager,
+            session_control,
+            session_tasks: JoinSet::new(),
             storage,
+            service_control,
+            service_rx,
+            drain_deadline: None,
+            campaigns,
         })
     }
 
+    /// Handle changing the services while the controller runs
+    pub fn service_control(&self) -> ServiceControl {
+        self.service_control.clone()
+    }
+
+    /// Opens the storage backend of `config`, offloading the large artifacts when
+    /// configured. Forwarding and buffering are left to the running honeypot.
+    ///
+    /// # Errors
+    /// Returns [`ControllerError::StorageError`] when the backend cannot be opened
+    /// and [`ControllerError::InitializationFailed`] for an invalid object store.
+    pub async fn open_storage(
+        config: &Config,
+    ) -> Result<Arc<dyn Storage + Send + Sync>, ControllerError> {
+        // Create storage backend based on configuration
+        let storage: Arc<dyn Storage + Send + Sync> = match config.storage_backend {
+            StorageBackend::Database => {
+                info!("Initializing Database storage backend");
+                Arc::new(
+                    DatabaseStorage::from_config_path(&config.storage_path)
+                        .await
+                        .map_err(ControllerError::StorageError)?
+                        .with_compression(config.artifact_compression),
+                )
+            }
+            StorageBackend::Postgres => {
+                info!("Initializing PostgreSQL storage backend");
+                Arc::new(
+                    DatabaseStorage::connect(&config.database.url, config.database.max_connections)
+                        .await
+                        .map_err(ControllerError::StorageError)?
+                        .with_compression(config.artifact_compression),
+                )
+            }
+            StorageBackend::FileSystem => {
+                info!("Initializing FileSystem storage backend");
+                let mut storage = FileStorage::from_config_path(&config.storage_path)
+                    .map_err(ControllerError::StorageError)?
+                    .with_compression(config.artifact_compression);
+                let key = EncryptionKey::from_config(&config.file_encryption)
+                    .context("Invalid file encryption key")?;
+                if let Some(key) = key {
+                    info!("Encrypting the stored files with AES-256-GCM");
+                    storage = storage.with_encryption(key);
+                }
+                Arc::new(storage)
+            }
+            StorageBackend::Memory => {
+                info!("Initializing in-memory storage backend, nothing will be kept on exit");
+                Arc::new(MemoryStorage::new())
+            }
+        };
+        // Under the offloading, which moves the largest contents to the bucket first
+        let storage: Arc<dyn Storage + Send + Sync> =
+            match DedupStore::from_config(&config.dedup, &config.storage_path) {
+                Some(mut store) => {
+                    info!(
+                        "Sharing the identical artifacts of at least {} bytes between sessions",
+                        config.dedup.min_size_bytes
+                    );
+                    let key = EncryptionKey::from_config(&config.file_encryption)
+                        .context("Invalid file encryption key")?;
+                    if let Some(key) = key {
+                        store = store.with_encryption(key);
+                    }
+                    Arc::new(DeduplicatingStorage::new(storage, Arc::new(store)))
+                }
+                None => storage,
+            };
+        let storage: Arc<dyn Storage + Send + Sync> = if config.offload.is_enabled() {
+            let store = ObjectStore::new(&config.offload).context("Invalid object store")?;
+            info!(
+                "Offloading artifacts of at least {} bytes to bucket {}",
+                config.offload.min_size_bytes, config.offload.bucket
+            );
+            Arc::new(OffloadingStorage::new(
+                storage,
+                store,
+                &config.offload.prefix,
+                config.offload.min_size_bytes,
+            ))
+        } else {
+            storage
+        };
+        // Outermost, so that the manifests hold the artifacts before they are offloaded
+        let storage: Arc<dyn Storage + Send + Sync> =
+            match IntegrityLedger::from_config(&config.integrity, &config.storage_path) {
+                Some(ledger) => {
+                    info!("Sealing the stored artifacts with checksum manifests");
+                    Arc::new(IntegrityStorage::new(storage, Arc::new(ledger)))
+                }
+                None => storage,
+            };
+        Ok(storage)
+    }
+
+    /// Enables configuration reloads from `path` when the process receives SIGHUP
+    pub fn with_config_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.config_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Applies the configuration profile named `profile` on reloads, the one the
+    /// configuration was first loaded with
+    pub fn with_profile(mut self, profile: Option<String>) -> Self {
+        self.profile = profile;
+        self
+    }
+
     pub async fn run(
         &mut self,
         mut shutdown_rx: tokio::sync::broadcast::Receiver<()>,
     ) -> Result<(), ControllerError> {
-        let (tx, rx) = mpsc::channel(100);
+        let ip_addr = IpAddr::from_str(self.config.bind_address.as_str()).map_err(|e| {
+            ConfigError::BadIPFormatting(format!("{}: {}", self.config.bind_address, e))
+        })?;
+
+        let (tx, rx) = mpsc::channel(self.config.session_queue_size);
         self.session_rx = Some(rx);
 
-        self.listener = Some(NetworkListener::new(tx));
+        let (udp_tx, udp_rx) = mpsc::channel(self.config.session_queue_size);
+        self.udp_session_rx = Some(udp_rx);
 
-        info!("Binding services in service detector...");
+        let connection_filter = Self::connection_filter(&self.config);
 
-        let _ = self.listener.as_mut().unwrap().bind_services(self.config.services.as_slice()).map_err(|e| {
-            error!("Calling bind_services() from Controller not working, returned with error: {:?}", e);
-            e
-        });
+        let listener = self.listener.insert(
+            NetworkListener::new(tx)
+                .with_connection_filter(connection_filter)
+                .with_ipv6_only(self.config.ipv6_only)
+                .with_redirect_mode(self.config.redirect.mode)
+                .with_udp_sessions(udp_tx),
+        );
 
-        info!("Services bound correctly in service detector");
+        info!("Binding services in service detector...");
 
-        let ip_addr = Ipv4Addr::from_str(self.config.bind_address.as_str())
-            .map_err(|e| e.to_string())
-            .unwrap();
+        let enabled = Self::enabled_services(&self.config);
+        if let Err(e) = listener.bind_services(enabled.as_slice()) {
+            error!("Services could not be bound in service detector: {}", e);
+        } else {
+            info!("Services bound correctly in service detector");
+        }
 
-        let copy = self.listener.as_mut().unwrap().extract_for_listening();
+        if let Err(e) = listener.listen(ip_addr).await {
+            error!("NetworkListener failure: {}", e);
+        }
+        self.apply_redirect().await;
+
+        let mut reload_signal = ControlSignal::hangup(self.config_path.is_some());
+        let mut drain_signal = ControlSignal::drain();
+        let mut scheduler = Scheduler::start(
+            &self.config.maintenance,
+            &self.config.retention,
+            &self.config.campaigns,
+            &self.config.reports,
+        );
+        let mut watchdog = Watchdog::from_env();
+        systemd::ready(&format!("Serving {} services", enabled.len()));
 
-        let handle = tokio::spawn(async move {
-            if let Err(e) = NetworkListener::start_listening(copy, ip_addr).await {
-                error!("NetworkListener failure: {:?}", e);
+        loop {
+            if self.drain_deadline.is_some() && self.session_tasks.is_empty() {
+                info!("Every connection closed, stopping drained controller");
+                break;
             }
-        });
-
-        self.listener_handle = Some(handle);
 
-        loop {
             tokio::select! {
-                session_request = self.session_rx.as_mut().unwrap().recv() => {
-                    match session_request {
-                        Some(request) => {
-                            if let Err(e) = self.handle_session_request(request).await {
-                                error!("Session handling failed: {:?}", e);
+                    session_request = Self::next_request(
+                        self.session_permits.clone(),
+                        self.session_rx.as_mut(),
+                    ) => {
+                        match session_request {
+                            Some((permit, request)) => self.spawn_session_request(request, permit),
+                            None => {
+                                info!("Session channel closed, stopping controller");
+                                break;
                             }
                         }
-                        None => {
-                            info!("Session channel closed, stopping controller");
-                            break;
+                    }
+
+                    Some((permit, request)) = Self::next_request(
+                        self.session_permits.clone(),
+                        self.udp_session_rx.as_mut(),
+                    ) => {
+                        self.spawn_udp_session_request(request, permit);
+                    }
+
+                    Some(served) = self.session_tasks.join_next() => {
+                        if let Err(e) = served {
+                            error!("Session task failed: {:?}", e);
                         }
                     }
-                }
 
-                _ = shutdown_rx.recv() => {
-                        info!("Shutdown signal received in controller, stopping gracefully");
+                    _ = reload_signal.recv(), if self.drain_deadline.is_none() => {
+                        info!("SIGHUP received, reloading configuration");
+                        systemd::reloading();
+                        if let Err(e) = self.reload_config().await {
+                            error!("Configuration reload failed: {}", e);
+                        }
+                        scheduler.shutdown().await;
+                        scheduler = Scheduler::start(
+                &self.config.maintenance,
+                &self.config.retention,
+                &self.config.campaigns,
+                &self.config.reports,
+            );
+                        let enabled = Self::enabled_services(&self.config).len();
+                        systemd::ready(&format!("Serving {} services", enabled));
+                    }
+
+                    _ = watchdog.tick() => {}
+
+                    _ = drain_signal.recv() => {
+                        info!("SIGUSR1 received, draining");
+                        self.drain().await;
+                    }
+
+                    _ = Self::drain_elapsed(self.drain_deadline) => {
+                        warn!(
+                            "Drain grace period elapsed, ending the {} remaining connection(s)",
+                            self.session_tasks.len()
+                        );
                         break;
                     }
-            }
+
+                    task = scheduler.next() => {
+                        self.run_maintenance(task).await;
+                    }
+
+                    Some(command) = self.service_rx.recv() => {
+                        if self.handle_service_command(command).await {
+                            scheduler.shutdown().await;
+                            scheduler = Scheduler::start(
+                                &self.config.maintenance,
+                                &self.config.retention,
+                                &self.config.campaigns,
+                                &self.config.reports,
+                            );
+                        }
+                    }
+
+                    _ = shutdown_rx.recv() => {
+                            info!("Shutdown signal received in controller, stopping gracefully");
+                            break;
+                        }
+                }
         }
 
         info!("Controller initiating graceful shutdown...");
+        systemd::stopping();
+        scheduler.shutdown().await;
         self.shutdown().await?;
         Ok(())
     }
 
+    /// Stops accepting connections ahead of a shutdown: every listener is closed, and
+    /// [`run`](Self::run) returns once the proxied connections closed or
+    /// `drain_grace_secs` elapsed, then ends the sessions and removes their
+    /// containers. Draining again keeps the first deadline.
+    pub async fn drain(&mut self) -> Duration {
+        let grace = Duration::from_secs(self.config.drain_grace_secs);
+        if let Some(deadline) = self.drain_deadline {
+            return deadline.saturating_duration_since(Instant::now());
+        }
+        info!(
+            "Draining: no connection is accepted anymore, {} still proxied are left {}s",
+            self.session_tasks.len(),
+            grace.as_secs()
+        );
+        systemd::stopping();
+        if let Some(listener) = &mut self.listener {
+            if let Err(e) = listener.shutdown().await {
+                error!("Failed to close the listeners for the drain: {:?}", e);
+            }
+        }
+        self.drain_deadline = Some(Instant::now() + grace);
+        grace
+    }
+
+    /// Resolves once the drain deadline passed, never without a drain
+    async fn drain_elapsed(deadline: Option<Instant>) {
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Runs a periodic job of the [`Scheduler`]
+    async fn run_maintenance(&mut self, task: MaintenanceTask) {
+        match task {
+            MaintenanceTask::SessionCheck => {
+                if let Err(e) = self.session_control.cleanup_expired_sessions().await {
+                    error!("Session cleanup failed: {}", e);
+                }
+                self.record_port_scans(false).await;
+            }
+            MaintenanceTask::Retention => self.apply_retention().await,
+            MaintenanceTask::HealthCheck => {
+                match self
+                    .session_control
+                    .check_containers(&self.config.services)
+                    .await
+                {
+                    Ok(0) => {}
+                    Ok(ended) => warn!("Ended {} sessions whose container stopped", ended),
+                    Err(e) => error!("Container health check failed: {}", e),
+                }
+                // Refreshes the containers the metrics report
+                if let Err(e) = self.session_control.container_report().await {
+                    error!("Cannot list the containers: {}", e);
+                }
+            }
+            MaintenanceTask::StatsLog => {
+                if let Err(e) = self.session_control.log_stats().await {
+                    error!("Cannot log the session stats: {}", e);
+                }
+            }
+            MaintenanceTask::Campaigns => self.cluster_campaigns().await,
+            MaintenanceTask::Reports => self.write_report().await,
+        }
+    }
+
+    /// Stores the port scans that ended as pseudo-sessions and reports them, every
+    /// scan when `all` is set
+    async fn record_port_scans(&self, all: bool) {
+        let Some(listener) = &self.listener else {
+            return;
+        };
+        let scans = if all {
+            listener.scan_detector().take_all()
+        } else {
+            listener.scan_detector().take_finished()
+        };
+        for scan in scans {
+            if let Err(e) = Self::store_port_scan(self.storage.as_ref(), &scan).await {
+                error!(
+                    "Cannot store the port scan from {}: {:?}",
+                    scan.client_addr, e
+                );
+            }
+        }
+    }
+
+    /// Saves `scan` as a `portscan` session, its ports listed in the notes
+    async fn store_port_scan(storage: &dyn Storage, scan: &PortScan) -> Result<(), StorageError> {
+        let session = Session {
+            id: Uuid::new_v4(),
+            service_name: PORT_SCAN_SERVICE.to_string(),
+            client_addr: SocketAddr::new(scan.client_addr, 0),
+            start_time: scan.start_time,
+            end_time: Some(scan.end_time),
+            container_id: None,
+            bytes_transferred: 0,
+            status: SessionStatus::Completed,
+            enrichment: None,
+            original_dst: None,
+        };
+        let ports: Vec<String> = scan.ports.iter().map(u16::to_string).collect();
+        let mut annotations = SessionAnnotations::new(session.id);
+        annotations.add_tag(PORT_SCAN_SERVICE, TagSource::Rule);
+        annotations.notes = Some(format!(
+            "{} connections to ports {}",
+            scan.connections,
+            ports.join(", ")
+        ));
+
+        storage.save_session(&session).await?;
+        storage.save_annotations(&annotations).await?;
+        events::emit(Event::PortScan {
+            session_id: session.id,
+            client_addr: scan.client_addr,
+            ports: scan.ports.clone(),
+            connections: scan.connections,
+            duration_secs: (scan.end_time - scan.start_time).num_seconds(),
+        });
+        Ok(())
+    }
+
+    /// Deletes the stored sessions past the retention limits and reports them
+    async fn apply_retention(&self) {
+        if !self.config.retention.is_enabled() {
+            return;
+        }
+        match retention::apply(self.storage.as_ref(), &self.config.retention, Utc::now()).await {
+            Ok(report) if report.deleted() > 0 => events::emit(Event::RetentionApplied {
+                expired: report.expired,
+                over_count: report.over_count.len(),
+                over_disk: report.over_disk.len(),
+                storage_bytes: report.storage_bytes,
+            }),
+            Ok(_) => {}
+            Err(e) => error!("Retention enforcement failed: {:?}", e),
+        }
+    }
+
+    /// Groups the stored sessions into campaigns, tags them and publishes them
+    async fn cluster_campaigns(&self) {
+        if !self.config.campaigns.enabled {
+            return;
+        }
+        let now = Utc::now();
+        match campaigns::refresh(self.storage.as_ref(), &self.config.campaigns, now).await {
+            Ok(found) => self.campaigns.publish(found, now),
+            Err(e) => error!("Campaign clustering failed: {:?}", e),
+        }
+    }
+
+    /// Writes and delivers the summary report of the last period, once
+    async fn write_report(&self) {
+        if !self.config.reports.enabled {
+            return;
+        }
+        if let Err(e) = reports::run(
+            self.storage.as_ref(),
+            &self.config.reports,
+            &self.config.storage_path,
+            Utc::now(),
+        )
+        .await
+        {
+            error!("Cannot write the summary report: {}", e);
+        }
+    }
+
     pub async fn shutdown(&mut self) -> Result<(), ControllerError> {
         info!("Starting Controller shutdown...");
 
+        // Connections being proxied hold their session's recorder, the sessions
+        // are ended with what was captured so far
+        self.session_tasks.shutdown().await;
+
         // First, shutdown all active sessions and save them to database
-        if let Err(e) = self.session_manager.shutdown_all_sessions().await {
+        if let Err(e) = self.session_control.shutdown().await {
             error!("Failed to shutdown sessions gracefully: {:?}", e);
         }
+        self.record_port_scans(true).await;
 
-        if let Some(listener) = &mut self.listener {
-            if let Err(e) = listener.shutdown().await {
-                error!("Failed to shutdown NetworkListener gracefully: {:?}", e);
-            }
+        if let Err(e) = self.storage.flush().await {
+            error!("Failed to flush buffered storage writes: {:?}", e);
         }
 
-        if let Some(handle) = self.listener_handle.take() {
-            info!("Aborting network listener task...");
-            handle.abort();
+        if self.config.services.iter().any(redirect::is_target) {
+            redirect::remove().await;
+        }
 
-            match handle.await {
-                Ok(()) => info!("Network listener task terminated cleanly"),
-                Err(e) if e.is_cancelled() => info!("Network listener task was cancelled"),
-                Err(e) => error!("Network listener task terminated with error: {:?}", e),
+        if let Some(listener) = &mut self.listener {
+            if let Err(e) = listener.shutdown().await {
+                error!("Failed to shutdown NetworkListener gracefully: {:?}", e);
             }
         }
 
@@ -163,32 +689,470 @@ impl Controller {
             info!("Session receiver channel closed");
         }
 
+        if let Some(udp_session_rx) = self.udp_session_rx.take() {
+            drop(udp_session_rx);
+            info!("UDP session receiver channel closed");
+        }
+
         self.listener = None;
 
         info!("Controller shutdown completed");
         Ok(())
     }
 
-    async fn handle_session_request(
+    /// Re-reads the configuration file and applies it without restarting.
+    ///
+    /// Listeners of removed or modified services are stopped, new ones are bound, and the
+    /// connection filter and session limit are updated. Active sessions are left running.
+    /// Bind address, redirect mode, storage, offloading, deduplication, forwarding, STIX push, web interface, control API, container runtime and directories, event sink,
+    /// notification and session queue settings are only read at startup. A configuration that fails to load or validate is rejected as a whole.
+    pub async fn reload_config(&mut self) -> Result<(), ControllerError> {
+        let Some(path) = self.config_path.clone() else {
+            return Err(ControllerError::InitializationFailed(
+                "No configuration file to reload from".to_string(),
+            ));
+        };
+
+        let config = Config::from_file_with_profile(&path, self.profile.as_deref())
+            .map_err(ControllerError::ConfigurationError)?;
+        let report = config.check();
+        for diagnostic in report.diagnostics() {
+            if diagnostic.is_error() {
+                error!("Reloaded configuration: {}", diagnostic);
+            } else {
+                warn!("Reloaded configuration: {}", diagnostic);
+            }
+        }
+        report
+            .into_result()
+            .map_err(ControllerError::ConfigurationError)?;
+        self.apply_config(config).await
+    }
+
+    async fn apply_config(&mut self, config: Config) -> Result<(), ControllerError> {
+        if config.bind_address != self.config.bind_address
+            || config.ipv6_only != self.config.ipv6_only
+            || config.redirect != self.config.redirect
+            || config.storage_backend != self.config.storage_backend
+            || config.storage_path != self.config.storage_path
+            || config.database != self.config.database
+            || config.artifact_compression != self.config.artifact_compression
+            || config.file_encryption != self.config.file_encryption
+            || config.web_ui_enabled != self.config.web_ui_enabled
+            || config.web_ui_port != self.config.web_ui_port
+            || config.web_ui_address != self.config.web_ui_address
+            || config.web_ui != self.config.web_ui
+            || config.container_runtime != self.config.container_runtime
+            || config.image_dir != self.config.image_dir
+            || config.container_paths() != self.config.container_paths()
+            || config.events != self.config.events
+            || config.notifications != self.config.notifications
+            || config.enrichment != self.config.enrichment
+            || config.forwarding != self.config.forwarding
+            || config.control != self.config.control
+            || config.stix != self.config.stix
+            || config.offload != self.config.offload
+            || config.integrity != self.config.integrity
+            || config.dedup != self.config.dedup
+            || config.logging != self.config.logging
+            || config.session_queue_size != self.config.session_queue_size
+        {
+            warn!("Bind address, redirect, storage, offloading, integrity, deduplication, forwarding, STIX push, web interface, control API, container runtime and directories, event sink, notification, enrichment, logging and session queue changes require a restart");
+        }
+
+        // Keep the settings that are only applied at startup
+        let config = Config {
+            bind_address: self.config.bind_address.clone(),
+            ipv6_only: self.config.ipv6_only,
+            redirect: self.config.redirect.clone(),
+            storage_backend: self.config.storage_backend.clone(),
+            storage_path: self.config.storage_path.clone(),
+            database: self.config.database.clone(),
+            artifact_compression: self.config.artifact_compression,
+            file_encryption: self.config.file_encryption.clone(),
+            web_ui_enabled: self.config.web_ui_enabled,
+            web_ui_port: self.config.web_ui_port,
+            web_ui_address: self.config.web_ui_address,
+            web_ui: self.config.web_ui.clone(),
+            container_runtime: self.config.container_runtime.clone(),
+            image_dir: self.config.image_dir.clone(),
+            container_dir: self.config.container_dir.clone(),
+            sandbox_dir: self.config.sandbox_dir.clone(),
+            log_dir: self.config.log_dir.clone(),
+            events: self.config.events.clone(),
+            notifications: self.config.notifications.clone(),
+            enrichment: self.config.enrichment.clone(),
+            forwarding: self.config.forwarding.clone(),
+            control: self.config.control.clone(),
+            stix: self.config.stix.clone(),
+            offload: self.config.offload.clone(),
+            integrity: self.config.integrity.clone(),
+            dedup: self.config.dedup.clone(),
+            logging: self.config.logging.clone(),
+            session_queue_size: self.config.session_queue_size,
+            ..config
+        };
+
+        let mut result = Ok(());
+        if let Some(listener) = self.listener.as_mut() {
+            let ip_addr = IpAddr::from_str(config.bind_address.as_str())
+                .map_err(|e| ControllerError::InitializationFailed(e.to_string()))?;
+            result = listener
+                .reload_services(&Self::enabled_services(&config), ip_addr)
+                .await
+                .map_err(ControllerError::NetworkError);
+            listener.update_connection_filter(&Self::connection_filter(&config));
+        }
+
+        match self.session_control.configure(&config).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("Keeping the previous SSH servers: {}", e),
+            Err(e) => error!("Cannot apply the session settings: {}", e),
+        }
+        // Requests being set up keep the permits of the previous limit
+        if config.session_workers != self.config.session_workers {
+            self.session_permits = Arc::new(Semaphore::new(config.session_workers));
+        }
+        http_client::set_timeout(Duration::from_secs(config.outbound_timeout_secs));
+        self.config = config;
+        self.apply_redirect().await;
+
+        info!(
+            "Configuration reloaded with {} services",
+            self.config.services.len()
+        );
+        result
+    }
+
+    /// Starts serving `service` while running, as if it had been added to the configuration.
+    ///
+    /// A disabled service is only added to the configuration. With a configuration file,
+    /// the service is saved to the service directory.
+    ///
+    /// # Errors
+    /// Returns [`ControllerError::ConfigurationError`] when the service settings are invalid,
+    /// [`ControllerError::ServiceConflict`] when a service with the same name or port exists,
+    /// and [`ControllerError::NetworkError`] when its port cannot be bound.
+    pub async fn add_service(&mut self, service: ServiceConfig) -> Result<(), ControllerError> {
+        Config::validate_service(&service).map_err(ControllerError::ConfigurationError)?;
+        if let Some(existing) = self
+            .config
+            .services
+            .iter()
+            .find(|s| s.name == service.name || s.port == service.port)
+        {
+            return Err(ControllerError::ServiceConflict(format!(
+                "Service {} already uses name {} or port {}",
+                existing.name, service.name, service.port
+            )));
+        }
+
+        if let (true, Some(listener)) = (service.enabled, self.listener.as_mut()) {
+            let ip_addr = IpAddr::from_str(self.config.bind_address.as_str())
+                .map_err(|e| ControllerError::InitializationFailed(e.to_string()))?;
+            listener
+                .add_service(&service, ip_addr)
+                .await
+                .map_err(ControllerError::NetworkError)?;
+        }
+
+        info!("Service {} added on port {}", service.name, service.port);
+        self.persist_service(&service);
+        self.config.services.push(service);
+        self.services_changed().await;
+        Ok(())
+    }
+
+    /// Starts or stops serving the configured service named `service_name`, and returns
+    /// its updated configuration.
+    ///
+    /// Sessions already running in its containers are left to finish. With a configuration
+    /// file, the change is saved to the service directory.
+    ///
+    /// # Errors
+    /// Returns [`ControllerError::UnknownService`] when no such service is configured, and
+    /// [`ControllerError::NetworkError`] when its port cannot be bound.
+    pub async fn set_service_enabled(
         &mut self,
-        request: SessionRequest,
-    ) -> Result<(), SessionError> {
+        service_name: &str,
+        enabled: bool,
+    ) -> Result<ServiceConfig, ControllerError> {
+        let index = self.service_index(service_name)?;
+        if self.config.services[index].enabled == enabled {
+            return Ok(self.config.services[index].clone());
+        }
+
+        let service = ServiceConfig {
+            enabled,
+            ..self.config.services[index].clone()
+        };
+        if let Some(listener) = self.listener.as_mut() {
+            if enabled {
+                let ip_addr = IpAddr::from_str(self.config.bind_address.as_str())
+                    .map_err(|e| ControllerError::InitializationFailed(e.to_string()))?;
+                listener
+                    .add_service(&service, ip_addr)
+                    .await
+                    .map_err(ControllerError::NetworkError)?;
+            } else {
+                listener.remove_service(service.port).await;
+            }
+        }
+
+        info!(
+            "Service {} {} on port {}",
+            service.name,
+            if enabled { "enabled" } else { "disabled" },
+            service.port
+        );
+        self.persist_service(&service);
+        self.config.services[index] = service.clone();
+        self.services_changed().await;
+        Ok(service)
+    }
+
+    /// Stops serving the service named `service_name` and returns its configuration.
+    ///
+    /// Sessions already running in its containers are left to finish. With a configuration
+    /// file, the service is deleted from the service directory.
+    ///
+    /// # Errors
+    /// Returns [`ControllerError::UnknownService`] when no such service is configured.
+    pub async fn remove_service(
+        &mut self,
+        service_name: &str,
+    ) -> Result<ServiceConfig, ControllerError> {
+        let index = self.service_index(service_name)?;
+        let service = self.config.services.remove(index);
+
+        if let Some(listener) = self.listener.as_mut() {
+            listener.remove_service(service.port).await;
+        }
+
+        info!(
+            "Service {} removed from port {}",
+            service.name, service.port
+        );
+        if self.config_path.is_some() {
+            if let Err(e) = Config::delete_service(&Config::service_dir(), &service.name) {
+                error!(
+                    "Cannot delete service {} from its file: {}",
+                    service.name, e
+                );
+            }
+        }
+        self.services_changed().await;
+        Ok(service)
+    }
+
+    /// Applies a command of [`ServiceControl`], true when the configuration was reloaded
+    async fn handle_service_command(&mut self, command: ServiceCommand) -> bool {
+        // A dropped reply only means the requester gave up waiting
+        let draining = self.drain_deadline.is_some();
+        let refused = || {
+            ControllerError::InitializationFailed(
+                "The honeypot is draining, no service is bound anymore".to_string(),
+            )
+        };
+        match command {
+            ServiceCommand::Add { reply, .. } if draining => {
+                let _ = reply.send(Err(refused()));
+            }
+            ServiceCommand::SetEnabled { reply, .. } if draining => {
+                let _ = reply.send(Err(refused()));
+            }
+            ServiceCommand::Reload { reply } if draining => {
+                let _ = reply.send(Err(refused()));
+            }
+            ServiceCommand::List { reply } => {
+                let _ = reply.send(self.config.services.clone());
+            }
+            ServiceCommand::Add { service, reply } => {
+                let _ = reply.send(self.add_service(*service).await);
+            }
+            ServiceCommand::SetEnabled {
+                name,
+                enabled,
+                reply,
+            } => {
+                let _ = reply.send(self.set_service_enabled(&name, enabled).await);
+            }
+            ServiceCommand::Remove { name, reply } => {
+                let _ = reply.send(self.remove_service(&name).await);
+            }
+            ServiceCommand::Drain { reply } => {
+                let _ = reply.send(self.drain().await);
+            }
+            ServiceCommand::Reload { reply } => {
+                info!("Reload requested through the control API, reloading configuration");
+                systemd::reloading();
+                let reloaded = self.reload_config().await;
+                if let Err(e) = &reloaded {
+                    error!("Configuration reload failed: {}", e);
+                }
+                let enabled = Self::enabled_services(&self.config).len();
+                systemd::ready(&format!("Serving {} services", enabled));
+                let _ = reply.send(reloaded.map(|()| self.config.services.len()));
+                return true;
+            }
+        }
+        false
+    }
+
+    fn service_index(&self, service_name: &str) -> Result<usize, ControllerError> {
+        self.config
+            .services
+            .iter()
+            .position(|s| s.name == service_name)
+            .ok_or_else(|| ControllerError::UnknownService(service_name.to_string()))
+    }
+
+    /// Saves `service` to the service directory when running from a configuration file.
+    ///
+    /// The running services are already changed, so a failure is only logged.
+    fn persist_service(&self, service: &ServiceConfig) {
+        if self.config_path.is_none() {
+            return;
+        }
+        if let Err(e) = Config::save_service(&Config::service_dir(), service) {
+            error!("Cannot save service {} to its file: {}", service.name, e);
+        }
+    }
+
+    /// Services to listen for, disabled ones are configured but not bound
+    fn enabled_services(config: &Config) -> Vec<ServiceConfig> {
+        config
+            .services
+            .iter()
+            .filter(|s| s.enabled)
+            .cloned()
+            .collect()
+    }
+
+    /// Propagates a change of `config.services` to the filter, the redirect rules and
+    /// the container pool
+    async fn services_changed(&mut self) {
+        if let Some(listener) = self.listener.as_ref() {
+            listener.update_connection_filter(&Self::connection_filter(&self.config));
+        }
+        self.apply_redirect().await;
+        match self
+            .session_control
+            .set_services(&self.config.services, self.config.warm_containers)
+            .await
+        {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("Keeping the previous SSH servers: {}", e),
+            Err(e) => error!("Cannot apply the service change to sessions: {}", e),
+        }
+    }
+
+    /// Loads the nftables rules funneling the `redirect_ports` of the enabled services
+    /// to their listeners, see [`redirect`]
+    async fn apply_redirect(&self) {
+        let services = Self::enabled_services(&self.config);
+        if let Err(e) = redirect::apply(&self.config.redirect, &services).await {
+            error!("Cannot redirect the redirect_ports of the services: {}", e);
+        }
+    }
+
+    fn connection_filter(config: &Config) -> ConnectionFilter {
+        ConnectionFilter::new(config.ip_filter.clone(), config.port_filter.clone())
+            .with_rate_limit(config.rate_limit.clone(), &config.services)
+            .with_scan_detection(config.scan_detection.clone())
+    }
+
+    /// Waits for a setup permit, then for the next request of `requests`
+    ///
+    /// Unhandled requests wait in the channel meanwhile, the listener shedding the
+    /// connections it cannot queue. Returns `None` once the channel is closed, or
+    /// when there is none.
+    async fn next_request<T>(
+        permits: Arc<Semaphore>,
+        requests: Option<&mut mpsc::Receiver<T>>,
+    ) -> Option<(OwnedSemaphorePermit, T)> {
+        let requests = requests?;
+        let permit = permits.acquire_owned().await.ok()?;
+        let request = requests.recv().await?;
+        Some((permit, request))
+    }
+
+    /// Serves `request` in a task of its own, the setup `permit` being released
+    /// once its session is ready and its connection proxied
+    fn spawn_session_request(&mut self, request: SessionRequest, permit: OwnedSemaphorePermit) {
         info!("Session request received from {}", request.client_addr);
         info!("Service detected as: {:?}", request.service_name);
 
-        // Clone the config to avoid holding a reference to self
-        let service = self
-            .find_config_for_service(&request.service_name)
-            .cloned()
-            .unwrap();
+        // The service may have been removed since its listener accepted the connection
+        let Some(service) = self.find_config_for_service(&request.service_name).cloned() else {
+            let e = SessionError::UnknownService(request.service_name.clone());
+            error!("Session handling failed: {}", e);
+            return;
+        };
+        let session_control = self.session_control.clone();
+        let span = request.span.clone();
+
+        self.session_tasks.spawn(
+            async move {
+                let connection = session_control.open_connection(request, &service).await;
+                drop(permit);
+                let served = match connection {
+                    Ok(connection) => {
+                        connection
+                            .proxy(&session_control)
+                            .instrument(info_span!("proxy"))
+                            .await
+                    }
+                    Err(e) => Err(e),
+                };
+                match served {
+                    Ok(()) => {
+                        info!("Session handling completed with capture lifecycle initialized")
+                    }
+                    Err(e) => error!("Session handling failed: {:?}", e),
+                }
+            }
+            .instrument(span),
+        );
+    }
 
-        // Handle the session and trigger capture lifecycle
-        self.session_manager
-            .handle_session(request, &service)
-            .await?;
+    /// Serves the UDP flow of `request` in a task of its own, like
+    /// [`spawn_session_request`](Self::spawn_session_request)
+    fn spawn_udp_session_request(
+        &mut self,
+        request: UdpSessionRequest,
+        permit: OwnedSemaphorePermit,
+    ) {
+        info!("UDP session request received from {}", request.client_addr);
+        info!("Service detected as: {:?}", request.service_name);
 
-        info!("Session handling completed with capture lifecycle initialized");
-        Ok(())
+        let Some(service) = self.find_config_for_service(&request.service_name).cloned() else {
+            let e = SessionError::UnknownService(request.service_name.clone());
+            error!("UDP session handling failed: {}", e);
+            return;
+        };
+        let session_control = self.session_control.clone();
+        let span = request.span.clone();
+
+        self.session_tasks.spawn(
+            async move {
+                let flow = session_control.open_udp_flow(request, &service).await;
+                drop(permit);
+                let served = match flow {
+                    Ok(flow) => {
+                        flow.proxy(&session_control)
+                            .instrument(info_span!("proxy"))
+                            .await
+                    }
+                    Err(e) => Err(e),
+                };
+                match served {
+                    Ok(()) => info!("UDP session handling completed"),
+                    Err(e) => error!("UDP session handling failed: {:?}", e),
+                }
+            }
+            .instrument(span),
+        );
     }
 
     /// Manually trigger capture finalization for a specific session
@@ -196,22 +1160,32 @@ impl Controller {
         &mut self,
         session_id: &uuid::Uuid,
     ) -> Result<(), SessionError> {
-        self.session_manager
+        self.session_control
             .finalize_session_capture(session_id)
             .await
     }
 
     /// Manually end a session and finalize its capture
     pub async fn end_session(&mut self, session_id: &uuid::Uuid) -> Result<(), SessionError> {
-        self.session_manager.end_session(session_id).await
+        self.session_control.end_session(session_id).await
+    }
+
+    /// The running containers, their age, health and resource usage
+    pub async fn container_report(&self) -> Result<ContainerReport, SessionError> {
+        self.session_control.container_report().await
+    }
+
+    /// Summaries of the sessions active right now
+    pub async fn list_active_sessions(&self) -> Result<Vec<ActiveSessionSummary>, SessionError> {
+        self.session_control.list_active_sessions().await
     }
 
     /// Get session statistics including capture information
-    pub fn get_session_stats(
+    pub async fn get_session_stats(
         &self,
         session_id: &uuid::Uuid,
     ) -> Option<(crate::SessionStatus, u64, chrono::Duration)> {
-        self.session_manager.get_session_stats(session_id)
+        self.session_control.get_session_stats(session_id).await
     }
 
     /// Trigger stdio capture for a specific session
@@ -219,7 +1193,7 @@ impl Controller {
         &mut self,
         session_id: &uuid::Uuid,
     ) -> Result<(), SessionError> {
-        self.session_manager.trigger_stdio_capture(session_id).await
+        self.session_control.trigger_stdio_capture(session_id).await
     }
 
     /// Called when a connection drops or times out to ensure proper capture finalization
@@ -245,17 +1219,16 @@ impl Controller {
     }
 
     /// Get all sessions using optional filtering
-    pub fn get_sessions(
+    pub async fn get_sessions(
         &self,
         filter: Option<crate::storage::types::SessionFilter>,
     ) -> Result<Vec<crate::session::Session>, crate::error_handling::types::StorageError> {
-        self.storage.get_sessions(filter)
+        self.storage.get_sessions(filter).await
     }
 
     /// Cleanup expired sessions manually
     pub async fn cleanup_expired_sessions(&mut self) -> Result<(), SessionError> {
-        self.session_manager.cleanup_expired_sessions().await;
-        Ok(())
+        self.session_control.cleanup_expired_sessions().await
     }
 
     fn find_config_for_service(&self, service_name: &str) -> Option<&ServiceConfig> {
@@ -277,29 +1250,96 @@ impl Controller {
 
         // Use file storage for tests to avoid database complexity
         let storage: Arc<dyn Storage + Send + Sync> =
-            Arc::new(FileStorage::new(temp_path).map_err(|e| ControllerError::StorageError(e))?);
+            Arc::new(FileStorage::new(temp_path).map_err(ControllerError::StorageError)?);
 
         // Create a mock container manager that doesn't require root privileges
         let container_manager = Arc::new(tokio::sync::Mutex::new(ContainerManager::new_mock()));
 
-        let session_manager = SessionManager::new(
+        let mut session_manager = SessionManager::new(
             container_manager.clone(),
             storage.clone(),
             config.max_sessions,
         );
+        session_manager.set_warm_containers(&config.services, config.warm_containers);
+        session_manager.set_session_reuse(config.session_reuse_minutes);
+        let (service_control, service_rx) = ServiceControl::channel();
 
         Ok(Self {
+            session_permits: Arc::new(Semaphore::new(config.session_workers)),
             config,
             listener: None,
             session_rx: None,
-            listener_handle: None,
+            udp_session_rx: None,
+            config_path: None,
+            profile: None,
             container_manager,
-            session_manager,
+            session_control: SessionControl::spawn(session_manager),
+            session_tasks: JoinSet::new(),
             storage,
+            service_control,
+            service_rx,
+            drain_deadline: None,
+            campaigns: Arc::new(CampaignBoard::new()),
         })
     }
 }
 
+/// Signal stream, pending forever when disabled or unsupported
+struct ControlSignal {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl ControlSignal {
+    /// SIGHUP, reloading the configuration when `enabled`
+    fn hangup(enabled: bool) -> Self {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::SignalKind;
+            Self::listen(enabled, SignalKind::hangup(), "SIGHUP")
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = enabled;
+            Self {}
+        }
+    }
+
+    /// SIGUSR1, draining the controller
+    fn drain() -> Self {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::SignalKind;
+            Self::listen(true, SignalKind::user_defined1(), "SIGUSR1")
+        }
+        #[cfg(not(unix))]
+        {
+            Self {}
+        }
+    }
+
+    #[cfg(unix)]
+    fn listen(enabled: bool, kind: tokio::signal::unix::SignalKind, name: &str) -> Self {
+        let signal = enabled
+            .then(|| tokio::signal::unix::signal(kind))
+            .and_then(|res| {
+                res.map_err(|e| error!("Failed to listen for {}: {}", name, e))
+                    .ok()
+            });
+        Self { signal }
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = self.signal.as_mut() {
+            if signal.recv().await.is_some() {
+                return;
+            }
+        }
+        std::future::pending::<()>().await
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -391,6 +1431,147 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_requests_wait_for_a_setup_permit() {
+        let permits = Arc::new(Semaphore::new(1));
+        let (tx, mut rx) = mpsc::channel(4);
+        tx.send("first").await.unwrap();
+        tx.send("second").await.unwrap();
+
+        let (permit, request) = Controller::next_request(permits.clone(), Some(&mut rx))
+            .await
+            .unwrap();
+        assert_eq!(request, "first");
+        // The second request stays queued while the first is set up
+        let waiting = time::timeout(
+            Duration::from_millis(50),
+            Controller::next_request(permits.clone(), Some(&mut rx)),
+        )
+        .await;
+        assert!(waiting.is_err());
+
+        drop(permit);
+        let (permit, request) = Controller::next_request(permits.clone(), Some(&mut rx))
+            .await
+            .unwrap();
+        assert_eq!(request, "second");
+        drop(permit);
+        drop(tx);
+        assert!(Controller::next_request(permits.clone(), Some(&mut rx))
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_add_and_remove_services() {
+        let config = create_http_test_config().await;
+        let http = config.services[0].clone();
+        let mut controller = Controller::new_for_test(config).await.unwrap();
+
+        let duplicate_port = ServiceConfig {
+            name: "other".to_string(),
+            ..http.clone()
+        };
+        assert!(controller.add_service(duplicate_port).await.is_err());
+
+        let ssh = ServiceConfig {
+            name: "ssh".to_string(),
+            port: get_free_port().await,
+            ..ServiceConfig::default()
+        };
+        controller.add_service(ssh.clone()).await.unwrap();
+        assert_eq!(controller.find_config_for_service("ssh"), Some(&ssh));
+
+        assert_eq!(controller.remove_service("http").await.unwrap(), http);
+        assert!(controller.find_config_for_service("http").is_none());
+        assert!(matches!(
+            controller.remove_service("http").await,
+            Err(ControllerError::UnknownService(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reload_keeps_the_settings_read_at_startup() {
+        let config = create_http_test_config().await;
+        let mut controller = Controller::new_for_test(config.clone()).await.unwrap();
+
+        let mut reloaded = config.clone();
+        reloaded.web_ui_address = "0.0.0.0".parse().unwrap();
+        reloaded.control.listen = Some("127.0.0.1:50051".parse().unwrap());
+        reloaded.control.tokens = vec!["s3cr3t".to_string()];
+        reloaded.max_sessions = config.max_sessions + 1;
+        controller.apply_config(reloaded).await.unwrap();
+
+        assert_eq!(controller.config.web_ui_address, config.web_ui_address);
+        assert_eq!(controller.config.control, config.control);
+        assert_eq!(controller.config.max_sessions, config.max_sessions + 1);
+    }
+
+    #[tokio::test]
+    async fn test_services_are_enabled_and_disabled() {
+        let mut config = create_http_test_config().await;
+        config.services[0].enabled = false;
+        let port = config.services[0].port;
+        let mut controller = Controller::new_for_test(config).await.unwrap();
+        let (tx, _rx) = mpsc::channel(10);
+        let mut listener = NetworkListener::new(tx);
+        listener
+            .listen(std::net::Ipv4Addr::LOCALHOST.into())
+            .await
+            .unwrap();
+        controller.listener = Some(listener);
+
+        let http = controller.set_service_enabled("http", true).await.unwrap();
+        assert!(http.enabled);
+        assert!(wait_for_service_ready(port, Duration::from_secs(2)).await);
+
+        let http = controller.set_service_enabled("http", false).await.unwrap();
+        assert!(!http.enabled);
+        assert_eq!(controller.find_config_for_service("http"), Some(&http));
+        assert!(TcpStream::connect(("127.0.0.1", port)).await.is_err());
+
+        let control = controller.service_control();
+        let request = tokio::spawn(async move { control.set_enabled("http", true).await });
+        let command = controller.service_rx.recv().await.unwrap();
+        controller.handle_service_command(command).await;
+        assert!(request.await.unwrap().unwrap().enabled);
+        assert!(wait_for_service_ready(port, Duration::from_secs(2)).await);
+
+        assert!(matches!(
+            controller.set_service_enabled("ftp", true).await,
+            Err(ControllerError::UnknownService(_))
+        ));
+        let invalid = ServiceConfig {
+            name: "../ssh".to_string(),
+            port: get_free_port().await,
+            ..ServiceConfig::default()
+        };
+        assert!(matches!(
+            controller.add_service(invalid).await,
+            Err(ControllerError::ConfigurationError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_drain_closes_the_listeners_and_stops_the_controller() {
+        let config = create_http_test_config().await;
+        let port = config.services[0].port;
+        let mut controller = Controller::new_for_test(config).await.unwrap();
+        let control = controller.service_control();
+        let (_tx, rx) = tokio::sync::broadcast::channel(1);
+        let controller_task = tokio::spawn(async move { controller.run(rx).await });
+        assert!(wait_for_service_ready(port, Duration::from_secs(10)).await);
+
+        assert_eq!(control.drain().await.unwrap(), Duration::from_secs(30));
+        assert!(TcpStream::connect(("127.0.0.1", port)).await.is_err());
+        // Without any connection left, the drain does not wait for its deadline
+        time::timeout(Duration::from_secs(5), controller_task)
+            .await
+            .expect("drained controller did not stop")
+            .unwrap()
+            .unwrap();
+    }
+
     #[tokio::test]
     async fn test_controller_flow_from_network_listener_to_session_request() {
         let _ = env_logger::builder()
@@ -0,0 +1,167 @@
+//! Operations run from the command line against the configured storage backend,
+//! without starting the honeypot: listing, inspecting and exporting the stored
+//! sessions, and deleting the old ones.
+//!
+//! The storage is opened with [`Controller::open_storage`], so that a running
+//! honeypot and these operations see the same sessions.
+//!
+//! [`Controller::open_storage`]: super::controller_handler::Controller::open_storage
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::error_handling::types::StorageError;
+use crate::session_management::session::Session;
+use crate::storage::storage_trait::Storage;
+use crate::storage::types::CredentialFilter;
+
+/// Parses an age such as `30d`, `12h` or `90m`, a bare number being days
+///
+/// # Errors
+/// Describes why `age` is not a positive number followed by `d`, `h` or `m`.
+pub fn parse_age(age: &str) -> Result<TimeDelta, String> {
+    let age = age.trim();
+    let (number, unit) = match age.find(|c: char| !c.is_ascii_digit()) {
+        Some(at) => age.split_at(at),
+        None => (age, "d"),
+    };
+    let number: i64 = number
+        .parse()
+        .ok()
+        .filter(|n| *n > 0)
+        .ok_or_else(|| format!("{:?} does not start with a positive number", age))?;
+    let delta = match unit {
+        "d" => TimeDelta::try_days(number),
+        "h" => TimeDelta::try_hours(number),
+        "m" => TimeDelta::try_minutes(number),
+        _ => {
+            return Err(format!(
+                "unknown unit {:?} in {:?}, use d, h or m",
+                unit, age
+            ))
+        }
+    };
+    delta.ok_or_else(|| format!("{:?} is too long", age))
+}
+
+/// Sessions as an aligned table, one line per session after a header
+pub fn session_table(sessions: &[Session]) -> String {
+    let mut table = format!(
+        "{:<36}  {:<20}  {:<12}  {:<21}  {:<9}  {:>10}\n",
+        "ID", "STARTED", "SERVICE", "CLIENT", "STATUS", "BYTES"
+    );
+    for session in sessions {
+        table.push_str(&format!(
+            "{:<36}  {:<20}  {:<12}  {:<21}  {:<9}  {:>10}\n",
+            session.id,
+            session.start_time.format("%Y-%m-%d %H:%M:%S"),
+            session.service_name,
+            session.client_addr,
+            format!("{:?}", session.status),
+            session.bytes_transferred
+        ));
+    }
+    table
+}
+
+/// Everything known about session `session_id` except its captures: the session,
+/// its annotations, the credentials tried and the commands executed
+///
+/// # Errors
+/// Returns [`StorageError::ReadFailed`] when the session is unknown, and the
+/// errors of the storage backend.
+pub async fn session_details(
+    storage: &dyn Storage,
+    session_id: Uuid,
+) -> Result<serde_json::Value, StorageError> {
+    let session = storage.get_session(session_id).await?;
+    let annotations = storage.get_annotations(session_id).await?;
+    let credentials = storage
+        .get_credentials(Some(CredentialFilter {
+            session_id: Some(session_id),
+            ..CredentialFilter::default()
+        }))
+        .await?;
+    let commands = storage.get_commands(session_id).await?;
+    Ok(json!({
+        "session": session,
+        "annotations": annotations,
+        "credentials": credentials,
+        "commands": commands,
+    }))
+}
+
+/// Deletes the sessions of `storage` ended more than `age` before `now`, and
+/// returns how many were deleted
+///
+/// # Errors
+/// Returns the errors of the storage backend.
+pub async fn cleanup(
+    storage: &dyn Storage,
+    age: TimeDelta,
+    now: DateTime<Utc>,
+) -> Result<usize, StorageError> {
+    let deleted = storage.cleanup_old_sessions(now - age).await?;
+    storage.flush().await?;
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session_management::SessionStatus;
+    use crate::storage::memory_storage::MemoryStorage;
+
+    fn session(service: &str, started: DateTime<Utc>) -> Session {
+        Session {
+            id: Uuid::new_v4(),
+            service_name: service.to_string(),
+            client_addr: "203.0.113.7:50022".parse().unwrap(),
+            start_time: started,
+            end_time: Some(started + TimeDelta::minutes(5)),
+            container_id: None,
+            bytes_transferred: 4096,
+            status: SessionStatus::Completed,
+            enrichment: None,
+            original_dst: None,
+        }
+    }
+
+    #[test]
+    fn ages_have_a_unit() {
+        assert_eq!(parse_age("30d"), Ok(TimeDelta::days(30)));
+        assert_eq!(parse_age("12h"), Ok(TimeDelta::hours(12)));
+        assert_eq!(parse_age("90m"), Ok(TimeDelta::minutes(90)));
+        assert_eq!(parse_age("7"), Ok(TimeDelta::days(7)));
+        assert!(parse_age("0d").is_err());
+        assert!(parse_age("2w").is_err());
+        assert!(parse_age("d").is_err());
+    }
+
+    #[tokio::test]
+    async fn old_sessions_are_cleaned_up_and_details_gathered() {
+        let storage = MemoryStorage::new();
+        let now = Utc::now();
+        let old = session("ssh", now - TimeDelta::days(40));
+        let recent = session("http", now - TimeDelta::days(2));
+        storage.save_session(&old).await.unwrap();
+        storage.save_session(&recent).await.unwrap();
+
+        let table = session_table(&storage.get_sessions(None).await.unwrap());
+        assert_eq!(table.lines().count(), 3);
+        assert!(table.starts_with("ID "));
+        assert!(table.contains("203.0.113.7:50022"));
+
+        let details = session_details(&storage, recent.id).await.unwrap();
+        assert_eq!(details["session"]["service_name"], "http");
+        assert!(details["commands"].as_array().unwrap().is_empty());
+
+        assert_eq!(
+            cleanup(&storage, TimeDelta::days(30), now).await.unwrap(),
+            1
+        );
+        assert!(storage.get_session(old.id).await.is_err());
+        assert!(session_details(&storage, old.id).await.is_err());
+    }
+}
@@ -0,0 +1,379 @@
+//! Replay of a stored session against a new container of its service, for
+//! reproducible analysis of attacker payloads.
+//!
+//! The bytes the client sent, `tcp_client_to_container`, are sent again in the
+//! chunks and with the pauses they were recorded with, through the proxy and
+//! recorder of a live session. What the container answers is stored as a new
+//! session tagged [`REPLAY_TAG`], whose notes name the original session, and
+//! compared with the original responses.
+//!
+//! Like the other operations, a replay runs from the command line against the
+//! configured storage, see [`super::operations`].
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use uuid::Uuid;
+
+use crate::configuration::types::{Protocol, ServiceConfig};
+use crate::container_management::ContainerManager;
+use crate::data_capture::{Direction, StreamRecorder};
+use crate::error_handling::types::{CaptureError, Context, ControllerError};
+use crate::session_management::session::Session;
+use crate::storage::storage_trait::Storage;
+use crate::storage::types::{SessionAnnotations, TagSource};
+use crate::SessionStatus;
+
+/// Longest pause kept between two chunks of the client, which may have been idle
+/// for minutes
+pub const MAX_REPLAY_PAUSE: Duration = Duration::from_secs(2);
+
+/// Silence of the container after which it is done answering the last chunk
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Tag of the sessions recorded by a replay
+pub const REPLAY_TAG: &str = "replay";
+
+/// Bytes the client sent at once, after pausing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayChunk {
+    pub pause: Duration,
+    pub data: Vec<u8>,
+}
+
+/// Outcome of [`replay_session`]
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayReport {
+    /// Session whose client was replayed
+    pub original_session_id: Uuid,
+    /// Session the replay was recorded as
+    pub session_id: Uuid,
+    pub service_name: String,
+    pub bytes_sent: usize,
+    pub bytes_received: usize,
+    /// Whether the container answered byte for byte as in the original session
+    pub same_response: bool,
+}
+
+/// Splits `data`, what the client of a session sent, into the chunks recorded in
+/// `timestamps`, each paused as long as the client did before sending it.
+///
+/// The first pause is measured from the first recorded chunk, usually the banner
+/// of the service. Without timestamps matching `data`, e.g. for a capture whose
+/// timestamps were lost, `data` is sent at once.
+pub fn client_chunks(
+    data: &[u8],
+    timestamps: &[(DateTime<Utc>, Direction, usize)],
+) -> Vec<ReplayChunk> {
+    let sent: Vec<_> = timestamps
+        .iter()
+        .filter(|(_, direction, len)| *direction == Direction::ClientToContainer && *len > 0)
+        .collect();
+    if sent.iter().map(|(_, _, len)| len).sum::<usize>() != data.len() {
+        return match data.is_empty() {
+            true => Vec::new(),
+            false => vec![ReplayChunk {
+                pause: Duration::ZERO,
+                data: data.to_vec(),
+            }],
+        };
+    }
+    let mut previous = timestamps.first().map(|(at, _, _)| *at);
+    let mut offset = 0;
+    sent.into_iter()
+        .map(|&(at, _, len)| {
+            let pause = previous
+                .and_then(|previous| (at - previous).to_std().ok())
+                .unwrap_or_default()
+                .min(MAX_REPLAY_PAUSE);
+            previous = Some(at);
+            offset += len;
+            ReplayChunk {
+                pause,
+                data: data[offset - len..offset].to_vec(),
+            }
+        })
+        .collect()
+}
+
+/// Sends `chunks` to `container_stream` as a client of `recorder` would, which
+/// records both directions.
+///
+/// The replay ends once the container closes the connection, or stays silent for
+/// [`RESPONSE_TIMEOUT`] after the last chunk, the proxy of `recorder` being
+/// terminated.
+///
+/// Errors
+/// - Returns [`CaptureError::TcpStreamError`] when a chunk cannot be sent, or the
+///   proxy failed.
+pub async fn replay_chunks(
+    recorder: &StreamRecorder,
+    chunks: &[ReplayChunk],
+    container_stream: TcpStream,
+) -> Result<(), CaptureError> {
+    // The recorder proxies a client connection, the replay connects over loopback
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .map_err(CaptureError::TcpStreamError)?;
+    let address = listener
+        .local_addr()
+        .map_err(CaptureError::TcpStreamError)?;
+    let (client, accepted) = tokio::join!(TcpStream::connect(address), listener.accept());
+    let client = client.map_err(CaptureError::TcpStreamError)?;
+    let (proxied, _) = accepted.map_err(CaptureError::TcpStreamError)?;
+
+    let termination = recorder.termination();
+    let (mut reader, mut writer) = client.into_split();
+    let sent = AtomicBool::new(false);
+    let send = async {
+        let mut result = Ok(());
+        for chunk in chunks {
+            tokio::time::sleep(chunk.pause).await;
+            result = writer.write_all(&chunk.data).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        sent.store(true, Ordering::Relaxed);
+        // The client stays connected until the container answered
+        (writer, result)
+    };
+    let receive = async {
+        let mut buf = vec![0u8; 16 * 1024];
+        loop {
+            match tokio::time::timeout(RESPONSE_TIMEOUT, reader.read(&mut buf)).await {
+                Ok(Ok(0)) | Ok(Err(_)) => break,
+                Ok(Ok(_)) => {}
+                Err(_) if sent.load(Ordering::Relaxed) => break,
+                Err(_) => {}
+            }
+        }
+        termination.terminate();
+    };
+    let (proxy, ((_writer, written), ())) =
+        tokio::join!(recorder.start_tcp_proxy(proxied, container_stream), async {
+            tokio::join!(send, receive)
+        });
+    written.map_err(CaptureError::TcpStreamError)?;
+    proxy
+}
+
+/// Replays the client of the stored session `session_id` against a new container
+/// of its service in `services`, see [`replay_chunks`]. The replay is stored in
+/// `storage` as a new session of the same client, tagged [`REPLAY_TAG`].
+///
+/// Errors
+/// - Returns [`ControllerError::UnknownService`] when the service of the session is
+///   no longer configured.
+/// - Returns [`ControllerError::ReplayFailed`] for a UDP session, or one whose client
+///   sent nothing.
+/// - Returns the errors of the storage, of the container manager and of the replay.
+pub async fn replay_session(
+    storage: Arc<dyn Storage + Send + Sync>,
+    container_manager: &mut ContainerManager,
+    services: &[ServiceConfig],
+    session_id: Uuid,
+) -> Result<ReplayReport, ControllerError> {
+    let original = storage.get_session(session_id).await?;
+    let original_artifacts = storage.get_capture_artifacts(session_id).await?;
+    let service = services
+        .iter()
+        .find(|service| service.name == original.service_name)
+        .ok_or_else(|| ControllerError::UnknownService(original.service_name.clone()))?;
+    if service.protocol != Protocol::TCP {
+        return Err(ControllerError::ReplayFailed(format!(
+            "service {} is not served over TCP",
+            service.name
+        )));
+    }
+    let chunks = client_chunks(
+        &original_artifacts.tcp_client_to_container,
+        &original_artifacts.tcp_timestamps,
+    );
+    if chunks.is_empty() {
+        return Err(ControllerError::ReplayFailed(format!(
+            "the client of session {} sent nothing",
+            session_id
+        )));
+    }
+
+    let mut container_handle = container_manager.create_container(service).await?;
+    let Some(container_stream) = container_handle.tcp_socket.take() else {
+        if let Err(e) = container_manager.cleanup_container(container_handle).await {
+            warn!("Failed to clean up the replay container: {}", e);
+        }
+        return Err(ControllerError::ReplayFailed(
+            "the container accepted no connection".to_string(),
+        ));
+    };
+
+    let id = Uuid::new_v4();
+    let mut session = Session {
+        id,
+        service_name: service.name.clone(),
+        client_addr: original.client_addr,
+        start_time: Utc::now(),
+        end_time: None,
+        container_id: Some(container_handle.id.clone()),
+        bytes_transferred: 0,
+        status: SessionStatus::Active,
+        enrichment: None,
+        original_dst: original.original_dst,
+    };
+    storage.save_session(&session).await?;
+
+    let mut recorder = StreamRecorder::new(id, storage.clone())
+        .with_service(&service.name)
+        .with_persona(container_handle.persona.clone());
+    // Stdio capture is optional, the replay goes on without it
+    if let Err(e) = container_handle
+        .stdio_sources()
+        .map_err(CaptureError::StdioError)
+        .and_then(|(log, terminal)| recorder.start_stdio_capture(log, terminal))
+    {
+        debug!("Could not start stdio capture for replay {}: {}", id, e);
+    }
+    debug!("Replaying session {} as session {}", session_id, id);
+    let replayed = replay_chunks(&recorder, &chunks, container_stream).await;
+    recorder.stop_stdio_capture();
+    let log_path = container_handle.activity_log();
+    if log_path.exists() {
+        if let Err(e) = recorder.parse_stdio_log_from_file(&log_path) {
+            warn!("Could not parse the activity log of replay {}: {}", id, e);
+        }
+    }
+    let finalized = recorder.finalize_capture().await;
+    if let Err(e) = container_manager.cleanup_container(container_handle).await {
+        warn!("Failed to clean up the container of replay {}: {}", id, e);
+    }
+
+    session.end_time = Some(Utc::now());
+    session.status = match (&replayed, &finalized) {
+        (Ok(()), Ok(_)) => SessionStatus::Completed,
+        _ => SessionStatus::Error,
+    };
+    if let Ok(artifacts) = &finalized {
+        session.bytes_transferred = artifacts.total_bytes;
+    }
+    storage.save_session(&session).await?;
+    let mut annotations = SessionAnnotations::new(id);
+    annotations.add_tag(REPLAY_TAG, TagSource::Analyst);
+    annotations.notes = Some(format!("Replay of session {}", session_id));
+    storage.save_annotations(&annotations).await?;
+
+    replayed.context(format!("Cannot replay session {}", session_id))?;
+    let artifacts = finalized.context(format!("Cannot record replay {}", id))?;
+    Ok(ReplayReport {
+        original_session_id: session_id,
+        session_id: id,
+        service_name: service.name.clone(),
+        bytes_sent: artifacts.tcp_client_to_container.len(),
+        bytes_received: artifacts.tcp_container_to_client.len(),
+        same_response: artifacts.tcp_container_to_client
+            == original_artifacts.tcp_container_to_client,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory_storage::MemoryStorage;
+    use chrono::TimeDelta;
+
+    #[test]
+    fn chunks_keep_the_pauses_of_the_client() {
+        let start = Utc::now();
+        let timestamps = vec![
+            (start, Direction::ContainerToClient, 9),
+            (
+                start + TimeDelta::milliseconds(300),
+                Direction::ClientToContainer,
+                5,
+            ),
+            (
+                start + TimeDelta::milliseconds(400),
+                Direction::ContainerToClient,
+                2,
+            ),
+            (
+                start + TimeDelta::minutes(3),
+                Direction::ClientToContainer,
+                4,
+            ),
+        ];
+        let chunks = client_chunks(b"USER root", &timestamps);
+        assert_eq!(
+            chunks,
+            vec![
+                ReplayChunk {
+                    pause: Duration::from_millis(300),
+                    data: b"USER ".to_vec(),
+                },
+                ReplayChunk {
+                    pause: MAX_REPLAY_PAUSE,
+                    data: b"root".to_vec(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn data_without_its_timestamps_is_sent_at_once() {
+        let chunks = client_chunks(b"GET / HTTP/1.0\r\n\r\n", &[]);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].pause, Duration::ZERO);
+        assert_eq!(chunks[0].data, b"GET / HTTP/1.0\r\n\r\n");
+        assert!(client_chunks(b"", &[]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn replayed_chunks_are_recorded_with_the_responses() {
+        // Fake service answering each line in upper case, closing after two
+        let service = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let service_addr = service.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = service.accept().await.unwrap();
+            let mut received = Vec::new();
+            let mut buf = [0u8; 64];
+            while received.iter().filter(|&&b| b == b'\n').count() < 2 {
+                let n = stream.read(&mut buf).await.unwrap();
+                received.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(&received.to_ascii_uppercase())
+                .await
+                .unwrap();
+        });
+        let container_stream = TcpStream::connect(service_addr).await.unwrap();
+
+        let id = Uuid::new_v4();
+        let recorder = StreamRecorder::new(id, Arc::new(MemoryStorage::new()));
+        let chunks = vec![
+            ReplayChunk {
+                pause: Duration::ZERO,
+                data: b"uname -a\n".to_vec(),
+            },
+            ReplayChunk {
+                pause: Duration::from_millis(20),
+                data: b"id\n".to_vec(),
+            },
+        ];
+        tokio::time::timeout(
+            Duration::from_secs(3),
+            replay_chunks(&recorder, &chunks, container_stream),
+        )
+        .await
+        .expect("the replay ends when the container closes")
+        .unwrap();
+
+        let artifacts = recorder.finalize_capture().await.unwrap();
+        assert_eq!(artifacts.tcp_client_to_container, b"uname -a\nid\n");
+        assert_eq!(artifacts.tcp_container_to_client, b"UNAME -A\nID\n");
+    }
+}
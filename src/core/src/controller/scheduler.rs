@@ -0,0 +1,179 @@
+//! Background maintenance of the running honeypot.
+//!
+//! The [`Scheduler`] runs one tokio task per periodic job, each ticking its own
+//! interval from the `[maintenance]`, `[retention]`, `[campaigns]` and `[reports]`
+//! configuration. Since the
+//! jobs act on the sessions the controller owns, the tasks do not run them: they
+//! queue a [`MaintenanceTask`] that the controller picks up between two session
+//! requests, see [`Scheduler::next`].
+//!
+//! A tick missed while the controller was busy is run once it is free, not
+//! repeated. The tasks are stopped by [`Scheduler::shutdown`], before the
+//! controller ends the remaining sessions.
+
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio::time::{Instant, MissedTickBehavior};
+
+use crate::configuration::types::{
+    CampaignConfig, MaintenanceConfig, ReportConfig, RetentionConfig,
+};
+
+/// A periodic job due to the controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaintenanceTask {
+    /// Ends the idle and timed out sessions
+    SessionCheck,
+    /// Deletes the stored sessions past the retention limits
+    Retention,
+    /// Ends the sessions whose container stopped
+    HealthCheck,
+    /// Logs the session and container counters
+    StatsLog,
+    /// Groups the stored sessions into attack campaigns
+    Campaigns,
+    /// Writes the summary report of the last period if it is missing
+    Reports,
+}
+
+/// Interval of the checks for a summary report to write
+const REPORT_CHECK: Duration = Duration::from_secs(3600);
+
+/// Drives the [`MaintenanceTask`]s on their configured intervals
+pub struct Scheduler {
+    tasks: JoinSet<()>,
+    due: mpsc::Receiver<MaintenanceTask>,
+}
+
+impl Scheduler {
+    /// Starts the tasks enabled by the configuration. Retention is enforced,
+    /// campaigns are clustered and the report is checked for right away, the other
+    /// tasks first run one interval after the start
+    pub fn start(
+        maintenance: &MaintenanceConfig,
+        retention: &RetentionConfig,
+        campaigns: &CampaignConfig,
+        reports: &ReportConfig,
+    ) -> Self {
+        let mut schedule = vec![(
+            MaintenanceTask::SessionCheck,
+            Duration::from_secs(maintenance.session_check_secs.max(1)),
+            false,
+        )];
+        if retention.is_enabled() {
+            schedule.push((
+                MaintenanceTask::Retention,
+                Duration::from_secs(retention.interval_minutes.max(1) * 60),
+                true,
+            ));
+        }
+        if maintenance.health_check_secs > 0 {
+            schedule.push((
+                MaintenanceTask::HealthCheck,
+                Duration::from_secs(maintenance.health_check_secs),
+                false,
+            ));
+        }
+        if maintenance.stats_log_minutes > 0 {
+            schedule.push((
+                MaintenanceTask::StatsLog,
+                Duration::from_secs(maintenance.stats_log_minutes * 60),
+                false,
+            ));
+        }
+        if campaigns.enabled {
+            schedule.push((
+                MaintenanceTask::Campaigns,
+                Duration::from_secs(campaigns.interval_minutes.max(1) * 60),
+                true,
+            ));
+        }
+        if reports.enabled {
+            schedule.push((MaintenanceTask::Reports, REPORT_CHECK, true));
+        }
+
+        // Each task waits for its previous tick to be taken before queuing another
+        let (sender, due) = mpsc::channel(schedule.len());
+        let mut tasks = JoinSet::new();
+        for (task, period, immediate) in schedule {
+            let sender = sender.clone();
+            tasks.spawn(async move {
+                let start = if immediate {
+                    Instant::now()
+                } else {
+                    Instant::now() + period
+                };
+                let mut timer = tokio::time::interval_at(start, period);
+                timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                loop {
+                    timer.tick().await;
+                    if sender.send(task).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        Self { tasks, due }
+    }
+
+    /// Next task due, pending forever once the scheduler is shut down
+    pub async fn next(&mut self) -> MaintenanceTask {
+        match self.due.recv().await {
+            Some(task) => task,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Stops the tasks, dropping the ticks not taken yet
+    pub async fn shutdown(&mut self) {
+        self.tasks.shutdown().await;
+        self.due.close();
+        while self.due.try_recv().is_ok() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn tasks_run_on_their_intervals() {
+        let maintenance = MaintenanceConfig {
+            session_check_secs: 10,
+            health_check_secs: 25,
+            stats_log_minutes: 0,
+            ..MaintenanceConfig::default()
+        };
+        let retention = RetentionConfig {
+            max_age_days: 30,
+            interval_minutes: 1,
+            ..RetentionConfig::default()
+        };
+        let mut scheduler = Scheduler::start(
+            &maintenance,
+            &retention,
+            &CampaignConfig::default(),
+            &ReportConfig::default(),
+        );
+
+        let mut due = Vec::new();
+        let end = Instant::now() + Duration::from_secs(55);
+        while let Ok(task) = tokio::time::timeout_at(end, scheduler.next()).await {
+            due.push(task);
+        }
+        let count = |task| due.iter().filter(|&&t| t == task).count();
+        assert_eq!(due[0], MaintenanceTask::Retention);
+        assert_eq!(count(MaintenanceTask::SessionCheck), 5);
+        assert_eq!(count(MaintenanceTask::HealthCheck), 2);
+        assert_eq!(count(MaintenanceTask::Retention), 1);
+        assert_eq!(count(MaintenanceTask::StatsLog), 0);
+        assert_eq!(count(MaintenanceTask::Campaigns), 0);
+        assert_eq!(count(MaintenanceTask::Reports), 0);
+
+        scheduler.shutdown().await;
+        let stopped = tokio::time::timeout(Duration::from_secs(3600), scheduler.next()).await;
+        assert!(stopped.is_err());
+    }
+}
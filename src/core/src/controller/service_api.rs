@@ -0,0 +1,127 @@
+//! Runtime management of the honeypot services.
+//!
+//! The [`Controller`](super::controller_handler::Controller) owns the network listener,
+//! so the other tasks, such as the web interface, change the services through a
+//! [`ServiceControl`] handle. Commands are queued until the controller runs, and each
+//! one carries the channel its outcome is sent back on.
+
+use std::time::Duration;
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::configuration::ServiceConfig;
+use crate::error_handling::types::ControllerError;
+
+/// Commands queued before the controller handles them
+const COMMAND_BUFFER: usize = 16;
+
+/// A change of the services requested to the controller
+#[derive(Debug)]
+pub enum ServiceCommand {
+    /// Lists the configured services, disabled ones included
+    List {
+        reply: oneshot::Sender<Vec<ServiceConfig>>,
+    },
+    /// Adds a service, bound right away when enabled
+    Add {
+        service: Box<ServiceConfig>,
+        reply: oneshot::Sender<Result<(), ControllerError>>,
+    },
+    /// Binds or unbinds a configured service
+    SetEnabled {
+        name: String,
+        enabled: bool,
+        reply: oneshot::Sender<Result<ServiceConfig, ControllerError>>,
+    },
+    /// Unbinds a service and removes it from the configuration
+    Remove {
+        name: String,
+        reply: oneshot::Sender<Result<ServiceConfig, ControllerError>>,
+    },
+    /// Unbinds every service, then stops once the connections closed or the
+    /// grace period, replied, elapsed
+    Drain { reply: oneshot::Sender<Duration> },
+    /// Re-reads the configuration file, as on SIGHUP, replying the number of
+    /// services configured afterwards
+    Reload {
+        reply: oneshot::Sender<Result<usize, ControllerError>>,
+    },
+}
+
+/// Cloneable handle sending [`ServiceCommand`]s to the controller
+#[derive(Debug, Clone)]
+pub struct ServiceControl {
+    sender: mpsc::Sender<ServiceCommand>,
+}
+
+impl ServiceControl {
+    /// Creates a handle and the receiver the controller reads its commands from
+    pub fn channel() -> (Self, mpsc::Receiver<ServiceCommand>) {
+        let (sender, receiver) = mpsc::channel(COMMAND_BUFFER);
+        (Self { sender }, receiver)
+    }
+
+    /// Configured services, in configuration order
+    pub async fn list(&self) -> Result<Vec<ServiceConfig>, ControllerError> {
+        self.request(|reply| ServiceCommand::List { reply }).await
+    }
+
+    /// See [`Controller::add_service`](super::controller_handler::Controller::add_service)
+    pub async fn add(&self, service: ServiceConfig) -> Result<(), ControllerError> {
+        self.request(|reply| ServiceCommand::Add {
+            service: Box::new(service),
+            reply,
+        })
+        .await?
+    }
+
+    /// See [`Controller::set_service_enabled`](super::controller_handler::Controller::set_service_enabled)
+    pub async fn set_enabled(
+        &self,
+        name: &str,
+        enabled: bool,
+    ) -> Result<ServiceConfig, ControllerError> {
+        self.request(|reply| ServiceCommand::SetEnabled {
+            name: name.to_string(),
+            enabled,
+            reply,
+        })
+        .await?
+    }
+
+    /// See [`Controller::remove_service`](super::controller_handler::Controller::remove_service)
+    pub async fn remove(&self, name: &str) -> Result<ServiceConfig, ControllerError> {
+        self.request(|reply| ServiceCommand::Remove {
+            name: name.to_string(),
+            reply,
+        })
+        .await?
+    }
+
+    /// See [`Controller::drain`](super::controller_handler::Controller::drain),
+    /// returns the grace period left to the connections
+    pub async fn drain(&self) -> Result<Duration, ControllerError> {
+        self.request(|reply| ServiceCommand::Drain { reply }).await
+    }
+
+    /// See [`Controller::reload_config`](super::controller_handler::Controller::reload_config),
+    /// returns the number of services configured after the reload
+    pub async fn reload(&self) -> Result<usize, ControllerError> {
+        self.request(|reply| ServiceCommand::Reload { reply })
+            .await?
+    }
+
+    async fn request<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<T>) -> ServiceCommand,
+    ) -> Result<T, ControllerError> {
+        let stopped =
+            || ControllerError::InitializationFailed("Controller is not running".to_string());
+        let (reply, response) = oneshot::channel();
+        self.sender
+            .send(command(reply))
+            .await
+            .map_err(|_| stopped())?;
+        response.await.map_err(|_| stopped())
+    }
+}
@@ -0,0 +1,227 @@
+//! Running miel as a systemd service.
+//!
+//! Under a `Type=notify` unit, systemd passes the path of a datagram socket in
+//! `NOTIFY_SOCKET` and waits for `READY=1` on it before starting the units
+//! ordered after miel. The controller reports when its listeners are bound,
+//! when it reloads its configuration on `SIGHUP` and when it stops. With
+//! `WatchdogSec` set, systemd also passes `WATCHDOG_USEC` and restarts the
+//! service when no `WATCHDOG=1` arrives within that time; the controller sends
+//! one every half of it from its main loop, so that a stuck loop is restarted.
+//!
+//! Outside systemd the variables are unset and nothing is sent.
+
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::{debug, warn};
+
+/// Sends `state`, newline separated `KEY=value` assignments, to the socket of
+/// `NOTIFY_SOCKET`, and returns whether it was sent
+pub fn notify(state: &str) -> bool {
+    let Ok(socket) = std::env::var("NOTIFY_SOCKET") else {
+        return false;
+    };
+    match notify_to(&socket, state) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("Failed to notify systemd on {}: {}", socket, e);
+            false
+        }
+    }
+}
+
+/// Tells systemd that the honeypot is serving
+pub fn ready(status: &str) -> bool {
+    notify(&format!("READY=1\nSTATUS={}", status))
+}
+
+/// Tells systemd that the configuration is being reloaded, [`ready`] ending the reload
+pub fn reloading() -> bool {
+    notify(&format!("RELOADING=1\nMONOTONIC_USEC={}", monotonic_usec()))
+}
+
+/// Tells systemd that the honeypot is shutting down
+pub fn stopping() -> bool {
+    notify("STOPPING=1")
+}
+
+fn notify_to(socket: &str, state: &str) -> io::Result<()> {
+    let sender = UnixDatagram::unbound()?;
+    match socket.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            sender.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "abstract sockets are only supported on Linux",
+            ))
+        }
+        None => {
+            sender.send_to(state.as_bytes(), socket)?;
+        }
+    }
+    debug!("Notified systemd: {}", state.replace('\n', " "));
+    Ok(())
+}
+
+/// `CLOCK_MONOTONIC` in microseconds, the clock of `MONOTONIC_USEC`
+fn monotonic_usec() -> u64 {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    now.tv_sec as u64 * 1_000_000 + now.tv_nsec as u64 / 1_000
+}
+
+/// Interval at which to ping the watchdog: half of `WATCHDOG_USEC`, when it is
+/// set and `WATCHDOG_PID` is unset or names this process
+fn watchdog_interval(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    if pid.is_some_and(|pid| pid.parse::<u32>().ok() != Some(own_pid)) {
+        return None;
+    }
+    let usec: u64 = usec?.parse().ok().filter(|usec| *usec > 0)?;
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Ticks at which the systemd watchdog is pinged, never when it is not enabled
+pub struct Watchdog {
+    interval: Option<tokio::time::Interval>,
+}
+
+impl Watchdog {
+    /// A watchdog set from `WATCHDOG_USEC` and `WATCHDOG_PID`
+    pub fn from_env() -> Self {
+        let usec = std::env::var("WATCHDOG_USEC").ok();
+        let pid = std::env::var("WATCHDOG_PID").ok();
+        let interval =
+            watchdog_interval(usec.as_deref(), pid.as_deref(), std::process::id()).map(|period| {
+                debug!("Pinging the systemd watchdog every {:?}", period);
+                let mut interval = tokio::time::interval(period);
+                interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                interval
+            });
+        Self { interval }
+    }
+
+    /// Waits for the next tick and pings the watchdog
+    pub async fn tick(&mut self) {
+        match self.interval.as_mut() {
+            Some(interval) => {
+                interval.tick().await;
+                notify("WATCHDOG=1");
+            }
+            None => std::future::pending::<()>().await,
+        }
+    }
+}
+
+/// File holding the PID of the running honeypot, removed when dropped
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Writes the PID of this process to `path`
+    ///
+    /// # Errors
+    /// Fails with [`ErrorKind::AlreadyExists`] when `path` holds the PID of a
+    /// running process, and with the errors of writing the file. A file left by
+    /// a process that no longer runs is replaced.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        if let Some(pid) = fs::read_to_string(path)
+            .ok()
+            .and_then(|content| content.trim().parse::<libc::pid_t>().ok())
+        {
+            if pid as u32 != std::process::id() && is_running(pid) {
+                return Err(io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{} belongs to running process {}", path.display(), pid),
+                ));
+            }
+        }
+        fs::write(path, format!("{}\n", std::process::id()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to remove PID file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Whether a process with `pid` exists, even one this process cannot signal
+fn is_running(pid: libc::pid_t) -> bool {
+    if pid <= 0 {
+        return false;
+    }
+    let signalled = unsafe { libc::kill(pid, 0) } == 0;
+    signalled || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchdog_pings_at_half_its_timeout_for_this_process() {
+        assert_eq!(
+            watchdog_interval(Some("30000000"), None, 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            watchdog_interval(Some("30000000"), Some("42"), 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(watchdog_interval(Some("30000000"), Some("7"), 42), None);
+        assert_eq!(watchdog_interval(Some("0"), None, 42), None);
+        assert_eq!(watchdog_interval(None, None, 42), None);
+    }
+
+    #[test]
+    fn notifications_reach_the_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        notify_to(path.to_str().unwrap(), "READY=1\nSTATUS=serving").unwrap();
+        let mut buf = [0u8; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1\nSTATUS=serving");
+    }
+
+    #[test]
+    fn pid_files_of_running_processes_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("miel.pid");
+
+        // PID 1 always runs
+        fs::write(&path, "1\n").unwrap();
+        let err = PidFile::create(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+
+        // A stale file is replaced, and removed with the PidFile
+        fs::write(&path, format!("{}\n", libc::pid_t::MAX)).unwrap();
+        let pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+}
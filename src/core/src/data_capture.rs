@@ -2,20 +2,50 @@
 //!
 //! This module groups the building blocks used to record honeypot sessions:
 //! - `tcp_capture`: full‑duplex TCP forwarding while recording bytes and timestamps
+//! - `udp_capture`: datagram relaying for one UDP client flow, recorded the same way
+//! - `credential_capture`: harvesting of login attempts from FTP, HTTP basic auth and sshd logs
+//! - `file_capture`: collection of the files and emails clients submitted to a service
+//! - `file_carving`: recovery of the files transferred inside the captured streams
+//! - `sftp_capture`: recovery of the files transferred over SFTP in the SSH channels
+//! - `http_capture`: splitting of HTTP streams into requests and their responses
+//! - `exploits`: detection of known attack patterns in the HTTP requests, e.g. log4shell
+//! - `yara`: scanning of the streams and files with YARA rules
+//! - `asciicast`: export of the stdio streams as an asciinema recording for terminal replay
+//! - `pcap`: export of the recorded network streams as a synthetic packet capture
+//! - `spool`: spilling of the recorded streams to chunk files past a threshold
 //! - `stdio_capture`: parse activity logs or snapshot a PTY into stdin/stdout/stderr streams
 //! - `storage`: trait to persist/retrieve capture artifacts
 //! - `recorder`: high‑level façade that orchestrates the above for one session
 //!
 //! Re‑exports: see the items below for quick access in downstream code.
 
+pub mod asciicast;
+pub mod credential_capture;
+pub mod exploits;
+pub mod file_capture;
+pub mod file_carving;
+pub mod http_capture;
+pub mod keystrokes;
+pub mod pcap;
 pub mod recorder;
+pub mod sftp_capture;
+pub mod spool;
 pub mod stdio_capture;
 pub mod storage;
 pub mod tcp_capture;
 pub mod types;
+pub mod udp_capture;
+pub mod yara;
 
 pub use recorder::StreamRecorder;
 pub use stdio_capture::StdioCapture;
 pub use storage::Storage;
 pub use tcp_capture::TcpCapture;
-pub use types::{CaptureArtifacts, Direction, StdioStream};
+pub use types::{
+    ByteCount, CaptureArtifacts, CapturedMessage, CarveSource, CarvedFile, Direction,
+    FlowEndpoints, HttpExchange, HttpRequest, HttpResponse, KeystrokeTiming, LastActivity,
+    LiveChunk, LiveFeed, LiveStream, LoginAttempt, OffloadedObject, PtyChunk, RuleMatch,
+    SftpTransfer, SshChannelCapture, SshChannelRequest, StdioStream, Termination, TlsMetadata,
+    TransferDirection, Transport, UploadedFile, VirusTotalReport,
+};
+pub use udp_capture::UdpCapture;
@@ -0,0 +1,213 @@
+//! Terminal replay: renders a session's stdio capture as an asciicast.
+//!
+//! The output follows the [asciicast v2] format used by asciinema: a JSON header
+//! line followed by one `[time, code, data]` event per recorded chunk, timed
+//! relative to the first chunk. Stdout and stderr become output (`"o"`) events and
+//! stdin becomes input (`"i"`) events, so `asciinema play` replays the session at
+//! the pace the attacker typed it.
+//!
+//! [asciicast v2]: https://docs.asciinema.org/manual/asciicast/v2/
+
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use uuid::Uuid;
+
+use super::types::{CaptureArtifacts, StdioStream};
+
+/// Terminal size announced in the header, the capture does not record it
+const TERMINAL_WIDTH: u16 = 80;
+const TERMINAL_HEIGHT: u16 = 24;
+
+/// Raw stdio bytes along with the timestamped chunk sizes describing them
+pub struct StdioRecording<'a> {
+    pub stdin: &'a [u8],
+    pub stdout: &'a [u8],
+    pub stderr: &'a [u8],
+    pub timestamps: &'a [(DateTime<Utc>, StdioStream, usize)],
+}
+
+/// Renders the stdio capture of `artifacts` as an asciicast.
+///
+/// Returns `None` when the session has no stdio capture to replay.
+pub fn to_asciicast(artifacts: &CaptureArtifacts) -> Option<String> {
+    render(
+        artifacts.session_id,
+        &StdioRecording {
+            stdin: artifacts.stdio_stdin.as_bytes(),
+            stdout: artifacts.stdio_stdout.as_bytes(),
+            stderr: artifacts.stdio_stderr.as_bytes(),
+            timestamps: &artifacts.stdio_timestamps,
+        },
+    )
+}
+
+/// Renders `recording` as an asciicast titled after `session_id`.
+///
+/// Returns `None` when no stdio chunk was recorded.
+pub fn render(session_id: Uuid, recording: &StdioRecording) -> Option<String> {
+    let mut chunks = recording.timestamps.to_vec();
+    chunks.sort_by_key(|(ts, _, _)| *ts);
+    let start = chunks.first()?.0;
+
+    let header = json!({
+        "version": 2,
+        "width": TERMINAL_WIDTH,
+        "height": TERMINAL_HEIGHT,
+        "timestamp": start.timestamp(),
+        "title": format!("miel session {}", session_id),
+    });
+    let mut cast = header.to_string();
+    cast.push('\n');
+
+    let mut streams = [
+        StreamCursor::new(recording.stdin),
+        StreamCursor::new(recording.stdout),
+        StreamCursor::new(recording.stderr),
+    ];
+    let mut last = start;
+
+    for (ts, stream, size) in chunks {
+        let text = streams[index(stream)].take(size);
+        push_event(&mut cast, elapsed(start, ts), stream, &text);
+        last = ts;
+    }
+
+    // Bytes not covered by a timestamp entry are flushed at the end of the replay
+    for stream in [StdioStream::Stdin, StdioStream::Stdout, StdioStream::Stderr] {
+        let text = streams[index(stream)].take(usize::MAX);
+        push_event(&mut cast, elapsed(start, last), stream, &text);
+    }
+
+    Some(cast)
+}
+
+fn index(stream: StdioStream) -> usize {
+    match stream {
+        StdioStream::Stdin => 0,
+        StdioStream::Stdout => 1,
+        StdioStream::Stderr => 2,
+    }
+}
+
+fn elapsed(start: DateTime<Utc>, ts: DateTime<Utc>) -> f64 {
+    (ts - start).num_microseconds().unwrap_or(0).max(0) as f64 / 1_000_000.0
+}
+
+fn push_event(cast: &mut String, time: f64, stream: StdioStream, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    let code = match stream {
+        StdioStream::Stdin => "i",
+        StdioStream::Stdout | StdioStream::Stderr => "o",
+    };
+    cast.push_str(&json!([time, code, text]).to_string());
+    cast.push('\n');
+}
+
+/// Reads one stream chunk by chunk without splitting UTF-8 characters
+struct StreamCursor<'a> {
+    data: &'a [u8],
+    pending: Vec<u8>,
+}
+
+impl<'a> StreamCursor<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Next `size` bytes as text; an incomplete trailing character is held back
+    /// for the following chunk
+    fn take(&mut self, size: usize) -> String {
+        let (chunk, rest) = self.data.split_at(size.min(self.data.len()));
+        self.data = rest;
+        self.pending.extend_from_slice(chunk);
+
+        let complete = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            Err(e) if e.error_len().is_none() && !self.data.is_empty() => e.valid_up_to(),
+            Err(_) => self.pending.len(),
+        };
+        let text = String::from_utf8_lossy(&self.pending[..complete]).to_string();
+        self.pending.drain(..complete);
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn artifacts(
+        stdin: &str,
+        stdout: &str,
+        timestamps: Vec<(DateTime<Utc>, StdioStream, usize)>,
+    ) -> CaptureArtifacts {
+        CaptureArtifacts {
+            session_id: Uuid::new_v4(),
+            stdio_stdin: stdin.to_string(),
+            stdio_stdout: stdout.to_string(),
+            stdio_timestamps: timestamps,
+            ..Default::default()
+        }
+    }
+
+    fn events(cast: &str) -> Vec<serde_json::Value> {
+        cast.lines()
+            .skip(1)
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn replays_chunks_with_relative_timing() {
+        let t0 = Utc::now();
+        let cast = to_asciicast(&artifacts(
+            "ls\n",
+            "$ a.txt\n",
+            vec![
+                (t0, StdioStream::Stdout, 2),
+                (t0 + Duration::milliseconds(1500), StdioStream::Stdin, 3),
+                (t0 + Duration::seconds(2), StdioStream::Stdout, 6),
+            ],
+        ))
+        .unwrap();
+
+        let header: serde_json::Value = serde_json::from_str(cast.lines().next().unwrap()).unwrap();
+        assert_eq!(header["version"], 2);
+        assert_eq!(header["timestamp"], t0.timestamp());
+
+        let events = events(&cast);
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0], json!([0.0, "o", "$ "]));
+        assert_eq!(events[1], json!([1.5, "i", "ls\n"]));
+        assert_eq!(events[2], json!([2.0, "o", "a.txt\n"]));
+    }
+
+    #[test]
+    fn keeps_characters_split_across_chunks() {
+        let t0 = Utc::now();
+        // "é" is two bytes, split between the first two chunks
+        let cast = to_asciicast(&artifacts(
+            "",
+            "é!",
+            vec![
+                (t0, StdioStream::Stdout, 1),
+                (t0 + Duration::seconds(1), StdioStream::Stdout, 2),
+            ],
+        ))
+        .unwrap();
+
+        let events = events(&cast);
+        assert_eq!(events, vec![json!([1.0, "o", "é!"])]);
+    }
+
+    #[test]
+    fn no_stdio_capture_has_nothing_to_replay() {
+        assert!(to_asciicast(&artifacts("", "", Vec::new())).is_none());
+    }
+}
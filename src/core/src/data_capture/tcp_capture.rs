@@ -2,20 +2,28 @@
 //!
 //! This module exposes [`TcpCapture`], which forwards data between a client and
 //! a container/service while buffering both directions and recording per‑chunk
-//! timestamps. It is used by the higher‑level `StreamRecorder` façade.
+//! timestamps. It is used by the higher‑level `StreamRecorder` façade. Both
+//! directions are [spooled](super::spool) to disk past a threshold.
 
 use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use chrono::{DateTime, Utc};
-use log::trace;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
+use tokio::sync::Notify;
 use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tracing::{debug, info, trace};
 use uuid::Uuid;
 
-use super::types::Direction;
+use super::spool::{Spool, SpoolSettings};
+use super::types::{ByteCount, Direction, LastActivity, LiveFeed, Termination};
+use crate::configuration::types::SessionQuota;
 use crate::error_handling::types::CaptureError;
+use crate::metrics;
 
 type TcpTimestamps = Vec<(DateTime<Utc>, Direction, usize)>;
 type TcpArtifacts = (Vec<u8>, Vec<u8>, TcpTimestamps);
@@ -24,9 +32,29 @@ type TcpArtifacts = (Vec<u8>, Vec<u8>, TcpTimestamps);
 /// Records TCP traffic for one session while acting as a transparent proxy.
 pub struct TcpCapture {
     pub(crate) session_id: Uuid,
-    pub(crate) client_to_container: Mutex<Vec<u8>>,
-    pub(crate) container_to_client: Mutex<Vec<u8>>,
+    pub(crate) client_to_container: Mutex<Spool>,
+    pub(crate) container_to_client: Mutex<Spool>,
     pub(crate) timestamps: Mutex<TcpTimestamps>,
+    /// Touched on each chunk forwarded in either direction.
+    pub(crate) last_activity: LastActivity,
+    /// Inactivity after which the proxied connection is closed, if any.
+    pub(crate) idle_timeout: Option<Duration>,
+    /// Limits after which the proxied connections are closed for good.
+    pub(crate) quota: SessionQuota,
+    /// When the capture was created, the start of the session for `max_duration_secs`.
+    pub(crate) started: Instant,
+    /// Bytes forwarded in both directions, across the connections of the session.
+    pub(crate) proxied: AtomicU64,
+    /// Set once a quota was exceeded, later connections being closed right away.
+    pub(crate) quota_exceeded: AtomicBool,
+    /// Wakes the proxy up when a direction exhausted the byte quota.
+    pub(crate) quota_reached: Notify,
+    /// Closes the proxied connections once the session is ended from outside.
+    pub(crate) termination: Termination,
+    /// Bytes forwarded in both directions, as the session reports them while it lasts.
+    pub(crate) transferred: ByteCount,
+    /// Sends the forwarded chunks to the watchers of the session.
+    pub(crate) live: LiveFeed,
 }
 
 impl TcpCapture {
@@ -34,30 +62,130 @@ impl TcpCapture {
     pub fn new(session_id: Uuid) -> Self {
         Self {
             session_id,
-            client_to_container: Mutex::new(Vec::new()),
-            container_to_client: Mutex::new(Vec::new()),
+            client_to_container: Mutex::new(Spool::in_memory()),
+            container_to_client: Mutex::new(Spool::in_memory()),
             timestamps: Mutex::new(Vec::new()),
+            last_activity: LastActivity::new(),
+            idle_timeout: None,
+            quota: SessionQuota::default(),
+            started: Instant::now(),
+            proxied: AtomicU64::new(0),
+            quota_exceeded: AtomicBool::new(false),
+            quota_reached: Notify::new(),
+            termination: Termination::new(),
+            transferred: ByteCount::new(),
+            live: LiveFeed::new(),
         }
     }
 
+    /// Spools both directions to disk as `settings` say, before any data was recorded.
+    pub fn with_spool(mut self, settings: &SpoolSettings) -> Self {
+        self.client_to_container =
+            Mutex::new(Spool::new(settings, self.session_id, "client_to_container"));
+        self.container_to_client =
+            Mutex::new(Spool::new(settings, self.session_id, "container_to_client"));
+        self
+    }
+
+    /// Closes the proxied connection once no data crossed it for `idle_timeout`.
+    pub fn with_idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Closes the proxied connection once the session forwarded `quota.max_bytes` or
+    /// lasted `quota.max_duration_secs`, and refuses the later ones.
+    pub fn with_quota(mut self, quota: SessionQuota) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    /// Closes the proxied connections when `termination` is signaled.
+    pub fn with_termination(mut self, termination: Termination) -> Self {
+        self.termination = termination;
+        self
+    }
+
+    /// Counts the forwarded bytes in `transferred`.
+    pub fn with_byte_count(mut self, transferred: ByteCount) -> Self {
+        self.transferred = transferred;
+        self
+    }
+
+    /// Sends the forwarded chunks to the watchers of `live`.
+    pub fn with_live_feed(mut self, live: LiveFeed) -> Self {
+        self.live = live;
+        self
+    }
+
+    /// Whether the proxy was closed for exceeding the quota of the session.
+    pub fn quota_exceeded(&self) -> bool {
+        self.quota_exceeded.load(Ordering::Relaxed)
+    }
+
+    /// When data last crossed the proxy, shared with whoever tracks the session.
+    pub fn last_activity(&self) -> LastActivity {
+        self.last_activity.clone()
+    }
+
     /// Forward data in both directions while recording bytes and timestamps.
     ///
     /// Behavior
     /// - Spawns two tasks (client→container and container→client).
     /// - Gracefully propagates EOF by shutting down the opposite writer.
     /// - Buffers payloads and pushes `(timestamp, direction, len)` entries.
+    /// - The client side can be any byte stream, e.g. a terminated TLS connection.
+    ///
+    /// - `greeting` is what the listener already sent to the client: it is recorded
+    ///   first and skipped when the container output repeats it, the output being
+    ///   forwarded whole once it diverges.
+    /// - With an idle timeout, both directions are dropped once no chunk was
+    ///   forwarded for that long, closing the connection.
+    /// - Past the quota, what is left of the byte budget is forwarded and both
+    ///   directions are dropped; the connection is not proxied at all once the quota
+    ///   was exceeded, see [`TcpCapture::quota_exceeded`].
+    /// - Once the session is terminated, both directions are dropped and later
+    ///   connections are not proxied.
     ///
     /// Errors
     /// - Returns [`CaptureError::TcpStreamError`] for I/O or task failures.
     pub async fn proxy_and_record(
         self: Arc<Self>,
-        client_stream: TcpStream,
+        client_stream: impl AsyncRead + AsyncWrite + Send + 'static,
         container_stream: TcpStream,
+        greeting: Vec<u8>,
     ) -> Result<(), CaptureError> {
-        let (cr, cw) = client_stream.into_split();
+        let (cr, cw) = tokio::io::split(client_stream);
         let (sr, sw) = container_stream.into_split();
 
+        if self.quota_exceeded() {
+            debug!(
+                "[{:?}] refusing tcp proxy past the session quota",
+                self.session_id
+            );
+            return Ok(());
+        }
+        if self.termination.is_terminated() {
+            debug!(
+                "[{:?}] refusing tcp proxy of the ended session",
+                self.session_id
+            );
+            return Ok(());
+        }
         trace!("[{:?}] starting tcp proxy", self.session_id);
+        self.last_activity.touch();
+
+        if !greeting.is_empty() {
+            self.container_to_client
+                .lock()
+                .unwrap()
+                .extend_from_slice(&greeting);
+            self.timestamps.lock().unwrap().push((
+                Utc::now(),
+                Direction::ContainerToClient,
+                greeting.len(),
+            ));
+        }
 
         let mut set = JoinSet::new();
 
@@ -70,7 +198,7 @@ impl TcpCapture {
                 let mut sw = sw; // forward to container writer
                 let mut buf = vec![0u8; 16 * 1024];
                 loop {
-                    let n = match cr.read(&mut buf).await {
+                    let mut n = match cr.read(&mut buf).await {
                         Ok(n) => n,
                         Err(e) => break Err(CaptureError::TcpStreamError(e)),
                     };
@@ -82,8 +210,13 @@ impl TcpCapture {
                         let _ = sw.shutdown().await; // signal EOF to container side
                         break Ok(());
                     }
-                    if let Err(e) = sw.write_all(&buf[..n]).await {
-                        break Err(CaptureError::TcpStreamError(e));
+                    let allowed = this.allow(n);
+                    let exhausted = allowed < n;
+                    n = allowed;
+                    if n > 0 {
+                        if let Err(e) = sw.write_all(&buf[..n]).await {
+                            break Err(CaptureError::TcpStreamError(e));
+                        }
                     }
                     // record and trace
                     {
@@ -94,6 +227,10 @@ impl TcpCapture {
                         let mut ts = this.timestamps.lock().unwrap();
                         ts.push((Utc::now(), Direction::ClientToContainer, n));
                     }
+                    this.last_activity.touch();
+                    this.transferred.add(n);
+                    this.live.publish(Direction::ClientToContainer, &buf[..n]);
+                    metrics::global().bytes_proxied(Direction::ClientToContainer, n);
                     let preview = &buf[..std::cmp::min(n, 64)];
                     trace!(
                         "[{:?}] captured C->S {} bytes: {}{}",
@@ -102,6 +239,9 @@ impl TcpCapture {
                         String::from_utf8_lossy(preview),
                         if n > 64 { " ..." } else { "" }
                     );
+                    if exhausted {
+                        break Ok(());
+                    }
                 }
             });
         }
@@ -114,11 +254,37 @@ impl TcpCapture {
                 let mut sr = sr;
                 let mut cw = cw; // forward to client writer
                 let mut buf = vec![0u8; 16 * 1024];
+                // Bytes of the greeting the container output matched so far
+                let mut greeted = if greeting.is_empty() { None } else { Some(0) };
                 loop {
-                    let n = match sr.read(&mut buf).await {
+                    let mut n = match sr.read(&mut buf).await {
                         Ok(n) => n,
                         Err(e) => break Err(CaptureError::TcpStreamError(e)),
                     };
+                    if let Some(matched) = greeted {
+                        let skip = greeting[matched..]
+                            .iter()
+                            .zip(&buf[..n])
+                            .take_while(|(sent, received)| sent == received)
+                            .count();
+                        if n > 0 && matched + skip == greeting.len() {
+                            buf.copy_within(skip..n, 0);
+                            n -= skip;
+                            greeted = None;
+                            if n == 0 {
+                                continue;
+                            }
+                        } else if n > 0 && skip == n {
+                            greeted = Some(matched + skip);
+                            continue;
+                        } else {
+                            // Another banner, or the end of the output: the bytes
+                            // held back are forwarded ahead of it
+                            buf.splice(0..0, greeting[..matched].iter().copied());
+                            n += matched;
+                            greeted = None;
+                        }
+                    }
                     if n == 0 {
                         trace!(
                             "[{:?}] S->C EOF; shutting down client writer",
@@ -127,8 +293,13 @@ impl TcpCapture {
                         let _ = cw.shutdown().await; // signal EOF to client side
                         break Ok(());
                     }
-                    if let Err(e) = cw.write_all(&buf[..n]).await {
-                        break Err(CaptureError::TcpStreamError(e));
+                    let allowed = this.allow(n);
+                    let exhausted = allowed < n;
+                    n = allowed;
+                    if n > 0 {
+                        if let Err(e) = cw.write_all(&buf[..n]).await {
+                            break Err(CaptureError::TcpStreamError(e));
+                        }
                     }
                     // record and trace
                     {
@@ -139,6 +310,10 @@ impl TcpCapture {
                         let mut ts = this.timestamps.lock().unwrap();
                         ts.push((Utc::now(), Direction::ContainerToClient, n));
                     }
+                    this.last_activity.touch();
+                    this.transferred.add(n);
+                    this.live.publish(Direction::ContainerToClient, &buf[..n]);
+                    metrics::global().bytes_proxied(Direction::ContainerToClient, n);
                     let preview = &buf[..std::cmp::min(n, 64)];
                     trace!(
                         "[{:?}] captured S->C {} bytes: {}{}",
@@ -147,22 +322,86 @@ impl TcpCapture {
                         String::from_utf8_lossy(preview),
                         if n > 64 { " ..." } else { "" }
                     );
+                    if exhausted {
+                        break Ok(());
+                    }
                 }
             });
         }
 
-        while let Some(res) = set.join_next().await {
-            res.map_err(|e| CaptureError::TcpStreamError(io::Error::other(e)))??;
+        let proxied = async {
+            while let Some(res) = set.join_next().await {
+                res.map_err(|e| CaptureError::TcpStreamError(io::Error::other(e)))??;
+            }
+            Ok::<(), CaptureError>(())
+        };
+        let idle_timeout = self.idle_timeout.unwrap_or_default();
+        let deadline = self
+            .quota
+            .max_duration_secs
+            .map(|secs| self.started + Duration::from_secs(secs));
+        tokio::select! {
+            res = proxied => res?,
+            _ = self.idle(idle_timeout), if self.idle_timeout.is_some() => {
+                debug!(
+                    "[{:?}] closing tcp proxy idle for {:?}",
+                    self.session_id, idle_timeout
+                );
+            }
+            _ = tokio::time::sleep_until(deadline.unwrap_or(self.started)), if deadline.is_some() => {
+                self.exceed("duration");
+            }
+            _ = self.quota_reached.notified() => {}
+            _ = self.termination.terminated() => {
+                debug!("[{:?}] closing tcp proxy of the ended session", self.session_id);
+            }
         }
 
         trace!("[{:?}] tcp proxy completed", self.session_id);
         Ok(())
     }
 
+    /// Takes `n` bytes from the byte quota, returning how many of them may be forwarded.
+    fn allow(&self, n: usize) -> usize {
+        let Some(max_bytes) = self.quota.max_bytes else {
+            return n;
+        };
+        let before = self.proxied.fetch_add(n as u64, Ordering::Relaxed);
+        let allowed = max_bytes.saturating_sub(before).min(n as u64) as usize;
+        if allowed < n {
+            self.exceed("byte");
+        }
+        allowed
+    }
+
+    /// Marks the session past its `quota` and wakes the proxy up to close it.
+    fn exceed(&self, quota: &str) {
+        if !self.quota_exceeded.swap(true, Ordering::Relaxed) {
+            info!(
+                "[{:?}] closing tcp proxy past the {} quota of the session",
+                self.session_id, quota
+            );
+        }
+        self.quota_reached.notify_one();
+    }
+
+    /// Resolves once no data was forwarded for `idle_timeout`.
+    async fn idle(&self, idle_timeout: Duration) {
+        loop {
+            let since = (Utc::now() - self.last_activity.get())
+                .to_std()
+                .unwrap_or_default();
+            if since >= idle_timeout {
+                return;
+            }
+            tokio::time::sleep(idle_timeout - since).await;
+        }
+    }
+
     /// Return copies of client→container, container→client, and timestamp log.
     pub fn get_artifacts(&self) -> TcpArtifacts {
-        let a = self.client_to_container.lock().unwrap().clone();
-        let b = self.container_to_client.lock().unwrap().clone();
+        let a = self.client_to_container.lock().unwrap().contents();
+        let b = self.container_to_client.lock().unwrap().contents();
         let t = self.timestamps.lock().unwrap().clone();
         (a, b, t)
     }
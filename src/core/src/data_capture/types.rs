@@ -2,9 +2,15 @@
 
 use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, Notify};
 use uuid::Uuid;
 
-/// Direction of TCP flow for captured bytes.
+use crate::container_management::persona::ContainerPersona;
+
+/// Direction of TCP or UDP flow for captured bytes.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Direction {
     /// Bytes flowing from the external client to the container/service.
@@ -24,10 +30,502 @@ pub enum StdioStream {
     Stderr,
 }
 
+/// Transport protocol of a captured flow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+/// Addressing of a captured flow, needed to rebuild packets on export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlowEndpoints {
+    pub transport: Transport,
+    /// Remote client address
+    pub client_addr: SocketAddr,
+    /// Honeypot address the client connected to
+    pub server_addr: SocketAddr,
+}
+
+/// TLS handshake parameters of a session whose TLS was terminated by the honeypot.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsMetadata {
+    /// Negotiated protocol version (e.g. "TLSv1_3")
+    pub version: String,
+    /// Negotiated cipher suite (e.g. "TLS13_AES_256_GCM_SHA384")
+    pub cipher_suite: String,
+    /// Server name requested by the client
+    pub sni: Option<String>,
+    /// Negotiated application protocol
+    pub alpn: Option<String>,
+    /// JA3 fingerprint string of the ClientHello
+    #[serde(default)]
+    pub ja3: Option<String>,
+    /// MD5 of `ja3`, the usual form to cluster clients on
+    #[serde(default)]
+    pub ja3_hash: Option<String>,
+}
+
+/// A file a client uploaded to the service, e.g. a malware sample dropped over FTP.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadedFile {
+    /// Name given by the client, without directories
+    pub name: String,
+    /// Hex encoded SHA-256 of `content`
+    pub sha256: String,
+    /// Hex encoded MD5 of `content`
+    #[serde(default)]
+    pub md5: String,
+    /// Hex encoded SHA-1 of `content`
+    #[serde(default)]
+    pub sha1: String,
+    /// Captured bytes, at most [`MAX_UPLOAD_LEN`](super::file_capture::MAX_UPLOAD_LEN)
+    pub content: Vec<u8>,
+    /// Set when the upload was larger than the capture limit and `content` was cut
+    pub truncated: bool,
+    /// Verdict of VirusTotal, once looked up
+    #[serde(default)]
+    pub virustotal: Option<VirusTotalReport>,
+}
+
+/// Detections of a file by the engines of VirusTotal, see
+/// [`VirusTotalClient`](crate::enrichment::virustotal::VirusTotalClient)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VirusTotalReport {
+    /// When VirusTotal was asked
+    pub checked_at: DateTime<Utc>,
+    /// Whether VirusTotal knew the file, the counts being zero otherwise
+    pub known: bool,
+    /// Engines flagging the file as malicious
+    pub malicious: u32,
+    /// Engines flagging the file as suspicious
+    pub suspicious: u32,
+    /// Engines finding nothing
+    pub undetected: u32,
+    /// Suggested threat label, e.g. `trojan.mirai/gafgyt`
+    pub label: Option<String>,
+}
+
+/// Where a [`CarvedFile`] was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CarveSource {
+    /// Body of an HTTP `PUT`, or file part of a `multipart/form-data` request
+    HttpUpload,
+    /// File sent to an `scp -t` sink typed in the shell
+    Scp,
+    /// Base64 blob typed in the shell, e.g. `echo f0VMRg... | base64 -d > x`
+    Base64,
+    /// `\xNN` escapes typed in the shell, e.g. `printf '\x7fELF...' > x`
+    HexEscape,
+    /// Executable printed to the terminal, e.g. by `wget -O-` or `curl`
+    TerminalOutput,
+}
+
+impl CarveSource {
+    pub const ALL: [CarveSource; 5] = [
+        CarveSource::HttpUpload,
+        CarveSource::Scp,
+        CarveSource::Base64,
+        CarveSource::HexEscape,
+        CarveSource::TerminalOutput,
+    ];
+
+    /// Name of the source, as in its JSON form
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CarveSource::HttpUpload => "http_upload",
+            CarveSource::Scp => "scp",
+            CarveSource::Base64 => "base64",
+            CarveSource::HexEscape => "hex_escape",
+            CarveSource::TerminalOutput => "terminal_output",
+        }
+    }
+
+    /// Source named `name`, see [`CarveSource::as_str`]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|source| source.as_str() == name)
+    }
+}
+
+/// A file recovered from a session's captured streams.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CarvedFile {
+    /// Name given by the client or the command, without directories, or a
+    /// generated `carved-<index>` when there was none
+    pub name: String,
+    pub source: CarveSource,
+    /// Hex encoded SHA-256 of `content`
+    pub sha256: String,
+    /// Hex encoded MD5 of `content`
+    #[serde(default)]
+    pub md5: String,
+    /// Hex encoded SHA-1 of `content`
+    #[serde(default)]
+    pub sha1: String,
+    /// Recovered bytes, at most [`MAX_UPLOAD_LEN`](super::file_capture::MAX_UPLOAD_LEN)
+    pub content: Vec<u8>,
+    /// Set when the file was larger than the capture limit, or its end was not captured
+    pub truncated: bool,
+    /// Same as [`UploadedFile::virustotal`]
+    #[serde(default)]
+    pub virustotal: Option<VirusTotalReport>,
+}
+
+/// Bytes read from the PTY master of a container, as they were written to the
+/// terminal.
+#[derive(Debug, Clone)]
+pub struct PtyChunk {
+    pub timestamp: DateTime<Utc>,
+    pub data: Arc<[u8]>,
+}
+
+/// When a session last exchanged data, shared between its capture and the session
+/// manager. Clones refer to the same timestamp.
+#[derive(Debug, Clone)]
+pub struct LastActivity(Arc<Mutex<DateTime<Utc>>>);
+
+impl LastActivity {
+    /// Starts at the current time
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(Utc::now())))
+    }
+
+    /// Records activity at the current time
+    pub fn touch(&self) {
+        *self.0.lock().unwrap() = Utc::now();
+    }
+
+    pub fn get(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap()
+    }
+}
+
+impl Default for LastActivity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Bytes a session forwarded so far in both directions, shared between its
+/// captures and the session manager. Clones refer to the same count.
+#[derive(Debug, Clone, Default)]
+pub struct ByteCount(Arc<AtomicU64>);
+
+impl ByteCount {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&self, n: usize) {
+        self.0.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Closes the proxies of a session for good when it is ended from outside, shared
+/// between its captures and the session manager. Clones refer to the same signal.
+#[derive(Debug, Clone, Default)]
+pub struct Termination(Arc<(AtomicBool, Notify)>);
+
+impl Termination {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Closes the running proxies of the session and refuses the later ones
+    pub fn terminate(&self) {
+        self.0 .0.store(true, Ordering::Relaxed);
+        self.0 .1.notify_waiters();
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.0 .0.load(Ordering::Relaxed)
+    }
+
+    /// Resolves once the session is terminated
+    pub async fn terminated(&self) {
+        let notified = self.0 .1.notified();
+        tokio::pin!(notified);
+        // Registered before the flag is checked, not to miss a termination in between
+        notified.as_mut().enable();
+        if !self.is_terminated() {
+            notified.await;
+        }
+    }
+}
+
+/// Chunks kept for each watcher of a [`LiveFeed`], a slower one missing the oldest
+const LIVE_FEED_CAPACITY: usize = 256;
+
+/// Bytes of a chunk sent to the watchers of a [`LiveFeed`]
+pub const LIVE_PREVIEW_LEN: usize = 4096;
+
+/// Stream a [`LiveChunk`] was recorded on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LiveStream {
+    ClientToContainer,
+    ContainerToClient,
+    Stdin,
+    Stdout,
+    Stderr,
+}
+
+impl From<Direction> for LiveStream {
+    fn from(direction: Direction) -> Self {
+        match direction {
+            Direction::ClientToContainer => LiveStream::ClientToContainer,
+            Direction::ContainerToClient => LiveStream::ContainerToClient,
+        }
+    }
+}
+
+impl From<StdioStream> for LiveStream {
+    fn from(stream: StdioStream) -> Self {
+        match stream {
+            StdioStream::Stdin => LiveStream::Stdin,
+            StdioStream::Stdout => LiveStream::Stdout,
+            StdioStream::Stderr => LiveStream::Stderr,
+        }
+    }
+}
+
+/// Preview of a chunk a session recorded, as sent to its watchers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveChunk {
+    pub timestamp: DateTime<Utc>,
+    pub stream: LiveStream,
+    /// Size of the recorded chunk, which may exceed the preview
+    pub len: usize,
+    /// First [`LIVE_PREVIEW_LEN`] bytes of the chunk, decoded as lossy UTF-8
+    pub preview: String,
+}
+
+/// Chunks recorded by a session as they cross its proxies and terminal, shared
+/// between its captures and the session manager, which hands it to the watchers
+/// of the session. Clones send to the same watchers.
+#[derive(Debug, Clone)]
+pub struct LiveFeed(broadcast::Sender<LiveChunk>);
+
+impl LiveFeed {
+    pub fn new() -> Self {
+        Self(broadcast::channel(LIVE_FEED_CAPACITY).0)
+    }
+
+    /// Sends a preview of `data`, recorded on `stream`, to the current watchers
+    pub fn publish(&self, stream: impl Into<LiveStream>, data: &[u8]) {
+        // Nothing is copied while nobody watches, which is most of the time
+        if self.0.receiver_count() == 0 {
+            return;
+        }
+        let preview = &data[..data.len().min(LIVE_PREVIEW_LEN)];
+        let _ = self.0.send(LiveChunk {
+            timestamp: Utc::now(),
+            stream: stream.into(),
+            len: data.len(),
+            preview: String::from_utf8_lossy(preview).into_owned(),
+        });
+    }
+
+    /// Receives the chunks recorded from now on, until the session ends
+    pub fn subscribe(&self) -> broadcast::Receiver<LiveChunk> {
+        self.0.subscribe()
+    }
+}
+
+impl Default for LiveFeed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An authentication attempt found in a session's captured streams or activity log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginAttempt {
+    /// When the attempt was received, if known
+    pub timestamp: Option<DateTime<Utc>>,
+    pub username: String,
+    /// `None` when the protocol does not reveal it, e.g. SSH
+    pub password: Option<String>,
+    /// Whether the service let the client in, `None` when its answer is unknown
+    pub accepted: Option<bool>,
+}
+
+/// An email a client submitted to the service, e.g. spam relayed over SMTP.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapturedMessage {
+    /// Name the client greeted the server with (HELO/EHLO)
+    pub helo: String,
+    /// Envelope sender, empty for bounces (`MAIL FROM:<>`)
+    pub mail_from: String,
+    /// Envelope recipients, in RCPT TO order
+    pub rcpt_to: Vec<String>,
+    /// When the end of the DATA section was received
+    pub received_at: DateTime<Utc>,
+    /// Message as submitted, headers included, at most
+    /// [`MAX_MESSAGE_LEN`](super::file_capture::MAX_MESSAGE_LEN)
+    pub content: Vec<u8>,
+    /// Set when the message was larger than the capture limit and `content` was cut
+    pub truncated: bool,
+}
+
+/// An HTTP request found in a session's client stream.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HttpRequest {
+    /// When the request line was received, if known
+    pub timestamp: Option<DateTime<Utc>>,
+    pub method: String,
+    /// Request target as sent, query string included
+    pub path: String,
+    pub version: String,
+    /// Header fields in the order they were sent, names as sent
+    pub headers: Vec<(String, String)>,
+    /// Decoded body as text, invalid UTF-8 replaced, at most
+    /// [`MAX_BODY_LEN`](super::http_capture::MAX_BODY_LEN) bytes
+    pub body: String,
+    /// Length of the decoded body before it was cut
+    pub body_len: usize,
+}
+
+/// An HTTP response found in a session's server stream.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HttpResponse {
+    /// When the status line was sent, if known
+    pub timestamp: Option<DateTime<Utc>>,
+    pub version: String,
+    pub status: u16,
+    pub reason: String,
+    /// Header fields in the order they were sent, names as sent
+    pub headers: Vec<(String, String)>,
+    /// Same as [`HttpRequest::body`]
+    pub body: String,
+    pub body_len: usize,
+}
+
+/// A request along with the response the service answered it with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HttpExchange {
+    pub request: HttpRequest,
+    /// `None` when the session ended before the service answered
+    pub response: Option<HttpResponse>,
+}
+
+/// A YARA rule that matched a stream or file of a session, see [`yara`](super::yara).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleMatch {
+    pub rule: String,
+    pub tags: Vec<String>,
+    /// What matched: `client_stream`, `server_stream`, `stdin`, `stdout`, `stderr`,
+    /// or `upload:<name>` and `carved:<name>` for files
+    pub target: String,
+    /// Identifiers of the strings found, e.g. `$magic`
+    pub strings: Vec<String>,
+}
+
+/// Typing cadence of the client of an interactive session, see
+/// [`keystrokes`](super::keystrokes).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeystrokeTiming {
+    /// Time of the first keystroke
+    pub started_at: DateTime<Utc>,
+    /// Keystrokes typed
+    pub count: usize,
+    /// Milliseconds from each keystroke to the next of the same run, in typing
+    /// order, at most [`MAX_INTERVALS`](super::keystrokes::MAX_INTERVALS)
+    pub intervals_ms: Vec<u64>,
+    /// Intervals longer than [`PAUSE_MS`](super::keystrokes::PAUSE_MS)
+    pub pauses: usize,
+    /// Median of the intervals that are not pauses
+    pub median_ms: u64,
+    /// Mean of the intervals that are not pauses
+    pub mean_ms: u64,
+    /// Standard deviation of the intervals that are not pauses, low for bots
+    pub stddev_ms: u64,
+}
+
+/// What the client of an SSH connection terminated by miel started in its session
+/// channel, see [`ssh`](crate::network::ssh).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SshChannelRequest {
+    /// Login shell, e.g. `ssh host`
+    Shell,
+    /// Single command, e.g. `ssh host 'uname -a'`
+    Exec { command: String },
+    /// Subsystem, `sftp` being the only one served
+    Subsystem { name: String },
+}
+
+/// A session channel of an SSH connection terminated by miel, recorded in
+/// plaintext.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SshChannelCapture {
+    /// When the shell, command or subsystem was started
+    pub started_at: DateTime<Utc>,
+    /// Identification line of the client, e.g. `SSH-2.0-OpenSSH_9.6`
+    pub client_version: String,
+    /// User the client logged in as
+    pub username: String,
+    pub request: SshChannelRequest,
+    /// Terminal type of the `pty-req`, `None` without a terminal
+    pub terminal: Option<String>,
+    /// Files read and written over SFTP, in opening order
+    pub sftp_transfers: Vec<SftpTransfer>,
+}
+
+/// Whether a file went to the service or came from it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+/// A file an SFTP client opened and read or wrote, see
+/// [`sftp_capture`](super::sftp_capture).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SftpTransfer {
+    /// Path as sent by the client
+    pub path: String,
+    pub direction: TransferDirection,
+    /// Bytes written, or read back by the client
+    pub bytes: u64,
+    /// Hex encoded SHA-256 of an upload, also found in the uploaded files
+    pub sha256: Option<String>,
+}
+
+/// Artifact content moved to an object store by the
+/// [`offloading_storage`](crate::storage::offloading_storage), the stored
+/// artifacts keeping the field emptied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OffloadedObject {
+    /// Emptied field: `tcp_client_to_container`, `tcp_container_to_client`, or
+    /// `uploaded_files/<i>`, `carved_files/<i>` and `messages/<i>` for the content
+    /// of the file or message at index `i`
+    pub field: String,
+    /// Object key in the bucket
+    pub key: String,
+    /// Bytes of the content
+    pub size: u64,
+    /// Hex encoded SHA-256 of the content, checked when it is fetched back
+    pub sha256: String,
+}
+
 /// Aggregated capture artifacts persisted after a session completes.
 /// TCP payloads are stored as raw bytes for protocol analysis,
 /// while STDIO streams are stored as UTF-8 text for readability.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// For TLS services the `tcp_*` fields hold the decrypted payload and `tls`
+/// describes the handshake.
+///
+/// UDP sessions reuse the `tcp_*` fields: datagram payloads are concatenated per
+/// direction and `tcp_timestamps` holds one entry per datagram.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CaptureArtifacts {
     /// The related session identifier
     pub session_id: Uuid,
@@ -49,4 +547,34 @@ pub struct CaptureArtifacts {
     pub total_bytes: u64,
     /// Total capture duration
     pub duration: Duration,
+    /// Flow addressing, `None` for artifacts recorded before it was tracked
+    #[serde(default)]
+    pub flow: Option<FlowEndpoints>,
+    /// Handshake parameters when TLS was terminated for the session
+    #[serde(default)]
+    pub tls: Option<TlsMetadata>,
+    /// Files uploaded through the service, in upload order
+    #[serde(default)]
+    pub uploaded_files: Vec<UploadedFile>,
+    /// Emails submitted through the service, in reception order
+    #[serde(default)]
+    pub messages: Vec<CapturedMessage>,
+    /// Files recovered from the streams, see [`file_carving`](super::file_carving)
+    #[serde(default)]
+    pub carved_files: Vec<CarvedFile>,
+    /// YARA rules matching the streams and files, in scan order
+    #[serde(default)]
+    pub rule_matches: Vec<RuleMatch>,
+    /// Typing cadence of the client, `None` when it did not type key by key
+    #[serde(default)]
+    pub keystrokes: Option<KeystrokeTiming>,
+    /// Channels of the SSH connections terminated by miel, in opening order
+    #[serde(default)]
+    pub ssh_channels: Vec<SshChannelCapture>,
+    /// Content held by the object store, empty unless offloading is configured
+    #[serde(default)]
+    pub offloaded: Vec<OffloadedObject>,
+    /// Identity drawn for the container of the session, when randomized
+    #[serde(default)]
+    pub persona: Option<ContainerPersona>,
 }
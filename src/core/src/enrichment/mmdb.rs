@@ -0,0 +1,398 @@
+//! Reader of MaxMind DB files, the format of the GeoIP2 and GeoLite2 databases.
+//!
+//! A database is a binary search tree over the bits of an address, whose leaves
+//! point into a data section of self-describing values, followed by a metadata
+//! map. See <https://maxmind.github.io/MaxMind-DB/>. Records are decoded into
+//! [`serde_json::Value`]s so that any database layout can be queried.
+
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+use super::{EnrichmentProvider, IpEnrichment, LookupFuture};
+
+/// Start of the metadata section, at the end of the file
+const METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+/// Bytes of zeros between the search tree and the data section
+const DATA_SECTION_SEPARATOR: usize = 16;
+/// Pointers followed while decoding one value, against looping files
+const MAX_DEPTH: usize = 32;
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid MaxMind DB: {}", reason),
+    )
+}
+
+/// An opened MaxMind database
+pub struct MmdbReader {
+    data: Vec<u8>,
+    node_count: usize,
+    record_size: usize,
+    ip_version: u64,
+    /// Size of the search tree in bytes
+    tree_size: usize,
+}
+
+impl MmdbReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::from_bytes(std::fs::read(path)?)
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> io::Result<Self> {
+        let marker = data
+            .windows(METADATA_MARKER.len())
+            .rposition(|w| w == METADATA_MARKER)
+            .ok_or_else(|| invalid("metadata marker not found"))?;
+        let metadata_start = marker + METADATA_MARKER.len();
+        let (metadata, _) = Decoder {
+            section: &data[metadata_start..],
+        }
+        .decode(0, 0)?;
+
+        let field = |name: &str| {
+            metadata
+                .get(name)
+                .and_then(Value::as_u64)
+                .ok_or_else(|| invalid(&format!("metadata lacks {}", name)))
+        };
+        let node_count = field("node_count")? as usize;
+        let record_size = field("record_size")? as usize;
+        let ip_version = field("ip_version")?;
+        if !matches!(record_size, 24 | 28 | 32) {
+            return Err(invalid("unsupported record size"));
+        }
+        let tree_size = node_count * record_size / 4;
+        if tree_size + DATA_SECTION_SEPARATOR > marker {
+            return Err(invalid("search tree exceeds the file"));
+        }
+
+        Ok(Self {
+            data,
+            node_count,
+            record_size,
+            ip_version,
+            tree_size,
+        })
+    }
+
+    /// Record of the network holding `ip`, `None` when the database has none
+    pub fn lookup(&self, ip: IpAddr) -> io::Result<Option<Value>> {
+        let (address, bit_count) = match (ip.to_canonical(), self.ip_version) {
+            (IpAddr::V4(v4), 4) => (u32::from(v4) as u128, 32),
+            // IPv4 networks are stored under ::/96 in IPv6 databases
+            (IpAddr::V4(v4), _) => (u32::from(v4) as u128, 128),
+            (IpAddr::V6(v6), 6) => (u128::from(v6), 128),
+            (IpAddr::V6(_), _) => return Ok(None),
+        };
+
+        let mut node = 0;
+        for i in 0..bit_count {
+            if node >= self.node_count {
+                break;
+            }
+            let bit = (address >> (bit_count - 1 - i)) & 1;
+            node = self.read_record(node, bit as usize)?;
+        }
+        if node <= self.node_count {
+            // node_count itself means no data, a lower value a truncated tree
+            return Ok(None);
+        }
+
+        let offset = node - self.node_count - DATA_SECTION_SEPARATOR;
+        let decoder = Decoder {
+            section: &self.data[self.tree_size + DATA_SECTION_SEPARATOR..],
+        };
+        decoder.decode(offset, 0).map(|(value, _)| Some(value))
+    }
+
+    fn read_record(&self, node: usize, bit: usize) -> io::Result<usize> {
+        let base = node * self.record_size / 4;
+        let bytes = self
+            .data
+            .get(base..base + self.record_size / 4)
+            .ok_or_else(|| invalid("node out of bounds"))?;
+        let be = |b: &[u8]| b.iter().fold(0usize, |acc, &b| (acc << 8) | b as usize);
+        Ok(match (self.record_size, bit) {
+            (24, 0) => be(&bytes[..3]),
+            (24, _) => be(&bytes[3..6]),
+            (28, 0) => ((bytes[3] as usize & 0xf0) << 20) | be(&bytes[..3]),
+            (28, _) => ((bytes[3] as usize & 0x0f) << 24) | be(&bytes[4..7]),
+            (_, 0) => be(&bytes[..4]),
+            (_, _) => be(&bytes[4..8]),
+        })
+    }
+}
+
+/// Decoder of the values of a data or metadata section
+struct Decoder<'a> {
+    section: &'a [u8],
+}
+
+impl Decoder<'_> {
+    fn bytes(&self, offset: usize, len: usize) -> io::Result<&[u8]> {
+        self.section
+            .get(offset..offset + len)
+            .ok_or_else(|| invalid("value out of bounds"))
+    }
+
+    fn uint(&self, offset: usize, len: usize) -> io::Result<u128> {
+        if len > 16 {
+            return Err(invalid("integer too large"));
+        }
+        Ok(self
+            .bytes(offset, len)?
+            .iter()
+            .fold(0u128, |acc, &b| (acc << 8) | b as u128))
+    }
+
+    /// Value at `offset`, and the offset following it
+    fn decode(&self, offset: usize, depth: usize) -> io::Result<(Value, usize)> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("too deeply nested values"));
+        }
+        let ctrl = self.bytes(offset, 1)?[0];
+        let mut pos = offset + 1;
+        let mut kind = ctrl >> 5;
+
+        if kind == 1 {
+            let high = (ctrl & 0x07) as usize;
+            let len = ((ctrl >> 3) & 0x03) as usize + 1;
+            let low = self.uint(pos, len)? as usize;
+            let target = match len {
+                1 => (high << 8) | low,
+                2 => ((high << 16) | low) + 2048,
+                3 => ((high << 24) | low) + 526_336,
+                _ => low,
+            };
+            let (value, _) = self.decode(target, depth + 1)?;
+            return Ok((value, pos + len));
+        }
+        if kind == 0 {
+            kind = 7 + self.bytes(pos, 1)?[0];
+            pos += 1;
+        }
+
+        let mut size = (ctrl & 0x1f) as usize;
+        if size >= 29 {
+            let len = size - 28;
+            let extra = self.uint(pos, len)? as usize;
+            pos += len;
+            size = match len {
+                1 => 29 + extra,
+                2 => 285 + extra,
+                _ => 65_821 + extra,
+            };
+        }
+
+        match kind {
+            2 => {
+                let s = std::str::from_utf8(self.bytes(pos, size)?)
+                    .map_err(|_| invalid("string is not UTF-8"))?;
+                Ok((Value::from(s), pos + size))
+            }
+            3 => {
+                let b: [u8; 8] = self
+                    .bytes(pos, 8)?
+                    .try_into()
+                    .map_err(|_| invalid("double size"))?;
+                Ok((Value::from(f64::from_be_bytes(b)), pos + 8))
+            }
+            4 => {
+                let b = self.bytes(pos, size)?;
+                Ok((Value::from(b.to_vec()), pos + size))
+            }
+            5 | 6 | 9 | 10 => {
+                let n = self.uint(pos, size)?;
+                let value =
+                    u64::try_from(n).map_or_else(|_| Value::from(n.to_string()), Value::from);
+                Ok((value, pos + size))
+            }
+            7 => {
+                let mut map = Map::new();
+                for _ in 0..size {
+                    let (key, next) = self.decode(pos, depth + 1)?;
+                    let Value::String(key) = key else {
+                        return Err(invalid("map key is not a string"));
+                    };
+                    let (value, next) = self.decode(next, depth + 1)?;
+                    map.insert(key, value);
+                    pos = next;
+                }
+                Ok((Value::Object(map), pos))
+            }
+            8 => {
+                let n = self.uint(pos, size.min(4))? as u32;
+                Ok((Value::from(n as i32), pos + size))
+            }
+            11 => {
+                let mut items = Vec::with_capacity(size.min(1024));
+                for _ in 0..size {
+                    let (value, next) = self.decode(pos, depth + 1)?;
+                    items.push(value);
+                    pos = next;
+                }
+                Ok((Value::Array(items), pos))
+            }
+            14 => Ok((Value::Bool(size != 0), pos)),
+            15 => {
+                let b: [u8; 4] = self
+                    .bytes(pos, 4)?
+                    .try_into()
+                    .map_err(|_| invalid("float size"))?;
+                Ok((Value::from(f32::from_be_bytes(b) as f64), pos + 4))
+            }
+            _ => Err(invalid("unsupported data type")),
+        }
+    }
+}
+
+/// Country and autonomous system from a GeoIP2 or GeoLite2 database
+pub struct GeoIpProvider {
+    name: String,
+    reader: MmdbReader,
+}
+
+impl GeoIpProvider {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            name: path.display().to_string(),
+            reader: MmdbReader::open(path)?,
+        })
+    }
+}
+
+impl EnrichmentProvider for GeoIpProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn lookup(&self, ip: IpAddr) -> LookupFuture<'_> {
+        let result = self.reader.lookup(ip).map(|record| {
+            let Some(record) = record else {
+                return IpEnrichment::default();
+            };
+            let text = |pointer: &str| {
+                record
+                    .pointer(pointer)
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+            };
+            IpEnrichment {
+                country_code: text("/country/iso_code")
+                    .or_else(|| text("/registered_country/iso_code")),
+                asn: record
+                    .pointer("/autonomous_system_number")
+                    .and_then(Value::as_u64)
+                    .and_then(|asn| u32::try_from(asn).ok()),
+                as_org: text("/autonomous_system_organization"),
+                abuse_score: None,
+            }
+        });
+        Box::pin(async move { result })
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Vec<u8> {
+        let mut out = match s.len() {
+            len @ 0..29 => vec![(2 << 5) | len as u8],
+            len => vec![(2 << 5) | 29, (len - 29) as u8],
+        };
+        out.extend_from_slice(s.as_bytes());
+        out
+    }
+
+    fn uint(kind: u8, n: u32) -> Vec<u8> {
+        let bytes: Vec<u8> = n
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|&b| b == 0)
+            .collect();
+        let mut out = vec![(kind << 5) | bytes.len() as u8];
+        out.extend(bytes);
+        out
+    }
+
+    fn map(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut out = vec![(7 << 5) | entries.len() as u8];
+        for (key, value) in entries {
+            out.extend(string(key));
+            out.extend_from_slice(value);
+        }
+        out
+    }
+
+    /// IPv6 database with 24 bit records holding `record` for one IPv4 network
+    pub(in crate::enrichment) fn database(
+        network: [u8; 4],
+        prefix_len: usize,
+        record: Vec<u8>,
+    ) -> Vec<u8> {
+        let address = u32::from_be_bytes(network) as u128;
+        let bits = 96 + prefix_len;
+        let node_count = bits;
+        let data_pointer = node_count + DATA_SECTION_SEPARATOR;
+
+        let mut file = Vec::new();
+        for i in 0..bits {
+            let bit = (address >> (127 - i)) & 1;
+            let next = if i + 1 == bits { data_pointer } else { i + 1 };
+            let (left, right) = if bit == 0 {
+                (next, node_count)
+            } else {
+                (node_count, next)
+            };
+            file.extend_from_slice(&(left as u32).to_be_bytes()[1..]);
+            file.extend_from_slice(&(right as u32).to_be_bytes()[1..]);
+        }
+        file.extend_from_slice(&[0; DATA_SECTION_SEPARATOR]);
+        file.extend(record);
+        file.extend_from_slice(METADATA_MARKER);
+        file.extend(map(&[
+            ("node_count", uint(6, node_count as u32)),
+            ("record_size", uint(5, 24)),
+            ("ip_version", uint(5, 6)),
+            ("database_type", string("Test")),
+        ]));
+        file
+    }
+
+    pub(in crate::enrichment) fn asn_record(asn: u32, org: &str) -> Vec<u8> {
+        map(&[
+            ("autonomous_system_number", uint(6, asn)),
+            ("autonomous_system_organization", string(org)),
+            ("country", map(&[("iso_code", string("NL"))])),
+        ])
+    }
+
+    #[test]
+    fn ipv4_networks_are_found_in_ipv6_databases() {
+        let reader =
+            MmdbReader::from_bytes(database([203, 0, 113, 0], 24, asn_record(64496, "Example")))
+                .unwrap();
+
+        let record = reader
+            .lookup("203.0.113.7".parse().unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(record["autonomous_system_number"], 64496);
+        assert_eq!(record["country"]["iso_code"], "NL");
+        assert!(reader
+            .lookup("203.0.114.7".parse().unwrap())
+            .unwrap()
+            .is_none());
+        assert!(reader
+            .lookup("2001:db8::1".parse().unwrap())
+            .unwrap()
+            .is_none());
+
+        assert!(MmdbReader::from_bytes(b"not a database".to_vec()).is_err());
+    }
+}
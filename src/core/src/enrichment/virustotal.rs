@@ -0,0 +1,300 @@
+//! Detections of the files captured in sessions, from the VirusTotal API.
+//!
+//! Once a session's capture is saved, [`VirusTotalClient::scan_session`] looks the
+//! SHA-256 of each uploaded and carved file up with the `files` endpoint, and saves
+//! the detection counts back into the artifacts as [`VirusTotalReport`]s. Files
+//! are never submitted: the ones VirusTotal does not know are reported as unknown.
+//!
+//! Lookups run in the background, one at a time and spaced to stay within the
+//! configured quota. Reports are cached per hash for a day, since the same samples
+//! are dropped by every bot of a botnet.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use log::{debug, info, warn};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::configuration::types::VirusTotalConfig;
+use crate::data_capture::VirusTotalReport;
+use crate::error_handling::types::StorageError;
+use crate::http_client::HttpEndpoint;
+use crate::storage::storage_trait::Storage;
+
+/// How long a report is reused for the same file
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Longest wait for one lookup
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize)]
+struct FileResponse {
+    data: FileObject,
+}
+
+#[derive(Deserialize)]
+struct FileObject {
+    attributes: FileAttributes,
+}
+
+#[derive(Deserialize)]
+struct FileAttributes {
+    #[serde(default)]
+    last_analysis_stats: AnalysisStats,
+    popular_threat_classification: Option<ThreatClassification>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct AnalysisStats {
+    malicious: u32,
+    suspicious: u32,
+    undetected: u32,
+}
+
+#[derive(Deserialize)]
+struct ThreatClassification {
+    suggested_threat_label: Option<String>,
+}
+
+/// Client of the VirusTotal `files` API, shared by all sessions
+pub struct VirusTotalClient {
+    config: VirusTotalConfig,
+    cache: Mutex<HashMap<String, (Instant, VirusTotalReport)>>,
+    /// When the next lookup may be sent
+    next_request: tokio::sync::Mutex<Instant>,
+}
+
+impl VirusTotalClient {
+    pub fn new(config: VirusTotalConfig) -> Self {
+        Self {
+            config,
+            cache: Mutex::new(HashMap::new()),
+            next_request: tokio::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Report of the file hashed `sha256`, waiting for the quota to allow it
+    pub async fn lookup(&self, sha256: &str) -> io::Result<VirusTotalReport> {
+        if let Some((at, cached)) = self.cache.lock().unwrap().get(sha256) {
+            if at.elapsed() < CACHE_TTL {
+                return Ok(cached.clone());
+            }
+        }
+
+        {
+            let mut next_request = self.next_request.lock().await;
+            tokio::time::sleep_until((*next_request).into()).await;
+            *next_request =
+                Instant::now() + Duration::from_secs(60) / self.config.requests_per_minute.max(1);
+        }
+        let url = format!("{}/{}", self.config.url.trim_end_matches('/'), sha256);
+        let endpoint = HttpEndpoint::parse(&url)?;
+        let headers = [
+            ("x-apikey", self.config.api_key.as_str()),
+            ("Accept", "application/json"),
+        ];
+        let report = match tokio::time::timeout(LOOKUP_TIMEOUT, endpoint.get(&headers)).await {
+            Ok(Ok(body)) => {
+                let response: FileResponse = serde_json::from_slice(&body)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                let attributes = response.data.attributes;
+                VirusTotalReport {
+                    checked_at: Utc::now(),
+                    known: true,
+                    malicious: attributes.last_analysis_stats.malicious,
+                    suspicious: attributes.last_analysis_stats.suspicious,
+                    undetected: attributes.last_analysis_stats.undetected,
+                    label: attributes
+                        .popular_threat_classification
+                        .and_then(|classification| classification.suggested_threat_label),
+                }
+            }
+            Ok(Err(e)) if e.kind() == io::ErrorKind::NotFound => VirusTotalReport {
+                checked_at: Utc::now(),
+                known: false,
+                malicious: 0,
+                suspicious: 0,
+                undetected: 0,
+                label: None,
+            },
+            Ok(Err(e)) => return Err(e),
+            Err(_) => return Err(io::Error::new(io::ErrorKind::TimedOut, "lookup timed out")),
+        };
+        debug!("VirusTotal report of {}: {:?}", sha256, report);
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (at, _)| at.elapsed() < CACHE_TTL);
+        cache.insert(sha256.to_string(), (Instant::now(), report.clone()));
+        Ok(report)
+    }
+
+    /// Looks up the files of the session not looked up yet and saves their reports
+    /// in its artifacts, returning how many files got one. A failed lookup is
+    /// logged and the file left without report.
+    pub async fn scan_session(
+        &self,
+        storage: &(dyn Storage + Send + Sync),
+        session_id: Uuid,
+    ) -> Result<usize, StorageError> {
+        let mut artifacts = storage.get_capture_artifacts(session_id).await?;
+        let mut hashes: Vec<&str> = artifacts
+            .uploaded_files
+            .iter()
+            .filter(|file| file.virustotal.is_none())
+            .map(|file| file.sha256.as_str())
+            .chain(
+                artifacts
+                    .carved_files
+                    .iter()
+                    .filter(|file| file.virustotal.is_none())
+                    .map(|file| file.sha256.as_str()),
+            )
+            .collect();
+        hashes.sort_unstable();
+        hashes.dedup();
+
+        let mut reports = HashMap::new();
+        for sha256 in hashes {
+            match self.lookup(sha256).await {
+                Ok(report) => {
+                    if report.malicious > 0 {
+                        info!(
+                            "File {} of session {} is flagged by {} engines ({})",
+                            sha256,
+                            session_id,
+                            report.malicious,
+                            report.label.as_deref().unwrap_or("no label")
+                        );
+                    }
+                    reports.insert(sha256.to_string(), report);
+                }
+                Err(e) => warn!("VirusTotal lookup of {} failed: {}", sha256, e),
+            }
+        }
+        if reports.is_empty() {
+            return Ok(0);
+        }
+
+        let mut scanned = 0;
+        for (sha256, virustotal) in artifacts
+            .uploaded_files
+            .iter_mut()
+            .map(|file| (&file.sha256, &mut file.virustotal))
+            .chain(
+                artifacts
+                    .carved_files
+                    .iter_mut()
+                    .map(|file| (&file.sha256, &mut file.virustotal)),
+            )
+        {
+            if virustotal.is_none() {
+                *virustotal = reports.get(sha256.as_str()).cloned();
+                scanned += usize::from(virustotal.is_some());
+            }
+        }
+        storage.save_capture_artifacts(&artifacts).await?;
+        Ok(scanned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_capture::{CaptureArtifacts, CarveSource, CarvedFile, UploadedFile};
+    use crate::storage::file_storage::FileStorage;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn upload(name: &str, sha256: &str) -> UploadedFile {
+        UploadedFile {
+            name: name.to_string(),
+            sha256: sha256.to_string(),
+            md5: String::new(),
+            sha1: String::new(),
+            content: name.as_bytes().to_vec(),
+            truncated: false,
+            virustotal: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_are_saved_in_the_artifacts() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api/v3/files", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let request = String::from_utf8_lossy(&request).to_string();
+                let response = if request.starts_with("GET /api/v3/files/aaaa ") {
+                    let body = r#"{"data":{"attributes":{"last_analysis_stats":{"malicious":40,"suspicious":0,"undetected":21,"harmless":0},"popular_threat_classification":{"suggested_threat_label":"trojan.mirai/gafgyt"}}}}"#;
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                } else {
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string()
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let storage = FileStorage::new(dir.path()).unwrap();
+        let session_id = Uuid::new_v4();
+        storage
+            .save_capture_artifacts(&CaptureArtifacts {
+                session_id,
+                duration: chrono::Duration::seconds(1),
+                uploaded_files: vec![upload("bot", "aaaa"), upload("new", "bbbb")],
+                carved_files: vec![CarvedFile {
+                    name: "carved-0".to_string(),
+                    source: CarveSource::Base64,
+                    sha256: "aaaa".to_string(),
+                    md5: String::new(),
+                    sha1: String::new(),
+                    content: b"bot".to_vec(),
+                    truncated: false,
+                    virustotal: None,
+                }],
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let client = VirusTotalClient::new(VirusTotalConfig {
+            api_key: "secret".to_string(),
+            url,
+            requests_per_minute: 6000,
+        });
+        assert_eq!(client.scan_session(&storage, session_id).await.unwrap(), 3);
+
+        let artifacts = storage.get_capture_artifacts(session_id).await.unwrap();
+        let known = artifacts.uploaded_files[0].virustotal.as_ref().unwrap();
+        assert!(known.known);
+        assert_eq!((known.malicious, known.undetected), (40, 21));
+        assert_eq!(known.label.as_deref(), Some("trojan.mirai/gafgyt"));
+        let unknown = artifacts.uploaded_files[1].virustotal.as_ref().unwrap();
+        assert!(!unknown.known);
+        // The same sample is looked up once
+        assert_eq!(artifacts.carved_files[0].virustotal.as_ref(), Some(known));
+
+        let requests = server.await.unwrap();
+        assert!(requests[0].contains("\r\nx-apikey: secret\r\n"));
+        // Files already looked up are skipped
+        assert_eq!(client.scan_session(&storage, session_id).await.unwrap(), 0);
+    }
+}
@@ -1,182 +1,252 @@
-use std::fmt;
+//! Error types of the subsystems.
+//!
+//! Each subsystem has its own enum, wrapping the errors of the subsystems it
+//! relies on as their [`source`](std::error::Error::source) so that the whole
+//! chain can be reported. [`Context`] adds what the controller was doing when an
+//! error reached it.
 
-#[derive(Debug)]
+use thiserror::Error;
+
+#[derive(Debug, Error)]
 pub enum WebError {
+    #[error("Web request failed")]
     RequestFailed,
+    #[error("Web server start failed: {0}")]
     StartFailed(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Error)]
 pub enum ConfigError {
-    IoError(std::io::Error),
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("TOML parsing error: {0}")]
     TomlError(String),
+    #[error("Services configuration error: {0}")]
     ServicesEmpty(String),
+    #[error("IP formatting error: {0}")]
     BadIPFormatting(String),
+    #[error("Port range error: {0}")]
     BadPortsRange(String),
+    #[error("Directory error: {0}")]
     DirectoryDoesNotExist(String),
+    #[error("Value out of range: {0}")]
     NotInRange(String),
-}
-
-impl fmt::Display for ConfigError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            ConfigError::IoError(e) => write!(f, "IO error: {}", e),
-            ConfigError::TomlError(e) => write!(f, "TOML parsing error: {}", e),
-            ConfigError::ServicesEmpty(e) => write!(f, "Services configuration error: {}", e),
-            ConfigError::BadIPFormatting(e) => write!(f, "IP formatting error: {}", e),
-            ConfigError::BadPortsRange(e) => write!(f, "Port range error: {}", e),
-            ConfigError::DirectoryDoesNotExist(e) => write!(f, "Directory error: {}", e),
-            ConfigError::NotInRange(e) => write!(f, "Value out of range: {}", e),
-        }
-    }
-}
-
-impl std::error::Error for ConfigError {}
-
-#[derive(Debug)]
+    #[error("TLS configuration error: {0}")]
+    TlsConfig(String),
+    #[error("SSH configuration error: {0}")]
+    SshConfig(String),
+    #[error("Event sink configuration error: {0}")]
+    EventSinkConfig(String),
+    #[error("Notification configuration error: {0}")]
+    NotificationConfig(String),
+    #[error("Enrichment configuration error: {0}")]
+    EnrichmentConfig(String),
+    #[error("Database configuration error: {0}")]
+    DatabaseConfig(String),
+    #[error("Web UI configuration error: {0}")]
+    WebUiConfig(String),
+    #[error("Forwarding configuration error: {0}")]
+    ForwardingConfig(String),
+    #[error("Control API configuration error: {0}")]
+    ControlConfig(String),
+    #[error("Offload configuration error: {0}")]
+    OffloadConfig(String),
+    #[error("STIX configuration error: {0}")]
+    StixConfig(String),
+    #[error("Report configuration error: {0}")]
+    ReportConfig(String),
+    #[error("Encryption configuration error: {0}")]
+    EncryptionConfig(String),
+    #[error("Header pattern error: {0}")]
+    HeaderPattern(String),
+    #[error("Tagging configuration error: {0}")]
+    TaggingConfig(String),
+    #[error("Honeytoken configuration error: {0}")]
+    HoneytokenConfig(String),
+    #[error("Persona pack error: {0}")]
+    PersonaPack(String),
+    #[error("Container runtime error: {0}")]
+    RuntimeConfig(String),
+    #[error("Obfuscation configuration error: {0}")]
+    ObfuscationConfig(String),
+    #[error("Configuration include error: {0}")]
+    IncludeConfig(String),
+    #[error("Configuration profile error: {0}")]
+    ProfileConfig(String),
+    #[error("Tarpit configuration error: {0}")]
+    TarpitConfig(String),
+    #[error("Logging configuration error: {0}")]
+    LoggingConfig(String),
+}
+
+#[derive(Debug, Error)]
 pub enum SessionError {
+    #[error("Session creation failed")]
     CreationFailed,
-    ContainerError(ContainerError),
-    StorageError(StorageError),
-    CaptureError(CaptureError),
+    #[error("Container error: {0}")]
+    ContainerError(#[from] ContainerError),
+    #[error("Storage error: {0}")]
+    StorageError(#[from] StorageError),
+    #[error("Capture error: {0}")]
+    CaptureError(#[from] CaptureError),
+    #[error("Session not found")]
     NotFound,
+    /// The listener detected a service that is no longer configured, e.g. removed
+    /// by a reload while its connection was queued
+    #[error("No service is configured under the name {0}")]
+    UnknownService(String),
+    #[error("Session limit reached")]
     SessionLimitReached,
+    #[error("Session registry is not running")]
+    RegistryStopped,
 }
 
-impl fmt::Display for SessionError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            SessionError::CreationFailed => write!(f, "Session creation failed"),
-            SessionError::ContainerError(e) => write!(f, "Container error: {}", e),
-            SessionError::StorageError(e) => write!(f, "Storage error: {}", e),
-            SessionError::CaptureError(e) => write!(f, "Capture error: {}", e),
-            SessionError::NotFound => write!(f, "Session not found"),
-            SessionError::SessionLimitReached => write!(f, "Session limit reached"),
-        }
-    }
-}
-
-impl std::error::Error for SessionError {}
-
-#[derive(Debug)]
+#[derive(Debug, Error)]
 pub enum ContainerError {
+    #[error("Container runtime not available")]
     RuntimeNotAvailable,
+    #[error("Container creation failed: {0}")]
     CreationFailed(String),
+    #[error("Container start failed: {0}")]
     StartFailed(String),
-    IoError(std::io::Error),
+    #[error("Container IO error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Container process error: {0}")]
     ProcessError(String),
+    #[error("Insufficient privileges for container operations")]
     InsufficientPrivileges,
+    #[error("Container connection failed: {0}")]
     ConnectionFailed(String),
 }
 
-impl fmt::Display for ContainerError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            ContainerError::RuntimeNotAvailable => write!(f, "Container runtime not available"),
-            ContainerError::CreationFailed(e) => write!(f, "Container creation failed: {}", e),
-            ContainerError::StartFailed(e) => write!(f, "Container start failed: {}", e),
-            ContainerError::IoError(e) => write!(f, "Container IO error: {}", e),
-            ContainerError::ProcessError(e) => write!(f, "Container process error: {}", e),
-            ContainerError::InsufficientPrivileges => {
-                write!(f, "Insufficient privileges for container operations")
-            }
-            ContainerError::ConnectionFailed(e) => write!(f, "Container connection failed: {}", e),
-        }
-    }
-}
-
-impl std::error::Error for ContainerError {}
-
-impl From<std::io::Error> for ContainerError {
-    fn from(err: std::io::Error) -> Self {
-        ContainerError::IoError(err)
-    }
-}
-
-#[derive(Debug)]
+#[derive(Debug, Error)]
 pub enum NetworkError {
-    BindError(std::io::Error),
+    #[error("Network bind error: {0}")]
+    BindError(#[source] std::io::Error),
+    #[error("Network channel failed")]
     ChannelFailed,
-    SockError(std::io::Error),
+    #[error("Socket error: {0}")]
+    SockError(#[source] std::io::Error),
+    #[error("Connection failed")]
     ConnectionFailed,
+    #[error("Service detection failed")]
     ServiceDetectionFailed,
-    BindFail(std::io::Error),
-}
-
-impl fmt::Display for NetworkError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            NetworkError::BindError(e) => write!(f, "Network bind error: {}", e),
-            NetworkError::ChannelFailed => write!(f, "Network channel failed"),
-            NetworkError::SockError(e) => write!(f, "Socket error: {}", e),
-            NetworkError::ConnectionFailed => write!(f, "Connection failed"),
-            NetworkError::ServiceDetectionFailed => write!(f, "Service detection failed"),
-            NetworkError::BindFail(e) => write!(f, "Bind failed: {}", e),
-        }
-    }
-}
-
-impl std::error::Error for NetworkError {}
-
-#[derive(Debug)]
+    #[error("Bind failed: {0}")]
+    BindFail(#[source] std::io::Error),
+    #[error("TLS error: {0}")]
+    TlsError(String),
+    #[error("SSH error: {0}")]
+    SshError(String),
+    #[error("PROXY protocol error: {0}")]
+    ProxyProtocol(String),
+    #[error("Transparent redirect failed: {0}")]
+    RedirectFailed(String),
+}
+
+#[derive(Debug, Error)]
 pub enum StorageError {
+    #[error("Storage connection failed")]
     ConnectionFailed,
+    #[error("Storage write failed")]
     WriteFailed,
+    #[error("Storage read failed")]
     ReadFailed,
+    #[error("Nothing stored")]
+    NotFound,
 }
 
-impl fmt::Display for StorageError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            StorageError::ConnectionFailed => write!(f, "Storage connection failed"),
-            StorageError::WriteFailed => write!(f, "Storage write failed"),
-            StorageError::ReadFailed => write!(f, "Storage read failed"),
-        }
-    }
+#[derive(Debug, Error)]
+pub enum IngestError {
+    #[error("Session {0} was not created by this agent")]
+    NotOwned(uuid::Uuid),
+    #[error("Ingest storage error: {0}")]
+    StorageError(#[from] StorageError),
 }
 
-impl std::error::Error for StorageError {}
-
-#[derive(Debug)]
+#[derive(Debug, Error)]
 pub enum CaptureError {
-    TcpStreamError(std::io::Error),
-    StdioError(std::io::Error),
-    StorageError(StorageError),
-}
-
-impl fmt::Display for CaptureError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            CaptureError::TcpStreamError(e) => write!(f, "TCP stream capture error: {}", e),
-            CaptureError::StdioError(e) => write!(f, "Stdio capture error: {}", e),
-            CaptureError::StorageError(e) => write!(f, "Capture storage error: {}", e),
-        }
-    }
-}
-
-impl std::error::Error for CaptureError {}
-
-#[derive(Debug)]
+    #[error("TCP stream capture error: {0}")]
+    TcpStreamError(#[source] std::io::Error),
+    #[error("UDP socket capture error: {0}")]
+    UdpSocketError(#[source] std::io::Error),
+    #[error("Stdio capture error: {0}")]
+    StdioError(#[source] std::io::Error),
+    #[error("Uploaded file capture error: {0}")]
+    UploadError(#[source] std::io::Error),
+    #[error("Received message capture error: {0}")]
+    MessageError(#[source] std::io::Error),
+    #[error("Capture storage error: {0}")]
+    StorageError(#[from] StorageError),
+    #[error("Rule scan error: {0}")]
+    RuleScanError(#[source] tokio::task::JoinError),
+}
+
+#[derive(Debug, Error)]
 pub enum ControllerError {
-    ConfigurationError(ConfigError),
-    NetworkError(NetworkError),
-    SessionError(SessionError),
-    ContainerError(ContainerError),
-    StorageError(StorageError),
+    #[error("Configuration error: {0}")]
+    ConfigurationError(#[from] ConfigError),
+    #[error("Network error: {0}")]
+    NetworkError(#[from] NetworkError),
+    #[error("Session error: {0}")]
+    SessionError(#[from] SessionError),
+    #[error("Container error: {0}")]
+    ContainerError(#[from] ContainerError),
+    #[error("Storage error: {0}")]
+    StorageError(#[from] StorageError),
+    #[error("Initialization failed: {0}")]
     InitializationFailed(String),
+    #[error("Unknown service: {0}")]
+    UnknownService(String),
+    #[error("Service conflict: {0}")]
+    ServiceConflict(String),
+    #[error("Replay failed: {0}")]
+    ReplayFailed(String),
+    /// `source` failed while the controller was doing `context`
+    #[error("{context}: {source}")]
+    Context {
+        context: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+/// Adds what the controller was doing to the error of a result, see
+/// [`ControllerError::Context`]
+pub trait Context<T> {
+    fn context(self, context: impl Into<String>) -> Result<T, ControllerError>;
+}
+
+impl<T, E> Context<T> for Result<T, E>
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    fn context(self, context: impl Into<String>) -> Result<T, ControllerError> {
+        self.map_err(|e| ControllerError::Context {
+            context: context.into(),
+            source: Box::new(e),
+        })
+    }
 }
 
-impl fmt::Display for ControllerError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            ControllerError::ConfigurationError(e) => write!(f, "Configuration error: {}", e),
-            ControllerError::NetworkError(e) => write!(f, "Network error: {}", e),
-            ControllerError::SessionError(e) => write!(f, "Session error: {}", e),
-            ControllerError::ContainerError(e) => write!(f, "Container error: {}", e),
-            ControllerError::StorageError(e) => write!(f, "Storage error: {}", e),
-            ControllerError::InitializationFailed(e) => write!(f, "Initialization failed: {}", e),
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn errors_keep_their_source_and_context() {
+        let io = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied");
+        let failed: Result<(), _> = Err(SessionError::from(ContainerError::from(io)));
+        let e = failed.context("Cannot start the session").unwrap_err();
+
+        assert_eq!(
+            e.to_string(),
+            "Cannot start the session: Container error: Container IO error: denied"
+        );
+        let session = e.source().unwrap();
+        assert!(session.downcast_ref::<SessionError>().is_some());
+        let container = session.source().unwrap();
+        assert_eq!(container.to_string(), "Container IO error: denied");
+        assert_eq!(container.source().unwrap().to_string(), "denied");
     }
 }
-
-impl std::error::Error for ControllerError {}
@@ -0,0 +1,524 @@
+//! Structured event log for SIEM ingestion.
+//!
+//! Subsystems report what happens to a session as [`Event`]s through [`emit`]. Each
+//! event is serialized as one JSON object carrying a `timestamp` and an `event` name,
+//! logged under the `miel::events` target, and forwarded to the sink configured in
+//! the `[events]` section ([`EventSinkConfig`]) once [`install`] was called.
+//!
+//! Delivery is best effort: a background task writes to the sink, and events are
+//! dropped rather than stalling sessions when the sink falls behind or is down.
+//!
+//! The web UI follows events live through [`subscribe`], which also hands over the
+//! last [`RECENT_EVENTS`] ones.
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Mutex, OnceLock};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket, UnixDatagram};
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::{self, Sender};
+use uuid::Uuid;
+
+use crate::configuration::types::{EventSinkConfig, HoneytokenKind};
+use crate::data_capture::exploits::ExploitKind;
+use crate::http_client::{self, HttpEndpoint};
+use crate::notifier;
+use crate::SessionStatus;
+
+/// Events waiting for the sink before new ones get dropped
+const QUEUE_SIZE: usize = 1024;
+const SYSLOG_SOCKET: &str = "/dev/log";
+/// Facility local0, severity informational
+const SYSLOG_PRIORITY: u8 = 134;
+
+/// Events kept for the live subscribers joining late
+pub const RECENT_EVENTS: usize = 100;
+/// Events buffered for a live subscriber before it lags and misses some
+const LIVE_BUFFER: usize = 256;
+
+static QUEUE: OnceLock<Sender<String>> = OnceLock::new();
+static LIVE: OnceLock<Live> = OnceLock::new();
+
+struct Live {
+    sender: broadcast::Sender<String>,
+    recent: Mutex<VecDeque<String>>,
+}
+
+fn live() -> &'static Live {
+    LIVE.get_or_init(|| Live {
+        sender: broadcast::channel(LIVE_BUFFER).0,
+        recent: Mutex::new(VecDeque::with_capacity(RECENT_EVENTS)),
+    })
+}
+
+/// Something worth reporting to a SIEM
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A container was spawned for a new client
+    SessionStarted {
+        session_id: Uuid,
+        service: String,
+        client_addr: SocketAddr,
+        container_id: Option<String>,
+    },
+    /// The listener matched a connection to a service
+    ServiceDetected {
+        client_addr: SocketAddr,
+        port: u16,
+        service: String,
+        /// Server name the TLS client asked for
+        sni: Option<String>,
+    },
+    /// A command line read from a container activity log
+    CommandExecuted {
+        session_id: Uuid,
+        /// Service tag of the activity log line (e.g. `SSH`)
+        source: String,
+        command: String,
+    },
+    /// A client tried to log in to a service, e.g. over telnet or FTP
+    LoginAttempt {
+        session_id: Uuid,
+        service: String,
+        client_ip: Option<IpAddr>,
+        username: String,
+        /// Unknown for SSH
+        password: Option<String>,
+        accepted: Option<bool>,
+    },
+    SessionEnded {
+        session_id: Uuid,
+        service: String,
+        client_addr: SocketAddr,
+        status: SessionStatus,
+        bytes_transferred: u64,
+        duration_secs: i64,
+    },
+    /// No container could be spawned for a client, or it stopped during the session
+    ContainerFailed {
+        service: String,
+        client_addr: SocketAddr,
+        error: String,
+    },
+    /// A connection exceeded a rate limit and was dropped or tarpitted
+    ConnectionRateLimited {
+        client_addr: SocketAddr,
+        port: u16,
+        reason: String,
+        action: String,
+    },
+    /// A connection to a tarpit service was closed, see [`crate::network::tarpit`]
+    TarpitConnection {
+        service: String,
+        client_addr: SocketAddr,
+        port: u16,
+        duration_secs: u64,
+        bytes_sent: u64,
+        bytes_received: u64,
+        /// First bytes the client sent, lossily decoded
+        payload: String,
+    },
+    /// A source connected to many ports, recorded as a `portscan` pseudo-session,
+    /// see [`crate::network::scan_detector`]
+    PortScan {
+        session_id: Uuid,
+        client_addr: IpAddr,
+        /// Distinct ports connected to, in ascending order
+        ports: Vec<u16>,
+        connections: u64,
+        duration_secs: i64,
+    },
+    /// A client uploaded a file to a service, e.g. a sample dropped over FTP
+    FileUploaded {
+        session_id: Uuid,
+        service: String,
+        name: String,
+        sha256: String,
+        size: usize,
+        truncated: bool,
+    },
+    /// A client submitted an email to a service, e.g. spam relayed over SMTP
+    MessageReceived {
+        session_id: Uuid,
+        service: String,
+        helo: String,
+        mail_from: String,
+        rcpt_to: Vec<String>,
+        size: usize,
+        truncated: bool,
+    },
+    /// A YARA rule matched a stream or file captured in a session
+    RuleMatched {
+        session_id: Uuid,
+        service: String,
+        rule: String,
+        tags: Vec<String>,
+        /// Stream or file that matched, see [`RuleMatch`](crate::data_capture::RuleMatch)
+        target: String,
+        strings: Vec<String>,
+    },
+    /// An HTTP request matched a known attack pattern, see
+    /// [`crate::data_capture::exploits`]
+    ExploitAttempted {
+        session_id: Uuid,
+        service: String,
+        kind: ExploitKind,
+        method: String,
+        path: String,
+        /// `path`, `body` or `header:<name>`
+        location: String,
+        evidence: String,
+    },
+    /// The value of a honeytoken showed up in a capture, see [`crate::honeytokens`]
+    HoneytokenTriggered {
+        session_id: Uuid,
+        service: String,
+        kind: HoneytokenKind,
+        /// Path the token was planted at
+        path: String,
+        /// Stream or file the value was found in, named as for `rule_matched`
+        target: String,
+        /// Container the token was planted in
+        container_id: Option<String>,
+        /// Session given that container, `session_id` itself when the token
+        /// was read in the session it was planted for
+        planted_session_id: Option<Uuid>,
+    },
+    /// Packets a container tried to send against its egress policy
+    EgressBlocked {
+        container_id: String,
+        service: String,
+        packets: u64,
+    },
+    /// Stored sessions were deleted by the retention policy
+    RetentionApplied {
+        /// Past the maximum age
+        expired: usize,
+        /// Oldest over the maximum number of stored sessions
+        over_count: usize,
+        /// Oldest over the disk quota
+        over_disk: usize,
+        /// Bytes still taken by the storage
+        storage_bytes: u64,
+    },
+}
+
+/// How urgently an event calls for an analyst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Normal,
+    High,
+}
+
+impl Priority {
+    fn is_normal(&self) -> bool {
+        *self == Priority::Normal
+    }
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    timestamp: DateTime<Utc>,
+    /// Only set for high-priority events
+    #[serde(skip_serializing_if = "Priority::is_normal")]
+    priority: Priority,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+impl Event {
+    pub fn priority(&self) -> Priority {
+        match self {
+            Event::HoneytokenTriggered { .. } => Priority::High,
+            _ => Priority::Normal,
+        }
+    }
+
+    /// One line JSON form, timestamped now
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&Record {
+            timestamp: Utc::now(),
+            priority: self.priority(),
+            event: self,
+        })
+        .unwrap_or_default()
+    }
+}
+
+/// Reports `event` to the log, the configured sink and the webhook notifier
+pub fn emit(event: Event) {
+    notifier::observe(&event);
+    let line = event.to_json();
+    debug!(target: "miel::events", "{}", line);
+
+    let live = live();
+    if let Ok(mut recent) = live.recent.lock() {
+        if recent.len() == RECENT_EVENTS {
+            recent.pop_front();
+        }
+        recent.push_back(line.clone());
+        // Sent under the lock so that subscribers get each event once
+        let _ = live.sender.send(line.clone());
+    }
+
+    if let Some(queue) = QUEUE.get() {
+        if queue.try_send(line).is_err() {
+            debug!("Event sink is falling behind, dropping event");
+        }
+    }
+}
+
+/// Follows the emitted events, returned as JSON lines with the most recent ones, oldest first
+pub fn subscribe() -> (Vec<String>, broadcast::Receiver<String>) {
+    let live = live();
+    let recent = live.recent.lock().unwrap_or_else(|e| e.into_inner());
+    (recent.iter().cloned().collect(), live.sender.subscribe())
+}
+
+/// Opens the sink described by `config` and starts forwarding emitted events to it.
+///
+/// Only the first installed sink is used for the lifetime of the process.
+///
+/// # Errors
+/// Returns the IO error raised while opening a file or syslog sink, or
+/// [`io::ErrorKind::InvalidInput`] for a malformed HTTP(S) url.
+pub async fn install(config: &EventSinkConfig) -> io::Result<()> {
+    let Some(mut sink) = Sink::open(config).await? else {
+        return Ok(());
+    };
+
+    let (tx, mut rx) = mpsc::channel::<String>(QUEUE_SIZE);
+    if QUEUE.set(tx).is_err() {
+        warn!("Event sink already installed, ignoring {:?}", config);
+        return Ok(());
+    }
+    info!("Structured events are sent to {:?}", config);
+
+    tokio::spawn(async move {
+        while let Some(line) = rx.recv().await {
+            if let Err(e) = sink.send(&line).await {
+                error!("Failed to deliver event: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+enum Sink {
+    File(File),
+    UnixSyslog(UnixDatagram),
+    UdpSyslog(UdpSocket),
+    /// Connected on first use, and again after a write failure
+    Tcp {
+        address: String,
+        stream: Option<TcpStream>,
+    },
+    Http(HttpEndpoint),
+}
+
+impl Sink {
+    async fn open(config: &EventSinkConfig) -> io::Result<Option<Self>> {
+        let sink = match config {
+            EventSinkConfig::None => return Ok(None),
+            EventSinkConfig::File { path } => Sink::File(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await?,
+            ),
+            EventSinkConfig::Syslog { address: None } => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(SYSLOG_SOCKET)?;
+                Sink::UnixSyslog(socket)
+            }
+            EventSinkConfig::Syslog {
+                address: Some(address),
+            } => {
+                let remote = tokio::net::lookup_host(address)
+                    .await?
+                    .next()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, address.clone()))?;
+                let local: SocketAddr = if remote.is_ipv4() {
+                    ([0, 0, 0, 0], 0).into()
+                } else {
+                    ([0u16; 8], 0).into()
+                };
+                let socket = UdpSocket::bind(local).await?;
+                socket.connect(remote).await?;
+                Sink::UdpSyslog(socket)
+            }
+            EventSinkConfig::Tcp { address } => Sink::Tcp {
+                address: address.clone(),
+                stream: None,
+            },
+            EventSinkConfig::Http { url } => Sink::Http(HttpEndpoint::parse(url)?),
+        };
+        Ok(Some(sink))
+    }
+
+    async fn send(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            Sink::File(file) => {
+                file.write_all(format!("{}\n", line).as_bytes()).await?;
+                file.flush().await
+            }
+            Sink::UnixSyslog(socket) => {
+                socket.send(syslog_message(line).as_bytes()).await.map(drop)
+            }
+            Sink::UdpSyslog(socket) => socket.send(syslog_message(line).as_bytes()).await.map(drop),
+            Sink::Tcp { address, stream } => {
+                let result = http_client::bounded(address, async {
+                    if stream.is_none() {
+                        *stream = Some(TcpStream::connect(address.as_str()).await?);
+                    }
+                    match stream.as_mut() {
+                        Some(connected) => {
+                            connected.write_all(format!("{}\n", line).as_bytes()).await
+                        }
+                        None => Ok(()),
+                    }
+                })
+                .await;
+                if result.is_err() {
+                    *stream = None;
+                }
+                result
+            }
+            Sink::Http(endpoint) => endpoint.post_json(line).await,
+        }
+    }
+}
+
+/// RFC 5424 message carrying the JSON event as its payload
+fn syslog_message(line: &str) -> String {
+    format!(
+        "<{}>1 {} - miel {} - - {}",
+        SYSLOG_PRIORITY,
+        Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        std::process::id(),
+        line
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    fn session_started() -> Event {
+        Event::SessionStarted {
+            session_id: Uuid::nil(),
+            service: "ssh".to_string(),
+            client_addr: "203.0.113.7:51234".parse().unwrap(),
+            container_id: Some("miel-ssh-1".to_string()),
+        }
+    }
+
+    #[test]
+    fn events_serialize_as_flat_json() {
+        let value: serde_json::Value = serde_json::from_str(&session_started().to_json()).unwrap();
+
+        assert_eq!(value["event"], "session_started");
+        assert_eq!(value["service"], "ssh");
+        assert_eq!(value["client_addr"], "203.0.113.7:51234");
+        assert!(value["timestamp"].is_string());
+        assert!(value.get("priority").is_none());
+
+        let triggered = Event::HoneytokenTriggered {
+            session_id: Uuid::nil(),
+            service: "ssh".to_string(),
+            kind: HoneytokenKind::AwsKey,
+            path: "/root/.aws/credentials".to_string(),
+            target: "stdout".to_string(),
+            container_id: Some("miel-ssh-1".to_string()),
+            planted_session_id: Some(Uuid::nil()),
+        };
+        let value: serde_json::Value = serde_json::from_str(&triggered.to_json()).unwrap();
+        assert_eq!(value["event"], "honeytoken_triggered");
+        assert_eq!(value["priority"], "high");
+        assert_eq!(value["kind"], "aws_key");
+    }
+
+    #[tokio::test]
+    async fn subscribers_get_recent_and_live_events() {
+        // Other tests emit too, so events are told apart by their session id
+        let (_, mut early) = subscribe();
+        let session_id = Uuid::new_v4();
+        emit(Event::CommandExecuted {
+            session_id,
+            source: "SSH".to_string(),
+            command: "uname -a".to_string(),
+        });
+
+        let id = session_id.to_string();
+        loop {
+            match early.recv().await {
+                Ok(line) if line.contains(&id) => break,
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(e) => panic!("Live channel closed: {}", e),
+            }
+        }
+
+        let (recent, _) = subscribe();
+        assert!(recent.len() <= RECENT_EVENTS);
+        assert!(recent.iter().any(|line| line.contains(&id)));
+    }
+
+    #[tokio::test]
+    async fn file_sink_appends_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let mut sink = Sink::open(&EventSinkConfig::File { path: path.clone() })
+            .await
+            .unwrap()
+            .unwrap();
+
+        sink.send(&session_started().to_json()).await.unwrap();
+        sink.send(&session_started().to_json()).await.unwrap();
+
+        let content = std::fs::read_to_string(path).unwrap();
+        assert_eq!(content.lines().count(), 2);
+        assert!(content.lines().all(|l| l.contains("\"session_started\"")));
+    }
+
+    #[tokio::test]
+    async fn http_sink_posts_each_event() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/ingest", listener.local_addr().unwrap());
+        let collector = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.ends_with(b"}") {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&request).to_string()
+        });
+
+        let mut sink = Sink::open(&EventSinkConfig::Http { url })
+            .await
+            .unwrap()
+            .unwrap();
+        sink.send("{\"event\":\"session_ended\"}").await.unwrap();
+
+        let request = collector.await.unwrap();
+        assert!(request.starts_with("POST /ingest HTTP/1.1\r\n"));
+        assert!(request.ends_with("\r\n\r\n{\"event\":\"session_ended\"}"));
+    }
+}
@@ -0,0 +1,445 @@
+//! Minimal HTTP/1.1 client posting JSON to collectors and webhooks, querying
+//! threat intelligence APIs, and exchanging objects with S3-compatible stores.
+//!
+//! Events and notifications are small and infrequent, so each request opens its own
+//! connection (`Connection: close`) and only the response status line is read.
+//! Lookups read the whole response, with a bounded size.
+//! `https://` endpoints are verified against the bundled Mozilla root certificates.
+//! Each request, from the connection to the response, is given up after the
+//! [timeout](set_timeout) of outbound requests.
+
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+/// Seconds an outbound request may take, see [`set_timeout`]
+static TIMEOUT_SECS: AtomicU64 = AtomicU64::new(30);
+
+/// Gives up the requests made from now on after `timeout`, see
+/// `Config::outbound_timeout_secs`
+pub fn set_timeout(timeout: Duration) {
+    TIMEOUT_SECS.store(timeout.as_secs().max(1), Ordering::Relaxed);
+}
+
+/// How long an outbound request may take
+pub(crate) fn timeout() -> Duration {
+    Duration::from_secs(TIMEOUT_SECS.load(Ordering::Relaxed))
+}
+
+/// Runs `request` to `host`, failing with [`io::ErrorKind::TimedOut`] once it
+/// took longer than [`timeout`]
+pub(crate) async fn bounded<T>(
+    host: &str,
+    request: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    let limit = timeout();
+    tokio::time::timeout(limit, request).await.map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("{} did not answer within {}s", host, limit.as_secs()),
+        )
+    })?
+}
+
+/// Endpoint parsed from an `http://` or `https://` url
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpEndpoint {
+    tls: bool,
+    /// Host name, without port, sent as `Host` and used for certificate checks
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl HttpEndpoint {
+    /// Parses `url`, which must use the `http` or `https` scheme
+    pub fn parse(url: &str) -> io::Result<Self> {
+        let invalid =
+            |reason: &str| io::Error::new(io::ErrorKind::InvalidInput, reason.to_string());
+
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(invalid("only http:// and https:// urls are supported"));
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse::<u16>()
+                    .map_err(|_| invalid("invalid port in url"))?,
+            ),
+            None => (authority, if tls { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            return Err(invalid("missing host in url"));
+        }
+
+        Ok(Self {
+            tls,
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// POSTs `body` as JSON, failing unless the endpoint answers with a 2xx status
+    pub async fn post_json(&self, body: &str) -> io::Result<()> {
+        self.post_json_with_headers(body, &[]).await
+    }
+
+    /// Same as [`HttpEndpoint::post_json`], sending the extra `headers`
+    pub async fn post_json_with_headers(
+        &self,
+        body: &str,
+        headers: &[(&str, &str)],
+    ) -> io::Result<()> {
+        bounded(&self.host, async {
+            let stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+            if self.tls {
+                let server_name = ServerName::try_from(self.host.clone())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                let stream = connector().connect(server_name, stream).await?;
+                self.exchange(stream, body, headers).await
+            } else {
+                self.exchange(stream, body, headers).await
+            }
+        })
+        .await
+    }
+
+    /// GETs the endpoint with the extra `headers`, returning the body of a 2xx response.
+    /// A 404 fails with [`io::ErrorKind::NotFound`], APIs answering it for unknown keys.
+    pub async fn get(&self, headers: &[(&str, &str)]) -> io::Result<Vec<u8>> {
+        bounded(&self.host, async {
+            let stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+            if self.tls {
+                let server_name = ServerName::try_from(self.host.clone())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                let stream = connector().connect(server_name, stream).await?;
+                self.fetch(stream, headers).await
+            } else {
+                self.fetch(stream, headers).await
+            }
+        })
+        .await
+    }
+
+    /// Sends a `method` request for `path`, relative to the host of the endpoint,
+    /// with the extra `headers` and `body`, returning the status code and body of
+    /// the response whatever the status. Responses longer than `max_len` fail with
+    /// [`io::ErrorKind::InvalidData`].
+    pub async fn send(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+        max_len: usize,
+    ) -> io::Result<(u16, Vec<u8>)> {
+        bounded(&self.host, async {
+            let stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+            if self.tls {
+                let server_name = ServerName::try_from(self.host.clone())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                let stream = connector().connect(server_name, stream).await?;
+                self.roundtrip(stream, method, path, headers, body, max_len)
+                    .await
+            } else {
+                self.roundtrip(stream, method, path, headers, body, max_len)
+                    .await
+            }
+        })
+        .await
+    }
+
+    /// Host name of the endpoint, as sent in the `Host` header
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Path of the endpoint, `/` when the url has none
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    async fn fetch<S>(&self, stream: S, headers: &[(&str, &str)]) -> io::Result<Vec<u8>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let (status, body) = self
+            .roundtrip(stream, "GET", &self.path, headers, &[], MAX_RESPONSE_LEN)
+            .await?;
+        if !(200..300).contains(&status) {
+            let kind = if status == 404 {
+                io::ErrorKind::NotFound
+            } else {
+                io::ErrorKind::Other
+            };
+            return Err(io::Error::new(
+                kind,
+                format!("{} answered {}", self.host, status),
+            ));
+        }
+        Ok(body)
+    }
+
+    async fn roundtrip<S>(
+        &self,
+        mut stream: S,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+        max_len: usize,
+    ) -> io::Result<(u16, Vec<u8>)>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+            method, path, self.host
+        );
+        if !body.is_empty() || method == "PUT" || method == "POST" {
+            request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+        stream.write_all(body).await?;
+        stream.flush().await?;
+
+        let mut response = Vec::new();
+        (&mut stream)
+            .take(max_len as u64 + 1)
+            .read_to_end(&mut response)
+            .await?;
+        if response.len() > max_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} sent a response over {} bytes", self.host, max_len),
+            ));
+        }
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response");
+        let header_end = response
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .ok_or_else(invalid)?;
+        let head = String::from_utf8_lossy(&response[..header_end]).to_string();
+        let body = &response[header_end + 4..];
+
+        let status_line = head.lines().next().unwrap_or_default();
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok())
+            .ok_or_else(invalid)?;
+        let chunked = head.lines().any(|line| {
+            line.split_once(':').is_some_and(|(name, value)| {
+                name.trim().eq_ignore_ascii_case("transfer-encoding")
+                    && value.trim().eq_ignore_ascii_case("chunked")
+            })
+        });
+        if chunked {
+            Ok((status, decode_chunked(body).ok_or_else(invalid)?))
+        } else {
+            Ok((status, body.to_vec()))
+        }
+    }
+
+    async fn exchange<S>(
+        &self,
+        mut stream: S,
+        body: &str,
+        headers: &[(&str, &str)],
+    ) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.path,
+            self.host,
+            body.len(),
+        );
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
+        request.push_str(body);
+        stream.write_all(request.as_bytes()).await?;
+        stream.flush().await?;
+
+        // Only the status line matters
+        let mut response = Vec::new();
+        let mut buf = [0u8; 512];
+        while !response.contains(&b'\n') {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            response.extend_from_slice(&buf[..n]);
+        }
+        let status_line = String::from_utf8_lossy(&response);
+        let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+        if status.starts_with('2') {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!(
+                "{} answered {}",
+                self.host,
+                status_line.lines().next().unwrap_or_default()
+            )))
+        }
+    }
+}
+
+/// Largest response read by [`HttpEndpoint::get`], headers included
+const MAX_RESPONSE_LEN: usize = 1024 * 1024;
+
+/// Body of a `Transfer-Encoding: chunked` response, trailers ignored
+fn decode_chunked(mut data: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = data.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&data[..line_end]).ok()?;
+        let size = size.split(';').next()?.trim();
+        let size = usize::from_str_radix(size, 16).ok()?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Some(body);
+        }
+        body.extend_from_slice(data.get(..size)?);
+        data = data.get(size + 2..)?;
+    }
+}
+
+/// TLS connector trusting the webpki root certificates, built once
+pub(crate) fn connector() -> TlsConnector {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    let config = CONFIG.get_or_init(|| {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .expect("ring supports the default protocol versions")
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Arc::new(config)
+    });
+    TlsConnector::from(config.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn parses_scheme_port_and_path() {
+        let endpoint = HttpEndpoint::parse("https://hooks.example.org/services/T0/B1").unwrap();
+        assert!(endpoint.tls);
+        assert_eq!(endpoint.host, "hooks.example.org");
+        assert_eq!(endpoint.port, 443);
+        assert_eq!(endpoint.path, "/services/T0/B1");
+
+        let endpoint = HttpEndpoint::parse("http://127.0.0.1:8088").unwrap();
+        assert_eq!((endpoint.port, endpoint.path.as_str()), (8088, "/"));
+
+        assert!(HttpEndpoint::parse("ftp://example.org/").is_err());
+        assert!(HttpEndpoint::parse("http://:80/").is_err());
+    }
+
+    #[tokio::test]
+    async fn posts_json_and_checks_status() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/ingest", listener.local_addr().unwrap());
+        let collector = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for status in ["204 No Content", "500 Internal Server Error"] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.ends_with(b"}") {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let response = format!("HTTP/1.1 {}\r\n\r\n", status);
+                stream.write_all(response.as_bytes()).await.unwrap();
+                requests.push(String::from_utf8_lossy(&request).to_string());
+            }
+            requests
+        });
+
+        let endpoint = HttpEndpoint::parse(&url).unwrap();
+        endpoint.post_json("{\"ok\":true}").await.unwrap();
+        assert!(endpoint.post_json("{\"ok\":false}").await.is_err());
+
+        let requests = collector.await.unwrap();
+        assert!(requests[0].starts_with("POST /ingest HTTP/1.1\r\n"));
+        assert!(requests[0].ends_with("\r\n\r\n{\"ok\":true}"));
+    }
+
+    #[tokio::test]
+    async fn gets_chunked_body_with_headers() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/check?ip=1", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\n{\"a\"\r\n3\r\n:1}\r\n0\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&request).to_string()
+        });
+
+        let endpoint = HttpEndpoint::parse(&url).unwrap();
+        let body = endpoint.get(&[("Key", "secret")]).await.unwrap();
+        assert_eq!(body, b"{\"a\":1}");
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("GET /check?ip=1 HTTP/1.1\r\n"));
+        assert!(request.contains("\r\nKey: secret\r\n"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn silent_endpoints_time_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/ingest", listener.local_addr().unwrap());
+        // Accepts, then never answers
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await;
+            drop(stream);
+        });
+
+        let endpoint = HttpEndpoint::parse(&url).unwrap();
+        let e = endpoint.post_json("{}").await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        server.abort();
+    }
+}
@@ -1,3 +1,5 @@
+pub mod analytics;
+
 pub mod controller;
 
 pub mod container_management;
@@ -12,8 +14,26 @@ pub mod data_capture;
 
 pub mod error_handling;
 
+pub mod enrichment;
+
+pub mod events;
+
+pub mod honeytokens;
+
+pub mod http_client;
+
+pub mod metrics;
+
+pub mod notifier;
+
+pub mod smtp_client;
+
 pub mod storage;
 
+pub mod tagging;
+
+pub mod telemetry;
+
 pub mod web_interface;
 
 pub use controller::*;
@@ -1,30 +1,408 @@
-use clap::Parser;
+#![recursion_limit = "256"]
+
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand};
 use log::{error, info, warn};
 use miel::configuration::config::Config;
+use miel::container_management::ContainerManager;
 use miel::controller::controller_handler::Controller;
-use std::path::Path;
+use miel::controller::operations;
+use miel::controller::replay;
+use miel::controller::systemd::PidFile;
+use miel::storage::cowrie;
+use miel::storage::integrity::IntegrityLedger;
+use miel::storage::storage_trait::Storage;
+use miel::storage::types::{Combine, SessionFilter, SessionSort};
+use miel::telemetry;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::signal;
+use uuid::Uuid;
+
+/// Time left to the collector to receive the last spans on exit
+const SPAN_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Parser)]
 #[command(name = "miel")]
 #[command(version = "0.0.1")]
 #[command(about = "A comprehensive Chameleon Research Honeypot")]
-struct Args {
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Starts the honeypot, same as `miel run`
+    #[command(flatten)]
+    run: Option<RunArgs>,
+}
+
+/// Options of the honeypot process
+#[derive(Args)]
+struct RunArgs {
+    #[command(flatten)]
+    config: ConfigArgs,
+    /// Write the PID of the honeypot to this file, removed on exit
+    #[arg(long)]
+    pid_file: Option<PathBuf>,
+}
+
+/// Configuration the command operates with
+#[derive(Args)]
+struct ConfigArgs {
+    /// Configuration file (TOML)
     config_file: String,
+    /// Profile of the configuration file to apply, e.g. "research" or "production"
+    #[arg(long)]
+    profile: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Start the honeypot
+    Run(RunArgs),
+    /// Check the configuration, print every error and warning found and exit
+    ValidateConfig(ConfigArgs),
+    /// Inspect the stored sessions
+    Sessions {
+        #[command(subcommand)]
+        command: SessionsCommand,
+    },
+    /// Delete the stored sessions that ended long ago
+    Cleanup {
+        #[command(flatten)]
+        config: ConfigArgs,
+        /// Age of the sessions to delete, e.g. 30d, 12h or 90m
+        #[arg(long)]
+        older_than: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum SessionsCommand {
+    /// List the sessions, most recent first
+    List {
+        #[command(flatten)]
+        config: ConfigArgs,
+        #[arg(long)]
+        service: Option<String>,
+        #[arg(long)]
+        client_ip: Option<IpAddr>,
+        #[arg(long)]
+        tag: Option<String>,
+        /// ISO 3166-1 alpha-2 code of the client country
+        #[arg(long)]
+        country: Option<String>,
+        #[arg(long)]
+        container: Option<String>,
+        #[arg(long)]
+        min_bytes: Option<u64>,
+        #[arg(long)]
+        max_bytes: Option<u64>,
+        /// Shortest duration of the sessions, in seconds
+        #[arg(long)]
+        min_duration: Option<u64>,
+        /// Longest duration of the sessions, in seconds
+        #[arg(long)]
+        max_duration: Option<u64>,
+        /// List the sessions matching any of the criteria instead of all of them
+        #[arg(long)]
+        any: bool,
+        #[arg(long, default_value_t = 50)]
+        limit: u64,
+        /// Print the sessions as JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print a session with its annotations, credentials and commands as JSON
+    Show {
+        #[command(flatten)]
+        config: ConfigArgs,
+        session_id: Uuid,
+    },
+    /// Write the evidence bundle of a session
+    Export {
+        #[command(flatten)]
+        config: ConfigArgs,
+        session_id: Uuid,
+        /// Bundle file, `<SESSION_ID>.tar.gz` by default
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Print sessions as Cowrie JSON events, one per line, every stored session
+    /// without ids
+    Cowrie {
+        #[command(flatten)]
+        config: ConfigArgs,
+        session_ids: Vec<Uuid>,
+    },
+    /// Print the client IPs, file hashes and URLs of the sessions as a STIX 2.1 bundle
+    Stix {
+        #[command(flatten)]
+        config: ConfigArgs,
+        #[arg(long)]
+        service: Option<String>,
+        /// Only the sessions started at or after this time, e.g. 2025-01-01T00:00:00Z
+        #[arg(long)]
+        since: Option<DateTime<Utc>>,
+    },
+    /// Send the client bytes of a session again to a new container of its service,
+    /// recording the responses as a new session tagged `replay`
+    Replay {
+        #[command(flatten)]
+        config: ConfigArgs,
+        session_id: Uuid,
+    },
+    /// Check the stored artifacts of a session against its integrity manifest,
+    /// exiting with 1 when they do not match
+    Verify {
+        #[command(flatten)]
+        config: ConfigArgs,
+        session_id: Uuid,
+    },
 }
 
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+    let command = match (cli.command, cli.run) {
+        (Some(command), _) => command,
+        (None, Some(run)) => Command::Run(run),
+        (None, None) => unreachable!("clap requires a configuration file without a command"),
+    };
+
     // Configure logging with specific levels for different modules
     // Respect RUST_LOG environment variable for overall level
-    env_logger::Builder::from_default_env()
-        .filter_level(log::LevelFilter::Info) // Default level
+    // Operations only log their problems, their output is what they print
+    let level = match command {
+        Command::Run(_) => log::LevelFilter::Info,
+        _ => log::LevelFilter::Warn,
+    };
+    env_logger::Builder::new()
+        .filter_level(level) // Default level
         .filter_module("sea_orm", log::LevelFilter::Warn) // Reduce ORM logging
         .filter_module("sqlx", log::LevelFilter::Warn) // Reduce SQLx logging
         .filter_module("sea_orm::query", log::LevelFilter::Error) // Suppress query logs
         .filter_module("sqlx::query", log::LevelFilter::Error) // Suppress SQLx query logs
-        .format_target(false)
+        .parse_default_env()
+        .format(telemetry::format)
         .init();
+    telemetry::install();
+
+    match command {
+        Command::Run(args) => run(args).await,
+        Command::ValidateConfig(args) => {
+            let report = load_config(&args).check();
+            println!("{}", report);
+            std::process::exit(if report.has_errors() { 1 } else { 0 });
+        }
+        Command::Sessions { command } => sessions(command).await,
+        Command::Cleanup { config, older_than } => {
+            let age = operations::parse_age(&older_than).unwrap_or_else(|e| {
+                eprintln!("Invalid --older-than: {}", e);
+                std::process::exit(2);
+            });
+            let storage = open_storage(&config).await;
+            match operations::cleanup(storage.as_ref(), age, Utc::now()).await {
+                Ok(deleted) => println!("Deleted {} session(s)", deleted),
+                Err(e) => fail("Cleanup failed", e),
+            }
+        }
+    }
+}
+
+/// Loads the configuration of `args`, exiting when it cannot be loaded
+fn load_config(args: &ConfigArgs) -> Config {
+    Config::from_file_with_profile(Path::new(&args.config_file), args.profile.as_deref())
+        .unwrap_or_else(|e| {
+            error!(
+                "Failed to load configuration from {}: {:?}",
+                args.config_file, e
+            );
+            std::process::exit(1);
+        })
+}
+
+/// Opens the storage backend configured in `args`, exiting when it cannot be opened
+async fn open_storage(args: &ConfigArgs) -> Arc<dyn Storage + Send + Sync> {
+    let config = load_config(args);
+    Controller::open_storage(&config)
+        .await
+        .unwrap_or_else(|e| fail("Cannot open the storage", e))
+}
+
+fn fail(what: &str, e: impl std::fmt::Display) -> ! {
+    eprintln!("{}: {}", what, e);
+    std::process::exit(1);
+}
+
+async fn sessions(command: SessionsCommand) {
+    match command {
+        SessionsCommand::List {
+            config,
+            service,
+            client_ip,
+            tag,
+            country,
+            container,
+            min_bytes,
+            max_bytes,
+            min_duration,
+            max_duration,
+            any,
+            limit,
+            json,
+        } => {
+            let storage = open_storage(&config).await;
+            let filter = SessionFilter {
+                service_name: service,
+                client_addr: client_ip,
+                tag,
+                country_code: country,
+                container_id: container,
+                min_bytes,
+                max_bytes,
+                min_duration_secs: min_duration,
+                max_duration_secs: max_duration,
+                combine: if any { Combine::Any } else { Combine::All },
+                sort: Some(SessionSort::StartTimeDesc),
+                limit: Some(limit),
+                ..SessionFilter::default()
+            };
+            let sessions = storage
+                .get_sessions(Some(filter))
+                .await
+                .unwrap_or_else(|e| fail("Cannot list the sessions", e));
+            if json {
+                println!("{}", serde_json::to_string_pretty(&sessions).unwrap());
+            } else {
+                print!("{}", operations::session_table(&sessions));
+            }
+        }
+        SessionsCommand::Show { config, session_id } => {
+            let storage = open_storage(&config).await;
+            let details = operations::session_details(storage.as_ref(), session_id)
+                .await
+                .unwrap_or_else(|e| fail(&format!("Cannot read session {}", session_id), e));
+            println!("{}", serde_json::to_string_pretty(&details).unwrap());
+        }
+        SessionsCommand::Export {
+            config,
+            session_id,
+            output,
+        } => {
+            let storage = open_storage(&config).await;
+            let bundle = storage
+                .export_session(session_id)
+                .await
+                .unwrap_or_else(|e| fail(&format!("Cannot export session {}", session_id), e));
+            let output = output.unwrap_or_else(|| PathBuf::from(format!("{}.tar.gz", session_id)));
+            if let Err(e) = std::fs::write(&output, bundle) {
+                fail(&format!("Cannot write {}", output.display()), e);
+            }
+            println!("Session {} exported to {}", session_id, output.display());
+        }
+        SessionsCommand::Cowrie {
+            config,
+            session_ids,
+        } => {
+            let storage = open_storage(&config).await;
+            let session_ids = match session_ids.is_empty() {
+                true => storage
+                    .get_sessions(Some(SessionFilter {
+                        sort: Some(SessionSort::StartTime),
+                        ..SessionFilter::default()
+                    }))
+                    .await
+                    .unwrap_or_else(|e| fail("Cannot list the sessions", e))
+                    .into_iter()
+                    .map(|session| session.id)
+                    .collect(),
+                false => session_ids,
+            };
+            let sensor = cowrie::sensor_name();
+            for session_id in session_ids {
+                let events = storage
+                    .get_cowrie_events(session_id, &sensor)
+                    .await
+                    .unwrap_or_else(|e| fail(&format!("Cannot read session {}", session_id), e));
+                print!("{}", cowrie::to_json_lines(&events));
+            }
+        }
+        SessionsCommand::Stix {
+            config,
+            service,
+            since,
+        } => {
+            let identity = load_config(&config).stix.identity;
+            let storage = open_storage(&config).await;
+            let indicators = storage
+                .get_indicators(Some(SessionFilter {
+                    service_name: service,
+                    start_date: since,
+                    ..SessionFilter::default()
+                }))
+                .await
+                .unwrap_or_else(|e| fail("Cannot read the sessions", e));
+            let bundle = indicators.to_bundle(&identity, Utc::now());
+            println!("{}", serde_json::to_string_pretty(&bundle).unwrap());
+        }
+        SessionsCommand::Replay { config, session_id } => {
+            let config = load_config(&config);
+            let storage = Controller::open_storage(&config)
+                .await
+                .unwrap_or_else(|e| fail("Cannot open the storage", e));
+            let mut container_manager =
+                ContainerManager::with_runtime(config.container_runtime.clone())
+                    .unwrap_or_else(|e| fail("Cannot start containers", e))
+                    .with_image_dir(&config.image_dir)
+                    .with_paths(config.container_paths());
+            let report = replay::replay_session(
+                storage.clone(),
+                &mut container_manager,
+                &config.services,
+                session_id,
+            )
+            .await
+            .unwrap_or_else(|e| fail(&format!("Cannot replay session {}", session_id), e));
+            if let Err(e) = storage.flush().await {
+                fail("Cannot store the replay", e);
+            }
+            println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        }
+        SessionsCommand::Verify { config, session_id } => {
+            let config = load_config(&config);
+            let Some(ledger) =
+                IntegrityLedger::from_config(&config.integrity, &config.storage_path)
+            else {
+                fail("Cannot verify", "integrity manifests are disabled")
+            };
+            let storage = Controller::open_storage(&config)
+                .await
+                .unwrap_or_else(|e| fail("Cannot open the storage", e));
+            let report = ledger
+                .verify(storage.as_ref(), session_id)
+                .await
+                .unwrap_or_else(|e| fail(&format!("Cannot verify session {}", session_id), e))
+                .unwrap_or_else(|| {
+                    fail(
+                        &format!("Cannot verify session {}", session_id),
+                        "no integrity manifest",
+                    )
+                });
+            println!("{}", serde_json::to_string_pretty(&report).unwrap());
+            std::process::exit(if report.intact { 0 } else { 1 });
+        }
+    }
+}
+
+/// Starts the honeypot and runs it until interrupted or terminated
+async fn run(run_args: RunArgs) {
+    let args = run_args.config;
+    let _pid_file = run_args.pid_file.map(|path| {
+        PidFile::create(&path)
+            .unwrap_or_else(|e| fail(&format!("Cannot write PID file {}", path.display()), e))
+    });
 
     println!(
         "
@@ -43,35 +421,26 @@ async fn main() {
 
     info!("Miel honeypot starting up");
 
-    // Get command-line arguments
-    let args = Args::parse();
-
-    if args.config_file.is_empty() {
-        error!("No configuration file specified");
-        std::process::exit(1);
-    }
-
-    let config = Config::from_file(Path::new(args.config_file.as_str())).map_err(|e| {
-        error!(
-            "Failed to load configuration from {}: {:?}",
-            args.config_file, e
-        );
-        std::process::exit(1);
-    });
-
+    let config = load_config(&args);
+    telemetry::configure(&config.logging);
     info!("Configuration loaded from {}", args.config_file);
+    for warning in config.check().warnings() {
+        warn!("{}", warning);
+    }
 
-    let mut controller = Controller::new(config.unwrap())
+    let mut controller = Controller::new(config)
         .await
         .map_err(|e| {
             error!("Failed to initialize controller: {:?}", e);
             std::process::exit(1);
         })
-        .unwrap();
+        .unwrap()
+        .with_config_path(&args.config_file)
+        .with_profile(args.profile);
 
     let (shutdown_tx, shutdown_rx) = tokio::sync::broadcast::channel(1);
 
-    let controller_handle = tokio::spawn(async move {
+    let mut controller_handle = tokio::spawn(async move {
         if let Err(e) = controller.run(shutdown_rx).await {
             error!("Controller error: {:?}", e);
         }
@@ -79,12 +448,23 @@ async fn main() {
 
     info!("Miel honeypot is now operational");
 
-    match signal::ctrl_c().await {
-        Ok(()) => {
-            info!("Shutdown signal received, stopping honeypot...");
-        }
-        Err(e) => {
-            error!("Failed to listen for shutdown signal: {}", e);
+    tokio::select! {
+        signal = shutdown_signal() => match signal {
+            Ok(()) => {
+                info!("Shutdown signal received, stopping honeypot...");
+            }
+            Err(e) => {
+                error!("Failed to listen for shutdown signal: {}", e);
+            }
+        },
+        // Drained on SIGUSR1 or through the API
+        stopped = &mut controller_handle => {
+            if let Err(e) = stopped {
+                error!("Controller task failed: {:?}", e);
+            }
+            info!("Miel honeypot drained and stopped");
+            telemetry::flush(SPAN_FLUSH_TIMEOUT).await;
+            return;
         }
     }
 
@@ -103,4 +483,14 @@ async fn main() {
             warn!("Controller shutdown timed out after 10 seconds");
         }
     }
+    telemetry::flush(SPAN_FLUSH_TIMEOUT).await;
+}
+
+/// Waits for Ctrl-C or, as sent by systemd to stop the service, `SIGTERM`
+async fn shutdown_signal() -> std::io::Result<()> {
+    let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+    tokio::select! {
+        res = signal::ctrl_c() => res,
+        _ = terminate.recv() => Ok(()),
+    }
 }
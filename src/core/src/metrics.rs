@@ -0,0 +1,360 @@
+//! Operational metrics in the Prometheus text exposition format.
+//!
+//! The subsystems update a process wide [`Metrics`] registry, reachable through
+//! [`global`], and the web interface serves its [`Metrics::render`] output on
+//! `GET /metrics`, [`crate::telemetry`] exporting the same values to an OTLP
+//! collector when configured. Only counters and gauges are tracked; rates such as accepted
+//! connections per second are left to PromQL (`rate(miel_connections_accepted_total[5m])`).
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use crate::container_management::ContainerInfo;
+use crate::data_capture::Direction;
+
+/// Content type of [`Metrics::render`] output
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Reads the value of a per-container metric, `None` when unknown
+type ContainerValue = fn(&ContainerInfo) -> Option<u64>;
+
+/// Registry of the honeypot metrics
+#[derive(Default)]
+pub struct Metrics {
+    active_sessions: AtomicU64,
+    sessions: LabeledCounter,
+    containers_created: AtomicU64,
+    container_failures: AtomicU64,
+    bytes_proxied: LabeledCounter,
+    connections_accepted: LabeledCounter,
+    connections_rejected: LabeledCounter,
+    storage_errors: LabeledCounter,
+    /// Running containers as last listed, with their resource usage
+    containers: Mutex<Vec<ContainerInfo>>,
+}
+
+/// Registry shared by the whole process
+pub fn global() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::default)
+}
+
+impl Metrics {
+    /// Sets the number of sessions currently tracked by the session manager
+    pub fn set_active_sessions(&self, count: usize) {
+        self.active_sessions.store(count as u64, Ordering::Relaxed);
+    }
+
+    /// Counts a new session for `service`
+    pub fn session_started(&self, service: &str) {
+        self.sessions.add(&[("service", service)], 1);
+    }
+
+    pub fn container_created(&self) {
+        self.containers_created.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn container_failed(&self) {
+        self.container_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts `bytes` forwarded by a TCP or UDP proxy
+    pub fn bytes_proxied(&self, direction: Direction, bytes: usize) {
+        let direction = match direction {
+            Direction::ClientToContainer => "client_to_container",
+            Direction::ContainerToClient => "container_to_client",
+        };
+        self.bytes_proxied
+            .add(&[("direction", direction)], bytes as u64);
+    }
+
+    /// Counts a connection (TCP) or new flow (UDP) accepted on `port`
+    pub fn connection_accepted(&self, port: u16, transport: &str) {
+        self.connections_accepted
+            .add(&[("port", &port.to_string()), ("transport", transport)], 1);
+    }
+
+    /// Counts a connection turned away on `port`, `reason` being `filtered` or `rate_limited`
+    pub fn connection_rejected(&self, port: u16, reason: &str) {
+        self.connections_rejected
+            .add(&[("port", &port.to_string()), ("reason", reason)], 1);
+    }
+
+    /// Counts a failed storage backend call
+    pub fn storage_error(&self, operation: &str) {
+        self.storage_errors.add(&[("operation", operation)], 1);
+    }
+
+    /// Replaces the running containers reported per container
+    pub fn set_containers(&self, containers: &[ContainerInfo]) {
+        *self.containers.lock().unwrap() = containers.to_vec();
+    }
+
+    /// Renders every metric in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for family in self.families() {
+            header(&mut out, family.name, family.help, family.kind.as_str());
+            for (labels, value) in &family.samples {
+                if labels.is_empty() {
+                    let _ = writeln!(out, "{} {}", family.name, value);
+                    continue;
+                }
+                let labels = labels
+                    .iter()
+                    .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                let _ = writeln!(out, "{}{{{}}} {}", family.name, labels, value);
+            }
+        }
+        out
+    }
+
+    /// Current value of every metric, also exported over OTLP by [`crate::telemetry`]
+    pub fn families(&self) -> Vec<MetricFamily> {
+        let single = |name, help, kind, value: &AtomicU64| MetricFamily {
+            name,
+            help,
+            kind,
+            samples: vec![(Vec::new(), value.load(Ordering::Relaxed))],
+        };
+        let mut families = vec![
+            single(
+                "miel_active_sessions",
+                "Sessions currently active",
+                MetricKind::Gauge,
+                &self.active_sessions,
+            ),
+            self.sessions
+                .family("miel_sessions_total", "Sessions started, by service"),
+            single(
+                "miel_containers_created_total",
+                "Containers created for sessions",
+                MetricKind::Counter,
+                &self.containers_created,
+            ),
+            single(
+                "miel_container_failures_total",
+                "Containers that failed to start",
+                MetricKind::Counter,
+                &self.container_failures,
+            ),
+            self.bytes_proxied.family(
+                "miel_bytes_proxied_total",
+                "Payload bytes forwarded between clients and containers",
+            ),
+            self.connections_accepted.family(
+                "miel_connections_accepted_total",
+                "Connections accepted by the listeners",
+            ),
+            self.connections_rejected.family(
+                "miel_connections_rejected_total",
+                "Connections rejected by the connection filter",
+            ),
+            self.storage_errors.family(
+                "miel_storage_errors_total",
+                "Failed storage backend operations",
+            ),
+        ];
+        self.container_families(&mut families);
+        families
+    }
+
+    fn container_families(&self, families: &mut Vec<MetricFamily>) {
+        let containers = self.containers.lock().unwrap();
+        families.push(MetricFamily {
+            name: "miel_active_containers",
+            help: "Containers currently running",
+            kind: MetricKind::Gauge,
+            samples: vec![(Vec::new(), containers.len() as u64)],
+        });
+        let per_container: [(&str, &str, MetricKind, ContainerValue); 4] = [
+            (
+                "miel_container_age_seconds",
+                "Seconds since the container was created",
+                MetricKind::Gauge,
+                |c| Some(c.age_secs),
+            ),
+            (
+                "miel_container_memory_bytes",
+                "Memory charged to the container",
+                MetricKind::Gauge,
+                |c| c.usage.as_ref()?.memory_bytes,
+            ),
+            (
+                "miel_container_cpu_usage_microseconds_total",
+                "CPU time consumed by the container",
+                MetricKind::Counter,
+                |c| c.usage.as_ref()?.cpu_usage_usec,
+            ),
+            (
+                "miel_container_pids",
+                "Processes and threads running in the container",
+                MetricKind::Gauge,
+                |c| c.usage.as_ref()?.pids,
+            ),
+        ];
+        for (name, help, kind, value) in per_container {
+            let samples = containers
+                .iter()
+                .filter_map(|container| {
+                    let labels = vec![
+                        ("container".to_string(), container.id.clone()),
+                        ("service".to_string(), container.service_name.clone()),
+                    ];
+                    Some((labels, value(container)?))
+                })
+                .collect();
+            families.push(MetricFamily {
+                name,
+                help,
+                kind,
+                samples,
+            });
+        }
+    }
+}
+
+/// Whether a metric only grows or goes up and down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+}
+
+impl MetricKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+        }
+    }
+}
+
+/// A metric and its values, one per label set
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricFamily {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub kind: MetricKind,
+    pub samples: Vec<(Vec<(String, String)>, u64)>,
+}
+
+/// Counter family keyed by its label set
+#[derive(Default)]
+struct LabeledCounter {
+    values: Mutex<BTreeMap<Vec<(String, String)>, u64>>,
+}
+
+impl LabeledCounter {
+    fn add(&self, labels: &[(&str, &str)], value: u64) {
+        let key = labels
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        *self.values.lock().unwrap().entry(key).or_insert(0) += value;
+    }
+
+    fn family(&self, name: &'static str, help: &'static str) -> MetricFamily {
+        MetricFamily {
+            name,
+            help,
+            kind: MetricKind::Counter,
+            samples: self
+                .values
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(labels, value)| (labels.clone(), *value))
+                .collect(),
+        }
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_counters_and_labels() {
+        let metrics = Metrics::default();
+        metrics.set_active_sessions(2);
+        metrics.session_started("ssh");
+        metrics.session_started("ssh");
+        metrics.session_started("http");
+        metrics.bytes_proxied(Direction::ClientToContainer, 10);
+        metrics.bytes_proxied(Direction::ClientToContainer, 5);
+        metrics.connection_accepted(22, "tcp");
+        metrics.container_failed();
+
+        let out = metrics.render();
+        assert!(out.contains("# TYPE miel_active_sessions gauge\nmiel_active_sessions 2\n"));
+        assert!(out.contains("miel_sessions_total{service=\"http\"} 1\n"));
+        assert!(out.contains("miel_sessions_total{service=\"ssh\"} 2\n"));
+        assert!(out.contains("miel_bytes_proxied_total{direction=\"client_to_container\"} 15\n"));
+        assert!(out.contains("miel_connections_accepted_total{port=\"22\",transport=\"tcp\"} 1\n"));
+        assert!(out.contains("miel_container_failures_total 1\n"));
+        assert!(out.contains("miel_containers_created_total 0\n"));
+    }
+
+    #[test]
+    fn renders_containers_with_their_usage() {
+        let metrics = Metrics::default();
+        let container = |id: &str, usage| ContainerInfo {
+            id: id.to_string(),
+            service_name: "ssh".to_string(),
+            runtime: crate::container_management::Runtime::Docker,
+            created_at: chrono::Utc::now(),
+            age_secs: 60,
+            healthy: true,
+            usage,
+        };
+        metrics.set_containers(&[
+            container(
+                "miel-ssh-a",
+                Some(crate::container_management::ResourceUsage {
+                    memory_bytes: Some(1024),
+                    cpu_usage_usec: None,
+                    pids: Some(3),
+                }),
+            ),
+            container("miel-ssh-b", None),
+        ]);
+
+        let out = metrics.render();
+        assert!(out.contains("miel_active_containers 2\n"));
+        assert!(out
+            .contains("miel_container_age_seconds{container=\"miel-ssh-b\",service=\"ssh\"} 60\n"));
+        assert!(out.contains(
+            "miel_container_memory_bytes{container=\"miel-ssh-a\",service=\"ssh\"} 1024\n"
+        ));
+        assert!(out.contains("miel_container_pids{container=\"miel-ssh-a\",service=\"ssh\"} 3\n"));
+        assert!(!out.contains("miel_container_memory_bytes{container=\"miel-ssh-b\""));
+        assert!(!out.contains("miel_container_cpu_usage_microseconds_total{"));
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        let metrics = Metrics::default();
+        metrics.session_started("we\"ird\\name");
+
+        assert!(metrics
+            .render()
+            .contains("miel_sessions_total{service=\"we\\\"ird\\\\name\"} 1\n"));
+    }
+}
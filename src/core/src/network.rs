@@ -1,4 +1,11 @@
+pub mod client_hello;
 pub mod connection_filter;
 pub mod network_listener;
+pub mod proxy_protocol;
+pub mod redirect;
+pub mod scan_detector;
 pub mod service_detector;
+pub mod ssh;
+pub mod tarpit;
+pub mod tls;
 pub mod types;
@@ -0,0 +1,256 @@
+//! TLS ClientHello inspection: server name and JA3 fingerprint.
+//!
+//! The ClientHello is read before the handshake starts, so a client can be routed on
+//! the server name it asks for and fingerprinted from the TLS parameters it offers.
+//! The [JA3] fingerprint is the MD5 of the offered version, cipher suites, extensions,
+//! groups and point formats; GREASE values are ignored so that a client keeps the
+//! same fingerprint across connections.
+//!
+//! [JA3]: https://github.com/salesforce/ja3
+
+use md5::{Digest, Md5};
+
+const CONTENT_TYPE_HANDSHAKE: u8 = 0x16;
+const HANDSHAKE_CLIENT_HELLO: u8 = 0x01;
+const RECORD_HEADER_LEN: usize = 5;
+
+const EXT_SERVER_NAME: u16 = 0;
+const EXT_SUPPORTED_GROUPS: u16 = 10;
+const EXT_EC_POINT_FORMATS: u16 = 11;
+
+/// What a client offered in its ClientHello
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientHello {
+    /// Server name indication, if sent
+    pub sni: Option<String>,
+    /// JA3 fingerprint string, before hashing
+    pub ja3: String,
+    /// JA3 fingerprint, lowercase hex MD5 of `ja3`
+    pub ja3_hash: String,
+}
+
+/// Whether `data` starts like a TLS handshake record
+pub fn looks_like_tls(data: &[u8]) -> bool {
+    data.len() >= 3 && data[0] == CONTENT_TYPE_HANDSHAKE && data[1] == 0x03
+}
+
+/// Length of the first TLS record, header included, once the header is available
+pub fn record_len(data: &[u8]) -> Option<usize> {
+    (data.len() >= RECORD_HEADER_LEN)
+        .then(|| RECORD_HEADER_LEN + u16::from_be_bytes([data[3], data[4]]) as usize)
+}
+
+/// Parses the ClientHello carried by the first TLS record of `data`.
+///
+/// Returns `None` when `data` is not a complete ClientHello record.
+pub fn parse(data: &[u8]) -> Option<ClientHello> {
+    if !looks_like_tls(data) {
+        return None;
+    }
+    let record = data.get(RECORD_HEADER_LEN..record_len(data)?)?;
+
+    let mut hello = Reader::new(record);
+    if hello.u8()? != HANDSHAKE_CLIENT_HELLO {
+        return None;
+    }
+    let body_len = hello.u24()?;
+    let mut body = Reader::new(hello.bytes(body_len)?);
+
+    let version = body.u16()?;
+    body.bytes(32)?; // random
+    let session_id_len = body.u8()? as usize;
+    body.bytes(session_id_len)?;
+
+    let cipher_suites_len = body.u16()? as usize;
+    let cipher_suites = u16_list(body.bytes(cipher_suites_len)?);
+    let compression_len = body.u8()? as usize;
+    body.bytes(compression_len)?;
+
+    let mut sni = None;
+    let mut extensions = Vec::new();
+    let mut groups = Vec::new();
+    let mut point_formats = Vec::new();
+
+    // Extensions are optional in a ClientHello
+    if let Some(extensions_len) = body.u16() {
+        let mut ext_reader = Reader::new(body.bytes(extensions_len as usize)?);
+        while let Some(ext_type) = ext_reader.u16() {
+            let ext_len = ext_reader.u16()? as usize;
+            let mut ext = Reader::new(ext_reader.bytes(ext_len)?);
+            extensions.push(ext_type);
+
+            match ext_type {
+                EXT_SERVER_NAME => sni = server_name(&mut ext),
+                EXT_SUPPORTED_GROUPS => {
+                    let len = ext.u16()? as usize;
+                    groups = u16_list(ext.bytes(len)?);
+                }
+                EXT_EC_POINT_FORMATS => {
+                    let len = ext.u8()? as usize;
+                    point_formats = ext.bytes(len)?.iter().map(|f| *f as u16).collect();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let ja3 = format!(
+        "{},{},{},{},{}",
+        version,
+        join(&cipher_suites),
+        join(&extensions),
+        join(&groups),
+        join(&point_formats)
+    );
+    let ja3_hash = Md5::digest(ja3.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    Some(ClientHello { sni, ja3, ja3_hash })
+}
+
+/// First host name of a server_name extension
+fn server_name(ext: &mut Reader) -> Option<String> {
+    let list_len = ext.u16()? as usize;
+    let mut list = Reader::new(ext.bytes(list_len)?);
+    while let Some(name_type) = list.u8() {
+        let name_len = list.u16()? as usize;
+        let name = list.bytes(name_len)?;
+        if name_type == 0 {
+            return std::str::from_utf8(name).ok().map(str::to_ascii_lowercase);
+        }
+    }
+    None
+}
+
+/// GREASE values (RFC 8701) are random placeholders, not part of the fingerprint
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+fn u16_list(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect()
+}
+
+fn join(values: &[u16]) -> String {
+    values
+        .iter()
+        .filter(|value| !is_grease(**value))
+        .map(u16::to_string)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Bounds checked big endian reader
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        self.bytes(3)
+            .map(|b| u32::from_be_bytes([0, b[0], b[1], b[2]]) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a ClientHello record offering the given parameters
+    fn client_hello(sni: Option<&str>, ciphers: &[u16], groups: &[u16]) -> Vec<u8> {
+        let mut extensions = Vec::new();
+        let mut push_ext = |ext_type: u16, data: Vec<u8>| {
+            extensions.extend_from_slice(&ext_type.to_be_bytes());
+            extensions.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            extensions.extend_from_slice(&data);
+        };
+        push_ext(0x0a0a, vec![]); // GREASE
+        if let Some(name) = sni {
+            let mut data = ((name.len() + 3) as u16).to_be_bytes().to_vec();
+            data.push(0);
+            data.extend_from_slice(&(name.len() as u16).to_be_bytes());
+            data.extend_from_slice(name.as_bytes());
+            push_ext(EXT_SERVER_NAME, data);
+        }
+        let mut data = ((groups.len() * 2) as u16).to_be_bytes().to_vec();
+        groups
+            .iter()
+            .for_each(|g| data.extend_from_slice(&g.to_be_bytes()));
+        push_ext(EXT_SUPPORTED_GROUPS, data);
+        push_ext(EXT_EC_POINT_FORMATS, vec![1, 0]); // uncompressed
+
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(&[0u8; 32]);
+        body.push(0); // session id
+        body.extend_from_slice(&((ciphers.len() * 2) as u16).to_be_bytes());
+        ciphers
+            .iter()
+            .for_each(|c| body.extend_from_slice(&c.to_be_bytes()));
+        body.extend_from_slice(&[1, 0]); // null compression
+        body.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        body.extend_from_slice(&extensions);
+
+        let mut handshake = vec![HANDSHAKE_CLIENT_HELLO];
+        handshake.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        handshake.extend_from_slice(&body);
+
+        let mut record = vec![CONTENT_TYPE_HANDSHAKE, 0x03, 0x01];
+        record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+        record.extend_from_slice(&handshake);
+        record
+    }
+
+    #[test]
+    fn extracts_sni_and_ja3_without_grease() {
+        let record = client_hello(Some("Mail.Example.org"), &[0x1a1a, 4865, 49195], &[29, 23]);
+        let hello = parse(&record).unwrap();
+
+        assert_eq!(hello.sni.as_deref(), Some("mail.example.org"));
+        assert_eq!(hello.ja3, "771,4865-49195,0-10-11,29-23,0");
+        assert_eq!(hello.ja3_hash.len(), 32);
+    }
+
+    #[test]
+    fn fingerprint_ignores_server_name() {
+        let a = parse(&client_hello(Some("a.example"), &[4865], &[29])).unwrap();
+        let b = parse(&client_hello(Some("b.example"), &[4865], &[29])).unwrap();
+        let c = parse(&client_hello(Some("a.example"), &[4866], &[29])).unwrap();
+
+        assert_eq!(a.ja3_hash, b.ja3_hash);
+        assert_ne!(a.ja3_hash, c.ja3_hash);
+    }
+
+    #[test]
+    fn rejects_truncated_and_non_tls_data() {
+        let record = client_hello(None, &[4865], &[29]);
+
+        assert!(parse(&record[..record.len() - 1]).is_none());
+        assert!(parse(b"GET / HTTP/1.1\r\n\r\n").is_none());
+        assert_eq!(parse(&record).unwrap().sni, None);
+    }
+}
@@ -1,20 +1,167 @@
-use crate::configuration::types::{IpFilter, PortFilter};
+use crate::configuration::types::{
+    IpFilter, PortFilter, RateLimitAction, RateLimitConfig, ScanDetectionConfig, ServiceConfig,
+};
+use crate::network::scan_detector::{source_of, ScanDetector};
 
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt;
 use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
+/// Sliding window used for the per source IP rate
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Number of tracked sources, past which the least recently seen is evicted
+const MAX_TRACKED_SOURCES: usize = 4096;
+
+/// Upper bound on connections held in the tarpit, further ones are dropped
+const MAX_TARPITTED: usize = 256;
+
+/// Source IP, by /64 for IPv6, and service port
+type SourceKey = (IpAddr, u16);
+
+/// Recent connection instants per source
+#[derive(Default)]
+struct RecentConnections {
+    hits: HashMap<SourceKey, VecDeque<Instant>>,
+    /// Last hit of every source, least recent first
+    by_last_hit: BTreeSet<(Instant, SourceKey)>,
+}
+
+/// Filters incoming connections on static IP/port rules and on rate limits.
+///
+/// Clones share the rules and the rate limiting state, so one filter can be handed
+/// to every port listener while the limits stay global, and a
+/// [`reconfigure`](ConnectionFilter::reconfigure) applies to all of them.
 #[derive(Clone, Default)]
 pub struct ConnectionFilter {
+    rules: Arc<RwLock<FilterRules>>,
+    recent_connections: Arc<Mutex<RecentConnections>>,
+    active_connections: Arc<AtomicUsize>,
+    tarpitted: Arc<AtomicUsize>,
+    scan_detector: ScanDetector,
+}
+
+/// Configured part of a [`ConnectionFilter`], replaced as a whole on reload
+#[derive(Clone, Default)]
+struct FilterRules {
     ip_filter: IpFilter,
     port_filter: PortFilter,
+    rate_limit: RateLimitConfig,
+    /// Per service port overrides of `connections_per_ip_per_minute`
+    port_rate_limits: HashMap<u16, u32>,
+}
+
+/// Why a connection was refused by the rate limiter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitReason {
+    /// The source IP opened too many connections to the service within a minute
+    PerIpRate,
+    /// The global concurrent connection cap is reached
+    ConcurrentCap,
+}
+
+impl fmt::Display for RateLimitReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RateLimitReason::PerIpRate => write!(f, "per_ip_rate"),
+            RateLimitReason::ConcurrentCap => write!(f, "concurrent_cap"),
+        }
+    }
+}
+
+/// Outcome of [`ConnectionFilter::admit`] for a connection over the limits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimited {
+    pub reason: RateLimitReason,
+    pub action: RateLimitAction,
+}
+
+/// Slot in the concurrent connection count, released when dropped.
+///
+/// Travels with the session request so the slot is held as long as the
+/// connection is being served.
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    active_connections: Arc<AtomicUsize>,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.active_connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Slot in the tarpit, released when dropped
+#[derive(Debug)]
+pub struct TarpitPermit {
+    tarpitted: Arc<AtomicUsize>,
+}
+
+impl Drop for TarpitPermit {
+    fn drop(&mut self) {
+        self.tarpitted.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 impl ConnectionFilter {
     pub fn new(ip_filter: IpFilter, port_filter: PortFilter) -> Self {
-        Self {
+        let rules = FilterRules {
             ip_filter,
             port_filter,
+            ..FilterRules::default()
+        };
+        Self {
+            rules: Arc::new(RwLock::new(rules)),
+            ..Self::default()
         }
     }
+
+    /// Enables rate limiting, taking per service overrides from `services`
+    pub fn with_rate_limit(self, rate_limit: RateLimitConfig, services: &[ServiceConfig]) -> Self {
+        {
+            let mut rules = self.rules.write().unwrap();
+            rules.rate_limit = rate_limit;
+            rules.port_rate_limits = services
+                .iter()
+                .filter_map(|s| s.connections_per_ip_per_minute.map(|limit| (s.port, limit)))
+                .collect();
+        }
+        self
+    }
+
+    /// Enables port scan detection
+    pub fn with_scan_detection(mut self, scan_detection: ScanDetectionConfig) -> Self {
+        self.scan_detector = ScanDetector::new(scan_detection);
+        self
+    }
+
+    /// Replaces the rules of this filter and of all its clones with the ones of `other`.
+    ///
+    /// Rate limiting state is kept: recent connections still count against the new
+    /// limits and permits already handed out stay valid. So are the sources tracked
+    /// by the scan detector.
+    pub fn reconfigure(&self, other: &ConnectionFilter) {
+        self.scan_detector.reconfigure(&other.scan_detector);
+        if Arc::ptr_eq(&self.rules, &other.rules) {
+            return;
+        }
+        let rules = other.rules.read().unwrap().clone();
+        *self.rules.write().unwrap() = rules;
+    }
+
+    /// Detector the accepted connections are observed by, shared by the clones
+    pub fn scan_detector(&self) -> &ScanDetector {
+        &self.scan_detector
+    }
+
+    /// Duration a tarpitted connection is held open
+    pub fn tarpit_duration(&self) -> Duration {
+        Duration::from_secs(self.rules.read().unwrap().rate_limit.tarpit_secs)
+    }
+
     pub fn should_accept_connection(&self, client_addr: &IpAddr, port: u16) -> bool {
         if !(self.is_ip_allowed(client_addr) && self.is_port_allowed(port)) {
             return false;
@@ -22,11 +169,120 @@ impl ConnectionFilter {
         true
     }
 
+    /// Applies the rate limits to a new connection from `client_addr` on `port`.
+    ///
+    /// An admitted connection counts against the per IP rate and holds a
+    /// [`ConnectionPermit`] until it is dropped. Connections refused for the per IP
+    /// rate are not recorded, so the window stays bounded by the limit.
+    pub fn admit(&self, client_addr: &IpAddr, port: u16) -> Result<ConnectionPermit, RateLimited> {
+        let (action, per_ip_limit, cap) = {
+            let rules = self.rules.read().unwrap();
+            let per_ip_limit = rules
+                .port_rate_limits
+                .get(&port)
+                .copied()
+                .unwrap_or(rules.rate_limit.connections_per_ip_per_minute);
+            (
+                rules.rate_limit.action,
+                per_ip_limit,
+                rules.rate_limit.max_concurrent_connections,
+            )
+        };
+        let limited = |reason| RateLimited { reason, action };
+
+        if per_ip_limit > 0 && !self.record_connection(*client_addr, port, per_ip_limit) {
+            return Err(limited(RateLimitReason::PerIpRate));
+        }
+
+        let admitted = self
+            .active_connections
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |active| {
+                (cap == 0 || active < cap).then_some(active + 1)
+            })
+            .is_ok();
+
+        if !admitted {
+            return Err(limited(RateLimitReason::ConcurrentCap));
+        }
+
+        Ok(ConnectionPermit {
+            active_connections: Arc::clone(&self.active_connections),
+        })
+    }
+
+    /// Reserves a tarpit slot, `None` when the tarpit is full
+    pub fn enter_tarpit(&self) -> Option<TarpitPermit> {
+        self.tarpitted
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |held| {
+                (held < MAX_TARPITTED).then_some(held + 1)
+            })
+            .ok()
+            .map(|_| TarpitPermit {
+                tarpitted: Arc::clone(&self.tarpitted),
+            })
+    }
+
+    /// Records a connection in the sliding window, returns `false` if it exceeds `limit`
+    fn record_connection(&self, ip: IpAddr, port: u16, limit: u32) -> bool {
+        self.record_connection_at(ip, port, limit, Instant::now())
+    }
+
+    fn record_connection_at(&self, ip: IpAddr, port: u16, limit: u32, now: Instant) -> bool {
+        let key = (source_of(ip), port);
+        let mut recent = self.recent_connections.lock().unwrap();
+        let RecentConnections { hits, by_last_hit } = &mut *recent;
+
+        // Forget the sources idle for a whole window, and the least recently seen
+        // ones while there is no room for a new source
+        while let Some(&(last, oldest)) = by_last_hit.first() {
+            let idle = now.duration_since(last) >= RATE_WINDOW;
+            let full = hits.len() >= MAX_TRACKED_SOURCES && !hits.contains_key(&key);
+            if !idle && !full {
+                break;
+            }
+            by_last_hit.pop_first();
+            hits.remove(&oldest);
+        }
+
+        let source = hits.entry(key).or_default();
+        while source
+            .front()
+            .is_some_and(|first| now.duration_since(*first) >= RATE_WINDOW)
+        {
+            source.pop_front();
+        }
+
+        if source.len() >= limit as usize {
+            return false;
+        }
+        if let Some(last) = source.back() {
+            by_last_hit.remove(&(*last, key));
+        }
+        source.push_back(now);
+        by_last_hit.insert((now, key));
+        true
+    }
+
     fn is_ip_allowed(&self, ip: &IpAddr) -> bool {
+        // IPv4 clients of dual-stack sockets match the IPv4 ranges
+        let ip = &ip.to_canonical();
+        let rules = self.rules.read().unwrap();
+        let ip_filter = &rules.ip_filter;
+
+        if ip_filter.denylist.iter().any(|cidr| cidr.contains(ip)) {
+            return false;
+        }
+
+        if !ip_filter.allowlist.is_empty()
+            && !ip_filter.allowlist.iter().any(|cidr| cidr.contains(ip))
+        {
+            return false;
+        }
+
         let mut result = true;
-        if self.ip_filter.whitelist_mode {
+        if ip_filter.whitelist_mode {
             // White list mode
-            for ip_range in self.ip_filter.allowed_ranges.iter() {
+            for ip_range in ip_filter.allowed_ranges.iter() {
                 if !(ip.min(&ip_range.start).eq(&ip_range.start)
                     && ip.max(&ip_range.end).eq(&ip_range.end))
                 {
@@ -36,7 +292,7 @@ impl ConnectionFilter {
             result
         } else {
             // Blacklist mode
-            for ip_range in self.ip_filter.blocked_ranges.iter() {
+            for ip_range in ip_filter.blocked_ranges.iter() {
                 if ip.min(&ip_range.start).eq(&ip_range.start)
                     && ip.max(&ip_range.end).eq(&ip_range.end)
                 {
@@ -49,8 +305,9 @@ impl ConnectionFilter {
 
     // Blacklists by default
     fn is_port_allowed(&self, port: u16) -> bool {
+        let rules = self.rules.read().unwrap();
         let mut result = true;
-        for port_range in self.port_filter.allowed_ports.iter() {
+        for port_range in rules.port_filter.allowed_ports.iter() {
             if !(port.min(port_range.start) == port_range.start
                 && port.max(port_range.end) == port_range.end)
             {
@@ -58,7 +315,7 @@ impl ConnectionFilter {
             }
         }
 
-        for port_range in self.port_filter.blocked_ports.iter() {
+        for port_range in rules.port_filter.blocked_ports.iter() {
             if port.min(port_range.start) == port_range.start
                 && port.max(port_range.end) == port_range.end
             {
@@ -68,3 +325,185 @@ impl ConnectionFilter {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::types::IpRange;
+
+    fn cidr_filter(allowlist: &[&str], denylist: &[&str]) -> ConnectionFilter {
+        let ip_filter = IpFilter {
+            allowlist: allowlist.iter().map(|c| c.parse().unwrap()).collect(),
+            denylist: denylist.iter().map(|c| c.parse().unwrap()).collect(),
+            ..IpFilter::default()
+        };
+        ConnectionFilter::new(ip_filter, PortFilter::default())
+    }
+
+    #[test]
+    fn denylist_rejects_matching_v4_and_v6_sources() {
+        let filter = cidr_filter(&[], &["192.0.2.0/24", "2001:db8::/32"]);
+
+        assert!(!filter.should_accept_connection(&"192.0.2.77".parse().unwrap(), 2222));
+        assert!(!filter.should_accept_connection(&"2001:db8:1::5".parse().unwrap(), 2222));
+        assert!(!filter.should_accept_connection(&"::ffff:192.0.2.1".parse().unwrap(), 2222));
+        assert!(filter.should_accept_connection(&"198.51.100.1".parse().unwrap(), 2222));
+    }
+
+    #[test]
+    fn allowlist_restricts_sources_and_denylist_wins() {
+        let filter = cidr_filter(&["10.0.0.0/8"], &["10.1.0.0/16"]);
+
+        assert!(filter.should_accept_connection(&"10.2.3.4".parse().unwrap(), 2222));
+        assert!(!filter.should_accept_connection(&"10.1.3.4".parse().unwrap(), 2222));
+        assert!(!filter.should_accept_connection(&"172.16.0.1".parse().unwrap(), 2222));
+    }
+
+    #[test]
+    fn ipv4_mapped_sources_match_ipv4_ranges() {
+        let ip_filter = IpFilter {
+            blocked_ranges: vec![IpRange {
+                start: "192.0.2.0".parse().unwrap(),
+                end: "192.0.2.255".parse().unwrap(),
+            }],
+            ..IpFilter::default()
+        };
+        let filter = ConnectionFilter::new(ip_filter, PortFilter::default());
+
+        assert!(!filter.should_accept_connection(&"::ffff:192.0.2.9".parse().unwrap(), 2222));
+        assert!(filter.should_accept_connection(&"2001:db8::9".parse().unwrap(), 2222));
+    }
+
+    fn filter(rate_limit: RateLimitConfig) -> ConnectionFilter {
+        ConnectionFilter::default().with_rate_limit(rate_limit, &[])
+    }
+
+    #[test]
+    fn unlimited_by_default() {
+        let filter = ConnectionFilter::default();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+        let permits: Vec<_> = (0..100).map(|_| filter.admit(&ip, 22).unwrap()).collect();
+        assert_eq!(permits.len(), 100);
+    }
+
+    #[test]
+    fn per_ip_rate_is_tracked_per_source_and_port() {
+        let filter = filter(RateLimitConfig {
+            connections_per_ip_per_minute: 2,
+            ..RateLimitConfig::default()
+        });
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+
+        assert!(filter.admit(&ip, 22).is_ok());
+        assert!(filter.admit(&ip, 22).is_ok());
+        assert_eq!(
+            filter.admit(&ip, 22).unwrap_err().reason,
+            RateLimitReason::PerIpRate
+        );
+
+        assert!(filter.admit(&ip, 80).is_ok());
+        assert!(filter.admit(&other, 22).is_ok());
+    }
+
+    #[test]
+    fn ipv6_sources_share_the_rate_of_their_prefix() {
+        let filter = filter(RateLimitConfig {
+            connections_per_ip_per_minute: 2,
+            ..RateLimitConfig::default()
+        });
+
+        assert!(filter
+            .admit(&"2001:db8:1:2::1".parse().unwrap(), 22)
+            .is_ok());
+        assert!(filter
+            .admit(&"2001:db8:1:2::2".parse().unwrap(), 22)
+            .is_ok());
+        assert!(filter
+            .admit(&"2001:db8:1:2::3".parse().unwrap(), 22)
+            .is_err());
+        assert!(filter
+            .admit(&"2001:db8:1:3::1".parse().unwrap(), 22)
+            .is_ok());
+    }
+
+    #[test]
+    fn least_recently_seen_sources_are_evicted_past_the_cap() {
+        let filter = filter(RateLimitConfig {
+            connections_per_ip_per_minute: 1,
+            ..RateLimitConfig::default()
+        });
+        let first: IpAddr = "203.0.113.5".parse().unwrap();
+        let start = Instant::now();
+        assert!(filter.record_connection_at(first, 22, 1, start));
+        assert!(!filter.record_connection_at(first, 22, 1, start));
+
+        // Within the window, so that none of them is forgotten as idle
+        for i in 0..MAX_TRACKED_SOURCES as u32 {
+            let ip = IpAddr::V4((0x0a00_0000 + i).into());
+            filter.record_connection_at(ip, 22, 1, start + Duration::from_secs(1));
+        }
+
+        let recent = filter.recent_connections.lock().unwrap();
+        assert_eq!(recent.hits.len(), MAX_TRACKED_SOURCES);
+        assert_eq!(recent.by_last_hit.len(), MAX_TRACKED_SOURCES);
+        assert!(!recent.hits.contains_key(&(first, 22)));
+    }
+
+    #[test]
+    fn service_override_replaces_global_rate() {
+        let service = ServiceConfig {
+            port: 22,
+            connections_per_ip_per_minute: Some(1),
+            ..ServiceConfig::default()
+        };
+        let filter = ConnectionFilter::default().with_rate_limit(
+            RateLimitConfig {
+                connections_per_ip_per_minute: 10,
+                action: RateLimitAction::Tarpit,
+                ..RateLimitConfig::default()
+            },
+            &[service],
+        );
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+        assert!(filter.admit(&ip, 22).is_ok());
+        let limited = filter.admit(&ip, 22).unwrap_err();
+        assert_eq!(limited.action, RateLimitAction::Tarpit);
+        assert!(filter.admit(&ip, 8080).is_ok());
+    }
+
+    #[test]
+    fn concurrent_cap_is_shared_by_clones_and_released_on_drop() {
+        let filter = filter(RateLimitConfig {
+            max_concurrent_connections: 1,
+            ..RateLimitConfig::default()
+        });
+        let clone = filter.clone();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+        let permit = filter.admit(&ip, 22).unwrap();
+        assert_eq!(
+            clone.admit(&ip, 80).unwrap_err().reason,
+            RateLimitReason::ConcurrentCap
+        );
+
+        drop(permit);
+        assert!(clone.admit(&ip, 80).is_ok());
+    }
+
+    #[test]
+    fn reconfigure_applies_to_clones() {
+        let filter = ConnectionFilter::default();
+        let clone = filter.clone();
+        let ip: IpAddr = "192.0.2.10".parse().unwrap();
+        assert!(clone.should_accept_connection(&ip, 2222));
+
+        filter.reconfigure(&cidr_filter(&[], &["192.0.2.0/24"]));
+        assert!(!clone.should_accept_connection(&ip, 2222));
+
+        filter.reconfigure(&ConnectionFilter::default());
+        assert!(clone.should_accept_connection(&ip, 2222));
+    }
+}
@@ -1,11 +1,12 @@
 //! # Network Listener Module
 //!
 //! This module provides network listening capacities for handling incoming TCP connections and
-//! routing them to appropriate service based on detected protocol
+//! UDP flows, and routing them to appropriate service based on detected protocol
 //!
 //! The main component is [`NetworkListener`] which manages multiple TCP sockets, detects incoming
 //! service types, filters connections, and forwards valid sessions to the [`SessionManager`] via
-//! [`SessionRequest`] through an async channel.
+//! [`SessionRequest`] through an async channel. UDP services are forwarded as
+//! [`UdpSessionRequest`] on a second channel, see [`NetworkListener::with_udp_sessions`].
 //!
 //! ## Architecture
 //!
@@ -15,6 +16,7 @@
 //! │ Connections     │    │                  │    │ (via mpsc)      │
 //! └─────────────────┘    │ - Service Detection   └─────────────────┘
 //!                        │ - Connection Filter
+//!                        │ - TLS Termination
 //!                        │ - Protocol Analysis
 //!                        └──────────────────┘
 //! ```
@@ -50,26 +52,199 @@
 //!     let copy = listener.extract_for_listening();
 //!
 //!     // Start listening for connections
-//!     NetworkListener::start_listening(copy, Ipv4Addr::new(0, 0, 0, 0)).await?;
+//!     NetworkListener::start_listening(copy, Ipv4Addr::UNSPECIFIED.into()).await?;
 //!
 //!     Ok(())
 //! }
 //! ```
 
 use super::connection_filter::*;
+use super::proxy_protocol;
+use super::redirect;
+use super::scan_detector::{ScanDetector, ScanVerdict};
 use super::service_detector::*;
-use super::types::SessionRequest;
-use crate::configuration::types::ServiceConfig;
+use super::tarpit::Tarpit;
+use super::tls;
+use super::types::{ClientStream, SessionRequest, UdpSessionRequest};
+use crate::configuration::types::{Cidr, Protocol, RedirectMode, ServiceConfig};
 use crate::error_handling::types::NetworkError;
+use crate::events::{self, Event};
+use crate::metrics;
+use crate::telemetry;
 
+use crate::configuration::types::RateLimitAction;
 use chrono::Utc;
-use log::{debug, error, info, warn};
+use socket2::{Domain, Socket, Type};
 use std::collections::HashMap;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::net::{TcpListener, TcpSocket, TcpStream};
-use tokio::sync::{broadcast, mpsc::Sender};
+use tokio::net::{TcpListener, TcpSocket, TcpStream, UdpSocket};
+use tokio::sync::{broadcast, mpsc, mpsc::Sender};
 use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, info, info_span, warn, Instrument, Span};
+
+/// Time a client has to complete the TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time a port listener has to stop once signaled before its task is aborted
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `addr` with IPv4-mapped IPv6 addresses, of IPv4 clients of dual-stack sockets,
+/// turned back into IPv4 ones
+fn canonical(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+/// How the connections accepted on a port are served
+enum PortService {
+    /// Detected and handed to a session, behind TLS with an acceptor
+    Session(Option<TlsAcceptor>),
+    /// Held by a tarpit, without session
+    Tarpit(Tarpit),
+}
+
+/// What the connections accepted on a port are handed to, shared with the tasks
+/// reading their PROXY protocol header
+struct PortContext {
+    session_tx: Sender<SessionRequest>,
+    service_detector: ServiceDetector,
+    connection_filter: ConnectionFilter,
+    port_service: PortService,
+    port: u16,
+    /// Proxies whose connections open with a PROXY protocol header, see
+    /// [`proxy_protocol`]. `None` when the port does not expect the header
+    trusted_proxies: Option<Vec<Cidr>>,
+    /// How the destination of connections redirected to this port is recovered,
+    /// see [`redirect`]
+    redirect: RedirectMode,
+}
+
+impl PortContext {
+    /// Runs the filter and rate limits on a connection from `client_addr`, handing
+    /// the stream back with its permit unless it is refused
+    fn admit(
+        &self,
+        stream: TcpStream,
+        client_addr: SocketAddr,
+    ) -> Option<(TcpStream, ConnectionPermit)> {
+        let connection_filter = &self.connection_filter;
+        let port = self.port;
+
+        // Check if connection should be accepted
+        if !connection_filter.should_accept_connection(&client_addr.ip(), port) {
+            debug!(
+                "Connection from {} on port {} rejected by filter",
+                client_addr, port
+            );
+            metrics::global().connection_rejected(port, "filtered");
+            return None;
+        }
+
+        match connection_filter.admit(&client_addr.ip(), port) {
+            Ok(permit) => Some((stream, permit)),
+            Err(limited) => {
+                metrics::global().connection_rejected(port, "rate_limited");
+                NetworkListener::report_rate_limited(client_addr, port, &limited);
+                if limited.action == RateLimitAction::Tarpit {
+                    NetworkListener::tarpit(stream, client_addr, connection_filter);
+                }
+                None
+            }
+        }
+    }
+
+    /// Filters a connection from `client_addr` and serves it unless it is refused
+    fn accept(&self, stream: TcpStream, client_addr: SocketAddr) {
+        let span = telemetry::session_span(client_addr);
+        let accepting = info_span!(parent: &span, "accept", port = self.port).entered();
+        let connection_filter = &self.connection_filter;
+        let port = self.port;
+        let original_dst = redirect::original_destination(&stream, self.redirect, port);
+        // The port the client targeted, which the listener port stands for
+        let targeted_port = original_dst.map_or(port, |dst| dst.port());
+
+        let Some((stream, permit)) = self.admit(stream, client_addr) else {
+            return;
+        };
+
+        metrics::global().connection_accepted(port, "tcp");
+        let verdict = connection_filter
+            .scan_detector()
+            .observe(client_addr.ip(), targeted_port);
+
+        let tls_acceptor = match &self.port_service {
+            PortService::Session(_) if verdict == ScanVerdict::Suppress => {
+                debug!(
+                    "Dropping connection from scanner {} on port {}",
+                    client_addr, port
+                );
+                metrics::global().connection_rejected(port, "scanner");
+                return;
+            }
+            PortService::Session(tls_acceptor) => tls_acceptor.clone(),
+            // Tarpit ports get neither detection nor container
+            PortService::Tarpit(tarpit) => {
+                match connection_filter.enter_tarpit() {
+                    Some(slot) => tarpit.spawn(stream, client_addr, targeted_port, slot, permit),
+                    None => debug!("Tarpit full, dropping connection from {}", client_addr),
+                }
+                return;
+            }
+        };
+
+        // Clone components for the connection handling task
+        let session_tx = self.session_tx.clone();
+        let service_detector = self.service_detector.clone();
+        drop(accepting);
+
+        tokio::spawn(
+            async move {
+                if let Err(e) = NetworkListener::handle_connection(
+                    stream,
+                    client_addr,
+                    original_dst,
+                    permit,
+                    session_tx,
+                    service_detector,
+                    tls_acceptor,
+                )
+                .await
+                {
+                    error!("Failed to handle connection from {}: {}", client_addr, e);
+                }
+            }
+            .instrument(span),
+        );
+    }
+}
+
+/// Listener task serving one port
+struct PortListener {
+    /// Sending on it makes the task stop accepting and release the port
+    stop_tx: broadcast::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl PortListener {
+    /// Signals the task and waits for it to end, aborting it after [`SHUTDOWN_TIMEOUT`]
+    async fn stop(self, port: u16) {
+        let PortListener {
+            stop_tx,
+            mut handle,
+        } = self;
+        let _ = stop_tx.send(());
+        match tokio::time::timeout(SHUTDOWN_TIMEOUT, &mut handle).await {
+            Ok(Ok(())) => debug!("Listener on port {} shut down", port),
+            Ok(Err(e)) => error!("Listener on port {} failed during shutdown: {}", port, e),
+            Err(_) => {
+                error!("Listener on port {} shutdown timed out, aborting it", port);
+                handle.abort();
+                let _ = handle.await;
+            }
+        }
+    }
+}
 
 /// A network listener that manages multiple TCP socket and routes connections to services.
 ///
@@ -77,6 +252,7 @@ use tokio::task::JoinHandle;
 /// - Binding to multiple ports based on service configuration
 /// - Detecting the type of incoming service requests
 /// - Filtering connections based on security policies
+/// - Terminating TLS for the services configured with it
 /// - Forwarding valid sessions to the session manager
 ///
 /// The listener operates asynchronously and uses an MPSC channel to communicate with the session
@@ -102,23 +278,39 @@ use tokio::task::JoinHandle;
 /// }
 /// ```
 pub struct NetworkListener {
-    /// Map of port number to their corresponding TCP sockets
-    listeners: HashMap<u16, TcpSocket>,
+    /// Ports of the TCP services, bound when listening starts
+    tcp_ports: Vec<u16>,
+
+    /// Ports of the UDP services, bound when listening starts
+    udp_ports: Vec<u16>,
 
     /// Channel sender for forwarding session requests to the session manager
     session_tx: Sender<SessionRequest>,
 
+    /// Channel sender for new UDP flows, UDP services are not served without it
+    udp_session_tx: Option<Sender<UdpSessionRequest>>,
+
     /// Service detection component for identifying connection protocols
     service_detector: ServiceDetector,
 
     /// Connection filtering component for security and access control
     connection_filter: ConnectionFilter,
 
-    /// Channel sender for broadcasting shutdown order
-    shutdown_tx: Option<broadcast::Sender<()>>,
+    /// Running listener tasks, by port, each stopped through its own shutdown channel
+    port_listeners: HashMap<u16, PortListener>,
+
+    /// Services currently bound, by port
+    services: HashMap<u16, ServiceConfig>,
 
-    /// Handles on the listeners to handle shutdown comprehensively
-    listener_handles: Vec<JoinHandle<()>>,
+    /// TLS terminators of the services configured with TLS, by port
+    tls_acceptors: HashMap<u16, TlsAcceptor>,
+
+    /// Sockets bound to an IPv6 address refuse IPv4 clients instead of accepting them
+    /// as IPv4-mapped addresses
+    ipv6_only: bool,
+
+    /// How the `redirect_ports` of the services reach their listeners
+    redirect_mode: RedirectMode,
 }
 
 impl NetworkListener {
@@ -143,62 +335,159 @@ impl NetworkListener {
     /// let listener = NetworkListener::new(tx);
     /// ```
     pub fn new(session_tx: Sender<SessionRequest>) -> Self {
-        let (shutdown_tx, _) = broadcast::channel(1);
-
         Self {
-            listeners: HashMap::new(),
+            tcp_ports: Vec::new(),
+            udp_ports: Vec::new(),
             session_tx,
+            udp_session_tx: None,
             service_detector: ServiceDetector {
                 service_patterns: HashMap::new(),
             },
             connection_filter: ConnectionFilter::default(),
-            shutdown_tx: Some(shutdown_tx),
-            listener_handles: Vec::new(),
+            port_listeners: HashMap::new(),
+            services: HashMap::new(),
+            tls_acceptors: HashMap::new(),
+            ipv6_only: false,
+            redirect_mode: RedirectMode::default(),
         }
     }
 
+    /// Replaces the default accept-all filter, e.g. with the filter built from [`Config`].
+    ///
+    /// [`Config`]: crate::configuration::config::Config
+    pub fn with_connection_filter(mut self, connection_filter: ConnectionFilter) -> Self {
+        self.connection_filter = connection_filter;
+        self
+    }
+
+    /// Keeps the sockets bound to an IPv6 address from accepting IPv4 clients, which
+    /// they accept by default
+    pub fn with_ipv6_only(mut self, ipv6_only: bool) -> Self {
+        self.ipv6_only = ipv6_only;
+        self
+    }
+
+    /// Sets how the `redirect_ports` of the services are redirected, the listeners of
+    /// these services being transparent sockets with [`RedirectMode::Tproxy`]
+    pub fn with_redirect_mode(mut self, redirect_mode: RedirectMode) -> Self {
+        self.redirect_mode = redirect_mode;
+        self
+    }
+
+    /// Sets the channel on which new UDP flows are forwarded as [`UdpSessionRequest`].
+    pub fn with_udp_sessions(mut self, udp_session_tx: Sender<UdpSessionRequest>) -> Self {
+        self.udp_session_tx = Some(udp_session_tx);
+        self
+    }
+
     pub fn extract_for_listening(&mut self) -> Self {
-        let listeners = std::mem::take(&mut self.listeners);
+        let tcp_ports = std::mem::take(&mut self.tcp_ports);
+        let udp_ports = std::mem::take(&mut self.udp_ports);
         let session_tx = self.session_tx.clone();
+        let udp_session_tx = self.udp_session_tx.clone();
         let service_detector = self.service_detector.clone();
         let connection_filter = self.connection_filter.clone();
-        let shutdown_tx = self.shutdown_tx.as_ref().unwrap().clone();
 
         Self {
-            listeners,
+            tcp_ports,
+            udp_ports,
             session_tx,
+            udp_session_tx,
             service_detector,
             connection_filter,
-            shutdown_tx: Some(shutdown_tx),
-            listener_handles: Vec::new(),
+            port_listeners: HashMap::new(),
+            services: self.services.clone(),
+            tls_acceptors: self.tls_acceptors.clone(),
+            ipv6_only: self.ipv6_only,
+            redirect_mode: self.redirect_mode,
         }
     }
 
-    /// Binds TCP sockets to the ports specified in the service configurations.
+    /// Records the services to listen for.
     ///
-    /// This method creates and configures TCP sockets for each service, storing them in the
-    /// internal listeners map. It also initializes the service detector with the provided service
-    /// configurations
+    /// The ports of the services are bound by [`NetworkListener::start_listening`] or
+    /// [`NetworkListener::listen`], once the address and so the family of their sockets
+    /// is known. TLS acceptors are set up here, and the service detector is initialized
+    /// with the provided service configurations
     pub fn bind_services(&mut self, services: &[ServiceConfig]) -> Result<(), NetworkError> {
         self.service_detector = ServiceDetector::new(services);
 
         let services_it = services.iter();
 
         for s in services_it {
-            let socket = match TcpSocket::new_v4() {
-                Ok(sock) => sock,
-                Err(err) => {
-                    error!("Failed to create TCP socket for port {}: {}", s.port, err);
-                    return Err(NetworkError::SockError(err));
-                }
-            };
-            self.listeners.insert(s.port, socket);
+            self.services.insert(s.port, s.clone());
+
+            if s.protocol == Protocol::UDP {
+                self.udp_ports.push(s.port);
+                continue;
+            }
+
+            self.prepare_tls(s)?;
+            self.tcp_ports.push(s.port);
         }
 
         debug!("Service bindings configured for {} ports", services.len());
         Ok(())
     }
 
+    fn prepare_tls(&mut self, service: &ServiceConfig) -> Result<(), NetworkError> {
+        match &service.tls {
+            Some(tls_config) => {
+                let acceptor = tls::build_acceptor(tls_config).inspect_err(|e| {
+                    error!("Failed to set up TLS for service {}: {}", service.name, e)
+                })?;
+                self.tls_acceptors.insert(service.port, acceptor);
+            }
+            None => {
+                self.tls_acceptors.remove(&service.port);
+            }
+        }
+        Ok(())
+    }
+
+    /// Address `port` is bound to, the `bind_address` of its service or `bind_addr`
+    fn service_address(&self, port: u16, bind_addr: IpAddr) -> SocketAddr {
+        let service_addr = self.services.get(&port).and_then(|s| s.bind_address);
+        SocketAddr::new(service_addr.unwrap_or(bind_addr), port)
+    }
+
+    /// Non-blocking socket of the family of `bind_addr`, IPv6 ones being dual-stack
+    /// unless `ipv6_only` is set, tied to the `bind_interface` of the service of its port
+    /// and transparent when the service gets ports redirected with `tproxy`
+    fn new_socket(&self, bind_addr: SocketAddr, ty: Type) -> std::io::Result<Socket> {
+        let socket = Socket::new(Domain::for_address(bind_addr), ty, None)?;
+        if bind_addr.is_ipv6() {
+            socket.set_only_v6(self.ipv6_only)?;
+        }
+        let service = self.services.get(&bind_addr.port());
+        if let Some(interface) = service.and_then(|s| s.bind_interface.as_deref()) {
+            socket.bind_device(Some(interface.as_bytes()))?;
+        }
+        if ty == Type::STREAM
+            && self.redirect_mode == RedirectMode::Tproxy
+            && service.is_some_and(redirect::is_target)
+        {
+            redirect::set_transparent(&socket, bind_addr.is_ipv6())?;
+        }
+        socket.set_nonblocking(true)?;
+        Ok(socket)
+    }
+
+    fn new_tcp_socket(&self, bind_addr: SocketAddr) -> Result<TcpSocket, NetworkError> {
+        let socket = self
+            .new_socket(bind_addr, Type::STREAM)
+            .map(|socket| TcpSocket::from_std_stream(socket.into()))
+            .map_err(|err| {
+                error!("Failed to create TCP socket for {}: {}", bind_addr, err);
+                NetworkError::SockError(err)
+            })?;
+        // Lets a port be bound again right after its listener stopped (reload, restart)
+        socket
+            .set_reuseaddr(true)
+            .map_err(NetworkError::SockError)?;
+        Ok(socket)
+    }
+
     /// Starts listening for incoming connections and processes them.
     ///
     /// This method begins the main listening loop, accepting incoming connections, performing
@@ -241,80 +530,292 @@ impl NetworkListener {
     ///
     ///     listener.bind_services(&[ServiceConfig::default()])?;
     ///     let copy = listener.extract_for_listening();
-    ///     NetworkListener::start_listening(copy, Ipv4Addr::new(127, 0, 0, 1));
+    ///     NetworkListener::start_listening(copy, Ipv4Addr::LOCALHOST.into());
     ///
     ///     Ok(())
     /// }
     /// ```
-    pub async fn start_listening(mut copy: Self, bind_addr: Ipv4Addr) -> Result<(), NetworkError> {
-        let mut listener_handles = Vec::new();
+    pub async fn start_listening(mut copy: Self, bind_addr: IpAddr) -> Result<(), NetworkError> {
+        copy.listen(bind_addr).await?;
 
+        for (_, port_listener) in copy.port_listeners.drain() {
+            if let Err(e) = port_listener.handle.await {
+                error!("Network listener task failed: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Binds every bound service and spawns its listener task without waiting on it.
+    ///
+    /// Unlike [`NetworkListener::start_listening`], the listener keeps the task handles, so
+    /// it can later [`reload_services`](NetworkListener::reload_services), add or remove
+    /// single services, or [`shutdown`](NetworkListener::shutdown).
+    pub async fn listen(&mut self, bind_addr: IpAddr) -> Result<(), NetworkError> {
         info!("Starting network listeners on {}", bind_addr);
 
         // Bind all sockets and create listeners
-        for (port, socket) in copy.listeners.drain() {
-            debug!("Binding service listener to {}:{}", bind_addr, port);
+        for port in std::mem::take(&mut self.tcp_ports) {
+            let port_listener = self.spawn_tcp_listener(port, bind_addr)?;
+            self.port_listeners.insert(port, port_listener);
+        }
 
-            // Bind socket to the bind_address with port specified in the ServiceConfig
-            if let Err(e) = socket.bind(SocketAddr::new(IpAddr::V4(bind_addr), port)) {
-                error!("Failed to bind to port {}: {}", port, e);
-                return Err(NetworkError::BindError(e));
+        // Bind UDP services, each port is a single socket shared by all client flows
+        for port in std::mem::take(&mut self.udp_ports) {
+            if let Some(port_listener) = self.spawn_udp_listener(port, bind_addr).await? {
+                self.port_listeners.insert(port, port_listener);
             }
+        }
 
-            // Convert to listener
-            let listener = match socket.listen(1024) {
-                Ok(listener) => listener,
-                Err(e) => {
-                    error!("Failed to listen on port {}: {}", port, e);
-                    return Err(NetworkError::BindError(e));
-                }
-            };
+        info!(
+            "Network listeners started on {} ports",
+            self.port_listeners.len()
+        );
+        Ok(())
+    }
 
-            debug!("Service listener bound to port {}", port);
+    /// Applies a new service list to running listeners.
+    ///
+    /// Ports of removed or modified services stop listening, ports of new or modified
+    /// services are bound. Listeners of unchanged services keep running, and connections
+    /// already handed to the session manager are never affected. Every port is attempted;
+    /// the first bind failure is returned.
+    pub async fn reload_services(
+        &mut self,
+        services: &[ServiceConfig],
+        bind_addr: IpAddr,
+    ) -> Result<(), NetworkError> {
+        let new_services: HashMap<u16, ServiceConfig> =
+            services.iter().map(|s| (s.port, s.clone())).collect();
+
+        let stale: Vec<u16> = self
+            .services
+            .iter()
+            .filter(|(port, service)| new_services.get(port) != Some(*service))
+            .map(|(port, _)| *port)
+            .collect();
+
+        for port in stale {
+            self.remove_service(port).await;
+        }
 
-            // Clone components used for the async listening session
-            let session_tx_clone = copy.session_tx.clone();
-            let service_detector_clone = copy.service_detector.clone();
-            let connection_filter_clone = copy.connection_filter.clone();
-            let shutdown_rx_clone = copy.shutdown_tx.as_ref().unwrap().subscribe();
+        let mut first_error = None;
+        for service in services {
+            if self.services.contains_key(&service.port) {
+                continue;
+            }
+            if let Err(e) = self.add_service(service, bind_addr).await {
+                first_error.get_or_insert(e);
+            }
+        }
 
-            let handle = tokio::spawn(async move {
-                Self::listen_on_port(
-                    listener,
-                    session_tx_clone,
-                    service_detector_clone,
-                    connection_filter_clone,
-                    port,
-                    shutdown_rx_clone,
-                )
-                .await
-            });
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
 
-            listener_handles.push(handle);
+    /// Starts listening for `service` on its port while the other listeners keep running.
+    ///
+    /// # Errors
+    /// Returns [`NetworkError::BindError`] when a service already listens on that port or
+    /// the port cannot be bound, and the TLS setup error of a TLS service.
+    pub async fn add_service(
+        &mut self,
+        service: &ServiceConfig,
+        bind_addr: IpAddr,
+    ) -> Result<(), NetworkError> {
+        if self.services.contains_key(&service.port) {
+            return Err(NetworkError::BindError(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                format!("a service already listens on port {}", service.port),
+            )));
         }
 
         info!(
-            "Network listeners started on {} ports",
-            listener_handles.len()
+            "Starting listener for service {} on port {}",
+            service.name, service.port
         );
+        self.services.insert(service.port, service.clone());
+        self.refresh_service_detector();
+
+        let spawned = match service.protocol {
+            Protocol::TCP => self
+                .prepare_tls(service)
+                .and_then(|_| self.spawn_tcp_listener(service.port, bind_addr))
+                .map(Some),
+            Protocol::UDP => self.spawn_udp_listener(service.port, bind_addr).await,
+        };
 
-        for handle in listener_handles {
-            if let Err(e) = handle.await {
-                error!("Network listener task failed: {}", e);
+        match spawned {
+            Ok(Some(port_listener)) => {
+                self.port_listeners.insert(service.port, port_listener);
+                Ok(())
+            }
+            Ok(None) => Ok(()),
+            Err(e) => {
+                error!("Failed to start listener on port {}: {}", service.port, e);
+                self.services.remove(&service.port);
+                self.tls_acceptors.remove(&service.port);
+                self.refresh_service_detector();
+                Err(e)
             }
         }
+    }
 
-        Ok(())
+    /// Stops listening on `port` and forgets its service, which is returned.
+    ///
+    /// The listener task is signaled and given [`SHUTDOWN_TIMEOUT`] to release the port;
+    /// connections it already accepted are left to finish.
+    pub async fn remove_service(&mut self, port: u16) -> Option<ServiceConfig> {
+        let removed = self.services.remove(&port);
+        self.tls_acceptors.remove(&port);
+        if let Some(port_listener) = self.port_listeners.remove(&port) {
+            info!("Stopping listener on port {}", port);
+            port_listener.stop(port).await;
+        }
+        if removed.is_some() {
+            self.refresh_service_detector();
+        }
+        removed
+    }
+
+    /// Ports currently served, in ascending order
+    pub fn service_ports(&self) -> Vec<u16> {
+        let mut ports: Vec<u16> = self.services.keys().copied().collect();
+        ports.sort_unstable();
+        ports
+    }
+
+    fn refresh_service_detector(&mut self) {
+        let services: Vec<ServiceConfig> = self.services.values().cloned().collect();
+        self.service_detector = ServiceDetector::new(&services);
+    }
+
+    /// Replaces the filtering rules of the running listeners, see [`ConnectionFilter::reconfigure`]
+    pub fn update_connection_filter(&self, connection_filter: &ConnectionFilter) {
+        self.connection_filter.reconfigure(connection_filter);
+    }
+
+    /// Detector correlating the connections of all the ports
+    pub fn scan_detector(&self) -> &ScanDetector {
+        self.connection_filter.scan_detector()
+    }
+
+    fn spawn_tcp_listener(
+        &self,
+        port: u16,
+        bind_addr: IpAddr,
+    ) -> Result<PortListener, NetworkError> {
+        let bind_addr = self.service_address(port, bind_addr);
+        debug!("Binding service listener to {}", bind_addr);
+        let socket = self.new_tcp_socket(bind_addr)?;
+
+        // Bind socket to the bind_address with port specified in the ServiceConfig
+        if let Err(e) = socket.bind(bind_addr) {
+            error!("Failed to bind to port {}: {}", port, e);
+            return Err(NetworkError::BindError(e));
+        }
+
+        // Convert to listener
+        let listener = match socket.listen(1024) {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Failed to listen on port {}: {}", port, e);
+                return Err(NetworkError::BindError(e));
+            }
+        };
+
+        debug!("Service listener bound to port {}", port);
+
+        let service = self.services.get(&port);
+        let port_service = match service.and_then(Tarpit::for_service) {
+            Some(tarpit) => PortService::Tarpit(tarpit),
+            None => PortService::Session(self.tls_acceptors.get(&port).cloned()),
+        };
+        let context = PortContext {
+            session_tx: self.session_tx.clone(),
+            service_detector: self.service_detector.clone(),
+            connection_filter: self.connection_filter.clone(),
+            port_service,
+            port,
+            trusted_proxies: service
+                .filter(|service| service.proxy_protocol)
+                .map(|service| service.trusted_proxies.clone()),
+            redirect: match self.redirect_mode {
+                RedirectMode::Tproxy if service.is_some_and(redirect::is_target) => {
+                    RedirectMode::Tproxy
+                }
+                _ => RedirectMode::Redirect,
+            },
+        };
+        let (stop_tx, stop_rx) = broadcast::channel(1);
+
+        let handle = tokio::spawn(Self::listen_on_port(listener, Arc::new(context), stop_rx));
+        Ok(PortListener { stop_tx, handle })
+    }
+
+    async fn spawn_udp_listener(
+        &self,
+        port: u16,
+        bind_addr: IpAddr,
+    ) -> Result<Option<PortListener>, NetworkError> {
+        let Some(udp_session_tx) = self.udp_session_tx.clone() else {
+            warn!(
+                "No UDP session channel configured, skipping UDP service on port {}",
+                port
+            );
+            return Ok(None);
+        };
+
+        let Some(service_name) = self
+            .service_detector
+            .service_patterns
+            .get(&port)
+            .map(|pattern| pattern.service_name.clone())
+        else {
+            warn!("No service registered for UDP port {}", port);
+            return Ok(None);
+        };
+
+        let bind_addr = self.service_address(port, bind_addr);
+        debug!("Binding UDP service listener to {}", bind_addr);
+        let socket = self
+            .new_socket(bind_addr, Type::DGRAM)
+            .and_then(|socket| socket.bind(&bind_addr.into()).map(|_| socket))
+            .and_then(|socket| UdpSocket::from_std(socket.into()));
+        let socket = match socket {
+            Ok(socket) => Arc::new(socket),
+            Err(e) => {
+                error!("Failed to bind UDP port {}: {}", port, e);
+                return Err(NetworkError::BindError(e));
+            }
+        };
+
+        let connection_filter_clone = self.connection_filter.clone();
+        let (stop_tx, stop_rx) = broadcast::channel(1);
+
+        let handle = tokio::spawn(async move {
+            Self::listen_on_udp_port(
+                socket,
+                udp_session_tx,
+                service_name,
+                connection_filter_clone,
+                port,
+                stop_rx,
+            )
+            .await
+        });
+        Ok(Some(PortListener { stop_tx, handle }))
     }
 
     async fn listen_on_port(
         listener: TcpListener,
-        session_tx: Sender<SessionRequest>,
-        service_detector: ServiceDetector,
-        connection_filter: ConnectionFilter,
-        port: u16,
+        context: Arc<PortContext>,
         mut shutdown_rx: broadcast::Receiver<()>,
     ) {
+        let port = context.port;
         debug!("Network listener active on port {}", port);
 
         loop {
@@ -324,7 +825,7 @@ impl NetworkListener {
                     let (stream, client_addr) = match accept_result {
                         Ok((stream, addr)) => {
                             debug!("Connection received from {} on port {}", addr, port);
-                            (stream, addr)
+                            (stream, canonical(addr))
                         }
                         Err(e) => {
                             error!("Failed to accept connection on port {}: {}", port, e);
@@ -332,26 +833,32 @@ impl NetworkListener {
                         }
                     };
 
-                    // Check if connection should be accepted
-                    if !connection_filter.should_accept_connection(&client_addr.ip(), port) {
-                        debug!("Connection from {} on port {} rejected by filter", client_addr, port);
+                    let Some(trusted_proxies) = &context.trusted_proxies else {
+                        context.accept(stream, client_addr);
                         continue;
-                    }
-
-                    // Clone components for the connection handling task
-                    let session_tx_clone = session_tx.clone();
-                    let service_detector_clone = service_detector.clone();
+                    };
 
+                    // Anyone else could make up the client address
+                    if !trusted_proxies.iter().any(|cidr| cidr.contains(&client_addr.ip())) {
+                        debug!("Dropping connection from untrusted proxy {}", client_addr);
+                        metrics::global().connection_rejected(port, "untrusted_proxy");
+                        continue;
+                    }
+                    // The proxy itself is filtered before its header is waited for,
+                    // then the client address it gives once the header is read
+                    let Some((mut stream, proxy_permit)) = context.admit(stream, client_addr) else {
+                        continue;
+                    };
+                    let context = Arc::clone(&context);
                     tokio::spawn(async move {
-                        if let Err(e) = Self::handle_connection(
-                            stream,
-                            client_addr,
-                            session_tx_clone,
-                            service_detector_clone,
-                        )
-                        .await
-                        {
-                            error!("Failed to handle connection from {}: {}", client_addr, e);
+                        let header = proxy_protocol::read_header(&mut stream).await;
+                        drop(proxy_permit);
+                        match header {
+                            Ok(source) => context.accept(stream, source.unwrap_or(client_addr)),
+                            Err(e) => {
+                                debug!("Dropping connection from proxy {}: {}", client_addr, e);
+                                metrics::global().connection_rejected(port, "proxy_protocol");
+                            }
                         }
                     });
                 }
@@ -366,47 +873,186 @@ impl NetworkListener {
         debug!("Network listener on port {} stopped", port);
     }
 
-    pub async fn shutdown(&mut self) -> Result<(), NetworkError> {
-        debug!("Initiating network listener shutdown");
+    /// Emits the structured `connection_rate_limited` event for an over-limit connection
+    fn report_rate_limited(client_addr: SocketAddr, port: u16, limited: &RateLimited) {
+        let action = match limited.action {
+            RateLimitAction::Drop => "drop",
+            RateLimitAction::Tarpit => "tarpit",
+        };
+        warn!(
+            "Connection from {} on port {} rate limited ({}), action: {}",
+            client_addr, port, limited.reason, action
+        );
+        events::emit(Event::ConnectionRateLimited {
+            client_addr,
+            port,
+            reason: limited.reason.to_string(),
+            action: action.to_string(),
+        });
+    }
+
+    /// Holds an over-limit connection open without ever answering, then closes it.
+    ///
+    /// The connection is dropped right away when the tarpit is already full.
+    fn tarpit(stream: TcpStream, client_addr: SocketAddr, connection_filter: &ConnectionFilter) {
+        let Some(tarpit_permit) = connection_filter.enter_tarpit() else {
+            debug!("Tarpit full, dropping connection from {}", client_addr);
+            return;
+        };
+        let duration = connection_filter.tarpit_duration();
+
+        tokio::spawn(async move {
+            let _held = (stream, tarpit_permit);
+            tokio::time::sleep(duration).await;
+            debug!("Released tarpitted connection from {}", client_addr);
+        });
+    }
+
+    /// Receives datagrams on a UDP service port and demultiplexes them per client.
+    ///
+    /// The first datagram from an unknown client address opens a flow announced as a
+    /// [`UdpSessionRequest`]; following datagrams are queued on that flow. A flow whose
+    /// receiver was dropped is forgotten, the next datagram from that client opens a new
+    /// one. Datagrams arriving while a flow's queue is full are dropped.
+    async fn listen_on_udp_port(
+        socket: Arc<UdpSocket>,
+        udp_session_tx: Sender<UdpSessionRequest>,
+        service_name: String,
+        connection_filter: ConnectionFilter,
+        port: u16,
+        mut shutdown_rx: broadcast::Receiver<()>,
+    ) {
+        debug!("UDP listener active on port {}", port);
+
+        let mut flows: HashMap<SocketAddr, Sender<Vec<u8>>> = HashMap::new();
+        let mut buf = vec![0u8; 64 * 1024];
 
-        if let Some(shutdown_tx) = &self.shutdown_tx {
-            if shutdown_tx.send(()).is_err() {
-                debug!("No active listeners found during shutdown");
-            } else {
-                debug!("Shutdown signal sent to all listeners");
+        loop {
+            tokio::select! {
+                recv_result = socket.recv_from(&mut buf) => {
+                    let (n, client_addr) = match recv_result {
+                        Ok((n, addr)) => (n, canonical(addr)),
+                        Err(e) => {
+                            error!("Failed to receive datagram on port {}: {}", port, e);
+                            continue;
+                        }
+                    };
+
+                    if !connection_filter.should_accept_connection(&client_addr.ip(), port) {
+                        debug!("Datagram from {} on port {} rejected by filter", client_addr, port);
+                        metrics::global().connection_rejected(port, "filtered");
+                        continue;
+                    }
+
+                    let mut datagram = buf[..n].to_vec();
+
+                    if let Some(flow_tx) = flows.get(&client_addr) {
+                        match flow_tx.try_send(datagram) {
+                            Ok(()) => continue,
+                            Err(mpsc::error::TrySendError::Full(_)) => {
+                                debug!("UDP flow from {} is full, dropping datagram", client_addr);
+                                continue;
+                            }
+                            Err(mpsc::error::TrySendError::Closed(returned)) => {
+                                flows.remove(&client_addr);
+                                datagram = returned;
+                            }
+                        }
+                    }
+
+                    // Rate limits apply to new flows, datagrams cannot be tarpitted
+                    let permit = match connection_filter.admit(&client_addr.ip(), port) {
+                        Ok(permit) => permit,
+                        Err(limited) => {
+                            metrics::global().connection_rejected(port, "rate_limited");
+                            Self::report_rate_limited(client_addr, port, &limited);
+                            continue;
+                        }
+                    };
+
+                    metrics::global().connection_accepted(port, "udp");
+                    Self::open_udp_flow(&mut flows, &socket, &udp_session_tx, &service_name, client_addr, datagram, permit).await;
+                }
+
+                _ = shutdown_rx.recv() => {
+                    debug!("Shutdown signal received for UDP port {}", port);
+                    break;
+                }
             }
         }
 
-        let timeout_duration = Duration::from_secs(5);
-        let mut shutdown_tasks = Vec::new();
+        debug!("UDP listener on port {} stopped", port);
+    }
+
+    async fn open_udp_flow(
+        flows: &mut HashMap<SocketAddr, Sender<Vec<u8>>>,
+        socket: &Arc<UdpSocket>,
+        udp_session_tx: &Sender<UdpSessionRequest>,
+        service_name: &str,
+        client_addr: SocketAddr,
+        first_datagram: Vec<u8>,
+        permit: ConnectionPermit,
+    ) {
+        // Forget flows whose session already ended
+        flows.retain(|_, flow_tx| !flow_tx.is_closed());
 
-        for handle in self.listener_handles.drain(..) {
-            shutdown_tasks.push(handle);
+        let (flow_tx, flow_rx) = mpsc::channel(64);
+        // Cannot fail, the receiver is alive and the queue empty
+        let _ = flow_tx.try_send(first_datagram);
+
+        let request = UdpSessionRequest {
+            socket: Arc::clone(socket),
+            datagrams: flow_rx,
+            service_name: service_name.to_string(),
+            client_addr,
+            timestamp: Utc::now(),
+            permit: Some(permit),
+            span: telemetry::session_span(client_addr),
+        };
+        request.span.record("service", service_name);
+
+        let port = socket
+            .local_addr()
+            .map(|addr| addr.port())
+            .unwrap_or_default();
+        if !matches!(
+            Self::queue_request(udp_session_tx, request, client_addr, port),
+            Ok(true)
+        ) {
+            return;
         }
 
-        if !shutdown_tasks.is_empty() {
+        debug!("UDP session request sent for {}", client_addr);
+        events::emit(Event::ServiceDetected {
+            client_addr,
+            port,
+            service: service_name.to_string(),
+            sni: None,
+        });
+        flows.insert(client_addr, flow_tx);
+    }
+
+    /// Stops every listener, waiting up to [`SHUTDOWN_TIMEOUT`] for each to release its port.
+    ///
+    /// Services stay registered, connections already accepted are left to finish.
+    pub async fn shutdown(&mut self) -> Result<(), NetworkError> {
+        debug!("Initiating network listener shutdown");
+
+        // Signal every port first so that they all wind down concurrently
+        for port_listener in self.port_listeners.values() {
+            let _ = port_listener.stop_tx.send(());
+        }
+
+        if !self.port_listeners.is_empty() {
             debug!(
                 "Waiting for {} listeners to shut down",
-                shutdown_tasks.len()
+                self.port_listeners.len()
             );
-
-            for (i, handle) in shutdown_tasks.into_iter().enumerate() {
-                match tokio::time::timeout(timeout_duration, handle).await {
-                    Ok(Ok(())) => {
-                        debug!("Listener {} shutdown completed", i + 1);
-                    }
-                    Ok(Err(e)) => {
-                        error!("Listener {} failed during shutdown: {}", i + 1, e);
-                    }
-                    Err(_) => {
-                        error!("Listener {} shutdown timed out", i + 1);
-                    }
-                }
-            }
+        }
+        for (port, port_listener) in self.port_listeners.drain() {
+            port_listener.stop(port).await;
         }
 
-        // Clear the shutdown channel
-        self.shutdown_tx = None;
         info!("Network listener shutdown completed");
         Ok(())
     }
@@ -414,11 +1060,47 @@ impl NetworkListener {
     async fn handle_connection(
         mut stream: TcpStream,
         client_addr: SocketAddr,
+        original_dst: Option<SocketAddr>,
+        permit: ConnectionPermit,
         session_tx: Sender<SessionRequest>,
         service_detector: ServiceDetector,
+        tls_acceptor: Option<TlsAcceptor>,
     ) -> Result<(), NetworkError> {
         debug!("Identifying service for connection from {}", client_addr);
-        let service_name = match service_detector.identify_service(&mut stream).await {
+
+        let (client_hello, greeting, detected) = async {
+            // Clients of TLS services can be routed on the server name they request
+            let client_hello = match tls_acceptor {
+                Some(_) => service_detector.inspect_client_hello(&stream).await,
+                None => None,
+            };
+            let sni_service = client_hello
+                .as_ref()
+                .and_then(|hello| hello.sni.as_deref())
+                .and_then(|sni| service_detector.detect_from_sni(sni));
+
+            // Services where the server speaks first greet before the client is matched
+            let greeting = match sni_service {
+                Some(_) => None,
+                None => {
+                    service_detector
+                        .send_greeting(&mut stream, original_dst)
+                        .await?
+                }
+            };
+            let detected = match sni_service {
+                Some(name) => Ok(name),
+                None => {
+                    service_detector
+                        .identify_service(&stream, original_dst)
+                        .await
+                }
+            };
+            Ok::<_, NetworkError>((client_hello, greeting, detected))
+        }
+        .instrument(info_span!("detect"))
+        .await?;
+        let service_name = match detected {
             Ok(name) => name,
             Err(e) => {
                 warn!(
@@ -429,10 +1111,40 @@ impl NetworkListener {
             }
         };
 
+        telemetry::record_service(&service_name);
         debug!(
             "Detected service '{}' for connection from {}",
             service_name, client_addr
         );
+        events::emit(Event::ServiceDetected {
+            client_addr,
+            port: original_dst
+                .or_else(|| stream.local_addr().ok())
+                .map(|addr| addr.port())
+                .unwrap_or_default(),
+            service: service_name.clone(),
+            sni: client_hello.as_ref().and_then(|hello| hello.sni.clone()),
+        });
+
+        let stream = match tls_acceptor {
+            Some(acceptor) => {
+                match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(tls_stream)) => ClientStream::Tls {
+                        stream: Box::new(tls_stream),
+                        client_hello,
+                    },
+                    Ok(Err(e)) => {
+                        debug!("TLS handshake with {} failed: {}", client_addr, e);
+                        return Err(NetworkError::TlsError(e.to_string()));
+                    }
+                    Err(_) => {
+                        debug!("TLS handshake with {} timed out", client_addr);
+                        return Err(NetworkError::TlsError("handshake timed out".to_string()));
+                    }
+                }
+            }
+            None => ClientStream::Plain(stream),
+        };
 
         // Create session request
         let session_request = SessionRequest {
@@ -440,22 +1152,63 @@ impl NetworkListener {
             service_name,
             client_addr,
             timestamp: Utc::now(),
+            permit: Some(permit),
+            greeting,
+            original_dst,
+            span: Span::current(),
         };
 
-        if session_tx.send(session_request).await.is_err() {
-            error!("Failed to send session request - channel may be closed");
-            return Err(NetworkError::ChannelFailed);
+        let port = session_request
+            .stream
+            .as_ref()
+            .and_then(|stream| stream.local_addr().ok())
+            .map(|addr| addr.port())
+            .unwrap_or_default();
+        if !Self::queue_request(&session_tx, session_request, client_addr, port)? {
+            return Ok(());
         }
 
         debug!("Session request sent for {}", client_addr);
         Ok(())
     }
+
+    /// Queues `request` for the controller and returns whether it was queued.
+    ///
+    /// When the queue is full the request is dropped, closing its connection, so that
+    /// a burst of connections is shed instead of piling up while containers start.
+    ///
+    /// # Errors
+    /// Returns [`NetworkError::ChannelFailed`] when the controller stopped receiving.
+    fn queue_request<T>(
+        session_tx: &Sender<T>,
+        request: T,
+        client_addr: SocketAddr,
+        port: u16,
+    ) -> Result<bool, NetworkError> {
+        match session_tx.try_send(request) {
+            Ok(()) => Ok(true),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                warn!(
+                    "Session queue full, shedding connection from {} on port {}",
+                    client_addr, port
+                );
+                metrics::global().connection_rejected(port, "overloaded");
+                Ok(false)
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                error!("Failed to send session request - channel may be closed");
+                Err(NetworkError::ChannelFailed)
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use crate::configuration::types::TarpitConfig;
+    use std::net::Ipv4Addr;
     use tokio::net::TcpListener;
     use tokio::sync::mpsc;
     use tokio::time;
@@ -487,7 +1240,7 @@ mod tests {
         let copy = network_listener.extract_for_listening();
 
         // Start listening in a separate task with timeout since it runs indefinitely
-        let bind_addr = Ipv4Addr::new(127, 0, 0, 1);
+        let bind_addr = IpAddr::V4(Ipv4Addr::LOCALHOST);
         let listening_task =
             tokio::spawn(async move { NetworkListener::start_listening(copy, bind_addr).await });
 
@@ -510,7 +1263,7 @@ mod tests {
         let server_addr = test_listener.local_addr().unwrap();
         let port = server_addr.port();
 
-        log::debug!(
+        tracing::debug!(
             "Port value in test_listen_on_port_accepts_connections: {}",
             port
         );
@@ -528,10 +1281,15 @@ mod tests {
         let listen_task = tokio::spawn(async move {
             NetworkListener::listen_on_port(
                 test_listener,
-                session_tx,
-                service_detector,
-                connection_filter,
-                port,
+                Arc::new(PortContext {
+                    session_tx,
+                    service_detector,
+                    connection_filter,
+                    port_service: PortService::Session(None),
+                    port,
+                    trusted_proxies: None,
+                    redirect: RedirectMode::Redirect,
+                }),
                 shutdown_rx,
             )
             .await;
@@ -551,6 +1309,252 @@ mod tests {
         listen_task.abort();
     }
 
+    #[tokio::test]
+    async fn test_tarpit_ports_hold_connections_without_sessions() {
+        use tokio::io::AsyncReadExt;
+
+        let (session_tx, mut session_rx) = mpsc::channel::<SessionRequest>(100);
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        let test_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = test_listener.local_addr().unwrap();
+        let service = ServiceConfig {
+            port: server_addr.port(),
+            name: "tarpit".to_string(),
+            tarpit: Some(TarpitConfig {
+                banner: Some("220 ".to_string()),
+                interval_ms: 10,
+                ..TarpitConfig::default()
+            }),
+            ..ServiceConfig::default()
+        };
+
+        let context = PortContext {
+            session_tx,
+            service_detector: ServiceDetector::new(std::slice::from_ref(&service)),
+            connection_filter: ConnectionFilter::default(),
+            port_service: PortService::Tarpit(Tarpit::for_service(&service).unwrap()),
+            port: service.port,
+            trusted_proxies: None,
+            redirect: RedirectMode::Redirect,
+        };
+        let listen_task = tokio::spawn(NetworkListener::listen_on_port(
+            test_listener,
+            Arc::new(context),
+            shutdown_rx,
+        ));
+
+        let mut client = TcpStream::connect(server_addr).await.unwrap();
+        let mut dripped = [0u8; 6];
+        time::timeout(
+            time::Duration::from_secs(2),
+            client.read_exact(&mut dripped),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(&dripped, b"220 22");
+        assert!(session_rx.try_recv().is_err());
+
+        listen_task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_proxy_protocol_ports_take_the_client_address_from_the_header() {
+        use tokio::io::AsyncWriteExt;
+
+        let (session_tx, mut session_rx) = mpsc::channel::<SessionRequest>(100);
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        let test_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = test_listener.local_addr().unwrap();
+        let service = ServiceConfig {
+            port: server_addr.port(),
+            name: "ssh".to_string(),
+            proxy_protocol: true,
+            ..ServiceConfig::default()
+        };
+        let context = PortContext {
+            session_tx,
+            service_detector: ServiceDetector::new(std::slice::from_ref(&service)),
+            connection_filter: ConnectionFilter::default(),
+            port_service: PortService::Session(None),
+            port: service.port,
+            trusted_proxies: Some(vec!["127.0.0.0/8".parse().unwrap()]),
+            redirect: RedirectMode::Redirect,
+        };
+        let listen_task = tokio::spawn(NetworkListener::listen_on_port(
+            test_listener,
+            Arc::new(context),
+            shutdown_rx,
+        ));
+
+        let mut client = TcpStream::connect(server_addr).await.unwrap();
+        client
+            .write_all(b"PROXY TCP4 203.0.113.9 192.0.2.1 51234 22\r\nSSH-2.0-x\r\n")
+            .await
+            .unwrap();
+        let request = time::timeout(time::Duration::from_secs(2), session_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.client_addr, "203.0.113.9:51234".parse().unwrap());
+
+        listen_task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_proxy_protocol_headers_from_untrusted_peers_are_refused() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (session_tx, mut session_rx) = mpsc::channel::<SessionRequest>(100);
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        let test_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = test_listener.local_addr().unwrap();
+        let service = ServiceConfig {
+            port: server_addr.port(),
+            name: "ssh".to_string(),
+            proxy_protocol: true,
+            ..ServiceConfig::default()
+        };
+        let context = PortContext {
+            session_tx,
+            service_detector: ServiceDetector::new(std::slice::from_ref(&service)),
+            connection_filter: ConnectionFilter::default(),
+            port_service: PortService::Session(None),
+            port: service.port,
+            trusted_proxies: Some(vec!["192.0.2.0/24".parse().unwrap()]),
+            redirect: RedirectMode::Redirect,
+        };
+        let listen_task = tokio::spawn(NetworkListener::listen_on_port(
+            test_listener,
+            Arc::new(context),
+            shutdown_rx,
+        ));
+
+        let mut client = TcpStream::connect(server_addr).await.unwrap();
+        let _ = client
+            .write_all(b"PROXY TCP4 203.0.113.9 192.0.2.1 51234 22\r\nSSH-2.0-x\r\n")
+            .await;
+        // Closed without a session, whatever the header says
+        let mut rest = Vec::new();
+        let read = time::timeout(time::Duration::from_secs(2), client.read_to_end(&mut rest))
+            .await
+            .unwrap();
+        assert!(read.map_or(true, |n| n == 0));
+        assert!(session_rx.try_recv().is_err());
+
+        listen_task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_dual_stack_listeners_accept_ipv4_and_ipv6_clients() {
+        let (session_tx, mut session_rx) = mpsc::channel::<SessionRequest>(100);
+        let port = TcpListener::bind("[::]:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut network_listener = NetworkListener::new(session_tx);
+        network_listener
+            .bind_services(&[ServiceConfig {
+                port,
+                name: "test_service".to_string(),
+                ..ServiceConfig::default()
+            }])
+            .unwrap();
+        network_listener
+            .listen("::".parse().unwrap())
+            .await
+            .unwrap();
+
+        for client in ["[::1]", "127.0.0.1"] {
+            let _stream = TcpStream::connect(format!("{}:{}", client, port))
+                .await
+                .unwrap();
+            let request = time::timeout(time::Duration::from_secs(2), session_rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            // IPv4 clients are not recorded with an IPv4-mapped address
+            assert_eq!(
+                request.client_addr.ip(),
+                client.trim_matches(['[', ']']).parse::<IpAddr>().unwrap()
+            );
+        }
+        network_listener.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_services_bind_their_own_address() {
+        let (session_tx, _session_rx) = mpsc::channel::<SessionRequest>(100);
+        let port = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut network_listener = NetworkListener::new(session_tx);
+        network_listener
+            .bind_services(&[ServiceConfig {
+                port,
+                name: "test_service".to_string(),
+                bind_address: Some("::1".parse().unwrap()),
+                ..ServiceConfig::default()
+            }])
+            .unwrap();
+        network_listener
+            .listen(IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .await
+            .unwrap();
+
+        assert!(TcpStream::connect(("::1", port)).await.is_ok());
+        assert!(TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+            .await
+            .is_err());
+        network_listener.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_listen_on_udp_port_demultiplexes_flows() {
+        let (udp_tx, mut udp_rx) = mpsc::channel::<UdpSessionRequest>(100);
+        let (shutdown_tx, _) = broadcast::channel(1);
+        let shutdown_rx = shutdown_tx.subscribe();
+
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let server_addr = socket.local_addr().unwrap();
+
+        let listen_task = tokio::spawn(NetworkListener::listen_on_udp_port(
+            socket,
+            udp_tx,
+            "dns".to_string(),
+            ConnectionFilter::default(),
+            server_addr.port(),
+            shutdown_rx,
+        ));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(b"first", server_addr).await.unwrap();
+
+        let mut request = time::timeout(time::Duration::from_millis(500), udp_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.service_name, "dns");
+        assert_eq!(request.client_addr, client.local_addr().unwrap());
+        assert_eq!(request.datagrams.recv().await.unwrap(), b"first");
+
+        // Same client: queued on the existing flow, no new session request
+        client.send_to(b"second", server_addr).await.unwrap();
+        let next = time::timeout(time::Duration::from_millis(500), request.datagrams.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(next, b"second");
+        assert!(udp_rx.try_recv().is_err());
+
+        listen_task.abort();
+    }
+
     /*
     #[tokio::test]
     async fn test_handle_connection_success() {
@@ -566,7 +1570,7 @@ mod tests {
         let _client_stream = TcpStream::connect(addr).await.unwrap();
         let (server_stream, client_addr) = listener.accept().await.unwrap();
 
-        log::debug!("Starting handling built connection...");
+        tracing::debug!("Starting handling built connection...");
         let result = NetworkListener::handle_connection(
             server_stream,
             client_addr,
@@ -575,12 +1579,12 @@ mod tests {
         )
         .await;
 
-        log::debug!("Came out of handle connection await in test");
+        tracing::debug!("Came out of handle connection await in test");
 
         assert!(result.is_ok());
 
         // Verify session request was sent
-        log::debug!("Waiting to receive a SessionRequest");
+        tracing::debug!("Waiting to receive a SessionRequest");
         let session_request = time::timeout(time::Duration::from_millis(100), session_rx.recv())
             .await
             .unwrap()
@@ -590,4 +1594,178 @@ mod tests {
         assert!(session_request.timestamp <= Utc::now());
     }
     */
+
+    #[tokio::test]
+    async fn test_reload_services_rebinds_changed_ports() {
+        let free_port = || {
+            std::net::TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port()
+        };
+        let service = |name: &str, port| ServiceConfig {
+            name: name.to_string(),
+            port,
+            ..ServiceConfig::default()
+        };
+        let (old_port, new_port) = (free_port(), free_port());
+        let bind_addr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        let (session_tx, _session_rx) = mpsc::channel::<SessionRequest>(100);
+        let mut network_listener = NetworkListener::new(session_tx);
+        network_listener
+            .bind_services(&[service("old", old_port)])
+            .unwrap();
+        network_listener.listen(bind_addr).await.unwrap();
+        assert!(TcpStream::connect((bind_addr, old_port)).await.is_ok());
+
+        network_listener
+            .reload_services(&[service("new", new_port)], bind_addr)
+            .await
+            .unwrap();
+
+        assert!(TcpStream::connect((bind_addr, old_port)).await.is_err());
+        assert!(TcpStream::connect((bind_addr, new_port)).await.is_ok());
+        assert_eq!(
+            network_listener
+                .service_detector
+                .service_patterns
+                .get(&new_port)
+                .map(|p| p.service_name.as_str()),
+            Some("new")
+        );
+
+        network_listener.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_add_and_remove_service_at_runtime() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let service = ServiceConfig {
+            name: "added".to_string(),
+            port,
+            ..ServiceConfig::default()
+        };
+        let bind_addr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        let (session_tx, _session_rx) = mpsc::channel::<SessionRequest>(100);
+        let mut network_listener = NetworkListener::new(session_tx);
+        network_listener.listen(bind_addr).await.unwrap();
+
+        network_listener
+            .add_service(&service, bind_addr)
+            .await
+            .unwrap();
+        assert_eq!(network_listener.service_ports(), vec![port]);
+        assert!(TcpStream::connect((bind_addr, port)).await.is_ok());
+        assert!(matches!(
+            network_listener.add_service(&service, bind_addr).await,
+            Err(NetworkError::BindError(_))
+        ));
+
+        let removed = network_listener.remove_service(port).await;
+        assert_eq!(removed.map(|s| s.name), Some("added".to_string()));
+        assert!(TcpStream::connect((bind_addr, port)).await.is_err());
+        assert!(network_listener.remove_service(port).await.is_none());
+        assert!(network_listener
+            .service_detector
+            .service_patterns
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tls_service_hands_over_plaintext() {
+        use crate::configuration::types::TlsConfig;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio_rustls::rustls::{self, pki_types::ServerName};
+
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let (cert_path, key_path) = (dir.path().join("cert.pem"), dir.path().join("key.pem"));
+        std::fs::write(&cert_path, generated.cert.pem()).unwrap();
+        std::fs::write(&key_path, generated.key_pair.serialize_pem()).unwrap();
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let service = ServiceConfig {
+            name: "https".to_string(),
+            port,
+            tls: Some(TlsConfig {
+                cert_path: Some(cert_path),
+                key_path: Some(key_path),
+                ..TlsConfig::default()
+            }),
+            ..ServiceConfig::default()
+        };
+
+        let (session_tx, mut session_rx) = mpsc::channel::<SessionRequest>(100);
+        let mut network_listener = NetworkListener::new(session_tx);
+        network_listener.bind_services(&[service]).unwrap();
+        network_listener
+            .listen(IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .await
+            .unwrap();
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(generated.cert.der().clone()).unwrap();
+        let client_config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config));
+
+        let tcp = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let mut client = connector.connect(server_name, tcp).await.unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+
+        let mut request = time::timeout(time::Duration::from_secs(2), session_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let mut stream = request.take_stream().unwrap();
+        let tls = stream.tls_metadata().expect("tls terminated");
+        assert_eq!(tls.sni.as_deref(), Some("localhost"));
+        assert_eq!(tls.ja3_hash.map(|hash| hash.len()), Some(32));
+
+        let mut buf = [0u8; 64];
+        let n = stream.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"GET / HTTP/1.1\r\n\r\n");
+
+        network_listener.shutdown().await.unwrap();
+    }
+
+    #[test]
+    fn test_requests_over_the_queue_are_shed() {
+        let (tx, mut rx) = mpsc::channel::<u8>(1);
+        let client_addr: SocketAddr = "203.0.113.7:50000".parse().unwrap();
+
+        assert!(matches!(
+            NetworkListener::queue_request(&tx, 1, client_addr, 22),
+            Ok(true)
+        ));
+        assert!(matches!(
+            NetworkListener::queue_request(&tx, 2, client_addr, 22),
+            Ok(false)
+        ));
+        assert_eq!(rx.try_recv().unwrap(), 1);
+        assert!(rx.try_recv().is_err());
+
+        drop(rx);
+        assert!(matches!(
+            NetworkListener::queue_request(&tx, 3, client_addr, 22),
+            Err(NetworkError::ChannelFailed)
+        ));
+    }
 }
@@ -0,0 +1,245 @@
+//! PROXY protocol headers, giving the client address behind a load balancer.
+//!
+//! A load balancer or TLS terminator in front of miel opens its own connections,
+//! so every client seems to come from it. Ports of services with `proxy_protocol`
+//! expect the [PROXY protocol] header these proxies send first, in its text (v1) or
+//! binary (v2) form, and the client address it carries is used instead of the
+//! address of the socket. `UNKNOWN` (v1) and `LOCAL` (v2) headers, sent e.g. by
+//! health checks, keep the address of the socket.
+//!
+//! The header is peeked and only its own bytes are consumed, so the stream is
+//! handed to service detection as if the client had connected directly.
+//!
+//! [PROXY protocol]: https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+
+use crate::error_handling::types::NetworkError;
+
+/// Time the proxy has to send the whole header
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest v1 header, line break included
+const V1_MAX_LEN: usize = 107;
+/// Signature opening every v2 header
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+/// Signature, version and command, family and length
+const V2_FIXED_LEN: usize = 16;
+/// Longest v2 header kept in the peek buffer, with room for TLV extensions
+const V2_MAX_LEN: usize = 536;
+
+/// What a complete header holds
+#[derive(Debug, PartialEq)]
+pub struct ProxyHeader {
+    /// Address of the client, `None` when the proxy did not give one
+    pub source: Option<SocketAddr>,
+    /// Length of the header, in bytes
+    pub len: usize,
+}
+
+/// Parses the PROXY header at the start of `data`, `Ok(None)` until it was received
+/// in full
+pub fn parse(data: &[u8]) -> Result<Option<ProxyHeader>, NetworkError> {
+    let prefix = data.len().min(V2_SIGNATURE.len());
+    if data[..prefix] == V2_SIGNATURE[..prefix] {
+        return parse_v2(data);
+    }
+    let prefix = data.len().min(6);
+    if data[..prefix] == b"PROXY "[..prefix] {
+        return parse_v1(data);
+    }
+    Err(invalid("no PROXY protocol header"))
+}
+
+fn parse_v1(data: &[u8]) -> Result<Option<ProxyHeader>, NetworkError> {
+    let Some(end) = data.windows(2).position(|w| w == b"\r\n") else {
+        return if data.len() >= V1_MAX_LEN {
+            Err(invalid("v1 header too long"))
+        } else {
+            Ok(None)
+        };
+    };
+    let line = std::str::from_utf8(&data[..end]).map_err(|_| invalid("v1 header not ASCII"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+
+    let source = match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => None,
+        ["PROXY", family @ ("TCP4" | "TCP6"), src, _dst, sport, _dport] => {
+            let ip: IpAddr = src.parse().map_err(|_| invalid("bad v1 source address"))?;
+            if ip.is_ipv4() != (*family == "TCP4") {
+                return Err(invalid("v1 source address of the wrong family"));
+            }
+            let port: u16 = sport.parse().map_err(|_| invalid("bad v1 source port"))?;
+            Some(SocketAddr::new(ip, port))
+        }
+        _ => return Err(invalid("malformed v1 header")),
+    };
+    Ok(Some(ProxyHeader {
+        source,
+        len: end + 2,
+    }))
+}
+
+fn parse_v2(data: &[u8]) -> Result<Option<ProxyHeader>, NetworkError> {
+    if data.len() < V2_FIXED_LEN {
+        return Ok(None);
+    }
+    let version_command = data[12];
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported v2 version"));
+    }
+    let len = V2_FIXED_LEN + u16::from_be_bytes([data[14], data[15]]) as usize;
+    if len > V2_MAX_LEN {
+        return Err(invalid("v2 header too long"));
+    }
+    if data.len() < len {
+        return Ok(None);
+    }
+    let addresses = &data[V2_FIXED_LEN..len];
+
+    let source = match (version_command & 0x0f, data[13]) {
+        // LOCAL, the proxy speaking for itself
+        (0x0, _) => None,
+        // PROXY over TCP or UDP, on IPv4
+        (0x1, 0x11 | 0x12) if addresses.len() >= 12 => {
+            let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&addresses[..4]).unwrap());
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Some(SocketAddr::new(IpAddr::V4(ip), port))
+        }
+        // PROXY over TCP or UDP, on IPv6
+        (0x1, 0x21 | 0x22) if addresses.len() >= 36 => {
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&addresses[..16]).unwrap());
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Some(SocketAddr::new(IpAddr::V6(ip), port))
+        }
+        // Unspecified or Unix socket family
+        (0x1, family) if family >> 4 == 0 || family >> 4 == 3 => None,
+        _ => return Err(invalid("malformed v2 header")),
+    };
+    Ok(Some(ProxyHeader { source, len }))
+}
+
+fn invalid(reason: &str) -> NetworkError {
+    NetworkError::ProxyProtocol(reason.to_string())
+}
+
+/// Reads the PROXY header `stream` opens with and returns the client address it
+/// gives, leaving what follows it unread
+pub async fn read_header(stream: &mut TcpStream) -> Result<Option<SocketAddr>, NetworkError> {
+    let mut buf = [0u8; V2_MAX_LEN];
+    let peek = async {
+        loop {
+            let n = stream
+                .peek(&mut buf)
+                .await
+                .map_err(NetworkError::SockError)?;
+            if n == 0 {
+                return Err(invalid("connection closed before the header"));
+            }
+            match parse(&buf[..n])? {
+                Some(header) => return Ok(header),
+                // Rest of the header not received yet
+                None => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        }
+    };
+    let header = tokio::time::timeout(HEADER_TIMEOUT, peek)
+        .await
+        .map_err(|_| invalid("no header received in time"))??;
+
+    let mut consumed = vec![0u8; header.len];
+    stream
+        .read_exact(&mut consumed)
+        .await
+        .map_err(NetworkError::SockError)?;
+    Ok(header.source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family);
+        header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+        header.extend_from_slice(addresses);
+        header
+    }
+
+    #[test]
+    fn v1_headers_give_the_source_address() {
+        let data = b"PROXY TCP4 203.0.113.9 192.0.2.1 51234 22\r\nSSH-2.0-x\r\n";
+        assert_eq!(
+            parse(data).unwrap(),
+            Some(ProxyHeader {
+                source: Some("203.0.113.9:51234".parse().unwrap()),
+                len: 43,
+            })
+        );
+
+        let data = b"PROXY TCP6 2001:db8::7 2001:db8::1 4000 443\r\n";
+        assert_eq!(
+            parse(data).unwrap().unwrap().source,
+            Some("[2001:db8::7]:4000".parse().unwrap())
+        );
+        assert_eq!(parse(b"PROXY UNKNOWN\r\n").unwrap().unwrap().source, None);
+    }
+
+    #[test]
+    fn partial_headers_wait_and_garbage_is_refused() {
+        assert_eq!(parse(b"PROXY TCP4 203.0").unwrap(), None);
+        assert_eq!(parse(&V2_SIGNATURE[..7]).unwrap(), None);
+        assert!(parse(b"SSH-2.0-OpenSSH\r\n").is_err());
+        assert!(parse(b"PROXY TCP4 2001:db8::7 192.0.2.1 1 2\r\n").is_err());
+        assert!(parse(&[b'A'; V1_MAX_LEN][..]).is_err());
+    }
+
+    #[test]
+    fn v2_headers_give_the_source_address() {
+        let mut addresses = vec![203, 0, 113, 9, 192, 0, 2, 1];
+        addresses.extend_from_slice(&51234u16.to_be_bytes());
+        addresses.extend_from_slice(&22u16.to_be_bytes());
+        let mut data = v2(0x1, 0x11, &addresses);
+        let len = data.len();
+        data.extend_from_slice(b"SSH-2.0-x\r\n");
+
+        assert_eq!(
+            parse(&data).unwrap(),
+            Some(ProxyHeader {
+                source: Some("203.0.113.9:51234".parse().unwrap()),
+                len,
+            })
+        );
+        assert_eq!(parse(&data[..len - 1]).unwrap(), None);
+        assert_eq!(parse(&v2(0x0, 0x00, &[])).unwrap().unwrap().source, None);
+    }
+
+    #[tokio::test]
+    async fn only_the_header_is_consumed() {
+        use tokio::io::AsyncWriteExt;
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+
+        client
+            .write_all(b"PROXY TCP4 203.0.113.9 192.0.2.1 51234 22\r\nhello")
+            .await
+            .unwrap();
+        let source = read_header(&mut server).await.unwrap();
+        assert_eq!(source, Some("203.0.113.9:51234".parse().unwrap()));
+
+        let mut rest = [0u8; 5];
+        server.read_exact(&mut rest).await.unwrap();
+        assert_eq!(&rest, b"hello");
+    }
+}
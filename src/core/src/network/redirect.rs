@@ -0,0 +1,362 @@
+//! Transparent redirection of whole port ranges to a few listeners with nftables.
+//!
+//! A service with `redirect_ports` gets the connections to these ports as well as
+//! to its own, so that e.g. every port from 1 to 1024 is answered without binding
+//! a thousand sockets. The rules live in the `inet miel_redirect` table, loaded
+//! when the controller starts, replaced as a whole when the services change and
+//! deleted on shutdown. Only connections to the addresses of the host are
+//! redirected, and ports of the other services are left out of the ranges.
+//!
+//! With the `redirect` mode the connections are NATed to the port of the service,
+//! and the address the client connected to is read back from conntrack with
+//! `SO_ORIGINAL_DST`, as it is for connections NATed by other DNAT rules of the
+//! host. With `tproxy` the connections keep their destination, which is the local
+//! address of the accepted socket, and the listeners of these services are made
+//! transparent. Both need root. The original destination is recorded in the
+//! session as its `original_dst`, and the service is detected from its port.
+
+use std::io;
+use std::net::SocketAddr;
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+use std::process::Stdio;
+
+#[cfg(target_os = "linux")]
+use socket2::SockAddr;
+use socket2::Socket;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+use crate::configuration::types::{
+    PortRange, Protocol, RedirectConfig, RedirectMode, ServiceConfig,
+};
+use crate::error_handling::types::NetworkError;
+
+const TABLE: &str = "miel_redirect";
+
+/// Whether `service` is the target of redirected ports
+pub fn is_target(service: &ServiceConfig) -> bool {
+    service.enabled && service.protocol == Protocol::TCP && !service.redirect_ports.is_empty()
+}
+
+/// `nft -f` script replacing the table with the rules of `services`, `None` when no
+/// service has `redirect_ports`
+pub fn ruleset(config: &RedirectConfig, services: &[ServiceConfig]) -> Option<String> {
+    let targets: Vec<&ServiceConfig> = services.iter().filter(|s| is_target(s)).collect();
+    if targets.is_empty() {
+        return None;
+    }
+
+    let mut served: Vec<u16> = services
+        .iter()
+        .filter(|s| s.enabled && s.protocol == Protocol::TCP)
+        .map(|s| s.port)
+        .collect();
+    served.sort_unstable();
+    served.dedup();
+
+    let chain = match config.mode {
+        RedirectMode::Redirect => "type nat hook prerouting priority dstnat; policy accept;",
+        RedirectMode::Tproxy => "type filter hook prerouting priority mangle; policy accept;",
+    };
+    // Adding the table first lets the delete succeed on the first run
+    let mut script = format!(
+        "add table inet {table}\ndelete table inet {table}\nadd table inet {table}\nadd chain inet {table} prerouting {{ {chain} }}\n",
+        table = TABLE,
+        chain = chain
+    );
+
+    let mut matcher = String::new();
+    if let Some(interface) = &config.interface {
+        matcher.push_str(&format!("iifname \"{}\" ", interface));
+    }
+    matcher.push_str("fib daddr type local ");
+    for service in targets {
+        let ports: Vec<String> = service.redirect_ports.iter().map(range).collect();
+        let excluded: Vec<String> = served
+            .iter()
+            .filter(|port| service.redirect_ports.iter().any(|r| contains(r, **port)))
+            .map(u16::to_string)
+            .collect();
+        let mut rule = format!("{}tcp dport {{ {} }} ", matcher, ports.join(", "));
+        if !excluded.is_empty() {
+            rule.push_str(&format!("tcp dport != {{ {} }} ", excluded.join(", ")));
+        }
+        match config.mode {
+            RedirectMode::Redirect => rule.push_str(&format!("redirect to :{}", service.port)),
+            RedirectMode::Tproxy => rule.push_str(&format!(
+                "meta l4proto tcp tproxy to :{} accept",
+                service.port
+            )),
+        }
+        script.push_str(&format!("add rule inet {} prerouting {}\n", TABLE, rule));
+    }
+    Some(script)
+}
+
+fn range(range: &PortRange) -> String {
+    if range.start == range.end {
+        range.start.to_string()
+    } else {
+        format!("{}-{}", range.start, range.end)
+    }
+}
+
+fn contains(range: &PortRange, port: u16) -> bool {
+    range.start <= port && port <= range.end
+}
+
+/// Loads the rules of `services`, or deletes those of a previous run when no service
+/// redirects any port
+///
+/// # Errors
+/// Returns [`NetworkError::RedirectFailed`] when `nft` is missing or rejects the rules.
+pub async fn apply(
+    config: &RedirectConfig,
+    services: &[ServiceConfig],
+) -> Result<(), NetworkError> {
+    let Some(script) = ruleset(config, services) else {
+        remove().await;
+        return Ok(());
+    };
+    nft(&["-f", "-"], Some(&script))
+        .await
+        .map_err(|e| NetworkError::RedirectFailed(e.to_string()))?;
+    info!(
+        "Transparent {:?} rules loaded for {} services",
+        config.mode,
+        services.iter().filter(|s| is_target(s)).count()
+    );
+    Ok(())
+}
+
+/// Deletes the rules, best effort
+pub async fn remove() {
+    if nft(&["delete", "table", "inet", TABLE], None).await.is_ok() {
+        debug!("Transparent redirect rules removed");
+    }
+}
+
+/// Lets a listener accept the connections `tproxy` hands it, whatever their destination
+#[cfg(target_os = "linux")]
+pub fn set_transparent(socket: &Socket, ipv6: bool) -> io::Result<()> {
+    if !ipv6 {
+        return socket.set_ip_transparent_v4(true);
+    }
+    let enabled: libc::c_int = 1;
+    // SAFETY: the option value is a c_int living across the call
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_IPV6,
+            libc::IPV6_TRANSPARENT,
+            (&enabled as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Dual-stack listeners get IPv4 clients as well
+    if !socket.only_v6()? {
+        socket.set_ip_transparent_v4(true)?;
+    }
+    Ok(())
+}
+
+/// Transparent sockets only exist on Linux
+#[cfg(not(target_os = "linux"))]
+pub fn set_transparent(_socket: &Socket, _ipv6: bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "transparent sockets are only supported on Linux",
+    ))
+}
+
+/// Address `stream` was opened to before being redirected to the listener of `port`,
+/// `None` for connections made to the listener itself
+pub fn original_destination(
+    stream: &TcpStream,
+    mode: RedirectMode,
+    port: u16,
+) -> Option<SocketAddr> {
+    let local_addr = canonical(stream.local_addr().ok()?);
+    let destination = match mode {
+        RedirectMode::Redirect => conntrack_destination(stream)
+            .inspect_err(|e| debug!("No original destination: {}", e))
+            .map(canonical)
+            .ok()?,
+        // The listener got the connection with its destination untouched
+        RedirectMode::Tproxy if local_addr.port() != port => return Some(local_addr),
+        RedirectMode::Tproxy => return None,
+    };
+    (destination != local_addr).then_some(destination)
+}
+
+fn canonical(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+/// Destination of `stream` before NAT, read from conntrack
+#[cfg(target_os = "linux")]
+fn conntrack_destination(stream: &TcpStream) -> io::Result<SocketAddr> {
+    // IPv4 clients of dual-stack listeners are tracked as IPv4 connections
+    let ipv4 = stream.peer_addr()?.ip().to_canonical().is_ipv4();
+    let (level, name) = match ipv4 {
+        true => (libc::SOL_IP, libc::SO_ORIGINAL_DST),
+        false => (libc::SOL_IPV6, libc::IP6T_SO_ORIGINAL_DST),
+    };
+    let fd = stream.as_raw_fd();
+    // SAFETY: the kernel writes at most `len` bytes of a sockaddr into the storage
+    let (_, address) = unsafe {
+        SockAddr::try_init(|storage, len| {
+            if libc::getsockopt(fd, level, name, storage.cast(), len) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        })
+    }?;
+    address.as_socket().ok_or_else(|| {
+        warn!("Original destination of unknown family");
+        io::Error::from(io::ErrorKind::InvalidData)
+    })
+}
+
+/// Conntrack only exists on Linux
+#[cfg(not(target_os = "linux"))]
+fn conntrack_destination(_stream: &TcpStream) -> io::Result<SocketAddr> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "original destinations are only supported on Linux",
+    ))
+}
+
+/// Runs `nft` with `args`, feeding it `input`
+async fn nft(args: &[&str], input: Option<&str>) -> io::Result<()> {
+    let mut child = Command::new("nft")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        if let Some(input) = input {
+            stdin.write_all(input.as_bytes()).await?;
+        }
+    }
+
+    let output = child.wait_with_output().await?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(name: &str, port: u16, redirect_ports: Vec<PortRange>) -> ServiceConfig {
+        ServiceConfig {
+            name: name.to_string(),
+            port,
+            enabled: true,
+            redirect_ports,
+            ..ServiceConfig::default()
+        }
+    }
+
+    #[test]
+    fn ranges_are_redirected_around_the_ports_of_other_services() {
+        let services = [
+            service(
+                "catch-all",
+                2222,
+                vec![
+                    PortRange {
+                        start: 1,
+                        end: 1024,
+                    },
+                    PortRange {
+                        start: 8080,
+                        end: 8080,
+                    },
+                ],
+            ),
+            service("http", 80, vec![]),
+            service("https", 4443, vec![]),
+        ];
+        let config = RedirectConfig {
+            interface: Some("eth0".to_string()),
+            ..RedirectConfig::default()
+        };
+        let script = ruleset(&config, &services).unwrap();
+
+        assert!(script.starts_with(
+            "add table inet miel_redirect\ndelete table inet miel_redirect\nadd table inet miel_redirect\n"
+        ));
+        assert!(script.contains("type nat hook prerouting priority dstnat;"));
+        let rules: Vec<&str> = script
+            .lines()
+            .filter(|line| line.starts_with("add rule"))
+            .collect();
+        assert_eq!(
+            rules,
+            ["add rule inet miel_redirect prerouting iifname \"eth0\" fib daddr type local tcp dport { 1-1024, 8080 } tcp dport != { 80 } redirect to :2222"]
+        );
+    }
+
+    #[test]
+    fn tproxy_keeps_the_destination() {
+        let services = [service(
+            "catch-all",
+            2222,
+            vec![PortRange { start: 1, end: 100 }],
+        )];
+        let config = RedirectConfig {
+            mode: RedirectMode::Tproxy,
+            interface: None,
+        };
+        let script = ruleset(&config, &services).unwrap();
+
+        assert!(script.contains("type filter hook prerouting priority mangle;"));
+        assert!(script.ends_with(
+            "prerouting fib daddr type local tcp dport { 1-100 } meta l4proto tcp tproxy to :2222 accept\n"
+        ));
+    }
+
+    #[test]
+    fn no_rules_without_redirected_ports() {
+        let mut disabled = service("catch-all", 2222, vec![PortRange::default()]);
+        disabled.enabled = false;
+        let services = [service("ssh", 22, vec![]), disabled];
+        assert!(ruleset(&RedirectConfig::default(), &services).is_none());
+    }
+
+    #[tokio::test]
+    async fn direct_connections_have_no_original_destination() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _client = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+
+        let port = addr.port();
+        assert_eq!(
+            original_destination(&stream, RedirectMode::Redirect, port),
+            None
+        );
+        assert_eq!(
+            original_destination(&stream, RedirectMode::Tproxy, port),
+            None
+        );
+        assert_eq!(
+            original_destination(&stream, RedirectMode::Tproxy, port + 1),
+            Some(addr)
+        );
+    }
+}
@@ -0,0 +1,356 @@
+//! Port scan detection, correlating the connections of a source across services.
+//!
+//! Every connection accepted by the listener is observed with its source IP and
+//! port. A source connecting to `min_ports` distinct ports within `window_secs` is
+//! flagged as a scanner, and the ports it connects to from then on are added to its
+//! scan. Once the source stayed quiet for a whole window its scan is over, and
+//! [`ScanDetector::take_finished`] hands it out once, so that a sweep of the
+//! services is reported as one `portscan` pseudo-session rather than as many
+//! sessions. With `suppress_containers`, the connections of a flagged source get
+//! no session at all.
+//!
+//! IPv6 sources are tracked per /64, the prefix a single host usually owns, so
+//! that a scanner rotating its addresses within it is still seen as one. At most
+//! [`MAX_TRACKED_SOURCES`] sources are tracked: past it, the least recently seen
+//! one is evicted, its scan, if any, being handed out as finished.
+
+use std::collections::{BTreeSet, HashMap};
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use tracing::{debug, info};
+
+use crate::configuration::types::ScanDetectionConfig;
+
+/// Number of tracked sources above which idle unflagged ones are pruned, and
+/// which the least recently seen are evicted to stay under
+const MAX_TRACKED_SOURCES: usize = 4096;
+
+/// Length of the prefix IPv6 sources are aggregated by
+const IPV6_PREFIX_LEN: u32 = 64;
+
+/// What the listener does with an observed connection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScanVerdict {
+    /// Not a scanner, or scanners are still served
+    Serve,
+    /// The source is a scanner and gets no session
+    Suppress,
+}
+
+/// A finished scan from one source
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortScan {
+    /// Address of the source, the network address of its /64 for IPv6
+    pub client_addr: IpAddr,
+    /// Distinct ports connected to, in ascending order
+    pub ports: Vec<u16>,
+    /// Connections observed during the scan, repeated ports included
+    pub connections: u64,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+}
+
+/// Connections of one source
+struct SourceActivity {
+    /// Last connection instant of each port, within the window until flagged
+    ports: HashMap<u16, Instant>,
+    /// Every port connected to since the source was flagged
+    scanned: BTreeSet<u16>,
+    flagged: bool,
+    connections: u64,
+    first_seen: DateTime<Utc>,
+    last_seen: Instant,
+    last_seen_at: DateTime<Utc>,
+}
+
+/// Flags the sources connecting to many ports and collects their scans.
+///
+/// Clones share the configuration and the tracked sources, like the
+/// [`ConnectionFilter`](super::connection_filter::ConnectionFilter) holding it.
+#[derive(Clone, Default)]
+pub struct ScanDetector {
+    config: Arc<RwLock<ScanDetectionConfig>>,
+    sources: Arc<Mutex<Sources>>,
+}
+
+#[derive(Default)]
+struct Sources {
+    active: HashMap<IpAddr, SourceActivity>,
+    /// Scans of the sources evicted before they were over
+    evicted: Vec<PortScan>,
+}
+
+impl ScanDetector {
+    pub fn new(config: ScanDetectionConfig) -> Self {
+        Self {
+            config: Arc::new(RwLock::new(config)),
+            ..Self::default()
+        }
+    }
+
+    /// Replaces the configuration of this detector and of all its clones with the one
+    /// of `other`, the tracked sources being kept
+    pub fn reconfigure(&self, other: &ScanDetector) {
+        if Arc::ptr_eq(&self.config, &other.config) {
+            return;
+        }
+        let config = other.config.read().unwrap().clone();
+        *self.config.write().unwrap() = config;
+    }
+
+    /// Records a connection from `ip` on `port`
+    pub fn observe(&self, ip: IpAddr, port: u16) -> ScanVerdict {
+        self.observe_at(ip, port, Instant::now(), Utc::now())
+    }
+
+    fn observe_at(&self, ip: IpAddr, port: u16, now: Instant, at: DateTime<Utc>) -> ScanVerdict {
+        let config = self.config.read().unwrap().clone();
+        if !config.enabled {
+            return ScanVerdict::Serve;
+        }
+        let window = Duration::from_secs(config.window_secs);
+        let ip = source_of(ip);
+        let mut sources = self.sources.lock().unwrap();
+        let Sources { active, evicted } = &mut *sources;
+
+        if active.len() >= MAX_TRACKED_SOURCES && !active.contains_key(&ip) {
+            active.retain(|_, source| {
+                source.flagged || now.duration_since(source.last_seen) < window
+            });
+            while active.len() >= MAX_TRACKED_SOURCES {
+                let Some(oldest) = active
+                    .iter()
+                    .min_by_key(|(_, source)| source.last_seen)
+                    .map(|(ip, _)| *ip)
+                else {
+                    break;
+                };
+                debug!("Too many sources tracked, forgetting {}", oldest);
+                let source = active.remove(&oldest).unwrap();
+                evicted.extend(source.into_scan(oldest));
+            }
+        }
+
+        let source = active.entry(ip).or_insert_with(|| SourceActivity {
+            ports: HashMap::new(),
+            scanned: BTreeSet::new(),
+            flagged: false,
+            connections: 0,
+            first_seen: at,
+            last_seen: now,
+            last_seen_at: at,
+        });
+        if !source.flagged {
+            source
+                .ports
+                .retain(|_, last| now.duration_since(*last) < window);
+            if source.ports.is_empty() {
+                // A new burst from a source seen before
+                source.connections = 0;
+                source.first_seen = at;
+            }
+        }
+        source.ports.insert(port, now);
+        source.connections += 1;
+        source.last_seen = now;
+        source.last_seen_at = at;
+
+        if !source.flagged && source.ports.len() >= config.min_ports {
+            source.flagged = true;
+            source.scanned.extend(source.ports.keys());
+            source.ports.clear();
+            info!(
+                "Port scan detected from {}, {} ports within {:?}",
+                ip,
+                source.scanned.len(),
+                window
+            );
+        } else if source.flagged {
+            source.ports.clear();
+            source.scanned.insert(port);
+        }
+
+        if source.flagged && config.suppress_containers {
+            ScanVerdict::Suppress
+        } else {
+            ScanVerdict::Serve
+        }
+    }
+
+    /// Removes the scans of the sources quiet for a whole window and returns them
+    pub fn take_finished(&self) -> Vec<PortScan> {
+        self.take_finished_at(Instant::now())
+    }
+
+    /// Removes every scan, finished or not, e.g. before shutting down
+    pub fn take_all(&self) -> Vec<PortScan> {
+        self.take_where(|_| true)
+    }
+
+    fn take_finished_at(&self, now: Instant) -> Vec<PortScan> {
+        let window = Duration::from_secs(self.config.read().unwrap().window_secs);
+        self.take_where(|source| now.duration_since(source.last_seen) >= window)
+    }
+
+    fn take_where(&self, finished: impl Fn(&SourceActivity) -> bool) -> Vec<PortScan> {
+        let mut sources = self.sources.lock().unwrap();
+        let ended: Vec<IpAddr> = sources
+            .active
+            .iter()
+            .filter(|(_, source)| finished(source))
+            .map(|(ip, _)| *ip)
+            .collect();
+
+        let mut scans = std::mem::take(&mut sources.evicted);
+        for ip in ended {
+            if let Some(source) = sources.active.remove(&ip) {
+                scans.extend(source.into_scan(ip));
+            }
+        }
+        scans.sort_by_key(|scan| scan.start_time);
+        scans
+    }
+}
+
+impl SourceActivity {
+    /// Scan of the source at `ip`, `None` when it was never flagged
+    fn into_scan(self, ip: IpAddr) -> Option<PortScan> {
+        self.flagged.then(|| PortScan {
+            client_addr: ip,
+            ports: self.scanned.into_iter().collect(),
+            connections: self.connections,
+            start_time: self.first_seen,
+            end_time: self.last_seen_at,
+        })
+    }
+}
+
+/// Key `ip` is tracked under: IPv4 addresses as they are, IPv6 ones by /64, the
+/// prefix a single host usually owns
+pub(crate) fn source_of(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => IpAddr::V4(ip),
+        IpAddr::V6(ip) => {
+            let mask = u128::MAX << (128 - IPV6_PREFIX_LEN);
+            IpAddr::V6(Ipv6Addr::from_bits(ip.to_bits() & mask))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detector(suppress_containers: bool) -> ScanDetector {
+        ScanDetector::new(ScanDetectionConfig {
+            enabled: true,
+            min_ports: 3,
+            window_secs: 60,
+            suppress_containers,
+        })
+    }
+
+    #[test]
+    fn sources_hitting_many_ports_are_reported_once_quiet() {
+        let detector = detector(false);
+        let scanner: IpAddr = "203.0.113.5".parse().unwrap();
+        let client: IpAddr = "198.51.100.7".parse().unwrap();
+        let start = Instant::now();
+
+        for (i, port) in [22, 80, 22, 443, 8080].into_iter().enumerate() {
+            let now = start + Duration::from_secs(i as u64);
+            assert_eq!(
+                detector.observe_at(scanner, port, now, Utc::now()),
+                ScanVerdict::Serve
+            );
+        }
+        detector.observe_at(client, 22, start, Utc::now());
+        detector.observe_at(client, 80, start, Utc::now());
+
+        assert!(detector
+            .take_finished_at(start + Duration::from_secs(30))
+            .is_empty());
+
+        let scans = detector.take_finished_at(start + Duration::from_secs(120));
+        assert_eq!(scans.len(), 1);
+        assert_eq!(scans[0].client_addr, scanner);
+        assert_eq!(scans[0].ports, [22, 80, 443, 8080]);
+        assert_eq!(scans[0].connections, 5);
+        assert!(detector.take_all().is_empty());
+    }
+
+    #[test]
+    fn ports_outside_the_window_do_not_add_up() {
+        let detector = detector(true);
+        let ip: IpAddr = "203.0.113.5".parse().unwrap();
+        let start = Instant::now();
+
+        detector.observe_at(ip, 22, start, Utc::now());
+        detector.observe_at(ip, 80, start + Duration::from_secs(61), Utc::now());
+        assert_eq!(
+            detector.observe_at(ip, 443, start + Duration::from_secs(62), Utc::now()),
+            ScanVerdict::Serve
+        );
+        assert_eq!(
+            detector.observe_at(ip, 8080, start + Duration::from_secs(63), Utc::now()),
+            ScanVerdict::Suppress
+        );
+        assert_eq!(detector.take_all()[0].ports, [80, 443, 8080]);
+    }
+
+    #[test]
+    fn ipv6_sources_are_aggregated_per_prefix() {
+        let detector = detector(false);
+        let start = Instant::now();
+        for (i, port) in [22, 80, 443].into_iter().enumerate() {
+            let ip: IpAddr = format!("2001:db8:1:2::{:x}", i + 1).parse().unwrap();
+            detector.observe_at(ip, port, start, Utc::now());
+        }
+        detector.observe_at("2001:db8:1:3::1".parse().unwrap(), 8080, start, Utc::now());
+
+        let scans = detector.take_all();
+        assert_eq!(scans.len(), 1);
+        assert_eq!(
+            scans[0].client_addr,
+            "2001:db8:1:2::".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(scans[0].ports, [22, 80, 443]);
+    }
+
+    #[test]
+    fn least_recently_seen_sources_are_evicted_past_the_cap() {
+        let detector = detector(false);
+        let scanner: IpAddr = "203.0.113.5".parse().unwrap();
+        let start = Instant::now();
+        for port in [22, 80, 443] {
+            detector.observe_at(scanner, port, start, Utc::now());
+        }
+        // Within the window, so that none of them is pruned as idle
+        for i in 0..MAX_TRACKED_SOURCES as u32 {
+            let ip = IpAddr::V4((0x0a00_0000 + i).into());
+            detector.observe_at(ip, 22, start + Duration::from_secs(1), Utc::now());
+        }
+
+        assert_eq!(
+            detector.sources.lock().unwrap().active.len(),
+            MAX_TRACKED_SOURCES
+        );
+        // The scan of the evicted source is still reported
+        let scans = detector.take_finished_at(start + Duration::from_secs(2));
+        assert_eq!(scans.len(), 1);
+        assert_eq!(scans[0].client_addr, scanner);
+    }
+
+    #[test]
+    fn disabled_detection_tracks_nothing() {
+        let detector = ScanDetector::default();
+        let ip: IpAddr = "203.0.113.5".parse().unwrap();
+        for port in 1..100 {
+            assert_eq!(detector.observe(ip, port), ScanVerdict::Serve);
+        }
+        assert!(detector.take_all().is_empty());
+    }
+}
@@ -1,11 +1,79 @@
-use super::types::ServicePattern;
+use super::client_hello::{self, ClientHello};
+use super::types::{self, PayloadRule, ServicePattern};
+use crate::configuration::types::DetectionStrategy;
 use crate::configuration::types::ServiceConfig;
 use crate::error_handling::types::NetworkError;
-use log::{debug, error};
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use tokio::io::AsyncReadExt;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
+use tracing::{debug, error, warn};
+
+/// Time allowed for a complete ClientHello to arrive
+const CLIENT_HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest ClientHello record looked at, bigger ones are not fingerprinted
+const MAX_CLIENT_HELLO_LEN: usize = 16 * 1024 + 5;
+
+/// First bytes of a connection matched against the payload rules
+pub const PAYLOAD_PEEK_LEN: usize = 1024;
+
+/// Time a client has to send its first bytes before the service is chosen by port,
+/// unless the service sets `detection_timeout_ms`.
+///
+/// Clients of protocols where the server speaks first (FTP, SMTP) wait for the banner,
+/// so this delays their banner when other services have payload rules, unless the
+/// service uses the `server_first` detection.
+pub const PAYLOAD_PEEK_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Commands FTP clients open with, matched for `ftp` services configured without
+/// `header_patterns`
+pub const FTP_COMMANDS: &[&str] = &["USER ", "AUTH TLS", "AUTH SSL", "FEAT", "SYST", "OPTS UTF8"];
+
+/// Greetings SMTP clients open with, matched for `smtp` services configured without
+/// `header_patterns`
+pub const SMTP_COMMANDS: &[&str] = &["EHLO ", "HELO "];
+
+/// Arrays of bulk strings, as RESP clients send commands, and the inline commands
+/// Redis scanners open with, matched for `redis` services configured without
+/// `header_patterns`. MySQL clients wait for the server greeting.
+pub const REDIS_COMMANDS: &[&str] = &["*1\r\n$", "*2\r\n$", "*3\r\n$", "PING\r\n", "INFO\r\n"];
+
+/// Commands matched for a service configured without `header_patterns`, by name
+fn default_header_patterns(service_name: &str) -> &'static [&'static str] {
+    match service_name {
+        "ftp" => FTP_COMMANDS,
+        "smtp" => SMTP_COMMANDS,
+        "redis" => REDIS_COMMANDS,
+        _ => &[],
+    }
+}
+
+/// Name of the service usually found on `port`, as miel services are named
+fn well_known_service(port: u16) -> Option<&'static str> {
+    Some(match port {
+        21 => "ftp",
+        22 | 2222 => "ssh",
+        23 | 2323 => "telnet",
+        25 | 587 => "smtp",
+        80 | 8000 | 8080 => "http",
+        443 | 8443 => "https",
+        3306 => "mysql",
+        5432 => "postgres",
+        6379 => "redis",
+        _ => return None,
+    })
+}
+
+/// Telnet "interpret as command" byte, starting option negotiations
+const TELNET_IAC: u8 = 0xff;
+
+/// Whether `data` opens with a telnet option negotiation (IAC followed by
+/// SB, WILL, WONT, DO or DONT), as telnet clients do before any input
+pub fn looks_like_telnet(data: &[u8]) -> bool {
+    matches!(data, [TELNET_IAC, 0xfa..=0xfe, ..])
+}
 
 #[derive(Clone)]
 pub struct ServiceDetector {
@@ -17,15 +85,51 @@ impl ServiceDetector {
         let mut service_patterns = HashMap::new();
 
         for service in services {
+            // Tarpits are served by their listener, never detected
+            if service.tarpit.is_some() {
+                continue;
+            }
+            let header_patterns = if service.header_patterns.is_empty() {
+                default_header_patterns(&service.name)
+                    .iter()
+                    .map(|c| PayloadRule::Text(c.to_string()))
+                    .collect()
+            } else {
+                service
+                    .header_patterns
+                    .iter()
+                    .filter_map(|pattern| match PayloadRule::parse(pattern) {
+                        Ok(rule) => Some(rule),
+                        Err(e) => {
+                            warn!(
+                                "Ignoring header pattern {:?} of service {}: {}",
+                                pattern, service.name, e
+                            );
+                            None
+                        }
+                    })
+                    .collect()
+            };
             let pattern = ServicePattern {
                 service_name: service.name.clone(),
                 port: service.port,
                 protocol: service.protocol.clone(),
-                header_patterns: service.header_patterns.clone(),
+                header_patterns,
                 banner_patterns: match &service.banner_response {
                     Some(banner) => vec![banner.clone()],
                     None => Vec::new(),
                 },
+                sni_hosts: service
+                    .sni_hosts
+                    .iter()
+                    .map(|host| host.to_ascii_lowercase())
+                    .collect(),
+                detection: service.detection,
+                banner_response: service.banner_response.clone(),
+                detection_timeout: service
+                    .detection_timeout_ms
+                    .map(Duration::from_millis)
+                    .unwrap_or(PAYLOAD_PEEK_TIMEOUT),
             };
 
             service_patterns.insert(pattern.port, pattern);
@@ -34,63 +138,554 @@ impl ServiceDetector {
         Self { service_patterns }
     }
 
-    pub async fn identify_service(&self, stream: &mut TcpStream) -> Result<String, NetworkError> {
+    /// Port whose service a connection accepted on `local_port` is detected as.
+    ///
+    /// A client redirected from `original_dst` targeted the service of its port, else
+    /// the service named after the protocol usually served on it, e.g. `ssh` for
+    /// port 22. The listener port is kept when no service matches either.
+    pub fn targeted_port(&self, local_port: u16, original_dst: Option<SocketAddr>) -> u16 {
+        let Some(original_port) = original_dst.map(|dst| dst.port()) else {
+            return local_port;
+        };
+        if self.service_patterns.contains_key(&original_port) {
+            return original_port;
+        }
+        let Some(protocol) = well_known_service(original_port) else {
+            return local_port;
+        };
+        self.service_patterns
+            .values()
+            .filter(|pattern| pattern.service_name == protocol)
+            .map(|pattern| pattern.port)
+            .min()
+            .unwrap_or(local_port)
+    }
+
+    /// Sends the banner of the targeted port's service when it speaks first, see
+    /// [`targeted_port`](Self::targeted_port).
+    ///
+    /// Returns the bytes sent, which the container sends again once connected, or
+    /// `None` when the service waits for the client.
+    pub async fn send_greeting(
+        &self,
+        stream: &mut TcpStream,
+        original_dst: Option<SocketAddr>,
+    ) -> Result<Option<Vec<u8>>, NetworkError> {
+        let local_port = stream
+            .local_addr()
+            .map_err(|_| NetworkError::ServiceDetectionFailed)?
+            .port();
+        let port = self.targeted_port(local_port, original_dst);
+        let banner = match self.service_patterns.get(&port) {
+            Some(pattern) if pattern.detection == DetectionStrategy::ServerFirst => {
+                match &pattern.banner_response {
+                    Some(banner) => banner.as_bytes().to_vec(),
+                    None => return Ok(None),
+                }
+            }
+            _ => return Ok(None),
+        };
+
+        stream.write_all(&banner).await.map_err(|e| {
+            debug!("Failed to send the banner of port {}: {}", port, e);
+            NetworkError::ServiceDetectionFailed
+        })?;
+        Ok(Some(banner))
+    }
+
+    /// Service a client connected to `stream` is after.
+    ///
+    /// The first bytes the client sends are peeked, without consuming them, and matched
+    /// against the rules of every service, so that e.g. an SSH client on the HTTP port
+    /// reaches the SSH service. How long the client is waited for depends on the
+    /// detection of the port's service, see [`Self::peek_timeout`]. The service of the
+    /// port is chosen otherwise, or when the client sends nothing in time. Clients
+    /// redirected from `original_dst` are matched as if connected to the port they
+    /// targeted, see [`targeted_port`](Self::targeted_port).
+    pub async fn identify_service(
+        &self,
+        stream: &TcpStream,
+        original_dst: Option<SocketAddr>,
+    ) -> Result<String, NetworkError> {
         let local_addr: SocketAddr = stream.local_addr().map_err(|e| {
             error!("Failed to get local address: {}", e);
             NetworkError::ServiceDetectionFailed
         })?;
-        let port = local_addr.port();
+        let port = self.targeted_port(local_addr.port(), original_dst);
 
         debug!("Identifying service on port {}", port);
 
+        if let Some(timeout) = self.peek_timeout(port) {
+            let mut buf = [0u8; PAYLOAD_PEEK_LEN];
+            match tokio::time::timeout(timeout, stream.peek(&mut buf)).await {
+                Ok(Ok(n)) if n > 0 => {
+                    if let Some(service) = self.detect_from_payload(port, &buf[..n]) {
+                        debug!("Service '{}' detected from payload", service);
+                        return Ok(service);
+                    }
+                }
+                Ok(Ok(_)) => debug!("Client closed before sending data"),
+                Ok(Err(e)) => debug!("Failed to peek at the client payload: {}", e),
+                Err(_) => debug!("Client sent nothing, falling back to port {}", port),
+            }
+        }
+
         if let Some(service) = self.detect_from_port(port) {
             debug!("Service '{}' detected from port {}", service, port);
             return Ok(service);
         }
 
-        debug!(
-            "No service detected from port {}, checking payload...",
-            port
-        );
-
-        let mut buf = [0u8; 1024];
-        let n = stream.read(&mut buf).await.map_err(|e| {
-            error!("Failed to read from stream: {}", e);
-            NetworkError::ServiceDetectionFailed
-        })?;
+        debug!("No service detected from payload or port {}", port);
+        Err(NetworkError::ServiceDetectionFailed)
+    }
 
-        if n > 0 {
-            if let Some(service) = self.detect_from_payload(&buf[..n]) {
-                debug!("Service '{}' detected from payload", service);
-                return Ok(service);
-            }
+    /// Time the client payload is waited for on `port`, `None` to go by the port.
+    ///
+    /// `client_first` services always wait, `server_first` ones only once their banner
+    /// was sent, and `auto` ones when services on other ports have payload rules.
+    fn peek_timeout(&self, port: u16) -> Option<Duration> {
+        match self.service_patterns.get(&port) {
+            Some(pattern) => match pattern.detection {
+                DetectionStrategy::ClientFirst => Some(pattern.detection_timeout),
+                DetectionStrategy::ServerFirst => pattern
+                    .banner_response
+                    .as_ref()
+                    .map(|_| pattern.detection_timeout),
+                DetectionStrategy::Auto => self
+                    .has_payload_rules_besides(port)
+                    .then_some(pattern.detection_timeout),
+            },
+            None => self
+                .has_payload_rules_besides(port)
+                .then_some(PAYLOAD_PEEK_TIMEOUT),
         }
-
-        debug!("No service detected from port {} or payload", port);
-        Err(NetworkError::ServiceDetectionFailed)
     }
 
-    fn detect_from_port(&self, port: u16) -> Option<String> {
-        self.service_patterns
-            .get(&port)
-            .map(|pattern| pattern.service_name.clone())
+    /// Whether a service on another port than `port` could claim a connection from its payload
+    fn has_payload_rules_besides(&self, port: u16) -> bool {
+        self.service_patterns.values().any(|service| {
+            service.port != port
+                && (!service.header_patterns.is_empty()
+                    || !service.banner_patterns.is_empty()
+                    || service.service_name == "telnet")
+        })
     }
 
-    fn detect_from_payload(&self, data: &[u8]) -> Option<String> {
-        let data_str = std::str::from_utf8(data).ok()?;
+    /// Peeks at the TLS ClientHello a client sent, without consuming it.
+    ///
+    /// Waits until the whole first record arrived, up to a timeout. Returns `None`
+    /// when the first bytes are not a TLS handshake or the ClientHello is malformed.
+    pub async fn inspect_client_hello(&self, stream: &TcpStream) -> Option<ClientHello> {
+        let mut buf = vec![0u8; MAX_CLIENT_HELLO_LEN];
+        let peek = async {
+            loop {
+                let n = stream.peek(&mut buf).await.ok()?;
+                if n == 0 || (n >= 3 && !client_hello::looks_like_tls(&buf[..n])) {
+                    return None;
+                }
+                match client_hello::record_len(&buf[..n]) {
+                    Some(len) if len > buf.len() => return None,
+                    Some(len) if n >= len => return client_hello::parse(&buf[..n]),
+                    // Rest of the record not received yet
+                    _ => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            }
+        };
+
+        let hello = tokio::time::timeout(CLIENT_HELLO_TIMEOUT, peek)
+            .await
+            .ok()
+            .flatten();
+        if let Some(hello) = &hello {
+            debug!(
+                "ClientHello with SNI {:?} and JA3 {}",
+                hello.sni, hello.ja3_hash
+            );
+        }
+        hello
+    }
 
+    /// Service whose `sni_hosts` match the requested server name
+    pub fn detect_from_sni(&self, sni: &str) -> Option<String> {
+        let sni = sni.to_ascii_lowercase();
         self.service_patterns
             .values()
             .find(|service| {
                 service
-                    .header_patterns
+                    .sni_hosts
                     .iter()
-                    .any(|pattern| data_str.contains(pattern))
-                    || service
-                        .banner_patterns
-                        .iter()
-                        .any(|pattern| data_str.contains(pattern))
+                    .any(|host| match host.strip_prefix("*.") {
+                        Some(domain) => sni
+                            .strip_suffix(domain)
+                            .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+                        None => *host == sni,
+                    })
             })
             .map(|service| service.service_name.clone())
     }
+
+    fn detect_from_port(&self, port: u16) -> Option<String> {
+        self.service_patterns
+            .get(&port)
+            .map(|pattern| pattern.service_name.clone())
+    }
+
+    /// Service whose rules match `data`, the one of `port` being preferred, then the
+    /// others by ascending port so that overlapping rules resolve the same way each time
+    fn detect_from_payload(&self, port: u16, data: &[u8]) -> Option<String> {
+        let mut services: Vec<&ServicePattern> = self.service_patterns.values().collect();
+        services.sort_by_key(|service| (service.port != port, service.port));
+        services
+            .into_iter()
+            .find(|service| Self::payload_matches(service, data))
+            .map(|service| service.service_name.clone())
+    }
+
+    fn payload_matches(service: &ServicePattern, data: &[u8]) -> bool {
+        // Negotiations are not text, so telnet services recognize them without rules
+        (service.service_name == "telnet" && looks_like_telnet(data))
+            || service
+                .header_patterns
+                .iter()
+                .any(|rule| rule.matches(data))
+            || service
+                .banner_patterns
+                .iter()
+                .any(|banner| types::contains(data, banner.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ftp_services_match_ftp_commands_by_default() {
+        let detector = ServiceDetector::new(&[ServiceConfig {
+            name: "ftp".to_string(),
+            port: 21,
+            ..ServiceConfig::default()
+        }]);
+
+        assert_eq!(
+            detector
+                .detect_from_payload(0, b"USER anonymous\r\n")
+                .as_deref(),
+            Some("ftp")
+        );
+        assert_eq!(
+            detector.detect_from_payload(0, b"AUTH TLS\r\n").as_deref(),
+            Some("ftp")
+        );
+        assert_eq!(
+            detector.detect_from_payload(0, b"GET / HTTP/1.1\r\nUser-Agent: x\r\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn smtp_services_match_greetings_by_default() {
+        let detector = ServiceDetector::new(&[ServiceConfig {
+            name: "smtp".to_string(),
+            port: 25,
+            ..ServiceConfig::default()
+        }]);
+
+        assert_eq!(
+            detector
+                .detect_from_payload(0, b"EHLO mail.example.org\r\n")
+                .as_deref(),
+            Some("smtp")
+        );
+        assert_eq!(detector.detect_from_payload(0, b"USER anonymous\r\n"), None);
+    }
+
+    #[test]
+    fn redis_services_match_commands_by_default() {
+        let detector = ServiceDetector::new(&[ServiceConfig {
+            name: "redis".to_string(),
+            port: 6379,
+            ..ServiceConfig::default()
+        }]);
+
+        assert_eq!(
+            detector
+                .detect_from_payload(0, b"*2\r\n$4\r\nAUTH\r\n$8\r\nfoobared\r\n")
+                .as_deref(),
+            Some("redis")
+        );
+        assert_eq!(
+            detector.detect_from_payload(0, b"INFO\r\n").as_deref(),
+            Some("redis")
+        );
+        assert_eq!(
+            detector.detect_from_payload(0, b"EHLO example.org\r\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn telnet_services_match_option_negotiations() {
+        let detector = ServiceDetector::new(&[ServiceConfig {
+            name: "telnet".to_string(),
+            port: 23,
+            ..ServiceConfig::default()
+        }]);
+
+        assert_eq!(
+            detector
+                .detect_from_payload(0, b"\xff\xfd\x03\xff\xfb\x18\xff\xfb\x1f")
+                .as_deref(),
+            Some("telnet")
+        );
+        assert_eq!(detector.detect_from_payload(0, b"\xff\xf4"), None);
+        assert_eq!(detector.detect_from_payload(0, b"root\r\n"), None);
+    }
+
+    #[test]
+    fn sni_matches_exact_and_wildcard_hosts() {
+        let services = [
+            ServiceConfig {
+                name: "webmail".to_string(),
+                port: 8443,
+                sni_hosts: vec!["Mail.Example.org".to_string()],
+                ..ServiceConfig::default()
+            },
+            ServiceConfig {
+                name: "intranet".to_string(),
+                port: 9443,
+                sni_hosts: vec!["*.corp.example".to_string()],
+                ..ServiceConfig::default()
+            },
+        ];
+        let detector = ServiceDetector::new(&services);
+
+        assert_eq!(
+            detector.detect_from_sni("mail.example.org").as_deref(),
+            Some("webmail")
+        );
+        assert_eq!(
+            detector.detect_from_sni("wiki.corp.example").as_deref(),
+            Some("intranet")
+        );
+        assert_eq!(detector.detect_from_sni("corp.example"), None);
+        assert_eq!(detector.detect_from_sni("evilcorp.example"), None);
+    }
+
+    #[test]
+    fn regex_and_byte_rules_match_binary_payloads() {
+        let services = [
+            ServiceConfig {
+                name: "ssh".to_string(),
+                port: 22,
+                header_patterns: vec!["re:^SSH-[12]\\.\\d+-".to_string()],
+                ..ServiceConfig::default()
+            },
+            ServiceConfig {
+                name: "rdp".to_string(),
+                port: 3389,
+                header_patterns: vec!["hex:03 00".to_string()],
+                ..ServiceConfig::default()
+            },
+            ServiceConfig {
+                name: "http".to_string(),
+                port: 80,
+                header_patterns: vec!["GET ".to_string(), "re:(?i)^post ".to_string()],
+                ..ServiceConfig::default()
+            },
+        ];
+        let detector = ServiceDetector::new(&services);
+
+        assert_eq!(
+            detector
+                .detect_from_payload(80, b"SSH-2.0-OpenSSH_9.6\r\n")
+                .as_deref(),
+            Some("ssh")
+        );
+        assert_eq!(
+            detector
+                .detect_from_payload(80, b"\x03\x00\x00\x13\x0e\xe0")
+                .as_deref(),
+            Some("rdp")
+        );
+        assert_eq!(
+            detector.detect_from_payload(22, b"post /login").as_deref(),
+            Some("http")
+        );
+        assert_eq!(detector.detect_from_payload(80, b"X-SSH-2.0-"), None);
+    }
+
+    #[test]
+    fn overlapping_rules_prefer_the_service_of_the_port() {
+        let services = [
+            ServiceConfig {
+                name: "http".to_string(),
+                port: 80,
+                header_patterns: vec!["GET ".to_string()],
+                ..ServiceConfig::default()
+            },
+            ServiceConfig {
+                name: "admin".to_string(),
+                port: 8080,
+                header_patterns: vec!["GET ".to_string()],
+                ..ServiceConfig::default()
+            },
+        ];
+        let detector = ServiceDetector::new(&services);
+
+        assert_eq!(
+            detector.detect_from_payload(8080, b"GET /").as_deref(),
+            Some("admin")
+        );
+        assert_eq!(
+            detector.detect_from_payload(9000, b"GET /").as_deref(),
+            Some("http")
+        );
+    }
+
+    #[test]
+    fn redirected_clients_are_detected_from_the_port_they_targeted() {
+        let services = [
+            ServiceConfig {
+                name: "catch-all".to_string(),
+                port: 9000,
+                ..ServiceConfig::default()
+            },
+            ServiceConfig {
+                name: "ssh".to_string(),
+                port: 2222,
+                ..ServiceConfig::default()
+            },
+            ServiceConfig {
+                name: "http".to_string(),
+                port: 8080,
+                ..ServiceConfig::default()
+            },
+        ];
+        let detector = ServiceDetector::new(&services);
+        let from = |port: u16| Some(SocketAddr::from(([192, 0, 2, 1], port)));
+
+        assert_eq!(detector.targeted_port(9000, None), 9000);
+        assert_eq!(detector.targeted_port(9000, from(22)), 2222);
+        assert_eq!(detector.targeted_port(9000, from(80)), 8080);
+        assert_eq!(detector.targeted_port(9000, from(8080)), 8080);
+        assert_eq!(detector.targeted_port(9000, from(3306)), 9000);
+        assert_eq!(detector.targeted_port(9000, from(1234)), 9000);
+    }
+
+    #[tokio::test]
+    async fn payload_is_peeked_before_falling_back_to_the_port() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let detector = ServiceDetector::new(&[
+            ServiceConfig {
+                name: "http".to_string(),
+                port,
+                header_patterns: vec!["GET ".to_string()],
+                ..ServiceConfig::default()
+            },
+            ServiceConfig {
+                name: "ssh".to_string(),
+                port: 22,
+                header_patterns: vec!["re:^SSH-".to_string()],
+                ..ServiceConfig::default()
+            },
+        ]);
+
+        let mut client = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        client.write_all(b"SSH-2.0-libssh\r\n").await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+        assert_eq!(
+            detector.identify_service(&stream, None).await.unwrap(),
+            "ssh"
+        );
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"SSH-");
+
+        // Silent clients get the service of the port
+        let _client = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        assert_eq!(
+            detector.identify_service(&stream, None).await.unwrap(),
+            "http"
+        );
+    }
+
+    #[tokio::test]
+    async fn server_first_services_send_their_banner_before_detection() {
+        use tokio::io::AsyncReadExt;
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let detector = ServiceDetector::new(&[
+            ServiceConfig {
+                name: "smtp".to_string(),
+                port,
+                banner_response: Some("220 mail ESMTP\r\n".to_string()),
+                detection: DetectionStrategy::ServerFirst,
+                detection_timeout_ms: Some(100),
+                ..ServiceConfig::default()
+            },
+            ServiceConfig {
+                name: "http".to_string(),
+                port: 80,
+                header_patterns: vec!["GET ".to_string()],
+                ..ServiceConfig::default()
+            },
+        ]);
+
+        // Scanners ignoring the banner are still routed from what they send
+        let mut client = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let greeting = detector.send_greeting(&mut stream, None).await.unwrap();
+        assert_eq!(greeting.as_deref(), Some(&b"220 mail ESMTP\r\n"[..]));
+        let mut banner = [0u8; 16];
+        client.read_exact(&mut banner).await.unwrap();
+        assert_eq!(&banner, b"220 mail ESMTP\r\n");
+        client.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        assert_eq!(
+            detector.identify_service(&stream, None).await.unwrap(),
+            "http"
+        );
+
+        // Clients waiting after the banner get the service of the port
+        let _client = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        assert_eq!(
+            detector.identify_service(&stream, None).await.unwrap(),
+            "smtp"
+        );
+    }
+
+    #[test]
+    fn peek_timeout_follows_the_detection_of_the_port() {
+        let mut ssh = ServiceConfig {
+            name: "ssh".to_string(),
+            port: 22,
+            detection: DetectionStrategy::ServerFirst,
+            ..ServiceConfig::default()
+        };
+        let http = ServiceConfig {
+            name: "http".to_string(),
+            port: 80,
+            detection: DetectionStrategy::ClientFirst,
+            detection_timeout_ms: Some(2000),
+            ..ServiceConfig::default()
+        };
+
+        // Nothing on other ports has rules, yet client first services wait
+        let detector = ServiceDetector::new(&[ssh.clone(), http.clone()]);
+        assert_eq!(detector.peek_timeout(80), Some(Duration::from_secs(2)));
+        // Server first services without a banner are handed over right away
+        assert_eq!(detector.peek_timeout(22), None);
+
+        ssh.banner_response = Some("SSH-2.0-OpenSSH_9.6\r\n".to_string());
+        let detector = ServiceDetector::new(&[ssh, http]);
+        assert_eq!(detector.peek_timeout(22), Some(PAYLOAD_PEEK_TIMEOUT));
+    }
 }
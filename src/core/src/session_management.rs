@@ -7,8 +7,14 @@ use serde::{Deserialize, Serialize};
 
 /// Submodule for handling active session logic.
 pub mod active_session;
+/// Submodule for the write-ahead journal of the sessions, replayed after a crash.
+pub mod journal;
+/// Submodule for the session lifecycle notifications.
+pub mod lifecycle;
 /// Submodule for session data structures and utilities.
 pub mod session;
+/// Submodule for the message-based access to the session registry.
+pub mod session_control;
 /// Submodule for session manager implementation.
 pub mod session_manager;
 
@@ -19,10 +25,12 @@ pub mod session_manager;
 /// - `Active`: The session is currently active.
 /// - `Completed`: The session has finished successfully.
 /// - `Error`: The session encountered an error.
+/// - `Interrupted`: The honeypot stopped unexpectedly while the session was active.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum SessionStatus {
     Pending,
     Active,
     Completed,
     Error,
+    Interrupted,
 }
@@ -1,8 +1,13 @@
 use crate::container_management::ContainerHandle;
-use crate::data_capture::StreamRecorder;
+use crate::data_capture::{ByteCount, LastActivity, LiveFeed, StreamRecorder, Termination};
 use crate::session_management::session::Session;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
+use uuid::Uuid;
 
 /// Represents an active session, containing the session state,
 /// an optional handle to a running container, and a stream recorder
@@ -15,4 +20,54 @@ pub struct ActiveSession {
     /// Recorder for capturing streaming data during the session.
     /// Wrapped in Arc<Mutex<>> for thread-safe access across async contexts.
     pub stream_recorder: Arc<Mutex<StreamRecorder>>,
+    /// When the last client connection closed, `None` while one is proxied.
+    pub idle_since: Option<DateTime<Utc>>,
+    /// When data last crossed a connection of the session, touched by its TCP capture.
+    pub last_activity: LastActivity,
+    /// Closes the proxies of the session, which hold `stream_recorder` until they stop.
+    pub termination: Termination,
+    /// Bytes forwarded so far, touched by its TCP and UDP captures.
+    pub transferred: ByteCount,
+    /// Chunks recorded by its captures, sent to the watchers of the session.
+    pub live: LiveFeed,
+    /// Inactivity after which the session is ended, `None` to keep it until it times out.
+    pub idle_timeout: Option<Duration>,
+    /// Times the container was replaced after it stopped.
+    pub container_restarts: u32,
+}
+
+impl ActiveSession {
+    /// What the session is doing at `now`, without waiting for its recorder
+    pub fn summary(&self, now: DateTime<Utc>) -> ActiveSessionSummary {
+        ActiveSessionSummary {
+            id: self.session.id,
+            service_name: self.session.service_name.clone(),
+            client_addr: self.session.client_addr,
+            start_time: self.session.start_time,
+            uptime_secs: (now - self.session.start_time).num_seconds().max(0) as u64,
+            bytes_transferred: self.transferred.get(),
+            last_activity: self.last_activity.get(),
+            idle_since: self.idle_since,
+        }
+    }
+}
+
+/// A live session as listed by [`SessionManager::list_active_sessions`], unlike the
+/// stored [`Session`] records which are complete once the session ended.
+///
+/// [`SessionManager::list_active_sessions`]: crate::session_manager::SessionManager::list_active_sessions
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveSessionSummary {
+    pub id: Uuid,
+    pub service_name: String,
+    pub client_addr: SocketAddr,
+    pub start_time: DateTime<Utc>,
+    /// Seconds since the session started
+    pub uptime_secs: u64,
+    /// Bytes forwarded so far in both directions
+    pub bytes_transferred: u64,
+    /// When data last crossed a connection of the session
+    pub last_activity: DateTime<Utc>,
+    /// When its last connection closed, `None` while one is proxied
+    pub idle_since: Option<DateTime<Utc>>,
 }
@@ -0,0 +1,620 @@
+//! Write-ahead journal of the sessions and containers, for crash recovery.
+//!
+//! Every container started and every session recorded is appended to
+//! `session-journal.jsonl` in the storage directory before it goes live, and
+//! appended again once it is cleaned up or finalized. Each line is synced to disk
+//! as it is written, so that what a run left open can be told apart after a
+//! crash, however abrupt.
+//!
+//! When the honeypot starts, [`SessionJournal::open`] replays the journal and
+//! [`recover`] resolves what it left open:
+//! - the sessions are marked [`SessionStatus::Interrupted`], recreated from the
+//!   journal when their record never reached the storage, and the part of their
+//!   streams spooled to disk is saved as their capture;
+//! - the containers are stopped and removed along with their directories.
+//!
+//! Containers the journal does not know of, e.g. left by a run predating it, are
+//! reclaimed by [`ContainerManager::reclaim_leftovers`].
+//!
+//! Once the journal grew past [`COMPACT_AFTER`] bytes, it is rewritten with only
+//! the entries left open. The lines are written and synced on the blocking
+//! threads of the runtime, the tasks recording them waiting without blocking.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex as AsyncMutex;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::container_management::container_manager::ContainerManager;
+use crate::container_management::types::Runtime;
+use crate::data_capture::spool::{self, SpoolSettings};
+use crate::data_capture::CaptureArtifacts;
+use crate::events::{self, Event};
+use crate::session::Session;
+use crate::storage::storage_trait::Storage;
+use crate::SessionStatus;
+
+/// Name of the journal in the storage directory
+pub const JOURNAL_FILE: &str = "session-journal.jsonl";
+
+/// Size past which the journal is rewritten with only the entries left open
+pub const COMPACT_AFTER: u64 = 1024 * 1024;
+
+/// A container started by the honeypot
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournaledContainer {
+    pub container_id: String,
+    pub runtime: Runtime,
+    pub started_at: DateTime<Utc>,
+}
+
+/// A session recorded by the honeypot, with what is needed to recreate its record
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournaledSession {
+    pub session_id: Uuid,
+    pub service_name: String,
+    pub client_addr: SocketAddr,
+    pub container_id: Option<String>,
+    pub start_time: DateTime<Utc>,
+}
+
+impl JournaledSession {
+    /// What the journal knows of `session`
+    pub fn of(session: &Session) -> Self {
+        Self {
+            session_id: session.id,
+            service_name: session.service_name.clone(),
+            client_addr: session.client_addr,
+            container_id: session.container_id.clone(),
+            start_time: session.start_time,
+        }
+    }
+}
+
+/// One line of the journal
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "entry", rename_all = "snake_case")]
+pub enum JournalEntry {
+    /// A container is being started
+    ContainerStarted(JournaledContainer),
+    /// The container was cleaned up, or failed to start
+    ContainerRemoved { container_id: String },
+    /// A session is being recorded
+    SessionStarted(JournaledSession),
+    /// The final state of the session was saved
+    SessionEnded { session_id: Uuid },
+}
+
+/// What a previous run left open, in journal order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Orphans {
+    pub sessions: Vec<JournaledSession>,
+    pub containers: Vec<JournaledContainer>,
+}
+
+impl Orphans {
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty() && self.containers.is_empty()
+    }
+
+    /// The entries still open in `entries`
+    fn from_entries(entries: Vec<JournalEntry>) -> Self {
+        let mut orphans = Orphans::default();
+        for entry in entries {
+            orphans.apply(entry);
+        }
+        orphans
+    }
+
+    /// Opens or closes what `entry` is about
+    fn apply(&mut self, entry: JournalEntry) {
+        match entry {
+            JournalEntry::ContainerStarted(container) => self.containers.push(container),
+            JournalEntry::ContainerRemoved { container_id } => self
+                .containers
+                .retain(|container| container.container_id != container_id),
+            JournalEntry::SessionStarted(session) => self.sessions.push(session),
+            JournalEntry::SessionEnded { session_id } => self
+                .sessions
+                .retain(|session| session.session_id != session_id),
+        }
+    }
+
+    /// The entries opening what is left open
+    fn entries(&self) -> impl Iterator<Item = JournalEntry> + '_ {
+        self.containers
+            .iter()
+            .cloned()
+            .map(JournalEntry::ContainerStarted)
+            .chain(
+                self.sessions
+                    .iter()
+                    .cloned()
+                    .map(JournalEntry::SessionStarted),
+            )
+    }
+}
+
+/// The journal file, with what its entries left open
+struct JournalFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    /// Length of the journal once last rewritten, which the open entries take
+    compacted_len: u64,
+    open: Orphans,
+}
+
+/// Append-only journal of the sessions and containers, shared by the managers.
+///
+/// A journal that cannot be written to logs the failure and carries on: losing
+/// crash recovery is no reason to stop capturing sessions.
+#[derive(Default)]
+pub struct SessionJournal {
+    /// `None` when the journal is disabled
+    file: Option<Arc<Mutex<JournalFile>>>,
+}
+
+impl SessionJournal {
+    /// A journal recording nothing
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Opens the journal at `path`, creating it if needed, along with what the
+    /// previous run left open. An unreadable line, e.g. the last one when the
+    /// honeypot crashed writing it, is skipped.
+    ///
+    /// The journal is rewritten with only the entries left open.
+    pub fn open(path: &Path) -> io::Result<(Self, Orphans)> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let orphans = match File::open(path) {
+            Ok(file) => Orphans::from_entries(read_entries(file, path)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Orphans::default(),
+            Err(e) => return Err(e),
+        };
+
+        let (file, len) = rewrite(path, &orphans)?;
+        debug!(
+            "Session journal {} opened, {} entries left open",
+            path.display(),
+            orphans.sessions.len() + orphans.containers.len()
+        );
+        let journal = Self {
+            file: Some(Arc::new(Mutex::new(JournalFile {
+                path: path.to_path_buf(),
+                file,
+                len,
+                compacted_len: len,
+                open: orphans.clone(),
+            }))),
+        };
+        Ok((journal, orphans))
+    }
+
+    /// Appends `entry` and syncs it to disk, on a blocking thread
+    pub async fn record(&self, entry: JournalEntry) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let file = file.clone();
+        let appended = tokio::task::spawn_blocking(move || {
+            let mut journal = file.lock().unwrap();
+            if let Err(e) = journal.append(&entry) {
+                error!(
+                    "Cannot write {:?} to the session journal {}: {}",
+                    entry,
+                    journal.path.display(),
+                    e
+                );
+            }
+        })
+        .await;
+        if let Err(e) = appended {
+            error!("Cannot write to the session journal: {}", e);
+        }
+    }
+}
+
+impl JournalFile {
+    fn append(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        let line = line(entry)?;
+        self.file.write_all(&line)?;
+        self.file.sync_data()?;
+        self.len += line.len() as u64;
+        self.open.apply(entry.clone());
+
+        // The open entries alone may outgrow the threshold, the journal is then
+        // rewritten once it doubled
+        if self.len > COMPACT_AFTER.max(self.compacted_len * 2) {
+            let (file, len) = rewrite(&self.path, &self.open)?;
+            debug!(
+                "Session journal {} compacted from {} to {} bytes",
+                self.path.display(),
+                self.len,
+                len
+            );
+            self.file = file;
+            self.len = len;
+            self.compacted_len = len;
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the journal at `path` with the entries opening what `open` holds,
+/// and returns it opened for appending, along with its length
+fn rewrite(path: &Path, open: &Orphans) -> io::Result<(File, u64)> {
+    let rewritten = path.with_extension("jsonl.tmp");
+    {
+        let mut file = File::create(&rewritten)?;
+        for entry in open.entries() {
+            file.write_all(&line(&entry)?)?;
+        }
+        file.sync_all()?;
+    }
+    fs::rename(&rewritten, path)?;
+
+    let file = OpenOptions::new().append(true).open(path)?;
+    let len = file.metadata()?.len();
+    Ok((file, len))
+}
+
+fn line(entry: &JournalEntry) -> io::Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+fn read_entries(file: File, path: &Path) -> Vec<JournalEntry> {
+    let mut entries = Vec::new();
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = match line {
+            Ok(line) if line.trim().is_empty() => continue,
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Cannot read the session journal {}: {}", path.display(), e);
+                break;
+            }
+        };
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => warn!(
+                "Skipping line {} of the session journal {}: {}",
+                n + 1,
+                path.display(),
+                e
+            ),
+        }
+    }
+    entries
+}
+
+/// What the recovery pass resolved
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recovery {
+    /// Sessions marked interrupted
+    pub interrupted_sessions: usize,
+    /// Interrupted sessions whose spooled streams were saved
+    pub salvaged_captures: usize,
+    /// Containers stopped and removed
+    pub removed_containers: usize,
+}
+
+/// Resolves what the previous run left open, see the [module](self) documentation.
+///
+/// Must run before any container is started or session recorded, and before the
+/// spooled streams are cleared.
+pub async fn recover(
+    journal: &SessionJournal,
+    orphans: Orphans,
+    storage: &Arc<dyn Storage + Send + Sync>,
+    container_manager: &AsyncMutex<ContainerManager>,
+    spool: &SpoolSettings,
+) -> Recovery {
+    let mut recovery = Recovery::default();
+
+    for orphan in orphans.sessions {
+        let session_id = orphan.session_id;
+        if interrupt(&orphan, storage, spool, &mut recovery).await {
+            journal
+                .record(JournalEntry::SessionEnded { session_id })
+                .await;
+        }
+    }
+
+    let manager = container_manager.lock().await;
+    for orphan in orphans.containers {
+        manager.remove_orphan(&orphan).await;
+        journal
+            .record(JournalEntry::ContainerRemoved {
+                container_id: orphan.container_id,
+            })
+            .await;
+        recovery.removed_containers += 1;
+    }
+
+    if recovery != Recovery::default() {
+        info!(
+            "Recovered from an unclean shutdown: {} session(s) interrupted, {} with a partial capture, {} container(s) removed",
+            recovery.interrupted_sessions, recovery.salvaged_captures, recovery.removed_containers
+        );
+    }
+    recovery
+}
+
+/// Saves `orphan` as interrupted, returns whether its final state is saved
+async fn interrupt(
+    orphan: &JournaledSession,
+    storage: &Arc<dyn Storage + Send + Sync>,
+    spool: &SpoolSettings,
+    recovery: &mut Recovery,
+) -> bool {
+    let mut session = match storage.get_session(orphan.session_id).await {
+        // Finalized, only the journal entry was lost
+        Ok(session) if session.status != SessionStatus::Active => return true,
+        Ok(session) => session,
+        Err(_) => {
+            debug!(
+                "Session {} never reached the storage, recreating it from the journal",
+                orphan.session_id
+            );
+            Session {
+                id: orphan.session_id,
+                service_name: orphan.service_name.clone(),
+                client_addr: orphan.client_addr,
+                start_time: orphan.start_time,
+                end_time: None,
+                container_id: orphan.container_id.clone(),
+                bytes_transferred: 0,
+                status: SessionStatus::Active,
+                enrichment: None,
+                original_dst: None,
+            }
+        }
+    };
+    // The time of the crash is unknown, the session ends when it is noticed
+    let end_time = *session.end_time.get_or_insert_with(Utc::now);
+    session.status = SessionStatus::Interrupted;
+
+    if let Some(artifacts) = salvage(spool, &session) {
+        match storage.save_capture_artifacts(&artifacts).await {
+            Ok(()) => {
+                session.bytes_transferred = artifacts.total_bytes;
+                recovery.salvaged_captures += 1;
+            }
+            Err(e) => warn!(
+                "Cannot save the partial capture of session {}: {}",
+                session.id, e
+            ),
+        }
+    }
+
+    if let Err(e) = storage.save_session(&session).await {
+        error!("Cannot mark session {} as interrupted: {}", session.id, e);
+        return false;
+    }
+    warn!(
+        "Session {} of {} from {} was interrupted by an unclean shutdown",
+        session.id, session.service_name, session.client_addr
+    );
+    events::emit(Event::SessionEnded {
+        session_id: session.id,
+        service: session.service_name.clone(),
+        client_addr: session.client_addr,
+        status: SessionStatus::Interrupted,
+        bytes_transferred: session.bytes_transferred,
+        duration_secs: (end_time - session.start_time).num_seconds(),
+    });
+    recovery.interrupted_sessions += 1;
+    true
+}
+
+/// The capture of `session` made of the streams spooled before the crash, `None`
+/// when none was. What was still in memory is lost, as are the timestamps
+fn salvage(spool: &SpoolSettings, session: &Session) -> Option<CaptureArtifacts> {
+    let stream = |name| spool::recover(spool, session.id, name);
+    let c2s = stream("client_to_container");
+    let s2c = stream("container_to_client");
+    let stdin = stream("stdin");
+    let stdout = stream("stdout");
+    let stderr = stream("stderr");
+    if [&c2s, &s2c, &stdin, &stdout, &stderr]
+        .iter()
+        .all(|stream| stream.is_none())
+    {
+        return None;
+    }
+
+    let [c2s, s2c, stdin, stdout, stderr] =
+        [c2s, s2c, stdin, stdout, stderr].map(Option::unwrap_or_default);
+    let total_bytes = [&c2s, &s2c, &stdin, &stdout, &stderr]
+        .iter()
+        .map(|stream| stream.len() as u64)
+        .sum();
+    Some(CaptureArtifacts {
+        session_id: session.id,
+        tcp_client_to_container: c2s,
+        tcp_container_to_client: s2c,
+        stdio_stdin: String::from_utf8_lossy(&stdin).to_string(),
+        stdio_stdout: String::from_utf8_lossy(&stdout).to_string(),
+        stdio_stderr: String::from_utf8_lossy(&stderr).to_string(),
+        total_bytes,
+        duration: session
+            .end_time
+            .map_or(TimeDelta::zero(), |end| end - session.start_time),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_capture::spool::Spool;
+    use crate::storage::file_storage::FileStorage;
+
+    fn container(id: &str) -> JournaledContainer {
+        JournaledContainer {
+            container_id: id.to_string(),
+            runtime: Runtime::Docker,
+            started_at: Utc::now(),
+        }
+    }
+
+    fn session(container_id: &str) -> JournaledSession {
+        JournaledSession {
+            session_id: Uuid::new_v4(),
+            service_name: "ssh".to_string(),
+            client_addr: "203.0.113.7:40022".parse().unwrap(),
+            container_id: Some(container_id.to_string()),
+            start_time: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn what_is_left_open_is_replayed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(JOURNAL_FILE);
+        let (journal, orphans) = SessionJournal::open(&path).unwrap();
+        assert!(orphans.is_empty());
+
+        let ended = session("miel-ssh-1");
+        let open = session("miel-ssh-2");
+        journal
+            .record(JournalEntry::ContainerStarted(container("miel-ssh-1")))
+            .await;
+        journal
+            .record(JournalEntry::SessionStarted(ended.clone()))
+            .await;
+        journal
+            .record(JournalEntry::ContainerStarted(container("miel-ssh-2")))
+            .await;
+        journal
+            .record(JournalEntry::SessionStarted(open.clone()))
+            .await;
+        journal
+            .record(JournalEntry::SessionEnded {
+                session_id: ended.session_id,
+            })
+            .await;
+        journal
+            .record(JournalEntry::ContainerRemoved {
+                container_id: "miel-ssh-1".to_string(),
+            })
+            .await;
+        drop(journal);
+        // A line torn by the crash
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"entry\":\"session_en").unwrap();
+
+        let (_journal, orphans) = SessionJournal::open(&path).unwrap();
+        assert_eq!(orphans.sessions, vec![open]);
+        assert_eq!(orphans.containers.len(), 1);
+        assert_eq!(orphans.containers[0].container_id, "miel-ssh-2");
+        // Only the open entries are kept
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn journals_are_compacted_to_their_open_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(JOURNAL_FILE);
+        let (journal, _) = SessionJournal::open(&path).unwrap();
+
+        // Left open through the compaction
+        let open = session("miel-ssh-2");
+        journal
+            .record(JournalEntry::SessionStarted(open.clone()))
+            .await;
+        let mut written = 0;
+        for n in 0..32 {
+            let id = format!("{}-{}", "x".repeat(64 * 1024), n);
+            journal
+                .record(JournalEntry::ContainerStarted(container(&id)))
+                .await;
+            journal
+                .record(JournalEntry::ContainerRemoved { container_id: id })
+                .await;
+            written += 2 * 64 * 1024;
+        }
+        assert!(written > 2 * COMPACT_AFTER);
+        assert!(fs::metadata(&path).unwrap().len() <= COMPACT_AFTER);
+
+        journal
+            .record(JournalEntry::ContainerStarted(container("miel-ssh-3")))
+            .await;
+        let (_, orphans) = SessionJournal::open(&path).unwrap();
+        assert_eq!(orphans.sessions, vec![open]);
+        assert_eq!(orphans.containers[0].container_id, "miel-ssh-3");
+    }
+
+    #[tokio::test]
+    async fn orphaned_sessions_are_interrupted_with_their_spooled_streams() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage: Arc<dyn Storage + Send + Sync> =
+            Arc::new(FileStorage::new(dir.path().join("sessions")).unwrap());
+        let spool = SpoolSettings {
+            dir: Some(dir.path().join("spool")),
+            threshold: 4,
+        };
+        let (journal, _) = SessionJournal::open(&dir.path().join(JOURNAL_FILE)).unwrap();
+
+        // Recorded but never saved, its spooled stream survived the crash
+        let lost = session("miel-ssh-4");
+        let mut c2s = Spool::new(&spool, lost.session_id, "client_to_container");
+        c2s.extend_from_slice(b"uname -a\n");
+        std::mem::forget(c2s);
+        // Saved as active
+        let saved = session("miel-ssh-5");
+        let mut record = Session {
+            id: saved.session_id,
+            service_name: saved.service_name.clone(),
+            client_addr: saved.client_addr,
+            start_time: saved.start_time,
+            end_time: None,
+            container_id: saved.container_id.clone(),
+            bytes_transferred: 0,
+            status: SessionStatus::Active,
+            enrichment: None,
+            original_dst: None,
+        };
+        storage.save_session(&record).await.unwrap();
+        // Finalized before the crash
+        let finalized = session("miel-ssh-6");
+        record.id = finalized.session_id;
+        record.status = SessionStatus::Completed;
+        storage.save_session(&record).await.unwrap();
+
+        let orphans = Orphans {
+            sessions: vec![lost.clone(), saved.clone(), finalized.clone()],
+            containers: Vec::new(),
+        };
+        let manager = AsyncMutex::new(ContainerManager::new_mock());
+        let recovery = recover(&journal, orphans, &storage, &manager, &spool).await;
+        assert_eq!(recovery.interrupted_sessions, 2);
+        assert_eq!(recovery.salvaged_captures, 1);
+
+        let lost = storage.get_session(lost.session_id).await.unwrap();
+        assert_eq!(lost.status, SessionStatus::Interrupted);
+        assert!(lost.end_time.is_some());
+        assert_eq!(lost.bytes_transferred, 9);
+        let artifacts = storage.get_capture_artifacts(lost.id).await.unwrap();
+        assert_eq!(artifacts.tcp_client_to_container, b"uname -a\n");
+        let saved = storage.get_session(saved.session_id).await.unwrap();
+        assert_eq!(saved.status, SessionStatus::Interrupted);
+        let finalized = storage.get_session(finalized.session_id).await.unwrap();
+        assert_eq!(finalized.status, SessionStatus::Completed);
+
+        let (_, orphans) = SessionJournal::open(&dir.path().join(JOURNAL_FILE)).unwrap();
+        assert!(orphans.is_empty());
+    }
+}
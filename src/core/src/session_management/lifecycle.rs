@@ -0,0 +1,65 @@
+//! Session lifecycle notifications.
+//!
+//! The [`SessionManager`](super::session_manager::SessionManager) broadcasts a
+//! [`SessionLifecycle`] whenever a session is created, ends, or has its capture
+//! finalized. Subscribers, such as the web interface `/events` stream, receive
+//! them in real time; a subscriber falling more than [`LIFECYCLE_BUFFER`] events
+//! behind misses the oldest ones.
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::data_capture::CaptureArtifacts;
+use crate::session::Session;
+
+/// Events buffered for each subscriber
+pub const LIFECYCLE_BUFFER: usize = 256;
+
+/// A change in the life of a session
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SessionLifecycle {
+    /// A container was spawned for a new session
+    SessionCreated { session: Session },
+    /// The session is over and its container cleaned up, `status` is final
+    SessionEnded { session: Session },
+    /// The capture artifacts of the session were saved
+    CaptureFinalized {
+        session_id: Uuid,
+        total_bytes: u64,
+        uploaded_files: usize,
+        messages: usize,
+        carved_files: usize,
+    },
+}
+
+impl SessionLifecycle {
+    /// Summary of the artifacts saved for `session_id`
+    pub fn capture_finalized(session_id: &Uuid, artifacts: &CaptureArtifacts) -> Self {
+        SessionLifecycle::CaptureFinalized {
+            session_id: *session_id,
+            total_bytes: artifacts.total_bytes,
+            uploaded_files: artifacts.uploaded_files.len(),
+            messages: artifacts.messages.len(),
+            carved_files: artifacts.carved_files.len(),
+        }
+    }
+
+    /// Name of the event, as in its JSON form
+    pub fn name(&self) -> &'static str {
+        match self {
+            SessionLifecycle::SessionCreated { .. } => "session_created",
+            SessionLifecycle::SessionEnded { .. } => "session_ended",
+            SessionLifecycle::CaptureFinalized { .. } => "capture_finalized",
+        }
+    }
+}
+
+/// Sending half of the lifecycle channel, subscribers are added with `subscribe`
+pub type LifecycleSender = broadcast::Sender<SessionLifecycle>;
+
+/// Creates a lifecycle channel without subscribers
+pub fn channel() -> LifecycleSender {
+    broadcast::channel(LIFECYCLE_BUFFER).0
+}
@@ -1,3 +1,4 @@
+use crate::enrichment::IpEnrichment;
 use crate::SessionStatus;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
@@ -26,4 +27,10 @@ pub struct Session {
     pub bytes_transferred: u64,
     /// Final status of the session
     pub status: SessionStatus,
+    /// What the enrichment providers know about the client IP
+    #[serde(default)]
+    pub enrichment: Option<IpEnrichment>,
+    /// Address the client connected to, when it was redirected to the listener
+    #[serde(default)]
+    pub original_dst: Option<SocketAddr>,
 }
@@ -0,0 +1,661 @@
+//! Message-based access to the session registry.
+//!
+//! The [`SessionManager`] runs in a task of its own, see [`SessionManager::run`],
+//! and is only reached through [`SessionControl`] handles. Each command carries the
+//! channel its outcome is sent back on.
+//!
+//! Every session request is served by a task of its own owning the lifecycle of
+//! its session: the registry admits the request, the task starts the container of
+//! a new session, proxies the client and, for the sessions it ends, finalizes the
+//! capture. The registry is therefore never held up by a container starting, a
+//! client being proxied or a capture being finalized, and requests are admitted
+//! while other sessions start.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::active_session::{ActiveSession, ActiveSessionSummary};
+use crate::configuration::config::Config;
+use crate::configuration::types::ServiceConfig;
+use crate::container_management::{cgroup, ContainerReport};
+use crate::data_capture::{LiveFeed, StreamRecorder};
+use crate::error_handling::types::{NetworkError, SessionError};
+use crate::metrics;
+use crate::network::connection_filter::ConnectionPermit;
+use crate::network::ssh::SshServer;
+use crate::network::types::{ClientStream, SessionRequest, UdpSessionRequest};
+use crate::session_manager::{Admission, SessionFinalizer, SessionManager, SessionStarter};
+use crate::telemetry;
+use crate::SessionStatus;
+
+/// Commands queued before the registry handles them
+const COMMAND_BUFFER: usize = 64;
+
+/// Inactivity after which a UDP flow is considered over and its session ended,
+/// unless its service sets its own idle timeout
+const UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A request to the session registry
+pub enum SessionCommand {
+    /// Admits a TCP connection to the session it joins or to a new one. The
+    /// request comes without its stream, which stays with the task serving it
+    Open {
+        request: Box<SessionRequest>,
+        service: Box<ServiceConfig>,
+        reply: oneshot::Sender<Result<Admission, SessionError>>,
+    },
+    /// Admits a UDP flow to a new session
+    OpenUdp {
+        client_addr: SocketAddr,
+        reply: oneshot::Sender<Result<SessionStarter, SessionError>>,
+    },
+    /// Registers a session admitted to start, `None` when it failed to
+    Started { session: Option<Box<ActiveSession>> },
+    /// Leaves a session idle once its connection closed
+    Idle { session_id: Uuid },
+    /// Removes a session from the registry, to be ended by the sender
+    Take {
+        session_id: Uuid,
+        reply: oneshot::Sender<Option<(Box<ActiveSession>, SessionFinalizer)>>,
+    },
+    /// Takes the expired sessions out of the registry, see
+    /// [`SessionManager::cleanup_expired_sessions`], and finalizes them in the background
+    CleanupExpired { reply: oneshot::Sender<()> },
+    /// See [`SessionManager::check_containers`]
+    CheckContainers {
+        services: Vec<ServiceConfig>,
+        reply: oneshot::Sender<usize>,
+    },
+    /// See [`SessionManager::log_stats`]
+    LogStats { reply: oneshot::Sender<()> },
+    /// See [`SessionManager::configure`]
+    Configure {
+        config: Box<Config>,
+        reply: oneshot::Sender<Result<(), NetworkError>>,
+    },
+    /// Applies a change of the services to the warm containers and SSH servers
+    SetServices {
+        services: Vec<ServiceConfig>,
+        warm_containers: usize,
+        reply: oneshot::Sender<Result<(), NetworkError>>,
+    },
+    /// See [`SessionManager::finalize_session_capture`]
+    FinalizeCapture {
+        session_id: Uuid,
+        reply: oneshot::Sender<Result<(), SessionError>>,
+    },
+    /// See [`SessionManager::trigger_stdio_capture`]
+    TriggerStdioCapture {
+        session_id: Uuid,
+        reply: oneshot::Sender<Result<(), SessionError>>,
+    },
+    /// See [`SessionManager::container_report`]
+    Containers {
+        reply: oneshot::Sender<ContainerReport>,
+    },
+    /// See [`SessionManager::list_active_sessions`]
+    ListActive {
+        reply: oneshot::Sender<Vec<ActiveSessionSummary>>,
+    },
+    /// See [`SessionManager::live_feed`]
+    LiveFeed {
+        session_id: Uuid,
+        reply: oneshot::Sender<Option<LiveFeed>>,
+    },
+    /// See [`SessionManager::get_session_stats`]
+    Stats {
+        session_id: Uuid,
+        reply: oneshot::Sender<Option<(SessionStatus, u64, chrono::Duration)>>,
+    },
+    /// Ends every session and stops the registry
+    Shutdown {
+        reply: oneshot::Sender<Result<(), SessionError>>,
+    },
+}
+
+/// Cloneable handle sending [`SessionCommand`]s to the session registry
+#[derive(Debug, Clone)]
+pub struct SessionControl {
+    sender: mpsc::Sender<SessionCommand>,
+}
+
+impl SessionControl {
+    /// Runs `manager` as the session registry, in a task of its own
+    pub fn spawn(manager: SessionManager) -> Self {
+        let (sender, commands) = mpsc::channel(COMMAND_BUFFER);
+        tokio::spawn(manager.run(commands));
+        Self { sender }
+    }
+
+    /// Admits `request` to a session, and returns its connection to [proxy](Connection::proxy).
+    ///
+    /// The container of a new session is started by the caller, the registry
+    /// counting the session against the limit meanwhile.
+    pub async fn open_connection(
+        &self,
+        mut request: SessionRequest,
+        service_config: &ServiceConfig,
+    ) -> Result<Connection, SessionError> {
+        let client_stream = request.stream.take().ok_or(SessionError::CreationFailed)?;
+        let greeting = request.greeting.take().unwrap_or_default();
+        let permit = request.permit.take();
+        let service_name = request.service_name.clone();
+        let client_addr = request.client_addr;
+        let original_dst = request.original_dst;
+        let timestamp = request.timestamp;
+
+        let admission = self
+            .request(|reply| SessionCommand::Open {
+                request: Box::new(request),
+                service: Box::new(service_config.clone()),
+                reply,
+            })
+            .await??;
+        let starter = match admission {
+            Admission::Join {
+                session_id,
+                recorder,
+                container_stream,
+                ssh_server,
+            } => {
+                telemetry::record_session(&session_id);
+                return Ok(Connection {
+                    session_id,
+                    recorder,
+                    client_stream,
+                    container_stream,
+                    greeting,
+                    ssh_server,
+                    _permit: permit,
+                });
+            }
+            Admission::Start(starter) => starter,
+        };
+
+        debug!("Creating new session for {}", client_addr);
+        let (session, mut container_handle) = match starter
+            .create_session(
+                service_name,
+                client_addr,
+                original_dst,
+                timestamp,
+                service_config,
+            )
+            .await
+        {
+            Ok(started) => started,
+            Err(e) => {
+                self.register(None).await;
+                return Err(e);
+            }
+        };
+        let Some(container_stream) = container_handle.tcp_socket.take() else {
+            starter.abandon(session, container_handle).await;
+            self.register(None).await;
+            return Err(SessionError::CreationFailed);
+        };
+        let active_session = starter.active_session(session, container_handle);
+        SessionManager::start_stdio_capture(&active_session).await;
+
+        let session_id = active_session.session.id;
+        telemetry::record_session(&session_id);
+        let recorder = active_session.stream_recorder.clone();
+        self.register(Some(active_session)).await;
+        info!("New session {} established for {}", session_id, client_addr);
+
+        Ok(Connection {
+            session_id,
+            recorder,
+            client_stream,
+            container_stream,
+            greeting,
+            ssh_server: starter.ssh_server(),
+            _permit: permit,
+        })
+    }
+
+    /// Starts the session of a UDP client flow, and returns the flow to
+    /// [proxy](UdpFlow::proxy) until it goes idle, which ends the session.
+    ///
+    /// Unlike TCP sessions, a UDP session is never reused: the listener opens a new
+    /// flow, hence a new session, once the previous one has ended.
+    pub async fn open_udp_flow(
+        &self,
+        request: UdpSessionRequest,
+        service_config: &ServiceConfig,
+    ) -> Result<UdpFlow, SessionError> {
+        let UdpSessionRequest {
+            socket,
+            datagrams,
+            service_name,
+            client_addr,
+            timestamp,
+            permit,
+            span: _,
+        } = request;
+        let starter = self
+            .request(|reply| SessionCommand::OpenUdp { client_addr, reply })
+            .await??;
+
+        debug!("Creating new UDP session for {}", client_addr);
+        let (session, mut container_handle) = match starter
+            .create_session(service_name, client_addr, None, timestamp, service_config)
+            .await
+        {
+            Ok(started) => started,
+            Err(e) => {
+                self.register(None).await;
+                return Err(e);
+            }
+        };
+        let Some(container_socket) = container_handle.udp_socket.take() else {
+            starter.abandon(session, container_handle).await;
+            self.register(None).await;
+            return Err(SessionError::CreationFailed);
+        };
+        let active_session = starter.active_session(session, container_handle);
+
+        let session_id = active_session.session.id;
+        telemetry::record_session(&session_id);
+        let recorder = active_session.stream_recorder.clone();
+        self.register(Some(active_session)).await;
+        info!(
+            "New UDP session {} established for {}",
+            session_id, client_addr
+        );
+
+        Ok(UdpFlow {
+            session_id,
+            recorder,
+            socket,
+            client_addr,
+            datagrams,
+            container_socket,
+            idle_timeout: service_config
+                .idle_timeout_secs
+                .map_or(UDP_IDLE_TIMEOUT, Duration::from_secs),
+            _permit: permit,
+        })
+    }
+
+    /// Ends a session, finalizing its capture outside of the registry. The
+    /// connections still proxied to it are closed first.
+    ///
+    /// Errors
+    /// - Returns [`SessionError::NotFound`] when no such session is active.
+    pub async fn end_session(&self, session_id: &Uuid) -> Result<(), SessionError> {
+        self.end_session_as(session_id, None).await
+    }
+
+    /// Ends a session like [`end_session`](Self::end_session), with `status` when set
+    async fn end_session_as(
+        &self,
+        session_id: &Uuid,
+        status: Option<SessionStatus>,
+    ) -> Result<(), SessionError> {
+        let session_id = *session_id;
+        let (mut active_session, finalizer) = self
+            .request(|reply| SessionCommand::Take { session_id, reply })
+            .await?
+            .ok_or(SessionError::NotFound)?;
+        if let Some(status) = status {
+            active_session.session.status = status;
+        }
+        finalizer.finish(*active_session).await
+    }
+
+    /// See [`SessionManager::cleanup_expired_sessions`], the sessions being
+    /// finalized in the background once out of the registry
+    pub async fn cleanup_expired_sessions(&self) -> Result<(), SessionError> {
+        self.request(|reply| SessionCommand::CleanupExpired { reply })
+            .await
+    }
+
+    /// See [`SessionManager::check_containers`]
+    pub async fn check_containers(
+        &self,
+        services: &[ServiceConfig],
+    ) -> Result<usize, SessionError> {
+        self.request(|reply| SessionCommand::CheckContainers {
+            services: services.to_vec(),
+            reply,
+        })
+        .await
+    }
+
+    /// See [`SessionManager::log_stats`]
+    pub async fn log_stats(&self) -> Result<(), SessionError> {
+        self.request(|reply| SessionCommand::LogStats { reply })
+            .await
+    }
+
+    /// See [`SessionManager::configure`]
+    pub async fn configure(
+        &self,
+        config: &Config,
+    ) -> Result<Result<(), NetworkError>, SessionError> {
+        self.request(|reply| SessionCommand::Configure {
+            config: Box::new(config.clone()),
+            reply,
+        })
+        .await
+    }
+
+    /// Keeps `warm_containers` warm containers per service of `services`, and
+    /// starts their SSH servers
+    pub async fn set_services(
+        &self,
+        services: &[ServiceConfig],
+        warm_containers: usize,
+    ) -> Result<Result<(), NetworkError>, SessionError> {
+        self.request(|reply| SessionCommand::SetServices {
+            services: services.to_vec(),
+            warm_containers,
+            reply,
+        })
+        .await
+    }
+
+    /// See [`SessionManager::finalize_session_capture`]
+    pub async fn finalize_session_capture(&self, session_id: &Uuid) -> Result<(), SessionError> {
+        let session_id = *session_id;
+        self.request(|reply| SessionCommand::FinalizeCapture { session_id, reply })
+            .await?
+    }
+
+    /// See [`SessionManager::trigger_stdio_capture`]
+    pub async fn trigger_stdio_capture(&self, session_id: &Uuid) -> Result<(), SessionError> {
+        let session_id = *session_id;
+        self.request(|reply| SessionCommand::TriggerStdioCapture { session_id, reply })
+            .await?
+    }
+
+    /// The running containers with what they use, which is read outside of the
+    /// registry. The containers reported by the metrics are updated along.
+    pub async fn container_report(&self) -> Result<ContainerReport, SessionError> {
+        let mut report = self
+            .request(|reply| SessionCommand::Containers { reply })
+            .await?;
+        for container in &mut report.containers {
+            container.usage = cgroup::resource_usage(&container.runtime, &container.id).await;
+        }
+        metrics::global().set_containers(&report.containers);
+        Ok(report)
+    }
+
+    /// See [`SessionManager::list_active_sessions`]
+    pub async fn list_active_sessions(&self) -> Result<Vec<ActiveSessionSummary>, SessionError> {
+        self.request(|reply| SessionCommand::ListActive { reply })
+            .await
+    }
+
+    /// See [`SessionManager::live_feed`]
+    pub async fn live_feed(&self, session_id: &Uuid) -> Result<Option<LiveFeed>, SessionError> {
+        let session_id = *session_id;
+        self.request(|reply| SessionCommand::LiveFeed { session_id, reply })
+            .await
+    }
+
+    /// See [`SessionManager::get_session_stats`]
+    pub async fn get_session_stats(
+        &self,
+        session_id: &Uuid,
+    ) -> Option<(SessionStatus, u64, chrono::Duration)> {
+        let session_id = *session_id;
+        self.request(|reply| SessionCommand::Stats { session_id, reply })
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Ends every session, then stops the registry
+    pub async fn shutdown(&self) -> Result<(), SessionError> {
+        self.request(|reply| SessionCommand::Shutdown { reply })
+            .await?
+    }
+
+    /// Hands a session admitted to start over to the registry
+    async fn register(&self, active_session: Option<ActiveSession>) {
+        let command = SessionCommand::Started {
+            session: active_session.map(Box::new),
+        };
+        if self.sender.send(command).await.is_err() {
+            warn!("Session registry stopped, a started session is dropped");
+        }
+    }
+
+    async fn send(&self, command: SessionCommand) {
+        if self.sender.send(command).await.is_err() {
+            debug!("Session registry stopped, command dropped");
+        }
+    }
+
+    async fn request<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<T>) -> SessionCommand,
+    ) -> Result<T, SessionError> {
+        let (reply, response) = oneshot::channel();
+        self.sender
+            .send(command(reply))
+            .await
+            .map_err(|_| SessionError::RegistryStopped)?;
+        response.await.map_err(|_| SessionError::RegistryStopped)
+    }
+}
+
+/// A client connection admitted to a session, ready to be proxied to its container
+pub struct Connection {
+    session_id: Uuid,
+    recorder: Arc<Mutex<StreamRecorder>>,
+    client_stream: ClientStream,
+    container_stream: TcpStream,
+    greeting: Vec<u8>,
+    ssh_server: Option<Arc<SshServer>>,
+    _permit: Option<ConnectionPermit>,
+}
+
+impl Connection {
+    pub fn session_id(&self) -> Uuid {
+        self.session_id
+    }
+
+    /// Proxies the connection until it closes, then leaves its session idle in
+    /// the registry, to be joined again or ended once it times out. A session
+    /// past its quota is ended right away
+    pub async fn proxy(self, control: &SessionControl) -> Result<(), SessionError> {
+        let (proxy_result, quota_exceeded) = {
+            let recorder = self.recorder.lock().await;
+            let proxy_result = match &self.ssh_server {
+                Some(server) => {
+                    recorder
+                        .start_ssh_proxy(
+                            self.client_stream,
+                            self.container_stream,
+                            self.greeting,
+                            server,
+                        )
+                        .await
+                }
+                None => {
+                    recorder
+                        .start_tcp_proxy_with_greeting(
+                            self.client_stream,
+                            self.container_stream,
+                            self.greeting,
+                        )
+                        .await
+                }
+            };
+            (proxy_result, recorder.quota_exceeded())
+        };
+        if quota_exceeded {
+            // Not to be joined again, the quota holds for the whole session
+            info!("Ending session {} past its quota", self.session_id);
+            if let Err(e) = control.end_session(&self.session_id).await {
+                warn!("Could not end session {}: {}", self.session_id, e);
+            }
+        } else {
+            control
+                .send(SessionCommand::Idle {
+                    session_id: self.session_id,
+                })
+                .await;
+        }
+        proxy_result.map_err(|e| {
+            error!(
+                "Failed to start TCP proxy for session {}: {}",
+                self.session_id, e
+            );
+            SessionError::CreationFailed
+        })
+    }
+}
+
+/// A UDP client flow with its session started, ready to be proxied to its container
+pub struct UdpFlow {
+    session_id: Uuid,
+    recorder: Arc<Mutex<StreamRecorder>>,
+    socket: Arc<UdpSocket>,
+    client_addr: SocketAddr,
+    datagrams: Receiver<Vec<u8>>,
+    container_socket: UdpSocket,
+    idle_timeout: Duration,
+    _permit: Option<ConnectionPermit>,
+}
+
+impl UdpFlow {
+    pub fn session_id(&self) -> Uuid {
+        self.session_id
+    }
+
+    /// Relays the datagrams of the flow until it goes idle, then ends and
+    /// finalizes its session
+    pub async fn proxy(self, control: &SessionControl) -> Result<(), SessionError> {
+        let id = self.session_id;
+        let proxy_result = self
+            .recorder
+            .lock()
+            .await
+            .start_udp_proxy(
+                self.socket,
+                self.client_addr,
+                self.datagrams,
+                self.container_socket,
+                self.idle_timeout,
+            )
+            .await;
+
+        let status = match &proxy_result {
+            Ok(()) => None,
+            Err(e) => {
+                error!("UDP proxy failed for session {}: {}", id, e);
+                Some(SessionStatus::Error)
+            }
+        };
+        match control.end_session_as(&id, status).await {
+            // Ended from outside, which closed the flow
+            Err(SessionError::NotFound) => {}
+            result => result?,
+        }
+        proxy_result.map_err(SessionError::CaptureError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container_management::container_manager::ContainerManager;
+    use crate::storage::file_storage::FileStorage;
+    use crate::storage::storage_trait::Storage;
+
+    #[tokio::test]
+    async fn sessions_being_started_count_against_the_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage: Arc<dyn Storage + Send + Sync> =
+            Arc::new(FileStorage::new(dir.path()).unwrap());
+        let manager = SessionManager::new(
+            Arc::new(Mutex::new(ContainerManager::new_mock())),
+            storage,
+            1,
+        );
+        let control = SessionControl::spawn(manager);
+        let client_addr: SocketAddr = "203.0.113.7:40000".parse().unwrap();
+        let admit = || control.request(|reply| SessionCommand::OpenUdp { client_addr, reply });
+
+        assert!(admit().await.unwrap().is_ok());
+        // The registry answers while the first session starts
+        assert!(matches!(
+            admit().await.unwrap(),
+            Err(SessionError::SessionLimitReached)
+        ));
+
+        // Failing to start frees its place
+        control.register(None).await;
+        assert!(admit().await.unwrap().is_ok());
+
+        control.shutdown().await.unwrap();
+        assert!(matches!(
+            control.log_stats().await,
+            Err(SessionError::RegistryStopped)
+        ));
+    }
+
+    #[tokio::test]
+    async fn containers_of_sessions_failing_to_start_are_cleaned_up() {
+        if std::process::Command::new("python3")
+            .arg("--version")
+            .output()
+            .is_err()
+        {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let storage: Arc<dyn Storage + Send + Sync> =
+            Arc::new(FileStorage::new(dir.path()).unwrap());
+        let manager = SessionManager::new(
+            Arc::new(Mutex::new(ContainerManager::new_mock())),
+            storage.clone(),
+            1,
+        );
+        let control = SessionControl::spawn(manager);
+        // A TCP service, whose container has no UDP socket to serve a flow with
+        let service = ServiceConfig {
+            name: "telnet".to_string(),
+            port: 23,
+            runtime: Some(crate::container_management::Runtime::ProcessSandbox),
+            ..ServiceConfig::default()
+        };
+        let (_sender, datagrams) = mpsc::channel(1);
+        let request = UdpSessionRequest {
+            socket: Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap()),
+            datagrams,
+            service_name: service.name.clone(),
+            client_addr: "203.0.113.7:40000".parse().unwrap(),
+            timestamp: chrono::Utc::now(),
+            permit: None,
+            span: tracing::Span::none(),
+        };
+
+        assert!(matches!(
+            control.open_udp_flow(request, &service).await,
+            Err(SessionError::CreationFailed)
+        ));
+        let report = control.container_report().await.unwrap();
+        assert_eq!(report.stats.total_created, 1);
+        assert_eq!(report.stats.active_count, 0);
+        let sessions = storage.get_sessions(None).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].status, SessionStatus::Error);
+        assert!(sessions[0].end_time.is_some());
+        assert!(control.list_active_sessions().await.unwrap().is_empty());
+
+        control.shutdown().await.unwrap();
+    }
+}
@@ -1,19 +1,35 @@
-use crate::active_session::ActiveSession;
-use crate::configuration::types::ServiceConfig;
+use crate::active_session::{ActiveSession, ActiveSessionSummary};
+use crate::configuration::config::Config;
+use crate::configuration::types::{CaptureSpoolConfig, ServiceConfig, SessionQuota, SshMode};
 use crate::container_management::container_manager::ContainerManager;
-use crate::container_management::ContainerHandle;
-use crate::data_capture::StreamRecorder;
-use crate::error_handling::types::SessionError;
+use crate::container_management::{ContainerHandle, ContainerPool, ContainerReport};
+use crate::data_capture::spool::SpoolSettings;
+use crate::data_capture::yara::RuleSet;
+use crate::data_capture::{CaptureArtifacts, LiveFeed, StreamRecorder};
+use crate::enrichment::Enricher;
+use crate::error_handling::types::{CaptureError, NetworkError, SessionError, StorageError};
+use crate::events::{self, Event};
+use crate::honeytokens;
+use crate::journal::{JournalEntry, JournaledSession, SessionJournal};
+use crate::lifecycle::{self, LifecycleSender, SessionLifecycle};
+use crate::metrics;
+use crate::network::ssh::SshServer;
 use crate::network::types::SessionRequest;
 use crate::session::Session;
+use crate::session_control::SessionCommand;
 use crate::storage::storage_trait::Storage;
+use crate::storage::types::TagSource;
+use crate::tagging::{SessionActivity, Tagger};
 use crate::SessionStatus;
-use chrono::Utc;
-use log::{debug, error, info, warn};
+use chrono::{TimeDelta, Utc};
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::Mutex;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Mutex};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 use uuid::Uuid;
 
 /// The structure related to session management
@@ -23,9 +39,34 @@ use uuid::Uuid;
 pub struct SessionManager {
     active_sessions: HashMap<Uuid, ActiveSession>,
     container_manager: Arc<Mutex<ContainerManager>>,
+    /// Source of the containers of new sessions, warm when pre-warming is configured
+    container_pool: ContainerPool,
     storage: Arc<dyn Storage + Send + Sync>,
     max_sessions: usize,
+    /// Sessions whose container is starting, counted against `max_sessions`
+    starting: usize,
+    /// Longest lifetime of a session from its start
     session_timeout: Duration,
+    /// Inactivity after which a session is ended, unless its service sets its own
+    idle_timeout: Option<Duration>,
+    /// Times the container of a session is restarted after it stopped
+    max_container_restarts: u32,
+    /// How long a session stays joinable from its client IP after its last connection
+    reuse_window: Option<TimeDelta>,
+    /// Looks up the client IPs of new sessions
+    enricher: Arc<Enricher>,
+    /// Where the streams of new sessions are spooled past their threshold
+    spool: SpoolSettings,
+    /// YARA rules the captures are scanned with
+    rules: Arc<RuleSet>,
+    /// Tags the sessions when their capture is finalized
+    tagger: Arc<Tagger>,
+    /// Broadcasts session creations, ends and finalized captures
+    lifecycle: LifecycleSender,
+    /// Embedded SSH servers of the services terminating SSH, by service name
+    ssh_servers: HashMap<String, Arc<SshServer>>,
+    /// Records the sessions started and finalized
+    journal: Arc<SessionJournal>,
 }
 
 impl SessionManager {
@@ -36,177 +77,572 @@ impl SessionManager {
     ) -> Self {
         Self {
             active_sessions: HashMap::new(),
+            container_pool: ContainerPool::new(container_manager.clone()),
             container_manager,
             storage,
             max_sessions,
+            starting: 0,
             session_timeout: Duration::new(170000, 0), //170'000sec = ~48H
+            idle_timeout: None,
+            max_container_restarts: 0,
+            reuse_window: None,
+            enricher: Arc::new(Enricher::new()),
+            spool: SpoolSettings::default(),
+            rules: Arc::new(RuleSet::default()),
+            tagger: Arc::new(Tagger::default()),
+            lifecycle: lifecycle::channel(),
+            ssh_servers: HashMap::new(),
+            journal: Arc::new(SessionJournal::disabled()),
         }
     }
 
-    pub async fn handle_session(
+    /// Channel of the session lifecycle events, to `subscribe` to
+    pub fn lifecycle(&self) -> LifecycleSender {
+        self.lifecycle.clone()
+    }
+
+    /// Updates the concurrent session limit, sessions over a lowered limit are left running
+    pub fn set_max_sessions(&mut self, max_sessions: usize) {
+        self.max_sessions = max_sessions;
+    }
+
+    /// Ends sessions `secs` seconds after they started, however active they are
+    pub fn set_session_timeout(&mut self, secs: u64) {
+        self.session_timeout = Duration::from_secs(secs);
+    }
+
+    /// Ends sessions that exchanged no data for `secs` seconds, `0` to never end
+    /// them for inactivity. Services can override it with their `idle_timeout_secs`
+    pub fn set_idle_timeout(&mut self, secs: u64) {
+        self.idle_timeout = (secs > 0).then(|| Duration::from_secs(secs));
+    }
+
+    /// Spools the streams of new sessions to disk past `config.threshold_kb`, in its
+    /// `dir` or in the `spool` directory of `storage_path`
+    pub fn set_capture_spool(&mut self, config: &CaptureSpoolConfig, storage_path: &Path) {
+        let dir = config
+            .dir
+            .clone()
+            .unwrap_or_else(|| storage_path.join("spool"));
+        self.spool = SpoolSettings::new(dir, config.threshold_kb);
+    }
+
+    /// Spooling of the streams of new sessions
+    pub fn capture_spool(&self) -> &SpoolSettings {
+        &self.spool
+    }
+
+    /// Records the sessions started and finalized in `journal`, so that those a
+    /// crash interrupts are recovered on startup
+    pub fn set_journal(&mut self, journal: Arc<SessionJournal>) {
+        self.journal = journal;
+    }
+
+    /// Restarts the container of a session up to `restarts` times when it stopped,
+    /// `0` to end the session instead
+    pub fn set_container_restarts(&mut self, restarts: u32) {
+        self.max_container_restarts = restarts;
+    }
+
+    /// Idle timeout of the sessions of `service_config`
+    fn idle_timeout_for(&self, service_config: &ServiceConfig) -> Option<Duration> {
+        match service_config.idle_timeout_secs {
+            Some(0) => None,
+            Some(secs) => Some(Duration::from_secs(secs)),
+            None => self.idle_timeout,
+        }
+    }
+
+    /// Lets connections from a session's IP join it for `minutes` after its last
+    /// connection closed, `0` to only join sessions of the same IP and port
+    pub fn set_session_reuse(&mut self, minutes: u64) {
+        self.reuse_window = (minutes > 0).then(|| TimeDelta::minutes(minutes as i64));
+    }
+
+    /// Keeps `per_service` warm containers for each enabled service, `0` to create
+    /// containers on demand only
+    pub fn set_warm_containers(&mut self, services: &[ServiceConfig], per_service: usize) {
+        self.container_pool.configure(services, per_service);
+    }
+
+    /// Attaches what `enricher` knows about the client IP to each new session
+    pub fn set_enrichment(&mut self, enricher: Arc<Enricher>) {
+        self.enricher = enricher;
+    }
+
+    /// Scans the capture of each session with `rules` when it is finalized
+    pub fn set_rules(&mut self, rules: RuleSet) {
+        self.rules = Arc::new(rules);
+    }
+
+    /// Tags each session with the rules of `tagger` whose criteria it meets, when
+    /// its capture is finalized
+    pub fn set_tagging(&mut self, tagger: Tagger) {
+        self.tagger = Arc::new(tagger);
+    }
+
+    /// Terminates the SSH connections of the `services` in the embedded mode with
+    /// a server of their own, see [`SshServer`]. The server of a service whose
+    /// settings did not change is kept, so that its clients see the same host key.
+    ///
+    /// # Errors
+    /// Fails when the host key of a service cannot be loaded, the servers in use
+    /// being kept.
+    pub fn set_ssh_servers(&mut self, services: &[ServiceConfig]) -> Result<(), NetworkError> {
+        let mut servers = HashMap::new();
+        for service in services
+            .iter()
+            .filter(|service| service.ssh.mode == SshMode::Embedded)
+        {
+            // A generated host key lasts as long as its service settings
+            if let Some(server) = self
+                .ssh_servers
+                .get(&service.name)
+                .filter(|server| server.serves(service))
+            {
+                servers.insert(service.name.clone(), server.clone());
+                continue;
+            }
+            let server = SshServer::new(service)?;
+            info!(
+                "Embedded SSH server of {} uses host key {}",
+                service.name,
+                server.fingerprint()
+            );
+            servers.insert(service.name.clone(), Arc::new(server));
+        }
+        self.ssh_servers = servers;
+        Ok(())
+    }
+
+    /// Serves the commands of the [`SessionControl`] handles until it is told to
+    /// shut down, or until every handle is dropped.
+    ///
+    /// The manager stays the registry of the active sessions: the tasks serving
+    /// the session requests start containers, proxy the clients and finalize the
+    /// sessions they end on their own, so that commands are answered meanwhile.
+    pub async fn run(mut self, mut commands: mpsc::Receiver<SessionCommand>) {
+        while let Some(command) = commands.recv().await {
+            match command {
+                SessionCommand::Open {
+                    request,
+                    service,
+                    reply,
+                } => {
+                    let _ = reply.send(self.admit(&request, &service).await);
+                }
+                SessionCommand::OpenUdp { client_addr, reply } => {
+                    let _ = reply.send(self.admit_udp(client_addr));
+                }
+                SessionCommand::Started { session } => self.register(session.map(|s| *s)),
+                SessionCommand::Idle { session_id } => {
+                    if let Some(active_session) = self.active_sessions.get_mut(&session_id) {
+                        active_session.idle_since = Some(Utc::now());
+                    }
+                }
+                SessionCommand::Take { session_id, reply } => {
+                    let taken = self
+                        .take_session(&session_id)
+                        .map(|(active_session, finalizer)| (Box::new(active_session), finalizer));
+                    let _ = reply.send(taken);
+                }
+                SessionCommand::CleanupExpired { reply } => {
+                    Self::finish_in_background(self.take_expired_sessions());
+                    let _ = reply.send(());
+                }
+                SessionCommand::CheckContainers { services, reply } => {
+                    let _ = reply.send(self.check_containers(&services).await);
+                }
+                SessionCommand::LogStats { reply } => {
+                    self.log_stats().await;
+                    let _ = reply.send(());
+                }
+                SessionCommand::Configure { config, reply } => {
+                    let _ = reply.send(self.configure(&config));
+                }
+                SessionCommand::SetServices {
+                    services,
+                    warm_containers,
+                    reply,
+                } => {
+                    self.set_warm_containers(&services, warm_containers);
+                    let _ = reply.send(self.set_ssh_servers(&services));
+                }
+                SessionCommand::FinalizeCapture { session_id, reply } => {
+                    let _ = reply.send(self.finalize_session_capture(&session_id).await);
+                }
+                SessionCommand::TriggerStdioCapture { session_id, reply } => {
+                    let _ = reply.send(self.trigger_stdio_capture(&session_id).await);
+                }
+                SessionCommand::Containers { reply } => {
+                    let _ = reply.send(self.container_report().await);
+                }
+                SessionCommand::ListActive { reply } => {
+                    let _ = reply.send(self.list_active_sessions());
+                }
+                SessionCommand::LiveFeed { session_id, reply } => {
+                    let _ = reply.send(self.live_feed(&session_id));
+                }
+                SessionCommand::Stats { session_id, reply } => {
+                    let _ = reply.send(self.get_session_stats(&session_id));
+                }
+                SessionCommand::Shutdown { reply } => {
+                    let _ = reply.send(self.shutdown_all_sessions().await);
+                    break;
+                }
+            }
+        }
+        debug!("Session registry stopped");
+    }
+
+    /// Applies the session settings of a reloaded configuration. The previous SSH
+    /// servers are kept when the new ones cannot be started
+    pub fn configure(&mut self, config: &Config) -> Result<(), NetworkError> {
+        self.set_max_sessions(config.max_sessions);
+        self.set_warm_containers(&config.services, config.warm_containers);
+        self.set_session_reuse(config.session_reuse_minutes);
+        self.set_session_timeout(config.session_timeout_secs);
+        self.set_idle_timeout(config.idle_timeout_secs);
+        self.set_container_restarts(config.maintenance.max_container_restarts);
+        self.set_capture_spool(&config.capture_spool, &config.storage_path);
+        self.set_ssh_servers(&config.services)
+    }
+
+    /// Admits `request` to the session it joins, connecting to its container, or
+    /// to a new session when the limit allows it. Sessions being started count
+    /// against the limit until they are [registered](Self::register).
+    async fn admit(
         &mut self,
-        mut request: SessionRequest,
+        request: &SessionRequest,
         service_config: &ServiceConfig,
-    ) -> Result<(), SessionError> {
-        let request_stream = request.stream.take().ok_or(SessionError::CreationFailed)?;
+    ) -> Result<Admission, SessionError> {
+        let ssh_server = self.ssh_servers.get(&service_config.name).cloned();
+        Self::finish_in_background(self.take_idle_sessions());
+
+        debug!("Processing session request from {}", request.client_addr);
+
+        // Joining a session does not count against the limit
+        if let Some(active_session) = self.find_session(request) {
+            debug!("Reusing existing session for {}", request.client_addr);
+
+            let container_handle = active_session
+                .container_handle
+                .as_mut()
+                .ok_or(SessionError::CreationFailed)?;
+            let container_stream = match container_handle.tcp_socket.take() {
+                Some(socket) => socket,
+                None => container_handle.connect_service().await.map_err(|e| {
+                    error!(
+                        "Failed to reconnect to container {}: {}",
+                        container_handle.id, e
+                    );
+                    SessionError::CreationFailed
+                })?,
+            };
+            active_session.idle_since = None;
+            Self::start_stdio_capture(active_session).await;
+
+            return Ok(Admission::Join {
+                session_id: active_session.session.id,
+                recorder: active_session.stream_recorder.clone(),
+                container_stream,
+                ssh_server,
+            });
+        }
 
         // Check session limits
-        if self.active_sessions.len() >= self.max_sessions {
+        if self.active_sessions.len() + self.starting >= self.max_sessions {
             warn!(
                 "Session limit reached ({}/{}), rejecting connection from {}",
-                self.active_sessions.len(),
+                self.active_sessions.len() + self.starting,
                 self.max_sessions,
                 request.client_addr
             );
             return Err(SessionError::SessionLimitReached);
         }
+        self.starting += 1;
+        let idle_timeout = self.idle_timeout_for(service_config);
+        Ok(Admission::Start(self.starter(
+            ssh_server,
+            idle_timeout,
+            service_config.quota,
+        )))
+    }
 
-        debug!("Processing session request from {}", request.client_addr);
+    /// Admits a UDP flow of `client_addr` to a new session, see [`admit`](Self::admit)
+    fn admit_udp(&mut self, client_addr: SocketAddr) -> Result<SessionStarter, SessionError> {
+        if self.active_sessions.len() + self.starting >= self.max_sessions {
+            warn!(
+                "Session limit reached ({}/{}), rejecting UDP flow from {}",
+                self.active_sessions.len() + self.starting,
+                self.max_sessions,
+                client_addr
+            );
+            return Err(SessionError::SessionLimitReached);
+        }
+        self.starting += 1;
+        Ok(self.starter(None, None, SessionQuota::default()))
+    }
 
-        if let Some(active_session) = self.find_session(&request) {
-            debug!("Reusing existing session for {}", request.client_addr);
+    /// Adds a session admitted by [`admit`](Self::admit) once started, `None` when
+    /// it failed to start
+    fn register(&mut self, active_session: Option<ActiveSession>) {
+        self.starting = self.starting.saturating_sub(1);
+        if let Some(active_session) = active_session {
+            self.active_sessions
+                .insert(active_session.session.id, active_session);
+            self.publish_active_sessions();
+        }
+    }
 
-            let container_tcp_socket = active_session
-                .container_handle
-                .as_mut()
-                .and_then(|handle| handle.tcp_socket.take())
-                .ok_or(SessionError::CreationFailed)?;
+    /// What starting a session needs, so that it starts outside of the registry
+    fn starter(
+        &self,
+        ssh_server: Option<Arc<SshServer>>,
+        idle_timeout: Option<Duration>,
+        quota: SessionQuota,
+    ) -> SessionStarter {
+        SessionStarter {
+            container_pool: self.container_pool.clone(),
+            enricher: self.enricher.clone(),
+            storage: self.storage.clone(),
+            rules: self.rules.clone(),
+            lifecycle: self.lifecycle.clone(),
+            ssh_server,
+            idle_timeout,
+            quota,
+            spool: self.spool.clone(),
+            journal: self.journal.clone(),
+        }
+    }
 
-            // Start TCP proxy with existing session's recorder
-            {
-                let recorder = active_session.stream_recorder.lock().await;
-                recorder
-                    .start_tcp_proxy(request_stream, container_tcp_socket)
-                    .await
-                    .map_err(|e| {
-                        error!(
-                            "Failed to start TCP proxy for session {}: {}",
-                            active_session.session.id, e
-                        );
-                        SessionError::CreationFailed
-                    })?;
-            }
+    /// What ending a session needs, so that it is finalized outside of the registry
+    fn finalizer(&self) -> SessionFinalizer {
+        SessionFinalizer {
+            container_manager: self.container_manager.clone(),
+            storage: self.storage.clone(),
+            enricher: self.enricher.clone(),
+            tagger: self.tagger.clone(),
+            lifecycle: self.lifecycle.clone(),
+            journal: self.journal.clone(),
+        }
+    }
 
-            // Attempt to start stdio capture if PTY is available
-            if let Some(ref container_handle) = active_session.container_handle {
-                if let Some(pty_master) = container_handle.pty_master.as_ref() {
-                    let mut recorder = active_session.stream_recorder.lock().await;
-                    if let Err(e) = recorder.start_stdio_capture(
-                        pty_master
-                            .try_clone()
-                            .map_err(|_| SessionError::CreationFailed)?,
-                    ) {
-                        debug!(
-                            "Could not start stdio capture for session {}: {}",
-                            active_session.session.id, e
-                        );
-                        // Continue execution - stdio capture is optional
-                    }
+    /// Session a new connection joins: the one of the same IP and port or, with a
+    /// reuse window, the session of the same IP on the same service whose last
+    /// connection closed less than the window ago.
+    fn find_session(&mut self, request: &SessionRequest) -> Option<&mut ActiveSession> {
+        let reuse_window = self.reuse_window;
+        let now = Utc::now();
+        self.active_sessions.values_mut().find(|active_s| {
+            let session = &active_s.session;
+            if request.client_addr.ip() != session.client_addr.ip() {
+                return false;
+            }
+            match reuse_window {
+                None => request.client_addr.port() == session.client_addr.port(),
+                Some(window) => {
+                    session.service_name == request.service_name
+                        && active_s.idle_since.is_none_or(|idle| now - idle < window)
                 }
             }
+        })
+    }
 
-            return Ok(());
-        }
+    /// Removes the sessions idle for longer than the reuse window, if any, to be
+    /// ended with their finalizers
+    fn take_idle_sessions(&mut self) -> Vec<(ActiveSession, SessionFinalizer)> {
+        let Some(window) = self.reuse_window else {
+            return Vec::new();
+        };
+        let now = Utc::now();
+        let idle: Vec<Uuid> = self
+            .active_sessions
+            .iter()
+            .filter(|(_, active_s)| active_s.idle_since.is_some_and(|idle| now - idle >= window))
+            .map(|(id, _)| *id)
+            .collect();
 
-        debug!("Creating new session for {}", request.client_addr);
-        let client_addr = request.client_addr; // Store client_addr before moving request
-        let (session, container_handle) = self.create_session(request, service_config).await?;
-        let id = session.id;
+        idle.iter()
+            .filter_map(|session_id| {
+                debug!("Session {} outlived its reuse window", session_id);
+                self.take_session(session_id)
+            })
+            .collect()
+    }
 
-        // Save the new session to the database before creating ActiveSession
-        if let Err(e) = self.storage.save_session(&session) {
-            error!("Failed to persist session {} to storage: {}", id, e);
-            // Continue anyway, session can still proceed
-        } else {
-            debug!("Session {} persisted to storage", id);
-        }
+    /// Removes the sessions whose connections all closed that outlived the session
+    /// timeout since they started, or went idle for longer than their idle timeout
+    /// or the reuse window, to be ended with their finalizers. A proxied connection
+    /// is closed by its own idle timeout
+    fn take_expired_sessions(&mut self) -> Vec<(ActiveSession, SessionFinalizer)> {
+        let mut ended = self.take_idle_sessions();
 
-        let mut active_session = ActiveSession {
-            session,
-            container_handle: Some(container_handle),
-            stream_recorder: Arc::new(Mutex::new(StreamRecorder::new(id, self.storage.clone()))),
-        };
+        let now = Utc::now();
+        let session_timeout = TimeDelta::from_std(self.session_timeout).unwrap_or(TimeDelta::MAX);
+        let expired: Vec<Uuid> = self
+            .active_sessions
+            .iter()
+            .filter(|(_, active_s)| {
+                // Its connection holds the recorder the session is finalized with
+                if active_s.idle_since.is_none() {
+                    return false;
+                }
+                let idle = active_s.idle_timeout.is_some_and(|timeout| {
+                    (now - active_s.last_activity.get())
+                        .to_std()
+                        .is_ok_and(|since| since >= timeout)
+                });
+                idle || now - active_s.session.start_time >= session_timeout
+            })
+            .map(|(id, _)| *id)
+            .collect();
 
-        let container_tcp_socket = active_session
-            .container_handle
-            .as_mut()
-            .and_then(|handle| handle.tcp_socket.take())
-            .ok_or(SessionError::CreationFailed)?;
+        if !expired.is_empty() {
+            info!("Cleaning up {} expired sessions", expired.len());
+            ended.extend(
+                expired
+                    .iter()
+                    .filter_map(|session_id| self.take_session(session_id)),
+            );
+        }
+        ended
+    }
 
-        // Start TCP proxy for new session
-        {
-            let recorder = active_session.stream_recorder.lock().await;
-            recorder
-                .start_tcp_proxy(request_stream, container_tcp_socket)
-                .await
-                .map_err(|e| {
-                    error!("Failed to start TCP proxy for session {}: {}", id, e);
-                    SessionError::CreationFailed
-                })?;
-        }
-
-        // Attempt to start stdio capture if PTY is available
-        if let Some(ref container_handle) = active_session.container_handle {
-            if let Some(pty_master) = container_handle.pty_master.as_ref() {
-                let mut recorder = active_session.stream_recorder.lock().await;
-                if let Err(e) = recorder.start_stdio_capture(
-                    pty_master
-                        .try_clone()
-                        .map_err(|_| SessionError::CreationFailed)?,
-                ) {
-                    debug!("Could not start stdio capture for session {}: {}", id, e);
-                    // Continue execution - stdio capture is optional
-                }
+    /// Ends the expired sessions, see [`take_expired_sessions`](Self::take_expired_sessions)
+    pub async fn cleanup_expired_sessions(&mut self) {
+        for (active_session, finalizer) in self.take_expired_sessions() {
+            let session_id = active_session.session.id;
+            if let Err(e) = finalizer.finish(active_session).await {
+                error!("Failed to cleanup expired session {}: {}", session_id, e);
             }
         }
-
-        self.active_sessions.insert(id, active_session);
-        info!("New session {} established for {}", id, client_addr);
-
-        Ok(())
     }
 
-    fn find_session(&mut self, request: &SessionRequest) -> Option<&mut ActiveSession> {
-        let found = self.active_sessions.iter_mut().find(|(_, active_s)| {
-            request.client_addr.ip() == active_s.session.client_addr.ip()
-                && request.client_addr.port() == active_s.session.client_addr.port()
-        });
-        match found {
-            Some((_, active_s)) => Some(active_s),
-            None => None,
+    /// Finalizes `ended` sessions on their own tasks, so that the registry keeps
+    /// serving commands meanwhile
+    fn finish_in_background(ended: Vec<(ActiveSession, SessionFinalizer)>) {
+        for (active_session, finalizer) in ended {
+            tokio::spawn(async move {
+                let session_id = active_session.session.id;
+                if let Err(e) = finalizer.finish(active_session).await {
+                    error!("Failed to end session {}: {}", session_id, e);
+                }
+            });
         }
     }
 
-    pub async fn cleanup_expired_sessions(&mut self) {
-        let now = Utc::now();
-        let timeout_secs = self.session_timeout.as_secs() as i64;
-        let mut expired = Vec::new();
-
-        self.active_sessions.retain(|id, session| {
-            let expired_session = session
-                .session
-                .end_time
-                .map(|end| (now - end).num_seconds() >= timeout_secs)
-                .unwrap_or(false);
-
-            if expired_session {
-                expired.push(*id);
-                false // remove from map
-            } else {
-                true // keep in map
+    /// Checks the containers of the idle sessions, restarting the ones that stopped
+    /// while the session has restarts left and ending the session otherwise.
+    ///
+    /// Returns how many sessions were ended.
+    pub async fn check_containers(&mut self, services: &[ServiceConfig]) -> usize {
+        let mut ended = Vec::new();
+        for (id, active_s) in self.active_sessions.iter_mut() {
+            // A session whose connection is proxied is checked once it closed
+            if active_s.idle_since.is_none() {
+                continue;
             }
-        });
+            let Some(service_config) = services
+                .iter()
+                .find(|s| s.name == active_s.session.service_name)
+            else {
+                continue;
+            };
+            let Some(handle) = active_s.container_handle.as_mut() else {
+                continue;
+            };
 
-        if !expired.is_empty() {
-            info!("Cleaning up {} expired sessions", expired.len());
+            let mut manager = self.container_manager.lock().await;
+            let health = manager.check_health(handle, service_config).await;
+            if health.is_healthy() {
+                continue;
+            }
+            metrics::global().container_failed();
+            events::emit(Event::ContainerFailed {
+                service: active_s.session.service_name.clone(),
+                client_addr: active_s.session.client_addr,
+                error: format!("container {}: {}", handle.id, health),
+            });
 
-            // Finalize captures and cleanup containers for expired sessions
-            for session_id in expired {
-                if let Err(e) = self.end_session(&session_id).await {
-                    error!("Failed to cleanup expired session {}: {}", session_id, e);
+            // Otherwise ending the session cleans the container up
+            if active_s.container_restarts < self.max_container_restarts {
+                active_s.container_restarts += 1;
+                // What the stopped container captured is collected before it is removed
+                {
+                    let mut recorder = active_s.stream_recorder.lock().await;
+                    recorder.stop_stdio_capture();
+                    Self::collect_activity(active_s, &mut recorder);
+                    Self::collect_uploads(active_s, &recorder).await;
+                    Self::collect_messages(active_s, &recorder).await;
                 }
+                let Some(handle) = active_s.container_handle.take() else {
+                    continue;
+                };
+                match manager.restart_container(handle, service_config).await {
+                    Ok(restarted) => {
+                        metrics::global().container_created();
+                        info!(
+                            "Container of session {} restarted ({}/{})",
+                            id, active_s.container_restarts, self.max_container_restarts
+                        );
+                        if let Ok((log, terminal)) = restarted.stdio_sources() {
+                            let mut recorder = active_s.stream_recorder.lock().await;
+                            if let Err(e) = recorder.restart_stdio_capture(log, terminal) {
+                                debug!("Could not restart stdio capture for session {}: {}", id, e);
+                            }
+                        }
+                        active_s.container_handle = Some(restarted);
+                        continue;
+                    }
+                    Err(e) => error!("Failed to restart container of session {}: {}", id, e),
+                }
+            }
+            active_s.session.status = SessionStatus::Error;
+            ended.push(*id);
+        }
+
+        for session_id in &ended {
+            if let Err(e) = self.end_session(session_id).await {
+                error!(
+                    "Failed to end session {} of a stopped container: {}",
+                    session_id, e
+                );
+            }
+        }
+        ended.len()
+    }
+
+    /// Logs the active sessions per service and the container counters
+    pub async fn log_stats(&self) {
+        let mut per_service: Vec<(&str, usize)> = Vec::new();
+        for active_s in self.active_sessions.values() {
+            let service = active_s.session.service_name.as_str();
+            match per_service.iter_mut().find(|(name, _)| *name == service) {
+                Some((_, count)) => *count += 1,
+                None => per_service.push((service, 1)),
             }
         }
+        per_service.sort_unstable();
+        let per_service: Vec<String> = per_service
+            .iter()
+            .map(|(service, count)| format!("{}: {}", service, count))
+            .collect();
+
+        let containers = self.container_manager.lock().await.get_container_stats();
+        info!(
+            "{}/{} active sessions ({}), {} containers running, {} created, {} failed",
+            self.active_sessions.len(),
+            self.max_sessions,
+            if per_service.is_empty() {
+                "none".to_string()
+            } else {
+                per_service.join(", ")
+            },
+            containers.active_count,
+            containers.total_created,
+            containers.failed_count
+        );
     }
 
     pub async fn shutdown_all_sessions(&mut self) -> Result<(), SessionError> {
@@ -224,6 +660,7 @@ impl SessionManager {
         }
 
         self.active_sessions.clear();
+        self.publish_active_sessions();
         debug!("Session manager shutdown completed");
         Ok(())
     }
@@ -237,7 +674,7 @@ impl SessionManager {
             active_session.session.end_time = Some(Utc::now());
 
             // First, save the updated session to ensure it exists in the database
-            if let Err(e) = self.storage.save_session(&active_session.session) {
+            if let Err(e) = self.storage.save_session(&active_session.session).await {
                 error!(
                     "Failed to persist session {} before finalizing capture: {}",
                     session_id, e
@@ -246,8 +683,11 @@ impl SessionManager {
                 return Err(SessionError::CreationFailed);
             }
 
-            let recorder = active_session.stream_recorder.lock().await;
-            match recorder.finalize_capture() {
+            let mut recorder = active_session.stream_recorder.lock().await;
+            Self::collect_activity(active_session, &mut recorder);
+            Self::collect_uploads(active_session, &recorder).await;
+            Self::collect_messages(active_session, &recorder).await;
+            match recorder.finalize_capture().await {
                 Ok(artifacts) => {
                     debug!(
                         "Capture finalized for session {}: {} bytes total",
@@ -256,9 +696,23 @@ impl SessionManager {
 
                     // Update session with capture statistics
                     active_session.session.bytes_transferred = artifacts.total_bytes;
+                    Self::scan_files(&self.enricher, &self.storage, &artifacts);
+                    Self::tag_exploits(&self.storage, session_id, &recorder).await;
+                    Self::tag_session(
+                        &self.tagger,
+                        &self.storage,
+                        active_session,
+                        &recorder,
+                        &artifacts,
+                    )
+                    .await;
+                    // Nobody listening is not an error, the event is simply dropped
+                    let _ = self
+                        .lifecycle
+                        .send(SessionLifecycle::capture_finalized(session_id, &artifacts));
 
                     // Save the updated session again with the final byte count
-                    if let Err(e) = self.storage.save_session(&active_session.session) {
+                    if let Err(e) = self.storage.save_session(&active_session.session).await {
                         error!(
                             "Failed to update session {} with final statistics: {}",
                             session_id, e
@@ -276,7 +730,8 @@ impl SessionManager {
                     active_session.session.status = SessionStatus::Error;
 
                     // Save the error status to database
-                    if let Err(save_err) = self.storage.save_session(&active_session.session) {
+                    if let Err(save_err) = self.storage.save_session(&active_session.session).await
+                    {
                         error!(
                             "Failed to persist error status for session {}: {}",
                             session_id, save_err
@@ -297,71 +752,17 @@ impl SessionManager {
 
     /// Manually end a session and finalize its capture
     pub async fn end_session(&mut self, session_id: &Uuid) -> Result<(), SessionError> {
-        if let Some(mut active_session) = self.active_sessions.remove(session_id) {
-            debug!("Ending session {}", session_id);
-
-            // Finalize capture
-            active_session.session.end_time = Some(Utc::now());
-
-            // First, save the session with end_time to ensure it exists in the database
-            if let Err(e) = self.storage.save_session(&active_session.session) {
-                error!(
-                    "Failed to persist session {} before finalizing capture: {}",
-                    session_id, e
-                );
-                active_session.session.status = SessionStatus::Error;
-            }
-
-            let recorder = active_session.stream_recorder.lock().await;
-
-            match recorder.finalize_capture() {
-                Ok(artifacts) => {
-                    debug!(
-                        "Capture finalized for session {}: {} bytes total",
-                        session_id, artifacts.total_bytes
-                    );
-                    active_session.session.bytes_transferred = artifacts.total_bytes;
-                }
-                Err(e) => {
-                    error!(
-                        "Failed to finalize capture for session {}: {}",
-                        session_id, e
-                    );
-                    active_session.session.status = SessionStatus::Error;
-                }
-            }
-
-            active_session.session.status = if active_session.session.status != SessionStatus::Error
-            {
-                SessionStatus::Completed
-            } else {
-                SessionStatus::Error
-            };
-
-            // Clean up container if present
-            if let Some(container_handle) = active_session.container_handle.take() {
-                let mut manager = self.container_manager.lock().await;
-                manager
-                    .cleanup_container(container_handle)
-                    .await
-                    .map_err(SessionError::ContainerError)?;
-            }
-
-            // Update the session in the database with final status and statistics
-            if let Err(e) = self.storage.save_session(&active_session.session) {
-                error!(
-                    "Failed to persist final session {} state: {}",
-                    session_id, e
-                );
-            } else {
-                debug!("Session {} final state persisted", session_id);
-            }
+        let (active_session, finalizer) = self
+            .take_session(session_id)
+            .ok_or(SessionError::NotFound)?;
+        finalizer.finish(active_session).await
+    }
 
-            debug!("Session {} ended successfully", session_id);
-            Ok(())
-        } else {
-            Err(SessionError::NotFound)
-        }
+    /// Removes a session from the registry, to be ended with the returned finalizer
+    pub fn take_session(&mut self, session_id: &Uuid) -> Option<(ActiveSession, SessionFinalizer)> {
+        let active_session = self.active_sessions.remove(session_id)?;
+        self.publish_active_sessions();
+        Some((active_session, self.finalizer()))
     }
 
     /// Get access to a session's stream recorder for additional capture operations
@@ -375,14 +776,10 @@ impl SessionManager {
     pub async fn trigger_stdio_capture(&mut self, session_id: &Uuid) -> Result<(), SessionError> {
         if let Some(active_session) = self.active_sessions.get_mut(session_id) {
             if let Some(ref container_handle) = active_session.container_handle {
-                if let Some(pty_master) = container_handle.pty_master.as_ref() {
+                if let Ok((log, terminal)) = container_handle.stdio_sources() {
                     let mut recorder = active_session.stream_recorder.lock().await;
                     recorder
-                        .start_stdio_capture(
-                            pty_master
-                                .try_clone()
-                                .map_err(|_| SessionError::CreationFailed)?,
-                        )
+                        .start_stdio_capture(log, terminal)
                         .map_err(SessionError::CaptureError)?;
                     debug!(
                         "Stdio capture manually triggered for session {}",
@@ -402,6 +799,34 @@ impl SessionManager {
         }
     }
 
+    /// Summaries of the sessions active right now, oldest first
+    pub fn list_active_sessions(&self) -> Vec<ActiveSessionSummary> {
+        let now = Utc::now();
+        let mut sessions: Vec<ActiveSessionSummary> = self
+            .active_sessions
+            .values()
+            .map(|active_session| active_session.summary(now))
+            .collect();
+        sessions.sort_by_key(|summary| summary.start_time);
+        sessions
+    }
+
+    /// The chunks recorded by an active session, to be watched as they are
+    pub fn live_feed(&self, session_id: &Uuid) -> Option<LiveFeed> {
+        self.active_sessions
+            .get(session_id)
+            .map(|active_session| active_session.live.clone())
+    }
+
+    /// The container counters and the running containers, without their usage
+    pub async fn container_report(&self) -> ContainerReport {
+        let manager = self.container_manager.lock().await;
+        ContainerReport {
+            stats: manager.get_container_stats(),
+            containers: manager.list_containers(Utc::now()),
+        }
+    }
+
     /// Get session statistics including capture information
     pub fn get_session_stats(
         &self,
@@ -421,40 +846,711 @@ impl SessionManager {
         })
     }
 
-    async fn create_session(
-        &mut self,
-        request: SessionRequest,
-        service_config: &ServiceConfig,
-    ) -> Result<(Session, ContainerHandle), SessionError> {
-        if self.max_sessions == self.active_sessions.len() {
-            return Err(SessionError::CreationFailed);
+    /// Tails the activity log and terminal of the session's container while it
+    /// lasts, best effort
+    pub(crate) async fn start_stdio_capture(active_session: &ActiveSession) {
+        let Some(container_handle) = active_session.container_handle.as_ref() else {
+            return;
+        };
+        let result = match container_handle.stdio_sources() {
+            Ok((log, terminal)) => active_session
+                .stream_recorder
+                .lock()
+                .await
+                .start_stdio_capture(log, terminal),
+            Err(e) => Err(CaptureError::StdioError(e)),
+        };
+        // Stdio capture is optional, the session goes on without it
+        if let Err(e) = result {
+            debug!(
+                "Could not start stdio capture for session {}: {}",
+                active_session.session.id, e
+            );
+        }
+    }
+
+    /// Adds the stdio and login attempts logged by the session's container to its
+    /// capture, best effort
+    fn collect_activity(active_session: &ActiveSession, recorder: &mut StreamRecorder) {
+        let Some(container_handle) = active_session.container_handle.as_ref() else {
+            return;
+        };
+        let log_path = container_handle.activity_log();
+        if !log_path.exists() {
+            return;
         }
+        if let Err(e) = recorder.parse_stdio_log_from_file(&log_path) {
+            warn!(
+                "Could not parse the activity log of session {}: {}",
+                active_session.session.id, e
+            );
+        }
+    }
 
-        let container_handle = match self
-            .container_manager
-            .lock()
+    /// Adds the files uploaded to the session's container to its capture, best effort
+    async fn collect_uploads(active_session: &ActiveSession, recorder: &StreamRecorder) {
+        let Some(container_handle) = active_session.container_handle.as_ref() else {
+            return;
+        };
+        match recorder
+            .collect_uploads(container_handle.upload_dir())
             .await
-            .create_container(service_config)
+        {
+            Ok(uploads) => {
+                for upload in uploads {
+                    info!(
+                        "Session {} uploaded {} ({} bytes, sha256 {})",
+                        active_session.session.id,
+                        upload.name,
+                        upload.content.len(),
+                        upload.sha256
+                    );
+                    events::emit(Event::FileUploaded {
+                        session_id: active_session.session.id,
+                        service: active_session.session.service_name.clone(),
+                        name: upload.name,
+                        sha256: upload.sha256,
+                        size: upload.content.len(),
+                        truncated: upload.truncated,
+                    });
+                }
+            }
+            Err(e) => warn!(
+                "Could not collect uploads of session {}: {}",
+                active_session.session.id, e
+            ),
+        }
+    }
+
+    /// Looks the files of a finalized capture up on VirusTotal in the background,
+    /// when configured
+    fn scan_files(
+        enricher: &Enricher,
+        storage: &Arc<dyn Storage + Send + Sync>,
+        artifacts: &CaptureArtifacts,
+    ) {
+        let Some(virustotal) = enricher.virustotal().cloned() else {
+            return;
+        };
+        if artifacts.uploaded_files.is_empty() && artifacts.carved_files.is_empty() {
+            return;
+        }
+        let storage = storage.clone();
+        let session_id = artifacts.session_id;
+        tokio::spawn(async move {
+            if let Err(e) = virustotal.scan_session(storage.as_ref(), session_id).await {
+                warn!(
+                    "Could not save the VirusTotal reports of session {}: {}",
+                    session_id, e
+                );
+            }
+        });
+    }
+
+    /// Applies the tagging rules to a finalized capture, best effort
+    async fn tag_session(
+        tagger: &Tagger,
+        storage: &Arc<dyn Storage + Send + Sync>,
+        active_session: &ActiveSession,
+        recorder: &StreamRecorder,
+        artifacts: &CaptureArtifacts,
+    ) {
+        if tagger.is_empty() {
+            return;
+        }
+        let activity = SessionActivity {
+            service: &active_session.session.service_name,
+            commands: &recorder.commands(),
+            credentials: &recorder.credentials(),
+            artifacts,
+        };
+        if let Err(e) = tagger
+            .apply(storage.as_ref(), active_session.session.id, &activity)
             .await
         {
-            Ok(container_handle) => container_handle,
+            warn!("Could not tag session {}: {}", active_session.session.id, e);
+        }
+    }
+
+    /// Tags a session with the kinds of the exploit attempts found in its HTTP
+    /// requests, e.g. `log4shell`
+    async fn tag_exploits(
+        storage: &Arc<dyn Storage + Send + Sync>,
+        session_id: &Uuid,
+        recorder: &StreamRecorder,
+    ) {
+        let attempts = recorder.exploit_attempts();
+        if attempts.is_empty() {
+            return;
+        }
+        let tagged = async {
+            let mut annotations = storage.get_annotations(*session_id).await?;
+            let mut added = false;
+            for attempt in &attempts {
+                added |= annotations.add_tag(attempt.kind.as_str(), TagSource::Rule);
+            }
+            if added {
+                storage.save_annotations(&annotations).await?;
+            }
+            Ok::<_, StorageError>(())
+        };
+        if let Err(e) = tagged.await {
+            warn!(
+                "Could not tag the exploit attempts of session {}: {}",
+                session_id, e
+            );
+        }
+    }
+
+    /// Tags a session whose proxy was closed past its quota
+    async fn tag_quota_exceeded(storage: &Arc<dyn Storage + Send + Sync>, session_id: &Uuid) {
+        let tagged = async {
+            let mut annotations = storage.get_annotations(*session_id).await?;
+            if annotations.add_tag("quota_exceeded", TagSource::Rule) {
+                storage.save_annotations(&annotations).await?;
+            }
+            Ok::<_, StorageError>(())
+        };
+        if let Err(e) = tagged.await {
+            warn!("Could not tag session {} past its quota: {}", session_id, e);
+        }
+    }
+
+    /// Adds the emails submitted to the session's container to its capture, best effort
+    async fn collect_messages(active_session: &ActiveSession, recorder: &StreamRecorder) {
+        let Some(container_handle) = active_session.container_handle.as_ref() else {
+            return;
+        };
+        match recorder.collect_messages(container_handle.mail_dir()).await {
+            Ok(messages) => {
+                for message in messages {
+                    info!(
+                        "Session {} submitted a message from {:?} to {} ({} bytes)",
+                        active_session.session.id,
+                        message.mail_from,
+                        message.rcpt_to.join(", "),
+                        message.content.len()
+                    );
+                    events::emit(Event::MessageReceived {
+                        session_id: active_session.session.id,
+                        service: active_session.session.service_name.clone(),
+                        helo: message.helo,
+                        mail_from: message.mail_from,
+                        rcpt_to: message.rcpt_to,
+                        size: message.content.len(),
+                        truncated: message.truncated,
+                    });
+                }
+            }
+            Err(e) => warn!(
+                "Could not collect messages of session {}: {}",
+                active_session.session.id, e
+            ),
+        }
+    }
+
+    fn publish_active_sessions(&self) {
+        metrics::global().set_active_sessions(self.active_sessions.len());
+    }
+}
+
+/// What starting a session needs from the [`SessionManager`], see [`SessionManager::starter`]
+pub struct SessionStarter {
+    container_pool: ContainerPool,
+    enricher: Arc<Enricher>,
+    storage: Arc<dyn Storage + Send + Sync>,
+    rules: Arc<RuleSet>,
+    lifecycle: LifecycleSender,
+    ssh_server: Option<Arc<SshServer>>,
+    /// Idle timeout of the TCP connections of the session
+    idle_timeout: Option<Duration>,
+    /// Limits on what the TCP proxy of the session forwards
+    quota: SessionQuota,
+    /// Where the streams of the session are spooled past their threshold
+    spool: SpoolSettings,
+    journal: Arc<SessionJournal>,
+}
+
+impl SessionStarter {
+    /// Starts the container of a new session and records the session, along with the
+    /// address the client connected to when it was redirected to the listener
+    pub async fn create_session(
+        &self,
+        service_name: String,
+        client_addr: std::net::SocketAddr,
+        original_dst: Option<std::net::SocketAddr>,
+        timestamp: chrono::DateTime<Utc>,
+        service_config: &ServiceConfig,
+    ) -> Result<(Session, ContainerHandle), SessionError> {
+        // The lookup runs while the container starts, so that it does not delay the session
+        let (container_handle, enrichment) = tokio::join!(
+            self.container_pool
+                .acquire(service_config)
+                .instrument(info_span!("container_create")),
+            self.enricher.lookup(client_addr.ip())
+        );
+        let container_handle = match container_handle {
+            Ok(container_handle) => {
+                metrics::global().container_created();
+                container_handle
+            }
             Err(e) => {
                 error!("Failed to create container: {:?}", e);
+                metrics::global().container_failed();
+                events::emit(Event::ContainerFailed {
+                    service: service_name,
+                    client_addr,
+                    error: e.to_string(),
+                });
                 return Err(SessionError::CreationFailed);
             }
         };
+        metrics::global().session_started(&service_name);
 
         let new_session = Session {
             id: Uuid::new_v4(),
-            service_name: request.service_name.clone(),
-            client_addr: request.client_addr,
-            start_time: request.timestamp,
+            service_name,
+            client_addr,
+            start_time: timestamp,
             end_time: None,
             container_id: Some(container_handle.id.to_string()),
             bytes_transferred: 0,
             status: SessionStatus::Active,
+            enrichment,
+            original_dst,
         };
 
+        honeytokens::assign(&container_handle.id, new_session.id);
+        events::emit(Event::SessionStarted {
+            session_id: new_session.id,
+            service: new_session.service_name.clone(),
+            client_addr,
+            container_id: new_session.container_id.clone(),
+        });
+        let _ = self.lifecycle.send(SessionLifecycle::SessionCreated {
+            session: new_session.clone(),
+        });
+
+        // Save the new session to the database before creating ActiveSession
+        self.journal
+            .record(JournalEntry::SessionStarted(JournaledSession::of(
+                &new_session,
+            )))
+            .await;
+        if let Err(e) = self.storage.save_session(&new_session).await {
+            error!(
+                "Failed to persist session {} to storage: {}",
+                new_session.id, e
+            );
+            // Continue anyway, session can still proceed
+        } else {
+            debug!("Session {} persisted to storage", new_session.id);
+        }
+
         Ok((new_session, container_handle))
     }
+
+    /// Cleans up the container of a session [created](Self::create_session) but
+    /// never registered, and records the session as failed
+    pub async fn abandon(&self, mut session: Session, container_handle: ContainerHandle) {
+        if let Err(e) = self.container_pool.release(container_handle).await {
+            error!(
+                "Failed to clean up the container of session {}: {}",
+                session.id, e
+            );
+        }
+        session.end_time = Some(Utc::now());
+        session.status = SessionStatus::Error;
+
+        events::emit(Event::SessionEnded {
+            session_id: session.id,
+            service: session.service_name.clone(),
+            client_addr: session.client_addr,
+            status: session.status.clone(),
+            bytes_transferred: 0,
+            duration_secs: (Utc::now() - session.start_time).num_seconds(),
+        });
+        if let Err(e) = self.storage.save_session(&session).await {
+            error!("Failed to persist failed session {}: {}", session.id, e);
+        }
+        self.journal
+            .record(JournalEntry::SessionEnded {
+                session_id: session.id,
+            })
+            .await;
+        let _ = self
+            .lifecycle
+            .send(SessionLifecycle::SessionEnded { session });
+    }
+
+    /// SSH server terminating the connections of the session, if its service has one
+    pub fn ssh_server(&self) -> Option<Arc<SshServer>> {
+        self.ssh_server.clone()
+    }
+
+    /// The registry entry of a started session, recording what crosses its proxy
+    pub fn active_session(
+        &self,
+        session: Session,
+        container_handle: ContainerHandle,
+    ) -> ActiveSession {
+        let stream_recorder = StreamRecorder::new(session.id, self.storage.clone())
+            .with_service(&session.service_name)
+            .with_rules(self.rules.clone())
+            .with_persona(container_handle.persona.clone())
+            .with_idle_timeout(self.idle_timeout)
+            .with_quota(self.quota)
+            .with_spool(self.spool.clone());
+        ActiveSession {
+            session,
+            container_handle: Some(container_handle),
+            last_activity: stream_recorder.last_activity(),
+            termination: stream_recorder.termination(),
+            transferred: stream_recorder.byte_count(),
+            live: stream_recorder.live_feed(),
+            stream_recorder: Arc::new(Mutex::new(stream_recorder)),
+            idle_since: None,
+            idle_timeout: self.idle_timeout,
+            container_restarts: 0,
+        }
+    }
+}
+
+/// What ending a session needs from the [`SessionManager`], see [`SessionManager::take_session`]
+pub struct SessionFinalizer {
+    container_manager: Arc<Mutex<ContainerManager>>,
+    storage: Arc<dyn Storage + Send + Sync>,
+    enricher: Arc<Enricher>,
+    tagger: Arc<Tagger>,
+    lifecycle: LifecycleSender,
+    journal: Arc<SessionJournal>,
+}
+
+impl SessionFinalizer {
+    /// Finalizes the capture of a session taken from the registry, cleans its
+    /// container up and records how the session ended
+    #[tracing::instrument(name = "finalize", skip_all, fields(session_id = %active_session.session.id))]
+    pub async fn finish(&self, mut active_session: ActiveSession) -> Result<(), SessionError> {
+        let id = active_session.session.id;
+        let session_id = &id;
+        debug!("Ending session {}", session_id);
+
+        // Finalize capture
+        active_session.session.end_time = Some(Utc::now());
+
+        // First, save the session with end_time to ensure it exists in the database
+        if let Err(e) = self.storage.save_session(&active_session.session).await {
+            error!(
+                "Failed to persist session {} before finalizing capture: {}",
+                session_id, e
+            );
+            active_session.session.status = SessionStatus::Error;
+        }
+
+        // A connection still proxied holds the recorder until it is closed
+        active_session.termination.terminate();
+        let mut recorder = active_session.stream_recorder.lock().await;
+        recorder.stop_stdio_capture();
+        SessionManager::collect_activity(&active_session, &mut recorder);
+        SessionManager::collect_uploads(&active_session, &recorder).await;
+        SessionManager::collect_messages(&active_session, &recorder).await;
+
+        match recorder.finalize_capture().await {
+            Ok(artifacts) => {
+                debug!(
+                    "Capture finalized for session {}: {} bytes total",
+                    session_id, artifacts.total_bytes
+                );
+                active_session.session.bytes_transferred = artifacts.total_bytes;
+                SessionManager::scan_files(&self.enricher, &self.storage, &artifacts);
+                if recorder.quota_exceeded() {
+                    SessionManager::tag_quota_exceeded(&self.storage, session_id).await;
+                }
+                SessionManager::tag_exploits(&self.storage, session_id, &recorder).await;
+                SessionManager::tag_session(
+                    &self.tagger,
+                    &self.storage,
+                    &active_session,
+                    &recorder,
+                    &artifacts,
+                )
+                .await;
+                self.notify(SessionLifecycle::capture_finalized(session_id, &artifacts));
+            }
+            Err(e) => {
+                error!(
+                    "Failed to finalize capture for session {}: {}",
+                    session_id, e
+                );
+                active_session.session.status = SessionStatus::Error;
+            }
+        }
+
+        active_session.session.status = if active_session.session.status != SessionStatus::Error {
+            SessionStatus::Completed
+        } else {
+            SessionStatus::Error
+        };
+
+        events::emit(Event::SessionEnded {
+            session_id: *session_id,
+            service: active_session.session.service_name.clone(),
+            client_addr: active_session.session.client_addr,
+            status: active_session.session.status.clone(),
+            bytes_transferred: active_session.session.bytes_transferred,
+            duration_secs: (Utc::now() - active_session.session.start_time).num_seconds(),
+        });
+
+        // Clean up container if present
+        if let Some(container_handle) = active_session.container_handle.take() {
+            let mut manager = self.container_manager.lock().await;
+            manager
+                .cleanup_container(container_handle)
+                .await
+                .map_err(SessionError::ContainerError)?;
+        }
+
+        // Update the session in the database with final status and statistics
+        if let Err(e) = self.storage.save_session(&active_session.session).await {
+            error!(
+                "Failed to persist final session {} state: {}",
+                session_id, e
+            );
+        } else {
+            debug!("Session {} final state persisted", session_id);
+        }
+        self.journal
+            .record(JournalEntry::SessionEnded { session_id: id })
+            .await;
+        self.notify(SessionLifecycle::SessionEnded {
+            session: active_session.session,
+        });
+
+        debug!("Session {} ended successfully", session_id);
+        Ok(())
+    }
+
+    // Nobody listening is not an error, the event is simply dropped
+    fn notify(&self, event: SessionLifecycle) {
+        let _ = self.lifecycle.send(event);
+    }
+}
+
+/// How the registry admitted a session request
+pub enum Admission {
+    /// The request joins a running session, connected to its container
+    Join {
+        session_id: Uuid,
+        recorder: Arc<Mutex<StreamRecorder>>,
+        container_stream: TcpStream,
+        ssh_server: Option<Arc<SshServer>>,
+    },
+    /// The request starts a new session, to be registered once started
+    Start(SessionStarter),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_capture::{ByteCount, LastActivity, LiveFeed, Termination};
+    use crate::storage::file_storage::FileStorage;
+
+    fn manager(dir: &tempfile::TempDir) -> SessionManager {
+        let storage: Arc<dyn Storage + Send + Sync> =
+            Arc::new(FileStorage::new(dir.path()).unwrap());
+        SessionManager::new(
+            Arc::new(Mutex::new(ContainerManager::new_mock())),
+            storage,
+            10,
+        )
+    }
+
+    fn add_session(manager: &mut SessionManager, client_addr: &str, idle_minutes: i64) -> Uuid {
+        let id = Uuid::new_v4();
+        let session = Session {
+            id,
+            service_name: "ssh".to_string(),
+            client_addr: client_addr.parse().unwrap(),
+            start_time: Utc::now(),
+            end_time: None,
+            container_id: None,
+            bytes_transferred: 0,
+            status: SessionStatus::Active,
+            enrichment: None,
+            original_dst: None,
+        };
+        let stream_recorder =
+            Arc::new(Mutex::new(StreamRecorder::new(id, manager.storage.clone())));
+        manager.active_sessions.insert(
+            id,
+            ActiveSession {
+                session,
+                container_handle: None,
+                last_activity: LastActivity::new(),
+                termination: Termination::new(),
+                transferred: ByteCount::new(),
+                live: LiveFeed::new(),
+                stream_recorder,
+                idle_since: Some(Utc::now() - TimeDelta::minutes(idle_minutes)),
+                idle_timeout: None,
+                container_restarts: 0,
+            },
+        );
+        id
+    }
+
+    fn request(client_addr: &str, service_name: &str) -> SessionRequest {
+        SessionRequest {
+            stream: None,
+            service_name: service_name.to_string(),
+            client_addr: client_addr.parse().unwrap(),
+            timestamp: Utc::now(),
+            permit: None,
+            greeting: None,
+            original_dst: None,
+            span: tracing::Span::none(),
+        }
+    }
+
+    fn found(manager: &mut SessionManager, request: &SessionRequest) -> Option<Uuid> {
+        manager
+            .find_session(request)
+            .map(|active_s| active_s.session.id)
+    }
+
+    #[test]
+    fn active_sessions_are_listed_with_their_traffic() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = manager(&dir);
+        assert!(manager.list_active_sessions().is_empty());
+        let first = add_session(&mut manager, "203.0.113.7:40000", 2);
+        let second = add_session(&mut manager, "198.51.100.9:40000", 0);
+        manager
+            .active_sessions
+            .get_mut(&first)
+            .unwrap()
+            .session
+            .start_time -= TimeDelta::minutes(5);
+        manager.active_sessions[&second].transferred.add(42);
+
+        let listed = manager.list_active_sessions();
+        assert_eq!(
+            listed.iter().map(|s| s.id).collect::<Vec<_>>(),
+            vec![first, second]
+        );
+        assert!(listed[0].uptime_secs >= 300);
+        assert_eq!(listed[0].bytes_transferred, 0);
+        assert_eq!(listed[1].bytes_transferred, 42);
+        assert_eq!(listed[1].client_addr.to_string(), "198.51.100.9:40000");
+        assert_eq!(listed[1].service_name, "ssh");
+    }
+
+    #[test]
+    fn sessions_are_joined_by_ip_within_the_reuse_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = manager(&dir);
+        let recent = add_session(&mut manager, "203.0.113.7:40000", 2);
+        add_session(&mut manager, "198.51.100.9:40000", 30);
+
+        // Without a window only the exact address matches
+        assert_eq!(
+            found(&mut manager, &request("203.0.113.7:40000", "ssh")),
+            Some(recent)
+        );
+        assert_eq!(
+            found(&mut manager, &request("203.0.113.7:40001", "ssh")),
+            None
+        );
+
+        manager.set_session_reuse(10);
+        assert_eq!(
+            found(&mut manager, &request("203.0.113.7:40001", "ssh")),
+            Some(recent)
+        );
+        assert_eq!(
+            found(&mut manager, &request("203.0.113.7:40001", "http")),
+            None
+        );
+        // Idle for longer than the window
+        assert_eq!(
+            found(&mut manager, &request("198.51.100.9:40001", "ssh")),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn sessions_past_the_reuse_window_are_taken_out_of_the_registry() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = manager(&dir);
+        let recent = add_session(&mut manager, "203.0.113.7:40000", 2);
+        let stale = add_session(&mut manager, "198.51.100.9:40000", 30);
+        assert!(manager.take_idle_sessions().is_empty());
+
+        manager.set_session_reuse(10);
+        let taken = manager.take_idle_sessions();
+        assert_eq!(taken.len(), 1);
+        assert_eq!(taken[0].0.session.id, stale);
+        assert_eq!(
+            manager.active_sessions.keys().copied().collect::<Vec<_>>(),
+            [recent]
+        );
+    }
+
+    #[tokio::test]
+    async fn ending_a_session_is_broadcast() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = manager(&dir);
+        let mut lifecycle = manager.lifecycle().subscribe();
+        let id = add_session(&mut manager, "203.0.113.7:40000", 0);
+
+        manager.end_session(&id).await.unwrap();
+
+        match lifecycle.try_recv().unwrap() {
+            SessionLifecycle::CaptureFinalized { session_id, .. } => assert_eq!(session_id, id),
+            other => panic!("Expected capture_finalized first, got {:?}", other),
+        }
+        match lifecycle.try_recv().unwrap() {
+            SessionLifecycle::SessionEnded { session } => {
+                assert_eq!(session.id, id);
+                assert_eq!(session.status, SessionStatus::Completed);
+            }
+            other => panic!("Expected session_ended, got {:?}", other),
+        }
+        assert!(lifecycle.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn idle_and_timed_out_sessions_are_cleaned_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = manager(&dir);
+        let idle = add_session(&mut manager, "203.0.113.7:40000", 0);
+        let active = add_session(&mut manager, "203.0.113.8:40000", 0);
+        let open = add_session(&mut manager, "203.0.113.9:40000", 0);
+        let old = add_session(&mut manager, "203.0.113.10:40000", 0);
+        for id in [idle, active, open] {
+            manager.active_sessions.get_mut(&id).unwrap().idle_timeout =
+                Some(Duration::from_millis(50));
+        }
+        // A connection is still proxied
+        manager.active_sessions.get_mut(&open).unwrap().idle_since = None;
+        manager
+            .active_sessions
+            .get_mut(&old)
+            .unwrap()
+            .session
+            .start_time = Utc::now() - TimeDelta::hours(2);
+        manager.set_session_timeout(3600);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        manager.active_sessions[&active].last_activity.touch();
+        manager.cleanup_expired_sessions().await;
+
+        let mut remaining: Vec<Uuid> = manager.active_sessions.keys().copied().collect();
+        remaining.sort();
+        let mut expected = vec![active, open];
+        expected.sort();
+        assert_eq!(remaining, expected);
+        let ended = manager.storage.get_session(idle).await.unwrap();
+        assert!(ended.end_time.is_some());
+    }
 }
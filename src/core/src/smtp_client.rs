@@ -0,0 +1,370 @@
+//! Minimal SMTP client mailing the summary reports through a relay.
+//!
+//! Each message opens its own connection. `smtps://` relays are spoken to over
+//! TLS from the start (implicit TLS, usually on port 465) and verified against the
+//! bundled Mozilla root certificates; a login is only sent to them, with
+//! `AUTH PLAIN`. STARTTLS is not supported, `smtp://` being meant for a local relay.
+//! A message is given up once it took longer than the
+//! [timeout](crate::http_client::set_timeout) of outbound requests.
+
+use std::io;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use uuid::Uuid;
+
+use crate::http_client::{bounded, connector};
+
+/// Longest reply line read from the relay
+const MAX_REPLY_LINE: usize = 4096;
+
+/// Base64 characters per line of the encoded bodies
+const BASE64_LINE: usize = 76;
+
+/// Relay parsed from an `smtp://` or `smtps://` url
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmtpEndpoint {
+    tls: bool,
+    host: String,
+    port: u16,
+}
+
+/// A message with a plain text body and, optionally, an HTML alternative
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mail {
+    pub from: String,
+    pub to: Vec<String>,
+    pub subject: String,
+    pub text: String,
+    pub html: Option<String>,
+}
+
+impl SmtpEndpoint {
+    /// Parses `url`, which must use the `smtp` or `smtps` scheme and have no path
+    pub fn parse(url: &str) -> io::Result<Self> {
+        let invalid =
+            |reason: &str| io::Error::new(io::ErrorKind::InvalidInput, reason.to_string());
+
+        let (tls, authority) = if let Some(rest) = url.strip_prefix("smtps://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("smtp://") {
+            (false, rest)
+        } else {
+            return Err(invalid("only smtp:// and smtps:// urls are supported"));
+        };
+        let authority = authority.strip_suffix('/').unwrap_or(authority);
+        if authority.contains('/') {
+            return Err(invalid("smtp urls have no path"));
+        }
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse::<u16>()
+                    .map_err(|_| invalid("invalid port in url"))?,
+            ),
+            None => (authority, if tls { 465 } else { 25 }),
+        };
+        if host.is_empty() {
+            return Err(invalid("missing host in url"));
+        }
+
+        Ok(Self {
+            tls,
+            host: host.to_string(),
+            port,
+        })
+    }
+
+    /// Whether the relay is spoken to over TLS
+    pub fn is_tls(&self) -> bool {
+        self.tls
+    }
+
+    /// Sends `mail`, logging in with `login` first when given.
+    ///
+    /// # Errors
+    /// Fails with [`io::ErrorKind::PermissionDenied`] for a login to an `smtp://`
+    /// relay, with [`io::ErrorKind::TimedOut`] when the relay is too slow, and when
+    /// it rejects a command.
+    pub async fn send(&self, login: Option<(&str, &str)>, mail: &Mail) -> io::Result<()> {
+        if login.is_some() && !self.tls {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the login is only sent over smtps://",
+            ));
+        }
+        bounded(&self.host, async {
+            let stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+            if self.tls {
+                let server_name = ServerName::try_from(self.host.clone())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                let stream = connector().connect(server_name, stream).await?;
+                self.transaction(BufReader::new(stream), login, mail).await
+            } else {
+                self.transaction(BufReader::new(stream), login, mail).await
+            }
+        })
+        .await
+    }
+
+    async fn transaction<S>(
+        &self,
+        mut stream: BufReader<S>,
+        login: Option<(&str, &str)>,
+        mail: &Mail,
+    ) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        expect(&mut stream, "greeting", &[220]).await?;
+        command(&mut stream, "EHLO miel", &[250]).await?;
+        if let Some((username, password)) = login {
+            let credentials = STANDARD.encode(format!("\0{}\0{}", username, password));
+            command(&mut stream, &format!("AUTH PLAIN {}", credentials), &[235]).await?;
+        }
+        command(&mut stream, &format!("MAIL FROM:<{}>", mail.from), &[250]).await?;
+        for to in &mail.to {
+            command(&mut stream, &format!("RCPT TO:<{}>", to), &[250, 251]).await?;
+        }
+        command(&mut stream, "DATA", &[354]).await?;
+        let message = mail.to_message(Utc::now());
+        stream.write_all(message.as_bytes()).await?;
+        command(&mut stream, ".", &[250]).await?;
+        // The message is accepted, the answer to QUIT does not matter
+        let _ = command(&mut stream, "QUIT", &[221]).await;
+        Ok(())
+    }
+}
+
+impl Mail {
+    /// Headers and body of the message as sent after `DATA`, dot-stuffed and
+    /// ending with a line break
+    pub fn to_message(&self, date: DateTime<Utc>) -> String {
+        let mut message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMessage-ID: <{}@miel>\r\nMIME-Version: 1.0\r\n",
+            self.from,
+            self.to.join(", "),
+            encoded_word(&self.subject),
+            date.to_rfc2822(),
+            Uuid::new_v4().simple(),
+        );
+        match &self.html {
+            Some(html) => {
+                let boundary = format!("miel-{}", Uuid::new_v4().simple());
+                message.push_str(&format!(
+                    "Content-Type: multipart/alternative; boundary=\"{}\"\r\n\r\n",
+                    boundary
+                ));
+                for (content_type, body) in [("text/plain", &self.text), ("text/html", html)] {
+                    message.push_str(&format!("--{}\r\n", boundary));
+                    message.push_str(&part(content_type, body));
+                }
+                message.push_str(&format!("--{}--\r\n", boundary));
+            }
+            None => message.push_str(&part("text/plain", &self.text)),
+        }
+        message.replace("\r\n.", "\r\n..")
+    }
+}
+
+/// Headers and base64 body of a UTF-8 text part
+fn part(content_type: &str, body: &str) -> String {
+    let encoded = STANDARD.encode(body);
+    let mut part = format!(
+        "Content-Type: {}; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n",
+        content_type
+    );
+    for line in encoded.as_bytes().chunks(BASE64_LINE) {
+        part.push_str(&String::from_utf8_lossy(line));
+        part.push_str("\r\n");
+    }
+    part
+}
+
+/// `text` as is when ASCII, as an RFC 2047 encoded word otherwise
+fn encoded_word(text: &str) -> String {
+    if text.is_ascii() {
+        text.to_string()
+    } else {
+        format!("=?UTF-8?B?{}?=", STANDARD.encode(text))
+    }
+}
+
+/// Sends `line` and checks the reply code is `expected`
+async fn command<S>(stream: &mut BufReader<S>, line: &str, expected: &[u16]) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(format!("{}\r\n", line).as_bytes()).await?;
+    stream.flush().await?;
+    let verb = line.split_whitespace().next().unwrap_or(line);
+    expect(stream, verb, expected).await
+}
+
+/// Reads a reply, possibly on several lines, and checks its code is `expected`
+async fn expect<S>(stream: &mut BufReader<S>, after: &str, expected: &[u16]) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed SMTP reply");
+    let mut text = String::new();
+    loop {
+        let mut line = String::new();
+        let read = (&mut *stream)
+            .take(MAX_REPLY_LINE as u64)
+            .read_line(&mut line)
+            .await?;
+        if read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "relay closed the connection",
+            ));
+        }
+        let code = line
+            .get(..3)
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(invalid)?;
+        text.push_str(line.get(4..).unwrap_or_default().trim_end());
+        if line.as_bytes().get(3) == Some(&b'-') {
+            text.push(' ');
+            continue;
+        }
+        if expected.contains(&code) {
+            return Ok(());
+        }
+        return Err(io::Error::other(format!(
+            "relay answered {} {} to {}",
+            code, text, after
+        )));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn parses_scheme_and_port() {
+        let relay = SmtpEndpoint::parse("smtps://mail.example.org").unwrap();
+        assert!(relay.is_tls());
+        assert_eq!((relay.host.as_str(), relay.port), ("mail.example.org", 465));
+
+        let relay = SmtpEndpoint::parse("smtp://127.0.0.1:2525").unwrap();
+        assert!(!relay.is_tls());
+        assert_eq!(relay.port, 2525);
+
+        assert!(SmtpEndpoint::parse("http://mail.example.org").is_err());
+        assert!(SmtpEndpoint::parse("smtp://mail.example.org/inbox").is_err());
+        assert!(SmtpEndpoint::parse("smtp://:25").is_err());
+    }
+
+    #[test]
+    fn message_has_both_alternatives() {
+        let mail = Mail {
+            from: "miel@example.org".to_string(),
+            to: vec!["soc@example.org".to_string(), "ir@example.org".to_string()],
+            subject: "Rapport journalier – 2026-10-13".to_string(),
+            text: "# Report\n".to_string(),
+            html: Some("<h1>Report</h1>".to_string()),
+        };
+        let message = mail.to_message(Utc::now());
+        assert!(message.contains("To: soc@example.org, ir@example.org\r\n"));
+        assert!(message.contains("Subject: =?UTF-8?B?"));
+        assert!(message.contains("Content-Type: multipart/alternative"));
+        assert!(message.contains(&STANDARD.encode("# Report\n")));
+        assert!(message.contains(&STANDARD.encode("<h1>Report</h1>")));
+        assert!(message.ends_with("--\r\n"));
+    }
+
+    #[tokio::test]
+    async fn sends_through_the_relay() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("smtp://{}", listener.local_addr().unwrap());
+        let relay = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            stream.write_all(b"220 relay ESMTP\r\n").await.unwrap();
+            let mut received = String::new();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if stream.read_line(&mut line).await.unwrap() == 0 {
+                    break;
+                }
+                received.push_str(&line);
+                let reply: &[u8] = if in_data {
+                    if line != ".\r\n" {
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else if line.starts_with("EHLO") {
+                    b"250-relay\r\n250 8BITMIME\r\n"
+                } else if line.starts_with("DATA") {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                } else if line.starts_with("QUIT") {
+                    stream.write_all(b"221 bye\r\n").await.unwrap();
+                    break;
+                } else {
+                    b"250 ok\r\n"
+                };
+                stream.write_all(reply).await.unwrap();
+            }
+            let mut rest = Vec::new();
+            let _ = stream.read_to_end(&mut rest).await;
+            received
+        });
+
+        let mail = Mail {
+            from: "miel@example.org".to_string(),
+            to: vec!["soc@example.org".to_string()],
+            subject: "Daily report".to_string(),
+            text: "Sessions: 3\n.\n".to_string(),
+            html: None,
+        };
+        let endpoint = SmtpEndpoint::parse(&url).unwrap();
+        endpoint.send(None, &mail).await.unwrap();
+        let received = relay.await.unwrap();
+        assert!(received.starts_with("EHLO miel\r\nMAIL FROM:<miel@example.org>\r\n"));
+        assert!(received.contains("RCPT TO:<soc@example.org>\r\nDATA\r\n"));
+        assert!(received.ends_with("\r\n.\r\nQUIT\r\n"));
+
+        // The login would go in clear
+        assert!(endpoint
+            .send(Some(("miel", "secret")), &mail)
+            .await
+            .is_err_and(|e| e.kind() == io::ErrorKind::PermissionDenied));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn silent_relays_time_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("smtp://{}", listener.local_addr().unwrap());
+        // Accepts, then never greets
+        let relay = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await;
+            drop(stream);
+        });
+
+        let mail = Mail {
+            from: "miel@example.org".to_string(),
+            to: vec!["soc@example.org".to_string()],
+            subject: "Daily report".to_string(),
+            text: "Sessions: 3\n".to_string(),
+            html: None,
+        };
+        let endpoint = SmtpEndpoint::parse(&url).unwrap();
+        assert!(endpoint
+            .send(None, &mail)
+            .await
+            .is_err_and(|e| e.kind() == io::ErrorKind::TimedOut));
+        relay.abort();
+    }
+}
@@ -8,12 +8,47 @@
 //! - `types`: shared data types used by storage backends.
 //! - `database_storage`: ORM-based SQLite implementation using SeaORM.
 //! - `file_storage`: filesystem-backed implementation for simple persistence and inspection.
+//! - `memory_storage`: in-memory implementation for tests and benchmarks.
+//! - `metered_storage`: decorator counting backend errors for the metrics endpoint.
+//! - `buffered_storage`: decorator batching interaction writes, flushed in the background.
+//! - `forwarding_storage`: decorator sending a copy of the writes to a central collector.
+//! - `deduplicating_storage`: decorator storing the identical artifacts once, tagging duplicate sessions.
+//! - `offloading_storage`: decorator moving the large artifacts to an S3-compatible bucket.
+//! - `object_store`: signed client of the S3-compatible buckets.
+//! - `encryption`: AES-GCM encryption at rest of the files of the filesystem backend.
+//! - `compression`: versioned compression of the stored capture artifact payloads.
+//! - `integrity`: checksum manifests of the stored artifacts, optionally HMAC-signed.
+//! - `export`: tar.gz evidence bundles of a session, with a manifest of checksums.
+//! - `cowrie`: events of a session in the JSON format of the Cowrie honeypot.
+//! - `stix`: STIX 2.1 indicators of the sessions, pushed to a TAXII collection.
+//! - `retention`: periodic pruning of old sessions under age, count and disk limits.
+//! - `search`: text of the sessions indexed for full-text search.
 //! - `session_filter`: helpers to build session queries.
 //! - `db_entities`: SeaORM entity models for the database backend.
 
+pub mod buffered_storage;
+pub mod compression;
+pub mod cowrie;
 pub mod database_storage;
 pub mod db_entities;
+pub mod deduplicating_storage;
+pub mod encryption;
+pub mod export;
 pub mod file_storage;
+pub mod forwarding_storage;
+pub mod integrity;
+pub mod memory_storage;
+pub mod metered_storage;
+pub mod object_store;
+pub mod offloading_storage;
+pub mod retention;
+pub mod search;
 pub mod session_filter;
+pub mod stix;
 pub mod storage_trait;
 pub mod types;
+
+/// Lowercase hexadecimal form of `bytes`, as digests and signatures are stored
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,384 @@
+//! Storage decorator batching interaction writes.
+//!
+//! [`BufferedStorage`] queues the chunks passed to `save_interaction` per session
+//! and writes a queue in a single call once it holds [`DEFAULT_FLUSH_BYTES`], when
+//! the background flusher ticks, or before the session data is read back. While
+//! more than [`DEFAULT_MAX_PENDING_BYTES`] are queued across sessions, writers
+//! flush the queues themselves before queueing more, which bounds the memory held
+//! when the backend is slow.
+//!
+//! Every other call is forwarded to the wrapped backend.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::{debug, error};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::data_capture::CaptureArtifacts;
+use crate::error_handling::types::StorageError;
+use crate::honeytokens::Honeytoken;
+use crate::session::Session;
+use crate::storage::storage_trait::Storage;
+use crate::storage::types::{
+    Credential, CredentialFilter, ExecutedCommand, SearchHit, SessionAnnotations, SessionFilter,
+};
+
+/// Bytes queued for a session before they are written
+pub const DEFAULT_FLUSH_BYTES: usize = 64 * 1024;
+
+/// Bytes queued across sessions before writers have to flush
+pub const DEFAULT_MAX_PENDING_BYTES: usize = 8 * 1024 * 1024;
+
+/// Period of the background flush started by [`BufferedStorage::spawn_flusher`]
+pub const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Interaction chunks of a session not written yet
+#[derive(Default)]
+struct SessionQueue {
+    buffer: Mutex<Vec<u8>>,
+    /// Held while the buffer is written, so that chunks reach the backend in order
+    writing: tokio::sync::Mutex<()>,
+}
+
+/// Wraps a [`Storage`] backend to batch its interaction writes
+pub struct BufferedStorage {
+    inner: Arc<dyn Storage + Send + Sync>,
+    queues: Mutex<HashMap<Uuid, Arc<SessionQueue>>>,
+    /// Bytes queued and not written yet, across sessions
+    pending: AtomicUsize,
+    flush_bytes: usize,
+    max_pending_bytes: usize,
+}
+
+impl BufferedStorage {
+    pub fn new(inner: Arc<dyn Storage + Send + Sync>) -> Self {
+        Self {
+            inner,
+            queues: Mutex::new(HashMap::new()),
+            pending: AtomicUsize::new(0),
+            flush_bytes: DEFAULT_FLUSH_BYTES,
+            max_pending_bytes: DEFAULT_MAX_PENDING_BYTES,
+        }
+    }
+
+    /// Sets the bytes queued for a session before they are written
+    pub fn with_flush_bytes(mut self, flush_bytes: usize) -> Self {
+        self.flush_bytes = flush_bytes;
+        self
+    }
+
+    /// Sets the bytes queued across sessions before writers have to flush
+    pub fn with_max_pending_bytes(mut self, max_pending_bytes: usize) -> Self {
+        self.max_pending_bytes = max_pending_bytes;
+        self
+    }
+
+    /// Bytes queued and not written yet
+    pub fn pending_bytes(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    /// Spawns the task flushing the queues every `period`.
+    ///
+    /// The task holds a weak reference, and stops once the storage is dropped.
+    pub fn spawn_flusher(self: &Arc<Self>, period: Duration) -> JoinHandle<()> {
+        let storage = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut timer = tokio::time::interval(period);
+            timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                timer.tick().await;
+                let Some(storage) = storage.upgrade() else {
+                    break;
+                };
+                // Failed writes stay queued for the next tick
+                let _ = storage.flush_queues().await;
+                storage.prune_idle_queues();
+            }
+            debug!("Storage flusher stopped");
+        })
+    }
+
+    fn queue(&self, session_id: Uuid) -> Option<Arc<SessionQueue>> {
+        self.queues.lock().unwrap().get(&session_id).cloned()
+    }
+
+    /// Writes the chunks queued for a session
+    async fn flush_session(&self, session_id: Uuid) -> Result<(), StorageError> {
+        let Some(queue) = self.queue(session_id) else {
+            return Ok(());
+        };
+        let _writing = queue.writing.lock().await;
+        let data = std::mem::take(&mut *queue.buffer.lock().unwrap());
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let len = data.len();
+        if let Err(e) = self.inner.save_interaction(session_id, &data).await {
+            error!(
+                "Failed to flush {} bytes of interactions for session {}: {}",
+                len, session_id, e
+            );
+            // Back in front of the chunks queued meanwhile, for the next flush
+            queue.buffer.lock().unwrap().splice(0..0, data);
+            return Err(e);
+        }
+        self.pending.fetch_sub(len, Ordering::SeqCst);
+        debug!(
+            "Flushed {} bytes of interactions for session {}",
+            len, session_id
+        );
+        Ok(())
+    }
+
+    /// Writes the chunks queued for every session, returning the last error
+    async fn flush_queues(&self) -> Result<(), StorageError> {
+        let session_ids: Vec<Uuid> = self.queues.lock().unwrap().keys().copied().collect();
+        let mut result = Ok(());
+        for session_id in session_ids {
+            if let Err(e) = self.flush_session(session_id).await {
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    /// Forgets the queues of sessions with nothing left to write
+    fn prune_idle_queues(&self) {
+        self.queues.lock().unwrap().retain(|_, queue| {
+            !queue.buffer.lock().unwrap().is_empty() || queue.writing.try_lock().is_err()
+        });
+    }
+
+    /// Drops the chunks queued for deleted sessions, once their writes in flight ended
+    async fn discard(&self, session_ids: &[Uuid]) {
+        let removed: Vec<Arc<SessionQueue>> = {
+            let mut queues = self.queues.lock().unwrap();
+            session_ids
+                .iter()
+                .filter_map(|session_id| queues.remove(session_id))
+                .collect()
+        };
+        for queue in removed {
+            let _writing = queue.writing.lock().await;
+            let dropped = std::mem::take(&mut *queue.buffer.lock().unwrap()).len();
+            self.pending.fetch_sub(dropped, Ordering::SeqCst);
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for BufferedStorage {
+    async fn save_session(&self, session: &Session) -> Result<(), StorageError> {
+        self.inner.save_session(session).await
+    }
+
+    async fn get_sessions(
+        &self,
+        filter: Option<SessionFilter>,
+    ) -> Result<Vec<Session>, StorageError> {
+        self.inner.get_sessions(filter).await
+    }
+
+    async fn get_session(&self, session_id: Uuid) -> Result<Session, StorageError> {
+        self.inner.get_session(session_id).await
+    }
+
+    async fn save_interaction(&self, session_id: Uuid, data: &[u8]) -> Result<(), StorageError> {
+        if self.pending_bytes() + data.len() > self.max_pending_bytes {
+            debug!("Interaction queues are full, flushing before queueing more");
+            self.flush_queues().await?;
+        }
+
+        let queued = {
+            let mut queues = self.queues.lock().unwrap();
+            let queue = queues.entry(session_id).or_default();
+            let mut buffer = queue.buffer.lock().unwrap();
+            buffer.extend_from_slice(data);
+            // Counted under the buffer lock, so that a flush never subtracts it first
+            self.pending.fetch_add(data.len(), Ordering::SeqCst);
+            buffer.len()
+        };
+        if queued >= self.flush_bytes {
+            self.flush_session(session_id).await?;
+        }
+        Ok(())
+    }
+
+    async fn get_session_data(&self, session_id: Uuid) -> Result<Vec<u8>, StorageError> {
+        self.flush_session(session_id).await?;
+        self.inner.get_session_data(session_id).await
+    }
+
+    async fn cleanup_old_sessions(&self, older_than: DateTime<Utc>) -> Result<usize, StorageError> {
+        // Written first, so that no chunk of a removed session lands afterwards
+        self.flush_queues().await?;
+        self.inner.cleanup_old_sessions(older_than).await
+    }
+
+    async fn delete_sessions(&self, session_ids: &[Uuid]) -> Result<usize, StorageError> {
+        self.discard(session_ids).await;
+        self.inner.delete_sessions(session_ids).await
+    }
+
+    async fn storage_size(&self) -> Result<u64, StorageError> {
+        // Queued chunks are on their way to the disk
+        Ok(self.inner.storage_size().await? + self.pending_bytes() as u64)
+    }
+
+    async fn save_capture_artifacts(
+        &self,
+        artifacts: &CaptureArtifacts,
+    ) -> Result<(), StorageError> {
+        self.inner.save_capture_artifacts(artifacts).await
+    }
+
+    async fn get_capture_artifacts(
+        &self,
+        session_id: Uuid,
+    ) -> Result<CaptureArtifacts, StorageError> {
+        self.inner.get_capture_artifacts(session_id).await
+    }
+
+    async fn save_credentials(&self, credentials: &[Credential]) -> Result<(), StorageError> {
+        self.inner.save_credentials(credentials).await
+    }
+
+    async fn get_credentials(
+        &self,
+        filter: Option<CredentialFilter>,
+    ) -> Result<Vec<Credential>, StorageError> {
+        self.inner.get_credentials(filter).await
+    }
+
+    async fn save_commands(
+        &self,
+        session_id: Uuid,
+        commands: &[ExecutedCommand],
+    ) -> Result<(), StorageError> {
+        self.inner.save_commands(session_id, commands).await
+    }
+
+    async fn get_commands(&self, session_id: Uuid) -> Result<Vec<ExecutedCommand>, StorageError> {
+        self.inner.get_commands(session_id).await
+    }
+
+    async fn save_annotations(&self, annotations: &SessionAnnotations) -> Result<(), StorageError> {
+        self.inner.save_annotations(annotations).await
+    }
+
+    async fn get_annotations(&self, session_id: Uuid) -> Result<SessionAnnotations, StorageError> {
+        self.inner.get_annotations(session_id).await
+    }
+
+    async fn save_honeytokens(&self, tokens: &[Honeytoken]) -> Result<(), StorageError> {
+        self.inner.save_honeytokens(tokens).await
+    }
+
+    async fn get_honeytokens(&self, limit: usize) -> Result<Vec<Honeytoken>, StorageError> {
+        self.inner.get_honeytokens(limit).await
+    }
+
+    async fn delete_honeytokens(&self, token_ids: &[Uuid]) -> Result<(), StorageError> {
+        self.inner.delete_honeytokens(token_ids).await
+    }
+
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, StorageError> {
+        self.inner.search(query, limit).await
+    }
+
+    async fn flush(&self) -> Result<(), StorageError> {
+        self.flush_queues().await?;
+        self.inner.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::file_storage::FileStorage;
+    use tempfile::TempDir;
+
+    fn buffered(dir: &TempDir) -> (Arc<FileStorage>, BufferedStorage) {
+        let inner = Arc::new(FileStorage::new(dir.path()).unwrap());
+        let buffered = BufferedStorage::new(inner.clone());
+        (inner, buffered)
+    }
+
+    #[tokio::test]
+    async fn chunks_are_written_in_batches() {
+        let dir = TempDir::new().unwrap();
+        let (inner, storage) = buffered(&dir);
+        let storage = storage.with_flush_bytes(8);
+        let id = Uuid::new_v4();
+
+        storage.save_interaction(id, b"ls ").await.unwrap();
+        storage.save_interaction(id, b"-la").await.unwrap();
+        assert!(inner.get_session_data(id).await.is_err());
+        assert_eq!(storage.pending_bytes(), 6);
+
+        storage.save_interaction(id, b"\nid\n").await.unwrap();
+        assert_eq!(inner.get_session_data(id).await.unwrap(), b"ls -la\nid\n");
+        assert_eq!(storage.pending_bytes(), 0);
+
+        // Reads see the chunks still queued
+        storage.save_interaction(id, b"w").await.unwrap();
+        assert_eq!(
+            storage.get_session_data(id).await.unwrap(),
+            b"ls -la\nid\nw"
+        );
+    }
+
+    #[tokio::test]
+    async fn writers_flush_over_the_pending_limit() {
+        let dir = TempDir::new().unwrap();
+        let (inner, storage) = buffered(&dir);
+        let storage = storage.with_max_pending_bytes(16);
+        let (first, second, third) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+        storage.save_interaction(first, &[1; 8]).await.unwrap();
+        storage.save_interaction(second, &[2; 8]).await.unwrap();
+        assert_eq!(storage.pending_bytes(), 16);
+        storage.save_interaction(third, &[3; 8]).await.unwrap();
+
+        assert_eq!(storage.pending_bytes(), 8);
+        assert_eq!(inner.get_session_data(first).await.unwrap(), [1; 8]);
+        assert_eq!(inner.get_session_data(second).await.unwrap(), [2; 8]);
+        assert!(inner.get_session_data(third).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn flusher_writes_queues_and_deletions_drop_them() {
+        let dir = TempDir::new().unwrap();
+        let (inner, storage) = buffered(&dir);
+        let storage = Arc::new(storage);
+        let (kept, deleted) = (Uuid::new_v4(), Uuid::new_v4());
+
+        storage
+            .save_interaction(deleted, b"rm -rf /")
+            .await
+            .unwrap();
+        storage.delete_sessions(&[deleted]).await.unwrap();
+        assert_eq!(storage.pending_bytes(), 0);
+
+        storage.save_interaction(kept, b"uname -a").await.unwrap();
+        let flusher = storage.spawn_flusher(Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(inner.get_session_data(kept).await.unwrap(), b"uname -a");
+        assert!(inner.get_session_data(deleted).await.is_err());
+        assert!(storage.queues.lock().unwrap().is_empty());
+
+        drop(storage);
+        tokio::time::timeout(Duration::from_secs(1), flusher)
+            .await
+            .expect("flusher stops with the storage")
+            .unwrap();
+    }
+}
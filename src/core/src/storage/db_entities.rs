@@ -4,6 +4,10 @@
 //! - `sessions` — top-level session metadata
 //! - `interactions` — ordered chunks of raw interaction bytes per session
 //! - `artifacts` — JSON-serialized `CaptureArtifacts` per session
+//! - `credentials` — login attempts captured per session
+//! - `commands` — command timeline per session
+//! - `tags` and `notes` — analyst and rule annotations per session
+//! - `honeytokens` — JSON-serialized honeytokens planted in containers
 
 use sea_orm::entity::prelude::*;
 
@@ -30,6 +34,16 @@ pub struct Model {
     pub bytes_transferred: i64,
     /// Session status as string enum
     pub status: String,
+    /// Country code of the client IP, if known
+    pub country_code: Option<String>,
+    /// Autonomous system number of the client IP, if known
+    pub asn: Option<i64>,
+    /// Autonomous system organization of the client IP, if known
+    pub as_org: Option<String>,
+    /// Abuse confidence score of the client IP, if known
+    pub abuse_score: Option<i32>,
+    /// Socket address the client connected to before being redirected, if it was
+    pub original_dst: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -47,6 +61,30 @@ impl Related<self::artifacts::Entity> for Entity {
     }
 }
 
+impl Related<self::credentials::Entity> for Entity {
+    fn to() -> RelationDef {
+        self::credentials::Relation::Session.def()
+    }
+}
+
+impl Related<self::commands::Entity> for Entity {
+    fn to() -> RelationDef {
+        self::commands::Relation::Session.def()
+    }
+}
+
+impl Related<self::tags::Entity> for Entity {
+    fn to() -> RelationDef {
+        self::tags::Relation::Session.def()
+    }
+}
+
+impl Related<self::notes::Entity> for Entity {
+    fn to() -> RelationDef {
+        self::notes::Relation::Session.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
 
 /// Interactions table entity models.
@@ -91,8 +129,88 @@ pub mod artifacts {
         /// Primary key and FK to `sessions.id`
         #[sea_orm(primary_key)]
         pub session_id: String,
-        /// Pretty/compact JSON payload
+        /// Compact JSON payload, empty when it is compressed in `payload`
         pub json: String,
+        /// JSON payload encoded by [`crate::storage::compression`], if compressed
+        pub payload: Option<Vec<u8>>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {
+        /// Belongs to a session
+        #[sea_orm(
+            belongs_to = "super::Entity",
+            from = "Column::SessionId",
+            to = "super::Column::Id"
+        )]
+        Session,
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+/// Credentials table entity models.
+pub mod credentials {
+    use sea_orm::entity::prelude::*;
+
+    /// A login attempt associated to a session.
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "credentials")]
+    pub struct Model {
+        /// Auto-increment row id
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        /// Foreign key to `sessions.id`
+        pub session_id: String,
+        /// RFC3339 timestamp of the attempt
+        pub timestamp: String,
+        /// Name of the targeted service
+        pub service: String,
+        /// IP address the attempt came from
+        pub client_ip: Option<String>,
+        pub username: String,
+        /// Unknown for some protocols, e.g. SSH
+        pub password: Option<String>,
+        /// Whether the attempt was accepted, if known
+        pub accepted: Option<bool>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {
+        /// Belongs to a session
+        #[sea_orm(
+            belongs_to = "super::Entity",
+            from = "Column::SessionId",
+            to = "super::Column::Id"
+        )]
+        Session,
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+/// Commands table entity models.
+pub mod commands {
+    use sea_orm::entity::prelude::*;
+
+    /// A command entered during a session.
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "commands")]
+    pub struct Model {
+        /// Auto-increment row id
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        /// Foreign key to `sessions.id`
+        pub session_id: String,
+        /// RFC3339 timestamp of the command
+        pub timestamp: String,
+        /// Service tag the command was logged with
+        pub source: String,
+        pub command: String,
+        pub stdout_lines: i64,
+        pub stderr_lines: i64,
+        /// Exit hint as snake_case string
+        pub exit_hint: Option<String>,
     }
 
     #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -108,3 +226,129 @@ pub mod artifacts {
 
     impl ActiveModelBehavior for ActiveModel {}
 }
+
+/// Extracted files table entity models.
+pub mod files {
+    use sea_orm::entity::prelude::*;
+
+    /// A file uploaded or carved during a session, indexed by hash while its
+    /// content stays in the session's artifacts.
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "files")]
+    pub struct Model {
+        /// Auto-increment row id
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        /// Foreign key to `sessions.id`
+        pub session_id: String,
+        pub name: String,
+        /// `upload` for files stored by the service, else the carving source
+        pub source: String,
+        /// Hex encoded SHA-256 of the content
+        pub sha256: String,
+        /// Hex encoded MD5 of the content, `None` for files saved before it was recorded
+        pub md5: Option<String>,
+        /// Hex encoded SHA-1 of the content, same as `md5`
+        pub sha1: Option<String>,
+        /// Stored size in bytes
+        pub size: i64,
+        pub truncated: bool,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {
+        /// Belongs to a session
+        #[sea_orm(
+            belongs_to = "super::Entity",
+            from = "Column::SessionId",
+            to = "super::Column::Id"
+        )]
+        Session,
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+/// Session tags table entity models.
+pub mod tags {
+    use sea_orm::entity::prelude::*;
+
+    /// A tag attached to a session.
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "tags")]
+    pub struct Model {
+        /// Auto-increment row id, keeping the tags in the order they were added
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        /// Foreign key to `sessions.id`
+        pub session_id: String,
+        pub tag: String,
+        /// Tag source as snake_case string
+        pub source: String,
+        /// RFC3339 timestamp of the tagging
+        pub added_at: String,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {
+        /// Belongs to a session
+        #[sea_orm(
+            belongs_to = "super::Entity",
+            from = "Column::SessionId",
+            to = "super::Column::Id"
+        )]
+        Session,
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+/// Session notes table entity models.
+pub mod notes {
+    use sea_orm::entity::prelude::*;
+
+    /// The notes an analyst wrote about a session.
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "notes")]
+    pub struct Model {
+        /// Primary key and FK to `sessions.id`
+        #[sea_orm(primary_key)]
+        pub session_id: String,
+        pub notes: String,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {
+        /// Belongs to a session
+        #[sea_orm(
+            belongs_to = "super::Entity",
+            from = "Column::SessionId",
+            to = "super::Column::Id"
+        )]
+        Session,
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub mod honeytokens {
+    use sea_orm::entity::prelude::*;
+
+    /// A honeytoken planted in a container, kept across restarts.
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "honeytokens")]
+    pub struct Model {
+        /// Token UUID as string primary key
+        #[sea_orm(primary_key)]
+        pub id: String,
+        /// RFC3339 timestamp the token was generated at
+        pub created_at: String,
+        /// JSON-serialized `Honeytoken`
+        pub json: String,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
@@ -0,0 +1,713 @@
+//! Deduplication of the identical capture artifacts.
+//!
+//! Mass scanners send the same payloads thousands of times. [`DeduplicatingStorage`]
+//! wraps the storage backend when `[dedup]` is enabled: when the artifacts of a
+//! session are saved, the TCP streams and the contents of the uploaded files, carved
+//! files and messages of at least `min_size_bytes` are written once to
+//! `<dir>/blobs/<sha256>`, and the sessions referencing each blob are counted in
+//! `<dir>/refs/<sha256>`. The backend stores the artifacts with these fields emptied,
+//! the [`DedupManifest`] of the session in `<dir>/sessions/<session id>.json` listing
+//! the blobs they went to.
+//!
+//! A session whose client sent the same bytes as an earlier session of the same
+//! service duplicates this canonical session: it is tagged [`DUPLICATE_TAG`] and its
+//! manifest names the canonical session, as served on `GET /api/sessions/:id/dedup`.
+//!
+//! Reading the artifacts back restores the fields from the blobs. Deleting sessions,
+//! directly or through retention, releases their blobs, a blob being removed with
+//! its last reference; the next session sending the payload of a deleted canonical
+//! session becomes the canonical one. Interactions are appended chunk by chunk as
+//! the sessions go and are not shared.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::configuration::types::DedupConfig;
+use crate::data_capture::CaptureArtifacts;
+use crate::error_handling::types::StorageError;
+use crate::honeytokens::Honeytoken;
+use crate::session::Session;
+use crate::storage::encryption::{self, EncryptionKey};
+use crate::storage::export::sha256_hex;
+use crate::storage::offloading_storage::{field_mut, offloadable_fields};
+use crate::storage::storage_trait::Storage;
+use crate::storage::types::{
+    Credential, CredentialFilter, ExecutedCommand, SearchHit, SessionAnnotations, SessionFilter,
+    TagSource,
+};
+
+/// Tag of the sessions duplicating a canonical session
+pub const DUPLICATE_TAG: &str = "duplicate";
+
+/// Content of an artifact field, kept in a shared blob
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SharedBlob {
+    /// Field of the artifacts, see [`OffloadedObject::field`](crate::data_capture::OffloadedObject::field)
+    pub field: String,
+    pub sha256: String,
+    pub size: u64,
+}
+
+/// Blobs of a session and the session it duplicates
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DedupManifest {
+    pub session_id: Uuid,
+    pub blobs: Vec<SharedBlob>,
+    /// Fingerprint of the service and client payload, `None` when the client sent nothing
+    pub payload: Option<String>,
+    /// Earliest stored session whose client sent the same payload
+    pub duplicate_of: Option<Uuid>,
+}
+
+/// Where the blobs, their reference counts and the manifests are kept
+pub struct DedupStore {
+    dir: PathBuf,
+    min_size: usize,
+    encryption: Option<EncryptionKey>,
+    /// Reference counts are read, updated and written back under this lock
+    lock: Mutex<()>,
+}
+
+impl DedupStore {
+    pub fn new(dir: PathBuf, min_size: usize) -> Self {
+        Self {
+            dir,
+            min_size: min_size.max(1),
+            encryption: None,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// The store of `config`, in the `dedup` directory of `storage_path` unless it
+    /// sets its own. `None` when deduplication is disabled
+    pub fn from_config(config: &DedupConfig, storage_path: &Path) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        let dir = config
+            .dir
+            .clone()
+            .unwrap_or_else(|| storage_path.join("dedup"));
+        Some(Self::new(dir, config.min_size_bytes))
+    }
+
+    /// Encrypts the blobs with `key`, see [`encryption`]
+    pub fn with_encryption(mut self, key: EncryptionKey) -> Self {
+        self.encryption = Some(key);
+        self
+    }
+
+    fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join("blobs").join(sha256)
+    }
+
+    fn refs_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join("refs").join(sha256)
+    }
+
+    fn manifest_path(&self, session_id: Uuid) -> PathBuf {
+        self.dir
+            .join("sessions")
+            .join(format!("{}.json", session_id))
+    }
+
+    fn payload_path(&self, payload: &str) -> PathBuf {
+        self.dir.join("payloads").join(payload)
+    }
+
+    /// The manifest of `session_id`, `None` when its artifacts were saved without
+    /// deduplication
+    pub fn manifest(&self, session_id: Uuid) -> Result<Option<DedupManifest>, StorageError> {
+        let json = match fs::read(self.manifest_path(session_id)) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                error!("Failed to read dedup manifest of {}: {}", session_id, e);
+                return Err(StorageError::ReadFailed);
+            }
+        };
+        serde_json::from_slice(&json).map(Some).map_err(|e| {
+            error!("Invalid dedup manifest of {}: {}", session_id, e);
+            StorageError::ReadFailed
+        })
+    }
+
+    /// Copy of `artifacts` with the large contents moved to blobs, and the manifest
+    /// written for them. `service` is the service of the session, duplicates are
+    /// only looked for when it is known
+    fn share(
+        &self,
+        artifacts: &CaptureArtifacts,
+        service: Option<&str>,
+    ) -> Result<(CaptureArtifacts, DedupManifest), StorageError> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let session_id = artifacts.session_id;
+        let previous = self.manifest(session_id)?;
+
+        let mut stored = artifacts.clone();
+        let mut blobs = Vec::new();
+        for field in offloadable_fields(&stored) {
+            let Some(content) = field_mut(&mut stored, &field) else {
+                continue;
+            };
+            if content.len() < self.min_size {
+                continue;
+            }
+            let sha256 = sha256_hex(content);
+            self.write_blob(&sha256, content)?;
+            self.add_ref(&sha256)?;
+            let content = std::mem::take(content);
+            blobs.push(SharedBlob {
+                field,
+                sha256,
+                size: content.len() as u64,
+            });
+        }
+
+        let payload = service.and_then(|service| payload_fingerprint(artifacts, service));
+        let duplicate_of = match (&payload, &previous) {
+            (Some(payload), Some(previous)) if previous.payload.as_ref() == Some(payload) => {
+                previous.duplicate_of
+            }
+            (Some(payload), _) => self.canonical(payload, session_id)?,
+            (None, _) => None,
+        };
+
+        // Released once the new references are counted, so that blobs shared with
+        // the previous save are kept
+        if let Some(previous) = &previous {
+            self.release_manifest(previous, payload.as_deref());
+        }
+
+        let manifest = DedupManifest {
+            session_id,
+            blobs,
+            payload,
+            duplicate_of,
+        };
+        let json = serde_json::to_vec_pretty(&manifest).map_err(|e| {
+            error!("Failed to serialize dedup manifest: {}", e);
+            StorageError::WriteFailed
+        })?;
+        write_atomically(&self.manifest_path(session_id), &json).map_err(|e| {
+            error!("Failed to write dedup manifest of {}: {}", session_id, e);
+            StorageError::WriteFailed
+        })?;
+        Ok((stored, manifest))
+    }
+
+    /// Canonical session of `payload`, `None` when `session_id` becomes it
+    fn canonical(&self, payload: &str, session_id: Uuid) -> Result<Option<Uuid>, StorageError> {
+        let path = self.payload_path(payload);
+        match fs::read_to_string(&path) {
+            Ok(id) => match Uuid::parse_str(id.trim()) {
+                Ok(canonical) if canonical != session_id => return Ok(Some(canonical)),
+                Ok(_) => return Ok(None),
+                Err(_) => warn!("Replacing the malformed canonical session of {}", payload),
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                error!("Failed to read the canonical session of {}: {}", payload, e);
+                return Err(StorageError::ReadFailed);
+            }
+        }
+        write_atomically(&path, session_id.to_string().as_bytes()).map_err(|e| {
+            error!(
+                "Failed to record the canonical session of {}: {}",
+                payload, e
+            );
+            StorageError::WriteFailed
+        })?;
+        Ok(None)
+    }
+
+    /// Fetches the shared contents of `artifacts` back into their fields
+    fn restore(&self, artifacts: &mut CaptureArtifacts) -> Result<(), StorageError> {
+        let Some(manifest) = self.manifest(artifacts.session_id)? else {
+            return Ok(());
+        };
+        for blob in &manifest.blobs {
+            let content = self.read_blob(&blob.sha256).map_err(|e| {
+                error!("Cannot read shared blob {}: {}", blob.sha256, e);
+                StorageError::ReadFailed
+            })?;
+            if content.len() as u64 != blob.size || sha256_hex(&content) != blob.sha256 {
+                error!("Shared blob {} does not match its checksum", blob.sha256);
+                return Err(StorageError::ReadFailed);
+            }
+            match field_mut(artifacts, &blob.field) {
+                Some(field) => *field = content,
+                None => warn!("Shared blob {} has no field {}", blob.sha256, blob.field),
+            }
+        }
+        Ok(())
+    }
+
+    /// Releases the blobs and manifests of `session_ids`, best effort
+    fn release(&self, session_ids: &[Uuid]) {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        for &session_id in session_ids {
+            let manifest = match self.manifest(session_id) {
+                Ok(Some(manifest)) => manifest,
+                Ok(None) | Err(_) => continue,
+            };
+            self.release_manifest(&manifest, None);
+            if let Err(e) = fs::remove_file(self.manifest_path(session_id)) {
+                warn!("Failed to remove dedup manifest of {}: {}", session_id, e);
+            }
+        }
+    }
+
+    /// Drops the references of `manifest` and its canonical payload, unless the
+    /// session still sends `kept_payload`
+    fn release_manifest(&self, manifest: &DedupManifest, kept_payload: Option<&str>) {
+        for blob in &manifest.blobs {
+            if let Err(e) = self.drop_ref(&blob.sha256) {
+                warn!("Failed to release shared blob {}: {}", blob.sha256, e);
+            }
+        }
+        let Some(payload) = manifest.payload.as_deref() else {
+            return;
+        };
+        if kept_payload == Some(payload) {
+            return;
+        }
+        let path = self.payload_path(payload);
+        let canonical = fs::read_to_string(&path).ok();
+        if canonical.is_some_and(|id| id.trim() == manifest.session_id.to_string()) {
+            if let Err(e) = fs::remove_file(&path) {
+                warn!(
+                    "Failed to remove the canonical session of {}: {}",
+                    payload, e
+                );
+            }
+        }
+    }
+
+    fn write_blob(&self, sha256: &str, content: &[u8]) -> Result<(), StorageError> {
+        let path = self.blob_path(sha256);
+        if path.exists() {
+            return Ok(());
+        }
+        let data = match &self.encryption {
+            Some(key) => key.seal(&format!("blobs/{}", sha256), content)?,
+            None => content.to_vec(),
+        };
+        write_atomically(&path, &data).map_err(|e| {
+            error!("Failed to write shared blob {}: {}", sha256, e);
+            StorageError::WriteFailed
+        })?;
+        debug!("Stored shared blob {} of {} bytes", sha256, content.len());
+        Ok(())
+    }
+
+    fn read_blob(&self, sha256: &str) -> io::Result<Vec<u8>> {
+        let payload = fs::read(self.blob_path(sha256))?;
+        match &self.encryption {
+            Some(key) => key
+                .open(&format!("blobs/{}", sha256), &payload)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+            None if encryption::is_encrypted(&payload) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "blob is encrypted and no key is configured",
+            )),
+            None => Ok(payload),
+        }
+    }
+
+    fn refs(&self, sha256: &str) -> io::Result<u64> {
+        match fs::read_to_string(self.refs_path(sha256)) {
+            Ok(count) => count
+                .trim()
+                .parse()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "malformed count")),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn add_ref(&self, sha256: &str) -> Result<(), StorageError> {
+        self.refs(sha256)
+            .and_then(|count| {
+                write_atomically(&self.refs_path(sha256), (count + 1).to_string().as_bytes())
+            })
+            .map_err(|e| {
+                error!("Failed to count a reference to blob {}: {}", sha256, e);
+                StorageError::WriteFailed
+            })
+    }
+
+    /// Removes the blob along with its last reference
+    fn drop_ref(&self, sha256: &str) -> io::Result<()> {
+        match self.refs(sha256)? {
+            0 | 1 => {
+                debug!("Removing shared blob {}, no longer referenced", sha256);
+                remove_if_exists(&self.blob_path(sha256))?;
+                remove_if_exists(&self.refs_path(sha256))
+            }
+            count => write_atomically(&self.refs_path(sha256), (count - 1).to_string().as_bytes()),
+        }
+    }
+
+    /// Bytes taken by the blobs
+    fn size(&self) -> u64 {
+        fs::read_dir(self.dir.join("blobs"))
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .filter_map(|entry| entry.metadata().ok())
+                    .map(|metadata| metadata.len())
+                    .sum()
+            })
+            .unwrap_or(0)
+    }
+}
+
+/// Fingerprint of what the client of the session sent, `None` when it sent nothing.
+///
+/// A stream moved to an object store by an outer decorator is taken by its checksum.
+pub(crate) fn payload_fingerprint(artifacts: &CaptureArtifacts, service: &str) -> Option<String> {
+    let offloaded = artifacts
+        .offloaded
+        .iter()
+        .find(|object| object.field == "tcp_client_to_container");
+    let client = match offloaded {
+        Some(object) if artifacts.tcp_client_to_container.is_empty() => object.sha256.clone(),
+        _ if artifacts.tcp_client_to_container.is_empty() && artifacts.stdio_stdin.is_empty() => {
+            return None
+        }
+        _ => sha256_hex(&artifacts.tcp_client_to_container),
+    };
+    let stdin = sha256_hex(artifacts.stdio_stdin.as_bytes());
+    Some(sha256_hex(
+        format!("{}\n{}\n{}", service, client, stdin).as_bytes(),
+    ))
+}
+
+fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, data)?;
+    fs::rename(&temporary, path)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Storage decorator sharing the identical artifacts between sessions, see the
+/// [module](self) documentation
+pub struct DeduplicatingStorage {
+    inner: Arc<dyn Storage + Send + Sync>,
+    store: Arc<DedupStore>,
+}
+
+impl DeduplicatingStorage {
+    pub fn new(inner: Arc<dyn Storage + Send + Sync>, store: Arc<DedupStore>) -> Self {
+        Self { inner, store }
+    }
+
+    /// Tags `session_id` as a duplicate of `canonical`, best effort
+    async fn tag_duplicate(&self, session_id: Uuid, canonical: Uuid) {
+        let tagged = async {
+            let mut annotations = self.inner.get_annotations(session_id).await?;
+            if annotations.add_tag(DUPLICATE_TAG, TagSource::Rule) {
+                self.inner.save_annotations(&annotations).await?;
+                info!("Session {} duplicates session {}", session_id, canonical);
+            }
+            Ok::<_, StorageError>(())
+        };
+        if let Err(e) = tagged.await {
+            warn!("Could not tag session {} as a duplicate: {}", session_id, e);
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for DeduplicatingStorage {
+    async fn save_session(&self, session: &Session) -> Result<(), StorageError> {
+        self.inner.save_session(session).await
+    }
+
+    async fn get_sessions(
+        &self,
+        filter: Option<SessionFilter>,
+    ) -> Result<Vec<Session>, StorageError> {
+        self.inner.get_sessions(filter).await
+    }
+
+    async fn get_session(&self, session_id: Uuid) -> Result<Session, StorageError> {
+        self.inner.get_session(session_id).await
+    }
+
+    async fn save_interaction(&self, session_id: Uuid, data: &[u8]) -> Result<(), StorageError> {
+        self.inner.save_interaction(session_id, data).await
+    }
+
+    async fn get_session_data(&self, session_id: Uuid) -> Result<Vec<u8>, StorageError> {
+        self.inner.get_session_data(session_id).await
+    }
+
+    async fn cleanup_old_sessions(&self, older_than: DateTime<Utc>) -> Result<usize, StorageError> {
+        let filter = SessionFilter {
+            end_date: Some(older_than),
+            ..Default::default()
+        };
+        let expired: Vec<Uuid> = self
+            .inner
+            .get_sessions(Some(filter))
+            .await?
+            .into_iter()
+            .filter(|s| s.end_time.unwrap_or(s.start_time) < older_than)
+            .map(|s| s.id)
+            .collect();
+        let deleted = self.inner.cleanup_old_sessions(older_than).await?;
+        self.store.release(&expired);
+        Ok(deleted)
+    }
+
+    async fn delete_sessions(&self, session_ids: &[Uuid]) -> Result<usize, StorageError> {
+        let deleted = self.inner.delete_sessions(session_ids).await?;
+        self.store.release(session_ids);
+        Ok(deleted)
+    }
+
+    async fn storage_size(&self) -> Result<u64, StorageError> {
+        Ok(self.inner.storage_size().await? + self.store.size())
+    }
+
+    async fn save_capture_artifacts(
+        &self,
+        artifacts: &CaptureArtifacts,
+    ) -> Result<(), StorageError> {
+        let service = self
+            .inner
+            .get_session(artifacts.session_id)
+            .await
+            .ok()
+            .map(|session| session.service_name);
+        let (stored, manifest) = self.store.share(artifacts, service.as_deref())?;
+        self.inner.save_capture_artifacts(&stored).await?;
+        if let Some(canonical) = manifest.duplicate_of {
+            self.tag_duplicate(artifacts.session_id, canonical).await;
+        }
+        Ok(())
+    }
+
+    async fn get_capture_artifacts(
+        &self,
+        session_id: Uuid,
+    ) -> Result<CaptureArtifacts, StorageError> {
+        let mut artifacts = self.inner.get_capture_artifacts(session_id).await?;
+        self.store.restore(&mut artifacts)?;
+        Ok(artifacts)
+    }
+
+    async fn save_credentials(&self, credentials: &[Credential]) -> Result<(), StorageError> {
+        self.inner.save_credentials(credentials).await
+    }
+
+    async fn get_credentials(
+        &self,
+        filter: Option<CredentialFilter>,
+    ) -> Result<Vec<Credential>, StorageError> {
+        self.inner.get_credentials(filter).await
+    }
+
+    async fn save_commands(
+        &self,
+        session_id: Uuid,
+        commands: &[ExecutedCommand],
+    ) -> Result<(), StorageError> {
+        self.inner.save_commands(session_id, commands).await
+    }
+
+    async fn get_commands(&self, session_id: Uuid) -> Result<Vec<ExecutedCommand>, StorageError> {
+        self.inner.get_commands(session_id).await
+    }
+
+    async fn save_annotations(&self, annotations: &SessionAnnotations) -> Result<(), StorageError> {
+        self.inner.save_annotations(annotations).await
+    }
+
+    async fn get_annotations(&self, session_id: Uuid) -> Result<SessionAnnotations, StorageError> {
+        self.inner.get_annotations(session_id).await
+    }
+
+    async fn save_honeytokens(&self, tokens: &[Honeytoken]) -> Result<(), StorageError> {
+        self.inner.save_honeytokens(tokens).await
+    }
+
+    async fn get_honeytokens(&self, limit: usize) -> Result<Vec<Honeytoken>, StorageError> {
+        self.inner.get_honeytokens(limit).await
+    }
+
+    async fn delete_honeytokens(&self, token_ids: &[Uuid]) -> Result<(), StorageError> {
+        self.inner.delete_honeytokens(token_ids).await
+    }
+
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, StorageError> {
+        self.inner.search(query, limit).await
+    }
+
+    async fn flush(&self) -> Result<(), StorageError> {
+        self.inner.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_capture::types::UploadedFile;
+    use crate::session_management::SessionStatus;
+    use crate::storage::memory_storage::MemoryStorage;
+
+    fn session(service: &str) -> Session {
+        Session {
+            id: Uuid::new_v4(),
+            service_name: service.to_string(),
+            client_addr: "203.0.113.7:50022".parse().unwrap(),
+            start_time: Utc::now(),
+            end_time: Some(Utc::now()),
+            container_id: None,
+            bytes_transferred: 0,
+            status: SessionStatus::Completed,
+            enrichment: None,
+            original_dst: None,
+        }
+    }
+
+    fn artifacts(session_id: Uuid, payload: &[u8]) -> CaptureArtifacts {
+        CaptureArtifacts {
+            session_id,
+            tcp_client_to_container: payload.to_vec(),
+            tcp_container_to_client: b"ok\n".to_vec(),
+            uploaded_files: vec![UploadedFile {
+                name: "bot".to_string(),
+                sha256: sha256_hex(&[0x7f; 64]),
+                md5: String::new(),
+                sha1: String::new(),
+                content: vec![0x7f; 64],
+                truncated: false,
+                virustotal: None,
+            }],
+            total_bytes: payload.len() as u64 + 67,
+            duration: chrono::Duration::seconds(1),
+            ..Default::default()
+        }
+    }
+
+    fn deduplicating() -> (tempfile::TempDir, Arc<MemoryStorage>, DeduplicatingStorage) {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = Arc::new(MemoryStorage::new());
+        let store = Arc::new(DedupStore::new(dir.path().to_path_buf(), 16));
+        let storage = DeduplicatingStorage::new(backend.clone(), store);
+        (dir, backend, storage)
+    }
+
+    async fn saved(storage: &DeduplicatingStorage, service: &str, payload: &[u8]) -> Uuid {
+        let session = session(service);
+        storage.save_session(&session).await.unwrap();
+        storage
+            .save_capture_artifacts(&artifacts(session.id, payload))
+            .await
+            .unwrap();
+        session.id
+    }
+
+    #[tokio::test]
+    async fn identical_artifacts_are_stored_once() {
+        let (dir, backend, storage) = deduplicating();
+        let payload = b"GET /cgi-bin/luci;stok=/locale?form=country HTTP/1.1\r\n\r\n";
+        let first = saved(&storage, "http", payload).await;
+        let second = saved(&storage, "http", payload).await;
+        let other = saved(&storage, "ssh", payload).await;
+
+        // Both the payload and the upload are shared by the three sessions
+        let blobs = fs::read_dir(dir.path().join("blobs")).unwrap().count();
+        assert_eq!(blobs, 2);
+        let stored = backend.get_capture_artifacts(second).await.unwrap();
+        assert!(stored.tcp_client_to_container.is_empty());
+        assert!(stored.uploaded_files[0].content.is_empty());
+        assert_eq!(stored.tcp_container_to_client, b"ok\n");
+        assert_eq!(
+            storage
+                .get_capture_artifacts(second)
+                .await
+                .unwrap()
+                .tcp_client_to_container,
+            payload
+        );
+
+        let store = &storage.store;
+        assert_eq!(store.manifest(first).unwrap().unwrap().duplicate_of, None);
+        assert_eq!(
+            store.manifest(second).unwrap().unwrap().duplicate_of,
+            Some(first)
+        );
+        assert_eq!(store.manifest(other).unwrap().unwrap().duplicate_of, None);
+        assert!(storage
+            .get_annotations(second)
+            .await
+            .unwrap()
+            .has_tag(DUPLICATE_TAG));
+        assert!(!storage
+            .get_annotations(first)
+            .await
+            .unwrap()
+            .has_tag(DUPLICATE_TAG));
+
+        // Saving again keeps the references and the canonical session
+        storage
+            .save_capture_artifacts(&storage.get_capture_artifacts(first).await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(store.manifest(first).unwrap().unwrap().duplicate_of, None);
+        assert_eq!(store.refs(&sha256_hex(payload)).unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn blobs_go_with_their_last_session() {
+        let (dir, _, storage) = deduplicating();
+        let payload = b"\x00\x00\x00\x2f\xff\x53\x4d\x42\x72\x00\x00\x00\x00\x18\x53\xc8";
+        let first = saved(&storage, "smb", payload).await;
+        let second = saved(&storage, "smb", payload).await;
+        let blob = dir.path().join("blobs").join(sha256_hex(payload));
+
+        storage.delete_sessions(&[first]).await.unwrap();
+        assert!(blob.exists());
+        assert!(storage.store.manifest(first).unwrap().is_none());
+        assert_eq!(
+            storage
+                .get_capture_artifacts(second)
+                .await
+                .unwrap()
+                .tcp_client_to_container,
+            payload
+        );
+
+        // The payload of the deleted canonical session has a new one
+        let third = saved(&storage, "smb", payload).await;
+        assert_eq!(
+            storage.store.manifest(third).unwrap().unwrap().duplicate_of,
+            None
+        );
+
+        storage.delete_sessions(&[second, third]).await.unwrap();
+        assert!(!blob.exists());
+        assert_eq!(fs::read_dir(dir.path().join("blobs")).unwrap().count(), 0);
+    }
+}
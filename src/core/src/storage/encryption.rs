@@ -0,0 +1,288 @@
+//! Encryption at rest of the files of the filesystem backend.
+//!
+//! Captured data holds the credentials and personal data of whoever connected, so
+//! with `[file_encryption]` the files are sealed with AES-256-GCM before they are
+//! written. An encrypted file is a sequence of records, each being [`MAGIC`], the
+//! format version, a random 96-bit nonce, the big-endian `u32` length of the
+//! ciphertext and the ciphertext with its tag. Files the backend appends to, such
+//! as interactions and credentials, get one record per write.
+//!
+//! The path of the file relative to the storage root is authenticated with each
+//! record, so that the files of two sessions cannot be swapped unnoticed. Files
+//! without the magic are plain, written before encryption was enabled, and are read
+//! as they are. A plain file appended to once encryption is enabled is sealed whole
+//! first, see [`starts_encrypted`]; those earlier versions left as plain data
+//! followed by records are still read back.
+
+use std::fs;
+use std::io;
+
+use log::error;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::configuration::types::FileEncryptionConfig;
+use crate::error_handling::types::StorageError;
+
+/// First bytes of an encrypted record
+pub const MAGIC: &[u8; 4] = b"\0MLE";
+
+/// Version of the records written by [`EncryptionKey::seal`]
+pub const FORMAT_VERSION: u8 = 1;
+
+const HEADER_LEN: usize = MAGIC.len() + 1 + NONCE_LEN + 4;
+
+/// Whether `payload` is made of encrypted records. Plain data that merely starts
+/// with the magic, e.g. a crafted TCP stream, is not
+pub fn is_encrypted(payload: &[u8]) -> bool {
+    let mut rest = payload;
+    while !rest.is_empty() {
+        let Some(len) = record_len(rest) else {
+            return false;
+        };
+        rest = &rest[HEADER_LEN + len..];
+    }
+    !payload.is_empty()
+}
+
+/// Whether `head`, the first bytes of a file, starts like an encrypted record.
+/// Appending records to a file that does not would leave it half plain
+pub fn starts_encrypted(head: &[u8]) -> bool {
+    head.starts_with(MAGIC) && head.get(MAGIC.len()) == Some(&FORMAT_VERSION)
+}
+
+/// Length of the ciphertext of the record `rest` starts with, `None` when it does
+/// not start with a whole record
+fn record_len(rest: &[u8]) -> Option<usize> {
+    if rest.len() < HEADER_LEN || !rest.starts_with(MAGIC) || rest[MAGIC.len()] != FORMAT_VERSION {
+        return None;
+    }
+    let len_bytes = <[u8; 4]>::try_from(&rest[HEADER_LEN - 4..HEADER_LEN]).ok()?;
+    let len = u32::from_be_bytes(len_bytes) as usize;
+    (rest.len() >= HEADER_LEN + len).then_some(len)
+}
+
+/// Key the files are encrypted with
+#[derive(Debug)]
+pub struct EncryptionKey {
+    key: LessSafeKey,
+    rng: SystemRandom,
+}
+
+impl EncryptionKey {
+    /// Key given as 64 hexadecimal characters
+    pub fn parse(hex: &str) -> io::Result<Self> {
+        let hex = hex.trim();
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the key needs 64 hexadecimal characters",
+            )
+        };
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| invalid())?;
+        let key = UnboundKey::new(&AES_256_GCM, &bytes).map_err(|_| invalid())?;
+        Ok(Self {
+            key: LessSafeKey::new(key),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    /// Key of `config`, read from its file or environment variable, `None` when
+    /// encryption is not configured
+    pub fn from_config(config: &FileEncryptionConfig) -> io::Result<Option<Self>> {
+        let hex = if let Some(key) = &config.key {
+            key.clone()
+        } else if let Some(path) = &config.key_file {
+            fs::read_to_string(path).map_err(|e| {
+                io::Error::new(e.kind(), format!("cannot read {}: {}", path.display(), e))
+            })?
+        } else if let Some(name) = &config.key_env {
+            std::env::var(name).map_err(|_| {
+                io::Error::new(io::ErrorKind::NotFound, format!("{} is not set", name))
+            })?
+        } else {
+            return Ok(None);
+        };
+        Self::parse(&hex).map(Some)
+    }
+
+    /// Encrypts `data` as a single record bound to `context`
+    pub fn seal(&self, context: &str, data: &[u8]) -> Result<Vec<u8>, StorageError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng.fill(&mut nonce).map_err(|_| {
+            error!("No randomness available to encrypt {}", context);
+            StorageError::WriteFailed
+        })?;
+        let mut ciphertext = data.to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(context.as_bytes()),
+                &mut ciphertext,
+            )
+            .map_err(|_| {
+                error!("Failed to encrypt {}", context);
+                StorageError::WriteFailed
+            })?;
+        let len = u32::try_from(ciphertext.len()).map_err(|_| {
+            error!("{} is too large to be encrypted", context);
+            StorageError::WriteFailed
+        })?;
+
+        let mut record = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+        record.extend_from_slice(MAGIC);
+        record.push(FORMAT_VERSION);
+        record.extend_from_slice(&nonce);
+        record.extend_from_slice(&len.to_be_bytes());
+        record.extend_from_slice(&ciphertext);
+        Ok(record)
+    }
+
+    /// Decrypts the records of `payload` bound to `context`, or returns plain data
+    /// as is, along with the records appended to it
+    pub fn open(&self, context: &str, payload: &[u8]) -> Result<Vec<u8>, StorageError> {
+        if !starts_encrypted(payload) {
+            return Ok(self.open_appended(context, payload));
+        }
+        // Truncated files are corrupt, not plain
+        if !is_encrypted(payload) {
+            error!("Truncated encrypted record in {}", context);
+            return Err(StorageError::ReadFailed);
+        }
+        self.open_records(context, payload).ok_or_else(|| {
+            error!(
+                "Cannot decrypt {}: wrong key, or the file was modified",
+                context
+            );
+            StorageError::ReadFailed
+        })
+    }
+
+    /// Plain data, followed by the records appended once encryption was enabled
+    /// when the file was not sealed first. Records that do not decrypt are taken
+    /// as plain data crafted to look like them
+    fn open_appended(&self, context: &str, payload: &[u8]) -> Vec<u8> {
+        let records = (1..payload.len())
+            .filter(|&start| is_encrypted(&payload[start..]))
+            .find_map(|start| Some((start, self.open_records(context, &payload[start..])?)));
+        match records {
+            Some((start, data)) => [&payload[..start], data.as_slice()].concat(),
+            None => payload.to_vec(),
+        }
+    }
+
+    /// Plaintext of the whole `records`, `None` when one does not decrypt
+    fn open_records(&self, context: &str, records: &[u8]) -> Option<Vec<u8>> {
+        let mut data = Vec::with_capacity(records.len());
+        let mut rest = records;
+        while !rest.is_empty() {
+            let len = record_len(rest)?;
+            let nonce_start = MAGIC.len() + 1;
+            let nonce =
+                <[u8; NONCE_LEN]>::try_from(&rest[nonce_start..nonce_start + NONCE_LEN]).ok()?;
+            let mut plaintext = rest[HEADER_LEN..HEADER_LEN + len].to_vec();
+            let opened = self
+                .key
+                .open_in_place(
+                    Nonce::assume_unique_for_key(nonce),
+                    Aad::from(context.as_bytes()),
+                    &mut plaintext,
+                )
+                .ok()?;
+            data.extend_from_slice(opened);
+            rest = &rest[HEADER_LEN + len..];
+        }
+        Some(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> EncryptionKey {
+        EncryptionKey::parse(&"1f".repeat(32)).unwrap()
+    }
+
+    #[test]
+    fn records_roundtrip_and_concatenate() {
+        let key = key();
+        let first = key.seal("credentials/x.jsonl", b"root:toor\n").unwrap();
+        assert!(is_encrypted(&first));
+        assert!(!first.windows(4).any(|w| w == b"toor"));
+        // A random nonce per record
+        assert_ne!(
+            first,
+            key.seal("credentials/x.jsonl", b"root:toor\n").unwrap()
+        );
+
+        let second = key.seal("credentials/x.jsonl", b"admin:admin\n").unwrap();
+        let file = [first, second].concat();
+        assert_eq!(
+            key.open("credentials/x.jsonl", &file).unwrap(),
+            b"root:toor\nadmin:admin\n"
+        );
+        assert_eq!(key.open("legacy", b"plain").unwrap(), b"plain");
+        let crafted = [MAGIC.as_slice(), b" looks sealed"].concat();
+        assert!(!is_encrypted(&crafted));
+        assert_eq!(key.open("legacy", &crafted).unwrap(), crafted);
+    }
+
+    #[test]
+    fn tampering_moving_and_other_keys_are_refused() {
+        let key = key();
+        let mut record = key.seal("sessions/a.session", b"id: a").unwrap();
+        assert!(key.open("sessions/b.session", &record).is_err());
+
+        let other = EncryptionKey::parse(&"2e".repeat(32)).unwrap();
+        assert!(other.open("sessions/a.session", &record).is_err());
+
+        let last = record.len() - 1;
+        record[last] ^= 1;
+        assert!(key.open("sessions/a.session", &record).is_err());
+        assert!(key.open("sessions/a.session", &record[..10]).is_err());
+    }
+
+    #[test]
+    fn plain_files_appended_to_are_read_whole() {
+        let key = key();
+        let records = [
+            key.seal("credentials/x.jsonl", b"admin:admin\n").unwrap(),
+            key.seal("credentials/x.jsonl", b"root:root\n").unwrap(),
+        ]
+        .concat();
+        let mixed = [b"root:toor\n".as_slice(), &records].concat();
+        assert!(!starts_encrypted(&mixed));
+        assert_eq!(
+            key.open("credentials/x.jsonl", &mixed).unwrap(),
+            b"root:toor\nadmin:admin\nroot:root\n"
+        );
+
+        // Records of another file, or crafted ones, are plain data
+        assert_eq!(key.open("credentials/y.jsonl", &mixed).unwrap(), mixed);
+        let crafted = [
+            b"GET /".as_slice(),
+            &[0u8; 2],
+            MAGIC,
+            &[1],
+            &[0; 12],
+            &[0, 0, 0, 0],
+        ]
+        .concat();
+        assert_eq!(key.open("tcp/client.bin", &crafted).unwrap(), crafted);
+    }
+
+    #[test]
+    fn keys_need_64_hex_characters() {
+        assert!(EncryptionKey::parse("00ff").is_err());
+        assert!(EncryptionKey::parse(&"zz".repeat(32)).is_err());
+        assert!(EncryptionKey::parse(&format!("{}\n", "AB".repeat(32))).is_ok());
+    }
+}
@@ -0,0 +1,518 @@
+//! Evidence bundles of a session.
+//!
+//! [`to_tar_gz`] assembles everything stored for a session into a single tar.gz,
+//! to be handed over to incident response teams. Entries live under a directory
+//! named after the session:
+//! - `session.json`, `credentials.json` and `commands.json`: the session metadata,
+//!   the login attempts and the command timeline
+//! - `capture.json`: flow, TLS and size metadata of the capture, listing the
+//!   extracted files
+//! - `streams/`: client and container TCP streams, and the stdio streams
+//! - `timestamps/`: `tcp.csv` and `stdio.csv`, one `time,stream,bytes` row per chunk
+//! - `files/`: uploaded files, received emails and files carved from the streams,
+//!   as captured
+//! - `capture.pcap` and `replay.cast`, when the artifacts allow rendering them
+//! - `http.json`: the HTTP requests and their responses, for sessions speaking HTTP
+//! - `manifest.json`: the size and SHA-256 of every other entry
+//!
+//! Sessions still running, whose artifacts are not saved yet, export their
+//! metadata only.
+//!
+//! An [`Archive`] gathers the bundles of several sessions into one tar.gz, each in
+//! its directory, for bulk exports. It is written as the sessions are added, to
+//! memory or to any writer such as the file a bulk export is streamed from.
+
+use std::io::Write;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use flate2::write::GzEncoder;
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::data_capture::types::{Direction, StdioStream};
+use crate::data_capture::{asciicast, http_capture, pcap, CaptureArtifacts};
+use crate::error_handling::types::StorageError;
+use crate::session::Session;
+use crate::storage::types::{Credential, ExecutedCommand};
+
+/// Version of the bundle layout, recorded in the manifest
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Content of `manifest.json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format_version: u32,
+    pub session_id: Uuid,
+    pub exported_at: DateTime<Utc>,
+    /// Every entry of the bundle but the manifest, in archive order
+    pub files: Vec<ManifestEntry>,
+}
+
+/// An entry of the bundle, its path being relative to the session directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+struct Bundle<'a, W: Write> {
+    builder: &'a mut tar::Builder<GzEncoder<W>>,
+    root: String,
+    mtime: u64,
+    files: Vec<ManifestEntry>,
+}
+
+impl<W: Write> Bundle<'_, W> {
+    fn add(&mut self, path: &str, content: &[u8]) -> Result<(), StorageError> {
+        self.append(path, content)?;
+        self.files.push(ManifestEntry {
+            path: path.to_string(),
+            size: content.len() as u64,
+            sha256: sha256_hex(content),
+        });
+        Ok(())
+    }
+
+    fn add_json(&mut self, path: &str, value: &impl Serialize) -> Result<(), StorageError> {
+        let json = serde_json::to_vec_pretty(value).map_err(|e| {
+            error!("Failed to serialize {} of the export bundle: {}", path, e);
+            StorageError::ReadFailed
+        })?;
+        self.add(path, &json)
+    }
+
+    fn append(&mut self, path: &str, content: &[u8]) -> Result<(), StorageError> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(self.mtime);
+        header.set_cksum();
+        self.builder
+            .append_data(&mut header, format!("{}/{}", self.root, path), content)
+            .map_err(|e| {
+                error!("Failed to add {} to the export bundle: {}", path, e);
+                StorageError::ReadFailed
+            })
+    }
+}
+
+pub(crate) fn sha256_hex(content: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    super::hex(&Sha256::digest(content))
+}
+
+/// Keeps the characters of an uploaded file name that are safe in an archive path
+pub(crate) fn safe_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    name.trim_start_matches('.').to_string()
+}
+
+fn timestamp(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+/// Assembles the evidence bundle of `session`, see the [module documentation](self)
+pub fn to_tar_gz(
+    session: &Session,
+    artifacts: Option<&CaptureArtifacts>,
+    credentials: &[Credential],
+    commands: &[ExecutedCommand],
+    exported_at: DateTime<Utc>,
+) -> Result<Vec<u8>, StorageError> {
+    let mut archive = Archive::new(exported_at);
+    archive.add_session(session, artifacts, credentials, commands)?;
+    archive.finish()
+}
+
+/// A tar.gz of the evidence bundles of several sessions, each in the directory
+/// named after the session
+pub struct Archive<W: Write = Vec<u8>> {
+    builder: tar::Builder<GzEncoder<W>>,
+    exported_at: DateTime<Utc>,
+    sessions: usize,
+}
+
+impl Archive {
+    pub fn new(exported_at: DateTime<Utc>) -> Self {
+        Self::to_writer(Vec::new(), exported_at)
+    }
+}
+
+impl<W: Write> Archive<W> {
+    /// Archive compressed into `out` as the sessions are added
+    pub fn to_writer(out: W, exported_at: DateTime<Utc>) -> Self {
+        Self {
+            builder: tar::Builder::new(GzEncoder::new(out, flate2::Compression::default())),
+            exported_at,
+            sessions: 0,
+        }
+    }
+
+    /// Appends the bundle of `session`, with its own manifest
+    pub fn add_session(
+        &mut self,
+        session: &Session,
+        artifacts: Option<&CaptureArtifacts>,
+        credentials: &[Credential],
+        commands: &[ExecutedCommand],
+    ) -> Result<(), StorageError> {
+        let exported_at = self.exported_at;
+        let mut bundle = Bundle {
+            builder: &mut self.builder,
+            root: session.id.to_string(),
+            mtime: exported_at.timestamp().max(0) as u64,
+            files: Vec::new(),
+        };
+
+        bundle.add_json("session.json", session)?;
+        bundle.add_json("credentials.json", &credentials)?;
+        bundle.add_json("commands.json", &commands)?;
+
+        if let Some(artifacts) = artifacts {
+            let mut uploads = Vec::new();
+            for (i, upload) in artifacts.uploaded_files.iter().enumerate() {
+                let path = format!("files/uploads/{}-{}", i, safe_name(&upload.name));
+                bundle.add(&path, &upload.content)?;
+                uploads.push(json!({
+                    "path": path,
+                    "name": upload.name,
+                    "md5": upload.md5,
+                    "sha1": upload.sha1,
+                    "sha256": upload.sha256,
+                    "truncated": upload.truncated,
+                    "virustotal": upload.virustotal,
+                }));
+            }
+            let mut carved = Vec::new();
+            for (i, file) in artifacts.carved_files.iter().enumerate() {
+                let path = format!("files/carved/{}-{}", i, safe_name(&file.name));
+                bundle.add(&path, &file.content)?;
+                carved.push(json!({
+                    "path": path,
+                    "name": file.name,
+                    "source": file.source,
+                    "md5": file.md5,
+                    "sha1": file.sha1,
+                    "sha256": file.sha256,
+                    "truncated": file.truncated,
+                    "virustotal": file.virustotal,
+                }));
+            }
+            let mut messages = Vec::new();
+            for (i, message) in artifacts.messages.iter().enumerate() {
+                let path = format!("files/messages/{}.eml", i);
+                bundle.add(&path, &message.content)?;
+                messages.push(json!({
+                    "path": path,
+                    "helo": message.helo,
+                    "mail_from": message.mail_from,
+                    "rcpt_to": message.rcpt_to,
+                    "received_at": timestamp(&message.received_at),
+                    "truncated": message.truncated,
+                }));
+            }
+            bundle.add_json(
+                "capture.json",
+                &json!({
+                    "total_bytes": artifacts.total_bytes,
+                    "duration_secs": artifacts.duration.num_milliseconds() as f64 / 1000.0,
+                    "flow": artifacts.flow,
+                    "tls": artifacts.tls,
+                    "uploaded_files": uploads,
+                    "carved_files": carved,
+                    "messages": messages,
+                    "keystrokes": artifacts.keystrokes,
+                    "ssh_channels": artifacts.ssh_channels,
+                }),
+            )?;
+
+            bundle.add(
+                "streams/tcp_client_to_container.bin",
+                &artifacts.tcp_client_to_container,
+            )?;
+            bundle.add(
+                "streams/tcp_container_to_client.bin",
+                &artifacts.tcp_container_to_client,
+            )?;
+            bundle.add("streams/stdin.txt", artifacts.stdio_stdin.as_bytes())?;
+            bundle.add("streams/stdout.txt", artifacts.stdio_stdout.as_bytes())?;
+            bundle.add("streams/stderr.txt", artifacts.stdio_stderr.as_bytes())?;
+
+            let mut tcp = String::from("time,stream,bytes\n");
+            for (time, direction, bytes) in &artifacts.tcp_timestamps {
+                let stream = match direction {
+                    Direction::ClientToContainer => "client_to_container",
+                    Direction::ContainerToClient => "container_to_client",
+                };
+                tcp.push_str(&format!("{},{},{}\n", timestamp(time), stream, bytes));
+            }
+            bundle.add("timestamps/tcp.csv", tcp.as_bytes())?;
+            let mut stdio = String::from("time,stream,bytes\n");
+            for (time, stream, bytes) in &artifacts.stdio_timestamps {
+                let stream = match stream {
+                    StdioStream::Stdin => "stdin",
+                    StdioStream::Stdout => "stdout",
+                    StdioStream::Stderr => "stderr",
+                };
+                stdio.push_str(&format!("{},{},{}\n", timestamp(time), stream, bytes));
+            }
+            bundle.add("timestamps/stdio.csv", stdio.as_bytes())?;
+
+            if let Some(capture) = pcap::to_pcap(artifacts) {
+                bundle.add("capture.pcap", &capture)?;
+            }
+            if let Some(replay) = asciicast::to_asciicast(artifacts) {
+                bundle.add("replay.cast", replay.as_bytes())?;
+            }
+            let exchanges = http_capture::from_artifacts(artifacts);
+            if !exchanges.is_empty() {
+                let json =
+                    serde_json::to_vec_pretty(&exchanges).map_err(|_| StorageError::ReadFailed)?;
+                bundle.add("http.json", &json)?;
+            }
+        }
+
+        let manifest = Manifest {
+            format_version: FORMAT_VERSION,
+            session_id: session.id,
+            exported_at,
+            files: std::mem::take(&mut bundle.files),
+        };
+        let json = serde_json::to_vec_pretty(&manifest).map_err(|_| StorageError::ReadFailed)?;
+        bundle.append("manifest.json", &json)?;
+        self.sessions += 1;
+        Ok(())
+    }
+
+    /// Number of sessions added so far
+    pub fn sessions(&self) -> usize {
+        self.sessions
+    }
+
+    /// Completes the archive, handing back the writer it was compressed into
+    pub fn finish(self) -> Result<W, StorageError> {
+        let out = self
+            .builder
+            .into_inner()
+            .and_then(|encoder| encoder.finish())
+            .map_err(|e| {
+                error!("Failed to finish the export bundle: {}", e);
+                StorageError::ReadFailed
+            })?;
+        debug!("Exported {} session(s)", self.sessions);
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_capture::types::{FlowEndpoints, Transport, UploadedFile};
+    use crate::session_management::SessionStatus;
+    use flate2::read::GzDecoder;
+    use std::collections::HashMap;
+    use std::io::Read;
+
+    fn session() -> Session {
+        let start = DateTime::parse_from_rfc3339("2025-09-03T20:17:18Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        Session {
+            id: Uuid::new_v4(),
+            service_name: "ssh".into(),
+            client_addr: "203.0.113.7:51000".parse().unwrap(),
+            start_time: start,
+            end_time: Some(start + chrono::Duration::seconds(3)),
+            container_id: None,
+            bytes_transferred: 9,
+            status: SessionStatus::Completed,
+            enrichment: None,
+            original_dst: None,
+        }
+    }
+
+    fn entries(bundle: &[u8]) -> HashMap<String, Vec<u8>> {
+        let mut archive = tar::Archive::new(GzDecoder::new(bundle));
+        archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let path = entry.path().unwrap().to_string_lossy().to_string();
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content).unwrap();
+                (path, content)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bundle_holds_every_artifact_with_its_checksum() {
+        let session = session();
+        let start = session.start_time;
+        let artifacts = CaptureArtifacts {
+            session_id: session.id,
+            tcp_client_to_container: b"id\n".to_vec(),
+            tcp_container_to_client: b"uid=0\n".to_vec(),
+            stdio_stdin: "id\n".to_string(),
+            stdio_stdout: "uid=0\n".to_string(),
+            tcp_timestamps: vec![(start, Direction::ClientToContainer, 3)],
+            stdio_timestamps: vec![(start, StdioStream::Stdin, 3)],
+            total_bytes: 9,
+            duration: chrono::Duration::seconds(3),
+            flow: Some(FlowEndpoints {
+                transport: Transport::Tcp,
+                client_addr: "203.0.113.7:51000".parse().unwrap(),
+                server_addr: "192.0.2.1:22".parse().unwrap(),
+            }),
+            uploaded_files: vec![UploadedFile {
+                name: "../../etc/cron.d/bot".to_string(),
+                sha256: sha256_hex(b"* * * * * root /tmp/x\n"),
+                md5: String::new(),
+                sha1: String::new(),
+                content: b"* * * * * root /tmp/x\n".to_vec(),
+                truncated: false,
+                virustotal: None,
+            }],
+            ..Default::default()
+        };
+
+        let bundle = to_tar_gz(&session, Some(&artifacts), &[], &[], Utc::now()).unwrap();
+        let entries = entries(&bundle);
+        let root = session.id.to_string();
+        let manifest: Manifest =
+            serde_json::from_slice(&entries[&format!("{}/manifest.json", root)]).unwrap();
+        assert_eq!(manifest.format_version, FORMAT_VERSION);
+        assert_eq!(manifest.session_id, session.id);
+        assert_eq!(manifest.files.len() + 1, entries.len());
+        for file in &manifest.files {
+            let content = &entries[&format!("{}/{}", root, file.path)];
+            assert_eq!(file.size, content.len() as u64);
+            assert_eq!(file.sha256, sha256_hex(content));
+        }
+
+        let paths: Vec<&str> = manifest.files.iter().map(|f| f.path.as_str()).collect();
+        assert!(paths.contains(&"files/uploads/0-_.._etc_cron.d_bot"));
+        assert!(paths.contains(&"capture.pcap"));
+        assert!(paths.contains(&"replay.cast"));
+        assert_eq!(
+            entries[&format!("{}/timestamps/tcp.csv", root)],
+            b"time,stream,bytes\n2025-09-03T20:17:18.000000Z,client_to_container,3\n"
+        );
+        let capture: serde_json::Value =
+            serde_json::from_slice(&entries[&format!("{}/capture.json", root)]).unwrap();
+        assert_eq!(capture["uploaded_files"][0]["name"], "../../etc/cron.d/bot");
+    }
+
+    #[tokio::test]
+    async fn stored_sessions_are_exported() {
+        use crate::storage::file_storage::FileStorage;
+        use crate::storage::storage_trait::Storage;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let storage = FileStorage::new(dir.path()).unwrap();
+        let session = session();
+        storage.save_session(&session).await.unwrap();
+        let credential = Credential {
+            session_id: session.id,
+            timestamp: session.start_time,
+            service: "ssh".into(),
+            client_ip: Some(session.client_addr.ip()),
+            username: "root".into(),
+            password: Some("toor".into()),
+            accepted: Some(false),
+        };
+        storage
+            .save_credentials(std::slice::from_ref(&credential))
+            .await
+            .unwrap();
+
+        let bundle = storage.export_session(session.id).await.unwrap();
+        let entries = entries(&bundle);
+        let credentials: Vec<Credential> =
+            serde_json::from_slice(&entries[&format!("{}/credentials.json", session.id)]).unwrap();
+        assert_eq!(credentials, vec![credential]);
+        assert!(storage.export_session(Uuid::new_v4()).await.is_err());
+
+        // Artifacts that cannot be read fail the export rather than being left out
+        let artifacts_dir = dir.path().join("artifacts").join(session.id.to_string());
+        std::fs::create_dir(artifacts_dir).unwrap();
+        assert!(storage.export_session(session.id).await.is_err());
+        assert!(storage
+            .export_sessions(None, &mut Vec::new())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn matching_sessions_are_exported_together() {
+        use crate::storage::memory_storage::MemoryStorage;
+        use crate::storage::storage_trait::Storage;
+        use crate::storage::types::SessionFilter;
+
+        let storage = MemoryStorage::new();
+        let (ssh, telnet) = (session(), session());
+        let telnet = Session {
+            service_name: "telnet".into(),
+            ..telnet
+        };
+        storage.save_session(&ssh).await.unwrap();
+        storage.save_session(&telnet).await.unwrap();
+
+        let mut archive = Vec::new();
+        let exported = storage.export_sessions(None, &mut archive).await.unwrap();
+        assert_eq!(exported, 2);
+        let both = entries(&archive);
+        for session in [&ssh, &telnet] {
+            let manifest: Manifest =
+                serde_json::from_slice(&both[&format!("{}/manifest.json", session.id)]).unwrap();
+            assert_eq!(manifest.session_id, session.id);
+        }
+
+        let filter = SessionFilter {
+            service_name: Some("telnet".into()),
+            ..SessionFilter::default()
+        };
+        let mut archive = Vec::new();
+        storage
+            .export_sessions(Some(filter), &mut archive)
+            .await
+            .unwrap();
+        let entries = entries(&archive);
+        assert!(entries
+            .keys()
+            .all(|path| path.starts_with(&telnet.id.to_string())));
+        assert_eq!(entries.len(), 4);
+    }
+
+    #[test]
+    fn running_sessions_export_their_metadata() {
+        let session = session();
+        let bundle = to_tar_gz(&session, None, &[], &[], Utc::now()).unwrap();
+        let mut paths: Vec<String> = entries(&bundle).into_keys().collect();
+        paths.sort();
+        let root = session.id;
+        assert_eq!(
+            paths,
+            [
+                "commands.json",
+                "credentials.json",
+                "manifest.json",
+                "session.json"
+            ]
+            .map(|path| format!("{}/{}", root, path))
+        );
+    }
+}
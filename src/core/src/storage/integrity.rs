@@ -0,0 +1,595 @@
+//! Integrity manifests of the stored capture artifacts.
+//!
+//! [`IntegrityStorage`] wraps the storage backend when `[integrity]` is enabled.
+//! Once the artifacts of a session are saved, the size and SHA-256 of its TCP and
+//! stdio streams, uploaded files, carved files and messages are written to
+//! `<dir>/<session id>.json`, named as in the [export bundles](super::export). With
+//! an `hmac_key` the manifest is signed with HMAC-SHA256, so that whoever can edit
+//! the stored files cannot forge a matching manifest without the key.
+//!
+//! [`IntegrityLedger::verify`] reads the artifacts back from the storage and compares
+//! them with the manifest, reporting every entry that was modified, went missing or
+//! was added, and whether the signature holds. It backs `miel sessions verify` and
+//! `GET /api/sessions/:id/verify`, so that captures can be relied on as evidence.
+//!
+//! Manifests are kept on the filesystem whatever the backend, and removed with their
+//! sessions.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::{debug, error, warn};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::configuration::types::IntegrityConfig;
+use crate::data_capture::CaptureArtifacts;
+use crate::error_handling::types::StorageError;
+use crate::honeytokens::Honeytoken;
+use crate::session::Session;
+use crate::storage::export::{safe_name, sha256_hex, ManifestEntry};
+use crate::storage::hex;
+use crate::storage::storage_trait::Storage;
+use crate::storage::types::{
+    Credential, CredentialFilter, ExecutedCommand, SearchHit, SessionAnnotations, SessionFilter,
+};
+
+/// Version of the manifest layout
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Checksums of the artifacts of a session, as written when they were saved
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IntegrityManifest {
+    pub format_version: u32,
+    pub session_id: Uuid,
+    pub sealed_at: DateTime<Utc>,
+    pub files: Vec<ManifestEntry>,
+    /// Hex HMAC-SHA256 of the manifest without this field, when a key is configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hmac: Option<String>,
+}
+
+impl IntegrityManifest {
+    /// Manifest of `artifacts`, unsigned
+    pub fn of(artifacts: &CaptureArtifacts, sealed_at: DateTime<Utc>) -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            session_id: artifacts.session_id,
+            sealed_at,
+            files: entries(artifacts),
+            hmac: None,
+        }
+    }
+
+    /// What the HMAC is computed over
+    fn signed_bytes(&self) -> Vec<u8> {
+        let unsigned = Self {
+            hmac: None,
+            ..self.clone()
+        };
+        serde_json::to_vec(&unsigned).unwrap_or_default()
+    }
+}
+
+/// Checksummed contents of `artifacts`, named as in the export bundles
+fn entries(artifacts: &CaptureArtifacts) -> Vec<ManifestEntry> {
+    let mut contents: Vec<(String, &[u8])> = vec![
+        (
+            "streams/tcp_client_to_container.bin".to_string(),
+            &artifacts.tcp_client_to_container,
+        ),
+        (
+            "streams/tcp_container_to_client.bin".to_string(),
+            &artifacts.tcp_container_to_client,
+        ),
+        (
+            "streams/stdin.txt".to_string(),
+            artifacts.stdio_stdin.as_bytes(),
+        ),
+        (
+            "streams/stdout.txt".to_string(),
+            artifacts.stdio_stdout.as_bytes(),
+        ),
+        (
+            "streams/stderr.txt".to_string(),
+            artifacts.stdio_stderr.as_bytes(),
+        ),
+    ];
+    for (i, upload) in artifacts.uploaded_files.iter().enumerate() {
+        let path = format!("files/uploads/{}-{}", i, safe_name(&upload.name));
+        contents.push((path, &upload.content));
+    }
+    for (i, file) in artifacts.carved_files.iter().enumerate() {
+        let path = format!("files/carved/{}-{}", i, safe_name(&file.name));
+        contents.push((path, &file.content));
+    }
+    for (i, message) in artifacts.messages.iter().enumerate() {
+        contents.push((format!("files/messages/{}.eml", i), &message.content));
+    }
+    contents
+        .into_iter()
+        .map(|(path, content)| ManifestEntry {
+            path,
+            size: content.len() as u64,
+            sha256: sha256_hex(content),
+        })
+        .collect()
+}
+
+/// State of an entry of the manifest against the stored artifacts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryStatus {
+    Modified,
+    Missing,
+    /// Stored but not in the manifest
+    Unexpected,
+}
+
+/// An entry that does not match its manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EntryMismatch {
+    pub path: String,
+    pub status: EntryStatus,
+    pub expected_sha256: Option<String>,
+    pub actual_sha256: Option<String>,
+}
+
+/// Outcome of [`IntegrityLedger::verify`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IntegrityReport {
+    pub session_id: Uuid,
+    pub sealed_at: DateTime<Utc>,
+    /// Entries of the manifest
+    pub checked: usize,
+    /// `None` when the manifest is unsigned or no key is configured to check it
+    pub signature_valid: Option<bool>,
+    pub mismatches: Vec<EntryMismatch>,
+    /// Whether every entry matches and the signature, if checked, holds
+    pub intact: bool,
+}
+
+/// Where the manifests are written and the key they are signed with
+pub struct IntegrityLedger {
+    dir: PathBuf,
+    key: Option<hmac::Key>,
+}
+
+impl IntegrityLedger {
+    pub fn new(dir: PathBuf, hmac_key: Option<&str>) -> Self {
+        Self {
+            dir,
+            key: hmac_key.map(|key| hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes())),
+        }
+    }
+
+    /// The ledger of `config`, its manifests in the `integrity` directory of
+    /// `storage_path` unless it sets its own. `None` when integrity is disabled
+    pub fn from_config(config: &IntegrityConfig, storage_path: &Path) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        let dir = config
+            .dir
+            .clone()
+            .unwrap_or_else(|| storage_path.join("integrity"));
+        Some(Self::new(dir, config.hmac_key.as_deref()))
+    }
+
+    fn path(&self, session_id: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.json", session_id))
+    }
+
+    /// Writes the manifest of `artifacts`, replacing the one of a previous save
+    pub fn seal(&self, artifacts: &CaptureArtifacts) -> Result<IntegrityManifest, StorageError> {
+        let mut manifest = IntegrityManifest::of(artifacts, Utc::now());
+        if let Some(key) = &self.key {
+            let tag = hmac::sign(key, &manifest.signed_bytes());
+            manifest.hmac = Some(hex(tag.as_ref()));
+        }
+        let json = serde_json::to_vec_pretty(&manifest).map_err(|e| {
+            error!("Failed to serialize integrity manifest: {}", e);
+            StorageError::WriteFailed
+        })?;
+
+        let path = self.path(manifest.session_id);
+        let temporary = path.with_extension("json.tmp");
+        fs::create_dir_all(&self.dir)
+            .and_then(|()| fs::write(&temporary, json))
+            .and_then(|()| fs::rename(&temporary, &path))
+            .map_err(|e| {
+                error!(
+                    "Failed to write integrity manifest {}: {}",
+                    path.display(),
+                    e
+                );
+                StorageError::WriteFailed
+            })?;
+        debug!(
+            "Sealed {} artifacts of session {}",
+            manifest.files.len(),
+            manifest.session_id
+        );
+        Ok(manifest)
+    }
+
+    /// The manifest of `session_id`, `None` when its artifacts were never sealed
+    pub fn manifest(&self, session_id: Uuid) -> Result<Option<IntegrityManifest>, StorageError> {
+        let json = match fs::read(self.path(session_id)) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                error!("Failed to read integrity manifest of {}: {}", session_id, e);
+                return Err(StorageError::ReadFailed);
+            }
+        };
+        serde_json::from_slice(&json).map(Some).map_err(|e| {
+            error!("Invalid integrity manifest of {}: {}", session_id, e);
+            StorageError::ReadFailed
+        })
+    }
+
+    /// Checks the artifacts `storage` holds for `session_id` against its manifest,
+    /// `None` when the session has no manifest
+    pub async fn verify(
+        &self,
+        storage: &(dyn Storage + Send + Sync),
+        session_id: Uuid,
+    ) -> Result<Option<IntegrityReport>, StorageError> {
+        let Some(manifest) = self.manifest(session_id)? else {
+            return Ok(None);
+        };
+        let stored = entries(&storage.get_capture_artifacts(session_id).await?);
+        Ok(Some(self.compare(&manifest, &stored)))
+    }
+
+    fn compare(&self, manifest: &IntegrityManifest, stored: &[ManifestEntry]) -> IntegrityReport {
+        let mut mismatches = Vec::new();
+        for expected in &manifest.files {
+            match stored.iter().find(|entry| entry.path == expected.path) {
+                Some(actual) if actual == expected => {}
+                actual => mismatches.push(EntryMismatch {
+                    path: expected.path.clone(),
+                    status: match actual {
+                        Some(_) => EntryStatus::Modified,
+                        None => EntryStatus::Missing,
+                    },
+                    expected_sha256: Some(expected.sha256.clone()),
+                    actual_sha256: actual.map(|entry| entry.sha256.clone()),
+                }),
+            }
+        }
+        for actual in stored {
+            if !manifest.files.iter().any(|entry| entry.path == actual.path) {
+                mismatches.push(EntryMismatch {
+                    path: actual.path.clone(),
+                    status: EntryStatus::Unexpected,
+                    expected_sha256: None,
+                    actual_sha256: Some(actual.sha256.clone()),
+                });
+            }
+        }
+
+        let signature_valid = match (&self.key, &manifest.hmac) {
+            (Some(key), Some(tag)) => Some(
+                unhex(tag)
+                    .is_some_and(|tag| hmac::verify(key, &manifest.signed_bytes(), &tag).is_ok()),
+            ),
+            // A manifest stripped of its signature is as good as forged
+            (Some(_), None) => Some(false),
+            (None, _) => None,
+        };
+        if !mismatches.is_empty() || signature_valid == Some(false) {
+            warn!(
+                "Artifacts of session {} fail verification: {} mismatching entries, signature {:?}",
+                manifest.session_id,
+                mismatches.len(),
+                signature_valid
+            );
+        }
+        IntegrityReport {
+            session_id: manifest.session_id,
+            sealed_at: manifest.sealed_at,
+            checked: manifest.files.len(),
+            intact: mismatches.is_empty() && signature_valid != Some(false),
+            signature_valid,
+            mismatches,
+        }
+    }
+
+    /// Removes the manifests of `session_ids`, best effort
+    fn remove(&self, session_ids: &[Uuid]) {
+        for id in session_ids {
+            match fs::remove_file(self.path(*id)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => warn!("Failed to remove integrity manifest of {}: {}", id, e),
+            }
+        }
+    }
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Storage decorator sealing the artifacts as they are saved, see the
+/// [module](self) documentation
+pub struct IntegrityStorage {
+    inner: Arc<dyn Storage + Send + Sync>,
+    ledger: Arc<IntegrityLedger>,
+}
+
+impl IntegrityStorage {
+    pub fn new(inner: Arc<dyn Storage + Send + Sync>, ledger: Arc<IntegrityLedger>) -> Self {
+        Self { inner, ledger }
+    }
+}
+
+#[async_trait]
+impl Storage for IntegrityStorage {
+    async fn save_session(&self, session: &Session) -> Result<(), StorageError> {
+        self.inner.save_session(session).await
+    }
+
+    async fn get_sessions(
+        &self,
+        filter: Option<SessionFilter>,
+    ) -> Result<Vec<Session>, StorageError> {
+        self.inner.get_sessions(filter).await
+    }
+
+    async fn get_session(&self, session_id: Uuid) -> Result<Session, StorageError> {
+        self.inner.get_session(session_id).await
+    }
+
+    async fn save_interaction(&self, session_id: Uuid, data: &[u8]) -> Result<(), StorageError> {
+        self.inner.save_interaction(session_id, data).await
+    }
+
+    async fn get_session_data(&self, session_id: Uuid) -> Result<Vec<u8>, StorageError> {
+        self.inner.get_session_data(session_id).await
+    }
+
+    async fn cleanup_old_sessions(&self, older_than: DateTime<Utc>) -> Result<usize, StorageError> {
+        let filter = SessionFilter {
+            end_date: Some(older_than),
+            ..Default::default()
+        };
+        let expired: Vec<Uuid> = self
+            .inner
+            .get_sessions(Some(filter))
+            .await?
+            .into_iter()
+            .filter(|s| s.end_time.unwrap_or(s.start_time) < older_than)
+            .map(|s| s.id)
+            .collect();
+        let deleted = self.inner.cleanup_old_sessions(older_than).await?;
+        self.ledger.remove(&expired);
+        Ok(deleted)
+    }
+
+    async fn delete_sessions(&self, session_ids: &[Uuid]) -> Result<usize, StorageError> {
+        let deleted = self.inner.delete_sessions(session_ids).await?;
+        self.ledger.remove(session_ids);
+        Ok(deleted)
+    }
+
+    async fn storage_size(&self) -> Result<u64, StorageError> {
+        self.inner.storage_size().await
+    }
+
+    async fn save_capture_artifacts(
+        &self,
+        artifacts: &CaptureArtifacts,
+    ) -> Result<(), StorageError> {
+        self.inner.save_capture_artifacts(artifacts).await?;
+        self.ledger.seal(artifacts).map(|_| ())
+    }
+
+    async fn get_capture_artifacts(
+        &self,
+        session_id: Uuid,
+    ) -> Result<CaptureArtifacts, StorageError> {
+        self.inner.get_capture_artifacts(session_id).await
+    }
+
+    async fn save_credentials(&self, credentials: &[Credential]) -> Result<(), StorageError> {
+        self.inner.save_credentials(credentials).await
+    }
+
+    async fn get_credentials(
+        &self,
+        filter: Option<CredentialFilter>,
+    ) -> Result<Vec<Credential>, StorageError> {
+        self.inner.get_credentials(filter).await
+    }
+
+    async fn save_commands(
+        &self,
+        session_id: Uuid,
+        commands: &[ExecutedCommand],
+    ) -> Result<(), StorageError> {
+        self.inner.save_commands(session_id, commands).await
+    }
+
+    async fn get_commands(&self, session_id: Uuid) -> Result<Vec<ExecutedCommand>, StorageError> {
+        self.inner.get_commands(session_id).await
+    }
+
+    async fn save_annotations(&self, annotations: &SessionAnnotations) -> Result<(), StorageError> {
+        self.inner.save_annotations(annotations).await
+    }
+
+    async fn get_annotations(&self, session_id: Uuid) -> Result<SessionAnnotations, StorageError> {
+        self.inner.get_annotations(session_id).await
+    }
+
+    async fn save_honeytokens(&self, tokens: &[Honeytoken]) -> Result<(), StorageError> {
+        self.inner.save_honeytokens(tokens).await
+    }
+
+    async fn get_honeytokens(&self, limit: usize) -> Result<Vec<Honeytoken>, StorageError> {
+        self.inner.get_honeytokens(limit).await
+    }
+
+    async fn delete_honeytokens(&self, token_ids: &[Uuid]) -> Result<(), StorageError> {
+        self.inner.delete_honeytokens(token_ids).await
+    }
+
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, StorageError> {
+        self.inner.search(query, limit).await
+    }
+
+    async fn flush(&self) -> Result<(), StorageError> {
+        self.inner.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_capture::types::{CapturedMessage, CarveSource, CarvedFile, UploadedFile};
+    use crate::storage::memory_storage::MemoryStorage;
+
+    fn artifacts(session_id: Uuid) -> CaptureArtifacts {
+        CaptureArtifacts {
+            session_id,
+            tcp_client_to_container: b"uname -a\n".to_vec(),
+            tcp_container_to_client: b"Linux\n".to_vec(),
+            uploaded_files: vec![UploadedFile {
+                name: "x.sh".to_string(),
+                sha256: sha256_hex(b"#!/bin/sh\n"),
+                md5: String::new(),
+                sha1: String::new(),
+                content: b"#!/bin/sh\n".to_vec(),
+                truncated: false,
+                virustotal: None,
+            }],
+            messages: vec![CapturedMessage {
+                helo: "mx.example".to_string(),
+                mail_from: "spam@example.com".to_string(),
+                rcpt_to: vec!["root@localhost".to_string()],
+                received_at: Utc::now(),
+                content: b"Subject: hi\r\n\r\nspam".to_vec(),
+                truncated: false,
+            }],
+            total_bytes: 15,
+            duration: chrono::Duration::seconds(1),
+            ..Default::default()
+        }
+    }
+
+    async fn sealed(
+        key: Option<&str>,
+    ) -> (tempfile::TempDir, Arc<MemoryStorage>, IntegrityStorage) {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = Arc::new(MemoryStorage::new());
+        let ledger = Arc::new(IntegrityLedger::new(dir.path().to_path_buf(), key));
+        let storage = IntegrityStorage::new(backend.clone(), ledger);
+        (dir, backend, storage)
+    }
+
+    #[tokio::test]
+    async fn untouched_artifacts_verify() {
+        let (_dir, _, storage) = sealed(Some("0123456789abcdef0123456789abcdef")).await;
+        let id = Uuid::new_v4();
+        storage
+            .save_capture_artifacts(&artifacts(id))
+            .await
+            .unwrap();
+
+        let manifest = storage.ledger.manifest(id).unwrap().unwrap();
+        assert_eq!(manifest.files.len(), 7);
+        assert_eq!(manifest.files[5].path, "files/uploads/0-x.sh");
+        assert!(manifest.hmac.is_some());
+
+        let report = storage.ledger.verify(&storage, id).await.unwrap().unwrap();
+        assert!(report.intact);
+        assert_eq!(report.checked, 7);
+        assert_eq!(report.signature_valid, Some(true));
+        assert!(storage
+            .ledger
+            .verify(&storage, Uuid::new_v4())
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn tampered_artifacts_are_reported() {
+        let (_dir, backend, storage) = sealed(None).await;
+        let id = Uuid::new_v4();
+        storage
+            .save_capture_artifacts(&artifacts(id))
+            .await
+            .unwrap();
+
+        // Edited behind the back of the decorator, as on disk or in the database
+        let mut tampered = artifacts(id);
+        tampered.tcp_container_to_client = b"FreeBSD\n".to_vec();
+        tampered.messages.clear();
+        tampered.carved_files.push(CarvedFile {
+            name: "a.out".to_string(),
+            source: CarveSource::Base64,
+            sha256: sha256_hex(b"\x7fELF"),
+            md5: String::new(),
+            sha1: String::new(),
+            content: b"\x7fELF".to_vec(),
+            truncated: false,
+            virustotal: None,
+        });
+        backend.save_capture_artifacts(&tampered).await.unwrap();
+
+        let report = storage.ledger.verify(&storage, id).await.unwrap().unwrap();
+        assert!(!report.intact);
+        assert_eq!(report.signature_valid, None);
+        let statuses: Vec<(&str, EntryStatus)> = report
+            .mismatches
+            .iter()
+            .map(|m| (m.path.as_str(), m.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("streams/tcp_container_to_client.bin", EntryStatus::Modified),
+                ("files/messages/0.eml", EntryStatus::Missing),
+                ("files/carved/0-a.out", EntryStatus::Unexpected),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn forged_manifests_fail_the_signature() {
+        let (dir, _, storage) = sealed(Some("0123456789abcdef0123456789abcdef")).await;
+        let id = Uuid::new_v4();
+        storage
+            .save_capture_artifacts(&artifacts(id))
+            .await
+            .unwrap();
+
+        let path = dir.path().join(format!("{}.json", id));
+        let mut manifest: IntegrityManifest =
+            serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        manifest.files[0].size += 1;
+        fs::write(&path, serde_json::to_vec(&manifest).unwrap()).unwrap();
+
+        let report = storage.ledger.verify(&storage, id).await.unwrap().unwrap();
+        assert_eq!(report.signature_valid, Some(false));
+        assert!(!report.intact);
+
+        storage.delete_sessions(&[id]).await.unwrap();
+        assert!(!path.exists());
+    }
+}
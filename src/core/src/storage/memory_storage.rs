@@ -0,0 +1,495 @@
+//! In-memory storage backend
+//!
+//! `MemoryStorage` keeps sessions and their data in maps behind a lock, so that
+//! integration tests, fuzzing and benchmarks can run the whole controller
+//! pipeline without touching the disk or a database. Nothing outlives the
+//! process, select it with `storage_backend = "memory"`.
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::{debug, info};
+use uuid::Uuid;
+
+use crate::data_capture::CaptureArtifacts;
+use crate::error_handling::types::StorageError;
+use crate::honeytokens::{self, Honeytoken};
+use crate::session::Session;
+use crate::storage::storage_trait::Storage;
+use crate::storage::types::{
+    Credential, CredentialFilter, ExecutedCommand, SessionAnnotations, SessionFilter,
+};
+
+#[derive(Default)]
+struct Store {
+    sessions: HashMap<Uuid, Session>,
+    interactions: HashMap<Uuid, Vec<u8>>,
+    artifacts: HashMap<Uuid, CaptureArtifacts>,
+    /// In the order they were saved
+    credentials: Vec<Credential>,
+    commands: HashMap<Uuid, Vec<ExecutedCommand>>,
+    annotations: HashMap<Uuid, SessionAnnotations>,
+    honeytokens: HashMap<Uuid, Honeytoken>,
+}
+
+impl Store {
+    /// Drops everything stored for `session_id`, returning whether the session existed
+    fn remove(&mut self, session_id: Uuid) -> bool {
+        self.interactions.remove(&session_id);
+        self.artifacts.remove(&session_id);
+        self.credentials.retain(|c| c.session_id != session_id);
+        self.commands.remove(&session_id);
+        self.annotations.remove(&session_id);
+        self.sessions.remove(&session_id).is_some()
+    }
+}
+
+/// Storage backend keeping everything in memory, see the [module](self) documentation
+#[derive(Default)]
+pub struct MemoryStorage {
+    store: RwLock<Store>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Bytes held by the artifacts of a session: its streams and files
+fn artifacts_size(artifacts: &CaptureArtifacts) -> u64 {
+    let streams = artifacts.tcp_client_to_container.len()
+        + artifacts.tcp_container_to_client.len()
+        + artifacts.stdio_stdin.len()
+        + artifacts.stdio_stdout.len()
+        + artifacts.stdio_stderr.len();
+    let files = artifacts
+        .uploaded_files
+        .iter()
+        .map(|file| file.content.len())
+        .chain(artifacts.carved_files.iter().map(|file| file.content.len()))
+        .chain(
+            artifacts
+                .messages
+                .iter()
+                .map(|message| message.content.len()),
+        )
+        .sum::<usize>();
+    (streams + files) as u64
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn save_session(&self, session: &Session) -> Result<(), StorageError> {
+        let mut store = self.store.write().unwrap();
+        store.sessions.insert(session.id, session.clone());
+        Ok(())
+    }
+
+    async fn get_sessions(
+        &self,
+        filter: Option<SessionFilter>,
+    ) -> Result<Vec<Session>, StorageError> {
+        let store = self.store.read().unwrap();
+        let sessions: Vec<Session> = store.sessions.values().cloned().collect();
+        let Some(f) = filter else {
+            return Ok(sessions);
+        };
+        let sessions = sessions
+            .into_iter()
+            .filter(|s| {
+                f.matches(s, |tag| {
+                    store
+                        .annotations
+                        .get(&s.id)
+                        .is_some_and(|annotations| annotations.has_tag(tag))
+                })
+            })
+            .collect();
+        Ok(f.paginate(sessions))
+    }
+
+    async fn get_session(&self, session_id: Uuid) -> Result<Session, StorageError> {
+        let store = self.store.read().unwrap();
+        store
+            .sessions
+            .get(&session_id)
+            .cloned()
+            .ok_or(StorageError::ReadFailed)
+    }
+
+    async fn save_interaction(&self, session_id: Uuid, data: &[u8]) -> Result<(), StorageError> {
+        let mut store = self.store.write().unwrap();
+        store
+            .interactions
+            .entry(session_id)
+            .or_default()
+            .extend_from_slice(data);
+        Ok(())
+    }
+
+    async fn get_session_data(&self, session_id: Uuid) -> Result<Vec<u8>, StorageError> {
+        let store = self.store.read().unwrap();
+        store
+            .interactions
+            .get(&session_id)
+            .cloned()
+            .ok_or(StorageError::ReadFailed)
+    }
+
+    async fn cleanup_old_sessions(&self, older_than: DateTime<Utc>) -> Result<usize, StorageError> {
+        let mut store = self.store.write().unwrap();
+        let expired: Vec<Uuid> = store
+            .sessions
+            .values()
+            .filter(|s| s.end_time.unwrap_or(s.start_time) < older_than)
+            .map(|s| s.id)
+            .collect();
+        for &id in &expired {
+            store.remove(id);
+        }
+        info!("Cleaned up {} expired sessions", expired.len());
+        Ok(expired.len())
+    }
+
+    async fn delete_sessions(&self, session_ids: &[Uuid]) -> Result<usize, StorageError> {
+        let mut store = self.store.write().unwrap();
+        let removed = session_ids.iter().filter(|&&id| store.remove(id)).count();
+        info!("Deleted {} sessions", removed);
+        Ok(removed)
+    }
+
+    async fn storage_size(&self) -> Result<u64, StorageError> {
+        let store = self.store.read().unwrap();
+        let interactions: u64 = store.interactions.values().map(|d| d.len() as u64).sum();
+        let artifacts: u64 = store.artifacts.values().map(artifacts_size).sum();
+        Ok(interactions + artifacts)
+    }
+
+    async fn save_capture_artifacts(
+        &self,
+        artifacts: &CaptureArtifacts,
+    ) -> Result<(), StorageError> {
+        let mut store = self.store.write().unwrap();
+        store
+            .artifacts
+            .insert(artifacts.session_id, artifacts.clone());
+        debug!("Session artifacts stored in memory");
+        Ok(())
+    }
+
+    async fn get_capture_artifacts(
+        &self,
+        session_id: Uuid,
+    ) -> Result<CaptureArtifacts, StorageError> {
+        let store = self.store.read().unwrap();
+        store
+            .artifacts
+            .get(&session_id)
+            .cloned()
+            .ok_or(StorageError::NotFound)
+    }
+
+    async fn save_credentials(&self, credentials: &[Credential]) -> Result<(), StorageError> {
+        let mut store = self.store.write().unwrap();
+        store.credentials.extend_from_slice(credentials);
+        Ok(())
+    }
+
+    async fn get_credentials(
+        &self,
+        filter: Option<CredentialFilter>,
+    ) -> Result<Vec<Credential>, StorageError> {
+        let filter = filter.unwrap_or_default();
+        let store = self.store.read().unwrap();
+        let mut credentials: Vec<Credential> = store
+            .credentials
+            .iter()
+            .filter(|c| filter.matches(c))
+            .cloned()
+            .collect();
+        // Stable, so that attempts of a session keep their order on ties
+        credentials.sort_by_key(|c| c.timestamp);
+        Ok(credentials)
+    }
+
+    async fn save_commands(
+        &self,
+        session_id: Uuid,
+        commands: &[ExecutedCommand],
+    ) -> Result<(), StorageError> {
+        let mut store = self.store.write().unwrap();
+        store.commands.insert(session_id, commands.to_vec());
+        Ok(())
+    }
+
+    async fn get_commands(&self, session_id: Uuid) -> Result<Vec<ExecutedCommand>, StorageError> {
+        let store = self.store.read().unwrap();
+        Ok(store.commands.get(&session_id).cloned().unwrap_or_default())
+    }
+
+    async fn save_annotations(&self, annotations: &SessionAnnotations) -> Result<(), StorageError> {
+        let mut store = self.store.write().unwrap();
+        store
+            .annotations
+            .insert(annotations.session_id, annotations.clone());
+        Ok(())
+    }
+
+    async fn get_annotations(&self, session_id: Uuid) -> Result<SessionAnnotations, StorageError> {
+        let store = self.store.read().unwrap();
+        Ok(store
+            .annotations
+            .get(&session_id)
+            .cloned()
+            .unwrap_or_else(|| SessionAnnotations::new(session_id)))
+    }
+
+    async fn save_honeytokens(&self, tokens: &[Honeytoken]) -> Result<(), StorageError> {
+        let mut store = self.store.write().unwrap();
+        for token in tokens {
+            store.honeytokens.insert(token.id, token.clone());
+        }
+        Ok(())
+    }
+
+    async fn get_honeytokens(&self, limit: usize) -> Result<Vec<Honeytoken>, StorageError> {
+        let store = self.store.read().unwrap();
+        Ok(honeytokens::most_recent(
+            store.honeytokens.values().cloned().collect(),
+            limit,
+        ))
+    }
+
+    async fn delete_honeytokens(&self, token_ids: &[Uuid]) -> Result<(), StorageError> {
+        let mut store = self.store.write().unwrap();
+        for id in token_ids {
+            store.honeytokens.remove(id);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session_management::SessionStatus;
+    use crate::storage::types::{SessionSort, TagSource};
+    use chrono::Duration;
+
+    fn session(service: &str, start_time: DateTime<Utc>, bytes: u64) -> Session {
+        Session {
+            id: Uuid::new_v4(),
+            service_name: service.to_string(),
+            client_addr: "203.0.113.7:40000".parse().unwrap(),
+            start_time,
+            end_time: Some(start_time + Duration::seconds(30)),
+            container_id: None,
+            bytes_transferred: bytes,
+            status: SessionStatus::Completed,
+            enrichment: None,
+            original_dst: None,
+        }
+    }
+
+    fn credential(session_id: Uuid, timestamp: DateTime<Utc>, username: &str) -> Credential {
+        Credential {
+            session_id,
+            timestamp,
+            service: "ssh".to_string(),
+            client_ip: None,
+            username: username.to_string(),
+            password: Some("123456".to_string()),
+            accepted: Some(false),
+        }
+    }
+
+    #[tokio::test]
+    async fn sessions_are_filtered_sorted_and_paginated() {
+        let storage = MemoryStorage::new();
+        let t0 = Utc::now() - Duration::hours(1);
+        let ssh = session("ssh", t0, 300);
+        let http = session("http", t0 + Duration::minutes(1), 100);
+        let late = session("ssh", t0 + Duration::minutes(2), 200);
+        for s in [&ssh, &http, &late] {
+            storage.save_session(s).await.unwrap();
+        }
+        let mut annotations = SessionAnnotations::new(late.id);
+        annotations.add_tag("miner", TagSource::Rule);
+        storage.save_annotations(&annotations).await.unwrap();
+
+        let ids = |sessions: Vec<Session>| sessions.iter().map(|s| s.id).collect::<Vec<_>>();
+        let all = storage.get_sessions(None).await.unwrap();
+        assert_eq!(all.len(), 3);
+
+        let by_bytes = SessionFilter {
+            service_name: Some("ssh".to_string()),
+            sort: Some(SessionSort::BytesTransferredDesc),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(storage.get_sessions(Some(by_bytes)).await.unwrap()),
+            [ssh.id, late.id]
+        );
+
+        let page = SessionFilter {
+            offset: Some(1),
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(storage.get_sessions(Some(page)).await.unwrap()),
+            [http.id]
+        );
+
+        let tagged = SessionFilter {
+            tag: Some("miner".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(storage.get_sessions(Some(tagged)).await.unwrap()),
+            [late.id]
+        );
+        assert_eq!(storage.get_session(http.id).await.unwrap().id, http.id);
+        assert!(storage.get_session(Uuid::new_v4()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn sessions_are_filtered_by_size_duration_and_either_criterion() {
+        let storage = MemoryStorage::new();
+        let t0 = Utc::now() - Duration::hours(1);
+        let small = session("ssh", t0, 100);
+        let mut long = session("http", t0, 5000);
+        long.end_time = Some(t0 + Duration::minutes(10));
+        long.container_id = Some("c0ffee".to_string());
+        let mut active = session("ssh", t0, 5000);
+        active.end_time = None;
+        for s in [&small, &long, &active] {
+            storage.save_session(s).await.unwrap();
+        }
+        let mut annotations = SessionAnnotations::new(small.id);
+        annotations.add_tag("scanner", TagSource::Analyst);
+        storage.save_annotations(&annotations).await.unwrap();
+
+        let ids = |sessions: Vec<Session>| {
+            let mut ids = sessions.iter().map(|s| s.id).collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        let sorted = |mut expected: Vec<Uuid>| {
+            expected.sort();
+            expected
+        };
+
+        let filter: SessionFilter =
+            serde_json::from_value(serde_json::json!({"min_bytes": 1000})).unwrap();
+        assert_eq!(
+            ids(storage.get_sessions(Some(filter)).await.unwrap()),
+            sorted(vec![long.id, active.id])
+        );
+
+        // Sessions still running have no duration
+        let filter: SessionFilter =
+            serde_json::from_value(serde_json::json!({"min_duration_secs": 60})).unwrap();
+        assert_eq!(
+            ids(storage.get_sessions(Some(filter)).await.unwrap()),
+            [long.id]
+        );
+        let filter: SessionFilter =
+            serde_json::from_value(serde_json::json!({"max_duration_secs": 30})).unwrap();
+        assert_eq!(
+            ids(storage.get_sessions(Some(filter)).await.unwrap()),
+            [small.id]
+        );
+
+        let filter: SessionFilter = serde_json::from_value(
+            serde_json::json!({"container_id": "c0ffee", "service_name": "ssh"}),
+        )
+        .unwrap();
+        assert!(storage.get_sessions(Some(filter)).await.unwrap().is_empty());
+
+        let filter: SessionFilter = serde_json::from_value(
+            serde_json::json!({"combine": "or", "container_id": "c0ffee", "tag": "scanner"}),
+        )
+        .unwrap();
+        assert_eq!(
+            ids(storage.get_sessions(Some(filter)).await.unwrap()),
+            sorted(vec![small.id, long.id])
+        );
+    }
+
+    #[tokio::test]
+    async fn matching_sessions_are_deleted() {
+        let storage = MemoryStorage::new();
+        let t0 = Utc::now() - Duration::hours(1);
+        let scans: Vec<Session> = (0..3).map(|_| session("ssh", t0, 0)).collect();
+        let shell = session("ssh", t0, 4096);
+        for s in scans.iter().chain([&shell]) {
+            storage.save_session(s).await.unwrap();
+        }
+
+        let empty = SessionFilter {
+            max_bytes: Some(0),
+            ..Default::default()
+        };
+        assert!(!empty.selects_all());
+        assert_eq!(storage.delete_matching_sessions(empty).await.unwrap(), 3);
+        let left = storage.get_sessions(None).await.unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].id, shell.id);
+        assert!(SessionFilter::default().selects_all());
+    }
+
+    #[tokio::test]
+    async fn deleting_a_session_drops_its_data() {
+        let storage = MemoryStorage::new();
+        let t0 = Utc::now() - Duration::days(10);
+        let old = session("ssh", t0, 0);
+        let kept = session("ssh", Utc::now(), 0);
+        for s in [&old, &kept] {
+            storage.save_session(s).await.unwrap();
+            storage.save_interaction(s.id, b"SSH-2.0-").await.unwrap();
+        }
+        storage.save_interaction(old.id, b"libssh").await.unwrap();
+        storage
+            .save_credentials(&[
+                credential(kept.id, Utc::now(), "admin"),
+                credential(old.id, t0, "root"),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.get_session_data(old.id).await.unwrap(),
+            b"SSH-2.0-libssh"
+        );
+        assert_eq!(storage.storage_size().await.unwrap(), 22);
+
+        let credentials = storage.get_credentials(None).await.unwrap();
+        assert_eq!(credentials[0].username, "root");
+        let admin = CredentialFilter {
+            username: Some("admin".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(storage.get_credentials(Some(admin)).await.unwrap().len(), 1);
+
+        let removed = storage
+            .cleanup_old_sessions(Utc::now() - Duration::days(1))
+            .await
+            .unwrap();
+        assert_eq!(removed, 1);
+        assert!(storage.get_session_data(old.id).await.is_err());
+        assert_eq!(storage.get_credentials(None).await.unwrap().len(), 1);
+
+        assert_eq!(
+            storage
+                .delete_sessions(&[kept.id, Uuid::new_v4()])
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(storage.get_sessions(None).await.unwrap().is_empty());
+        assert_eq!(storage.storage_size().await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,177 @@
+//! Storage decorator counting backend failures.
+//!
+//! [`MeteredStorage`] forwards every call to the wrapped backend and reports the
+//! failed ones to the `miel_storage_errors_total` metric, labeled by operation.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::data_capture::CaptureArtifacts;
+use crate::error_handling::types::StorageError;
+use crate::honeytokens::Honeytoken;
+use crate::metrics;
+use crate::session::Session;
+use crate::storage::storage_trait::Storage;
+use crate::storage::types::{
+    Credential, CredentialFilter, ExecutedCommand, SearchHit, SessionAnnotations, SessionFilter,
+};
+
+/// Wraps a [`Storage`] backend to count its errors
+pub struct MeteredStorage {
+    inner: Arc<dyn Storage + Send + Sync>,
+}
+
+impl MeteredStorage {
+    pub fn new(inner: Arc<dyn Storage + Send + Sync>) -> Self {
+        Self { inner }
+    }
+}
+
+fn metered<T>(operation: &str, result: Result<T, StorageError>) -> Result<T, StorageError> {
+    if result.is_err() {
+        metrics::global().storage_error(operation);
+    }
+    result
+}
+
+#[async_trait]
+impl Storage for MeteredStorage {
+    async fn save_session(&self, session: &Session) -> Result<(), StorageError> {
+        metered("save_session", self.inner.save_session(session).await)
+    }
+
+    async fn get_sessions(
+        &self,
+        filter: Option<SessionFilter>,
+    ) -> Result<Vec<Session>, StorageError> {
+        metered("get_sessions", self.inner.get_sessions(filter).await)
+    }
+
+    async fn get_session(&self, session_id: Uuid) -> Result<Session, StorageError> {
+        metered("get_session", self.inner.get_session(session_id).await)
+    }
+
+    async fn save_interaction(&self, session_id: Uuid, data: &[u8]) -> Result<(), StorageError> {
+        metered(
+            "save_interaction",
+            self.inner.save_interaction(session_id, data).await,
+        )
+    }
+
+    async fn get_session_data(&self, session_id: Uuid) -> Result<Vec<u8>, StorageError> {
+        metered(
+            "get_session_data",
+            self.inner.get_session_data(session_id).await,
+        )
+    }
+
+    async fn cleanup_old_sessions(&self, older_than: DateTime<Utc>) -> Result<usize, StorageError> {
+        metered(
+            "cleanup_old_sessions",
+            self.inner.cleanup_old_sessions(older_than).await,
+        )
+    }
+
+    async fn delete_sessions(&self, session_ids: &[Uuid]) -> Result<usize, StorageError> {
+        metered(
+            "delete_sessions",
+            self.inner.delete_sessions(session_ids).await,
+        )
+    }
+
+    async fn storage_size(&self) -> Result<u64, StorageError> {
+        metered("storage_size", self.inner.storage_size().await)
+    }
+
+    async fn save_capture_artifacts(
+        &self,
+        artifacts: &CaptureArtifacts,
+    ) -> Result<(), StorageError> {
+        metered(
+            "save_capture_artifacts",
+            self.inner.save_capture_artifacts(artifacts).await,
+        )
+    }
+
+    async fn get_capture_artifacts(
+        &self,
+        session_id: Uuid,
+    ) -> Result<CaptureArtifacts, StorageError> {
+        metered(
+            "get_capture_artifacts",
+            self.inner.get_capture_artifacts(session_id).await,
+        )
+    }
+
+    async fn save_credentials(&self, credentials: &[Credential]) -> Result<(), StorageError> {
+        metered(
+            "save_credentials",
+            self.inner.save_credentials(credentials).await,
+        )
+    }
+
+    async fn get_credentials(
+        &self,
+        filter: Option<CredentialFilter>,
+    ) -> Result<Vec<Credential>, StorageError> {
+        metered("get_credentials", self.inner.get_credentials(filter).await)
+    }
+
+    async fn save_commands(
+        &self,
+        session_id: Uuid,
+        commands: &[ExecutedCommand],
+    ) -> Result<(), StorageError> {
+        metered(
+            "save_commands",
+            self.inner.save_commands(session_id, commands).await,
+        )
+    }
+
+    async fn get_commands(&self, session_id: Uuid) -> Result<Vec<ExecutedCommand>, StorageError> {
+        metered("get_commands", self.inner.get_commands(session_id).await)
+    }
+
+    async fn save_annotations(&self, annotations: &SessionAnnotations) -> Result<(), StorageError> {
+        metered(
+            "save_annotations",
+            self.inner.save_annotations(annotations).await,
+        )
+    }
+
+    async fn get_annotations(&self, session_id: Uuid) -> Result<SessionAnnotations, StorageError> {
+        metered(
+            "get_annotations",
+            self.inner.get_annotations(session_id).await,
+        )
+    }
+
+    async fn save_honeytokens(&self, tokens: &[Honeytoken]) -> Result<(), StorageError> {
+        metered(
+            "save_honeytokens",
+            self.inner.save_honeytokens(tokens).await,
+        )
+    }
+
+    async fn get_honeytokens(&self, limit: usize) -> Result<Vec<Honeytoken>, StorageError> {
+        metered("get_honeytokens", self.inner.get_honeytokens(limit).await)
+    }
+
+    async fn delete_honeytokens(&self, token_ids: &[Uuid]) -> Result<(), StorageError> {
+        metered(
+            "delete_honeytokens",
+            self.inner.delete_honeytokens(token_ids).await,
+        )
+    }
+
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, StorageError> {
+        metered("search", self.inner.search(query, limit).await)
+    }
+
+    async fn flush(&self) -> Result<(), StorageError> {
+        metered("flush", self.inner.flush().await)
+    }
+}
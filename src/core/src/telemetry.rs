@@ -0,0 +1,703 @@
+//! Application log and tracing of the sessions.
+//!
+//! The network, session, container and capture modules log through `tracing`,
+//! each connection being served in a [`session_span`] whose `session_id`,
+//! `service` and `client` fields are attached to every line logged meanwhile.
+//! The stages of a session are spans of their own inside it: `accept`, `detect`,
+//! `container_create` and `proxy`, then `finalize`, which carries the
+//! `session_id` as it may run once the connection is gone.
+//!
+//! - [`SpanLogger`] keeps track of the spans and hands the events over to the `log`
+//!   logger, which `RUST_LOG` filters like the records of the other modules.
+//! - [`format`] writes both as text or as one JSON object per line.
+//! - When `logging.otlp_endpoint` is set, closed spans are exported in batches to
+//!   its `/v1/traces`, and the [metrics](crate::metrics) every
+//!   `otlp_metrics_interval_secs` to its `/v1/metrics`, over OTLP/HTTP as JSON.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{SecondsFormat, Utc};
+use log::kv::{self, Key, Value, VisitSource};
+use serde_json::{json, Map};
+use tokio::sync::{mpsc, oneshot};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Span, Subscriber};
+use tracing_core::span::Current;
+use uuid::Uuid;
+
+use crate::configuration::types::{LogFormat, LoggingConfig};
+use crate::http_client::HttpEndpoint;
+use crate::metrics::{self, MetricFamily, MetricKind};
+
+/// Spans posted to the collector at once
+const EXPORT_BATCH: usize = 512;
+/// Closed spans waiting to be exported, newer ones being dropped past it
+const EXPORT_QUEUE: usize = 4096;
+/// Longest time a closed span waits to be exported
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+/// Scope the spans and metrics are exported under
+const SCOPE: &str = "miel";
+
+/// Whether [`format`] writes JSON
+static JSON: AtomicBool = AtomicBool::new(false);
+/// Queue of the span exporter, once started
+static EXPORTER: OnceLock<mpsc::Sender<Export>> = OnceLock::new();
+
+thread_local! {
+    /// Spans entered on this thread, innermost last
+    static ENTERED: RefCell<Vec<Id>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Span of a connection of `client`, opened once it is accepted. Its `service`
+/// is [recorded](record_service) once detected, and its `session_id` once the
+/// connection is [admitted](record_session) to a session.
+pub fn session_span(client: SocketAddr) -> Span {
+    tracing::info_span!(
+        "session",
+        session_id = tracing::field::Empty,
+        service = tracing::field::Empty,
+        client = %client
+    )
+}
+
+/// Records the `service` detected in the session span being served
+pub fn record_service(service: &str) {
+    Span::current().record("service", service);
+}
+
+/// Records `session_id` in the session span being served
+pub fn record_session(session_id: &Uuid) {
+    Span::current().record("session_id", tracing::field::display(session_id));
+}
+
+/// Routes the `tracing` events to the `log` logger, to be called once it is installed
+pub fn install() {
+    if tracing::subscriber::set_global_default(SpanLogger::default()).is_err() {
+        log::warn!("A tracing subscriber is already installed, session spans are not logged");
+    }
+}
+
+/// Applies the log format of `config`, and starts exporting the spans and metrics
+/// to its `otlp_endpoint`. The exporters only start once, on the first call.
+pub fn configure(config: &LoggingConfig) {
+    JSON.store(config.format == LogFormat::Json, Ordering::Relaxed);
+
+    let Some(url) = &config.otlp_endpoint else {
+        return;
+    };
+    let endpoints = HttpEndpoint::parse(&otlp_url(url, "traces"))
+        .and_then(|traces| Ok((traces, HttpEndpoint::parse(&otlp_url(url, "metrics"))?)));
+    let (traces, metrics) = match endpoints {
+        Ok(endpoints) => endpoints,
+        Err(e) => {
+            log::warn!("Telemetry is not exported, invalid endpoint {}: {}", url, e);
+            return;
+        }
+    };
+    let (tx, rx) = mpsc::channel(EXPORT_QUEUE);
+    if EXPORTER.set(tx).is_err() {
+        return;
+    }
+    log::info!("Exporting the spans and metrics to {}", url);
+    tokio::spawn(export(traces, config.service_name.clone(), rx));
+    if config.otlp_metrics_interval_secs > 0 {
+        tokio::spawn(export_metrics(
+            metrics,
+            config.service_name.clone(),
+            Duration::from_secs(config.otlp_metrics_interval_secs),
+        ));
+    }
+}
+
+/// Url of the OTLP/HTTP endpoint receiving `signal` on the collector at `base`
+fn otlp_url(base: &str, signal: &str) -> String {
+    format!("{}/v1/{}", base.trim_end_matches('/'), signal)
+}
+
+/// Exports the spans closed so far, waiting for the collector up to `timeout`
+pub async fn flush(timeout: Duration) {
+    let Some(exporter) = EXPORTER.get() else {
+        return;
+    };
+    let (done, flushed) = oneshot::channel();
+    if exporter.send(Export::Flush(done)).await.is_ok() {
+        let _ = tokio::time::timeout(timeout, flushed).await;
+    }
+}
+
+/// Formats the records for `env_logger`, with the fields of their spans
+pub fn format(buf: &mut env_logger::fmt::Formatter, record: &log::Record) -> io::Result<()> {
+    let mut fields = KeyValues::default();
+    let _ = record.key_values().visit(&mut fields);
+
+    if JSON.load(Ordering::Relaxed) {
+        let line = json_line(
+            &Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            record,
+            fields.0,
+        );
+        writeln!(buf, "{}", line)
+    } else {
+        let timestamp = buf.timestamp();
+        let style = buf.default_level_style(record.level());
+        write!(
+            buf,
+            "[{} {style}{:<5}{style:#}] {}",
+            timestamp,
+            record.level(),
+            record.args()
+        )?;
+        for (key, value) in &fields.0 {
+            write!(buf, " {}={}", key, value)?;
+        }
+        writeln!(buf)
+    }
+}
+
+/// JSON line of `record`, its fields being keys of their own
+fn json_line(timestamp: &str, record: &log::Record, fields: Vec<(String, String)>) -> String {
+    let mut line = Map::new();
+    line.insert("timestamp".to_string(), timestamp.into());
+    line.insert("level".to_string(), record.level().as_str().into());
+    line.insert("target".to_string(), record.target().into());
+    line.insert("message".to_string(), record.args().to_string().into());
+    for (key, value) in fields {
+        line.entry(key).or_insert(value.into());
+    }
+    serde_json::Value::Object(line).to_string()
+}
+
+/// Fields of a record, in the order they were logged
+#[derive(Default)]
+struct KeyValues(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for KeyValues {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
+}
+
+/// Fields handed over to the `log` logger with an event
+struct Fields<'a>(&'a [(&'static str, String)]);
+
+impl kv::Source for Fields<'_> {
+    fn visit<'kvs>(&'kvs self, visitor: &mut dyn VisitSource<'kvs>) -> Result<(), kv::Error> {
+        for (key, value) in self.0 {
+            visitor.visit_pair(Key::from_str(key), Value::from(value.as_str()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Message and fields of an event or a span
+#[derive(Default)]
+struct FieldVisitor {
+    message: Option<String>,
+    fields: Vec<(&'static str, String)>,
+}
+
+impl FieldVisitor {
+    fn push(&mut self, field: &Field, value: String) {
+        if field.name() == "message" {
+            self.message = Some(value);
+        } else {
+            self.fields.retain(|(name, _)| *name != field.name());
+            self.fields.push((field.name(), value));
+        }
+    }
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.push(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.push(field, format!("{:?}", value));
+    }
+}
+
+/// A span not closed yet
+struct SpanData {
+    metadata: &'static Metadata<'static>,
+    parent: Option<Id>,
+    fields: Vec<(&'static str, String)>,
+    /// Handles of the span, and spans it is the parent of
+    refs: usize,
+    trace_id: u128,
+    span_id: u64,
+    start: SystemTime,
+}
+
+/// A closed span, as exported
+#[derive(Debug, Clone, PartialEq)]
+struct ClosedSpan {
+    name: &'static str,
+    trace_id: u128,
+    span_id: u64,
+    parent_span_id: Option<u64>,
+    start: SystemTime,
+    end: SystemTime,
+    fields: Vec<(&'static str, String)>,
+}
+
+enum Export {
+    Span(ClosedSpan),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Subscriber logging the `tracing` events with the fields of their spans
+#[derive(Default)]
+pub struct SpanLogger {
+    last_id: AtomicU64,
+    spans: Mutex<HashMap<Id, SpanData>>,
+}
+
+impl SpanLogger {
+    /// Fields of `span` and of its parents, outermost first
+    fn context(&self, span: Option<Id>) -> Vec<(&'static str, String)> {
+        let spans = self.spans.lock().unwrap();
+        let mut chain = Vec::new();
+        let mut next = span;
+        while let Some(span) = next.and_then(|id| spans.get(&id)) {
+            chain.push(span);
+            next = span.parent.clone();
+        }
+        chain
+            .into_iter()
+            .rev()
+            .flat_map(|span| span.fields.iter().cloned())
+            .collect()
+    }
+
+    /// Drops one reference to `id`, closing it and releasing its parent once unused
+    fn release(spans: &mut HashMap<Id, SpanData>, id: &Id) -> bool {
+        let Some(span) = spans.get_mut(id) else {
+            return false;
+        };
+        span.refs -= 1;
+        if span.refs > 0 {
+            return false;
+        }
+        let span = spans.remove(id).expect("span looked up above");
+        let parent_span_id = span
+            .parent
+            .as_ref()
+            .and_then(|p| spans.get(p))
+            .map(|p| p.span_id);
+        if let Some(exporter) = EXPORTER.get() {
+            let _ = exporter.try_send(Export::Span(ClosedSpan {
+                name: span.metadata.name(),
+                trace_id: span.trace_id,
+                span_id: span.span_id,
+                parent_span_id,
+                start: span.start,
+                end: SystemTime::now(),
+                fields: span.fields,
+            }));
+        }
+        if let Some(parent) = span.parent {
+            Self::release(spans, &parent);
+        }
+        true
+    }
+}
+
+/// Innermost span entered on this thread
+fn entered() -> Option<Id> {
+    ENTERED.with(|entered| entered.borrow().last().cloned())
+}
+
+fn log_level(level: &Level) -> log::Level {
+    match *level {
+        Level::ERROR => log::Level::Error,
+        Level::WARN => log::Level::Warn,
+        Level::INFO => log::Level::Info,
+        Level::DEBUG => log::Level::Debug,
+        Level::TRACE => log::Level::Trace,
+    }
+}
+
+impl Subscriber for SpanLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        let level = log_level(metadata.level());
+        if metadata.is_span() {
+            return level <= log::max_level();
+        }
+        log::logger().enabled(
+            &log::Metadata::builder()
+                .level(level)
+                .target(metadata.target())
+                .build(),
+        )
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+        let parent = if attrs.is_contextual() {
+            entered()
+        } else {
+            attrs.parent().cloned()
+        };
+        let id = Id::from_u64(self.last_id.fetch_add(1, Ordering::Relaxed) + 1);
+
+        let mut spans = self.spans.lock().unwrap();
+        let parent = parent.filter(|parent| spans.contains_key(parent));
+        let trace_id = match parent.as_ref().and_then(|parent| spans.get_mut(parent)) {
+            Some(parent) => {
+                parent.refs += 1;
+                parent.trace_id
+            }
+            None => Uuid::new_v4().as_u128(),
+        };
+        spans.insert(
+            id.clone(),
+            SpanData {
+                metadata: attrs.metadata(),
+                parent,
+                fields: visitor.fields,
+                refs: 1,
+                trace_id,
+                span_id: Uuid::new_v4().as_u64_pair().0,
+                start: SystemTime::now(),
+            },
+        );
+        id
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut visitor = FieldVisitor::default();
+        values.record(&mut visitor);
+        if let Some(span) = self.spans.lock().unwrap().get_mut(span) {
+            for (name, value) in visitor.fields {
+                span.fields.retain(|(field, _)| *field != name);
+                span.fields.push((name, value));
+            }
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let parent = if event.is_contextual() {
+            entered()
+        } else {
+            event.parent().cloned()
+        };
+        let mut fields = self.context(parent);
+        fields.extend(visitor.fields);
+
+        let metadata = event.metadata();
+        log::logger().log(
+            &log::Record::builder()
+                .args(format_args!("{}", visitor.message.unwrap_or_default()))
+                .level(log_level(metadata.level()))
+                .target(metadata.target())
+                .module_path(metadata.module_path())
+                .file(metadata.file())
+                .line(metadata.line())
+                .key_values(&Fields(&fields))
+                .build(),
+        );
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.clone()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(i) = entered.iter().rposition(|id| id == span) {
+                entered.remove(i);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(span) = self.spans.lock().unwrap().get_mut(span) {
+            span.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        Self::release(&mut self.spans.lock().unwrap(), &span)
+    }
+
+    fn current_span(&self) -> Current {
+        let spans = self.spans.lock().unwrap();
+        match entered().and_then(|id| spans.get(&id).map(|span| (id, span.metadata))) {
+            Some((id, metadata)) => Current::new(id, metadata),
+            None => Current::none(),
+        }
+    }
+}
+
+/// Posts the closed spans received on `spans` to `endpoint` in batches
+async fn export(endpoint: HttpEndpoint, service_name: String, mut spans: mpsc::Receiver<Export>) {
+    let mut batch = Vec::new();
+    let mut interval = tokio::time::interval(EXPORT_INTERVAL);
+    loop {
+        let flushed = tokio::select! {
+            export = spans.recv() => match export {
+                Some(Export::Span(span)) => {
+                    batch.push(span);
+                    if batch.len() < EXPORT_BATCH {
+                        continue;
+                    }
+                    None
+                }
+                Some(Export::Flush(done)) => Some(done),
+                None => break,
+            },
+            _ = interval.tick() => None,
+        };
+
+        if !batch.is_empty() {
+            let body = otlp_json(&service_name, &batch);
+            batch.clear();
+            if let Err(e) = endpoint.post_json(&body).await {
+                log::warn!("Spans could not be exported to {}: {}", endpoint.host(), e);
+            }
+        }
+        if let Some(done) = flushed {
+            let _ = done.send(());
+        }
+    }
+}
+
+/// Posts the [metrics](crate::metrics) to `endpoint` every `interval`
+async fn export_metrics(endpoint: HttpEndpoint, service_name: String, interval: Duration) {
+    let start = SystemTime::now();
+    let mut ticks = tokio::time::interval(interval);
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        let body = metrics_json(
+            &service_name,
+            &metrics::global().families(),
+            start,
+            SystemTime::now(),
+        );
+        if let Err(e) = endpoint.post_json(&body).await {
+            log::warn!(
+                "Metrics could not be exported to {}: {}",
+                endpoint.host(),
+                e
+            );
+        }
+    }
+}
+
+/// Nanoseconds since the Unix epoch, as OTLP/JSON encodes them
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+fn attribute(key: &str, value: &str) -> serde_json::Value {
+    json!({"key": key, "value": {"stringValue": value}})
+}
+
+/// OTLP/HTTP JSON request exporting the `families` counted since `start`.
+///
+/// Counters are cumulative sums named without their Prometheus `_total` suffix.
+fn metrics_json(
+    service_name: &str,
+    families: &[MetricFamily],
+    start: SystemTime,
+    now: SystemTime,
+) -> String {
+    let metrics: Vec<_> = families
+        .iter()
+        .map(|family| {
+            let points: Vec<_> = family
+                .samples
+                .iter()
+                .map(|(labels, value)| {
+                    json!({
+                        "attributes": labels.iter().map(|(key, value)| attribute(key, value)).collect::<Vec<_>>(),
+                        "startTimeUnixNano": unix_nanos(start),
+                        "timeUnixNano": unix_nanos(now),
+                        "asInt": value.to_string(),
+                    })
+                })
+                .collect();
+            match family.kind {
+                MetricKind::Counter => json!({
+                    "name": family.name.strip_suffix("_total").unwrap_or(family.name),
+                    "description": family.help,
+                    "sum": {"dataPoints": points, "aggregationTemporality": 2, "isMonotonic": true},
+                }),
+                MetricKind::Gauge => json!({
+                    "name": family.name,
+                    "description": family.help,
+                    "gauge": {"dataPoints": points},
+                }),
+            }
+        })
+        .collect();
+    json!({
+        "resourceMetrics": [{
+            "resource": {"attributes": [attribute("service.name", service_name)]},
+            "scopeMetrics": [{
+                "scope": {"name": SCOPE, "version": env!("CARGO_PKG_VERSION")},
+                "metrics": metrics,
+            }],
+        }],
+    })
+    .to_string()
+}
+
+/// OTLP/HTTP JSON request exporting `spans`
+fn otlp_json(service_name: &str, spans: &[ClosedSpan]) -> String {
+    let spans: Vec<_> = spans
+        .iter()
+        .map(|span| {
+            json!({
+                "traceId": format!("{:032x}", span.trace_id),
+                "spanId": format!("{:016x}", span.span_id),
+                "parentSpanId": span.parent_span_id.map(|id| format!("{:016x}", id)).unwrap_or_default(),
+                "name": span.name,
+                "kind": 1,
+                "startTimeUnixNano": unix_nanos(span.start),
+                "endTimeUnixNano": unix_nanos(span.end),
+                "attributes": span.fields.iter().map(|(key, value)| attribute(key, value)).collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    json!({
+        "resourceSpans": [{
+            "resource": {"attributes": [attribute("service.name", service_name)]},
+            "scopeSpans": [{
+                "scope": {"name": SCOPE, "version": env!("CARGO_PKG_VERSION")},
+                "spans": spans,
+            }],
+        }],
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn events_carry_the_fields_of_their_session() {
+        let logger = Arc::new(SpanLogger::default());
+        let session_id = Uuid::new_v4();
+        let client: SocketAddr = "203.0.113.7:40022".parse().unwrap();
+
+        tracing::subscriber::with_default(logger.clone(), || {
+            let span = session_span(client);
+            let _session = span.enter();
+            record_service("ssh");
+            record_session(&session_id);
+            let inner = tracing::info_span!("capture", stream = "stdin");
+            let _capture = inner.enter();
+
+            assert_eq!(
+                logger.context(entered()),
+                vec![
+                    ("client", client.to_string()),
+                    ("service", "ssh".to_string()),
+                    ("session_id", session_id.to_string()),
+                    ("stream", "stdin".to_string()),
+                ]
+            );
+        });
+        assert!(logger.spans.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn records_are_written_as_json() {
+        let fields = vec![("session_id".to_string(), "f00d".to_string())];
+        let line = json_line(
+            "2026-10-14T09:00:00.000Z",
+            &log::Record::builder()
+                .args(format_args!("New session established"))
+                .level(log::Level::Info)
+                .target("miel::session_management")
+                .build(),
+            fields,
+        );
+        let line: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["message"], "New session established");
+        assert_eq!(line["session_id"], "f00d");
+    }
+
+    #[test]
+    fn closed_spans_are_exported_as_otlp() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let body = otlp_json(
+            "miel",
+            &[ClosedSpan {
+                name: "session",
+                trace_id: 0xab,
+                span_id: 0x1f,
+                parent_span_id: None,
+                start,
+                end: start + Duration::from_millis(1500),
+                fields: vec![("service", "ssh".to_string())],
+            }],
+        );
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let span = &body["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["traceId"], "000000000000000000000000000000ab");
+        assert_eq!(span["spanId"], "000000000000001f");
+        assert_eq!(span["parentSpanId"], "");
+        assert_eq!(span["endTimeUnixNano"], "1700000001500000000");
+        assert_eq!(span["attributes"][0]["value"]["stringValue"], "ssh");
+    }
+
+    #[test]
+    fn metrics_are_exported_as_otlp() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let families = [
+            MetricFamily {
+                name: "miel_sessions_total",
+                help: "Sessions started, by service",
+                kind: MetricKind::Counter,
+                samples: vec![(vec![("service".to_string(), "ssh".to_string())], 3)],
+            },
+            MetricFamily {
+                name: "miel_active_sessions",
+                help: "Sessions currently active",
+                kind: MetricKind::Gauge,
+                samples: vec![(Vec::new(), 1)],
+            },
+        ];
+        let body = metrics_json("miel", &families, start, start + Duration::from_secs(60));
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let metrics = &body["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+
+        assert_eq!(metrics[0]["name"], "miel_sessions");
+        assert_eq!(metrics[0]["sum"]["isMonotonic"], true);
+        let point = &metrics[0]["sum"]["dataPoints"][0];
+        assert_eq!(point["asInt"], "3");
+        assert_eq!(point["attributes"][0]["key"], "service");
+        assert_eq!(point["timeUnixNano"], "1700000060000000000");
+        assert_eq!(metrics[1]["gauge"]["dataPoints"][0]["asInt"], "1");
+        assert_eq!(
+            otlp_url("http://localhost:4318/", "metrics"),
+            "http://localhost:4318/v1/metrics"
+        );
+    }
+}
@@ -1,6 +1,8 @@
 // Web Interface module root
+pub mod auth;
 pub mod routes;
 pub mod web_server;
+pub mod websocket;
 
 // Re-export commonly used items
 pub use routes::*;
@@ -0,0 +1,201 @@
+//! Authentication of the web API.
+//!
+//! When the [`WebUiConfig`] lists API tokens or users, the API routes require an
+//! `Authorization` header, either `Bearer <token>` or HTTP basic credentials.
+//! Rejected requests are answered `401 Unauthorized` by [`handle_rejection`], with a
+//! basic challenge so that browsers prompt for a login.
+//!
+//! The ingest route of a collector only accepts the `agent_tokens`, as bearer tokens.
+
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::debug;
+use sha2::{Digest, Sha256};
+use warp::{http::StatusCode, reply, Filter, Rejection, Reply};
+
+use super::ApiError;
+use crate::configuration::types::WebUiConfig;
+
+/// Bytes of the token digest naming an agent
+const AGENT_ID_LEN: usize = 6;
+
+/// Rejection of a request without valid credentials
+#[derive(Debug)]
+pub struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+/// Filter passing the requests allowed by `config`, and rejecting the others with [`Unauthorized`]
+pub fn with_auth(config: Arc<WebUiConfig>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |header: Option<String>| {
+            let config = config.clone();
+            async move {
+                if authorize(&config, header.as_deref()) {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(Unauthorized))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Filter extracting the agent bearing one of the agent tokens of `config`, see
+/// [`agent_of`], and rejecting the other requests with [`Unauthorized`]. Everything
+/// is rejected when no agent token is set
+pub fn with_agent_auth(
+    config: Arc<WebUiConfig>,
+) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization").and_then(move |header: Option<String>| {
+        let config = config.clone();
+        async move {
+            agent_of(&config, header.as_deref()).ok_or_else(|| warp::reject::custom(Unauthorized))
+        }
+    })
+}
+
+/// Whether the `Authorization` header value is one of the agent tokens of `config`
+pub fn authorize_agent(config: &WebUiConfig, header: Option<&str>) -> bool {
+    agent_of(config, header).is_some()
+}
+
+/// Name of the agent whose token is the `Authorization` header value, `None` when
+/// it is not one of the agent tokens of `config`
+///
+/// Agents are named `agent:` followed by the start of the SHA-256 of their token,
+/// which does not disclose the token and does not depend on the order of the list.
+pub fn agent_of(config: &WebUiConfig, header: Option<&str>) -> Option<String> {
+    let (scheme, token) = header.and_then(|h| h.trim().split_once(' '))?;
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+    let token = config
+        .agent_tokens
+        .iter()
+        .find(|agent| constant_time_eq(agent.as_bytes(), token.trim().as_bytes()))?;
+    let digest: String = Sha256::digest(token.as_bytes())[..AGENT_ID_LEN]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Some(format!("agent:{}", digest))
+}
+
+/// Whether the `Authorization` header value grants access under `config`
+pub fn authorize(config: &WebUiConfig, header: Option<&str>) -> bool {
+    if !config.requires_auth() {
+        return true;
+    }
+    let Some((scheme, credentials)) = header.and_then(|h| h.trim().split_once(' ')) else {
+        return false;
+    };
+    let credentials = credentials.trim();
+
+    if scheme.eq_ignore_ascii_case("bearer") {
+        return config
+            .api_tokens
+            .iter()
+            .any(|token| constant_time_eq(token.as_bytes(), credentials.as_bytes()));
+    }
+    if scheme.eq_ignore_ascii_case("basic") {
+        let Some(decoded) = STANDARD
+            .decode(credentials)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+        else {
+            debug!("Malformed basic credentials on the web API");
+            return false;
+        };
+        let Some((username, password)) = decoded.split_once(':') else {
+            return false;
+        };
+        return config.users.iter().any(|user| {
+            // Both compared to keep the time independent of which one is wrong
+            let username_ok = constant_time_eq(user.username.as_bytes(), username.as_bytes());
+            let password_ok = constant_time_eq(user.password.as_bytes(), password.as_bytes());
+            username_ok & password_ok
+        });
+    }
+    false
+}
+
+/// Answers [`Unauthorized`] rejections, leaving the others to warp
+pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Rejection> {
+    if err.find::<Unauthorized>().is_none() {
+        return Err(err);
+    }
+    Ok(reply::with_header(
+        reply::with_status(
+            reply::json(&ApiError {
+                message: "Authentication required".to_string(),
+            }),
+            StatusCode::UNAUTHORIZED,
+        ),
+        "WWW-Authenticate",
+        "Basic realm=\"miel\"",
+    ))
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::types::WebUiUser;
+
+    fn config() -> WebUiConfig {
+        WebUiConfig {
+            api_tokens: vec!["s3cr3t".to_string()],
+            users: vec![WebUiUser {
+                username: "analyst".to_string(),
+                password: "hunter2".to_string(),
+            }],
+            tls: None,
+            agent_tokens: vec![],
+        }
+    }
+
+    #[test]
+    fn tokens_and_users_are_checked() {
+        let config = config();
+        assert!(authorize(&config, Some("Bearer s3cr3t")));
+        assert!(authorize(&config, Some("bearer s3cr3t")));
+        assert!(!authorize(&config, Some("Bearer s3cr3")));
+
+        let basic = format!("Basic {}", STANDARD.encode("analyst:hunter2"));
+        assert!(authorize(&config, Some(&basic)));
+        let wrong = format!("Basic {}", STANDARD.encode("analyst:hunter3"));
+        assert!(!authorize(&config, Some(&wrong)));
+        assert!(!authorize(&config, Some("Basic not-base64!")));
+
+        assert!(!authorize(&config, None));
+        assert!(!authorize(&config, Some("s3cr3t")));
+    }
+
+    #[test]
+    fn agents_only_use_their_tokens() {
+        let mut config = config();
+        assert!(!authorize_agent(&config, Some("Bearer s3cr3t")));
+
+        config.agent_tokens = vec!["agent-1".to_string()];
+        assert!(authorize_agent(&config, Some("Bearer agent-1")));
+        assert!(!authorize_agent(&config, Some("Bearer agent-2")));
+        assert!(!authorize_agent(&config, None));
+        assert!(!authorize(&config, Some("Bearer agent-1")));
+
+        config.agent_tokens.insert(0, "agent-2".to_string());
+        let agent = agent_of(&config, Some("Bearer agent-1")).unwrap();
+        assert_eq!(agent, agent_of(&config, Some("bearer  agent-1 ")).unwrap());
+        assert_ne!(Some(agent), agent_of(&config, Some("Bearer agent-2")));
+    }
+
+    #[test]
+    fn open_without_credentials_configured() {
+        assert!(authorize(&WebUiConfig::default(), None));
+        assert!(authorize(&WebUiConfig::default(), Some("Bearer anything")));
+    }
+}
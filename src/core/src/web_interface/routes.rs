@@ -1,10 +1,35 @@
-use crate::storage::types::SessionFilter;
+use crate::storage::types::{AnnotationsUpdate, CredentialFilter, SearchQuery, SessionFilter};
+use chrono::Utc;
+use futures_util::stream::{self, StreamExt};
+use log::{debug, error};
 use rust_embed::RustEmbed;
+use std::convert::Infallible;
+use std::path::Path;
 use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tower_service::Service;
 use uuid::Uuid;
+use warp::http::HeaderMap;
 use warp::{http::StatusCode, reply, Filter, Rejection, Reply};
 
+use super::auth::with_agent_auth;
+use super::websocket;
 use super::ApiError;
+use crate::analytics::campaigns::CampaignBoard;
+use crate::configuration::types::WebUiConfig;
+use crate::configuration::ServiceConfig;
+use crate::controller::service_api::ServiceControl;
+use crate::enrichment::Enricher;
+use crate::error_handling::types::{ControllerError, IngestError, SessionError, StorageError};
+use crate::events;
+use crate::lifecycle::LifecycleSender;
+use crate::metrics;
+use crate::session_management::session_control::SessionControl;
+use crate::storage::cowrie;
+use crate::storage::deduplicating_storage::DedupStore;
+use crate::storage::forwarding_storage::Forwarded;
+use crate::storage::integrity::IntegrityLedger;
+use crate::storage::search;
 use crate::storage::storage_trait::Storage;
 use mime_guess;
 
@@ -53,7 +78,78 @@ pub fn dashboard_route() -> impl Filter<Extract = impl Reply, Error = Rejection>
     redirect_root.or(static_files)
 }
 
+/// GET /metrics
+pub fn metrics_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("metrics").and(warp::get()).map(|| {
+        reply::with_header(
+            metrics::global().render(),
+            "Content-Type",
+            metrics::CONTENT_TYPE,
+        )
+    })
+}
+
+/// GET /live
+///
+/// Server-sent events stream of the structured events, starting with the recent ones.
+/// Each message is named `event` and holds the JSON form of one [`events::Event`]
+pub fn live_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "live").and(warp::get()).map(|| {
+        let (recent, receiver) = events::subscribe();
+        let live = stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(line) => return Some((line, receiver)),
+                    // A slow client misses events rather than holding the others back
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+        let events = stream::iter(recent)
+            .chain(live)
+            .map(|line| Ok::<_, Infallible>(warp::sse::Event::default().event("event").data(line)));
+        warp::sse::reply(warp::sse::keep_alive().stream(events))
+    })
+}
+
+/// GET /events
+///
+/// Server-sent events stream of the session lifecycle, from the time of the request.
+/// Messages are named `session_created`, `session_ended` or `capture_finalized` and
+/// hold the JSON form of a [`crate::lifecycle::SessionLifecycle`]
+pub fn lifecycle_route(
+    lifecycle: LifecycleSender,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "events").and(warp::get()).map(move || {
+        let events = stream::unfold(lifecycle.subscribe(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    Err(RecvError::Lagged(missed)) => {
+                        debug!("Lifecycle subscriber lagging, {} events missed", missed)
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+        .filter_map(|event| async move {
+            warp::sse::Event::default()
+                .event(event.name())
+                .json_data(&event)
+                .ok()
+                .map(Ok::<_, Infallible>)
+        });
+        warp::sse::reply(warp::sse::keep_alive().stream(events))
+    })
+}
+
 /// GET /sessions
+///
+/// Query parameters are those of [`SessionFilter`], e.g.
+/// `?service=ssh&status=Active&tag=miner&since=2025-01-01T00:00:00Z&sort=-start_time&limit=50&offset=100`,
+/// or `?combine=or&country=NL&min_bytes=100000&min_duration_secs=600` for the
+/// sessions matching any of the criteria
 pub fn list_sessions_route(
     storage: Arc<dyn Storage + Send + Sync>,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
@@ -64,7 +160,7 @@ pub fn list_sessions_route(
         .and_then(move |filter: SessionFilter| {
             let storage = storage.clone();
             async move {
-                match storage.get_sessions(Some(filter)) {
+                match storage.get_sessions(Some(filter)).await {
                     Ok(list) => {
                         // Directly return storage sessions
                         Ok::<_, Rejection>(warp::reply::with_status(
@@ -83,6 +179,193 @@ pub fn list_sessions_route(
         })
 }
 
+/// DELETE /sessions
+///
+/// Deletes the stored sessions matching the [`SessionFilter`] of the query string,
+/// e.g. `?tag=scanner&max_bytes=0`, and answers how many were deleted. A filter
+/// setting no criterion is refused rather than deleting every session.
+pub fn delete_sessions_route(
+    storage: Arc<dyn Storage + Send + Sync>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "sessions")
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(warp::query::<SessionFilter>())
+        .and_then(move |filter: SessionFilter| {
+            let storage = storage.clone();
+            async move {
+                if filter.selects_all() {
+                    let res = reply::with_status(
+                        reply::json(&ApiError {
+                            message: "Set a criterion to select the sessions to delete".to_string(),
+                        }),
+                        StatusCode::BAD_REQUEST,
+                    )
+                    .into_response();
+                    return Ok::<_, Rejection>(res);
+                }
+                let res = match storage.delete_matching_sessions(filter).await {
+                    Ok(deleted) => reply::with_status(
+                        reply::json(&serde_json::json!({ "deleted": deleted })),
+                        StatusCode::OK,
+                    )
+                    .into_response(),
+                    Err(_) => reply::with_status(
+                        reply::json(&ApiError {
+                            message: "Failed to delete sessions".to_string(),
+                        }),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    )
+                    .into_response(),
+                };
+                Ok::<_, Rejection>(res)
+            }
+        })
+}
+
+/// POST /sessions/enrich
+///
+/// Looks the client IPs of the ended sessions matching the [`SessionFilter`] of the
+/// query string up again, see [`Enricher::reenrich`], and answers how many sessions
+/// changed.
+pub fn enrich_sessions_route(
+    storage: Arc<dyn Storage + Send + Sync>,
+    enricher: Option<Arc<Enricher>>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "sessions" / "enrich")
+        .and(warp::post())
+        .and(warp::query::<SessionFilter>())
+        .and_then(move |filter: SessionFilter| {
+            let storage = storage.clone();
+            let enricher = enricher.clone();
+            async move {
+                let Some(enricher) = enricher else {
+                    let res = reply::with_status(
+                        reply::json(&ApiError {
+                            message: "Enrichment is not running".to_string(),
+                        }),
+                        StatusCode::NOT_FOUND,
+                    )
+                    .into_response();
+                    return Ok::<_, Rejection>(res);
+                };
+                let res = match enricher.reenrich(storage.as_ref(), filter).await {
+                    Ok(enriched) => reply::with_status(
+                        reply::json(&serde_json::json!({ "enriched": enriched })),
+                        StatusCode::OK,
+                    )
+                    .into_response(),
+                    Err(_) => reply::with_status(
+                        reply::json(&ApiError {
+                            message: "Failed to enrich sessions".to_string(),
+                        }),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    )
+                    .into_response(),
+                };
+                Ok::<_, Rejection>(res)
+            }
+        })
+}
+
+/// Sessions exported per request of [`export_sessions_route`]
+const EXPORT_PAGE_SIZE: u64 = 500;
+
+/// GET /sessions/export
+///
+/// Evidence bundles of the sessions matching the [`SessionFilter`] of the query
+/// string in a single tar.gz, see [`Archive`](crate::storage::export::Archive).
+///
+/// At most `EXPORT_PAGE_SIZE` sessions, or `limit` when lower but at least one,
+/// are exported per request. A full page carries the `offset` of the next one in an
+/// `X-Next-Offset` header. The archive is spooled to a temporary file and
+/// streamed from it.
+pub fn export_sessions_route(
+    storage: Arc<dyn Storage + Send + Sync>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "sessions" / "export")
+        .and(warp::get())
+        .and(warp::query::<SessionFilter>())
+        .and_then(move |mut filter: SessionFilter| {
+            let storage = storage.clone();
+            async move {
+                let (limit, next_offset) = export_page(&mut filter);
+
+                let exported = match tempfile::NamedTempFile::new() {
+                    Ok(mut spool) => storage
+                        .export_sessions(Some(filter), spool.as_file_mut())
+                        .await
+                        .map(|exported| (spool, exported)),
+                    Err(e) => {
+                        error!("Cannot create the export spool: {}", e);
+                        Err(StorageError::WriteFailed)
+                    }
+                };
+                let (spool, exported) = match exported {
+                    Ok(exported) => exported,
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Failed to export sessions".to_string(),
+                            }),
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                        )
+                        .into_response();
+                        return Ok::<_, Rejection>(res);
+                    }
+                };
+
+                let disposition = format!(
+                    "attachment; filename=\"miel-sessions-{}.tar.gz\"",
+                    Utc::now().format("%Y%m%dT%H%M%SZ")
+                );
+                let body = file_stream(spool.path()).await;
+                // Opened by warp, the spool keeps being read once its path is removed
+                drop(spool);
+                let mut res = reply::with_header(
+                    reply::with_header(body, "Content-Type", "application/gzip"),
+                    "Content-Disposition",
+                    disposition,
+                )
+                .into_response();
+                if exported as u64 == limit {
+                    res.headers_mut()
+                        .insert("X-Next-Offset", next_offset.into());
+                }
+                Ok::<_, Rejection>(res)
+            }
+        })
+}
+
+/// Clamps the `limit` of `filter` to a page of export, returning it with the
+/// offset of the next page
+fn export_page(filter: &mut SessionFilter) -> (u64, u64) {
+    let limit = filter
+        .limit
+        .unwrap_or(EXPORT_PAGE_SIZE)
+        .clamp(1, EXPORT_PAGE_SIZE);
+    filter.limit = Some(limit);
+    (limit, filter.offset.unwrap_or(0) + limit)
+}
+
+/// Response streaming the file at `path`
+///
+/// Warp only streams the bodies of the files it serves, so the file is served
+/// through its file filter, on a task of its own since filters do not nest.
+async fn file_stream(path: &Path) -> reply::Response {
+    let mut service = warp::service(warp::fs::file(path.to_path_buf()));
+    let served =
+        tokio::spawn(async move { service.call(warp::http::Request::new(String::new())).await });
+    match served.await {
+        Ok(Ok(res)) => res,
+        Ok(Err(never)) => match never {},
+        Err(e) => {
+            error!("Failed to serve {}: {}", path.display(), e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
 /// GET /sessions/:id/data
 pub fn get_session_data_route(
     storage: Arc<dyn Storage + Send + Sync>,
@@ -106,7 +389,7 @@ pub fn get_session_data_route(
                     }
                 };
 
-                match storage.get_session_data(id) {
+                match storage.get_session_data(id).await {
                     Ok(bytes) => {
                         let res = reply::with_status(
                             reply::with_header(bytes, "Content-Type", "application/octet-stream"),
@@ -151,7 +434,7 @@ pub fn download_artifacts_route(
                     }
                 };
 
-                match storage.get_capture_artifacts(id) {
+                match storage.get_capture_artifacts(id).await {
                     Ok(artifacts) => Ok::<_, Rejection>(reply::with_status(
                         reply::json(&artifacts),
                         StatusCode::OK,
@@ -166,3 +449,1107 @@ pub fn download_artifacts_route(
             }
         })
 }
+
+/// GET /sessions/:id/pcap
+pub fn download_pcap_route(
+    storage: Arc<dyn Storage + Send + Sync>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "sessions" / String / "pcap")
+        .and(warp::get())
+        .and_then(move |id_str: String| {
+            let storage = storage.clone();
+            async move {
+                let id = match Uuid::parse_str(&id_str) {
+                    Ok(u) => u,
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Invalid session id".to_string(),
+                            }),
+                            StatusCode::BAD_REQUEST,
+                        )
+                        .into_response();
+                        return Ok::<_, Rejection>(res);
+                    }
+                };
+
+                match storage.get_session_pcap(id).await {
+                    Ok(bytes) => {
+                        let disposition = format!("attachment; filename=\"{}.pcap\"", id);
+                        let res = reply::with_status(
+                            reply::with_header(
+                                reply::with_header(
+                                    bytes,
+                                    "Content-Type",
+                                    "application/vnd.tcpdump.pcap",
+                                ),
+                                "Content-Disposition",
+                                disposition,
+                            ),
+                            StatusCode::OK,
+                        )
+                        .into_response();
+                        Ok::<_, Rejection>(res)
+                    }
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Packet capture not available".to_string(),
+                            }),
+                            StatusCode::NOT_FOUND,
+                        )
+                        .into_response();
+                        Ok::<_, Rejection>(res)
+                    }
+                }
+            }
+        })
+}
+
+/// GET /sessions/:id/export
+pub fn export_session_route(
+    storage: Arc<dyn Storage + Send + Sync>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "sessions" / String / "export")
+        .and(warp::get())
+        .and_then(move |id_str: String| {
+            let storage = storage.clone();
+            async move {
+                let id = match Uuid::parse_str(&id_str) {
+                    Ok(u) => u,
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Invalid session id".to_string(),
+                            }),
+                            StatusCode::BAD_REQUEST,
+                        )
+                        .into_response();
+                        return Ok::<_, Rejection>(res);
+                    }
+                };
+
+                match storage.export_session(id).await {
+                    Ok(bytes) => {
+                        let disposition =
+                            format!("attachment; filename=\"miel-session-{}.tar.gz\"", id);
+                        let res = reply::with_status(
+                            reply::with_header(
+                                reply::with_header(bytes, "Content-Type", "application/gzip"),
+                                "Content-Disposition",
+                                disposition,
+                            ),
+                            StatusCode::OK,
+                        )
+                        .into_response();
+                        Ok::<_, Rejection>(res)
+                    }
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Session not found".to_string(),
+                            }),
+                            StatusCode::NOT_FOUND,
+                        )
+                        .into_response();
+                        Ok::<_, Rejection>(res)
+                    }
+                }
+            }
+        })
+}
+
+/// GET /sessions/:id/cowrie
+///
+/// The session as Cowrie JSON events, one per line, see [`cowrie::to_events`].
+pub fn cowrie_route(
+    storage: Arc<dyn Storage + Send + Sync>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "sessions" / String / "cowrie")
+        .and(warp::get())
+        .and_then(move |id_str: String| {
+            let storage = storage.clone();
+            async move {
+                let id = match Uuid::parse_str(&id_str) {
+                    Ok(u) => u,
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Invalid session id".to_string(),
+                            }),
+                            StatusCode::BAD_REQUEST,
+                        )
+                        .into_response();
+                        return Ok::<_, Rejection>(res);
+                    }
+                };
+
+                match storage.get_cowrie_events(id, &cowrie::sensor_name()).await {
+                    Ok(events) => {
+                        let res = reply::with_status(
+                            reply::with_header(
+                                cowrie::to_json_lines(&events),
+                                "Content-Type",
+                                "application/x-ndjson",
+                            ),
+                            StatusCode::OK,
+                        )
+                        .into_response();
+                        Ok::<_, Rejection>(res)
+                    }
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Session not found".to_string(),
+                            }),
+                            StatusCode::NOT_FOUND,
+                        )
+                        .into_response();
+                        Ok::<_, Rejection>(res)
+                    }
+                }
+            }
+        })
+}
+
+/// GET /stix
+///
+/// STIX 2.1 bundle of the indicators of the sessions, see
+/// [`Indicators`](crate::storage::stix::Indicators).
+/// Query parameters are those of [`SessionFilter`], e.g. `?since=2025-01-01T00:00:00Z`
+pub fn stix_route(
+    storage: Arc<dyn Storage + Send + Sync>,
+    identity: String,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "stix")
+        .and(warp::get())
+        .and(warp::query::<SessionFilter>())
+        .and_then(move |filter: SessionFilter| {
+            let storage = storage.clone();
+            let identity = identity.clone();
+            async move {
+                match storage.get_indicators(Some(filter)).await {
+                    Ok(indicators) => Ok::<_, Rejection>(warp::reply::with_status(
+                        warp::reply::json(&indicators.to_bundle(&identity, Utc::now())),
+                        StatusCode::OK,
+                    )),
+                    Err(_) => Ok::<_, Rejection>(warp::reply::with_status(
+                        warp::reply::json(&ApiError {
+                            message: "Failed to load sessions".to_string(),
+                        }),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    )),
+                }
+            }
+        })
+}
+
+/// GET /sessions/:id/verify
+pub fn verify_session_route(
+    storage: Arc<dyn Storage + Send + Sync>,
+    integrity: Option<Arc<IntegrityLedger>>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "sessions" / String / "verify")
+        .and(warp::get())
+        .and_then(move |id_str: String| {
+            let storage = storage.clone();
+            let integrity = integrity.clone();
+            async move {
+                let id = match Uuid::parse_str(&id_str) {
+                    Ok(u) => u,
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Invalid session id".to_string(),
+                            }),
+                            StatusCode::BAD_REQUEST,
+                        )
+                        .into_response();
+                        return Ok::<_, Rejection>(res);
+                    }
+                };
+                let Some(integrity) = integrity else {
+                    let res = reply::with_status(
+                        reply::json(&ApiError {
+                            message: "Integrity manifests are disabled".to_string(),
+                        }),
+                        StatusCode::NOT_FOUND,
+                    )
+                    .into_response();
+                    return Ok::<_, Rejection>(res);
+                };
+
+                match integrity.verify(storage.as_ref(), id).await {
+                    Ok(Some(report)) => {
+                        let res = reply::with_status(reply::json(&report), StatusCode::OK)
+                            .into_response();
+                        Ok::<_, Rejection>(res)
+                    }
+                    Ok(None) | Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Session not found or never sealed".to_string(),
+                            }),
+                            StatusCode::NOT_FOUND,
+                        )
+                        .into_response();
+                        Ok::<_, Rejection>(res)
+                    }
+                }
+            }
+        })
+}
+
+/// GET /campaigns
+///
+/// Campaigns found by the last clustering, most recently active first, see
+/// [`CampaignReport`](crate::analytics::campaigns::CampaignReport).
+pub fn campaigns_route(
+    campaigns: Option<Arc<CampaignBoard>>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "campaigns")
+        .and(warp::get())
+        .and_then(move || {
+            let campaigns = campaigns.clone();
+            async move {
+                let res = match campaigns {
+                    Some(campaigns) => {
+                        reply::with_status(reply::json(&campaigns.report()), StatusCode::OK)
+                            .into_response()
+                    }
+                    None => reply::with_status(
+                        reply::json(&ApiError {
+                            message: "Campaign clustering is not running".to_string(),
+                        }),
+                        StatusCode::NOT_FOUND,
+                    )
+                    .into_response(),
+                };
+                Ok::<_, Rejection>(res)
+            }
+        })
+}
+
+/// GET /sessions/:id/dedup
+///
+/// Blobs the artifacts of the session share and the session it duplicates, see
+/// [`DedupManifest`](crate::storage::deduplicating_storage::DedupManifest).
+pub fn dedup_route(
+    dedup: Option<Arc<DedupStore>>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "sessions" / String / "dedup")
+        .and(warp::get())
+        .and_then(move |id_str: String| {
+            let dedup = dedup.clone();
+            async move {
+                let id = match Uuid::parse_str(&id_str) {
+                    Ok(u) => u,
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Invalid session id".to_string(),
+                            }),
+                            StatusCode::BAD_REQUEST,
+                        )
+                        .into_response();
+                        return Ok::<_, Rejection>(res);
+                    }
+                };
+                let Some(dedup) = dedup else {
+                    let res = reply::with_status(
+                        reply::json(&ApiError {
+                            message: "Deduplication is disabled".to_string(),
+                        }),
+                        StatusCode::NOT_FOUND,
+                    )
+                    .into_response();
+                    return Ok::<_, Rejection>(res);
+                };
+
+                match dedup.manifest(id) {
+                    Ok(Some(manifest)) => {
+                        let res = reply::with_status(reply::json(&manifest), StatusCode::OK)
+                            .into_response();
+                        Ok::<_, Rejection>(res)
+                    }
+                    Ok(None) | Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Session not found or never deduplicated".to_string(),
+                            }),
+                            StatusCode::NOT_FOUND,
+                        )
+                        .into_response();
+                        Ok::<_, Rejection>(res)
+                    }
+                }
+            }
+        })
+}
+
+/// GET /sessions/active
+///
+/// The sessions live right now, which the storage only holds once they ended.
+pub fn active_sessions_route(
+    sessions: Option<SessionControl>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "sessions" / "active")
+        .and(warp::get())
+        .and_then(move || {
+            let sessions = sessions.clone();
+            async move {
+                let result = match &sessions {
+                    Some(sessions) => sessions.list_active_sessions().await,
+                    None => Err(SessionError::RegistryStopped),
+                };
+                let res = match result {
+                    Ok(active) => {
+                        reply::with_status(reply::json(&active), StatusCode::OK).into_response()
+                    }
+                    Err(e) => reply::with_status(
+                        reply::json(&ApiError {
+                            message: e.to_string(),
+                        }),
+                        StatusCode::SERVICE_UNAVAILABLE,
+                    )
+                    .into_response(),
+                };
+                Ok::<_, Rejection>(res)
+            }
+        })
+}
+
+/// GET /containers
+///
+/// The counters of the container manager and the running containers, with the
+/// resources they use.
+pub fn containers_route(
+    sessions: Option<SessionControl>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "containers")
+        .and(warp::get())
+        .and_then(move || {
+            let sessions = sessions.clone();
+            async move {
+                let result = match &sessions {
+                    Some(sessions) => sessions.container_report().await,
+                    None => Err(SessionError::RegistryStopped),
+                };
+                let res = match result {
+                    Ok(report) => {
+                        reply::with_status(reply::json(&report), StatusCode::OK).into_response()
+                    }
+                    Err(e) => reply::with_status(
+                        reply::json(&ApiError {
+                            message: e.to_string(),
+                        }),
+                        StatusCode::SERVICE_UNAVAILABLE,
+                    )
+                    .into_response(),
+                };
+                Ok::<_, Rejection>(res)
+            }
+        })
+}
+
+/// DELETE /sessions/:id
+///
+/// Ends an active session: its connections are closed, its capture finalized and
+/// its container removed.
+pub fn end_session_route(
+    sessions: Option<SessionControl>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "sessions" / String)
+        .and(warp::delete())
+        .and_then(move |id_str: String| {
+            let sessions = sessions.clone();
+            async move {
+                let id = match Uuid::parse_str(&id_str) {
+                    Ok(u) => u,
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Invalid session id".to_string(),
+                            }),
+                            StatusCode::BAD_REQUEST,
+                        )
+                        .into_response();
+                        return Ok::<_, Rejection>(res);
+                    }
+                };
+                let result = match &sessions {
+                    Some(sessions) => sessions.end_session(&id).await,
+                    None => Err(SessionError::RegistryStopped),
+                };
+                let res = match result {
+                    Ok(()) => reply::with_status(reply(), StatusCode::NO_CONTENT).into_response(),
+                    Err(e) => {
+                        let status = match e {
+                            SessionError::NotFound => StatusCode::NOT_FOUND,
+                            SessionError::RegistryStopped => StatusCode::SERVICE_UNAVAILABLE,
+                            _ => StatusCode::INTERNAL_SERVER_ERROR,
+                        };
+                        reply::with_status(
+                            reply::json(&ApiError {
+                                message: e.to_string(),
+                            }),
+                            status,
+                        )
+                        .into_response()
+                    }
+                };
+                Ok::<_, Rejection>(res)
+            }
+        })
+}
+
+/// GET /sessions/:id/live
+///
+/// WebSocket streaming what an active session records from the time of the request,
+/// its network chunks and terminal output, one JSON [`LiveChunk`] per text message.
+/// The socket is closed once the session ends.
+///
+/// [`LiveChunk`]: crate::data_capture::LiveChunk
+pub fn live_session_route(
+    sessions: Option<SessionControl>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "sessions" / String / "live")
+        .and(warp::get())
+        .and(warp::header::headers_cloned())
+        .and(warp::ext::optional::<hyper::upgrade::OnUpgrade>())
+        .and_then(move |id_str: String, headers: HeaderMap, on_upgrade| {
+            let sessions = sessions.clone();
+            async move {
+                let error = |message: String, status| {
+                    reply::with_status(reply::json(&ApiError { message }), status).into_response()
+                };
+                let Ok(id) = Uuid::parse_str(&id_str) else {
+                    let res = error("Invalid session id".to_string(), StatusCode::BAD_REQUEST);
+                    return Ok::<_, Rejection>(res);
+                };
+                let result = match &sessions {
+                    Some(sessions) => sessions.live_feed(&id).await,
+                    None => Err(SessionError::RegistryStopped),
+                };
+                let chunks = match result {
+                    Ok(Some(feed)) => feed.subscribe(),
+                    Ok(None) => {
+                        let res = error(SessionError::NotFound.to_string(), StatusCode::NOT_FOUND);
+                        return Ok(res);
+                    }
+                    Err(e) => return Ok(error(e.to_string(), StatusCode::SERVICE_UNAVAILABLE)),
+                };
+                let messages = stream::unfold(chunks, |mut chunks| async move {
+                    loop {
+                        match chunks.recv().await {
+                            Ok(chunk) => return Some((chunk, chunks)),
+                            Err(RecvError::Lagged(missed)) => {
+                                debug!("Live capture watcher lagging, {} chunks missed", missed)
+                            }
+                            Err(RecvError::Closed) => return None,
+                        }
+                    }
+                })
+                .filter_map(|chunk| async move { serde_json::to_string(&chunk).ok() });
+                let res = websocket::accept(&headers, on_upgrade, move |socket| async move {
+                    if let Err(e) = websocket::send_all(socket, messages).await {
+                        debug!("Live capture of session {} stopped: {}", id, e);
+                    }
+                });
+                Ok(res.unwrap_or_else(|| {
+                    error(
+                        "Expected a WebSocket handshake".to_string(),
+                        StatusCode::BAD_REQUEST,
+                    )
+                }))
+            }
+        })
+}
+
+/// GET /sessions/:id/replay
+pub fn replay_route(
+    storage: Arc<dyn Storage + Send + Sync>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "sessions" / String / "replay")
+        .and(warp::get())
+        .and_then(move |id_str: String| {
+            let storage = storage.clone();
+            async move {
+                let id = match Uuid::parse_str(&id_str) {
+                    Ok(u) => u,
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Invalid session id".to_string(),
+                            }),
+                            StatusCode::BAD_REQUEST,
+                        )
+                        .into_response();
+                        return Ok::<_, Rejection>(res);
+                    }
+                };
+
+                match storage.get_session_replay(id).await {
+                    Ok(bytes) => {
+                        let disposition = format!("attachment; filename=\"{}.cast\"", id);
+                        let res = reply::with_status(
+                            reply::with_header(
+                                reply::with_header(
+                                    bytes,
+                                    "Content-Type",
+                                    "application/x-asciicast",
+                                ),
+                                "Content-Disposition",
+                                disposition,
+                            ),
+                            StatusCode::OK,
+                        )
+                        .into_response();
+                        Ok::<_, Rejection>(res)
+                    }
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Terminal replay not available".to_string(),
+                            }),
+                            StatusCode::NOT_FOUND,
+                        )
+                        .into_response();
+                        Ok::<_, Rejection>(res)
+                    }
+                }
+            }
+        })
+}
+
+/// GET /sessions/:id/http
+pub fn http_exchanges_route(
+    storage: Arc<dyn Storage + Send + Sync>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "sessions" / String / "http")
+        .and(warp::get())
+        .and_then(move |id_str: String| {
+            let storage = storage.clone();
+            async move {
+                let id = match Uuid::parse_str(&id_str) {
+                    Ok(u) => u,
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Invalid session id".to_string(),
+                            }),
+                            StatusCode::BAD_REQUEST,
+                        )
+                        .into_response();
+                        return Ok::<_, Rejection>(res);
+                    }
+                };
+
+                match storage.get_http_exchanges(id).await {
+                    Ok(exchanges) => Ok::<_, Rejection>(
+                        reply::with_status(reply::json(&exchanges), StatusCode::OK).into_response(),
+                    ),
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Capture not available".to_string(),
+                            }),
+                            StatusCode::NOT_FOUND,
+                        )
+                        .into_response();
+                        Ok::<_, Rejection>(res)
+                    }
+                }
+            }
+        })
+}
+
+/// GET /credentials
+pub fn list_credentials_route(
+    storage: Arc<dyn Storage + Send + Sync>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "credentials")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<CredentialFilter>())
+        .and_then(move |filter: CredentialFilter| {
+            let storage = storage.clone();
+            async move {
+                match storage.get_credentials(Some(filter)).await {
+                    Ok(list) => Ok::<_, Rejection>(warp::reply::with_status(
+                        warp::reply::json(&list),
+                        StatusCode::OK,
+                    )),
+                    Err(_) => Ok::<_, Rejection>(warp::reply::with_status(
+                        warp::reply::json(&ApiError {
+                            message: "Failed to load credentials".to_string(),
+                        }),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    )),
+                }
+            }
+        })
+}
+
+/// GET /search?q=...&limit=...
+pub fn search_route(
+    storage: Arc<dyn Storage + Send + Sync>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "search")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<SearchQuery>())
+        .and_then(move |query: SearchQuery| {
+            let storage = storage.clone();
+            async move {
+                let q = query.q.trim();
+                if q.is_empty() {
+                    return Ok::<_, Rejection>(warp::reply::with_status(
+                        warp::reply::json(&ApiError {
+                            message: "Missing search text".to_string(),
+                        }),
+                        StatusCode::BAD_REQUEST,
+                    ));
+                }
+                let limit = query
+                    .limit
+                    .unwrap_or(search::DEFAULT_LIMIT)
+                    .clamp(1, search::MAX_LIMIT);
+                match storage.search(q, limit).await {
+                    Ok(hits) => Ok::<_, Rejection>(warp::reply::with_status(
+                        warp::reply::json(&hits),
+                        StatusCode::OK,
+                    )),
+                    Err(_) => Ok::<_, Rejection>(warp::reply::with_status(
+                        warp::reply::json(&ApiError {
+                            message: "Failed to search sessions".to_string(),
+                        }),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    )),
+                }
+            }
+        })
+}
+
+/// GET /sessions/:id/credentials
+pub fn credentials_route(
+    storage: Arc<dyn Storage + Send + Sync>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "sessions" / String / "credentials")
+        .and(warp::get())
+        .and(warp::query::<CredentialFilter>())
+        .and_then(move |id_str: String, filter: CredentialFilter| {
+            let storage = storage.clone();
+            async move {
+                let id = match Uuid::parse_str(&id_str) {
+                    Ok(u) => u,
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Invalid session id".to_string(),
+                            }),
+                            StatusCode::BAD_REQUEST,
+                        )
+                        .into_response();
+                        return Ok::<_, Rejection>(res);
+                    }
+                };
+
+                let filter = CredentialFilter {
+                    session_id: Some(id),
+                    ..filter
+                };
+                match storage.get_credentials(Some(filter)).await {
+                    Ok(credentials) => {
+                        let res = reply::with_status(reply::json(&credentials), StatusCode::OK)
+                            .into_response();
+                        Ok::<_, Rejection>(res)
+                    }
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Failed to load credentials".to_string(),
+                            }),
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                        )
+                        .into_response();
+                        Ok::<_, Rejection>(res)
+                    }
+                }
+            }
+        })
+}
+
+/// GET /sessions/:id/commands
+pub fn commands_route(
+    storage: Arc<dyn Storage + Send + Sync>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "sessions" / String / "commands")
+        .and(warp::get())
+        .and_then(move |id_str: String| {
+            let storage = storage.clone();
+            async move {
+                let id = match Uuid::parse_str(&id_str) {
+                    Ok(u) => u,
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Invalid session id".to_string(),
+                            }),
+                            StatusCode::BAD_REQUEST,
+                        )
+                        .into_response();
+                        return Ok::<_, Rejection>(res);
+                    }
+                };
+
+                match storage.get_commands(id).await {
+                    Ok(commands) => {
+                        let res = reply::with_status(reply::json(&commands), StatusCode::OK)
+                            .into_response();
+                        Ok::<_, Rejection>(res)
+                    }
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Failed to load commands".to_string(),
+                            }),
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                        )
+                        .into_response();
+                        Ok::<_, Rejection>(res)
+                    }
+                }
+            }
+        })
+}
+
+/// Largest body accepted by [`update_annotations_route`]
+const ANNOTATIONS_BODY_LIMIT: u64 = 64 * 1024;
+
+/// GET /sessions/:id/annotations
+pub fn annotations_route(
+    storage: Arc<dyn Storage + Send + Sync>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "sessions" / String / "annotations")
+        .and(warp::get())
+        .and_then(move |id_str: String| {
+            let storage = storage.clone();
+            async move {
+                let id = match Uuid::parse_str(&id_str) {
+                    Ok(u) => u,
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Invalid session id".to_string(),
+                            }),
+                            StatusCode::BAD_REQUEST,
+                        )
+                        .into_response();
+                        return Ok::<_, Rejection>(res);
+                    }
+                };
+
+                match storage.get_annotations(id).await {
+                    Ok(annotations) => {
+                        let res = reply::with_status(reply::json(&annotations), StatusCode::OK)
+                            .into_response();
+                        Ok::<_, Rejection>(res)
+                    }
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Failed to load annotations".to_string(),
+                            }),
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                        )
+                        .into_response();
+                        Ok::<_, Rejection>(res)
+                    }
+                }
+            }
+        })
+}
+
+/// PATCH /sessions/:id
+///
+/// The body is a JSON [`AnnotationsUpdate`], e.g.
+/// `{"add_tags": ["miner"], "remove_tags": ["bruteforce"], "notes": "..."}`; the
+/// updated annotations are returned
+pub fn update_annotations_route(
+    storage: Arc<dyn Storage + Send + Sync>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "sessions" / String)
+        .and(warp::patch())
+        .and(warp::body::content_length_limit(ANNOTATIONS_BODY_LIMIT))
+        .and(warp::body::json::<AnnotationsUpdate>())
+        .and_then(move |id_str: String, update: AnnotationsUpdate| {
+            let storage = storage.clone();
+            async move {
+                let id = match Uuid::parse_str(&id_str) {
+                    Ok(u) => u,
+                    Err(_) => {
+                        let res = reply::with_status(
+                            reply::json(&ApiError {
+                                message: "Invalid session id".to_string(),
+                            }),
+                            StatusCode::BAD_REQUEST,
+                        )
+                        .into_response();
+                        return Ok::<_, Rejection>(res);
+                    }
+                };
+                if update.add_tags.iter().any(|tag| tag.trim().is_empty()) {
+                    let res = reply::with_status(
+                        reply::json(&ApiError {
+                            message: "Tags cannot be empty".to_string(),
+                        }),
+                        StatusCode::BAD_REQUEST,
+                    )
+                    .into_response();
+                    return Ok::<_, Rejection>(res);
+                }
+                if storage.get_session(id).await.is_err() {
+                    let res = reply::with_status(
+                        reply::json(&ApiError {
+                            message: "Session not found".to_string(),
+                        }),
+                        StatusCode::NOT_FOUND,
+                    )
+                    .into_response();
+                    return Ok::<_, Rejection>(res);
+                }
+
+                let saved = match storage.get_annotations(id).await {
+                    Ok(mut annotations) => {
+                        annotations.update(&update);
+                        storage
+                            .save_annotations(&annotations)
+                            .await
+                            .map(|()| annotations)
+                    }
+                    Err(e) => Err(e),
+                };
+                let res = match saved {
+                    Ok(annotations) => {
+                        reply::with_status(reply::json(&annotations), StatusCode::OK)
+                            .into_response()
+                    }
+                    Err(_) => reply::with_status(
+                        reply::json(&ApiError {
+                            message: "Failed to save annotations".to_string(),
+                        }),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    )
+                    .into_response(),
+                };
+                Ok::<_, Rejection>(res)
+            }
+        })
+}
+
+/// Largest record accepted by [`ingest_route`], capture artifacts included
+const INGEST_BODY_LIMIT: u64 = 64 * 1024 * 1024;
+
+/// POST /ingest
+///
+/// Stores a [`Forwarded`] record pushed by an agent, see
+/// [`crate::storage::forwarding_storage`]. Only the agent tokens of `config` are accepted,
+/// and writes to the sessions of another agent are answered `403 Forbidden`
+pub fn ingest_route(
+    storage: Arc<dyn Storage + Send + Sync>,
+    config: Arc<WebUiConfig>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "ingest")
+        .and(warp::post())
+        .and(with_agent_auth(config))
+        .and(warp::body::content_length_limit(INGEST_BODY_LIMIT))
+        .and(warp::body::json::<Forwarded>())
+        .and_then(move |agent: String, record: Forwarded| {
+            let storage = storage.clone();
+            async move {
+                let res = match record.persist(storage.as_ref(), &agent).await {
+                    Ok(()) => reply::with_status(reply(), StatusCode::NO_CONTENT).into_response(),
+                    Err(e @ IngestError::NotOwned(_)) => {
+                        debug!("Refused a record of {}: {}", agent, e);
+                        reply::with_status(
+                            reply::json(&ApiError {
+                                message: e.to_string(),
+                            }),
+                            StatusCode::FORBIDDEN,
+                        )
+                        .into_response()
+                    }
+                    Err(IngestError::StorageError(_)) => reply::with_status(
+                        reply::json(&ApiError {
+                            message: "Failed to store the forwarded record".to_string(),
+                        }),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    )
+                    .into_response(),
+                };
+                Ok::<_, Rejection>(res)
+            }
+        })
+}
+
+/// Largest service configuration accepted by [`add_service_route`]
+const SERVICE_BODY_LIMIT: u64 = 64 * 1024;
+
+/// GET /services
+pub fn list_services_route(
+    services: ServiceControl,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "services")
+        .and(warp::get())
+        .and_then(move || {
+            let services = services.clone();
+            async move {
+                let res = match services.list().await {
+                    Ok(list) => {
+                        reply::with_status(reply::json(&list), StatusCode::OK).into_response()
+                    }
+                    Err(e) => service_error(e),
+                };
+                Ok::<_, Rejection>(res)
+            }
+        })
+}
+
+/// POST /services
+///
+/// The body is a JSON [`ServiceConfig`]; the service is bound right away when enabled
+pub fn add_service_route(
+    services: ServiceControl,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "services")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(SERVICE_BODY_LIMIT))
+        .and(warp::body::json::<ServiceConfig>())
+        .and_then(move |service: ServiceConfig| {
+            let services = services.clone();
+            async move {
+                let res = match services.add(service.clone()).await {
+                    Ok(()) => reply::with_status(reply::json(&service), StatusCode::CREATED)
+                        .into_response(),
+                    Err(e) => service_error(e),
+                };
+                Ok::<_, Rejection>(res)
+            }
+        })
+}
+
+/// POST /services/:name/enable and POST /services/:name/disable
+pub fn service_state_route(
+    services: ServiceControl,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let enable = warp::path!("api" / "services" / String / "enable").map(|name| (name, true));
+    let disable = warp::path!("api" / "services" / String / "disable").map(|name| (name, false));
+    enable
+        .or(disable)
+        .unify()
+        .and(warp::post())
+        .and_then(move |(name, enabled): (String, bool)| {
+            let services = services.clone();
+            async move {
+                let res = match services.set_enabled(&name, enabled).await {
+                    Ok(service) => {
+                        reply::with_status(reply::json(&service), StatusCode::OK).into_response()
+                    }
+                    Err(e) => service_error(e),
+                };
+                Ok::<_, Rejection>(res)
+            }
+        })
+}
+
+/// DELETE /services/:name
+pub fn remove_service_route(
+    services: ServiceControl,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "services" / String)
+        .and(warp::delete())
+        .and_then(move |name: String| {
+            let services = services.clone();
+            async move {
+                let res = match services.remove(&name).await {
+                    Ok(service) => {
+                        reply::with_status(reply::json(&service), StatusCode::OK).into_response()
+                    }
+                    Err(e) => service_error(e),
+                };
+                Ok::<_, Rejection>(res)
+            }
+        })
+}
+
+/// POST /drain
+///
+/// Stops accepting connections, the honeypot shutting down once the proxied ones
+/// closed or the grace period elapsed.
+pub fn drain_route(
+    services: ServiceControl,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "drain")
+        .and(warp::post())
+        .and_then(move || {
+            let services = services.clone();
+            async move {
+                let res = match services.drain().await {
+                    Ok(grace) => reply::with_status(
+                        reply::json(&serde_json::json!({ "grace_secs": grace.as_secs() })),
+                        StatusCode::ACCEPTED,
+                    )
+                    .into_response(),
+                    Err(e) => service_error(e),
+                };
+                Ok::<_, Rejection>(res)
+            }
+        })
+}
+
+/// Answer to a service change refused by the controller
+fn service_error(error: ControllerError) -> warp::reply::Response {
+    let status = match error {
+        ControllerError::UnknownService(_) => StatusCode::NOT_FOUND,
+        ControllerError::ServiceConflict(_) => StatusCode::CONFLICT,
+        ControllerError::ConfigurationError(_) => StatusCode::BAD_REQUEST,
+        ControllerError::InitializationFailed(_) => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    reply::with_status(
+        reply::json(&ApiError {
+            message: error.to_string(),
+        }),
+        status,
+    )
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_pages_always_move_forward() {
+        let mut filter = SessionFilter {
+            limit: Some(0),
+            offset: Some(40),
+            ..SessionFilter::default()
+        };
+        assert_eq!(export_page(&mut filter), (1, 41));
+        assert_eq!(filter.limit, Some(1));
+
+        filter.limit = Some(10_000);
+        assert_eq!(
+            export_page(&mut filter),
+            (EXPORT_PAGE_SIZE, 40 + EXPORT_PAGE_SIZE)
+        );
+
+        let mut filter = SessionFilter::default();
+        assert_eq!(
+            export_page(&mut filter),
+            (EXPORT_PAGE_SIZE, EXPORT_PAGE_SIZE)
+        );
+    }
+}
@@ -1,10 +1,25 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
 
-use log::info;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
+use log::{debug, info, warn};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
 
+use super::auth::{handle_rejection, with_auth};
 use super::routes::*;
+use crate::analytics::campaigns::CampaignBoard;
+use crate::configuration::types::{StixConfig, WebUiConfig};
+use crate::controller::service_api::ServiceControl;
+use crate::enrichment::Enricher;
 use crate::error_handling::types::WebError;
+use crate::lifecycle::{self, LifecycleSender};
+use crate::network::tls;
+use crate::session_management::session_control::SessionControl;
+use crate::storage::deduplicating_storage::DedupStore;
+use crate::storage::integrity::IntegrityLedger;
 use crate::storage::storage_trait::Storage;
 
 use warp::Filter;
@@ -18,30 +33,193 @@ pub struct ApiError {
 /// Web server for HTTP API and dashboard
 pub struct WebServer {
     storage: Arc<dyn Storage + Send + Sync>,
+    config: Arc<WebUiConfig>,
+    lifecycle: LifecycleSender,
+    services: ServiceControl,
+    integrity: Option<Arc<IntegrityLedger>>,
+    dedup: Option<Arc<DedupStore>>,
+    campaigns: Option<Arc<CampaignBoard>>,
+    enricher: Option<Arc<Enricher>>,
+    sessions: Option<SessionControl>,
+    stix_identity: String,
 }
 
 impl WebServer {
-    /// Create a new WebServer instance
+    /// Create a new WebServer instance, open and served over plain HTTP
     pub fn new(storage: Arc<dyn Storage>) -> Self {
-        Self { storage }
+        Self {
+            storage,
+            config: Arc::new(WebUiConfig::default()),
+            lifecycle: lifecycle::channel(),
+            services: ServiceControl::channel().0,
+            integrity: None,
+            dedup: None,
+            campaigns: None,
+            enricher: None,
+            sessions: None,
+            stix_identity: StixConfig::default().identity,
+        }
     }
 
-    /// Start the web server on the given port
-    pub async fn start(&self, port: u16) -> Result<(), WebError> {
+    /// Manages the services through `services`, the `/services` routes answer
+    /// `503 Service Unavailable` otherwise
+    pub fn with_services(mut self, services: ServiceControl) -> Self {
+        self.services = services;
+        self
+    }
+
+    /// Streams the session lifecycle events sent on `lifecycle` to the `/events` subscribers
+    pub fn with_lifecycle(mut self, lifecycle: LifecycleSender) -> Self {
+        self.lifecycle = lifecycle;
+        self
+    }
+
+    /// Checks the sessions against the manifests of `integrity` on `/verify`, which
+    /// answers `404 Not Found` otherwise
+    pub fn with_integrity(mut self, integrity: Option<Arc<IntegrityLedger>>) -> Self {
+        self.integrity = integrity;
+        self
+    }
+
+    /// Serves the deduplication manifests of `dedup` on `/dedup`, which answers
+    /// `404 Not Found` otherwise
+    pub fn with_dedup(mut self, dedup: Option<Arc<DedupStore>>) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Serves the campaigns published on `campaigns` on `/campaigns`, which answers
+    /// `404 Not Found` otherwise
+    pub fn with_campaigns(mut self, campaigns: Arc<CampaignBoard>) -> Self {
+        self.campaigns = Some(campaigns);
+        self
+    }
+
+    /// Enriches the stored sessions again with `enricher` on `/sessions/enrich`, which
+    /// answers `404 Not Found` otherwise
+    pub fn with_enricher(mut self, enricher: Arc<Enricher>) -> Self {
+        self.enricher = Some(enricher);
+        self
+    }
+
+    /// Lists the active sessions of `sessions` on `/sessions/active`, streams their
+    /// captures on `/sessions/:id/live`, ends them on `DELETE /sessions/:id` and
+    /// reports their containers on `/containers`, which answer `503 Service
+    /// Unavailable` otherwise
+    pub fn with_sessions(mut self, sessions: SessionControl) -> Self {
+        self.sessions = Some(sessions);
+        self
+    }
+
+    /// Names the identity creating the indicators of `/stix` after `identity`
+    pub fn with_stix_identity(mut self, identity: String) -> Self {
+        self.stix_identity = identity;
+        self
+    }
+
+    /// Requires the credentials and serves over the TLS configured in `config`
+    pub fn with_config(mut self, config: WebUiConfig) -> Self {
+        self.config = Arc::new(config);
+        self
+    }
+
+    /// Start the web server on the given address
+    pub async fn start(&self, addr: SocketAddr) -> Result<(), WebError> {
         let dashboard = dashboard_route();
         let list_sessions = list_sessions_route(self.storage.clone());
+        let delete_sessions = delete_sessions_route(self.storage.clone());
+        let enrich_sessions = enrich_sessions_route(self.storage.clone(), self.enricher.clone());
+        let export_sessions = export_sessions_route(self.storage.clone());
         let get_session_data = get_session_data_route(self.storage.clone());
         let download_artifacts = download_artifacts_route(self.storage.clone());
+        let download_pcap = download_pcap_route(self.storage.clone());
+        let export_session = export_session_route(self.storage.clone());
+        let cowrie = cowrie_route(self.storage.clone());
+        let stix = stix_route(self.storage.clone(), self.stix_identity.clone());
+        let verify_session = verify_session_route(self.storage.clone(), self.integrity.clone());
+        let dedup = dedup_route(self.dedup.clone());
+        let campaigns = campaigns_route(self.campaigns.clone());
+        let active_sessions = active_sessions_route(self.sessions.clone());
+        let end_session = end_session_route(self.sessions.clone());
+        let containers = containers_route(self.sessions.clone());
+        let live_session = live_session_route(self.sessions.clone());
+        let replay = replay_route(self.storage.clone());
+        let http_exchanges = http_exchanges_route(self.storage.clone());
+        let list_credentials = list_credentials_route(self.storage.clone());
+        let search = search_route(self.storage.clone());
+        let credentials = credentials_route(self.storage.clone());
+        let commands = commands_route(self.storage.clone());
+        let annotations = annotations_route(self.storage.clone());
+        let update_annotations = update_annotations_route(self.storage.clone());
+        let metrics = metrics_route();
+        let live = live_route();
+        let lifecycle = lifecycle_route(self.lifecycle.clone());
+        let list_services = list_services_route(self.services.clone());
+        let add_service = add_service_route(self.services.clone());
+        let service_state = service_state_route(self.services.clone());
+        let remove_service = remove_service_route(self.services.clone());
+        let drain = drain_route(self.services.clone());
+        let ingest = ingest_route(self.storage.clone(), self.config.clone());
 
-        // Compose routes
-        let routes = dashboard
-            .or(list_sessions)
+        let services = list_services
+            .or(add_service)
+            .or(service_state)
+            .or(remove_service)
+            .or(drain);
+        // Bulk operations on the sessions matching a filter
+        let bulk = delete_sessions.or(enrich_sessions).or(export_sessions);
+
+        // Compose routes, the dashboard assets hold no data and stay public
+        let api = list_sessions
+            .or(bulk)
+            // Ahead of the session routes, which take `active` for an invalid id
+            .or(active_sessions)
             .or(get_session_data)
-            .or(download_artifacts);
+            .or(download_artifacts)
+            .or(download_pcap)
+            .or(export_session)
+            .or(cowrie)
+            .or(stix)
+            .or(verify_session)
+            .or(dedup)
+            .or(campaigns)
+            .or(end_session)
+            .or(containers)
+            .or(live_session)
+            .or(replay)
+            .or(http_exchanges)
+            .or(list_credentials)
+            .or(search)
+            .or(credentials)
+            .or(commands)
+            .or(annotations)
+            .or(update_annotations)
+            .or(live)
+            .or(lifecycle)
+            .or(services)
+            .or(metrics);
+        // Agents of a collector have their own tokens, checked by the ingest route
+        let routes = dashboard
+            .or(ingest)
+            .or(with_auth(self.config.clone()).and(api))
+            .recover(handle_rejection);
+
+        if !self.config.requires_auth() {
+            warn!("WebUI has no API token nor user configured, the API is open");
+        }
 
-        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        if let Some(tls_config) = &self.config.tls {
+            let acceptor = tls::build_acceptor(tls_config)
+                .map_err(|e| WebError::StartFailed(format!("{:?}", e)))?;
+            let listener = TcpListener::bind(addr)
+                .await
+                .map_err(|e| WebError::StartFailed(e.to_string()))?;
+            info!("WebUI starting over HTTPS on {}", addr);
+            serve_tls(listener, acceptor, routes).await;
+            return Ok(());
+        }
 
-        info!("WebUI starting on port {}", port);
+        info!("WebUI starting on {}", addr);
 
         //WARN: will crash the whole program if the web server cannot run
         warp::serve(routes).run(addr).await;
@@ -49,3 +227,40 @@ impl WebServer {
         Ok(())
     }
 }
+
+/// Accept loop of the HTTPS listener, warp only serving plain HTTP
+async fn serve_tls<F>(listener: TcpListener, acceptor: TlsAcceptor, routes: F)
+where
+    F: Filter<Error = warp::Rejection> + Clone + Send + Sync + 'static,
+    F::Extract: warp::Reply,
+{
+    let service = warp::service(routes);
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                // Most likely out of file descriptors, wait for some to be released
+                warn!("WebUI cannot accept a connection: {}", e);
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let service = TowerToHyperService::new(service.clone());
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("WebUI TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+            };
+            if let Err(e) = auto::Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("WebUI connection with {} failed: {}", peer, e);
+            }
+        });
+    }
+}
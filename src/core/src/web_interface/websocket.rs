@@ -0,0 +1,300 @@
+//! Server side of the WebSocket protocol (RFC 6455), as much as pushing text
+//! messages to a browser takes: the opening handshake, text frames sent to the
+//! client, and the ping and close frames it sends back.
+//!
+//! The messages of the client are read but not delivered, the routes using it
+//! only stream to their watchers.
+
+use std::future::Future;
+use std::io;
+
+use base64::Engine;
+use futures_util::{Stream, StreamExt};
+use hyper::upgrade::{OnUpgrade, Upgraded};
+use hyper_util::rt::TokioIo;
+use log::debug;
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Mutex;
+use warp::http::{header, HeaderMap, StatusCode};
+use warp::{reply, Reply};
+
+/// Appended to the key of the client to compute the accept key of the handshake
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Largest frame read from a client, which only has control frames to send
+const MAX_FRAME_LEN: u64 = 64 * 1024;
+
+pub const OPCODE_TEXT: u8 = 0x1;
+pub const OPCODE_CLOSE: u8 = 0x8;
+pub const OPCODE_PING: u8 = 0x9;
+pub const OPCODE_PONG: u8 = 0xA;
+
+/// Connection of a client once upgraded to the WebSocket protocol
+pub type WebSocket = TokioIo<Upgraded>;
+
+/// A frame read from a client, unmasked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub opcode: u8,
+    pub payload: Vec<u8>,
+}
+
+/// `Sec-WebSocket-Accept` value answering the `Sec-WebSocket-Key` of a client
+pub fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.trim().as_bytes());
+    hasher.update(HANDSHAKE_GUID.as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(hasher.finalize())
+}
+
+/// Answers the opening handshake found in `headers` with `101 Switching Protocols`,
+/// and runs `serve` on the connection once upgraded.
+///
+/// Returns `None` when the request is no WebSocket handshake, or cannot be upgraded.
+pub fn accept<F, Fut>(
+    headers: &HeaderMap,
+    on_upgrade: Option<OnUpgrade>,
+    serve: F,
+) -> Option<reply::Response>
+where
+    F: FnOnce(WebSocket) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let has_token = |name: header::HeaderName, token: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    };
+    if !has_token(header::UPGRADE, "websocket") || !has_token(header::CONNECTION, "upgrade") {
+        return None;
+    }
+    if headers
+        .get(header::SEC_WEBSOCKET_VERSION)
+        .is_none_or(|version| version != "13")
+    {
+        return None;
+    }
+    let key = headers.get(header::SEC_WEBSOCKET_KEY)?.to_str().ok()?;
+    let on_upgrade = on_upgrade?;
+
+    tokio::spawn(async move {
+        match on_upgrade.await {
+            Ok(upgraded) => serve(TokioIo::new(upgraded)).await,
+            Err(e) => debug!("WebSocket upgrade failed: {}", e),
+        }
+    });
+    let response = reply::with_status(reply(), StatusCode::SWITCHING_PROTOCOLS);
+    let response = reply::with_header(response, header::UPGRADE, "websocket");
+    let response = reply::with_header(response, header::CONNECTION, "upgrade");
+    let response = reply::with_header(response, header::SEC_WEBSOCKET_ACCEPT, accept_key(key));
+    Some(response.into_response())
+}
+
+/// A final, unmasked frame as a server sends it
+pub fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Reads the next frame of a client, unmasking its payload.
+///
+/// Errors
+/// - `InvalidData` for a frame longer than [`MAX_FRAME_LEN`].
+/// - `UnexpectedEof` once the client closed the connection.
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Frame> {
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head).await?;
+    let opcode = head[0] & 0x0F;
+    let masked = head[1] & 0x80 != 0;
+    let len = match head[1] & 0x7F {
+        126 => reader.read_u16().await? as u64,
+        127 => reader.read_u64().await?,
+        len => len as u64,
+    };
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("WebSocket frame of {} bytes", len),
+        ));
+    }
+    let mut mask = [0u8; 4];
+    if masked {
+        reader.read_exact(&mut mask).await?;
+    }
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).await?;
+    if masked {
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+    }
+    Ok(Frame { opcode, payload })
+}
+
+/// Sends `messages` to the client as text frames while answering its pings, until
+/// the messages end or the client closes the connection.
+pub async fn send_all<S, M>(socket: S, messages: M) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite,
+    M: Stream<Item = String>,
+{
+    let (mut reader, writer) = tokio::io::split(socket);
+    let writer = Mutex::new(writer);
+    tokio::pin!(messages);
+
+    let incoming = async {
+        loop {
+            let frame = read_frame(&mut reader).await?;
+            match frame.opcode {
+                OPCODE_PING => {
+                    let pong = encode_frame(OPCODE_PONG, &frame.payload);
+                    writer.lock().await.write_all(&pong).await?;
+                }
+                OPCODE_CLOSE => {
+                    // Echoes the status code of the client, as the closing handshake wants
+                    let close =
+                        encode_frame(OPCODE_CLOSE, &frame.payload[..frame.payload.len().min(2)]);
+                    writer.lock().await.write_all(&close).await?;
+                    return Ok::<_, io::Error>(());
+                }
+                _ => {}
+            }
+        }
+    };
+    let outgoing = async {
+        while let Some(message) = messages.next().await {
+            let frame = encode_frame(OPCODE_TEXT, message.as_bytes());
+            writer.lock().await.write_all(&frame).await?;
+        }
+        let mut writer = writer.lock().await;
+        writer.write_all(&encode_frame(OPCODE_CLOSE, &[])).await?;
+        writer.shutdown().await
+    };
+    tokio::select! {
+        result = incoming => result,
+        result = outgoing => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+
+    #[test]
+    fn accept_key_answers_the_rfc_example() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[tokio::test]
+    async fn frames_are_encoded_and_unmasked() {
+        assert_eq!(encode_frame(OPCODE_TEXT, b"Hello"), b"\x81\x05Hello");
+        let long = encode_frame(OPCODE_TEXT, &[b'a'; 300]);
+        assert_eq!(&long[..4], &[0x81, 126, 0x01, 0x2C]);
+
+        // Masked "Hello" from section 5.7 of the RFC
+        let masked: &[u8] = &[
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        let frame = read_frame(&mut &masked[..]).await.unwrap();
+        assert_eq!(frame.opcode, OPCODE_TEXT);
+        assert_eq!(frame.payload, b"Hello");
+
+        let mut read = &long[..];
+        assert_eq!(read_frame(&mut read).await.unwrap().payload.len(), 300);
+    }
+
+    #[tokio::test]
+    async fn messages_are_sent_until_the_client_closes() {
+        let (server, mut client) = tokio::io::duplex(1024);
+        let messages = stream::iter(vec!["first".to_string()]).chain(stream::pending());
+        let session = tokio::spawn(send_all(server, messages));
+
+        let frame = read_frame(&mut client).await.unwrap();
+        assert_eq!(frame.opcode, OPCODE_TEXT);
+        assert_eq!(frame.payload, b"first");
+
+        // Masked ping, then close with status 1000
+        client
+            .write_all(&[0x89, 0x82, 1, 2, 3, 4, b'h' ^ 1, b'i' ^ 2])
+            .await
+            .unwrap();
+        let pong = read_frame(&mut client).await.unwrap();
+        assert_eq!(pong.opcode, OPCODE_PONG);
+        assert_eq!(pong.payload, b"hi");
+
+        client
+            .write_all(&[0x88, 0x82, 0, 0, 0, 0, 0x03, 0xE8])
+            .await
+            .unwrap();
+        let close = read_frame(&mut client).await.unwrap();
+        assert_eq!(close.opcode, OPCODE_CLOSE);
+        assert_eq!(close.payload, [0x03, 0xE8]);
+        session.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn clients_are_upgraded_by_the_server() {
+        use warp::Filter;
+
+        let route = warp::header::headers_cloned()
+            .and(warp::ext::optional::<OnUpgrade>())
+            .map(|headers: HeaderMap, on_upgrade| {
+                accept(&headers, on_upgrade, |socket| async move {
+                    let _ = send_all(socket, stream::iter(vec!["hi".to_string()])).await;
+                })
+                .unwrap_or_else(|| StatusCode::BAD_REQUEST.into_response())
+            });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(warp::serve(route).incoming(listener).run());
+
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(
+                b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+                  Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                  Sec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(client.read_u8().await.unwrap());
+        }
+        let head = String::from_utf8(head).unwrap().to_ascii_lowercase();
+        assert!(head.starts_with("http/1.1 101"), "{}", head);
+        assert!(head.contains("sec-websocket-accept: s3pplmbitxaq9kygzzhzrbk+xoo="));
+        assert_eq!(read_frame(&mut client).await.unwrap().payload, b"hi");
+    }
+
+    #[tokio::test]
+    async fn the_connection_is_closed_once_the_messages_end() {
+        let (server, mut client) = tokio::io::duplex(1024);
+        let messages = stream::iter(vec!["only".to_string()]);
+        send_all(server, messages).await.unwrap();
+
+        assert_eq!(read_frame(&mut client).await.unwrap().payload, b"only");
+        assert_eq!(read_frame(&mut client).await.unwrap().opcode, OPCODE_CLOSE);
+    }
+}
@@ -5,17 +5,44 @@ import {
 import { LineChart, Line, XAxis, YAxis, CartesianGrid, Tooltip, ResponsiveContainer, BarChart, Bar } from 'recharts'
 import apiService from '../services/apiService.js'
 
+// Number of live events listed in the recent events card
+const RECENT_EVENTS = 20
+
+// One line summary of a structured event
+const describeEvent = (event) => {
+  switch (event.event) {
+  case 'session_started':
+    return `${event.service} session from ${event.client_addr}`
+  case 'session_ended':
+    return `${event.service} session from ${event.client_addr} ended (${event.status})`
+  case 'command_executed':
+    return `${event.source}: ${event.command}`
+  case 'login_attempt':
+    return `${event.service} login ${event.username} from ${event.client_ip || '?'}`
+  case 'file_uploaded':
+    return `${event.service} upload ${event.name} (${event.size} bytes)`
+  case 'message_received':
+    return `${event.service} mail from ${event.mail_from}`
+  case 'container_failed':
+    return `${event.service} container failed for ${event.client_addr}`
+  default:
+    return event.event.replace(/_/g, ' ')
+  }
+}
+
 const Dashboard = () => {
   const [dashboardData, setDashboardData] = useState({
     packetsPerHour: 0,
-    cpuUsage: 67,
-    memoryUsage: 45,
-    databaseStorage: 78,
-    activeConnections: 234,
     suspectIPs: [],
     mostAttackedService: 'HTTP',
     recentActivity: [],
   })
+
+  const [activeSessions, setActiveSessions] = useState([])
+  const [serviceCounters, setServiceCounters] = useState({})
+  const [recentEvents, setRecentEvents] = useState([])
+  const [live, setLive] = useState(false)
+  const [lastUpdate, setLastUpdate] = useState(new Date())
   
   // Uncomment to use the fetchNetworkActivity 
   // const networkData = fetchNetworkActivity
@@ -57,21 +84,27 @@ const Dashboard = () => {
   }
 
 
-  // Mock API service functions (replace with actual API calls)
+  const [originQuery, setOriginQuery] = useState({ countryCode: '', asn: '' })
+  const [originSessions, setOriginSessions] = useState(null)
+
+  const searchByOrigin = async (event) => {
+    event.preventDefault()
+    try {
+      const sessions = await apiService.getSessionsByOrigin(originQuery)
+      setOriginSessions(sessions)
+    } catch (error) {
+      console.error('Error searching sessions by origin:', error)
+    }
+  }
+
   const fetchDashboardData = async () => {
     try {
       const avgPacketPerHour = await apiService.getAvgPacketPerHour()
-      const activeConnections = await apiService.getNumberOfActiveSessions()
       const mostAttackedService = await apiService.getMostAttackedService()
       
-      // Mock data update
       setDashboardData(prev => ({
         ...prev,
         packetsPerHour: avgPacketPerHour,
-        cpuUsage: Math.floor(Math.random() * 40) + 40,
-        memoryUsage: Math.floor(Math.random() * 30) + 35,
-        databaseStorage: Math.floor(Math.random() * 20) + 70,
-        activeConnections,
         mostAttackedService,
       }))
     } catch (error) {
@@ -79,18 +112,63 @@ const Dashboard = () => {
     }   
   }
 
+  const fetchSessions = async () => {
+    try {
+      const [sessions, counters] = await Promise.all([
+        apiService.getActiveSessions(),
+        apiService.getSessionsPerService(),
+      ])
+      setActiveSessions(sessions)
+      setServiceCounters(counters)
+      setLastUpdate(new Date())
+    } catch (error) {
+      console.error('Error fetching sessions:', error)
+    }
+  }
+
+  // Keeps the session views in sync with the events pushed by the server
+  const handleLiveEvent = (event) => {
+    setRecentEvents(prev => [event, ...prev].slice(0, RECENT_EVENTS))
+    setLastUpdate(new Date())
+
+    if (event.event === 'session_started') {
+      setActiveSessions(prev => prev.some(session => session.id === event.session_id)
+        ? prev
+        : [{
+          id: event.session_id,
+          service_name: event.service,
+          client_addr: event.client_addr,
+          start_time: event.timestamp,
+        }, ...prev])
+      setServiceCounters(prev => ({ ...prev, [event.service]: (prev[event.service] || 0) + 1 }))
+    } else if (event.event === 'session_ended') {
+      setActiveSessions(prev => prev.filter(session => session.id !== event.session_id))
+    }
+  }
+
   useEffect(() => {
     fetchDashboardData()
     fetchNetworkActivity()
     fetchTopThreats()
+    fetchSessions()
 
-    // Set up polling for real-time updates
+    // Session changes are pushed live, the aggregates are refreshed periodically
+    const unsubscribe = apiService.subscribeLive(handleLiveEvent, (connected) => {
+      setLive(connected)
+      // Catch up on what was missed while disconnected
+      if (connected) {
+        fetchSessions()
+      }
+    })
     const interval = setInterval(() => {
       fetchDashboardData()
       fetchNetworkActivity()
     }, 30000) // Update every 30 seconds
 
-    return () => clearInterval(interval)
+    return () => {
+      unsubscribe()
+      clearInterval(interval)
+    }
   }, [])
 
 
@@ -247,6 +325,65 @@ for Enhanced Learning`}
                 ))}
               </div>
             </div>
+
+            {/* Active Sessions */}
+            <div className="dashboard-card p-4">
+              <h3 className="text-lg font-mono mb-4">Active sessions</h3>
+              <div className="space-y-2">
+                <div className="flex justify-between text-sm font-mono border-b border-gray-600 pb-2">
+                  <span>Client</span>
+                  <span>Service</span>
+                </div>
+                {activeSessions.length === 0 && (
+                  <div className="text-sm text-gray-500 font-mono">No active session</div>
+                )}
+                {activeSessions.slice(0, 10).map((session) => (
+                  <div key={session.id} className="threat-row">
+                    <span className="text-orange-400">{session.client_addr}</span>
+                    <span>{session.service_name}</span>
+                  </div>
+                ))}
+              </div>
+            </div>
+
+            {/* Sessions by Origin */}
+            <div className="dashboard-card p-4">
+              <h3 className="text-lg font-mono mb-4">Sessions by origin</h3>
+              <form className="flex gap-2 mb-4" onSubmit={searchByOrigin}>
+                <input
+                  className="w-1/3 bg-gray-800 text-white text-sm font-mono p-1 rounded"
+                  placeholder="CN"
+                  value={originQuery.countryCode}
+                  onChange={(e) => setOriginQuery({ ...originQuery, countryCode: e.target.value })}
+                />
+                <input
+                  className="w-1/2 bg-gray-800 text-white text-sm font-mono p-1 rounded"
+                  placeholder="AS4134"
+                  value={originQuery.asn}
+                  onChange={(e) => setOriginQuery({ ...originQuery, asn: e.target.value })}
+                />
+                <button type="submit" className="text-orange-400 text-sm font-mono">
+                  Search
+                </button>
+              </form>
+              {originSessions && (
+                <div className="space-y-2">
+                  <div className="flex justify-between text-sm font-mono border-b border-gray-600 pb-2">
+                    <span>Client</span>
+                    <span>{originSessions.length} sessions</span>
+                  </div>
+                  {originSessions.slice(0, 10).map((session) => (
+                    <div key={session.id} className="threat-row">
+                      <span className="text-orange-400">{session.client_addr}</span>
+                      <span>
+                        {session.enrichment?.country_code || '??'}
+                        {session.enrichment?.asn ? ` AS${session.enrichment.asn}` : ''}
+                      </span>
+                    </div>
+                  ))}
+                </div>
+              )}
+            </div>
           </div>
 
           {/* Center Column */}
@@ -295,28 +432,36 @@ for Enhanced Learning`}
                 </ResponsiveContainer>
               </div>
             </div>
+
+            {/* Recent Events */}
+            <div className="dashboard-card p-4">
+              <h3 className="text-lg font-mono mb-4">Recent events</h3>
+              <div className="space-y-1 max-h-64 overflow-y-auto">
+                {recentEvents.length === 0 && (
+                  <div className="text-sm text-gray-500 font-mono">Waiting for events</div>
+                )}
+                {recentEvents.map((event, index) => (
+                  <div key={`${event.timestamp}-${index}`} className="flex gap-2 text-sm font-mono">
+                    <span className="text-gray-500">
+                      {new Date(event.timestamp).toLocaleTimeString()}
+                    </span>
+                    <span className="truncate">{describeEvent(event)}</span>
+                  </div>
+                ))}
+              </div>
+            </div>
           </div>
 
           {/* Right Column */}
           <div className="col-span-12 md:col-span-3 space-y-4">
-            {/* System Indicators */}
+            {/* Sessions per Service */}
             <div className="grid grid-cols-3 gap-2">
-              <div className="metric-card">
-                <div className="text-white text-xs xs:text-[0.2rem] mb-1">Memory</div>
-                <div className="text-orange-400 text-sm font-mono">
-                  {dashboardData.memoryUsage}%
-                </div>
-              </div>
-              <div className="metric-card">
-                <div className="text-white text-xs mb-1">Storage</div>
-                <div className="text-orange-400 text-sm font-mono">
-                  {dashboardData.databaseStorage}%
+              {Object.entries(serviceCounters).map(([service, count]) => (
+                <div key={service} className="metric-card">
+                  <div className="text-white text-xs mb-1 truncate">{service}</div>
+                  <div className="text-orange-400 text-sm font-mono">{count}</div>
                 </div>
-              </div>
-              <div className="metric-card">
-                <div className="text-white text-xs mb-1">Uptime</div>
-                <div className="text-orange-400 text-sm font-mono">24h</div>
-              </div>
+              ))}
             </div>
 
             {/* Most Attacked Service */}
@@ -347,15 +492,15 @@ for Enhanced Learning`}
         <div className="mt-6 bg-gray-600 text-white p-3 rounded-lg flex flex-col md:flex-row justify-between items-center space-y-2 md:space-y-0">
           <div className="flex items-center space-x-4">
             <div className="flex items-center space-x-2">
-              <div className="w-2 h-2 bg-green-400 rounded-full animate-pulse"></div>
-              <span className="text-sm">System Online</span>
+              <div className={`w-2 h-2 rounded-full ${live ? 'bg-green-400 animate-pulse' : 'bg-red-400'}`}></div>
+              <span className="text-sm">{live ? 'Live' : 'Reconnecting'}</span>
             </div>
             <div className="text-sm">
-              Active Sessions: {dashboardData.activeConnections}
+              Active Sessions: {activeSessions.length}
             </div>
           </div>
           <div className="text-sm">
-            Last Update: {new Date().toLocaleTimeString()}
+            Last Update: {lastUpdate.toLocaleTimeString()}
           </div>
         </div>
       </div>
@@ -59,17 +59,8 @@ class ApiService {
 
   async getNumberOfActiveSessions() {
     try {
-      const sessions = await this.request('api/sessions')
-
-      const init = 0
-
-      const nbOfActiveSessions = sessions.map(session => session.status).reduce((acc, status) => {
-        // If status is active accumulate
-        if (status === 'Active') {
-          acc + 1 
-        }
-      }, init)
-      return nbOfActiveSessions
+      const sessions = await this.getActiveSessions()
+      return sessions.length
     } catch (error) {
       console.error('Error fetching client sessions:', error)
       throw error
@@ -77,6 +68,21 @@ class ApiService {
 
   }
 
+  // Sessions whose container is still running
+  async getActiveSessions() {
+    return this.request('api/sessions?status=Active')
+  }
+
+  // Number of stored sessions of each service
+  async getSessionsPerService() {
+    const sessions = await this.request('api/sessions')
+
+    return sessions.reduce((counts, session) => {
+      counts[session.service_name] = (counts[session.service_name] || 0) + 1
+      return counts
+    }, {})
+  }
+
   async getTotalBytesTransfered() {
     try {
       const sessions = await this.request('api/sessions')
@@ -158,6 +164,25 @@ class ApiService {
     return (totalBytesTransfered / 24 || 25)
   }
 
+  // Sessions whose client IP matches the given country code and/or AS number
+  async getSessionsByOrigin({ countryCode = '', asn = '' } = {}) {
+    const params = new URLSearchParams()
+    if (countryCode) {
+      params.set('country_code', countryCode.toUpperCase())
+    }
+    if (asn) {
+      // Accept both "AS4134" and "4134"
+      params.set('asn', String(asn).replace(/^AS/i, ''))
+    }
+
+    try {
+      return await this.request(`api/sessions?${params.toString()}`)
+    } catch (error) {
+      console.error('Error fetching sessions by origin:', error)
+      throw error
+    }
+  }
+
   async getMostAttackedService() {
     const sessions = await this.request('api/sessions')
 
@@ -204,35 +229,23 @@ class ApiService {
     return response.blob()
   }
 
-  // Real-time data (WebSocket simulation with polling)
-  startPolling(callback, interval = 30000) {
-    const poll = async () => {
+  // Live events pushed by the server, the recent ones first
+  // onStatus is called with true once connected and false while reconnecting
+  subscribeLive(onEvent, onStatus = () => {}) {
+    const source = new EventSource(`${this.baseURL}api/live`)
+
+    source.onopen = () => onStatus(true)
+    source.onerror = () => onStatus(false)
+    source.addEventListener('event', (message) => {
       try {
-        const [stats, activity, threats] = await Promise.all([
-          this.getDashboardStats(),
-          this.getNetworkActivity(),
-          this.getTopThreats(),
-        ])
-        
-        callback({
-          stats,
-          activity,
-          threats,
-          timestamp: new Date().toISOString(),
-        })
+        onEvent(JSON.parse(message.data))
       } catch (error) {
-        console.error('Polling error:', error)
+        console.error('Malformed live event:', error)
       }
-    }
+    })
 
-    // Initial call
-    poll()
-    
-    // Set up interval
-    const intervalId = setInterval(poll, interval)
-    
     // Return cleanup function
-    return () => clearInterval(intervalId)
+    return () => source.close()
   }
 }
 